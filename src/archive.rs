@@ -0,0 +1,175 @@
+use crate::output::OutputBuffer;
+use anyhow::{anyhow, Result};
+use std::fs::{self, File, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+/// Files a repository archive may hold when `NABLA_MAX_REPO_FILES` isn't set
+pub const DEFAULT_MAX_REPO_FILES: usize = 250_000;
+
+/// The most files an extracted repository may have: `NABLA_MAX_REPO_FILES`, or
+/// [`DEFAULT_MAX_REPO_FILES`]
+pub fn max_repo_files() -> usize {
+    std::env::var("NABLA_MAX_REPO_FILES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&max| max > 0)
+        .unwrap_or(DEFAULT_MAX_REPO_FILES)
+}
+
+/// Repository archive formats accepted on upload, identified by magic bytes rather than by
+/// filename or content type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extract `archive` into `dest`, dropping `strip_components` leading path components
+/// (tar.gz only; GitHub tarballs wrap everything in one directory). An archive of more than
+/// `max_files` files is refused before anything is written.
+pub async fn extract_archive(archive: &Path, dest: &Path, strip_components: u32, max_files: usize) -> Result<()> {
+    let mut header = [0u8; 4];
+    let read = io::Read::read(&mut File::open(archive)?, &mut header)?;
+    let format = ArchiveFormat::sniff(&header[..read])
+        .ok_or_else(|| anyhow!("Unsupported archive format - expected zip or tar.gz"))?;
+
+    tokio::fs::create_dir_all(dest).await?;
+
+    match format {
+        ArchiveFormat::TarGz => {
+            check_tar_file_count(archive, max_files).await?;
+            let mut command = Command::new("tar");
+            command.arg("-xzf").arg(archive).arg("-C").arg(dest);
+            if strip_components > 0 {
+                command.arg(format!("--strip-components={}", strip_components));
+            }
+            let output = command.output().await?;
+
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Failed to extract tar.gz: {}",
+                    OutputBuffer::text(&output.stderr)
+                ));
+            }
+            Ok(())
+        }
+        ArchiveFormat::Zip => {
+            let archive = archive.to_path_buf();
+            let dest = dest.to_path_buf();
+            tokio::task::spawn_blocking(move || extract_zip(&archive, &dest, max_files)).await?
+        }
+    }
+}
+
+/// An archive refused for holding more than `max_files` files
+#[derive(Debug, thiserror::Error)]
+#[error("Repository archive has more than {max_files} files, the limit set by NABLA_MAX_REPO_FILES; refusing to extract it")]
+pub struct TooManyFiles {
+    pub max_files: usize,
+}
+
+fn too_many_files(max_files: usize) -> anyhow::Error {
+    TooManyFiles { max_files }.into()
+}
+
+/// Whether extraction left at least one regular file under `dir`; a tarball holding nothing but
+/// its top-level directory leaves none
+pub fn contains_file(dir: &Path) -> bool {
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_file() => return true,
+                Ok(file_type) if file_type.is_dir() => pending.push(entry.path()),
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+/// Count the files a tar.gz lists, stopping the listing as soon as there are too many
+async fn check_tar_file_count(archive: &Path, max_files: usize) -> Result<()> {
+    let mut child = Command::new("tar")
+        .arg("-tzf")
+        .arg(archive)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    // Split on bytes: names needn't be UTF-8
+    let mut names = BufReader::new(child.stdout.take().expect("stdout is piped")).split(b'\n');
+
+    let mut files = 0;
+    while let Some(name) = names.next_segment().await? {
+        if !name.ends_with(b"/") {
+            files += 1;
+        }
+        if files > max_files {
+            let _ = child.kill().await;
+            return Err(too_many_files(max_files));
+        }
+    }
+    // A listing that fails is reported by the extraction itself
+    let _ = child.wait().await;
+    Ok(())
+}
+
+/// Entries whose names would escape `dest` (absolute paths, `..`) reject the whole archive;
+/// symlinks are skipped. Executable bits are kept so build scripts still run.
+fn extract_zip(archive: &Path, dest: &Path, max_files: usize) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)
+        .map_err(|e| anyhow!("Failed to read zip archive: {}", e))?;
+    // The central directory names every entry, so count them before extracting any
+    let files = zip.file_names().filter(|name| !name.ends_with('/')).count();
+    if files > max_files {
+        return Err(too_many_files(max_files));
+    }
+
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let relative = entry
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| anyhow!("Zip entry '{}' escapes the extraction directory", entry.name()))?;
+        let target = dest.join(relative);
+        let mode = entry.unix_mode();
+
+        if mode.is_some_and(|m| m & 0o170000 == 0o120000) {
+            continue;
+        }
+
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&target)?;
+        io::copy(&mut entry, &mut file)?;
+        if let Some(mode) = mode {
+            fs::set_permissions(&target, Permissions::from_mode(mode & 0o755))?;
+        }
+    }
+
+    Ok(())
+}
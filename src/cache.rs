@@ -0,0 +1,160 @@
+//! How much a build got out of the runner's caches: ccache hits and misses, whether an
+//! earlier build's output directory was reused, and how large the caches are.
+
+use crate::core::{BuildConfig, BuildSystem};
+use crate::process::on_path;
+use crate::workspace::{dir_size, CACHE_DIR_VARS};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs;
+use tokio::process::Command;
+
+/// How long `ccache -s` may take before the build is reported without ccache counts
+const CCACHE_STATS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cache statistics of one build, see [`CacheProbe`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Whether the build directory (`build/`, `.pio/build`, `target/`, ...) already held an
+    /// earlier build's output when the build started
+    pub build_dir_reused: bool,
+    /// Compilations ccache answered from its cache during the build. Builds sharing the
+    /// cache directory at the same time are counted too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ccache_hits: Option<u64>,
+    /// Compilations ccache had to run during the build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ccache_misses: Option<u64>,
+    /// Size of the tool caches the build was pointed at (`CCACHE_DIR`, `PLATFORMIO_CORE_DIR`,
+    /// `XDG_CACHE_HOME`) after the build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_bytes: Option<u64>,
+}
+
+/// Hit and miss counters from `ccache -s`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CcacheCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// What the caches looked like before a build, to compare with afterwards
+#[derive(Debug, Clone)]
+pub struct CacheProbe {
+    build_dir_reused: bool,
+    ccache: Option<CcacheCounts>,
+}
+
+impl CacheProbe {
+    /// Look at the build directories and ccache counters before `system` builds `path`
+    pub async fn start(path: &Path, system: BuildSystem, config: &BuildConfig) -> Self {
+        let mut build_dir_reused = false;
+        for dir in build_dirs(path, system, config) {
+            if has_entries(&dir).await {
+                build_dir_reused = true;
+                break;
+            }
+        }
+        Self { build_dir_reused, ccache: ccache_counts(config).await }
+    }
+
+    /// The build's statistics: ccache counters are the difference since [`start`](Self::start)
+    pub async fn finish(self, config: &BuildConfig) -> CacheStats {
+        let ccache = match (self.ccache, ccache_counts(config).await) {
+            (Some(before), Some(after)) => Some(CcacheCounts {
+                hits: after.hits.saturating_sub(before.hits),
+                misses: after.misses.saturating_sub(before.misses),
+            }),
+            _ => None,
+        };
+        let mut cache_bytes = None;
+        for (var, _) in CACHE_DIR_VARS {
+            if let Some(dir) = config.command_env.get(var) {
+                *cache_bytes.get_or_insert(0) += dir_size(Path::new(dir)).await;
+            }
+        }
+
+        CacheStats {
+            build_dir_reused: self.build_dir_reused,
+            ccache_hits: ccache.map(|counts| counts.hits),
+            ccache_misses: ccache.map(|counts| counts.misses),
+            cache_bytes,
+        }
+    }
+}
+
+/// Where `system` writes its build output under `path`, one directory per `matrix` entry
+fn build_dirs(path: &Path, system: BuildSystem, config: &BuildConfig) -> Vec<PathBuf> {
+    let default = match system {
+        BuildSystem::Cargo => match config.command_env.get("CARGO_TARGET_DIR") {
+            Some(dir) => return vec![path.join(dir)],
+            None => "target",
+        },
+        BuildSystem::PlatformIO => ".pio/build",
+        BuildSystem::Buildroot => "output",
+        BuildSystem::Dockerfile => return Vec::new(),
+        _ => "build",
+    };
+    if config.matrix.is_empty() {
+        return vec![path.join(default)];
+    }
+    config.matrix.iter().map(|entry| path.join(format!("{}-{}", default, entry.name))).collect()
+}
+
+async fn has_entries(dir: &Path) -> bool {
+    match fs::read_dir(dir).await {
+        Ok(mut entries) => matches!(entries.next_entry().await, Ok(Some(_))),
+        Err(_) => false,
+    }
+}
+
+/// Run `ccache -s` with the build's environment; `None` when ccache isn't installed
+async fn ccache_counts(config: &BuildConfig) -> Option<CcacheCounts> {
+    let search_path = config.command_env.get("PATH").map(OsString::from).or_else(|| std::env::var_os("PATH"));
+    if !on_path("ccache", search_path.as_deref()) {
+        return None;
+    }
+    let output = Command::new("ccache")
+        .arg("-s")
+        .envs(&config.command_env)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(CCACHE_STATS_TIMEOUT, output).await.ok()?.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_ccache_stats(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Hits and misses from the output of `ccache -s`, in the layout of ccache 4
+/// (`Hits: 12 / 20 (60.00 %)`) or ccache 3 (`cache hit (direct)  12`)
+pub fn parse_ccache_stats(output: &str) -> Option<CcacheCounts> {
+    let first_number = |text: &str| text.split_whitespace().next().and_then(|n| n.parse::<u64>().ok());
+    let (mut hits, mut misses) = (None, None);
+    let (mut legacy_hits, mut legacy_misses) = (None::<u64>, None);
+
+    for line in output.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Hits:") {
+            // ccache 4.6+ repeats Hits/Misses under "Local storage"; the first pair is the total
+            hits = hits.or_else(|| first_number(rest));
+        } else if let Some(rest) = line.strip_prefix("Misses:") {
+            misses = misses.or_else(|| first_number(rest));
+        } else if let Some(rest) = line.strip_prefix("cache hit (direct)").or_else(|| line.strip_prefix("cache hit (preprocessed)")) {
+            if let Some(n) = first_number(rest) {
+                *legacy_hits.get_or_insert(0) += n;
+            }
+        } else if let Some(rest) = line.strip_prefix("cache miss") {
+            legacy_misses = legacy_misses.or_else(|| first_number(rest));
+        }
+    }
+
+    match (hits.or(legacy_hits), misses.or(legacy_misses)) {
+        (None, None) => None,
+        (hits, misses) => Some(CcacheCounts { hits: hits.unwrap_or(0), misses: misses.unwrap_or(0) }),
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// A dotted CMake version such as `3.25` or `3.22.1`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CmakeVersion(pub Vec<u32>);
+
+impl CmakeVersion {
+    pub fn parse(version: &str) -> Option<Self> {
+        let components = version
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        Some(Self(components))
+    }
+
+    /// Compare component-wise, treating missing trailing components as 0 so `3.25` == `3.25.0`
+    pub fn at_least(&self, required: &CmakeVersion) -> bool {
+        let len = self.0.len().max(required.0.len());
+        let padded = |v: &CmakeVersion| (0..len).map(|i| v.0.get(i).copied().unwrap_or(0)).collect::<Vec<_>>();
+        padded(self) >= padded(required)
+    }
+}
+
+impl fmt::Display for CmakeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|part| part.to_string()).collect();
+        f.write_str(&parts.join("."))
+    }
+}
+
+/// The minimum from `cmake_minimum_required(VERSION 3.13)` in a CMakeLists.txt, including the
+/// `3.5...3.27` policy-range form and trailing `FATAL_ERROR`. CMake command names are
+/// case-insensitive.
+pub fn minimum_required_version(cmakelists: &str) -> Option<CmakeVersion> {
+    for line in cmakelists.lines() {
+        let line = line.trim();
+        if line.starts_with('#') || !line.to_ascii_lowercase().starts_with("cmake_minimum_required") {
+            continue;
+        }
+
+        let (_, args) = line.split_once('(')?;
+        let args = args.split(')').next().unwrap_or_default();
+        let mut words = args.split_whitespace();
+        if !words.any(|word| word.eq_ignore_ascii_case("VERSION")) {
+            return None;
+        }
+        let range = words.next()?;
+        let minimum = range.split("...").next().unwrap_or(range);
+        return CmakeVersion::parse(minimum);
+    }
+    None
+}
+
+/// The version from `cmake --version` output, e.g. `cmake version 3.22.1`
+pub fn parse_cmake_version(output: &str) -> Option<CmakeVersion> {
+    let first = output.lines().next()?;
+    let version = first.trim().strip_prefix("cmake version ")?;
+    // Development builds append suffixes such as `3.28.0-rc2` or `3.27.20230901-g1a2b3c`
+    let numeric = version.split(|c: char| !c.is_ascii_digit() && c != '.').next()?;
+    CmakeVersion::parse(numeric.trim_end_matches('.'))
+}
+
+/// An error message when the installed CMake is older than the project requires
+pub fn version_issue(required: &CmakeVersion, installed: &CmakeVersion) -> Option<String> {
+    if installed.at_least(required) {
+        return None;
+    }
+    Some(format!(
+        "CMake pre-flight check failed: CMakeLists.txt requires CMake {} or newer (cmake_minimum_required), \
+         but the runner has CMake {}. Upgrade CMake on the runner, or build in a `container` image that \
+         provides CMake {} or newer.",
+        required, installed, required
+    ))
+}
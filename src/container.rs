@@ -0,0 +1,475 @@
+use crate::core::{BuildSystem, ContainerConfig, PullPolicy};
+use crate::output::OutputBuffer;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::future::Future;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+const DEFAULT_PULL_TIMEOUT_SECS: u64 = 600;
+/// How long `docker rm -f` of a killed run's container may take
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Each build system's `NABLA_IMAGE_*` variable and the public image used when it's unset.
+/// Dockerfile builds bring their own image.
+const SYSTEM_IMAGES: &[(BuildSystem, &str, Option<&str>)] = &[
+    (BuildSystem::Cargo, "NABLA_IMAGE_CARGO", Some("docker.io/library/rust:1")),
+    (BuildSystem::Makefile, "NABLA_IMAGE_MAKEFILE", None),
+    (BuildSystem::CMake, "NABLA_IMAGE_CMAKE", None),
+    (BuildSystem::PlatformIO, "NABLA_IMAGE_PLATFORMIO", None),
+    (BuildSystem::ZephyrWest, "NABLA_IMAGE_ZEPHYR", Some("ghcr.io/zephyrproject-rtos/ci:latest")),
+    (BuildSystem::STM32CubeIDE, "NABLA_IMAGE_STM32CUBEIDE", None),
+    (BuildSystem::SCons, "NABLA_IMAGE_SCONS", None),
+    (BuildSystem::Buildroot, "NABLA_IMAGE_BUILDROOT", None),
+    (BuildSystem::Yocto, "NABLA_IMAGE_YOCTO", Some("docker.io/crops/poky:latest")),
+    (BuildSystem::MplabX, "NABLA_IMAGE_MPLABX", None),
+];
+
+/// Where the kernel lists registered binfmt_misc handlers, such as `qemu-aarch64`
+const BINFMT_MISC_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// qemu-user's name for each architecture it emulates, and docker's name for it
+const QEMU_ARCHES: &[(&str, &str)] = &[
+    ("x86_64", "amd64"),
+    ("aarch64", "arm64"),
+    ("arm", "arm"),
+    ("i386", "386"),
+    ("riscv64", "riscv64"),
+    ("ppc64le", "ppc64le"),
+    ("s390x", "s390x"),
+];
+
+/// The runner's CPU architecture and the others it can run containers of, in docker's
+/// names (`amd64`, `arm64`, ...), as found at startup
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HostArch {
+    pub arch: String,
+    /// Architectures with an enabled qemu-user binfmt_misc handler, which run emulated
+    pub emulated: Vec<String>,
+}
+
+impl HostArch {
+    /// The architecture the runner was built for and the emulators the kernel has registered
+    pub fn detect() -> Self {
+        Self::read(env::consts::ARCH, Path::new(BINFMT_MISC_DIR))
+    }
+
+    /// `rust_arch` as docker names it, with the emulators registered in `binfmt_misc`
+    pub fn read(rust_arch: &str, binfmt_misc: &Path) -> Self {
+        let arch = match rust_arch {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            "x86" => "386",
+            "powerpc64" => "ppc64le",
+            other => other,
+        };
+        let emulated = QEMU_ARCHES
+            .iter()
+            .filter(|(_, docker)| *docker != arch)
+            .filter(|(qemu, _)| {
+                std::fs::read_to_string(binfmt_misc.join(format!("qemu-{}", qemu)))
+                    .is_ok_and(|handler| handler.lines().next() == Some("enabled"))
+            })
+            .map(|(_, docker)| docker.to_string())
+            .collect();
+        Self { arch: arch.to_string(), emulated }
+    }
+
+    /// Whether the runner can run `platform`, e.g. `linux/arm64` or `linux/arm/v7`: natively,
+    /// or through a registered emulator. Platforms of other architectures are rejected rather
+    /// than left to fail with `exec format error` halfway through the build.
+    pub fn check_platform(&self, platform: &str) -> Result<()> {
+        let mut parts = platform.split('/');
+        let (os, arch) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+        if os != "linux" || !valid(arch) || parts.clone().count() > 1 || !parts.all(valid) {
+            return Err(anyhow!("Invalid container platform '{}': expected linux/<arch>[/<variant>], e.g. linux/arm64", platform));
+        }
+        if arch == self.arch || self.emulated.iter().any(|emulated| emulated == arch) {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "Container platform '{}' can't run on this {} runner: no qemu-user emulator for {} is registered with binfmt_misc (emulated: {})",
+            platform,
+            self.arch,
+            arch,
+            match self.emulated.is_empty() {
+                true => "none".to_string(),
+                false => self.emulated.join(", "),
+            }
+        ))
+    }
+}
+
+/// A parsed image reference such as `ghcr.io/acme/sdk:1.2@sha256:...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageRef {
+    /// Registry host, `docker.io` when the reference names none
+    pub registry: String,
+    /// Repository path, with Docker Hub's implicit `library/` for official images
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageRef {
+    /// `registry/repository`, the form allowlist entries are matched against
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+}
+
+/// Parse an image reference following the Docker reference grammar: lowercase path components
+/// separated by `/`, an optional registry host first, then an optional `:tag` and `@sha256:` digest.
+pub fn parse_image_reference(reference: &str) -> Result<ImageRef> {
+    let invalid = |why: &str| anyhow!("Invalid container image '{}': {}", reference, why);
+
+    let (rest, digest) = match reference.split_once('@') {
+        Some((rest, digest)) => {
+            let hex = digest.strip_prefix("sha256:").ok_or_else(|| invalid("digest must be sha256"))?;
+            if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)) {
+                return Err(invalid("digest must be 64 lowercase hex characters"));
+            }
+            (rest, Some(digest.to_string()))
+        }
+        None => (reference, None),
+    };
+
+    // A colon after the last slash starts the tag; earlier ones belong to a registry port
+    let (name, tag) = match rest.rfind(':') {
+        Some(at) if !rest[at..].contains('/') => (&rest[..at], Some(rest[at + 1..].to_string())),
+        _ => (rest, None),
+    };
+    if let Some(tag) = &tag {
+        let valid = (1..=128).contains(&tag.len())
+            && !tag.starts_with(['.', '-'])
+            && tag.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid {
+            return Err(invalid("malformed tag"));
+        }
+    }
+
+    let mut components: Vec<&str> = name.split('/').collect();
+    let registry = match components.first() {
+        Some(first) if components.len() > 1 && (first.contains(['.', ':']) || *first == "localhost") => {
+            let host = components.remove(0);
+            if !host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':')) {
+                return Err(invalid("malformed registry host"));
+            }
+            host.to_string()
+        }
+        _ => "docker.io".to_string(),
+    };
+
+    let valid_component = |component: &&str| {
+        !component.is_empty()
+            && component.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && component.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && component.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-'))
+    };
+    if components.is_empty() || !components.iter().all(valid_component) {
+        return Err(invalid("repository must be lowercase path components"));
+    }
+    if registry == "docker.io" && components.len() == 1 {
+        components.insert(0, "library");
+    }
+
+    Ok(ImageRef {
+        registry,
+        repository: components.join("/"),
+        tag,
+        digest,
+    })
+}
+
+/// Operator-side rules for per-request images, read from the environment so requests can
+/// never widen them
+#[derive(Debug, Clone, Default)]
+pub struct ContainerPolicy {
+    /// `docker` executable, `NABLA_DOCKER`
+    pub docker: String,
+    /// Registries or namespaces images may come from, e.g. `ghcr.io/acme`, from the
+    /// comma-separated `NABLA_CONTAINER_REGISTRIES`. Empty disables custom images.
+    pub allowed_images: Vec<String>,
+    /// `docker run` flags requests may pass, e.g. `--cpus`, from `NABLA_CONTAINER_RUN_ARGS`
+    pub allowed_run_args: Vec<String>,
+    pub pull_timeout: Duration,
+    /// The operator's image for each build system, used when a request's `container` names
+    /// none. From `NABLA_IMAGE_CARGO`, `NABLA_IMAGE_ZEPHYR` and so on, over built-in defaults
+    /// for Cargo, Zephyr and Yocto. These are trusted and need not be allowlisted.
+    pub images: HashMap<BuildSystem, String>,
+    /// What `container.platform` may name
+    pub host: HostArch,
+}
+
+impl ContainerPolicy {
+    pub fn from_env() -> Self {
+        let list = |name: &str| -> Vec<String> {
+            env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(|item| item.trim().trim_end_matches('/').to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        };
+
+        Self {
+            docker: env::var("NABLA_DOCKER").unwrap_or_else(|_| "docker".to_string()),
+            allowed_images: list("NABLA_CONTAINER_REGISTRIES"),
+            allowed_run_args: list("NABLA_CONTAINER_RUN_ARGS"),
+            pull_timeout: Duration::from_secs(
+                env::var("NABLA_CONTAINER_PULL_TIMEOUT_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_PULL_TIMEOUT_SECS),
+            ),
+            images: SYSTEM_IMAGES
+                .iter()
+                .filter_map(|&(system, var, default)| {
+                    let configured = env::var(var).ok().map(|image| image.trim().to_string()).filter(|image| !image.is_empty());
+                    Some((system, configured.or(default.map(String::from))?))
+                })
+                .collect(),
+            host: HostArch::detect(),
+        }
+    }
+
+    /// Whether the image's `registry/repository` falls under an allowlisted prefix. Prefixes
+    /// match whole path components, so `ghcr.io/acme` does not admit `ghcr.io/acme-evil`.
+    pub fn image_allowed(&self, image: &ImageRef) -> bool {
+        let name = image.name();
+        self.allowed_images.iter().any(|allowed| {
+            name == *allowed || name.strip_prefix(allowed.as_str()).is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+
+    /// Reject a request's container settings unless the operator allows them. Only an image
+    /// the request names must be allowlisted, and a `platform` must be one the host can run.
+    pub fn check(&self, container: &ContainerConfig) -> Result<()> {
+        if let Some(platform) = &container.platform {
+            self.host.check_platform(platform)?;
+        }
+        if let Some(requested) = &container.image {
+            let image = parse_image_reference(requested)?;
+            if !self.image_allowed(&image) {
+                return Err(anyhow!("Container image '{}' is not from an allowed registry", requested));
+            }
+        }
+
+        for arg in &container.run_args_allowlisted {
+            let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+            if !arg.contains('=') || !self.allowed_run_args.iter().any(|allowed| allowed == flag) {
+                return Err(anyhow!("Container run argument '{}' is not allowed (use --flag=value)", arg));
+            }
+        }
+        Ok(())
+    }
+
+    /// The image to build `system` in: the one `container` names, else the operator's
+    pub fn image_for(&self, container: &ContainerConfig, system: BuildSystem) -> Result<String> {
+        if let Some(image) = &container.image {
+            return Ok(image.clone());
+        }
+        self.images.get(&system).cloned().ok_or_else(|| {
+            let var = SYSTEM_IMAGES.iter().find(|(known, _, _)| *known == system).map_or("", |(_, var, _)| var);
+            match var {
+                "" => anyhow!("{:?} builds can't run in a container", system),
+                var => anyhow!("No container image for {:?} builds: name one in container.image or set {} on the runner", system, var),
+            }
+        })
+    }
+}
+
+/// What every command of a containerized build runs in
+#[derive(Debug, Clone)]
+pub struct ContainerContext {
+    pub docker: String,
+    pub image: String,
+    /// Resolved `repo@sha256:...` (or image ID) of `image`
+    pub digest: String,
+    /// Host directories mounted at the same path inside the container
+    pub mounts: Vec<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    pub run_args: Vec<String>,
+    /// `--platform` of the image, when the request named one
+    pub platform: Option<String>,
+}
+
+impl ContainerContext {
+    /// Check `container` against `policy`, pick the image for `system`, obtain it according to
+    /// its pull policy and resolve its digest.
+    pub async fn prepare(container: &ContainerConfig, system: BuildSystem, policy: &ContainerPolicy, mounts: Vec<PathBuf>) -> Result<Self> {
+        policy.check(container)?;
+        let image = policy.image_for(container, system)?;
+
+        let present = docker_output(&policy.docker, &["image", "inspect", &image], policy.pull_timeout).await.is_ok();
+        match container.pull_policy {
+            PullPolicy::Always => pull(policy, &image, container.platform.as_deref()).await?,
+            PullPolicy::IfNotPresent if !present => pull(policy, &image, container.platform.as_deref()).await?,
+            PullPolicy::Never if !present => {
+                return Err(anyhow!("Container image '{}' is not present and pull_policy is never", image));
+            }
+            _ => {}
+        }
+
+        let digest = docker_output(
+            &policy.docker,
+            &[
+                "image",
+                "inspect",
+                "--format",
+                "{{if .RepoDigests}}{{index .RepoDigests 0}}{{else}}{{.Id}}{{end}}",
+                &image,
+            ],
+            policy.pull_timeout,
+        )
+        .await?
+        .trim()
+        .to_string();
+        info!("Building in container image {} ({})", image, digest);
+
+        Ok(Self {
+            docker: policy.docker.clone(),
+            image,
+            digest,
+            mounts,
+            env: container.env.clone(),
+            run_args: container.run_args_allowlisted.clone(),
+            platform: container.platform.clone(),
+        })
+    }
+
+    /// Turn `command` into a `docker run` of the same program, arguments, working directory
+    /// and environment inside the image. Environment values are handed to docker through its
+    /// own environment (`-e NAME`), never on the command line. `isolate_network` adds
+    /// `--network none`. Each run gets its own container name, which the returned
+    /// [`RunningContainer`] removes the container by unless the run finished.
+    pub fn wrap(&self, command: &Command, isolate_network: bool) -> (Command, RunningContainer) {
+        let original = command.as_std();
+        let name = format!("nabla-run-{}", uuid::Uuid::new_v4());
+        let mut docker = Command::new(&self.docker);
+        docker.args(["run", "--rm"]).arg(format!("--name={}", name));
+        if let Some(platform) = &self.platform {
+            docker.arg("--platform").arg(platform);
+        }
+
+        // SAFETY: getuid/getgid cannot fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        docker.arg("--user").arg(format!("{}:{}", uid, gid));
+        if isolate_network {
+            docker.args(["--network", "none"]);
+        }
+        for mount in &self.mounts {
+            docker.arg("-v").arg(format!("{0}:{0}", mount.display()));
+        }
+        if let Some(dir) = original.get_current_dir() {
+            docker.arg("-w").arg(dir);
+        }
+
+        let explicit = original.get_envs().filter_map(|(name, value)| Some((name.to_os_string(), value?.to_os_string())));
+        let requested = self.env.iter().map(|(name, value)| (name.into(), value.into()));
+        for (name, value) in explicit.chain(requested) {
+            docker.arg("-e").arg(&name);
+            docker.env(name, value);
+        }
+
+        docker.args(&self.run_args);
+        docker.arg(&self.image).arg(original.get_program()).args(original.get_args());
+        (docker, RunningContainer { docker: self.docker.clone(), name, armed: true })
+    }
+}
+
+/// The container of one wrapped `docker run`. Killing the docker CLI, as a timeout or a
+/// cancelled build does, leaves the container running with the workspace mounted, and its
+/// `--rm` never fires; so it's force-removed unless [`finished`](Self::finished) says the run
+/// ended by itself.
+#[derive(Debug)]
+pub struct RunningContainer {
+    docker: String,
+    name: String,
+    armed: bool,
+}
+
+impl RunningContainer {
+    /// The run exited on its own, so `--rm` takes care of the container
+    pub fn finished(mut self) {
+        self.armed = false;
+    }
+
+    /// Force-remove the container now rather than on drop
+    pub async fn remove(mut self) {
+        self.armed = false;
+        if let Err(e) = docker_output(&self.docker, &["rm", "-f", &self.name], REMOVE_TIMEOUT).await {
+            warn!("Removing container {} failed: {}", self.name, e);
+        }
+    }
+}
+
+impl Drop for RunningContainer {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let (docker, name) = (self.docker.clone(), self.name.clone());
+        warn!("Build cancelled, removing container {}", name);
+        // Drop can't await, so remove from a plain thread
+        std::thread::spawn(move || {
+            let _ = std::process::Command::new(docker)
+                .args(["rm", "-f", &name])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        });
+    }
+}
+
+async fn pull(policy: &ContainerPolicy, image: &str, platform: Option<&str>) -> Result<()> {
+    info!("Pulling container image {}", image);
+    let args = match platform {
+        Some(platform) => vec!["pull", "--platform", platform, image],
+        None => vec!["pull", image],
+    };
+    docker_output(&policy.docker, &args, policy.pull_timeout)
+        .await
+        .map(|_| ())
+        .map_err(|e| anyhow!("Pulling container image '{}' failed: {}", image, e))
+}
+
+/// Run a docker CLI command, returning stdout. Registry credentials are the operator's
+/// (`DOCKER_CONFIG` / `~/.docker/config.json` of the runner), never the request's.
+async fn docker_output(docker: &str, args: &[&str], timeout: Duration) -> Result<String> {
+    let run = Command::new(docker)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(timeout, run)
+        .await
+        .map_err(|_| anyhow!("`{} {}` timed out after {}s", docker, args.join(" "), timeout.as_secs()))??;
+
+    if !output.status.success() {
+        return Err(anyhow!("{}", OutputBuffer::text(&output.stderr).trim()));
+    }
+    Ok(OutputBuffer::text(&output.stdout).to_string())
+}
+
+tokio::task_local! {
+    static CONTAINER: Arc<ContainerContext>;
+}
+
+/// Run `future` with every `run_command` it makes executed inside `context`'s image
+pub async fn in_container<F: Future>(context: Arc<ContainerContext>, future: F) -> F::Output {
+    CONTAINER.scope(context, future).await
+}
+
+/// The container of the enclosing `in_container` scope, if any
+pub fn current() -> Option<Arc<ContainerContext>> {
+    CONTAINER.try_with(Arc::clone).ok()
+}
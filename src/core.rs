@@ -1,13 +1,44 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::cache::CacheStats;
+use anyhow::{anyhow, Result};
+use jsonschema::error::{TypeKind, ValidationErrorKind};
+use jsonschema::primitive_type::PrimitiveType;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Component, Path};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BuildSystem {
+    Cargo,
     Makefile,
     CMake,
     PlatformIO,
     ZephyrWest,
     STM32CubeIDE,
     SCons,
+    Buildroot,
+    Yocto,
+    /// An MPLAB X project (`nbproject/`) built with a Microchip XC compiler
+    MplabX,
+    /// A repository whose `Dockerfile` performs the whole build
+    Dockerfile,
+}
+
+impl BuildSystem {
+    /// The format of the artifact a build usually produces, for clients preparing flashing
+    /// tooling before the build runs. A build may still deliver another, e.g. a PlatformIO
+    /// board that only links a `bin`.
+    pub fn default_artifact_format(self) -> &'static str {
+        match self {
+            BuildSystem::PlatformIO | BuildSystem::MplabX => "hex",
+            BuildSystem::Makefile | BuildSystem::SCons | BuildSystem::Dockerfile => "bin",
+            BuildSystem::Cargo | BuildSystem::CMake | BuildSystem::ZephyrWest | BuildSystem::STM32CubeIDE => "elf",
+            BuildSystem::Buildroot => "img",
+            BuildSystem::Yocto => "wic",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,5 +48,1206 @@ pub struct BuildResult {
     pub target_format: Option<String>,
     pub error_output: Option<String>,
     pub build_system: BuildSystem,
+    /// Wall-clock time from the start of the first attempt to the final outcome, including
+    /// transient-failure retries and the backoff between them
+    pub duration_ms: u64,
+    /// Every file produced by the build, primary artifact first.
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    /// Compiler warnings in the build output, excluding `warning_excludes` paths.
+    #[serde(default)]
+    pub warning_count: usize,
+    #[serde(default)]
+    pub error_count: usize,
+    /// Set instead of an artifact when the request ran the project's tests (`pio_test`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_results: Option<TestSummary>,
+    /// Outcome of each PlatformIO environment when `pio_envs` were built separately.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<EnvironmentResult>,
+    /// Outcome of each `pio_targets` entry per environment, in the order they ran
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<TargetResult>,
+    /// Outcome of each `matrix` entry, in matrix order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matrix: Vec<MatrixResult>,
+    /// Times the whole build was rerun after a transient failure.
+    #[serde(default)]
+    pub retries: u32,
+    /// Each run of the build, in order; the last is the one whose outcome this is
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<BuildAttempt>,
+    /// Problems the pre-flight check found in the project configuration (e.g. platformio.ini).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub config_warnings: Vec<String>,
+    #[serde(default)]
+    pub provenance: Provenance,
+    /// ccache hits and misses, build directory reuse and cache size, when the build ran
+    /// through [`FirmwareBuildRunner::run`](crate::FirmwareBuildRunner::run)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheStats>,
+    /// Compiler errors and warnings parsed from the build output, e.g. for inline annotations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Defects static analysis found, each with the `tool` that reported it (`pio_check`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub static_analysis: Vec<Diagnostic>,
+    /// What the build's commands printed to stdout, e.g. a size report, bounded like the build
+    /// log. From the last attempt only.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stdout: String,
+    /// What the build's commands printed to stderr, bounded the same way
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    /// Combined stdout and stderr of the `post_build` hook, when one ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_build_output: Option<String>,
+    /// What checking and installing the project's PlatformIO platforms logged, before the build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform_install_output: Option<String>,
+    /// Exit code of the command that failed the build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the command that failed the build, e.g. 9 (SIGKILL) when it ran
+    /// out of memory or 11 (SIGSEGV) when the compiler crashed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+}
+
+impl BuildResult {
+    /// Succeeded, but not in every environment or `matrix` entry
+    pub fn is_partial(&self) -> bool {
+        self.success && !self.failed_parts().is_empty()
+    }
+
+    /// Environments and `matrix` entries that didn't build
+    pub fn failed_parts(&self) -> Vec<String> {
+        let environments = self.environments.iter().filter(|environment| !environment.success).map(|environment| environment.env.clone());
+        environments.chain(self.matrix.iter().filter(|entry| !entry.success).map(|entry| entry.name.clone())).collect()
+    }
+
+    /// How long the attempt that produced this outcome took; the whole build when attempts
+    /// weren't recorded
+    pub fn final_attempt_ms(&self) -> u64 {
+        self.attempts.last().map_or(self.duration_ms, |attempt| attempt.duration_ms)
+    }
+}
+
+/// One run of a build, see [`BuildResult::attempts`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildAttempt {
+    /// 1 for the first run
+    pub attempt: u32,
     pub duration_ms: u64,
-}
\ No newline at end of file
+    /// Why the attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How a build was run, so its output can be attributed to an exact environment
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub network_policy: NetworkPolicy,
+    /// Whether the compile step actually ran without network access; isolation is best effort
+    /// and depends on the runner being allowed to create network namespaces.
+    pub network_isolated: bool,
+    /// Why the compile step ran with network access although `fetch-then-isolate` was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_isolation_unavailable: Option<String>,
+    /// Image the build ran in when `container` was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_image: Option<String>,
+    /// Resolved digest of `container_image`, identifying the exact build environment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_digest: Option<String>,
+    /// sha256 of the repository's source files before the build, leaving out paths its
+    /// `.gitignore` excludes; see [`crate::source::SourceSnapshot`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_sha256: Option<String>,
+    /// Timeout, output and artifact limits the build ran under; see [`crate::limits`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<crate::limits::BuildLimits>,
+    /// The requested `verbosity`, when it isn't `normal`
+    #[serde(default, skip_serializing_if = "Verbosity::is_normal")]
+    pub verbosity: Verbosity,
+}
+
+/// A client-chosen image to run the build in, e.g. a vendor SDK. Images must come from a
+/// registry the operator allowlisted; see [`crate::container::ContainerPolicy`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ContainerConfig {
+    /// The runner's image for the detected build system when left out, see
+    /// [`crate::container::ContainerPolicy::images`]
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+    /// Extra environment inside the container
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// `docker run` flags such as `--cpus=2`, each of which the operator must allow
+    #[serde(default)]
+    pub run_args_allowlisted: Vec<String>,
+    /// `docker --platform` of the image, e.g. `linux/arm64`. Another architecture than the
+    /// runner's needs a qemu-user emulator registered on the runner; see
+    /// [`crate::container::HostArch`].
+    #[serde(default)]
+    pub platform: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    Always,
+    #[default]
+    IfNotPresent,
+    Never,
+}
+
+/// When a build may use the network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkPolicy {
+    /// Every step has network access
+    #[default]
+    Allow,
+    /// Fetch dependencies (`pio pkg install`, `west update`, CMake configure) with network, then
+    /// compile in a network namespace with only loopback
+    FetchThenIsolate,
+}
+
+/// How much the build tools, and the runner itself, log during a build
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Verbosity {
+    /// Only warnings and errors, e.g. `make -s` or `pio run -s`
+    Quiet,
+    /// Each tool's own defaults
+    #[default]
+    Normal,
+    /// Full command lines, e.g. `make V=1`, `cmake --log-level=VERBOSE` or `pio run -v`, and the
+    /// runner's debug logs for the build
+    Verbose,
+    /// As much as each tool offers, e.g. `cargo build -vv` or `west -vv build`, and the runner's
+    /// trace logs for the build
+    Debug,
+}
+
+impl Verbosity {
+    pub fn is_normal(&self) -> bool {
+        *self == Verbosity::Normal
+    }
+
+    /// How many times the usual output cap a build at this verbosity keeps per command
+    pub fn log_scale(self) -> usize {
+        match self {
+            Verbosity::Quiet | Verbosity::Normal => 1,
+            Verbosity::Verbose => 4,
+            Verbosity::Debug => 8,
+        }
+    }
+}
+
+/// How one PlatformIO environment fared in a multi-environment build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentResult {
+    pub env: String,
+    pub success: bool,
+    /// Never built because an earlier environment failed under `fail_fast`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Paths of the environment's firmware relative to the project, e.g. `.pio/build/esp32/firmware.bin`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+    pub duration_ms: u64,
+}
+
+/// How one `pio_targets` entry fared in one PlatformIO environment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetResult {
+    pub env: String,
+    pub target: String,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Files the target wrote to the environment's build directory, relative to the project,
+    /// e.g. `.pio/build/esp32/littlefs.bin`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+    pub duration_ms: u64,
+}
+
+/// `pio run` targets that touch a device or the host rather than the build, refused in
+/// `pio_targets`
+pub const DENIED_PIO_TARGETS: &[&str] = &["upload", "uploadfs", "uploadfsota", "program", "monitor", "erase", "fuses", "bootloader"];
+
+/// How one `matrix` entry fared
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixResult {
+    pub name: String,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The entry's build directory relative to the project, e.g. `build-nucleo_f401re`
+    pub build_dir: String,
+    /// Paths of the entry's artifacts relative to the project
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+    pub duration_ms: u64,
+}
+
+/// One variant of a `matrix` build. Which fields apply depends on the build system:
+/// `env` for PlatformIO (`pio run -e`), `board` and `cmake_defines` for Zephyr (`west build
+/// -b`) and CMake (`-DBOARD=`), and `make_vars` for Makefile builds (`make VAR=value`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct MatrixEntry {
+    /// Labels the entry's results and artifacts and names its build directory; unique within
+    /// the matrix, of letters, digits, `-`, `_` and `.`
+    pub name: String,
+    #[serde(default)]
+    pub board: Option<String>,
+    #[serde(default)]
+    pub env: Option<String>,
+    #[serde(default)]
+    pub cmake_defines: BTreeMap<String, String>,
+    #[serde(default)]
+    pub make_vars: BTreeMap<String, String>,
+}
+
+/// Most entries a `matrix` may have
+pub const MAX_MATRIX_ENTRIES: usize = 32;
+
+/// `pio check` static analysis run after a successful PlatformIO build
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct PioCheck {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Fail the build when a defect of this severity or higher is found; unset only reports them
+    #[serde(default)]
+    pub severity_threshold: Option<CheckSeverity>,
+    /// Environments to check, each as `-e <env>`; empty checks the project's default environments
+    #[serde(default)]
+    pub environments: Vec<String>,
+    /// Limit on `pio check`, separate from the build's; [`DEFAULT_PIO_CHECK_TIMEOUT_SECS`] when unset
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub timeout_secs: Option<u64>,
+}
+
+pub const DEFAULT_PIO_CHECK_TIMEOUT_SECS: u64 = 600;
+
+/// Severity of a `pio check` defect, as PlatformIO labels it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckSeverity {
+    Low,
+    Medium,
+    High,
+}
+
+impl CheckSeverity {
+    /// PlatformIO's `high`, `medium` and `low` reported as error, warning and note
+    pub fn as_severity(self) -> Severity {
+        match self {
+            Self::High => Severity::Error,
+            Self::Medium => Severity::Warning,
+            Self::Low => Severity::Note,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Medium => "medium",
+            Self::Low => "low",
+        }
+    }
+
+    /// The check severity a [`Severity`] was reported as
+    pub fn from_severity(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => Self::High,
+            Severity::Warning => Self::Medium,
+            Severity::Note => Self::Low,
+        }
+    }
+}
+
+/// Pass/fail counts from a test run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestSummary {
+    pub total: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+/// A file produced by a build or derived from one by a post-processor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub path: String,
+    pub format: String,
+    /// Free-form facts attached by post-processors, e.g. `sha256`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Artifact {
+    pub fn new(path: impl Into<String>, format: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            format: format.into(),
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+/// A firmware image format a flashing pipeline can ask for, see `BuildConfig::artifact_format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactFormat {
+    /// Intel HEX
+    Hex,
+    /// Raw binary image
+    Bin,
+    Elf,
+}
+
+impl ArtifactFormat {
+    /// File extension and `Artifact::format` of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArtifactFormat::Hex => "hex",
+            ArtifactFormat::Bin => "bin",
+            ArtifactFormat::Elf => "elf",
+        }
+    }
+}
+
+/// An address such as `0x08000000` or `134217728`
+pub fn parse_address(address: &str) -> Option<u64> {
+    match address.strip_prefix("0x").or_else(|| address.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => address.parse().ok(),
+    }
+}
+
+/// Per-request build options, supplied as `build_config` on `/build`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct BuildConfig {
+    /// Install the PlatformIO platforms referenced by `platformio.ini` before `pio run`.
+    pub preinstall_platforms: bool,
+    /// C language standard such as `c11` or `gnu99`.
+    pub c_standard: Option<String>,
+    /// C++ language standard such as `c++17` or `gnu++20`.
+    pub cpp_standard: Option<String>,
+    /// Wall-clock limit for each build command; falls back to `NABLA_BUILD_TIMEOUT_SECS`.
+    #[schemars(range(min = 1))]
+    pub timeout_secs: Option<u64>,
+    /// Names of artifact post-processors to run in order, e.g. `["objcopy:bin", "checksum"]`.
+    pub post_processors: Vec<String>,
+    /// Buildroot defconfig to load, e.g. `raspberrypi4_defconfig`; auto-detected from `configs/` if unset.
+    pub defconfig: Option<String>,
+    /// MPLAB X configuration to build, as `make CONF=<name>`; the project's first (usually
+    /// `default`) if unset.
+    pub mplab_conf: Option<String>,
+    /// Template for artifact filenames, e.g. `{repo}-{sha}.{ext}`; [`DEFAULT_ARTIFACT_NAME`]
+    /// when unset. See [`ARTIFACT_NAME_PLACEHOLDERS`] for the supported fields.
+    pub artifact_name: Option<String>,
+    /// Board to build for, as `west build -b` or CMake's `-DBOARD=`, e.g. `nrf52840dk/nrf52840`.
+    /// A `matrix` entry's `board` takes its place.
+    pub board: Option<String>,
+    /// Zephyr snippets to apply, as `west build -S`, e.g. `["nordic-log-stm"]`.
+    pub snippets: Vec<String>,
+    /// Pass `--sysbuild` to `west build` to produce every image (e.g. MCUboot + app). Also
+    /// on when the application has a `sysbuild.conf` or `sysbuild.cmake`.
+    pub sysbuild: bool,
+    /// MCUboot signing key for sysbuild, a PEM file inside the repository, e.g. `keys/root-ec-p256.pem`.
+    /// Implies `sysbuild`.
+    pub mcuboot_key: Option<String>,
+    /// Name of a `secret_env` entry holding the MCUboot signing key's PEM, for keys kept out
+    /// of the repository. Implies `sysbuild`.
+    pub mcuboot_key_secret: Option<String>,
+    /// Fail an otherwise successful build that produced compiler warnings.
+    pub fail_on_warnings: bool,
+    /// Globs for source paths whose warnings are not counted, e.g. `vendor/**`.
+    pub warning_excludes: Vec<String>,
+    /// Run `pio test` instead of `pio run` and report test results rather than an artifact.
+    pub pio_test: bool,
+    /// Restrict `pio test` to one environment, e.g. `native`.
+    pub pio_test_env: Option<String>,
+    /// PlatformIO environments to build, each as its own `pio run -e <env>` in parallel.
+    /// Empty builds the project's default environments with a single `pio run`.
+    pub pio_envs: Vec<String>,
+    /// Run `pio check` after a successful build and report its defects in `static_analysis`.
+    pub pio_check: Option<PioCheck>,
+    /// Extra `pio run -t <target>` targets to run in each environment after a successful
+    /// build, e.g. `buildfs` or `size`. Files they write to `.pio/build/<env>` become
+    /// artifacts. Upload and monitor targets ([`DENIED_PIO_TARGETS`]) are refused.
+    pub pio_targets: Vec<String>,
+    /// Cap on environments (or `matrix` entries) built at once; falls back to
+    /// `NABLA_PIO_PARALLEL_ENVS`, then the CPU count.
+    #[schemars(range(min = 1))]
+    pub max_parallel_envs: Option<usize>,
+    /// Stop starting `pio_envs` once one has failed; those not yet started are reported skipped.
+    pub fail_fast: bool,
+    /// Fail the build unless every environment (or `matrix` entry) built. Otherwise a build
+    /// where only some did succeeds as partially completed.
+    pub require_all: bool,
+    /// Build the project once per entry, e.g. for several boards, each in its own build
+    /// directory of the same workspace. See [`MatrixEntry`].
+    #[schemars(length(max = "MAX_MATRIX_ENTRIES"))]
+    pub matrix: Vec<MatrixEntry>,
+    /// The `matrix` entry this build is for, set by the runner for each entry's build
+    #[serde(skip)]
+    pub matrix_entry: Option<MatrixEntry>,
+    /// Reruns allowed after a failure matching a transient error pattern; falls back to
+    /// `NABLA_TRANSIENT_RETRIES`.
+    #[schemars(range(max = "MAX_TRANSIENT_RETRIES"))]
+    pub transient_retries: Option<u32>,
+    /// Extra case-insensitive substrings, on top of the built-in network errors, that mark a
+    /// failure as transient.
+    pub transient_error_patterns: Vec<String>,
+    /// Fail before building when the pre-flight check finds problems in platformio.ini,
+    /// instead of reporting them as warnings.
+    pub strict_config: bool,
+    /// Whether the compile step may reach the network; see [`NetworkPolicy`].
+    pub network_policy: NetworkPolicy,
+    /// How much the build tools log; `verbose` and `debug` also keep more output per command
+    /// and log the runner's own debug lines for the build. See [`Verbosity`].
+    pub verbosity: Verbosity,
+    /// Run every build command inside this image instead of on the runner.
+    pub container: Option<ContainerConfig>,
+    /// Upload the artifact to this bucket instead of returning it inline. Requires the `s3` feature.
+    pub s3: Option<S3Target>,
+    /// Environment for every build command whose values are never shown: they are replaced
+    /// with `***` in build output, errors, logs and this config's Debug/serialized forms.
+    pub secret_env: SecretEnv,
+    /// Extra environment for every build command, set by the server (e.g. per-customer cache
+    /// directories). Never read from client-supplied config.
+    #[serde(skip)]
+    pub command_env: BTreeMap<String, String>,
+    /// Shell command run in the repository after a successful build, with `{artifact}`
+    /// replaced by the primary artifact's path, e.g. `./sign.sh {artifact}`. Only allowed when
+    /// the server sets `NABLA_ALLOW_HOOKS=1`.
+    pub post_build: Option<String>,
+    /// Absolute path of the artifact inside the image a `Dockerfile` build produces, e.g.
+    /// `/out/firmware.bin`
+    pub artifact_in_image: Option<String>,
+    /// Rust target triple to cross-compile for, e.g. `thumbv7em-none-eabihf`; defaults to the
+    /// `[build] target` in `.cargo/config.toml`, then the host.
+    pub cargo_target: Option<String>,
+    /// Cargo features to enable.
+    pub features: Vec<String>,
+    /// Pass `--no-default-features` to `cargo build`.
+    pub no_default_features: bool,
+    /// Build with the release profile instead of dev.
+    pub release: bool,
+    /// Workspace member to build, as `cargo build -p`; picked from the workspace when unset.
+    pub cargo_package: Option<String>,
+    /// Binary target to build, as `cargo build --bin`; the package's only binary when unset.
+    pub cargo_bin: Option<String>,
+    /// Only take files the build created or modified as artifacts, so a binary committed to
+    /// the repository is never returned in place of the build's output.
+    pub artifacts_new_only: bool,
+    /// Globs relative to the repository, e.g. `out/*.elf`, tried in order before the built-in
+    /// output names when looking for the artifact.
+    pub artifact_globs: Vec<String>,
+    /// Fetch submodules `.gitmodules` declares, which archives leave out, with git before
+    /// building. Needs a GitHub `archive_url` to clone from.
+    pub fetch_submodules: bool,
+    /// Directory to detect and build in, relative to the repository root, e.g. `apps/sensor`
+    /// in a monorepo. Replaces descending into a lone subdirectory.
+    pub project_dir: Option<String>,
+    /// SCons build file relative to the repository, e.g. `build/SConstruct` or
+    /// `firmware.scons`. `scons -f` runs from its directory. Defaults to the SConstruct
+    /// detection found.
+    pub sconstruct: Option<String>,
+    /// Jobs `scons -j` runs at once.
+    #[schemars(range(min = 1))]
+    pub scons_jobs: Option<u32>,
+    /// Formats to deliver the firmware in, most preferred first, e.g. `["hex", "bin"]`. The
+    /// first one the build produced, or that an ELF (or, for `hex`, a raw bin) converts to,
+    /// becomes the primary artifact.
+    pub artifact_format: Vec<ArtifactFormat>,
+    /// Address a raw `.bin` image is loaded at, e.g. `0x08000000`; needed to convert one to
+    /// Intel HEX.
+    pub bin_load_address: Option<String>,
+    /// Reject fields this runner doesn't know (the default). `false` ignores them instead,
+    /// for clients that also talk to newer runners.
+    pub strict: bool,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        Self {
+            preinstall_platforms: true,
+            c_standard: None,
+            cpp_standard: None,
+            timeout_secs: None,
+            post_processors: Vec::new(),
+            defconfig: None,
+            mplab_conf: None,
+            artifact_name: None,
+            board: None,
+            snippets: Vec::new(),
+            sysbuild: false,
+            mcuboot_key: None,
+            mcuboot_key_secret: None,
+            fail_on_warnings: false,
+            warning_excludes: Vec::new(),
+            pio_test: false,
+            pio_test_env: None,
+            pio_envs: Vec::new(),
+            pio_check: None,
+            pio_targets: Vec::new(),
+            max_parallel_envs: None,
+            fail_fast: false,
+            require_all: false,
+            matrix: Vec::new(),
+            matrix_entry: None,
+            transient_retries: None,
+            transient_error_patterns: Vec::new(),
+            strict_config: false,
+            network_policy: NetworkPolicy::Allow,
+            verbosity: Verbosity::Normal,
+            container: None,
+            s3: None,
+            secret_env: SecretEnv::default(),
+            command_env: BTreeMap::new(),
+            post_build: None,
+            artifact_in_image: None,
+            cargo_target: None,
+            features: Vec::new(),
+            no_default_features: false,
+            release: false,
+            cargo_package: None,
+            cargo_bin: None,
+            artifacts_new_only: false,
+            artifact_globs: Vec::new(),
+            fetch_submodules: false,
+            project_dir: None,
+            sconstruct: None,
+            scons_jobs: None,
+            artifact_format: Vec::new(),
+            bin_load_address: None,
+            strict: true,
+        }
+    }
+}
+
+/// A field of a request's `build_config` that doesn't match [`build_config_schema`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigViolation {
+    /// JSON pointer into `build_config`, e.g. `/container/pull_policy`
+    pub path: String,
+    /// What the field must be, e.g. `must be a positive integer`
+    pub message: String,
+}
+
+impl ConfigViolation {
+    /// The field as a client writes it, e.g. `build_config.container.pull_policy` or
+    /// `build_config.pio_envs[0]`
+    pub fn field(&self) -> String {
+        let mut field = "build_config".to_string();
+        for segment in self.path.split('/').skip(1) {
+            let segment = segment.replace("~1", "/").replace("~0", "~");
+            if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) {
+                field.push_str(&format!("[{}]", segment));
+            } else {
+                field.push('.');
+                field.push_str(&segment);
+            }
+        }
+        field
+    }
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.field(), self.message)
+    }
+}
+
+/// JSON Schema (draft 7) of `build_config`, derived from [`BuildConfig`]. Optional fields are
+/// described by their value's schema alone, so a bad nested value is reported at its own path;
+/// `null` is accepted for them like an absent field.
+pub fn build_config_schema() -> serde_json::Value {
+    let generator = schemars::gen::SchemaSettings::draft07()
+        .with(|settings| settings.option_add_null_type = false)
+        .into_generator();
+    serde_json::to_value(generator.into_root_schema_for::<BuildConfig>()).expect("schema serializes")
+}
+
+/// `value` without object members that are `null`, which serde treats like missing fields
+fn without_nulls(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => map
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.clone(), without_nulls(v)))
+            .collect(),
+        serde_json::Value::Array(items) => items.iter().map(without_nulls).collect(),
+        other => other.clone(),
+    }
+}
+
+/// Every schema violation in a raw `build_config`, not just the first. Unknown fields are
+/// violations unless the config sets `"strict": false`.
+pub fn check_build_config(config: &serde_json::Value) -> Vec<ConfigViolation> {
+    static SCHEMA: std::sync::OnceLock<(serde_json::Value, jsonschema::JSONSchema)> = std::sync::OnceLock::new();
+    let (raw_schema, schema) = SCHEMA.get_or_init(|| {
+        let raw = build_config_schema();
+        let compiled = jsonschema::JSONSchema::options()
+            .with_draft(jsonschema::Draft::Draft7)
+            .compile(&raw)
+            .expect("BuildConfig schema compiles");
+        (raw, compiled)
+    });
+    let strict = config.get("strict") != Some(&serde_json::Value::Bool(false));
+
+    let config = without_nulls(config);
+    let Err(errors) = schema.validate(&config) else {
+        return Vec::new();
+    };
+    let escape = |field: &str| field.replace('~', "~0").replace('/', "~1");
+    let mut violations = Vec::new();
+    for error in errors {
+        let path = error.instance_path.to_string();
+        match &error.kind {
+            ValidationErrorKind::AdditionalProperties { unexpected } => {
+                if strict {
+                    violations.extend(unexpected.iter().map(|field| ConfigViolation {
+                        path: format!("{}/{}", path, escape(field)),
+                        message: "is not a known field".to_string(),
+                    }));
+                }
+            }
+            ValidationErrorKind::Required { property } => violations.push(ConfigViolation {
+                path: format!("{}/{}", path, escape(property.as_str().unwrap_or_default())),
+                message: "is required".to_string(),
+            }),
+            _ => violations.push(ConfigViolation {
+                path,
+                message: violation_message(&error, raw_schema),
+            }),
+        }
+    }
+    violations
+}
+
+/// What a field that failed `error` must be, in terms of its schema: `must be a positive
+/// integer` rather than `-1 is less than the minimum of 1`
+fn violation_message(error: &jsonschema::ValidationError, schema: &serde_json::Value) -> String {
+    // The failed keyword's schema, e.g. `/properties/timeout_secs` for `/properties/timeout_secs/type`
+    let keyword_path = error.schema_path.to_string();
+    let field_schema = keyword_path
+        .rsplit_once('/')
+        .and_then(|(parent, _)| schema_at(schema, parent))
+        .unwrap_or(&serde_json::Value::Null);
+    let number = |limit: &serde_json::Value| match limit.as_f64() {
+        Some(n) if n.fract() == 0.0 => format!("{}", n as i64),
+        _ => limit.to_string(),
+    };
+    let one_of = |options: Vec<&serde_json::Value>| {
+        let options: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+        format!("must be one of {}", options.join(", "))
+    };
+
+    match &error.kind {
+        ValidationErrorKind::Type { .. } | ValidationErrorKind::Minimum { .. } | ValidationErrorKind::Maximum { .. }
+            if field_schema["type"] == "integer" =>
+        {
+            match (field_schema.get("minimum").map(number), field_schema.get("maximum").map(number)) {
+                (Some(min), Some(max)) => format!("must be an integer from {} to {}", min, max),
+                (Some(min), None) if min == "1" => "must be a positive integer".to_string(),
+                (Some(min), None) if min == "0" => "must be a non-negative integer".to_string(),
+                (Some(min), None) => format!("must be an integer of at least {}", min),
+                (None, Some(max)) => format!("must be an integer of at most {}", max),
+                (None, None) => "must be an integer".to_string(),
+            }
+        }
+        ValidationErrorKind::Type { kind: TypeKind::Single(kind) } => {
+            let article = if matches!(kind, PrimitiveType::Array | PrimitiveType::Integer | PrimitiveType::Object) { "an" } else { "a" };
+            format!("must be {} {}", article, kind)
+        }
+        ValidationErrorKind::Minimum { limit } => format!("must be at least {}", number(limit)),
+        ValidationErrorKind::Maximum { limit } => format!("must be at most {}", number(limit)),
+        ValidationErrorKind::Enum { options } => one_of(options.as_array().map(|o| o.iter().collect()).unwrap_or_default()),
+        // Unit enum variants with doc comments become a `oneOf` of single-value enums
+        ValidationErrorKind::OneOfNotValid => {
+            let options: Vec<&serde_json::Value> = field_schema["oneOf"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|variant| variant["enum"].as_array())
+                .flatten()
+                .collect();
+            if options.is_empty() {
+                error.to_string()
+            } else {
+                one_of(options)
+            }
+        }
+        _ => error.to_string(),
+    }
+}
+
+/// The schema at `pointer`, following the `$ref`s that the validator follows transparently
+fn schema_at<'a>(root: &'a serde_json::Value, pointer: &str) -> Option<&'a serde_json::Value> {
+    let resolve = |node: &'a serde_json::Value| match node.get("$ref").and_then(|r| r.as_str()) {
+        Some(reference) => reference.strip_prefix('#').and_then(|target| root.pointer(target)),
+        None => Some(node),
+    };
+    let mut node = root;
+    for segment in pointer.split('/').skip(1) {
+        let next = match node {
+            serde_json::Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => node.get(segment),
+        };
+        node = match next {
+            Some(next) => next,
+            None => resolve(node)?.get(segment)?,
+        };
+    }
+    resolve(node)
+}
+
+/// Environment variables that must not be disclosed, such as API keys compiled into firmware
+/// or signing passphrases. Only [`SecretEnv::expose`] gives access to the values.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct SecretEnv(BTreeMap<String, String>);
+
+impl SecretEnv {
+    pub fn new(vars: BTreeMap<String, String>) -> Self {
+        Self(vars)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The variables with their real values, for setting on a build command
+    pub fn expose(&self) -> &BTreeMap<String, String> {
+        &self.0
+    }
+
+    /// Replace every secret value in `text` with `***`, longest first so a secret containing
+    /// another is fully hidden.
+    pub fn redact(&self, text: &str) -> String {
+        let mut values: Vec<&str> = self.0.values().map(String::as_str).filter(|v| !v.is_empty()).collect();
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        values.iter().fold(text.to_string(), |text, value| text.replace(value, "***"))
+    }
+
+    fn redacted(&self) -> BTreeMap<&str, &str> {
+        self.0.keys().map(|key| (key.as_str(), "***")).collect()
+    }
+}
+
+impl std::fmt::Debug for SecretEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.redacted()).finish()
+    }
+}
+
+impl Serialize for SecretEnv {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.redacted().serialize(serializer)
+    }
+}
+
+/// Where a successful build's artifact is uploaded. Credentials come from the standard AWS
+/// environment (`AWS_ACCESS_KEY_ID`, profiles, instance roles), and `AWS_ENDPOINT_URL` points
+/// at S3-compatible stores.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct S3Target {
+    pub bucket: String,
+    pub key: String,
+    /// Falls back to the standard AWS region configuration.
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+/// An uploaded artifact, as reported in the build response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Object {
+    pub bucket: String,
+    pub key: String,
+    pub url: String,
+}
+
+/// Upper bound on `transient_retries`, so a misconfigured client can't rebuild indefinitely
+pub const MAX_TRANSIENT_RETRIES: u32 = 5;
+
+impl BuildConfig {
+    /// The board to build for: the `matrix` entry's, or else `board`
+    pub fn board(&self) -> Option<&str> {
+        self.matrix_entry.as_ref().and_then(|entry| entry.board.as_deref()).or(self.board.as_deref())
+    }
+
+    /// Where build output goes, relative to the repository: the build system's `default`, or
+    /// `<default>-<name>` for a `matrix` entry so entries never share one
+    pub fn build_dir(&self, default: &str) -> String {
+        match &self.matrix_entry {
+            Some(entry) => format!("{}-{}", default, entry.name),
+            None => default.to_string(),
+        }
+    }
+
+    fn validate_matrix(&self) -> Result<()> {
+        if self.matrix.len() > MAX_MATRIX_ENTRIES {
+            return Err(anyhow!("Invalid matrix - at most {} entries", MAX_MATRIX_ENTRIES));
+        }
+        if !self.matrix.is_empty() && (!self.pio_envs.is_empty() || self.pio_test) {
+            return Err(anyhow!("Invalid matrix - can't be combined with pio_envs or pio_test"));
+        }
+        let mut names = std::collections::HashSet::new();
+        for entry in &self.matrix {
+            let path_safe = entry.name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if entry.name.is_empty() || entry.name.len() > 64 || entry.name.starts_with('.') || !path_safe {
+                return Err(anyhow!(
+                    "Invalid matrix entry name '{}' - must be 1-64 letters, digits, '-', '_' or '.', not starting with '.'",
+                    entry.name
+                ));
+            }
+            if !names.insert(entry.name.as_str()) {
+                return Err(anyhow!("Invalid matrix - entry name '{}' is used more than once", entry.name));
+            }
+            if let Some(env) = entry.env.as_deref().filter(|env| !is_valid_pio_env(env)) {
+                return Err(anyhow!("Invalid matrix entry '{}' env '{}'", entry.name, env));
+            }
+            if let Some(board) = entry.board.as_deref().filter(|board| !is_valid_board(board)) {
+                return Err(anyhow!("Invalid matrix entry '{}' board '{}'", entry.name, board));
+            }
+            let invalid_name = |name: &String| name.is_empty() || name.starts_with('-') || name.contains(['=', ' ', '\t', '\n']);
+            if let Some(name) = entry.cmake_defines.keys().chain(entry.make_vars.keys()).find(|name| invalid_name(name)) {
+                return Err(anyhow!("Invalid matrix entry '{}' variable '{}'", entry.name, name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Environment variable names: `[A-Za-z_][A-Za-z0-9_]*`
+fn is_valid_env_name(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// PlatformIO environment names end up on the command line; keep them to `[A-Za-z0-9_.-]`
+fn is_valid_pio_env(env: &str) -> bool {
+    !env.is_empty() && env.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Board names such as `nrf5340dk/nrf5340/cpuapp/ns` or `esp32s3_devkitc@1.0.0`
+fn is_valid_board(board: &str) -> bool {
+    !board.starts_with(['-', '/']) && board.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '@')) && !board.is_empty()
+}
+
+impl BuildConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout_secs == Some(0) {
+            return Err(anyhow!("Invalid timeout_secs - must be greater than zero"));
+        }
+
+        if self.post_build.as_ref().is_some_and(|hook| hook.trim().is_empty()) {
+            return Err(anyhow!("Invalid post_build - must be a non-empty command"));
+        }
+
+        if self.artifact_in_image.as_ref().is_some_and(|path| !path.starts_with('/')) {
+            return Err(anyhow!("Invalid artifact_in_image - must be an absolute path inside the image"));
+        }
+
+        if let Some(std) = &self.c_standard {
+            if language_standard_version(std, "c").is_none() {
+                return Err(anyhow!("Invalid c_standard '{}' - expected e.g. c99, c11, gnu17", std));
+            }
+        }
+
+        if let Some(std) = &self.cpp_standard {
+            if language_standard_version(std, "c++").is_none() {
+                return Err(anyhow!("Invalid cpp_standard '{}' - expected e.g. c++14, c++17, gnu++20", std));
+            }
+        }
+
+        if let Some(defconfig) = &self.defconfig {
+            let valid_chars = defconfig
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if !valid_chars || !defconfig.ends_with("_defconfig") {
+                return Err(anyhow!("Invalid defconfig '{}' - expected e.g. raspberrypi4_defconfig", defconfig));
+            }
+        }
+
+        if let Some(conf) = &self.mplab_conf {
+            let valid_chars = conf.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if conf.is_empty() || !valid_chars {
+                return Err(anyhow!("Invalid mplab_conf '{}' - expected a configuration name such as default", conf));
+            }
+        }
+
+        if let Some(env) = &self.pio_test_env {
+            if !is_valid_pio_env(env) {
+                return Err(anyhow!("Invalid pio_test_env '{}'", env));
+            }
+        }
+
+        if let Some(env) = self.pio_envs.iter().find(|env| !is_valid_pio_env(env)) {
+            return Err(anyhow!("Invalid pio_envs entry '{}'", env));
+        }
+
+        if let Some(target) = &self.cargo_target {
+            let valid_chars = target.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if target.is_empty() || target.starts_with('-') || !valid_chars {
+                return Err(anyhow!("Invalid cargo_target '{}' - expected a target triple, e.g. thumbv7em-none-eabihf", target));
+            }
+        }
+
+        for (field, name) in [("cargo_package", &self.cargo_package), ("cargo_bin", &self.cargo_bin)] {
+            if let Some(name) = name {
+                let valid_chars = name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+                if name.is_empty() || name.starts_with('-') || !valid_chars {
+                    return Err(anyhow!("Invalid {} '{}'", field, name));
+                }
+            }
+        }
+
+        let valid_glob = |glob: &String| !glob.is_empty() && !glob.starts_with('/') && !glob.split('/').any(|part| part == "..");
+        if let Some(glob) = self.artifact_globs.iter().find(|glob| !valid_glob(glob)) {
+            return Err(anyhow!("Invalid artifact_globs entry '{}' - must be relative to the repository", glob));
+        }
+
+        if let Some(dir) = &self.project_dir {
+            let inside = Path::new(dir).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if dir.is_empty() || !inside {
+                return Err(anyhow!("Invalid project_dir '{}' - must be a directory inside the repository", dir));
+            }
+        }
+
+        if let Some(file) = &self.sconstruct {
+            let inside = Path::new(file).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if file.is_empty() || file.ends_with('/') || !inside {
+                return Err(anyhow!("Invalid sconstruct '{}' - must be a file inside the repository", file));
+            }
+        }
+
+        if let Some(file) = &self.mcuboot_key {
+            let inside = Path::new(file).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if file.is_empty() || file.ends_with('/') || !inside {
+                return Err(anyhow!("Invalid mcuboot_key '{}' - must be a file inside the repository", file));
+            }
+        }
+
+        if let Some(name) = &self.mcuboot_key_secret {
+            if self.mcuboot_key.is_some() {
+                return Err(anyhow!("Invalid mcuboot_key_secret - mcuboot_key is already set"));
+            }
+            if !self.secret_env.expose().contains_key(name) {
+                return Err(anyhow!("Invalid mcuboot_key_secret '{}' - not in secret_env", name));
+            }
+        }
+
+        if let Some(board) = self.board.as_deref().filter(|board| !is_valid_board(board)) {
+            return Err(anyhow!("Invalid board '{}'", board));
+        }
+        if let Some(snippet) = self.snippets.iter().find(|snippet| !is_valid_pio_env(snippet)) {
+            return Err(anyhow!("Invalid snippet '{}'", snippet));
+        }
+
+        self.validate_matrix()?;
+
+        if self.scons_jobs == Some(0) {
+            return Err(anyhow!("Invalid scons_jobs - must be greater than zero"));
+        }
+
+        if let Some(address) = self.bin_load_address.as_deref().filter(|address| parse_address(address).is_none()) {
+            return Err(anyhow!("Invalid bin_load_address '{}' - expected e.g. 0x08000000", address));
+        }
+
+        // `dep:name` and `crate/feature` are feature syntax too
+        let valid_feature = |feature: &String| {
+            !feature.is_empty()
+                && !feature.starts_with('-')
+                && feature.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '/' | ':' | '+' | '.'))
+        };
+        if let Some(feature) = self.features.iter().find(|feature| !valid_feature(feature)) {
+            return Err(anyhow!("Invalid features entry '{}'", feature));
+        }
+
+        if self.transient_retries.is_some_and(|retries| retries > MAX_TRANSIENT_RETRIES) {
+            return Err(anyhow!("Invalid transient_retries - at most {} allowed", MAX_TRANSIENT_RETRIES));
+        }
+
+        if let Some(s3) = &self.s3 {
+            if !cfg!(feature = "s3") {
+                return Err(anyhow!("S3 upload is not available - the runner was built without the `s3` feature"));
+            }
+            if s3.bucket.is_empty() || s3.key.is_empty() {
+                return Err(anyhow!("Invalid s3 target - bucket and key are required"));
+            }
+        }
+
+        if let Some(key) = self.secret_env.expose().keys().find(|key| !is_valid_env_name(key)) {
+            return Err(anyhow!("Invalid secret_env name '{}'", key));
+        }
+
+        if let Some(container) = &self.container {
+            if let Some(image) = &container.image {
+                crate::container::parse_image_reference(image)?;
+            }
+            if let Some(name) = container.env.keys().find(|name| !is_valid_env_name(name)) {
+                return Err(anyhow!("Invalid container env name '{}'", name));
+            }
+        }
+
+        if let Some(check) = &self.pio_check {
+            if check.timeout_secs == Some(0) {
+                return Err(anyhow!("Invalid pio_check.timeout_secs - must be greater than zero"));
+            }
+            if let Some(env) = check.environments.iter().find(|env| env.is_empty() || env.starts_with('-')) {
+                return Err(anyhow!("Invalid pio_check.environments entry '{}'", env));
+            }
+        }
+
+        if let Some(target) = self.pio_targets.iter().find(|target| target.is_empty() || target.starts_with('-')) {
+            return Err(anyhow!("Invalid pio_targets entry '{}'", target));
+        }
+        if let Some(target) = self.pio_targets.iter().find(|target| DENIED_PIO_TARGETS.contains(&target.as_str())) {
+            return Err(anyhow!("pio_targets entry '{}' is not allowed - runners never upload to or monitor a device", target));
+        }
+
+        if self.max_parallel_envs == Some(0) {
+            return Err(anyhow!("Invalid max_parallel_envs - must be greater than zero"));
+        }
+
+        if let Some(template) = &self.artifact_name {
+            render_artifact_name(template, |name| {
+                ARTIFACT_NAME_PLACEHOLDERS.contains(&name).then(|| "x".to_string())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fields available to the `artifact_name` template
+pub const ARTIFACT_NAME_PLACEHOLDERS: &[&str] = &[
+    "owner",
+    "repo",
+    "installation_id",
+    "job_id",
+    "sha",
+    "short_sha",
+    "env_or_target",
+    "build_system",
+    "ext",
+];
+
+/// How artifacts are named when a request doesn't set `artifact_name`
+pub const DEFAULT_ARTIFACT_NAME: &str = "{repo}-{short_sha}-{env_or_target}-{build_system}.{ext}";
+
+/// Expand `{field}` placeholders in an artifact filename template. `lookup` returns `None` for
+/// unknown fields. Substituted values are reduced to ASCII letters, digits, `.`, `-` and `_` so
+/// the result is safe as a filename or object key; separators in the template itself are
+/// rejected. A placeholder that comes out empty, such as a missing sha, takes the `-`, `_` or
+/// `.` before it along.
+pub fn render_artifact_name(template: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    if template.contains('/') || template.contains('\\') {
+        return Err(anyhow!("Invalid artifact_name '{}' - path separators are not allowed", template));
+    }
+
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Invalid artifact_name '{}' - unclosed '{{'", template))?;
+        let field = &rest[start + 1..start + end];
+        let value = lookup(field).ok_or_else(|| {
+            anyhow!(
+                "Invalid artifact_name '{}' - unknown placeholder '{{{}}}', expected one of {}",
+                template,
+                field,
+                ARTIFACT_NAME_PLACEHOLDERS.join(", ")
+            )
+        })?;
+        match sanitize_name_component(&value) {
+            value if value.is_empty() => {
+                if rendered.ends_with(NAME_SEPARATORS) {
+                    rendered.pop();
+                }
+            }
+            value => rendered.push_str(&value),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(anyhow!("Invalid artifact_name '{}' - unmatched '}}'", template));
+    }
+    rendered.push_str(rest);
+    let rendered = rendered.trim_start_matches(NAME_SEPARATORS).to_string();
+
+    if rendered.is_empty() || rendered == "." || rendered == ".." {
+        return Err(anyhow!("Invalid artifact_name '{}' - must produce a filename", template));
+    }
+
+    Ok(rendered)
+}
+
+const NAME_SEPARATORS: [char; 3] = ['-', '_', '.'];
+
+/// `value` as part of a filename: path separators become `-`, anything else outside
+/// `[A-Za-z0-9._-]` becomes `_`, with runs of `_` and leading or trailing separators dropped
+fn sanitize_name_component(value: &str) -> String {
+    let mut sanitized = String::new();
+    for c in value.chars() {
+        let c = match c {
+            '/' | '\\' => '-',
+            c if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') => c,
+            _ => '_',
+        };
+        if !(c == '_' && sanitized.ends_with('_')) {
+            sanitized.push(c);
+        }
+    }
+    sanitized.trim_matches(NAME_SEPARATORS).to_string()
+}
+
+/// Split a standard like `gnu11` or `c++17` into the CMake version number and whether
+/// GNU extensions are requested. `prefix` is `c` or `c++`.
+pub fn language_standard_version(std: &str, prefix: &str) -> Option<(&'static str, bool)> {
+    let (version, gnu) = if let Some(rest) = std.strip_prefix(&format!("gnu{}", prefix.trim_start_matches('c'))) {
+        (rest, true)
+    } else {
+        (std.strip_prefix(prefix)?, false)
+    };
+
+    let cmake_version = match (prefix, version) {
+        ("c", "89" | "90") => "90",
+        ("c", "99") => "99",
+        ("c", "11") => "11",
+        ("c", "17" | "18") => "17",
+        ("c", "2x" | "23") => "23",
+        ("c++", "98" | "03") => "98",
+        ("c++", "11") => "11",
+        ("c++", "14") => "14",
+        ("c++", "17") => "17",
+        ("c++", "20" | "2a") => "20",
+        ("c++", "23" | "2b") => "23",
+        _ => return None,
+    };
+
+    Some((cmake_version, gnu))
+}
@@ -1,50 +1,699 @@
-use crate::core::BuildSystem;
-use std::path::Path;
+use crate::core::{BuildConfig, BuildSystem};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
+/// Why a repository can't be built, with the way around it: any build wrapped in a Makefile
+/// can leave its output where the runner collects it
+pub const UNDETECTED_MESSAGE: &str = "Unsupported or undetected build system. Add a Makefile whose default target builds the project and copies the firmware into $(NABLA_OUT_DIR); files there are returned as artifacts";
+
+/// Build systems in the order detection tries them
+pub const DETECTION_ORDER: [BuildSystem; 11] = [
+    // Yocto layers and Buildroot trees both ship Makefiles, so check them first
+    BuildSystem::Yocto,
+    BuildSystem::Buildroot,
+    // Embedded Rust crates often carry a Makefile or CMake wrapper around cargo
+    BuildSystem::Cargo,
+    // MPLAB X generates a Makefile that only works with its nbproject/ Makefiles and an XC compiler
+    BuildSystem::MplabX,
+    BuildSystem::Makefile,
+    // A Zephyr application keeps its CMakeLists.txt next to west.yml
+    BuildSystem::ZephyrWest,
+    BuildSystem::CMake,
+    BuildSystem::PlatformIO,
+    BuildSystem::STM32CubeIDE,
+    BuildSystem::SCons,
+    // Last, so a repository that also ships a Dockerfile for deployment builds natively
+    BuildSystem::Dockerfile,
+];
+
+/// Directories read at once while walking a tree for build files
+const WALK_CONCURRENCY: usize = 16;
+
+/// Directory levels below a repository root searched for a nested project
+const MAX_NESTED_DEPTH: usize = 3;
+
+/// Directories a nested project search checks at most, so a huge tree can't stall detection
+const MAX_NESTED_DIRS: usize = 2_000;
+
+/// The build system named by `NABLA_DEFAULT_BUILD_SYSTEM`, for runners where every repository
+/// is built the same way, used when detection finds nothing. Names are matched without regard
+/// to case, e.g. `makefile` or `CMake`. `None` when unset; an unknown name is an error.
+pub fn default_build_system() -> anyhow::Result<Option<BuildSystem>> {
+    let Ok(name) = std::env::var("NABLA_DEFAULT_BUILD_SYSTEM") else {
+        return Ok(None);
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Ok(None);
+    }
+    DETECTION_ORDER
+        .into_iter()
+        .find(|system| format!("{:?}", system).eq_ignore_ascii_case(name))
+        .map(Some)
+        .ok_or_else(|| {
+            let known: Vec<String> = DETECTION_ORDER.iter().map(|system| format!("{:?}", system)).collect();
+            anyhow::anyhow!("NABLA_DEFAULT_BUILD_SYSTEM '{}' is not a build system (one of: {})", name, known.join(", "))
+        })
+}
+
 pub async fn detect_build_system(path: &Path) -> Option<BuildSystem> {
+    let listing = Listing::read(path).await;
+    for system in DETECTION_ORDER {
+        if matches(path, &listing, system).await {
+            return Some(system);
+        }
+    }
+    None
+}
+
+/// Every build system whose markers are present in `path`, in the order detection tries them.
+/// The first is the one [`detect_build_system`] picks.
+pub async fn detect_build_systems(path: &Path) -> Vec<BuildSystem> {
+    detect_in(path, &Listing::read(path).await).await
+}
+
+async fn detect_in(path: &Path, listing: &Listing) -> Vec<BuildSystem> {
+    let mut found = Vec::new();
+    for system in DETECTION_ORDER {
+        if matches(path, listing, system).await {
+            found.push(system);
+        }
+    }
+    found
+}
 
-    if path.join("Makefile").exists() || path.join("makefile").exists() {
-        return Some(BuildSystem::Makefile);
+async fn matches(path: &Path, listing: &Listing, system: BuildSystem) -> bool {
+    match system {
+        BuildSystem::Yocto => is_yocto_project(path, listing).await,
+        BuildSystem::Buildroot => is_buildroot_project(path, listing).await,
+        BuildSystem::Cargo => listing.has("Cargo.toml"),
+        BuildSystem::MplabX => listing.has_dir("nbproject") && path.join(crate::mplabx::CONFIGURATIONS_XML).is_file(),
+        BuildSystem::Makefile => listing.has("Makefile") || listing.has("makefile"),
+        BuildSystem::CMake => listing.has("CMakeLists.txt"),
+        BuildSystem::PlatformIO => listing.has("platformio.ini"),
+        BuildSystem::ZephyrWest => listing.has("west.yml") || listing.has_dir(".west"),
+        BuildSystem::STM32CubeIDE => !stm32_project_files(listing).is_empty(),
+        BuildSystem::SCons => listing.has("SConscript") || sconstruct_in(path, listing).is_some(),
+        BuildSystem::Dockerfile => listing.has("Dockerfile"),
     }
+}
 
-    if path.join("CMakeLists.txt").exists() {
-        return Some(BuildSystem::CMake);
+/// The entries of one directory, read once so every build system's markers can be checked
+/// without a syscall per candidate file; that adds up on NFS-backed workspaces.
+#[derive(Debug, Default)]
+struct Listing {
+    /// Every entry, with symlinks that resolve to nothing left out
+    names: HashSet<String>,
+    /// Entries that are directories, following symlinks
+    dirs: HashSet<String>,
+    /// Directories that are not symlinks
+    real_dirs: HashSet<String>,
+}
+
+impl Listing {
+    /// An unreadable directory lists as empty
+    async fn read(path: &Path) -> Self {
+        let path = path.to_path_buf();
+        // One blocking task for the whole directory rather than one per batch of entries
+        tokio::task::spawn_blocking(move || Self::read_blocking(&path))
+            .await
+            .unwrap_or_default()
     }
 
-    if path.join("platformio.ini").exists() {
-        return Some(BuildSystem::PlatformIO);
+    fn read_blocking(path: &Path) -> Self {
+        let mut listing = Self::default();
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return listing;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let is_dir = if file_type.is_symlink() {
+                // Only links cost a stat; the type of everything else comes with the entry
+                match std::fs::metadata(entry.path()) {
+                    Ok(target) => target.is_dir(),
+                    Err(_) => continue,
+                }
+            } else {
+                file_type.is_dir()
+            };
+            if is_dir {
+                listing.dirs.insert(name.clone());
+                if file_type.is_dir() {
+                    listing.real_dirs.insert(name.clone());
+                }
+            }
+            listing.names.insert(name);
+        }
+        listing
     }
 
-    if path.join("west.yml").exists() || path.join(".west").is_dir() {
-        return Some(BuildSystem::ZephyrWest);
+    fn has(&self, name: &str) -> bool {
+        self.names.contains(name)
     }
 
-    if has_stm32_project_files(path).await {
-        return Some(BuildSystem::STM32CubeIDE);
+    fn has_dir(&self, name: &str) -> bool {
+        self.dirs.contains(name)
     }
+}
 
-    if path.join("SConstruct").exists() || path.join("SConscript").exists() {
-        return Some(BuildSystem::SCons);
+/// What detection found in a repository, for callers that detect here and build elsewhere
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectionReport {
+    pub build_system: BuildSystem,
+    /// The subdirectory the build runs from, relative to the analyzed path, when the path
+    /// itself has no build system. See [`nested_project_root`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub_path: Option<PathBuf>,
+    /// Files that identified the build system, relative to the build directory
+    pub markers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flavor: Option<BuildFlavor>,
+    /// The command the runner would build with, run from the build directory. Uses default
+    /// build options; see [`execution::build_command_line`](crate::execution::build_command_line).
+    pub suggested_command: String,
+    /// Every build system the directory could be built with, in detection order; the first
+    /// is `build_system`
+    pub candidates: Vec<DetectionCandidate>,
+    /// The artifact format a build is expected to produce, see
+    /// [`BuildSystem::default_artifact_format`]
+    pub artifact_format: String,
+    /// Why `build_system` was chosen, e.g. `detected ZephyrWest: found west.yml at repo root;
+    /// preferred over CMake (CMakeLists.txt)`
+    pub explanation: String,
+    /// PlatformIO `[env:...]` sections
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+    /// The PlatformIO environments `pio run` builds without `-e`: `default_envs`, or all of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub default_environments: Vec<String>,
+    /// Boards named by PlatformIO environments or a Zephyr `set(BOARD ...)`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub boards: Vec<String>,
+    /// CPU architecture the build targets, when the build files say: `arm`, `avr`, `xtensa`,
+    /// `riscv`, or the architecture of a Cargo target triple
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_arch: Option<String>,
+    /// From `cmake_minimum_required` in CMakeLists.txt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmake_minimum_required: Option<String>,
+    /// The Zephyr application west builds, relative to the build directory, when it isn't the
+    /// build directory itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_dir: Option<PathBuf>,
+    /// Problems with the build files, e.g. a west.yml naming a path that doesn't exist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// A build system whose markers are present, with the files that matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectionCandidate {
+    pub build_system: BuildSystem,
+    pub markers: Vec<String>,
+}
+
+/// Detect the build system of the repository at `path` without building it. Returns `None`
+/// when nothing buildable is found.
+pub async fn analyze(path: &Path) -> Option<DetectionReport> {
+    let top = Listing::read(path).await;
+    let mut systems = detect_in(path, &top).await;
+    let (sub_dir, listing) = match systems.is_empty() {
+        true => {
+            let dir = nested_root(path, &top).await?;
+            let listing = Listing::read(&dir).await;
+            systems = detect_in(&dir, &listing).await;
+            (Some(dir), listing)
+        }
+        false => (None, top),
+    };
+    let build_dir = sub_dir.as_deref().unwrap_or(path);
+    let build_system = *systems.first()?;
+    let flavor = detect_flavor(build_dir, build_system).await;
+
+    let mut candidates = Vec::new();
+    for &system in &systems {
+        candidates.push(DetectionCandidate {
+            build_system: system,
+            markers: markers(build_dir, &listing, system).await,
+        });
     }
 
-    None
+    let ini = fs::read_to_string(build_dir.join("platformio.ini")).await.ok();
+    let cmakelists = fs::read_to_string(build_dir.join("CMakeLists.txt")).await.ok();
+    let mut boards = ini.as_deref().map(platformio_boards).unwrap_or_default();
+    if let Some(board) = cmakelists.as_deref().and_then(|text| cmake_set(text, "BOARD")) {
+        boards.push(board);
+    }
+    let target_arch = match build_system {
+        BuildSystem::PlatformIO => ini.as_deref().and_then(platformio_arch),
+        BuildSystem::Cargo => crate::execution::cargo_target(build_dir, &BuildConfig::default())
+            .await
+            .map(|triple| triple_arch(&triple)),
+        BuildSystem::Makefile if flavor == Some(BuildFlavor::Stm32Cubemx) => Some("arm".to_string()),
+        BuildSystem::STM32CubeIDE => Some("arm".to_string()),
+        BuildSystem::CMake => cmakelists.as_deref().and_then(|text| cmake_set(text, "CMAKE_SYSTEM_PROCESSOR")),
+        _ => None,
+    };
+    let west = match build_system {
+        BuildSystem::ZephyrWest => Some(crate::west::layout(build_dir).await),
+        _ => None,
+    };
+    if let Some(layout) = west.as_ref().filter(|_| flavor == Some(BuildFlavor::NrfConnect)) {
+        for board in crate::west::board_overlays(&build_dir.join(&layout.app_dir)).await {
+            if !boards.contains(&board) {
+                boards.push(board);
+            }
+        }
+    }
+
+    let sub_path = sub_dir.as_deref().and_then(|dir| dir.strip_prefix(path).ok()).map(Path::to_path_buf);
+    Some(DetectionReport {
+        build_system,
+        explanation: explain(&candidates, sub_path.as_deref()),
+        sub_path,
+        markers: candidates[0].markers.clone(),
+        flavor,
+        suggested_command: crate::execution::build_command_line(build_dir, build_system, &BuildConfig::default()).await,
+        candidates,
+        artifact_format: build_system.default_artifact_format().to_string(),
+        environments: ini.as_deref().map(crate::platformio::environments).unwrap_or_default(),
+        default_environments: ini.as_deref().map(crate::platformio::default_environments).unwrap_or_default(),
+        boards,
+        target_arch,
+        cmake_minimum_required: cmakelists
+            .as_deref()
+            .and_then(crate::cmake::minimum_required_version)
+            .map(|version| version.to_string()),
+        app_dir: west
+            .as_ref()
+            .map(|layout| layout.app_dir.clone())
+            .filter(|dir| !dir.as_os_str().is_empty()),
+        warnings: west.map(|layout| layout.warnings).unwrap_or_default(),
+    })
+}
+
+/// A sentence naming the markers that decided detection, where they were found and which
+/// other candidates the winner outranked
+fn explain(candidates: &[DetectionCandidate], sub_path: Option<&Path>) -> String {
+    let chosen = &candidates[0];
+    let location = match sub_path {
+        Some(dir) => format!("in {}/", dir.display()),
+        None => "at repo root".to_string(),
+    };
+    let mut explanation = match chosen.markers.is_empty() {
+        true => format!("detected {:?}: found its build files {}", chosen.build_system, location),
+        false => format!("detected {:?}: found {} {}", chosen.build_system, chosen.markers.join(" and "), location),
+    };
+    let others: Vec<String> = candidates[1..]
+        .iter()
+        .map(|candidate| match candidate.markers.is_empty() {
+            true => format!("{:?}", candidate.build_system),
+            false => format!("{:?} ({})", candidate.build_system, candidate.markers.join(", ")),
+        })
+        .collect();
+    if !others.is_empty() {
+        explanation.push_str(&format!("; preferred over {}", others.join(", ")));
+    }
+    explanation
+}
+
+/// Distinct `board = ...` values of the `[env:...]` sections, in file order
+fn platformio_boards(ini: &str) -> Vec<String> {
+    let mut boards: Vec<String> = Vec::new();
+    for section in crate::platformio::parse_ini(ini) {
+        if let (true, Some(board)) = (section.name.starts_with("env:"), section.get("board")) {
+            if !board.is_empty() && !board.contains("${") && !boards.iter().any(|b| b == board) {
+                boards.push(board.to_string());
+            }
+        }
+    }
+    boards
+}
+
+/// The architecture shared by every platform the project uses, if they all map to one
+fn platformio_arch(ini: &str) -> Option<String> {
+    let archs: Vec<&str> = crate::platformio::parse_platforms(ini)
+        .iter()
+        .map(|platform| platform_arch(&platform.name))
+        .collect::<Option<_>>()?;
+    let first = *archs.first()?;
+    archs.iter().all(|arch| *arch == first).then(|| first.to_string())
+}
+
+/// The primary CPU architecture of a PlatformIO platform
+fn platform_arch(platform: &str) -> Option<&'static str> {
+    match platform {
+        "ststm32" | "nordicnrf51" | "nordicnrf52" | "atmelsam" | "raspberrypi" | "teensy" | "nxplpc" | "nxpimxrt"
+        | "freescalekinetis" | "siliconlabsefm32" => Some("arm"),
+        "espressif32" | "espressif8266" => Some("xtensa"),
+        "atmelavr" | "atmelmegaavr" => Some("avr"),
+        "sifive" | "gd32v" | "chipsalliance" => Some("riscv"),
+        _ => None,
+    }
+}
+
+/// `arm` for `thumbv7em-none-eabihf`, `riscv` for `riscv32imac-unknown-none-elf`, otherwise the
+/// triple's first component
+fn triple_arch(triple: &str) -> String {
+    let arch = triple.split('-').next().unwrap_or(triple);
+    if arch.starts_with("thumb") || arch.starts_with("arm") {
+        "arm".to_string()
+    } else if arch.starts_with("riscv") {
+        "riscv".to_string()
+    } else {
+        arch.to_string()
+    }
+}
+
+/// The value of a top-level `set(NAME value)` in a CMakeLists.txt
+pub(crate) fn cmake_set(cmakelists: &str, name: &str) -> Option<String> {
+    cmakelists.lines().find_map(|line| {
+        let line = line.trim();
+        let args = line.strip_prefix("set(").or_else(|| line.strip_prefix("SET("))?;
+        let mut words = args.trim_end_matches(')').split_whitespace();
+        (words.next()? == name).then_some(())?;
+        let value = words.next()?.trim_matches('"');
+        (!value.is_empty() && !value.contains("${")).then(|| value.to_string())
+    })
+}
+
+/// The files present in `path` that [`detect_build_system`] looks for to recognize `system`
+async fn markers(path: &Path, listing: &Listing, system: BuildSystem) -> Vec<String> {
+    let candidates: &[&str] = match system {
+        BuildSystem::Yocto => &["conf/local.conf", "conf/bblayers.conf", "conf/layer.conf"],
+        BuildSystem::Buildroot => &["Config.in", "external.desc"],
+        BuildSystem::Cargo => &["Cargo.toml"],
+        BuildSystem::MplabX => &[crate::mplabx::CONFIGURATIONS_XML],
+        BuildSystem::Makefile => &["Makefile", "makefile"],
+        BuildSystem::CMake => &["CMakeLists.txt"],
+        BuildSystem::PlatformIO => &["platformio.ini"],
+        BuildSystem::ZephyrWest => &["west.yml", ".west"],
+        BuildSystem::STM32CubeIDE => &[".project", ".cproject"],
+        BuildSystem::SCons => &["SConstruct", "SConscript"],
+        BuildSystem::Dockerfile => &["Dockerfile"],
+    };
+    let mut found: Vec<String> = Vec::new();
+    for name in candidates {
+        let present = match name.split_once('/') {
+            Some((dir, _)) => listing.has_dir(dir) && path.join(name).exists(),
+            None => listing.has(name),
+        };
+        if present {
+            found.push(name.to_string());
+        }
+    }
+
+    match system {
+        BuildSystem::Buildroot => {
+            found.extend(find_defconfigs(path).await.into_iter().map(|name| format!("configs/{}", name)));
+        }
+        BuildSystem::Yocto if found.is_empty() => found.push("*/conf/layer.conf".to_string()),
+        BuildSystem::STM32CubeIDE if found.is_empty() => found = stm32_project_files(listing),
+        BuildSystem::SCons if found.is_empty() => {
+            found.extend(sconstruct_in(path, listing).map(|file| file.display().to_string()));
+        }
+        _ => {}
+    }
+
+    found
+}
+
+/// A variant of a detected build system that needs its own handling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildFlavor {
+    /// A Makefile exported by STM32CubeMX: cross-compiled with `arm-none-eabi-gcc` against the
+    /// STM32 HAL/CMSIS drivers, producing `build/<target>.elf`, `.hex` and `.bin`
+    Stm32Cubemx,
+    /// A Zephyr application for the nRF Connect SDK: a `prj.conf` application whose west.yml
+    /// pulls in `sdk-nrf`. Built for an explicit board, producing `merged.hex` and
+    /// `dfu_application.zip` alongside the Zephyr images
+    NrfConnect,
 }
 
-async fn has_stm32_project_files(path: &Path) -> bool {
-    let extensions = [".project", ".cproject"];
-    
-    for ext in &extensions {
-        if let Ok(mut entries) = fs::read_dir(path).await {
-            while let Ok(Some(entry)) = entries.next_entry().await {
-                if let Some(file_name) = entry.file_name().to_str() {
-                    if file_name.ends_with(ext) {
-                        return true;
+/// Refine a detected build system by looking at its build files' contents
+pub async fn detect_flavor(path: &Path, system: BuildSystem) -> Option<BuildFlavor> {
+    match system {
+        BuildSystem::Makefile => {
+            let makefile = match fs::read_to_string(path.join("Makefile")).await {
+                Ok(makefile) => makefile,
+                Err(_) => fs::read_to_string(path.join("makefile")).await.ok()?,
+            };
+            is_cubemx_makefile(&makefile).then_some(BuildFlavor::Stm32Cubemx)
+        }
+        BuildSystem::ZephyrWest => {
+            let layout = crate::west::layout(path).await;
+            let manifest = fs::read_to_string(path.join(&layout.manifest_dir).join("west.yml")).await.ok()?;
+            let application = path.join(&layout.app_dir).join("prj.conf").is_file();
+            (application && crate::west::manifest_uses_nrf(&manifest)).then_some(BuildFlavor::NrfConnect)
+        }
+        _ => None,
+    }
+}
+
+/// CubeMX Makefiles set `PREFIX = arm-none-eabi-` and build the HAL or CMSIS sources under `Drivers/`
+fn is_cubemx_makefile(makefile: &str) -> bool {
+    let cross_prefix = makefile.lines().any(|line| {
+        line.split_once('=')
+            .is_some_and(|(name, value)| name.trim_end_matches([':', '?', ' ', '\t']) == "PREFIX" && value.trim() == "arm-none-eabi-")
+    });
+    let stm32_drivers = makefile.contains("Drivers/CMSIS") || makefile.contains("_HAL_Driver");
+    cross_prefix && stm32_drivers
+}
+
+/// When `path` has no build markers of its own but holds exactly one subdirectory (an archive
+/// packed one level too deep, or a repo with everything under a single folder), return that
+/// subdirectory so detection and the build run from there. Hidden entries such as `.git` are ignored.
+pub async fn single_child_root(path: &Path) -> Option<PathBuf> {
+    let listing = Listing::read(path).await;
+    if !detect_in(path, &listing).await.is_empty() {
+        return None;
+    }
+    only_child(path, &listing)
+}
+
+/// The directory to build when `path` has no build markers of its own, e.g. in a monorepo
+/// of several subprojects: the nested project whose build system comes first in
+/// [`DETECTION_ORDER`], then the shallowest, then the first by path. Searches up to
+/// [`MAX_NESTED_DEPTH`] levels down, checking up to [`WALK_CONCURRENCY`] directories at once,
+/// and doesn't look inside a directory that is itself a project. Without any nested project
+/// this is [`single_child_root`].
+pub async fn nested_project_root(path: &Path) -> Option<PathBuf> {
+    let listing = Listing::read(path).await;
+    if !detect_in(path, &listing).await.is_empty() {
+        return None;
+    }
+    nested_root(path, &listing).await
+}
+
+async fn nested_root(path: &Path, listing: &Listing) -> Option<PathBuf> {
+    find_nested_project(path, listing).await.or_else(|| only_child(path, listing))
+}
+
+/// The best-ranked project below `path`, see [`nested_project_root`]
+async fn find_nested_project(path: &Path, listing: &Listing) -> Option<PathBuf> {
+    let rank = |system: BuildSystem| DETECTION_ORDER.iter().position(|&s| s == system).unwrap_or(usize::MAX);
+    let mut best: Option<(usize, usize, PathBuf)> = None;
+    let mut pending = visible_subdirs(path, listing);
+    let mut visited = 0;
+    for depth in 1..=MAX_NESTED_DEPTH {
+        // Listings finish in any order; check each level in a fixed one
+        pending.sort();
+        pending.truncate(MAX_NESTED_DIRS - visited);
+        if pending.is_empty() {
+            break;
+        }
+        visited += pending.len();
+        let checked: Vec<(PathBuf, Listing, Option<BuildSystem>)> = stream::iter(pending)
+            .map(|dir| async move {
+                let listing = Listing::read(&dir).await;
+                let system = detect_in(&dir, &listing).await.first().copied();
+                (dir, listing, system)
+            })
+            .buffer_unordered(WALK_CONCURRENCY)
+            .collect()
+            .await;
+
+        pending = Vec::new();
+        for (dir, listing, system) in checked {
+            match system {
+                Some(system) => {
+                    let candidate = (rank(system), depth, dir);
+                    if best.as_ref().is_none_or(|best| candidate < *best) {
+                        best = Some(candidate);
                     }
                 }
+                None => pending.extend(visible_subdirs(&dir, &listing)),
             }
         }
     }
-    
+    best.map(|(_, _, dir)| dir)
+}
+
+/// The one subdirectory of a directory, ignoring hidden ones
+fn only_child(path: &Path, listing: &Listing) -> Option<PathBuf> {
+    let mut children = listing.real_dirs.iter().filter(|name| !name.starts_with('.'));
+    match (children.next(), children.next()) {
+        (Some(child), None) => Some(path.join(child)),
+        _ => None,
+    }
+}
+
+/// The SConstruct of the SCons project at `path`, relative to it: the top-level one, or else
+/// one a directory down such as `build/SConstruct`, from the first such directory by name
+pub async fn find_sconstruct(path: &Path) -> Option<PathBuf> {
+    sconstruct_in(path, &Listing::read(path).await)
+}
+
+fn sconstruct_in(path: &Path, listing: &Listing) -> Option<PathBuf> {
+    if listing.has("SConstruct") {
+        return Some(PathBuf::from("SConstruct"));
+    }
+    let mut subdirs: Vec<&String> = listing.real_dirs.iter().filter(|name| !name.starts_with('.')).collect();
+    subdirs.sort();
+    subdirs
+        .into_iter()
+        .map(|dir| Path::new(dir).join("SConstruct"))
+        .find(|file| path.join(file).is_file())
+}
+
+/// Eclipse project files, which may carry a prefix, e.g. `firmware.project`; sorted
+fn stm32_project_files(listing: &Listing) -> Vec<String> {
+    let mut found: Vec<String> = listing
+        .names
+        .iter()
+        .filter(|name| name.ends_with(".project") || name.ends_with(".cproject"))
+        .cloned()
+        .collect();
+    found.sort();
+    found
+}
+
+async fn is_yocto_project(path: &Path, listing: &Listing) -> bool {
+    if listing.has_dir("conf") && (path.join("conf/local.conf").exists() || path.join("conf/bblayers.conf").exists()) {
+        return true;
+    }
+
+    // A recipe alone is as likely packaging for a firmware repo's own build; only recipes in a
+    // layer, the directory holding `conf/layer.conf`, make the tree Yocto's to build
+    if is_layer(path, listing) && has_bitbake_recipe(path, listing, 3).await {
+        return true;
+    }
+    let mut pending = visible_subdirs(path, listing);
+    for remaining in (0..LAYER_DEPTH).rev() {
+        let listings: Vec<(PathBuf, Listing)> = stream::iter(pending)
+            .map(|dir| async move {
+                let listing = Listing::read(&dir).await;
+                (dir, listing)
+            })
+            .buffer_unordered(WALK_CONCURRENCY)
+            .collect()
+            .await;
+        for (dir, listing) in listings.iter().filter(|(dir, listing)| is_layer(dir, listing)) {
+            if has_bitbake_recipe(dir, listing, 3).await {
+                return true;
+            }
+        }
+        pending = match remaining {
+            0 => Vec::new(),
+            _ => listings.iter().flat_map(|(dir, listing)| visible_subdirs(dir, listing)).collect(),
+        };
+    }
+
     false
-}
\ No newline at end of file
+}
+
+/// How far below the repository root a layer is looked for, e.g. `layers/meta-acme`
+const LAYER_DEPTH: usize = 2;
+
+fn is_layer(dir: &Path, listing: &Listing) -> bool {
+    listing.has_dir("conf") && dir.join("conf/layer.conf").exists()
+}
+
+/// Look for `*.bb` recipes, which live a couple of levels down (e.g. `recipes-core/foo/foo.bb`).
+/// Walks a level at a time, reading up to [`WALK_CONCURRENCY`] directories at once and never
+/// entering hidden ones.
+async fn has_bitbake_recipe(dir: &Path, listing: &Listing, depth: usize) -> bool {
+    if has_recipe_file(listing) {
+        return true;
+    }
+
+    let mut pending = visible_subdirs(dir, listing);
+    for remaining in (0..depth).rev() {
+        if pending.is_empty() {
+            break;
+        }
+        let listings: Vec<(PathBuf, Listing)> = stream::iter(pending)
+            .map(|dir| async move {
+                let listing = Listing::read(&dir).await;
+                (dir, listing)
+            })
+            .buffer_unordered(WALK_CONCURRENCY)
+            .collect()
+            .await;
+        if listings.iter().any(|(_, listing)| has_recipe_file(listing)) {
+            return true;
+        }
+        pending = match remaining {
+            0 => Vec::new(),
+            _ => listings.iter().flat_map(|(dir, listing)| visible_subdirs(dir, listing)).collect(),
+        };
+    }
+
+    false
+}
+
+fn has_recipe_file(listing: &Listing) -> bool {
+    listing
+        .names
+        .iter()
+        .any(|name| !listing.real_dirs.contains(name) && Path::new(name).extension().is_some_and(|ext| ext == "bb"))
+}
+
+/// Subdirectories a tree walk enters: not hidden, and not symlinks
+fn visible_subdirs(dir: &Path, listing: &Listing) -> Vec<PathBuf> {
+    listing.real_dirs.iter().filter(|name| !name.starts_with('.')).map(|name| dir.join(name)).collect()
+}
+
+async fn is_buildroot_project(path: &Path, listing: &Listing) -> bool {
+    if listing.has("Config.in") && listing.has("external.desc") {
+        return true;
+    }
+    if !listing.has_dir("configs") || find_defconfigs(path).await.is_empty() {
+        return false;
+    }
+
+    // U-Boot and Linux keep `configs/*_defconfig` too; only Buildroot pairs them with a
+    // `Config.in` of BR2_ options, a `package/` tree or an `external.desc`
+    if listing.has("external.desc") || (listing.has("Config.in") && listing.has_dir("package")) {
+        return true;
+    }
+    listing.has("Config.in")
+        && fs::read_to_string(path.join("Config.in"))
+            .await
+            .is_ok_and(|config_in| config_in.contains("BR2_"))
+}
+
+/// Names of the `*_defconfig` files under `configs/`, sorted
+pub async fn find_defconfigs(path: &Path) -> Vec<String> {
+    let mut defconfigs = Vec::new();
+
+    if let Ok(mut entries) = fs::read_dir(path.join("configs")).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Some(file_name) = entry.file_name().to_str() {
+                if file_name.ends_with("_defconfig") {
+                    defconfigs.push(file_name.to_string());
+                }
+            }
+        }
+    }
+
+    defconfigs.sort();
+    defconfigs
+}
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// One compiler message in the GCC/Clang `file:line:col: severity: message [-Wflag]` format,
+/// or a defect a static analyzer reported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    /// The `-W...` option that enabled a warning, if the compiler named one, or the
+    /// analyzer's check id, e.g. `uninitvar`.
+    pub flag: Option<String>,
+    /// The analyzer that reported it, e.g. `platformio-check`; unset for compiler messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    /// `note:` lines the compiler printed right after this error or warning, such as clang's
+    /// "previous definition is here" or a macro expansion chain.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    /// `file:line: message [-Wflag]`, as shown to users
+    pub fn summary(&self) -> String {
+        match &self.flag {
+            Some(flag) => format!("{}:{}: {} [{}]", self.file, self.line, self.message, flag),
+            None => format!("{}:{}: {}", self.file, self.line, self.message),
+        }
+    }
+}
+
+/// Extract diagnostics from build output, including the Python errors of
+/// [`parse_python_errors`]. Lines in any other format (make chatter, source excerpts, caret
+/// lines) are ignored, and repeated diagnostics are reported once. Notes
+/// attach to the error or warning before them; notes with nothing before them stand alone.
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut current: Option<Diagnostic> = None;
+
+    for line in output.lines() {
+        let Some(diagnostic) = parse_line(line.trim_end()) else {
+            continue;
+        };
+        match &mut current {
+            Some(parent) if diagnostic.severity == Severity::Note && parent.severity != Severity::Note => {
+                parent.notes.push(diagnostic)
+            }
+            _ => {
+                diagnostics.extend(current.take());
+                current = Some(diagnostic);
+            }
+        }
+    }
+    diagnostics.extend(current);
+    diagnostics.extend(parse_python_errors(output));
+
+    let mut unique: Vec<Diagnostic> = Vec::new();
+    for diagnostic in diagnostics {
+        if !unique.contains(&diagnostic) {
+            unique.push(diagnostic);
+        }
+    }
+    unique
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    let (severity, marker_at, marker_len) = [
+        (Severity::Error, ": error: "),
+        (Severity::Error, ": fatal error: "),
+        (Severity::Warning, ": warning: "),
+        (Severity::Note, ": note: "),
+    ]
+    .into_iter()
+    .filter_map(|(severity, marker)| line.find(marker).map(|at| (severity, at, marker.len())))
+    .min_by_key(|(_, at, _)| *at)?;
+
+    // Location is `file:line` or `file:line:col`; the file itself may contain colons
+    let mut location = line[..marker_at].rsplitn(3, ':');
+    let last = location.next()?;
+    let middle = location.next()?;
+    let (file, line_no, column) = match (middle.parse::<u32>(), last.parse::<u32>()) {
+        (Ok(line_no), Ok(column)) => (location.next()?, line_no, Some(column)),
+        (Err(_), Ok(line_no)) => {
+            let prefix = &line[..marker_at - last.len() - 1];
+            (prefix, line_no, None)
+        }
+        _ => return None,
+    };
+    if file.is_empty() {
+        return None;
+    }
+
+    let mut message = line[marker_at + marker_len..].trim().to_string();
+    let mut flag = None;
+    if message.ends_with(']') {
+        if let Some(start) = message.rfind(" [-W") {
+            flag = Some(message[start + 2..message.len() - 1].to_string());
+            message.truncate(start);
+        }
+    }
+
+    Some(Diagnostic {
+        file: file.trim().to_string(),
+        line: line_no,
+        column,
+        severity,
+        message,
+        flag,
+        tool: None,
+        notes: Vec::new(),
+    })
+}
+
+/// Python exceptions that ended a build script, such as a PlatformIO `extra_scripts` script
+/// importing a module the runner's Python lacks. Each points at the innermost frame outside
+/// the Python installation, the build's own script. Both Python's traceback, which names the
+/// exception after its frames, and SCons's, which names it first, are understood.
+pub fn parse_python_errors(output: &str) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = output.lines().map(str::trim_end).collect();
+    let mut errors = Vec::new();
+    let mut frames: Vec<(String, u32)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.starts_with("Traceback (most recent call last)") {
+            frames.clear();
+        } else if let Some(frame) = python_frame(line) {
+            frames.push(frame);
+        } else if let Some(message) = python_exception(line) {
+            // SCons: `ModuleNotFoundError: No module named 'git':` with the frames below it
+            let scons_frames: Vec<(String, u32)> = match message.strip_suffix(':') {
+                Some(_) => lines[i + 1..].iter().take_while(|line| line.starts_with(' ')).filter_map(|line| python_frame(line)).collect(),
+                None => Vec::new(),
+            };
+            let frames = match scons_frames.is_empty() {
+                true => std::mem::take(&mut frames),
+                false => scons_frames,
+            };
+            let frame = frames.iter().rev().find(|(file, _)| !is_python_library(file)).or(frames.last());
+            if let Some((file, line)) = frame {
+                errors.push(Diagnostic {
+                    file: file.clone(),
+                    line: *line,
+                    column: None,
+                    severity: Severity::Error,
+                    message: message.trim_end_matches(':').to_string(),
+                    flag: None,
+                    tool: Some("python".to_string()),
+                    notes: Vec::new(),
+                });
+            }
+        } else if !line.starts_with(' ') {
+            frames.clear();
+        }
+    }
+    errors
+}
+
+/// `File "<path>", line <n>, in <function>` (Python) or `File "<path>", line <n>:` (SCons)
+fn python_frame(line: &str) -> Option<(String, u32)> {
+    let rest = line.trim_start().strip_prefix("File \"")?;
+    let (file, rest) = rest.split_once("\", line ")?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    Some((file.to_string(), rest[..digits].parse().ok()?))
+}
+
+/// `<Name>Error: <message>` or `<Name>Exception: <message>` at the start of a line
+fn python_exception(line: &str) -> Option<&str> {
+    let (name, _) = line.split_once(": ")?;
+    let is_name = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    (is_name && (name.ends_with("Error") || name.ends_with("Exception"))).then_some(line)
+}
+
+/// Part of Python or an installed package rather than the build's own scripts
+fn is_python_library(file: &str) -> bool {
+    file.starts_with('<') || ["/site-packages/", "/dist-packages/", "/lib/python"].iter().any(|dir| file.contains(dir))
+}
+
+/// Match `path` against a glob where `*` matches within one path component, `**` matches
+/// across components and `?` matches a single character.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+                let rest = &rest[1..];
+                if rest.is_empty() {
+                    return true;
+                }
+                let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+                (0..=path.len()).any(|i| (i == 0 || path[i - 1] == b'/') && matches(rest, &path[i..]))
+            }
+            Some((b'*', rest)) => {
+                (0..=path.len()).take_while(|&i| i == 0 || path[i - 1] != b'/').any(|i| matches(rest, &path[i..]))
+            }
+            Some((b'?', rest)) => path.first().is_some_and(|&c| c != b'/') && matches(rest, &path[1..]),
+            Some((&c, rest)) => path.first() == Some(&c) && matches(rest, &path[1..]),
+        }
+    }
+
+    matches(pattern.as_bytes(), path.as_bytes())
+}
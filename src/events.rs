@@ -0,0 +1,376 @@
+use crate::core::BuildSystem;
+use crate::jobs::{BuildJob, JobAudit, JobEventKind};
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::warn;
+use uuid::Uuid;
+
+const DEFAULT_SUBJECT_PREFIX: &str = "nabla.builds";
+const DEFAULT_BUFFER_EVENTS: usize = 10_000;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Pipeline stages a running job moves through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildPhase {
+    /// Downloading or unpacking the repository archive
+    Fetch,
+    Detect,
+    Build,
+    /// Encoding or uploading artifacts
+    Package,
+}
+
+/// A job state transition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    Queued,
+    Started,
+    Phase { phase: BuildPhase },
+    Completed { build_system: BuildSystem },
+    /// Built, but `failed` environments didn't
+    PartiallyCompleted {
+        build_system: BuildSystem,
+        failed: Vec<String>,
+    },
+    Failed {
+        build_system: Option<BuildSystem>,
+        error: String,
+        /// See [`crate::fingerprint`]
+        fingerprint: String,
+        /// Times the repository has failed with this fingerprint, this failure included
+        occurrences: u64,
+    },
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Queued => "queued",
+            EventKind::Started => "started",
+            EventKind::Phase { .. } => "phase",
+            EventKind::Completed { .. } => "completed",
+            EventKind::PartiallyCompleted { .. } => "partially_completed",
+            EventKind::Failed { .. } => "failed",
+        }
+    }
+}
+
+/// The payload published for every job state transition. Delivery is at-least-once, so
+/// consumers should de-duplicate on `job_id` and `sequence`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildEvent {
+    #[serde(flatten)]
+    pub kind: EventKind,
+    /// Runner-assigned job id, as in the build response
+    pub job_id: Uuid,
+    /// The client's `job_id` from the request
+    pub client_job_id: String,
+    pub customer_id: String,
+    pub owner: String,
+    pub repo: String,
+    /// Position of this event within its job, starting at 0
+    pub sequence: u64,
+    /// Unix time in milliseconds
+    pub timestamp_ms: u64,
+    /// The job record as of this event, as `GET /jobs/{job_id}` would return it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<BuildJob>,
+}
+
+/// Where events are delivered, e.g. a NATS connection or a Redis channel
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn send(&self, subject: &str, payload: Vec<u8>) -> Result<()>;
+}
+
+struct Shared {
+    sink: Arc<dyn EventSink>,
+    subject_prefix: String,
+    capacity: usize,
+    /// Buffered events with an id that survives the front being dropped during a send
+    buffer: Mutex<VecDeque<(u64, BuildEvent)>>,
+    next_id: AtomicU64,
+    dropped: AtomicU64,
+    notify: Notify,
+}
+
+/// Hands job events to a background task that publishes them to the bus. `publish` never
+/// blocks or fails: while the bus is unreachable events wait in a bounded buffer, and when it
+/// fills up the oldest are dropped and counted.
+#[derive(Clone, Default)]
+pub struct EventPublisher {
+    shared: Option<Arc<Shared>>,
+}
+
+impl EventPublisher {
+    /// A publisher that discards everything, used when no bus is configured
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Start publishing to `sink` under `subject_prefix`; must be called within a Tokio runtime.
+    pub fn new(sink: Arc<dyn EventSink>, subject_prefix: &str, capacity: usize) -> Self {
+        let shared = Arc::new(Shared {
+            sink,
+            subject_prefix: subject_prefix.trim_end_matches('.').to_string(),
+            capacity: capacity.max(1),
+            buffer: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            notify: Notify::new(),
+        });
+        tokio::spawn(deliver(shared.clone()));
+        Self { shared: Some(shared) }
+    }
+
+    /// Configure from `NABLA_EVENT_BUS_URL` (or the older `EVENT_BUS_URL`),
+    /// `EVENT_BUS_SUBJECT_PREFIX` and `EVENT_BUS_BUFFER`. Without a URL, or without the
+    /// feature the URL's scheme needs, events are disabled.
+    pub fn from_env() -> Self {
+        let Ok(url) = env::var("NABLA_EVENT_BUS_URL").or_else(|_| env::var("EVENT_BUS_URL")) else {
+            return Self::disabled();
+        };
+        let prefix = env::var("EVENT_BUS_SUBJECT_PREFIX").unwrap_or_else(|_| DEFAULT_SUBJECT_PREFIX.to_string());
+        let capacity = env::var("EVENT_BUS_BUFFER")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_BUFFER_EVENTS);
+
+        match sink_for_url(&url) {
+            Some(sink) => Self::new(sink, &prefix, capacity),
+            None => {
+                warn!("Event bus URL {} is not supported by this build; job events are disabled", url);
+                Self::disabled()
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    pub fn publish(&self, event: BuildEvent) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+
+        let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut buffer = shared.buffer.lock();
+            if buffer.len() >= shared.capacity {
+                buffer.pop_front();
+                let dropped = shared.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("Event bus buffer full; dropped oldest event ({} dropped so far)", dropped);
+            }
+            buffer.push_back((id, event));
+        }
+        shared.notify.notify_one();
+    }
+
+    /// Events waiting for delivery
+    pub fn pending(&self) -> usize {
+        self.shared.as_ref().map_or(0, |shared| shared.buffer.lock().len())
+    }
+
+    /// Events discarded because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.as_ref().map_or(0, |shared| shared.dropped.load(Ordering::Relaxed))
+    }
+}
+
+/// Deliver buffered events in order, retrying the oldest until the sink accepts it
+async fn deliver(shared: Arc<Shared>) {
+    let mut delay = Duration::from_millis(100);
+    loop {
+        let next = shared.buffer.lock().front().cloned();
+        let Some((id, event)) = next else {
+            shared.notify.notified().await;
+            continue;
+        };
+
+        let subject = format!("{}.{}", shared.subject_prefix, event.kind.name());
+        let payload = serde_json::to_vec(&event).expect("events serialize");
+        match shared.sink.send(&subject, payload).await {
+            Ok(()) => {
+                let mut buffer = shared.buffer.lock();
+                if buffer.front().is_some_and(|(front, _)| *front == id) {
+                    buffer.pop_front();
+                }
+                delay = Duration::from_millis(100);
+            }
+            Err(e) => {
+                warn!("Publishing job event to {} failed, retrying in {:?}: {}", subject, delay, e);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+fn sink_for_url(url: &str) -> Option<Arc<dyn EventSink>> {
+    #[cfg(feature = "nats")]
+    if url.starts_with("nats://") || url.starts_with("tls://") {
+        return Some(Arc::new(NatsSink::new(url)));
+    }
+    #[cfg(feature = "redis")]
+    if url.starts_with("redis://") || url.starts_with("rediss://") {
+        return RedisSink::new(url)
+            .inspect_err(|e| warn!("Invalid Redis URL for job events: {}", e))
+            .ok()
+            .map(|sink| Arc::new(sink) as Arc<dyn EventSink>);
+    }
+    let _ = url;
+    None
+}
+
+/// Publishes to NATS core subjects; capture them in a JetStream stream for durable delivery.
+/// An event counts as delivered once the server has acknowledged a flush after it.
+#[cfg(feature = "nats")]
+pub struct NatsSink {
+    url: String,
+    client: tokio::sync::OnceCell<async_nats::Client>,
+}
+
+#[cfg(feature = "nats")]
+impl NatsSink {
+    const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: tokio::sync::OnceCell::new(),
+        }
+    }
+}
+
+#[cfg(feature = "nats")]
+#[async_trait]
+impl EventSink for NatsSink {
+    async fn send(&self, subject: &str, payload: Vec<u8>) -> Result<()> {
+        let client = self
+            .client
+            .get_or_try_init(|| async { async_nats::connect(self.url.as_str()).await })
+            .await?;
+        client.publish(subject.to_string(), payload.into()).await?;
+        tokio::time::timeout(Self::FLUSH_TIMEOUT, client.flush())
+            .await
+            .map_err(|_| anyhow::anyhow!("no acknowledgement from NATS within {:?}", Self::FLUSH_TIMEOUT))??;
+        Ok(())
+    }
+}
+
+/// Publishes to Redis pub/sub channels named like NATS subjects, e.g. `nabla.builds.completed`.
+/// Redis doesn't keep messages for subscribers that aren't connected; an event counts as
+/// delivered once the server has accepted the `PUBLISH`.
+#[cfg(feature = "redis")]
+pub struct RedisSink {
+    client: redis::Client,
+    connection: tokio::sync::Mutex<Option<redis::aio::MultiplexedConnection>>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisSink {
+    const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            connection: tokio::sync::Mutex::new(None),
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl EventSink for RedisSink {
+    async fn send(&self, subject: &str, payload: Vec<u8>) -> Result<()> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(self.client.get_multiplexed_tokio_connection().await?);
+        }
+        let mut command = redis::cmd("PUBLISH");
+        command.arg(subject).arg(payload);
+        let publish = command.query_async::<_, i64>(connection.as_mut().expect("connected above"));
+        let result = match tokio::time::timeout(Self::PUBLISH_TIMEOUT, publish).await {
+            Ok(result) => result.map_err(anyhow::Error::from),
+            Err(_) => Err(anyhow::anyhow!("no reply from Redis within {:?}", Self::PUBLISH_TIMEOUT)),
+        };
+        if result.is_err() {
+            // Reconnect on the next attempt rather than reuse a connection in an unknown state
+            *connection = None;
+        }
+        result.map(|_| ())
+    }
+}
+
+/// Builds the events of one job, numbering them in order, and records its audit trail
+pub struct JobEvents {
+    publisher: EventPublisher,
+    template: BuildEvent,
+    sequence: AtomicU64,
+    audit: Option<JobAudit>,
+}
+
+impl JobEvents {
+    pub fn new(publisher: EventPublisher, job_id: Uuid, client_job_id: &str, customer_id: &str, owner: &str, repo: &str) -> Self {
+        Self {
+            publisher,
+            template: BuildEvent {
+                kind: EventKind::Queued,
+                job_id,
+                client_job_id: client_job_id.to_string(),
+                customer_id: customer_id.to_string(),
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                sequence: 0,
+                timestamp_ms: 0,
+                job: None,
+            },
+            sequence: AtomicU64::new(0),
+            audit: None,
+        }
+    }
+
+    /// Also record [`audit`](Self::audit) events through `audit`
+    pub fn with_audit(mut self, audit: JobAudit) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Append to the job's audit trail, whether or not an event bus is configured
+    pub fn audit(&self, kind: JobEventKind, detail: impl Into<String>) {
+        if let Some(audit) = &self.audit {
+            audit.record(kind, detail);
+        }
+    }
+
+    pub fn emit(&self, kind: EventKind) {
+        if !self.publisher.is_enabled() {
+            return;
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        self.publisher.publish(BuildEvent {
+            kind,
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+            timestamp_ms,
+            job: self.audit.as_ref().and_then(JobAudit::snapshot),
+            ..self.template.clone()
+        });
+    }
+
+    pub fn phase(&self, phase: BuildPhase) {
+        self.emit(EventKind::Phase { phase });
+    }
+}
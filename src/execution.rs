@@ -1,37 +1,944 @@
-use crate::core::{BuildResult, BuildSystem};
+use crate::core::{language_standard_version, Artifact, BuildAttempt, BuildConfig, BuildResult, BuildSystem, CheckSeverity, EnvironmentResult, MatrixEntry, MatrixResult, NetworkPolicy, PioCheck, Provenance, TargetResult, Verbosity, DEFAULT_PIO_CHECK_TIMEOUT_SECS, MAX_TRANSIENT_RETRIES};
+use crate::cmake;
+use crate::container::{in_container, ContainerContext, ContainerPolicy};
+use crate::detection::{detect_flavor, BuildFlavor};
+use crate::diagnostics::{glob_match, parse_diagnostics, Diagnostic, Severity};
+use crate::limits::BuildLimits;
+use crate::output::{limit_output, output_limit, OutputBuffer};
+use crate::mplabx;
+use crate::platformio;
+use crate::workspace::OUT_DIR_ENV;
+use crate::source::{self, SourceSnapshot};
+use crate::process::{
+    capture_output, live_log, CapturedOutput, ProcessExit, network_isolated, network_isolation_available, on_path, record_output, run_command,
+    stream_output_to, without_network,
+};
 use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::future::Future;
+use std::pin::Pin;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::process::Command;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::fs;
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::process::Output;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
 pub async fn execute_build(path: &Path, system: BuildSystem) -> Result<BuildResult> {
+    execute_build_with_config(path, system, &BuildConfig::default()).await
+}
+
+pub async fn execute_build_with_config(path: &Path, system: BuildSystem, config: &BuildConfig) -> Result<BuildResult> {
+    if config.post_build.is_some() && !hooks_allowed() {
+        return Err(anyhow!("post_build hooks are disabled on this runner (NABLA_ALLOW_HOOKS is not set)"));
+    }
+    if !config.matrix.is_empty() {
+        return build_matrix(path, system, config).await;
+    }
+    // The operator's limits for this build system, with the request's overrides held to their maxima
+    let limits = crate::limits::current().resolve(system, config);
+    let config = &build_env(&limited_config(config, &limits), path, system);
+    let policy = RetryPolicy::from_config(config);
+    let mut retries = 0;
+
+    let container = match &config.container {
+        Some(container) => {
+            // The repository plus host cache directories such as PLATFORMIO_CORE_DIR
+            let mounts = std::iter::once(path.to_path_buf())
+                .chain(config.command_env.values().map(PathBuf::from).filter(|dir| dir.is_absolute() && dir.is_dir()))
+                .collect();
+            Some(Arc::new(ContainerContext::prepare(container, system, &ContainerPolicy::from_env(), mounts).await?))
+        }
+        None => None,
+    };
+
+    // Before dependencies are fetched, so the hash covers the source as received
+    let source = match SourceSnapshot::take(path).await {
+        Ok(snapshot) => Some(Arc::new(snapshot)),
+        Err(e) => {
+            tracing::warn!("Failed to hash the sources in {}: {}", path.display(), e);
+            None
+        }
+    };
+
+    let mut isolation_unavailable = None;
+    let isolated = match config.network_policy {
+        NetworkPolicy::Allow => false,
+        NetworkPolicy::FetchThenIsolate => {
+            run_scoped(fetch_dependencies(path, system, config), false, container.clone()).await?;
+            let available = container.is_some() || network_isolation_available().await;
+            if !available {
+                const UNAVAILABLE: &str = "Network namespaces are not permitted on this runner; compiling with network access";
+                tracing::warn!("{}", UNAVAILABLE);
+                isolation_unavailable = Some(UNAVAILABLE.to_string());
+            }
+            available
+        }
+    };
+
+    let provenance = Provenance {
+        network_policy: config.network_policy,
+        network_isolated: isolated,
+        network_isolation_unavailable: isolation_unavailable,
+        container_image: container.as_ref().map(|c| c.image.clone()),
+        image_digest: container.as_ref().map(|c| c.digest.clone()),
+        source_sha256: source.as_ref().map(|snapshot| snapshot.sha256.clone()),
+        limits: Some(limits),
+        verbosity: config.verbosity,
+    };
+    let new_files_only = source.filter(|_| config.artifacts_new_only);
+    let started = Instant::now();
+    let mut attempts = Vec::new();
+
+    loop {
+        // Boxed: the dispatched build is large enough to overflow a thread's stack in debug builds
+        let build = source::new_files_only(new_files_only.clone(), Box::pin(dispatch_build(path, system, config)));
+        let build = ARTIFACT_GLOBS.scope((path.to_path_buf(), config.artifact_globs.clone()), build);
+        let attempt_started = Instant::now();
+        let (result, captured) = limit_output(limits.max_log_bytes, capture_output(run_scoped(build, isolated, container.clone()))).await;
+        let CapturedOutput { text: output, stdout, stderr, failed_exit } = captured;
+        let result = with_out_dir_artifacts(result, failed_exit.is_some(), system, config, attempt_started).await;
+
+        let failure = match &result {
+            Ok(result) if result.success => None,
+            Ok(result) => Some(result.error_output.clone().unwrap_or_default()),
+            Err(e) => Some(e.to_string()),
+        };
+        attempts.push(BuildAttempt {
+            attempt: retries + 1,
+            duration_ms: attempt_started.elapsed().as_millis() as u64,
+            error: failure.clone(),
+        });
+
+        // Retrying can't help a build that needs the network it was denied
+        if isolated && failure.as_deref().is_some_and(|error| attempted_network_access(error, &output)) {
+            const CLASSIFICATION: &str = "Build attempted network access while network-isolated";
+            let mut result = result.map_err(|e| anyhow!("{}: {}", CLASSIFICATION, e))?;
+            result.error_output = Some(format!("{}: {}", CLASSIFICATION, result.error_output.unwrap_or_default()));
+            result.provenance = provenance;
+            result.attempts = attempts;
+            result.duration_ms = started.elapsed().as_millis() as u64;
+            result.stdout = stdout;
+            result.stderr = stderr;
+            apply_diagnostics(&mut result, &output, path, config);
+            record_exit(&mut result, failed_exit);
+            return Ok(result);
+        }
+
+        if let Some(pattern) = failure.and_then(|error| policy.transient_match(&error, &output)) {
+            if retries < policy.max_retries {
+                let delay = policy.backoff(retries);
+                retries += 1;
+                tracing::warn!(
+                    "Build failed with transient error '{}', retrying in {}ms (attempt {} of {})",
+                    pattern,
+                    delay.as_millis(),
+                    retries,
+                    policy.max_retries
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        }
+
+        let mut result = result.map_err(|e| {
+            let e = match retries {
+                0 => e,
+                n => anyhow!("{} (still failing after {} retries)", e, n),
+            };
+            let mut diagnostics = relevant_diagnostics(&output, path, config);
+            diagnostics.truncate(MAX_REPORTED_DIAGNOSTICS);
+            if diagnostics.is_empty() && failed_exit.is_none() && stdout.is_empty() && stderr.is_empty() {
+                e
+            } else {
+                BuildStepFailed {
+                    source: e,
+                    warning_count: diagnostics.iter().filter(|d| d.severity == Severity::Warning).count(),
+                    error_count: diagnostics.iter().filter(|d| d.severity == Severity::Error).count(),
+                    diagnostics,
+                    exit: failed_exit.unwrap_or_default(),
+                    stdout: stdout.clone(),
+                    stderr: stderr.clone(),
+                }
+                .into()
+            }
+        })?;
+        result.retries = retries;
+        result.stdout = stdout;
+        result.stderr = stderr;
+        result.attempts = attempts;
+        result.provenance = provenance;
+        apply_diagnostics(&mut result, &output, path, config);
+        record_exit(&mut result, failed_exit);
+
+        if let Some(hook) = config.post_build.as_deref().filter(|_| result.success) {
+            run_scoped(run_post_build(path, hook, &mut result, config), isolated, container).await?;
+        }
+        if let Some(max_bytes) = limits.max_artifact_bytes.filter(|_| result.success) {
+            enforce_artifact_size(&mut result, max_bytes).await;
+        }
+        result.duration_ms = started.elapsed().as_millis() as u64;
+        return Ok(result);
+    }
+}
+
+/// The `matrix` entry fields each build system can use
+fn matrix_fields(system: BuildSystem) -> Option<&'static [&'static str]> {
+    match system {
+        BuildSystem::PlatformIO => Some(&["env"]),
+        BuildSystem::ZephyrWest | BuildSystem::CMake => Some(&["board", "cmake_defines"]),
+        BuildSystem::Makefile => Some(&["make_vars"]),
+        _ => None,
+    }
+}
+
+/// Why `entry` can't be built by `system`, if it can't
+fn matrix_entry_problem(system: BuildSystem, entry: &MatrixEntry) -> Option<String> {
+    let Some(fields) = matrix_fields(system) else {
+        return Some(format!("matrix builds aren't supported for {:?} projects", system));
+    };
+    let set = [
+        ("board", entry.board.is_some()),
+        ("env", entry.env.is_some()),
+        ("cmake_defines", !entry.cmake_defines.is_empty()),
+        ("make_vars", !entry.make_vars.is_empty()),
+    ];
+    let unused: Vec<&str> = set.iter().filter(|(field, set)| *set && !fields.contains(field)).map(|(field, _)| *field).collect();
+    (!unused.is_empty()).then(|| {
+        format!(
+            "matrix entry '{}' sets {}, which {:?} builds don't use (they use {})",
+            entry.name,
+            unused.join(", "),
+            system,
+            fields.join(", ")
+        )
+    })
+}
+
+/// Boxed so a matrix entry's build can recurse into [`execute_build_with_config`]
+fn build_matrix_entry<'a>(path: &'a Path, system: BuildSystem, config: &'a BuildConfig) -> Pin<Box<dyn Future<Output = Result<BuildResult>> + Send + 'a>> {
+    Box::pin(execute_build_with_config(path, system, config))
+}
+
+/// Build each `matrix` entry as its own build of the same workspace, with its own build
+/// directory ([`BuildConfig::build_dir`]) and `NABLA_OUT_DIR`, up to `max_parallel_envs` at
+/// once. `matrix` records how each entry fared and every artifact is tagged with its entry's
+/// `matrix` name. Like a multi-environment build, it succeeds if any entry built, or only if all
+/// did with `require_all`.
+async fn build_matrix(path: &Path, system: BuildSystem, config: &BuildConfig) -> Result<BuildResult> {
+    if let Some(problem) = config.matrix.iter().find_map(|entry| matrix_entry_problem(system, entry)) {
+        return Err(anyhow!(problem));
+    }
+    let started = Instant::now();
+    let parallelism = platformio_env_parallelism(config, config.matrix.len());
+    tracing::info!("Building {} matrix entries, {} at a time", config.matrix.len(), parallelism);
+
+    let mut entries = Vec::new();
+    for entry in &config.matrix {
+        let mut entry_config = BuildConfig {
+            matrix: Vec::new(),
+            matrix_entry: Some(entry.clone()),
+            ..config.clone()
+        };
+        if let Some(env) = &entry.env {
+            entry_config.pio_envs = vec![env.clone()];
+        }
+        if let Some(out_dir) = config.command_env.get(OUT_DIR_ENV) {
+            let entry_out = Path::new(out_dir).join(&entry.name);
+            fs::create_dir_all(&entry_out).await?;
+            entry_config.command_env.insert(OUT_DIR_ENV.to_string(), entry_out.to_string_lossy().to_string());
+        }
+        entries.push(entry_config);
+    }
+    let slots = Semaphore::new(parallelism);
+    let mut builds = Vec::new();
+    for entry_config in &entries {
+        let slots = &slots;
+        builds.push(async move {
+            let _slot = slots.acquire().await;
+            let entry_started = Instant::now();
+            let outcome = build_matrix_entry(path, system, entry_config).await;
+            (entry_config, entry_started.elapsed().as_millis() as u64, outcome)
+        });
+    }
+    let outcomes = futures::future::join_all(builds).await;
+
+    let default_build_dir = match system {
+        BuildSystem::PlatformIO => ".pio/build",
+        _ => "build",
+    };
+    let relative = |file: &str| Path::new(file).strip_prefix(path).map(|p| p.display().to_string()).unwrap_or_else(|_| file.to_string());
+    let mut matrix = Vec::new();
+    let mut artifacts = Vec::new();
+    let mut failures = Vec::new();
+    let mut built = Vec::new();
+    for (entry_config, duration_ms, outcome) in outcomes {
+        let name = entry_config.matrix_entry.as_ref().map(|entry| entry.name.clone()).unwrap_or_default();
+        let error = match &outcome {
+            Ok(result) if result.success => None,
+            Ok(result) => Some(result.error_output.clone().unwrap_or_else(|| "Build failed".to_string())),
+            Err(e) => Some(e.to_string()),
+        };
+        let mut entry_artifacts = Vec::new();
+        if let Ok(result) = outcome {
+            if error.is_none() {
+                for mut artifact in result.artifacts.iter().cloned() {
+                    entry_artifacts.push(relative(&artifact.path));
+                    artifact.metadata.insert("matrix".to_string(), name.clone());
+                    artifacts.push(artifact);
+                }
+            }
+            built.push((name.clone(), result));
+        }
+        if let Some(error) = &error {
+            failures.push(format!("[{}] {}", name, error.trim_end()));
+        }
+        matrix.push(MatrixResult {
+            name,
+            success: error.is_none(),
+            error,
+            build_dir: entry_config.build_dir(default_build_dir),
+            artifacts: entry_artifacts,
+            duration_ms,
+        });
+    }
+
+    let failed: Vec<&str> = matrix.iter().filter(|entry| !entry.success).map(|entry| entry.name.as_str()).collect();
+    let success = failed.len() < matrix.len() && (!config.require_all || failed.is_empty());
+    let error_output = (!success).then(|| {
+        let scope = match failed.len() == matrix.len() {
+            true => "every entry".to_string(),
+            false => format!("{} of {} entries", failed.len(), matrix.len()),
+        };
+        format!("Matrix build failed in {} ({}):\n{}", scope, failed.join(", "), failures.join("\n"))
+    });
+    let prefixed = |name: &str, text: &str| text.lines().map(|line| format!("[{}] {}\n", name, line)).collect::<String>();
+    let mut diagnostics: Vec<Diagnostic> = built.iter().flat_map(|(_, result)| result.diagnostics.iter().cloned()).collect();
+    diagnostics.truncate(MAX_REPORTED_DIAGNOSTICS);
+    let mut config_warnings: Vec<String> = Vec::new();
+    for warning in built.iter().flat_map(|(_, result)| &result.config_warnings) {
+        if !config_warnings.contains(warning) {
+            config_warnings.push(warning.clone());
+        }
+    }
+
+    let primary = artifacts.first().cloned();
+    Ok(BuildResult {
+        success,
+        output_path: primary.as_ref().map(|a| a.path.clone()),
+        target_format: primary.map(|a| a.format),
+        error_output,
+        build_system: system,
+        duration_ms: started.elapsed().as_millis() as u64,
+        artifacts,
+        warning_count: built.iter().map(|(_, result)| result.warning_count).sum(),
+        error_count: built.iter().map(|(_, result)| result.error_count).sum(),
+        test_results: None,
+        environments: Vec::new(),
+        targets: built.iter().flat_map(|(_, result)| result.targets.iter().cloned()).collect(),
+        matrix,
+        cache: None,
+        retries: built.iter().map(|(_, result)| result.retries).sum(),
+        attempts: Vec::new(),
+        config_warnings,
+        provenance: built.first().map(|(_, result)| result.provenance.clone()).unwrap_or_default(),
+        diagnostics,
+        static_analysis: built.iter().flat_map(|(_, result)| result.static_analysis.iter().cloned()).collect(),
+        stdout: built.iter().map(|(name, result)| prefixed(name, &result.stdout)).collect(),
+        stderr: built.iter().map(|(name, result)| prefixed(name, &result.stderr)).collect(),
+        post_build_output: None,
+        platform_install_output: None,
+        exit_code: None,
+        signal: None,
+    })
+}
+
+/// Attribute a failed result to the command that failed, unless the build already did
+fn record_exit(result: &mut BuildResult, failed_exit: Option<ProcessExit>) {
+    if let Some(exit) = failed_exit.filter(|_| !result.success && result.exit_code.is_none() && result.signal.is_none()) {
+        result.exit_code = exit.exit_code;
+        result.signal = exit.signal;
+    }
+}
+
+/// `config` with the resolved `limits` applied: the timeout, and the parallelism as PlatformIO's
+/// environment count and the job count of make, CMake, Cargo and SCons, unless the request's
+/// environment already sets those
+fn limited_config(config: &BuildConfig, limits: &BuildLimits) -> BuildConfig {
+    let mut config = config.clone();
+    config.timeout_secs = Some(limits.timeout_secs);
+    if let Some(parallelism) = limits.parallelism {
+        config.max_parallel_envs = Some(parallelism);
+        for (name, value) in [
+            ("MAKEFLAGS", format!("-j{}", parallelism)),
+            ("CMAKE_BUILD_PARALLEL_LEVEL", parallelism.to_string()),
+            ("CARGO_BUILD_JOBS", parallelism.to_string()),
+            ("SCONSFLAGS", format!("-j{}", parallelism)),
+        ] {
+            config.command_env.entry(name.to_string()).or_insert(value);
+        }
+    }
+    config
+}
+
+/// `config` with the repository and build system exported to every command the build runs,
+/// as `NABLA_REPO_DIR` and `NABLA_BUILD_SYSTEM`
+fn build_env(config: &BuildConfig, path: &Path, system: BuildSystem) -> BuildConfig {
+    let mut config = config.clone();
+    config.command_env.insert("NABLA_REPO_DIR".to_string(), path.to_string_lossy().to_string());
+    config.command_env.insert("NABLA_BUILD_SYSTEM".to_string(), format!("{:?}", system).to_lowercase());
+    config
+}
+
+/// Put the files a build left in `NABLA_OUT_DIR` ahead of the artifacts it found, the first
+/// becoming the primary artifact. A build whose commands all succeeded but that found no
+/// artifact of its own succeeds with them.
+async fn with_out_dir_artifacts(
+    result: Result<BuildResult>,
+    command_failed: bool,
+    system: BuildSystem,
+    config: &BuildConfig,
+    start_time: Instant,
+) -> Result<BuildResult> {
+    let Some(out_dir) = config.command_env.get(OUT_DIR_ENV).map(PathBuf::from) else {
+        return result;
+    };
+    let files = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        list_files(&out_dir, &out_dir, &mut files);
+        files.sort();
+        files
+    })
+    .await
+    .unwrap_or_default();
+    let mut artifacts: Vec<Artifact> = files
+        .into_iter()
+        .map(|(_, file)| {
+            let format = file.extension().and_then(|ext| ext.to_str()).unwrap_or("bin").to_string();
+            Artifact::new(file.to_string_lossy().to_string(), format)
+        })
+        .collect();
+    let Some(primary) = artifacts.first().cloned() else {
+        return result;
+    };
+
+    let mut result = match result {
+        Ok(result) if result.success => result,
+        Err(e) if command_failed => return Err(e),
+        Ok(result) if command_failed => return Ok(result),
+        Ok(_) | Err(_) => {
+            tracing::info!("Build found no artifact of its own; using {} from {}", primary.path, OUT_DIR_ENV);
+            create_build_result(primary.path.clone(), primary.format.clone(), system, start_time)
+        }
+    };
+    let found: Vec<Artifact> = std::mem::take(&mut result.artifacts)
+        .into_iter()
+        .filter(|found| !artifacts.iter().any(|artifact| artifact.path == found.path))
+        .collect();
+    artifacts.extend(found);
+    result.output_path = Some(primary.path);
+    result.target_format = Some(primary.format);
+    result.artifacts = artifacts;
+    Ok(result)
+}
+
+/// Fail a build whose artifacts exceed the operator's `max_artifact_bytes`
+async fn enforce_artifact_size(result: &mut BuildResult, max_bytes: u64) {
+    for artifact in &result.artifacts {
+        let Ok(metadata) = fs::metadata(&artifact.path).await else {
+            continue;
+        };
+        if metadata.len() > max_bytes {
+            result.success = false;
+            result.error_output = Some(format!(
+                "Artifact {} is {} bytes, over the {} byte limit for {:?} builds",
+                artifact.path,
+                metadata.len(),
+                max_bytes,
+                result.build_system
+            ));
+            return;
+        }
+    }
+}
+
+/// Whether the operator allows `post_build` hooks, which run arbitrary commands from the
+/// request: `NABLA_ALLOW_HOOKS=1`
+pub fn hooks_allowed() -> bool {
+    std::env::var("NABLA_ALLOW_HOOKS").is_ok_and(|value| value == "1")
+}
+
+/// Whether the operator allows Dockerfile builds, which run the repository's `RUN` steps through
+/// the runner's docker daemon: `NABLA_ALLOW_DOCKERFILE=1`
+pub fn dockerfile_builds_allowed() -> bool {
+    std::env::var("NABLA_ALLOW_DOCKERFILE").is_ok_and(|value| value == "1")
+}
+
+/// Run the `post_build` hook in the repository; a non-zero exit fails the build. The artifact
+/// path is passed as a positional parameter rather than pasted into the command line.
+async fn run_post_build(path: &Path, hook: &str, result: &mut BuildResult, config: &BuildConfig) -> Result<()> {
+    let artifact = result.output_path.clone().unwrap_or_default();
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(hook.replace("{artifact}", "\"$1\""))
+        .arg("post_build")
+        .arg(&artifact)
+        .current_dir(path);
+    let output = run_command(command, config).await?;
+
+    let log = format!("{}{}", OutputBuffer::text(&output.stdout), OutputBuffer::text(&output.stderr));
+    if !output.status.success() {
+        result.success = false;
+        result.error_output = Some(format!("post_build hook failed ({}): {}", output.status, log.trim()));
+    }
+    result.post_build_output = Some(log);
+    Ok(())
+}
+
+/// Run `future` without network access and/or inside a container, as the build requires
+async fn run_scoped<F: std::future::Future>(future: F, isolated: bool, container: Option<Arc<ContainerContext>>) -> F::Output {
+    let future = async {
+        if isolated {
+            without_network(future).await
+        } else {
+            future.await
+        }
+    };
+    match container {
+        Some(container) => in_container(container, future).await,
+        None => future.await,
+    }
+}
+
+/// Errors a tool prints when it can't reach the network. Only name resolution and unreachable
+/// networks: a namespace keeps loopback, so "connection refused" is as likely a local service
+/// the build expected, e.g. a test fixture that didn't start.
+const NETWORK_ACCESS_PATTERNS: &[&str] = &[
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "name or service not known",
+    "network is unreachable",
+];
+
+/// Whether a failed build's error or output shows it tried to reach the network
+pub fn attempted_network_access(error: &str, output: &str) -> bool {
+    let error = error.to_lowercase();
+    let output = output.to_lowercase();
+    NETWORK_ACCESS_PATTERNS
+        .iter()
+        .any(|pattern| error.contains(pattern) || output.contains(pattern))
+}
+
+/// Download what the compile step needs, for builds that then run without network access
+async fn fetch_dependencies(path: &Path, system: BuildSystem, config: &BuildConfig) -> Result<()> {
+    let (step, command) = match system {
+        BuildSystem::PlatformIO => {
+            let mut command = Command::new("pio");
+            command.args(["pkg", "install"]).current_dir(path);
+            ("pio pkg install", command)
+        }
+        BuildSystem::ZephyrWest => {
+            let layout = crate::west::layout(path).await;
+            if !layout.initialized {
+                init_west_workspace(path, &layout, config).await?;
+            }
+            let mut command = Command::new("west");
+            command.arg("update").current_dir(path);
+            ("west update", command)
+        }
+        BuildSystem::CMake => {
+            // Configuring populates FetchContent dependencies; the isolated build then reuses them
+            let build_dir = path.join(config.build_dir("build"));
+            fs::create_dir_all(&build_dir).await?;
+            let mut command = Command::new("cmake");
+            command.args(cmake_configure_args(config)).current_dir(&build_dir);
+            ("cmake configure", command)
+        }
+        _ => return Ok(()),
+    };
+
+    tracing::info!("Fetching dependencies with {} before isolating the build", step);
+    let output = run_command(command, config).await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Dependency fetch ({}) failed: {}",
+            step,
+            OutputBuffer::text(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// Failures that come from a flaky network rather than the project, e.g. a package registry
+/// returning 503 while `pio` installs a platform
+const DEFAULT_TRANSIENT_ERROR_PATTERNS: &[&str] = &[
+    "502 bad gateway",
+    "503 service unavailable",
+    "504 gateway timeout",
+    "connection reset by peer",
+    "temporary failure in name resolution",
+    "could not resolve host",
+    "read timed out",
+];
+const DEFAULT_TRANSIENT_RETRIES: u32 = 2;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 5000;
+
+/// When and how often a failed build is rerun unchanged
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each one after
+    pub initial_backoff: Duration,
+    /// Lowercase substrings of the error or build output that mark a failure as transient
+    pub patterns: Vec<String>,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &BuildConfig) -> Self {
+        let env_number = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        let max_retries = config
+            .transient_retries
+            .or_else(|| env_number("NABLA_TRANSIENT_RETRIES").map(|n| n.min(MAX_TRANSIENT_RETRIES as u64) as u32))
+            .unwrap_or(DEFAULT_TRANSIENT_RETRIES);
+
+        Self {
+            max_retries,
+            initial_backoff: Duration::from_millis(env_number("NABLA_RETRY_BACKOFF_MS").unwrap_or(DEFAULT_RETRY_BACKOFF_MS)),
+            patterns: DEFAULT_TRANSIENT_ERROR_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .chain(config.transient_error_patterns.iter().map(|p| p.to_lowercase()))
+                .collect(),
+        }
+    }
+
+    /// The first pattern found in the failure message or build output, if any
+    pub fn transient_match(&self, error: &str, output: &str) -> Option<&str> {
+        let error = error.to_lowercase();
+        let output = output.to_lowercase();
+        self.patterns
+            .iter()
+            .find(|pattern| error.contains(pattern.as_str()) || output.contains(pattern.as_str()))
+            .map(String::as_str)
+    }
+
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+async fn dispatch_build(path: &Path, system: BuildSystem, config: &BuildConfig) -> Result<BuildResult> {
     match system {
-        BuildSystem::PlatformIO => build_platformio_original(path).await,
-        BuildSystem::CMake => build_cmake_original(path).await,
-        BuildSystem::Makefile => build_makefile_original(path).await,
-        BuildSystem::ZephyrWest => build_zephyr_original(path).await,
-        BuildSystem::STM32CubeIDE => build_stm32_original(path).await,
-        BuildSystem::SCons => build_scons_original(path).await,
+        BuildSystem::Cargo => build_cargo_original(path, config).await,
+        BuildSystem::PlatformIO => build_platformio_original(path, config).await,
+        BuildSystem::CMake => build_cmake_original(path, config).await,
+        BuildSystem::Makefile => build_makefile_original(path, config).await,
+        BuildSystem::ZephyrWest => build_zephyr_original(path, config).await,
+        BuildSystem::STM32CubeIDE => build_stm32_original(path, config).await,
+        BuildSystem::SCons => build_scons_original(path, config).await,
+        BuildSystem::Buildroot => build_buildroot_original(path, config).await,
+        BuildSystem::Yocto => build_yocto_original(path, config).await,
+        BuildSystem::MplabX => build_mplabx_original(path, config).await,
+        BuildSystem::Dockerfile => build_dockerfile_original(path, config).await,
+    }
+}
+
+/// Most diagnostics reported per build; one broken header can produce thousands
+const MAX_REPORTED_DIAGNOSTICS: usize = 200;
+
+/// A build command failed outright; carries the compiler diagnostics from its output
+#[derive(Debug, thiserror::Error)]
+#[error("{source}")]
+pub struct BuildStepFailed {
+    pub source: anyhow::Error,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Warnings and errors among `diagnostics`, as [`BuildResult::warning_count`] counts them
+    pub warning_count: usize,
+    pub error_count: usize,
+    /// How the failing command ended, when one did
+    pub exit: ProcessExit,
+    /// What the build's commands printed to stdout and stderr, see [`BuildResult::stdout`]
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Diagnostics in the build output, except those in files matched by `warning_excludes`
+fn relevant_diagnostics(output: &str, repo_dir: &Path, config: &BuildConfig) -> Vec<Diagnostic> {
+    let repo_prefix = format!("{}/", repo_dir.display());
+    parse_diagnostics(output)
+        .into_iter()
+        .filter(|d| {
+            let relative = d.file.strip_prefix(&repo_prefix).unwrap_or(&d.file);
+            let relative = relative.trim_start_matches("./");
+            !config.warning_excludes.iter().any(|pattern| glob_match(pattern, relative))
+        })
+        .collect()
+}
+
+/// Count compiler warnings and errors in the build output, skipping files matched by
+/// `warning_excludes`, and fail the build when `fail_on_warnings` is set and any remain.
+pub fn apply_diagnostics(result: &mut BuildResult, output: &str, repo_dir: &Path, config: &BuildConfig) {
+    let diagnostics = relevant_diagnostics(output, repo_dir, config);
+
+    let warnings: Vec<&Diagnostic> = diagnostics.iter().filter(|d| d.severity == Severity::Warning).collect();
+    result.warning_count = warnings.len();
+    result.error_count = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+
+    if config.fail_on_warnings && result.success && !warnings.is_empty() {
+        let listed: Vec<String> = warnings.iter().map(|d| d.summary()).collect();
+        result.success = false;
+        result.error_output = Some(format!(
+            "Build produced {} warning(s) with fail_on_warnings enabled:\n{}",
+            warnings.len(),
+            listed.join("\n")
+        ));
     }
+
+    result.diagnostics = diagnostics;
+    result.diagnostics.truncate(MAX_REPORTED_DIAGNOSTICS);
 }
 
+/// The result of a build that finished with `output_path` as its artifact. A zero exit
+/// status isn't enough: the result is only successful if that file exists.
 fn create_build_result(output_path: String, target_format: String, build_system: BuildSystem, start_time: Instant) -> BuildResult {
+    let success = Path::new(&output_path).is_file();
     BuildResult {
-        success: true,
-        output_path: Some(output_path),
-        target_format: Some(target_format),
-        error_output: None,
+        success,
+        output_path: Some(output_path.clone()),
+        target_format: Some(target_format.clone()),
+        error_output: (!success).then(|| format!("Build finished but its artifact {} does not exist", output_path)),
         build_system,
         duration_ms: start_time.elapsed().as_millis() as u64,
+        artifacts: vec![Artifact::new(output_path, target_format)],
+        warning_count: 0,
+        error_count: 0,
+        test_results: None,
+        environments: Vec::new(),
+        targets: Vec::new(),
+        matrix: Vec::new(),
+        cache: None,
+        retries: 0,
+        attempts: Vec::new(),
+        config_warnings: Vec::new(),
+        provenance: Provenance::default(),
+        diagnostics: Vec::new(),
+        static_analysis: Vec::new(),
+        stdout: String::new(),
+        stderr: String::new(),
+        post_build_output: None,
+        platform_install_output: None,
+        exit_code: None,
+        signal: None,
+    }
+}
+
+/// Flags that set `verbosity` for `system`'s build command: `cargo build`, `make` (for
+/// Makefile, STM32CubeIDE, Buildroot and MPLAB X projects), the CMake configure step,
+/// `pio run`, `west` (before `build`), `scons` and `docker build`. Empty for `normal`.
+pub fn verbosity_flags(system: BuildSystem, verbosity: Verbosity) -> &'static [&'static str] {
+    use BuildSystem::*;
+    match (system, verbosity) {
+        (_, Verbosity::Normal) | (Yocto, _) => &[],
+        (Cargo, Verbosity::Quiet) => &["-q"],
+        (Cargo, Verbosity::Verbose) => &["-v"],
+        (Cargo, Verbosity::Debug) => &["-vv"],
+        (Makefile | STM32CubeIDE | Buildroot | MplabX, Verbosity::Quiet) => &["-s"],
+        (Makefile | STM32CubeIDE | Buildroot | MplabX, Verbosity::Verbose) => &["V=1"],
+        (Makefile | STM32CubeIDE | Buildroot | MplabX, Verbosity::Debug) => &["V=1", "--debug=basic"],
+        (CMake, Verbosity::Quiet) => &["--log-level=WARNING"],
+        (CMake, Verbosity::Verbose) => &["--log-level=VERBOSE"],
+        (CMake, Verbosity::Debug) => &["--log-level=DEBUG"],
+        (PlatformIO, Verbosity::Quiet) => &["-s"],
+        (PlatformIO, Verbosity::Verbose | Verbosity::Debug) => &["-v"],
+        (ZephyrWest, Verbosity::Quiet) => &["-q"],
+        (ZephyrWest, Verbosity::Verbose) => &["-v"],
+        (ZephyrWest, Verbosity::Debug) => &["-vv"],
+        (SCons, Verbosity::Quiet) => &["-s"],
+        (SCons, Verbosity::Verbose) => &["--debug=explain"],
+        (SCons, Verbosity::Debug) => &["--debug=explain", "--debug=time"],
+        (Dockerfile, Verbosity::Quiet) => &["--quiet"],
+        (Dockerfile, Verbosity::Verbose | Verbosity::Debug) => &["--progress=plain"],
+    }
+}
+
+/// [`verbosity_flags`] as owned arguments
+fn verbosity_args(system: BuildSystem, verbosity: Verbosity) -> impl Iterator<Item = String> {
+    verbosity_flags(system, verbosity).iter().map(|flag| flag.to_string())
+}
+
+/// Arguments for the CMake configure step, run from the `build/` directory
+pub fn cmake_configure_args(config: &BuildConfig) -> Vec<String> {
+    let mut args = vec!["..".to_string()];
+    args.extend(verbosity_args(BuildSystem::CMake, config.verbosity));
+
+    if let Some((version, gnu)) = config.c_standard.as_deref().and_then(|s| language_standard_version(s, "c")) {
+        args.push(format!("-DCMAKE_C_STANDARD={}", version));
+        args.push(format!("-DCMAKE_C_EXTENSIONS={}", if gnu { "ON" } else { "OFF" }));
+    }
+
+    if let Some((version, gnu)) = config.cpp_standard.as_deref().and_then(|s| language_standard_version(s, "c++")) {
+        args.push(format!("-DCMAKE_CXX_STANDARD={}", version));
+        args.push(format!("-DCMAKE_CXX_EXTENSIONS={}", if gnu { "ON" } else { "OFF" }));
+    }
+
+    args.extend(config.board().map(|board| format!("-DBOARD={}", board)));
+    if let Some(entry) = &config.matrix_entry {
+        args.extend(entry.cmake_defines.iter().map(|(name, value)| format!("-D{}={}", name, value)));
+    }
+
+    args
+}
+
+/// Arguments for the CMake build step: `--verbose` echoes each compiler command line
+pub fn cmake_build_args(config: &BuildConfig) -> Vec<String> {
+    let mut args = vec!["--build".to_string(), ".".to_string()];
+    if matches!(config.verbosity, Verbosity::Verbose | Verbosity::Debug) {
+        args.push("--verbose".to_string());
+    }
+    args
+}
+
+/// Arguments for `cargo build`: package and binary, profile, target triple and feature selection
+pub fn cargo_build_args(config: &BuildConfig) -> Vec<String> {
+    let mut args = vec!["build".to_string()];
+    args.extend(verbosity_args(BuildSystem::Cargo, config.verbosity));
+
+    if let Some(package) = &config.cargo_package {
+        args.push("-p".to_string());
+        args.push(package.clone());
+    }
+
+    if let Some(bin) = &config.cargo_bin {
+        args.push("--bin".to_string());
+        args.push(bin.clone());
+    }
+
+    if config.release {
+        args.push("--release".to_string());
+    }
+
+    if let Some(target) = &config.cargo_target {
+        args.push("--target".to_string());
+        args.push(target.clone());
+    }
+
+    if config.no_default_features {
+        args.push("--no-default-features".to_string());
+    }
+
+    if !config.features.is_empty() {
+        args.push("--features".to_string());
+        args.push(config.features.join(","));
+    }
+
+    args
+}
+
+/// Variable assignments and verbosity flags appended to the `make` command line
+pub fn make_args(config: &BuildConfig) -> Vec<String> {
+    let mut args: Vec<String> = verbosity_args(BuildSystem::Makefile, config.verbosity).collect();
+
+    if let Some(std) = &config.c_standard {
+        args.push(format!("CFLAGS+=-std={}", std));
+    }
+
+    if let Some(std) = &config.cpp_standard {
+        args.push(format!("CXXFLAGS+=-std={}", std));
+    }
+
+    // A matrix entry builds into its own BUILD_DIR, which CubeMX and many other Makefiles honor
+    if let Some(entry) = &config.matrix_entry {
+        if !entry.make_vars.contains_key("BUILD_DIR") {
+            args.push(format!("BUILD_DIR={}", config.build_dir("build")));
+        }
+        args.extend(entry.make_vars.iter().map(|(name, value)| format!("{}={}", name, value)));
+    }
+
+    args
+}
+
+/// Extra flags passed to PlatformIO through `PLATFORMIO_BUILD_FLAGS`. SCons routes `-std=`
+/// values containing `++` to CXXFLAGS and the rest to CFLAGS, so both can be given at once.
+pub fn platformio_build_flags(config: &BuildConfig) -> Option<String> {
+    let flags: Vec<String> = [&config.c_standard, &config.cpp_standard]
+        .into_iter()
+        .flatten()
+        .map(|std| format!("-std={}", std))
+        .collect();
+
+    if flags.is_empty() {
+        None
+    } else {
+        Some(flags.join(" "))
+    }
+}
+
+tokio::task_local! {
+    /// The repository root and `artifact_globs` of the build running on this task
+    static ARTIFACT_GLOBS: (PathBuf, Vec<String>);
+}
+
+/// The first file under the repository matching one of the build's `artifact_globs`, trying
+/// the globs in order and paths alphabetically within each
+async fn find_by_artifact_globs() -> Option<PathBuf> {
+    let (root, globs) = ARTIFACT_GLOBS.try_with(Clone::clone).ok().filter(|(_, globs)| !globs.is_empty())?;
+    let files = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        list_files(&root, &root, &mut files);
+        files.sort();
+        files
+    })
+    .await
+    .ok()?;
+
+    let found = globs.iter().find_map(|glob| {
+        files
+            .iter()
+            .find(|(relative, path)| glob_match(glob, relative) && !source::skip_as_artifact(path))
+            .map(|(_, path)| path.clone())
+    });
+    if let Some(path) = &found {
+        tracing::info!("Found artifact matching artifact_globs: {:?}", path);
+    }
+    found
+}
+
+/// Regular files under `dir` outside `.git`, as (path relative to `root`, path)
+fn list_files(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() && entry.file_name() != ".git" => list_files(root, &path, files),
+            Ok(kind) if kind.is_file() => {
+                if let Ok(relative) = path.strip_prefix(root) {
+                    files.push((relative.to_string_lossy().to_string(), path));
+                }
+            }
+            _ => {}
+        }
     }
 }
 
 /// Helper function to find executable files in a directory
 async fn find_executable_in_dir(dir: &Path) -> Result<PathBuf> {
     tracing::debug!("Searching for executable in directory: {:?}", dir);
+
+    if let Some(path) = find_by_artifact_globs().await {
+        return Ok(path);
+    }
     
     if !dir.exists() {
         return Err(anyhow!("Directory does not exist: {:?}", dir));
@@ -47,9 +954,9 @@ async fn find_executable_in_dir(dir: &Path) -> Result<PathBuf> {
             let permissions = metadata.permissions();
             
             // Check if file is executable (Unix-specific)
-            if permissions.mode() & 0o111 != 0 {
+            if permissions.mode() & 0o111 != 0 && !source::skip_as_artifact(&path) {
                 // Additional check: ensure it's not a script or text file
-                if !path.extension().map_or(false, |ext| 
+                if !path.extension().is_some_and(|ext| 
                     ext == "sh" || ext == "py" || ext == "txt" || ext == "md" || ext == "yml" || ext == "yaml" || ext == "json"
                 ) {
                     tracing::debug!("Found executable candidate: {:?}", path);
@@ -71,6 +978,10 @@ async fn find_executable_in_dir(dir: &Path) -> Result<PathBuf> {
 /// Helper function to find binary files by common patterns
 async fn find_binary_by_patterns(dir: &Path, patterns: &[&str]) -> Result<PathBuf> {
     tracing::debug!("Searching for binary in {:?} with patterns: {:?}", dir, patterns);
+
+    if let Some(path) = find_by_artifact_globs().await {
+        return Ok(path);
+    }
     
     if !dir.exists() {
         tracing::warn!("Directory does not exist: {:?}", dir);
@@ -81,7 +992,7 @@ async fn find_binary_by_patterns(dir: &Path, patterns: &[&str]) -> Result<PathBu
     for pattern in patterns {
         let path = dir.join(pattern);
         tracing::trace!("Checking exact path: {:?}", path);
-        if path.exists() && path.is_file() {
+        if path.is_file() && !source::skip_as_artifact(&path) {
             tracing::info!("Found binary at exact path: {:?}", path);
             return Ok(path);
         }
@@ -94,7 +1005,7 @@ async fn find_binary_by_patterns(dir: &Path, patterns: &[&str]) -> Result<PathBu
                 dir.join(format!("{}{}", pattern, ext))
             };
             tracing::trace!("Checking path with extension: {:?}", path_with_ext);
-            if path_with_ext.exists() && path_with_ext.is_file() {
+            if path_with_ext.is_file() && !source::skip_as_artifact(&path_with_ext) {
                 tracing::info!("Found binary with extension: {:?}", path_with_ext);
                 return Ok(path_with_ext);
             }
@@ -117,181 +1028,1696 @@ async fn find_binary_by_patterns(dir: &Path, patterns: &[&str]) -> Result<PathBu
     find_executable_in_dir(dir).await
 }
 
-pub async fn build_makefile_original(path: &Path) -> Result<BuildResult> {
-    let start_time = Instant::now();
-    // First, try to get the output name from make (for future enhancement)
-    let _dry_run = Command::new("make")
-        .arg("-n")
-        .arg("--print-data-base")
-        .current_dir(path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await;
-    
-    // Run the actual build
-    let output = Command::new("make")
-        .current_dir(path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await?;
+/// What a runner operator or user can do about a failed Cargo build, when its output shows a
+/// known cause: a cross-compilation target without its standard library installed, a package,
+/// binary or feature that doesn't exist, or no `cargo` at all
+pub fn analyze_cargo_error(output: &str) -> Option<String> {
+    // rustc: "= note: the `thumbv7em-none-eabihf` target may not be installed"
+    if let Some(target) = output.lines().find_map(|line| {
+        let (_, rest) = line.split_once("the `")?;
+        let (target, rest) = rest.split_once('`')?;
+        rest.trim_start().starts_with("target may not be installed").then_some(target)
+    }) {
+        return Some(format!(
+            "The Rust target {} is not installed on the runner; install it with `rustup target add {}`",
+            target, target
+        ));
+    }
 
-    if !output.status.success() {
-        return Err(anyhow!("Make build failed: {}", String::from_utf8_lossy(&output.stderr)));
+    // The first `quoted` name after `pattern` on the line that has it
+    let named = |pattern: &str| {
+        output.lines().find_map(|line| {
+            let (_, rest) = line.split_once(pattern)?;
+            let (_, rest) = rest.split_once('`')?;
+            rest.split_once('`').map(|(name, _)| name.to_string())
+        })
+    };
+    if let Some(bin) = named("no bin target named") {
+        return Some(format!(
+            "The package has no binary named `{}`; set build_config.cargo_bin to one of its [[bin]] targets",
+            bin
+        ));
+    }
+    if let Some(package) = named("package ID specification") {
+        return Some(format!(
+            "No package named `{}` in the workspace; set build_config.cargo_package to a workspace member",
+            package
+        ));
+    }
+    if let Some(feature) = named("does not have the feature") {
+        return Some(format!("The selected package has no feature `{}`; check build_config.features", feature));
+    }
+    if let Some(line) = output.lines().find(|line| line.contains("none of the selected packages contains these features")) {
+        let features = line.rsplit(':').next().unwrap_or_default().trim();
+        return Some(format!("The selected package has no feature {}; check build_config.features", features));
     }
 
-    // Common output locations and names for firmware projects
-    let common_patterns = [
-        "firmware", "main", "app", "output", "build/firmware",
-        "bin/firmware", "out/firmware", "dist/firmware"
-    ];
-    
-    // Try to find the binary
-    let binary_path = find_binary_by_patterns(path, &common_patterns)
-        .await
-        .map_err(|_| anyhow!("Could not find built binary after make"))?;
-    
-    Ok(create_build_result(binary_path.to_string_lossy().to_string(), "bin".to_string(), BuildSystem::Makefile, start_time))
+    let lower = output.to_lowercase();
+    let cargo_missing = ["cargo: not found", "cargo: command not found", "\"cargo\": executable file not found"]
+        .iter()
+        .any(|pattern| lower.contains(pattern));
+    cargo_missing.then(|| CARGO_MISSING.to_string())
+}
+
+const CARGO_MISSING: &str =
+    "cargo was not found on the runner; install a Rust toolchain with rustup or build in a `container` that has one";
+
+/// What to do about a failed PlatformIO build whose output shows a build script, usually one of
+/// `extra_scripts`, importing a Python module PlatformIO's Python environment lacks: install it
+/// there with the command of [`platformio::penv_pip_install`]
+pub fn analyze_platformio_error(output: &str, envs: &BTreeMap<String, String>) -> Option<String> {
+    let missing = platformio::missing_python_module(output)?;
+    Some(format!(
+        "{}:{} imports the Python module `{}`, which is not installed in PlatformIO's Python environment; \
+         install it with `{}` or build in a `container` that has it",
+        missing.diagnostic.file,
+        missing.diagnostic.line,
+        missing.module,
+        platformio::penv_pip_install(envs, missing.package()).join(" ")
+    ))
 }
 
-pub async fn build_cmake_original(path: &Path) -> Result<BuildResult> {
+/// `cargo build`, then the binary under `target/[<triple>/]<debug|release>/`. In a workspace,
+/// builds the member chosen by [`select_cargo_package`]. A runner without `cargo` reports the
+/// project as recognized but unbuildable, like Yocto, rather than erroring.
+pub async fn build_cargo_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
     let start_time = Instant::now();
-    let build_dir = path.join("build");
-    tokio::fs::create_dir_all(&build_dir).await?;
+    let selection = select_cargo_package(path, config).await?;
+    let mut command = Command::new("cargo");
+    command.args(cargo_build_args(&selection.config)).current_dir(&selection.dir);
+    let output = match run_command(command, config).await {
+        Ok(output) => output,
+        Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) => {
+            let error = if path.is_dir() {
+                CARGO_MISSING.to_string()
+            } else {
+                format!("Cargo project directory {} does not exist", path.display())
+            };
+            return Ok(unbuilt_cargo_result(error, start_time));
+        }
+        Err(e) => return Err(e),
+    };
 
-    let configure = Command::new("cmake")
-        .arg("..")
-        .current_dir(&build_dir)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await?;
+    if !output.status.success() {
+        let stderr = OutputBuffer::text(&output.stderr);
+        return Err(match analyze_cargo_error(&stderr) {
+            Some(hint) => anyhow!("Cargo build failed: {}\n{}", hint, stderr),
+            None => anyhow!("Cargo build failed: {}", stderr),
+        });
+    }
 
-    if !configure.status.success() {
-        return Err(anyhow!("CMake configure failed: {}", String::from_utf8_lossy(&configure.stderr)));
+    // Members share the workspace's target directory
+    let target_dir = config
+        .command_env
+        .get("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| path.join("target"));
+    let mut out_dir = target_dir;
+    let target = match cargo_target(&selection.dir, config).await {
+        Some(target) => Some(target),
+        None => cargo_target(path, config).await,
+    };
+    if let Some(target) = target {
+        out_dir.push(target);
     }
+    out_dir.push(if config.release { "release" } else { "debug" });
 
-    let build = Command::new("cmake")
-        .arg("--build")
-        .arg(".")
-        .current_dir(&build_dir)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await?;
+    let name = match (&selection.config.cargo_bin, &selection.config.cargo_package) {
+        (Some(bin), _) => Some(bin.clone()),
+        (None, Some(package)) => Some(package.clone()),
+        (None, None) => cargo_package_name(path).await,
+    };
+    let binary = match name.map(|name| out_dir.join(name)) {
+        Some(binary) if binary.is_file() => binary,
+        _ => find_executable_in_dir(&out_dir)
+            .await
+            .map_err(|_| anyhow!("Could not find Cargo build output in {}", out_dir.display()))?,
+    };
 
-    if !build.status.success() {
-        return Err(anyhow!("CMake build failed: {}", String::from_utf8_lossy(&build.stderr)));
-    }
+    // Embedded targets link an ELF without an extension; host builds may produce anything
+    let mut magic = [0u8; 4];
+    let is_elf = match fs::File::open(&binary).await {
+        Ok(mut file) => tokio::io::AsyncReadExt::read_exact(&mut file, &mut magic).await.is_ok() && magic == *b"\x7fELF",
+        Err(_) => false,
+    };
+    let format = if is_elf { "elf" } else { "bin" };
+    Ok(create_build_result(binary.to_string_lossy().to_string(), format.to_string(), BuildSystem::Cargo, start_time))
+}
 
-    // CMake typically puts executables directly in build/ or in subdirectories
-    let common_patterns = [
-        "firmware", "main", "app", 
-        "bin/firmware", "bin/main",
-        "src/firmware", "src/main"
-    ];
-    
-    let binary_path = find_binary_by_patterns(&build_dir, &common_patterns)
-        .await
-        .map_err(|_| anyhow!("Could not find built binary in CMake build directory"))?;
-    
-    Ok(create_build_result(binary_path.to_string_lossy().to_string(), "elf".to_string(), BuildSystem::CMake, start_time))
+/// A package of a Cargo workspace and the binaries it builds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CargoMember {
+    pub dir: PathBuf,
+    pub package: String,
+    /// `[[bin]]` targets, then `src/main.rs` under the package name and `src/bin/*`
+    pub bins: Vec<String>,
 }
 
-pub async fn build_platformio_original(path: &Path) -> Result<BuildResult> {
-    let start_time = Instant::now();
-    let output = Command::new("pio")
-        .arg("run")
-        .current_dir(path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await?;
+/// The packages of the workspace whose root manifest is in `path`, in `members` order with
+/// `dir/*` globs expanded. Empty when the manifest has no `[workspace]`.
+pub async fn cargo_workspace_members(path: &Path) -> Result<Vec<CargoMember>> {
+    let manifest: toml::Table = toml::from_str(&fs::read_to_string(path.join("Cargo.toml")).await?)?;
+    let Some(workspace) = manifest.get("workspace").and_then(|w| w.as_table()) else {
+        return Ok(Vec::new());
+    };
+    let patterns = |key: &str| -> Vec<String> {
+        workspace
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|items| items.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+            .unwrap_or_default()
+    };
+    let excluded: Vec<PathBuf> = patterns("exclude").iter().map(|dir| path.join(dir)).collect();
 
-    if !output.status.success() {
-        return Err(anyhow!("PlatformIO build failed: {}", String::from_utf8_lossy(&output.stderr)));
+    // A root `[package]` is a member too
+    let mut dirs = Vec::new();
+    if manifest.contains_key("package") {
+        dirs.push(path.to_path_buf());
+    }
+    for pattern in patterns("members") {
+        match pattern.strip_suffix("/*") {
+            Some(parent) => {
+                let mut matched = Vec::new();
+                if let Ok(mut entries) = fs::read_dir(path.join(parent)).await {
+                    while let Ok(Some(entry)) = entries.next_entry().await {
+                        if entry.path().join("Cargo.toml").is_file() {
+                            matched.push(entry.path());
+                        }
+                    }
+                }
+                matched.sort();
+                dirs.extend(matched);
+            }
+            None => dirs.push(path.join(pattern)),
+        }
     }
 
-    // PlatformIO creates builds per environment
-    let build_base = path.join(".pio/build");
-    
-    // Find the first environment directory
-    let mut entries = fs::read_dir(&build_base).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        let env_path = entry.path();
-        if env_path.is_dir() {
-            // Look for firmware files in this environment
-            let patterns = ["firmware", "program"];
-            for pattern in &patterns {
-                for ext in &[".hex", ".bin", ".elf"] {
-                    let firmware_path = env_path.join(format!("{}{}", pattern, ext));
-                    if firmware_path.exists() && firmware_path.is_file() {
-                        let format = ext.trim_start_matches('.').to_string();
-                        return Ok(create_build_result(firmware_path.to_string_lossy().to_string(), format, BuildSystem::PlatformIO, start_time));
+    let mut members = Vec::new();
+    for dir in dirs.into_iter().filter(|dir| !excluded.contains(dir)) {
+        let Ok(text) = fs::read_to_string(dir.join("Cargo.toml")).await else {
+            continue;
+        };
+        let manifest: toml::Table = toml::from_str(&text)?;
+        let Some(package) = manifest.get("package").and_then(|p| p.get("name")).and_then(|n| n.as_str()) else {
+            continue;
+        };
+        let explicit = manifest.get("bin").and_then(|b| b.as_array()).cloned().unwrap_or_default();
+        let mut bins: Vec<String> = explicit.iter().filter_map(|t| t.get("name")?.as_str().map(str::to_string)).collect();
+        let main_claimed = explicit.iter().any(|t| t.get("path").and_then(|p| p.as_str()) == Some("src/main.rs"));
+        let autobins = manifest.get("package").and_then(|p| p.get("autobins")).and_then(|a| a.as_bool()) != Some(false);
+        if autobins {
+            if dir.join("src/main.rs").is_file() && !main_claimed {
+                bins.push(package.to_string());
+            }
+            let mut extra = Vec::new();
+            if let Ok(mut entries) = fs::read_dir(dir.join("src/bin")).await {
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    let entry_path = entry.path();
+                    if entry_path.extension().is_some_and(|ext| ext == "rs") || entry_path.join("main.rs").is_file() {
+                        if let Some(stem) = entry_path.file_stem() {
+                            extra.push(stem.to_string_lossy().to_string());
+                        }
                     }
                 }
             }
+            extra.sort();
+            extra.retain(|name| !bins.contains(name));
+            bins.extend(extra);
         }
+        members.push(CargoMember {
+            dir,
+            package: package.to_string(),
+            bins,
+        });
     }
-    
-    Err(anyhow!("Could not find PlatformIO build output"))
+    Ok(members)
 }
 
-pub async fn build_zephyr_original(path: &Path) -> Result<BuildResult> {
-    let start_time = Instant::now();
-    let output = Command::new("west")
-        .arg("build")
-        .current_dir(path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await?;
+/// The member a Cargo build targets: where to run `cargo` from, so that member's
+/// `.cargo/config.toml` applies, and `config` with the package and binary filled in
+struct CargoSelection {
+    dir: PathBuf,
+    config: BuildConfig,
+}
 
-    if !output.status.success() {
-        return Err(anyhow!("Zephyr build failed: {}", String::from_utf8_lossy(&output.stderr)));
+/// Pick the workspace member to build: `cargo_package` if set; otherwise the one member
+/// configured for an embedded target (thumbv*/riscv* in its `.cargo/config.toml`), or the one
+/// member with a binary. With no clear choice the whole workspace is built, as a plain
+/// `cargo build` would. The binary is `cargo_bin`, or the chosen member's only binary.
+async fn select_cargo_package(path: &Path, config: &BuildConfig) -> Result<CargoSelection> {
+    let unselected = CargoSelection {
+        dir: path.to_path_buf(),
+        config: config.clone(),
+    };
+    // A manifest that doesn't parse is left for cargo itself to report
+    let members = cargo_workspace_members(path).await.unwrap_or_default();
+    if members.is_empty() {
+        return Ok(unselected);
     }
 
-    // Zephyr puts the binary in build/zephyr/zephyr.elf
-    let zephyr_elf = path.join("build/zephyr/zephyr.elf");
-    if zephyr_elf.exists() && zephyr_elf.is_file() {
-        return Ok(create_build_result(zephyr_elf.to_string_lossy().to_string(), "elf".to_string(), BuildSystem::ZephyrWest, start_time));
+    let member = match &config.cargo_package {
+        Some(package) => Some(members.iter().find(|m| &m.package == package).ok_or_else(|| {
+            let names: Vec<&str> = members.iter().map(|m| m.package.as_str()).collect();
+            anyhow!(
+                "build_config.cargo_package '{}' is not a member of the workspace; members: {}",
+                package,
+                names.join(", ")
+            )
+        })?),
+        None => {
+            let mut embedded = Vec::new();
+            for member in &members {
+                let target = cargo_target(&member.dir, &BuildConfig::default()).await;
+                if target.is_some_and(|t| t.starts_with("thumb") || t.starts_with("riscv")) {
+                    embedded.push(member);
+                }
+            }
+            let with_bins: Vec<&CargoMember> = members.iter().filter(|m| !m.bins.is_empty()).collect();
+            match (embedded.as_slice(), with_bins.as_slice()) {
+                ([member], _) | ([], [member]) => Some(*member),
+                _ => None,
+            }
+        }
+    };
+    let Some(member) = member else {
+        tracing::warn!("No single Cargo workspace member to build in {}; building the whole workspace", path.display());
+        return Ok(unselected);
+    };
+
+    let mut config = config.clone();
+    config.cargo_package = Some(member.package.clone());
+    if config.cargo_bin.is_none() && member.bins.len() == 1 {
+        config.cargo_bin = member.bins.first().cloned();
     }
-    
-    // Alternative locations
-    let alt_patterns = [
-        "build/zephyr/zephyr.bin",
-        "build/zephyr/zephyr.hex",
-        "build/app.elf"
-    ];
-    
-    for pattern in &alt_patterns {
-        let alt_path = path.join(pattern);
+    Ok(CargoSelection {
+        dir: member.dir.clone(),
+        config,
+    })
+}
+
+/// The target triple a build lands under: `cargo_target`, or `[build] target` from the
+/// project's `.cargo/config.toml` (or legacy `.cargo/config`)
+pub(crate) async fn cargo_target(path: &Path, config: &BuildConfig) -> Option<String> {
+    if let Some(target) = &config.cargo_target {
+        return Some(target.clone());
+    }
+
+    for name in [".cargo/config.toml", ".cargo/config"] {
+        let Ok(contents) = fs::read_to_string(path.join(name)).await else {
+            continue;
+        };
+        let mut in_build = false;
+        for line in contents.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_build = line == "[build]";
+            } else if let Some(value) = line.strip_prefix("target").map(str::trim_start).and_then(|v| v.strip_prefix('=')) {
+                if in_build {
+                    return Some(value.trim().trim_matches('"').to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// `[package] name` from Cargo.toml, which names the default binary
+async fn cargo_package_name(path: &Path) -> Option<String> {
+    let manifest = fs::read_to_string(path.join("Cargo.toml")).await.ok()?;
+    let mut in_package = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+        } else if let Some(value) = line.strip_prefix("name").map(str::trim_start).and_then(|v| v.strip_prefix('=')) {
+            if in_package {
+                return Some(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    None
+}
+
+fn unbuilt_cargo_result(error: String, start_time: Instant) -> BuildResult {
+    BuildResult {
+        success: false,
+        output_path: None,
+        target_format: None,
+        error_output: Some(error),
+        build_system: BuildSystem::Cargo,
+        duration_ms: start_time.elapsed().as_millis() as u64,
+        artifacts: Vec::new(),
+        warning_count: 0,
+        error_count: 0,
+        test_results: None,
+        environments: Vec::new(),
+        targets: Vec::new(),
+        matrix: Vec::new(),
+        cache: None,
+        retries: 0,
+        attempts: Vec::new(),
+        config_warnings: Vec::new(),
+        provenance: Provenance::default(),
+        diagnostics: Vec::new(),
+        static_analysis: Vec::new(),
+        stdout: String::new(),
+        stderr: String::new(),
+        post_build_output: None,
+        platform_install_output: None,
+        exit_code: None,
+        signal: None,
+    }
+}
+
+pub async fn build_makefile_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
+    let start_time = Instant::now();
+    // The Makefile names the file the build should produce. It's read rather than asked of
+    // `make -n`, which still expands `$(shell ...)` and runs `+` recipes
+    let default_goal = makefile_text(path).await.and_then(|makefile| make_default_goal(&makefile));
+
+    // CubeMX Makefiles cross-compile; without the toolchain make fails on the first source file
+    let cubemx = detect_flavor(path, BuildSystem::Makefile).await == Some(BuildFlavor::Stm32Cubemx);
+    if cubemx {
+        check_arm_toolchain(path, config).await?;
+    }
+    
+    // Run the actual build
+    let mut command = Command::new("make");
+    command.args(make_args(config)).current_dir(path);
+    let output = run_command(command, config).await?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Make build failed: {}", OutputBuffer::text(&output.stderr)));
+    }
+
+    // Recipes that swallow their errors (`-cmd`, `|| true`) let make exit 0 with nothing built
+    if let Some(goal) = default_goal.filter(|goal| !path.join(goal).exists()) {
+        let stderr = OutputBuffer::text(&output.stderr);
+        return Err(anyhow!(
+            "make exited successfully but did not produce its default target `{}`\n{}",
+            goal,
+            stderr.trim_end()
+        ));
+    }
+
+    if cubemx {
+        let artifacts = cubemx_artifacts(&path.join(config.build_dir("build"))).await?;
+        let primary = artifacts[0].clone();
+        let mut result = create_build_result(primary.path, primary.format, BuildSystem::Makefile, start_time);
+        result.artifacts = artifacts;
+        return Ok(result);
+    }
+
+    // Try to find the binary, first in a matrix entry's own BUILD_DIR
+    let own_build = match &config.matrix_entry {
+        Some(_) => find_binary_by_patterns(&path.join(config.build_dir("build")), MAKE_OUTPUT_NAMES).await.ok(),
+        None => None,
+    };
+    let binary_path = match own_build {
+        Some(binary) => binary,
+        None => find_binary_by_patterns(path, MAKE_OUTPUT_NAMES)
+            .await
+            .map_err(|_| anyhow!("Could not find built binary after make"))?,
+    };
+    
+    Ok(create_build_result(binary_path.to_string_lossy().to_string(), BuildSystem::Makefile.default_artifact_format().to_string(), BuildSystem::Makefile, start_time))
+}
+
+/// Common output locations and names for firmware projects
+const MAKE_OUTPUT_NAMES: &[&str] = &[
+    "firmware", "main", "app", "output", "build/firmware",
+    "bin/firmware", "out/firmware", "dist/firmware"
+];
+
+/// The makefile GNU make reads in `path`, in its order of preference
+async fn makefile_text(path: &Path) -> Option<String> {
+    for name in ["GNUmakefile", "makefile", "Makefile"] {
+        if let Ok(text) = fs::read_to_string(path.join(name)).await {
+            return Some(text);
+        }
+    }
+    None
+}
+
+/// The file `make` builds by default, read from the makefile: `.DEFAULT_GOAL`, else the first
+/// rule's first target, when it names a file, i.e. has a directory or extension or is a usual
+/// firmware name. Goals such as `all` are often phony without saying so and never exist as
+/// files. `None` where reading can't tell, e.g. an `include` or a variable before the goal.
+pub fn make_default_goal(makefile: &str) -> Option<String> {
+    let mut default_goal = None;
+    let mut first_target = None;
+    let mut undecided = false;
+    let mut phony = Vec::new();
+    let mut in_define = false;
+
+    let joined = makefile.replace("\\\n", " ");
+    for line in joined.lines() {
+        if in_define {
+            in_define = line.trim() != "endef";
+            continue;
+        }
+        // Recipes, comments and blank lines can't define goals
+        if line.starts_with('\t') {
+            continue;
+        }
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let first_word = line.split_whitespace().next().unwrap_or_default();
+        if first_word == "define" {
+            in_define = true;
+            continue;
+        }
+        if ["include", "-include", "sinclude"].contains(&first_word) && first_target.is_none() {
+            undecided = true;
+            continue;
+        }
+        if let Some(value) = line.strip_prefix(".DEFAULT_GOAL") {
+            if let Some(goal) = value.trim_start().trim_start_matches([':', '?']).strip_prefix('=') {
+                default_goal = Some(goal.trim().to_string());
+            }
+            continue;
+        }
+        let Some((targets, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // Assignments, including `VAR := x` and target-specific `target: VAR = x`
+        if targets.contains('=') || rest.starts_with('=') || rest.starts_with(":=") || rest.contains('=') {
+            continue;
+        }
+        if targets.trim() == ".PHONY" {
+            phony.extend(rest.split_whitespace().map(str::to_string));
+            continue;
+        }
+        if first_target.is_none() {
+            let target = targets.split_whitespace().find(|target| !target.starts_with('.') || target.contains('/'));
+            if let Some(target) = target {
+                undecided |= target.contains('$') || target.contains('%');
+                first_target = Some(target.to_string());
+            }
+        }
+    }
+
+    let goal = match default_goal {
+        Some(goal) => goal,
+        None if undecided => return None,
+        None => first_target?,
+    };
+    let names_file = goal.contains('/') || goal.contains('.') || MAKE_OUTPUT_NAMES.contains(&goal.as_str());
+    (!goal.is_empty() && !goal.contains('$') && names_file && !phony.contains(&goal)).then_some(goal)
+}
+
+/// Something a build needs from the runner, checked by a dry run without building
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityCheck {
+    pub name: String,
+    pub ok: bool,
+    /// Why the check failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl CapabilityCheck {
+    fn new(name: &str, outcome: Result<()>) -> Self {
+        Self {
+            name: name.to_string(),
+            ok: outcome.is_ok(),
+            detail: outcome.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Executables the build of `system` runs
+pub fn required_tools(system: BuildSystem, flavor: Option<BuildFlavor>) -> Vec<&'static str> {
+    let mut tools = match system {
+        BuildSystem::Cargo => vec!["cargo"],
+        BuildSystem::Makefile | BuildSystem::STM32CubeIDE | BuildSystem::Buildroot | BuildSystem::MplabX => vec!["make"],
+        BuildSystem::CMake => vec!["cmake", "make"],
+        BuildSystem::PlatformIO => vec!["pio"],
+        BuildSystem::ZephyrWest => vec!["west"],
+        BuildSystem::SCons => vec!["scons"],
+        BuildSystem::Yocto => vec!["bitbake"],
+        BuildSystem::Dockerfile => vec![],
+    };
+    if flavor == Some(BuildFlavor::Stm32Cubemx) {
+        tools.push("arm-none-eabi-gcc");
+    }
+    tools
+}
+
+/// The commands a build of `system` runs in the repository, as a shell line someone building
+/// it outside the runner could type. Steps are joined with `&&`; the Dockerfile build's
+/// throwaway image tag and the artifact copy are left out.
+pub async fn build_command_line(path: &Path, system: BuildSystem, config: &BuildConfig) -> String {
+    let line = |program: &str, args: Vec<String>| {
+        std::iter::once(program.to_string()).chain(args).collect::<Vec<_>>().join(" ")
+    };
+
+    match system {
+        BuildSystem::Cargo => match select_cargo_package(path, config).await {
+            Ok(selection) => match selection.dir.strip_prefix(path) {
+                Ok(member) if !member.as_os_str().is_empty() => {
+                    format!("cd {} && {}", member.display(), line("cargo", cargo_build_args(&selection.config)))
+                }
+                _ => line("cargo", cargo_build_args(&selection.config)),
+            },
+            Err(_) => line("cargo", cargo_build_args(config)),
+        },
+        BuildSystem::Makefile => line("make", make_args(config)),
+        BuildSystem::CMake => format!(
+            "mkdir -p build && cd build && {} && {}",
+            line("cmake", cmake_configure_args(config)),
+            line("cmake", cmake_build_args(config))
+        ),
+        BuildSystem::PlatformIO => {
+            let flags = platformio_build_flags(config)
+                .map(|flags| format!("PLATFORMIO_BUILD_FLAGS='{}' ", flags))
+                .unwrap_or_default();
+            let run = line("pio run", verbosity_args(BuildSystem::PlatformIO, config.verbosity).collect());
+            if config.pio_test {
+                format!("{}{}", flags, line("pio", platformio_test_args(config)))
+            } else if config.pio_envs.is_empty() {
+                format!("{}{}", flags, run)
+            } else {
+                let runs: Vec<String> = config.pio_envs.iter().map(|env| format!("{}{} -e {}", flags, run, env)).collect();
+                runs.join(" && ")
+            }
+        }
+        BuildSystem::ZephyrWest => {
+            let layout = crate::west::layout(path).await;
+            let mut steps = Vec::new();
+            if !layout.initialized {
+                steps.push(line("west", layout.init_args()));
+                steps.push("west update".to_string());
+            }
+            steps.push(line("west", zephyr_build_args(config, path, &layout.app_dir)));
+            steps.join(" && ")
+        }
+        BuildSystem::STM32CubeIDE => {
+            line("make", ["-f".to_string(), "STM32Make.make".to_string()].into_iter().chain(make_args(config)).collect())
+        }
+        BuildSystem::SCons => {
+            let (dir, args) = scons_invocation(path, config).await;
+            match dir.as_os_str().is_empty() {
+                true => line("scons", args),
+                false => format!("cd {} && {}", dir.display(), line("scons", args)),
+            }
+        }
+        BuildSystem::Buildroot => {
+            let defconfig = buildroot_defconfig(path, config)
+                .await
+                .unwrap_or_else(|_| "<board>_defconfig".to_string());
+            let buildroot_dir = path
+                .join("external.desc")
+                .exists()
+                .then(|| std::env::var("NABLA_BUILDROOT_DIR").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("$NABLA_BUILDROOT_DIR")));
+            let steps: Vec<String> = buildroot_make_invocations(path, &defconfig, buildroot_dir.as_deref())
+                .into_iter()
+                .map(|args| line("make", args.into_iter().chain(verbosity_args(BuildSystem::Buildroot, config.verbosity)).collect()))
+                .collect();
+            steps.join(" && ")
+        }
+        BuildSystem::Yocto => "source oe-init-build-env && bitbake <image>".to_string(),
+        BuildSystem::MplabX => {
+            let configurations = mplabx::configurations(path).await;
+            let conf = match mplabx::select_configuration(&configurations, config.mplab_conf.as_deref()) {
+                Ok(conf) => conf.name,
+                Err(_) => config.mplab_conf.clone().unwrap_or_else(|| "default".to_string()),
+            };
+            let build = line("make", mplabx_make_args(&conf, config));
+            match path.join(format!("nbproject/Makefile-{}.mk", conf)).exists() {
+                true => build,
+                false => format!("{} .@{} && {}", mplabx::MAKEFILES_GENERATOR, conf, build),
+            }
+        }
+        BuildSystem::Dockerfile => line("docker build", verbosity_args(BuildSystem::Dockerfile, config.verbosity).chain([".".to_string()]).collect()),
+    }
+}
+
+/// Everything that can be checked about building `path` without building it: the tools on
+/// `PATH` (or the container policy, since the image isn't pulled), the CMake version and
+/// operator opt-ins. Only `cmake --version` is ever run.
+pub async fn check_capabilities(path: &Path, system: BuildSystem, flavor: Option<BuildFlavor>, config: &BuildConfig) -> Vec<CapabilityCheck> {
+    let mut checks = Vec::new();
+
+    match &config.container {
+        Some(container) => {
+            let policy = ContainerPolicy::from_env();
+            let outcome = policy.check(container).and_then(|_| policy.image_for(container, system).map(drop));
+            checks.push(CapabilityCheck::new("container", outcome));
+        }
+        None => {
+            let search_path = config.command_env.get("PATH").map(OsString::from).or_else(|| std::env::var_os("PATH"));
+            let mut tools: Vec<String> = required_tools(system, flavor).into_iter().map(String::from).collect();
+            if system == BuildSystem::Dockerfile {
+                tools.push(ContainerPolicy::from_env().docker);
+            }
+            for tool in &tools {
+                let found = match Path::new(tool).is_absolute() {
+                    true => Path::new(tool).is_file(),
+                    false => on_path(tool, search_path.as_deref()),
+                };
+                let outcome = if found { Ok(()) } else { Err(anyhow!("{} not found on PATH", tool)) };
+                checks.push(CapabilityCheck::new(tool, outcome));
+            }
+            if system == BuildSystem::CMake {
+                checks.push(CapabilityCheck::new("cmake_minimum_required", cmake_preflight(path, config).await));
+            }
+            if system == BuildSystem::MplabX {
+                let configurations = mplabx::configurations(path).await;
+                let outcome = mplabx::select_configuration(&configurations, config.mplab_conf.as_deref())
+                    .and_then(|conf| Ok(mplabx::locate_compiler(&conf, search_path.as_deref()).map(drop)?));
+                checks.push(CapabilityCheck::new("xc_compiler", outcome));
+            }
+        }
+    }
+
+    if system == BuildSystem::Dockerfile {
+        let outcome = match config.artifact_in_image {
+            Some(_) => Ok(()),
+            None => Err(anyhow!("build_config.artifact_in_image is required for Dockerfile builds")),
+        };
+        checks.push(CapabilityCheck::new("artifact_in_image", outcome));
+    }
+    if config.post_build.is_some() {
+        let outcome = match hooks_allowed() {
+            true => Ok(()),
+            false => Err(anyhow!("post_build hooks are disabled on this runner (NABLA_ALLOW_HOOKS is not set)")),
+        };
+        checks.push(CapabilityCheck::new("post_build", outcome));
+    }
+    checks
+}
+
+/// Check that `arm-none-eabi-gcc` runs before building a CubeMX Makefile
+async fn check_arm_toolchain(path: &Path, config: &BuildConfig) -> Result<()> {
+    let mut version = Command::new("arm-none-eabi-gcc");
+    version.arg("--version").current_dir(path);
+    match run_command(version, config).await {
+        Ok(output) if output.status.success() => Ok(()),
+        _ => Err(anyhow!(
+            "STM32CubeMX Makefile needs the ARM cross-compiler, but arm-none-eabi-gcc was not found on the runner. \
+             Install gcc-arm-none-eabi, or build in a `container` image that provides it."
+        )),
+    }
+}
+
+/// The `.bin`, `.hex` and `.elf` images a CubeMX Makefile writes to its `build_dir`,
+/// flashable image first
+async fn cubemx_artifacts(build_dir: &Path) -> Result<Vec<Artifact>> {
+    const FORMATS: [&str; 3] = ["bin", "hex", "elf"];
+    let mut artifacts = Vec::new();
+    let mut entries = fs::read_dir(build_dir)
+        .await
+        .map_err(|_| anyhow!("CubeMX build produced no {} directory", build_dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file = entry.path();
+        let format = file.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        if FORMATS.contains(&format) && file.is_file() {
+            artifacts.push(Artifact::new(file.to_string_lossy().to_string(), format));
+        }
+    }
+
+    if artifacts.is_empty() {
+        return Err(anyhow!("CubeMX build produced no .bin, .hex or .elf in {:?}", build_dir));
+    }
+    artifacts.sort_by_key(|a| (FORMATS.iter().position(|f| *f == a.format), a.path.clone()));
+    Ok(artifacts)
+}
+
+/// Fail before configuring when the installed CMake is older than the project's
+/// `cmake_minimum_required`, instead of letting configure fail with a policy error
+async fn cmake_preflight(path: &Path, config: &BuildConfig) -> Result<()> {
+    let Ok(cmakelists) = tokio::fs::read_to_string(path.join("CMakeLists.txt")).await else {
+        return Ok(());
+    };
+    let Some(required) = cmake::minimum_required_version(&cmakelists) else {
+        return Ok(());
+    };
+
+    // Without a usable cmake, configure reports the problem itself
+    let mut version = Command::new("cmake");
+    version.arg("--version").current_dir(path);
+    let Ok(output) = run_command(version, config).await else {
+        return Ok(());
+    };
+    let Some(installed) = cmake::parse_cmake_version(&OutputBuffer::text(&output.stdout)) else {
+        return Ok(());
+    };
+
+    match cmake::version_issue(&required, &installed) {
+        Some(issue) => Err(anyhow!(issue)),
+        None => Ok(()),
+    }
+}
+
+pub async fn build_cmake_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
+    let start_time = Instant::now();
+    cmake_preflight(path, config).await?;
+    let build_dir = path.join(config.build_dir("build"));
+    tokio::fs::create_dir_all(&build_dir).await?;
+
+    let mut configure = Command::new("cmake");
+    configure.args(cmake_configure_args(config)).current_dir(&build_dir);
+    if network_isolated() {
+        configure.arg("-DFETCHCONTENT_FULLY_DISCONNECTED=ON");
+    }
+    let configure = run_command(configure, config).await?;
+
+    if !configure.status.success() {
+        return Err(anyhow!("CMake configure failed: {}", OutputBuffer::text(&configure.stderr)));
+    }
+
+    let mut build = Command::new("cmake");
+    build.args(cmake_build_args(config)).current_dir(&build_dir);
+    let build = run_command(build, config).await?;
+
+    if !build.status.success() {
+        return Err(anyhow!("CMake build failed: {}", OutputBuffer::text(&build.stderr)));
+    }
+
+    // CMake typically puts executables directly in build/ or in subdirectories
+    let common_patterns = [
+        "firmware", "main", "app", 
+        "bin/firmware", "bin/main",
+        "src/firmware", "src/main"
+    ];
+    
+    let binary_path = find_binary_by_patterns(&build_dir, &common_patterns)
+        .await
+        .map_err(|_| anyhow!("Could not find built binary in CMake build directory"))?;
+    
+    Ok(create_build_result(binary_path.to_string_lossy().to_string(), BuildSystem::CMake.default_artifact_format().to_string(), BuildSystem::CMake, start_time))
+}
+
+pub async fn build_platformio_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
+    let start_time = Instant::now();
+
+    // Catch configuration mistakes before the slow parts: platform installs and `pio run`
+    let ini = fs::read_to_string(path.join("platformio.ini")).await?;
+    let requested_envs: Vec<String> = config.pio_envs.iter().chain(&config.pio_test_env).cloned().collect();
+    let issues = platformio::preflight_check(&ini, &requested_envs);
+    if !issues.is_empty() {
+        let listed = format!("- {}", issues.join("\n- "));
+        if config.strict_config {
+            return Err(anyhow!("platformio.ini pre-flight check failed:\n{}", listed));
+        }
+        tracing::warn!("platformio.ini pre-flight warnings:\n{}", listed);
+    }
+
+    let mut result = run_platformio(path, config, start_time).await.map_err(|e| {
+        if issues.is_empty() {
+            e
+        } else {
+            anyhow!("{}\nplatformio.ini pre-flight warnings:\n- {}", e, issues.join("\n- "))
+        }
+    })?;
+    result.config_warnings = issues;
+    if !config.pio_targets.is_empty() && !config.pio_test && result.success {
+        let envs: Vec<String> = match result.environments.is_empty() {
+            true => platformio::default_environments(&ini),
+            false => result.environments.iter().filter(|environment| environment.success).map(|environment| environment.env.clone()).collect(),
+        };
+        run_platformio_targets(path, &envs, config, &mut result).await;
+    }
+    if let Some(check) = config.pio_check.as_ref().filter(|check| check.enabled && !config.pio_test && result.success) {
+        check_platformio(path, check, config, &mut result).await;
+    }
+    Ok(result)
+}
+
+/// Run each of `config.pio_targets` as `pio run -e <env> -t <target>` in every environment
+/// that built, recording how each fared in `targets`. Files a target writes to the
+/// environment's `.pio/build/<env>` become artifacts. A failing target fails the build, and
+/// the targets after it still run so every outcome is reported.
+async fn run_platformio_targets(path: &Path, envs: &[String], config: &BuildConfig, result: &mut BuildResult) {
+    let mut failures = Vec::new();
+    for env in envs {
+        let build_dir = path.join(config.build_dir(".pio/build")).join(env);
+        for target in &config.pio_targets {
+            let started = Instant::now();
+            let before = build_dir_files(&build_dir);
+            let mut command = platformio_run_command(path, Some(env), config);
+            command.args(["-t", target]);
+            let error = match run_command(command, config).await {
+                Ok(output) if output.status.success() => None,
+                Ok(output) => Some(format!("pio run -t {} exited with {}: {}", target, output.status, OutputBuffer::text(&output.stderr).trim())),
+                Err(e) => Some(e.to_string()),
+            };
+
+            let mut produced = Vec::new();
+            if error.is_none() {
+                for (file, modified) in build_dir_files(&build_dir) {
+                    if before.get(&file) == Some(&modified) {
+                        continue;
+                    }
+                    let format = file.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+                    let mut artifact = Artifact::new(file.to_string_lossy(), format);
+                    artifact.metadata.insert("env".to_string(), env.clone());
+                    artifact.metadata.insert("pio_target".to_string(), target.clone());
+                    result.artifacts.push(artifact);
+                    produced.push(file.strip_prefix(path).unwrap_or(&file).to_string_lossy().to_string());
+                }
+            }
+
+            if let Some(error) = &error {
+                failures.push(format!("[{}] {}", env, error));
+            }
+            result.targets.push(TargetResult {
+                env: env.clone(),
+                target: target.clone(),
+                success: error.is_none(),
+                error,
+                artifacts: produced,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
+
+    if !failures.is_empty() {
+        result.success = false;
+        result.error_output = Some(format!("PlatformIO target failed:\n{}", failures.join("\n")));
+    }
+}
+
+/// Files directly in a build directory with when each was last modified, sorted by path
+fn build_dir_files(dir: &Path) -> BTreeMap<PathBuf, std::time::SystemTime> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((entry.path(), metadata.modified().ok()?))
+        })
+        .collect()
+}
+
+/// Arguments for `pio check`
+pub fn platformio_check_args(check: &PioCheck) -> Vec<String> {
+    let mut args = vec!["check".to_string(), "--json-output".to_string()];
+    for env in &check.environments {
+        args.push("-e".to_string());
+        args.push(env.clone());
+    }
+    args
+}
+
+/// Run `pio check` on a built project and report its defects in `static_analysis`. A check
+/// that fails to run, times out or finds nothing it can analyze is reported in
+/// `config_warnings` and leaves the build's outcome alone. Defects at or above
+/// `severity_threshold` fail the build.
+async fn check_platformio(path: &Path, check: &PioCheck, config: &BuildConfig, result: &mut BuildResult) {
+    let check_config = BuildConfig {
+        timeout_secs: Some(check.timeout_secs.unwrap_or(DEFAULT_PIO_CHECK_TIMEOUT_SECS)),
+        ..config.clone()
+    };
+    let mut command = Command::new("pio");
+    command.args(platformio_check_args(check)).current_dir(path);
+    let report = match run_command(command, &check_config).await {
+        Ok(output) => platformio::parse_check_report(&OutputBuffer::text(&output.stdout), path).map_err(|e| {
+            match OutputBuffer::text(&output.stderr).trim() {
+                "" => e,
+                stderr => anyhow!("{}: {}", e, stderr),
+            }
+        }),
+        Err(e) => Err(e),
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            result.config_warnings.push(format!("pio check did not complete: {}", e));
+            return;
+        }
+    };
+
+    if report.checked.is_empty() {
+        result.config_warnings.push("pio check found no environment it could analyze".to_string());
+    } else if !report.unchecked.is_empty() {
+        result.config_warnings.push(format!("pio check could not analyze: {}", report.unchecked.join(", ")));
+    }
+
+    if let Some(threshold) = check.severity_threshold {
+        let failing: Vec<String> = report
+            .defects
+            .iter()
+            .filter(|defect| CheckSeverity::from_severity(defect.severity) >= threshold)
+            .map(Diagnostic::summary)
+            .collect();
+        if !failing.is_empty() {
+            result.success = false;
+            result.error_output = Some(format!(
+                "pio check found {} defect(s) of {} severity or higher:\n{}",
+                failing.len(),
+                threshold.as_str(),
+                failing.join("\n")
+            ));
+        }
+    }
+
+    result.static_analysis = report.defects;
+    result.static_analysis.truncate(MAX_REPORTED_DIAGNOSTICS);
+}
+
+async fn run_platformio(path: &Path, config: &BuildConfig, start_time: Instant) -> Result<BuildResult> {
+    // Install missing platforms as a separate phase so cold-cache failures aren't reported as build failures
+    let mut cold_cache = true;
+    let mut install_log = Vec::new();
+    // An isolated build already fetched its packages, and installing now would need the network;
+    // a containerized one brings its own toolchains
+    if config.preinstall_platforms && !network_isolated() && crate::container::current().is_none() {
+        install_log = platformio::preinstall_platforms(path, &config.command_env).await?;
+        cold_cache = install_log.iter().any(|line| line.starts_with("Installed platform"));
+    }
+
+    let mut result = build_platformio(path, config, cold_cache, start_time).await?;
+    if !install_log.is_empty() {
+        result.platform_install_output = Some(install_log.join("\n"));
+    }
+    Ok(result)
+}
+
+async fn build_platformio(path: &Path, config: &BuildConfig, cold_cache: bool, start_time: Instant) -> Result<BuildResult> {
+    if config.pio_test {
+        return test_platformio(path, config, start_time).await;
+    }
+
+    if !config.pio_envs.is_empty() {
+        return build_platformio_envs(path, config, cold_cache, start_time).await;
+    }
+
+    let output = run_command(platformio_run_command(path, None, config), config).await?;
+
+    if !output.status.success() {
+        let stderr = OutputBuffer::text(&output.stderr);
+        let stdout = OutputBuffer::text(&output.stdout);
+        return Err(match analyze_platformio_error(&format!("{}{}", stdout, stderr), &config.command_env) {
+            Some(hint) => anyhow!("PlatformIO build failed: {}\n{}", hint, stderr),
+            None => anyhow!("PlatformIO build failed: {}", stderr),
+        });
+    }
+
+    // With several environments `pio run` can exit 0 although some failed; its summary says which
+    let summary = platformio::parse_run_summary(&OutputBuffer::text(&output.stdout));
+    if !summary.is_empty() {
+        return Ok(platformio_summary_result(path, summary, &output, config, start_time));
+    }
+
+    // PlatformIO creates builds per environment
+    let build_base = path.join(config.build_dir(".pio/build"));
+    
+    // Find the first environment directory
+    let mut entries = fs::read_dir(&build_base).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let env_path = entry.path();
+        if env_path.is_dir() {
+            if let Some((firmware_path, format)) = find_platformio_firmware(&env_path) {
+                return Ok(create_build_result(firmware_path.to_string_lossy().to_string(), format, BuildSystem::PlatformIO, start_time));
+            }
+        }
+    }
+    
+    Err(anyhow!("Could not find PlatformIO build output"))
+}
+
+/// The result of one `pio run` over several environments, from its summary table. Each
+/// environment reported successful must also have left firmware in `.pio/build/<env>`.
+fn platformio_summary_result(path: &Path, mut environments: Vec<EnvironmentResult>, output: &Output, config: &BuildConfig, start_time: Instant) -> BuildResult {
+    let mut artifacts = Vec::new();
+    for environment in environments.iter_mut().filter(|environment| environment.success) {
+        match platformio_env_artifact(path, &environment.env, config) {
+            Some((artifact, relative)) => {
+                artifacts.push(artifact);
+                environment.artifacts.push(relative);
+            }
+            None => {
+                environment.success = false;
+                environment.error = Some("Could not find PlatformIO build output".to_string());
+            }
+        }
+    }
+    multi_env_result(artifacts, environments, &OutputBuffer::text(&output.stderr), config, start_time)
+}
+
+/// The firmware a PlatformIO environment built, tagged with its `env`, and its path relative
+/// to the project
+fn platformio_env_artifact(path: &Path, env: &str, config: &BuildConfig) -> Option<(Artifact, String)> {
+    let (firmware, format) = find_platformio_firmware(&path.join(config.build_dir(".pio/build")).join(env))?;
+    let relative = firmware.strip_prefix(path).unwrap_or(&firmware).to_string_lossy().to_string();
+    let mut artifact = Artifact::new(firmware.to_string_lossy().to_string(), format);
+    artifact.metadata.insert("env".to_string(), env.to_string());
+    Some((artifact, relative))
+}
+
+/// A build over several environments. It succeeds if any environment built, or only if all
+/// did with `require_all`; one where only some did is partial, see [`BuildResult::is_partial`].
+fn multi_env_result(
+    artifacts: Vec<Artifact>,
+    environments: Vec<EnvironmentResult>,
+    failure_log: &str,
+    config: &BuildConfig,
+    start_time: Instant,
+) -> BuildResult {
+    let failed: Vec<&str> = environments
+        .iter()
+        .filter(|e| !e.success && !e.skipped)
+        .map(|e| e.env.as_str())
+        .collect();
+    let incomplete = environments.iter().any(|e| !e.success);
+    let success = !artifacts.is_empty() && (!config.require_all || !incomplete);
+    let error_output = (!success).then(|| {
+        let scope = match failed.len() == environments.len() {
+            true => "every environment".to_string(),
+            false => format!("{} of {} environments", failed.len(), environments.len()),
+        };
+        format!("PlatformIO build failed in {} ({}):\n{}", scope, failed.join(", "), failure_log)
+    });
+
+    let primary = artifacts.first().cloned();
+    BuildResult {
+        success,
+        output_path: primary.as_ref().map(|a| a.path.clone()),
+        target_format: primary.map(|a| a.format),
+        error_output,
+        build_system: BuildSystem::PlatformIO,
+        duration_ms: start_time.elapsed().as_millis() as u64,
+        artifacts,
+        warning_count: 0,
+        error_count: 0,
+        test_results: None,
+        environments,
+        targets: Vec::new(),
+        matrix: Vec::new(),
+        cache: None,
+        retries: 0,
+        attempts: Vec::new(),
+        config_warnings: Vec::new(),
+        provenance: Provenance::default(),
+        diagnostics: Vec::new(),
+        static_analysis: Vec::new(),
+        stdout: String::new(),
+        stderr: String::new(),
+        post_build_output: None,
+        platform_install_output: None,
+        exit_code: None,
+        signal: None,
+    }
+}
+
+/// `pio run`, optionally limited to one environment, with the requested language standards
+fn platformio_run_command(path: &Path, env: Option<&str>, config: &BuildConfig) -> Command {
+    let mut command = Command::new("pio");
+    command.arg("run").args(verbosity_flags(BuildSystem::PlatformIO, config.verbosity)).current_dir(path);
+    if let Some(env) = env {
+        command.args(["-e", env]);
+    }
+    if config.matrix_entry.is_some() {
+        command.env("PLATFORMIO_BUILD_DIR", path.join(config.build_dir(".pio/build")));
+    }
+    if let Some(flags) = platformio_build_flags(config) {
+        let existing = std::env::var("PLATFORMIO_BUILD_FLAGS").unwrap_or_default();
+        command.env("PLATFORMIO_BUILD_FLAGS", format!("{} {}", existing, flags).trim());
+    }
+    command
+}
+
+/// Firmware in one `.pio/build/<env>` directory and its format
+fn find_platformio_firmware(env_dir: &Path) -> Option<(PathBuf, String)> {
+    for pattern in ["firmware", "program"] {
+        for ext in ["hex", "bin", "elf"] {
+            let firmware_path = env_dir.join(format!("{}.{}", pattern, ext));
+            if firmware_path.is_file() {
+                return Some((firmware_path, ext.to_string()));
+            }
+        }
+    }
+    None
+}
+
+/// How many environments to build at once: `max_parallel_envs`, then `NABLA_PIO_PARALLEL_ENVS`,
+/// then the CPU count, never more than there are environments.
+pub fn platformio_env_parallelism(config: &BuildConfig, env_count: usize) -> usize {
+    config
+        .max_parallel_envs
+        .or_else(|| std::env::var("NABLA_PIO_PARALLEL_ENVS").ok().and_then(|v| v.parse().ok()))
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+        .clamp(1, env_count.max(1))
+}
+
+/// One environment's build command and what came of it
+#[derive(Debug)]
+pub struct EnvBuildOutput {
+    pub env: String,
+    pub output: Result<Output>,
+    /// The command's stdout and stderr with every line prefixed `[env] `
+    pub log: String,
+    pub duration_ms: u64,
+    /// Not run because an earlier environment failed under `fail_fast`
+    pub skipped: bool,
+}
+
+impl EnvBuildOutput {
+    fn succeeded(&self) -> bool {
+        matches!(&self.output, Ok(output) if output.status.success())
+    }
+
+    fn skipped(env: String) -> Self {
+        Self {
+            output: Err(anyhow!("skipped after an earlier environment failed")),
+            log: String::new(),
+            env,
+            duration_ms: 0,
+            skipped: true,
+        }
+    }
+}
+
+/// Run one command per environment, at most `parallelism` at a time, each on its own task.
+/// With `serialize_first` the first environment runs alone before the rest start, so a cold
+/// PlatformIO package directory is populated by one process instead of racing installs.
+/// With `config.fail_fast`, environments that haven't started when one fails are skipped.
+/// Results come back in `envs` order and every command's output is recorded for diagnostics.
+pub async fn run_env_builds<F>(
+    envs: &[String],
+    parallelism: usize,
+    serialize_first: bool,
+    config: &BuildConfig,
+    make_command: F,
+) -> Vec<EnvBuildOutput>
+where
+    F: Fn(&str) -> Command,
+{
+    let semaphore = Arc::new(Semaphore::new(parallelism.max(1)));
+    let mut results: Vec<Option<EnvBuildOutput>> = envs.iter().map(|_| None).collect();
+    let failed = Arc::new(AtomicBool::new(false));
+    let fail_fast = config.fail_fast;
+
+    let mut remaining = 0;
+    if serialize_first && envs.len() > 1 {
+        let first = run_env_build(envs[0].clone(), make_command(&envs[0]), config.clone()).await;
+        failed.store(fail_fast && !first.succeeded(), Ordering::SeqCst);
+        results[0] = Some(first);
+        remaining = 1;
+    }
+
+    let mut tasks = JoinSet::new();
+    for (index, env) in envs.iter().enumerate().skip(remaining) {
+        let semaphore = semaphore.clone();
+        let command = make_command(env);
+        let config = config.clone();
+        let env = env.clone();
+        // Task-locals don't cross spawn; carry network isolation, the container, the output
+        // cap and the live log over explicitly
+        let (isolated, container) = (network_isolated(), crate::container::current());
+        let limit = output_limit();
+        let live = live_log();
+        let failed = failed.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            if failed.load(Ordering::SeqCst) {
+                return (index, EnvBuildOutput::skipped(env));
+            }
+            let build = limit_output(limit, run_scoped(run_env_build(env, command, config), isolated, container));
+            let result = match live {
+                Some(live) => stream_output_to(live, build).await,
+                None => build.await,
+            };
+            if fail_fast && !result.succeeded() {
+                failed.store(true, Ordering::SeqCst);
+            }
+            (index, result)
+        });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((index, result)) => results[index] = Some(result),
+            Err(e) => tracing::error!("PlatformIO environment build task failed: {}", e),
+        }
+    }
+
+    results
+        .into_iter()
+        .zip(envs)
+        .map(|(result, env)| {
+            result.unwrap_or_else(|| EnvBuildOutput {
+                env: env.clone(),
+                output: Err(anyhow!("build task for environment {} did not complete", env)),
+                log: String::new(),
+                duration_ms: 0,
+                skipped: false,
+            })
+        })
+        .inspect(|result| {
+            if let Ok(output) = &result.output {
+                record_output(output);
+            }
+        })
+        .collect()
+}
+
+async fn run_env_build(env: String, command: Command, config: BuildConfig) -> EnvBuildOutput {
+    let started = Instant::now();
+    tracing::info!("Building PlatformIO environment {}", env);
+    let output = run_command(command, &config).await;
+
+    let log = match &output {
+        Ok(output) => OutputBuffer::text(&output.stdout)
+            .lines()
+            .chain(OutputBuffer::text(&output.stderr).lines())
+            .map(|line| format!("[{}] {}\n", env, line))
+            .collect(),
+        Err(e) => format!("[{}] {}\n", env, e),
+    };
+
+    EnvBuildOutput {
+        env,
+        output,
+        log,
+        duration_ms: started.elapsed().as_millis() as u64,
+        skipped: false,
+    }
+}
+
+/// Build each of `config.pio_envs` separately and in parallel. `environments` records how
+/// each one fared and every firmware image is returned as an artifact tagged with its `env`.
+async fn build_platformio_envs(path: &Path, config: &BuildConfig, cold_cache: bool, start_time: Instant) -> Result<BuildResult> {
+    let parallelism = platformio_env_parallelism(config, config.pio_envs.len());
+    tracing::info!("Building {} PlatformIO environments, {} at a time", config.pio_envs.len(), parallelism);
+
+    let outputs = run_env_builds(&config.pio_envs, parallelism, cold_cache, config, |env| {
+        platformio_run_command(path, Some(env), config)
+    })
+    .await;
+
+    let mut artifacts = Vec::new();
+    let mut environments = Vec::new();
+    let mut failures = Vec::new();
+    for result in outputs {
+        let mut built = Vec::new();
+        let error = match &result.output {
+            Ok(output) if output.status.success() => match platformio_env_artifact(path, &result.env, config) {
+                Some((artifact, relative)) => {
+                    artifacts.push(artifact);
+                    built.push(relative);
+                    None
+                }
+                None => Some("Could not find PlatformIO build output".to_string()),
+            },
+            Ok(output) => Some(match analyze_platformio_error(&result.log, &config.command_env) {
+                Some(hint) => format!("pio run exited with {}: {}", output.status, hint),
+                None => format!("pio run exited with {}", output.status),
+            }),
+            Err(e) => Some(e.to_string()),
+        };
+
+        if error.is_some() {
+            failures.push(result.log.clone());
+        }
+        environments.push(EnvironmentResult {
+            env: result.env,
+            success: error.is_none(),
+            skipped: result.skipped,
+            error,
+            artifacts: built,
+            duration_ms: result.duration_ms,
+        });
+    }
+
+    Ok(multi_env_result(artifacts, environments, &failures.join(""), config, start_time))
+}
+
+/// Where a `mcuboot_key_secret` key is written for the build, relative to the repository,
+/// which container builds mount
+pub const MCUBOOT_SECRET_KEY_FILE: &str = ".nabla-mcuboot-key.pem";
+
+/// Arguments for `west build` of the application in `app_dir`, relative to the repository at
+/// `repo`. The build directory stays `build/` in the repository whichever application is built,
+/// or `build-<name>` for a `matrix` entry, which also passes its CMake defines. The board is
+/// the entry's or `board`, and each of `snippets` is applied with `-S`.
+pub fn zephyr_build_args(config: &BuildConfig, repo: &Path, app_dir: &Path) -> Vec<String> {
+    // west's own verbosity flags come before the command
+    let mut args: Vec<String> = verbosity_args(BuildSystem::ZephyrWest, config.verbosity).collect();
+    args.push("build".to_string());
+    let sysbuild = uses_sysbuild(&repo.join(app_dir), config);
+    if sysbuild {
+        args.push("--sysbuild".to_string());
+    }
+    if config.matrix_entry.is_some() {
+        args.push("-d".to_string());
+        args.push(config.build_dir("build"));
+    }
+    if let Some(board) = config.board() {
+        args.push("-b".to_string());
+        args.push(board.to_string());
+    }
+    for snippet in &config.snippets {
+        args.push("-S".to_string());
+        args.push(snippet.clone());
+    }
+    if !app_dir.as_os_str().is_empty() {
+        args.push(app_dir.display().to_string());
+    }
+    let mut cmake_args: Vec<String> = Vec::new();
+    if let Some(key) = mcuboot_key_file(repo, config).filter(|_| sysbuild) {
+        cmake_args.push(format!("-DSB_CONFIG_BOOT_SIGNATURE_KEY_FILE=\"{}\"", key.display()));
+    }
+    if let Some(entry) = &config.matrix_entry {
+        cmake_args.extend(entry.cmake_defines.iter().map(|(name, value)| format!("-D{}={}", name, value)));
+    }
+    if !cmake_args.is_empty() {
+        args.push("--".to_string());
+        args.extend(cmake_args);
+    }
+    args
+}
+
+/// The MCUboot signing key the build is given, if one is configured
+fn mcuboot_key_file(repo: &Path, config: &BuildConfig) -> Option<PathBuf> {
+    match (&config.mcuboot_key, &config.mcuboot_key_secret) {
+        (Some(file), _) => Some(repo.join(file)),
+        (None, Some(_)) => Some(repo.join(MCUBOOT_SECRET_KEY_FILE)),
+        (None, None) => None,
+    }
+}
+
+/// Writes the `mcuboot_key_secret` key for the build, readable only by the runner, and
+/// removes it when dropped
+struct SecretKeyFile(Option<PathBuf>);
+
+impl SecretKeyFile {
+    fn write(repo: &Path, config: &BuildConfig) -> Result<Self> {
+        let Some(name) = &config.mcuboot_key_secret else {
+            return Ok(Self(None));
+        };
+        let pem = config
+            .secret_env
+            .expose()
+            .get(name)
+            .ok_or_else(|| anyhow!("mcuboot_key_secret '{}' is not in secret_env", name))?;
+        let file = repo.join(MCUBOOT_SECRET_KEY_FILE);
+        // Never write through whatever the repository put there, e.g. a symlink
+        let _ = std::fs::remove_file(&file);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true).mode(0o600);
+        options.open(&file)?.write_all(pem.as_bytes())?;
+        Ok(Self(Some(file)))
+    }
+}
+
+impl Drop for SecretKeyFile {
+    fn drop(&mut self) {
+        if let Some(file) = &self.0 {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+/// Make the repository's manifest the manifest of a new west workspace
+async fn init_west_workspace(path: &Path, layout: &crate::west::WestLayout, config: &BuildConfig) -> Result<()> {
+    let mut command = Command::new("west");
+    command.args(layout.init_args()).current_dir(path);
+    let output = run_command(command, config).await?;
+    if !output.status.success() {
+        return Err(anyhow!("west init failed: {}", OutputBuffer::text(&output.stderr)));
+    }
+    Ok(())
+}
+
+/// Files each sysbuild image may produce, in order of preference for the primary artifact
+const ZEPHYR_IMAGE_OUTPUTS: &[&str] = &[
+    "zephyr.elf", "zephyr.signed.hex", "zephyr.signed.bin", "zephyr.hex", "zephyr.bin",
+];
+
+/// Images sysbuild adds as bootloaders ahead of the application
+const ZEPHYR_BOOTLOADER_IMAGES: &[&str] = &["mcuboot", "b0", "b0n"];
+
+/// Collect the outputs of a sysbuild tree, where each image builds under `build/<image>/zephyr/`,
+/// followed by `merged.hex`, every image combined for flashing. The default image named in
+/// `domains.yaml` comes first. Every artifact records its `image` and its `role`:
+/// `application` for the default image, `bootloader`, `image` for any other, or `merged`.
+pub async fn collect_zephyr_sysbuild_artifacts(build_dir: &Path) -> Result<Vec<Artifact>> {
+    let default_image = fs::read_to_string(build_dir.join("domains.yaml"))
+        .await
+        .ok()
+        .and_then(|domains| {
+            domains
+                .lines()
+                .find_map(|line| line.strip_prefix("default:"))
+                .map(|name| name.trim().to_string())
+        });
+
+    let mut images = Vec::new();
+    let mut entries = fs::read_dir(build_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // build/zephyr/ is sysbuild's own CMake tree, not an image
+        if name != "zephyr" && entry.path().join("zephyr").is_dir() {
+            images.push(name);
+        }
+    }
+    images.sort_by_key(|name| (Some(name) != default_image.as_ref(), name.clone()));
+
+    let mut artifacts = Vec::new();
+    for image in images {
+        let role = if Some(&image) == default_image.as_ref() {
+            "application"
+        } else if ZEPHYR_BOOTLOADER_IMAGES.contains(&image.as_str()) {
+            "bootloader"
+        } else {
+            "image"
+        };
+        for output in ZEPHYR_IMAGE_OUTPUTS {
+            let file = build_dir.join(&image).join("zephyr").join(output);
+            if file.is_file() {
+                let format = file.extension().and_then(|e| e.to_str()).unwrap_or("bin").to_string();
+                let mut artifact = Artifact::new(file.to_string_lossy().to_string(), format);
+                artifact.metadata.insert("image".to_string(), image.clone());
+                artifact.metadata.insert("role".to_string(), role.to_string());
+                artifacts.push(artifact);
+            }
+        }
+    }
+
+    let merged = build_dir.join("merged.hex");
+    if merged.is_file() {
+        let mut artifact = Artifact::new(merged.to_string_lossy().to_string(), "hex".to_string());
+        artifact.metadata.insert("image".to_string(), "merged".to_string());
+        artifact.metadata.insert("role".to_string(), "merged".to_string());
+        artifacts.push(artifact);
+    }
+
+    Ok(artifacts)
+}
+
+/// An nRF Connect SDK application has to be built for a board: `board`, a `matrix` entry's,
+/// `BOARD` in `command_env`, or a `set(BOARD ...)` in the application's CMakeLists.txt
+async fn check_nrf_board(path: &Path, layout: &crate::west::WestLayout, config: &BuildConfig) -> Result<()> {
+    let app_dir = path.join(&layout.app_dir);
+    let cmakelists = fs::read_to_string(app_dir.join("CMakeLists.txt")).await.unwrap_or_default();
+    if config.board().is_some() || config.command_env.contains_key("BOARD") || crate::detection::cmake_set(&cmakelists, "BOARD").is_some() {
+        return Ok(());
+    }
+    let overlays = crate::west::board_overlays(&app_dir).await;
+    let hint = match overlays.is_empty() {
+        true => String::new(),
+        false => format!(" (the application has board files for {})", overlays.join(", ")),
+    };
+    Err(anyhow!("nRF Connect SDK application needs a board to build for: set `board` in the build config{}", hint))
+}
+
+/// Add the nRF Connect SDK's DFU package, `dfu_application.zip` in `dir`, to a build's artifacts
+fn nrf_connect_outputs(dir: &Path, artifacts: &mut Vec<Artifact>) {
+    let dfu = dir.join("dfu_application.zip");
+    if dfu.is_file() {
+        let mut artifact = Artifact::new(dfu.to_string_lossy().to_string(), "zip".to_string());
+        artifact.metadata.insert("role".to_string(), "dfu".to_string());
+        artifacts.push(artifact);
+    }
+}
+
+/// Whether the application in `app_dir` builds with sysbuild: requested, implied by an
+/// MCUboot signing key, or configured by the application
+fn uses_sysbuild(app_dir: &Path, config: &BuildConfig) -> bool {
+    config.sysbuild
+        || config.mcuboot_key.is_some()
+        || config.mcuboot_key_secret.is_some()
+        || app_dir.join("sysbuild.cmake").exists()
+        || app_dir.join("sysbuild.conf").exists()
+}
+
+/// Arguments for `pio test`
+pub fn platformio_test_args(config: &BuildConfig) -> Vec<String> {
+    let mut args = vec!["test".to_string()];
+    if let Some(env) = &config.pio_test_env {
+        args.push("-e".to_string());
+        args.push(env.clone());
+    }
+    args
+}
+
+/// Run the project's PlatformIO unit tests. The result carries test counts instead of an
+/// artifact and is unsuccessful if any test failed or `pio test` itself failed.
+async fn test_platformio(path: &Path, config: &BuildConfig, start_time: Instant) -> Result<BuildResult> {
+    let mut command = Command::new("pio");
+    command.args(platformio_test_args(config)).current_dir(path);
+    let output = run_command(command, config).await?;
+
+    let stdout = OutputBuffer::text(&output.stdout);
+    let summary = platformio::parse_test_summary(&stdout);
+    let success = output.status.success() && summary.is_some_and(|s| s.failed == 0);
+
+    let error_output = if success {
+        None
+    } else {
+        let failures: Vec<&str> = stdout.lines().filter(|line| line.contains("[FAILED]")).collect();
+        Some(match summary {
+            Some(s) => format!("{} of {} test(s) failed:\n{}", s.failed, s.total, failures.join("\n")),
+            None => format!(
+                "pio test did not report results: {}{}",
+                stdout,
+                OutputBuffer::text(&output.stderr)
+            ),
+        })
+    };
+
+    Ok(BuildResult {
+        success,
+        output_path: None,
+        target_format: None,
+        error_output,
+        build_system: BuildSystem::PlatformIO,
+        duration_ms: start_time.elapsed().as_millis() as u64,
+        artifacts: Vec::new(),
+        warning_count: 0,
+        error_count: 0,
+        test_results: summary,
+        environments: Vec::new(),
+        targets: Vec::new(),
+        matrix: Vec::new(),
+        cache: None,
+        retries: 0,
+        attempts: Vec::new(),
+        config_warnings: Vec::new(),
+        provenance: Provenance::default(),
+        diagnostics: Vec::new(),
+        static_analysis: Vec::new(),
+        stdout: String::new(),
+        stderr: String::new(),
+        post_build_output: None,
+        platform_install_output: None,
+        exit_code: None,
+        signal: None,
+    })
+}
+
+pub async fn build_zephyr_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
+    let start_time = Instant::now();
+    let layout = crate::west::layout(path).await;
+    for warning in &layout.warnings {
+        tracing::warn!("{}", warning);
+    }
+    let nrf_connect = detect_flavor(path, BuildSystem::ZephyrWest).await == Some(BuildFlavor::NrfConnect);
+    if nrf_connect {
+        // Fetching the nRF Connect SDK takes minutes; a build without a board would only fail after it
+        check_nrf_board(path, &layout, config).await?;
+    }
+    if !layout.initialized {
+        // An isolated build's dependency fetch already did this
+        init_west_workspace(path, &layout, config).await?;
+        let mut command = Command::new("west");
+        command.arg("update").current_dir(path);
+        let output = run_command(command, config).await?;
+        if !output.status.success() {
+            return Err(anyhow!("west update failed: {}", OutputBuffer::text(&output.stderr)));
+        }
+    }
+
+    let key_file = SecretKeyFile::write(path, config)?;
+    let mut command = Command::new("west");
+    command.args(zephyr_build_args(config, path, &layout.app_dir)).current_dir(path);
+    let output = run_command(command, config).await;
+    drop(key_file);
+    let output = output?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Zephyr build failed: {}", OutputBuffer::text(&output.stderr)));
+    }
+
+    let build_dir = path.join(config.build_dir("build"));
+    let built = |mut result: BuildResult| {
+        result.config_warnings = layout.warnings.clone();
+        Ok(result)
+    };
+    // A build directory sysbuild configured earlier stays a sysbuild one
+    if uses_sysbuild(&path.join(&layout.app_dir), config) || build_dir.join("domains.yaml").exists() {
+        let mut artifacts = collect_zephyr_sysbuild_artifacts(&build_dir).await?;
+        if nrf_connect {
+            nrf_connect_outputs(&build_dir, &mut artifacts);
+        }
+        if let Some(primary) = artifacts.first().cloned() {
+            let mut result = create_build_result(primary.path, primary.format, BuildSystem::ZephyrWest, start_time);
+            result.artifacts = artifacts;
+            return built(result);
+        }
+    }
+
+    // nRF Connect SDK builds without sysbuild merge the application and its child images into
+    // build/zephyr/merged.hex
+    if nrf_connect {
+        let mut artifacts = Vec::new();
+        let merged = build_dir.join("zephyr/merged.hex");
+        if merged.is_file() {
+            let mut artifact = Artifact::new(merged.to_string_lossy().to_string(), "hex".to_string());
+            artifact.metadata.insert("role".to_string(), "merged".to_string());
+            artifacts.push(artifact);
+        }
+        nrf_connect_outputs(&build_dir.join("zephyr"), &mut artifacts);
+        if let Some(primary) = artifacts.first().cloned() {
+            let mut result = create_build_result(primary.path, primary.format, BuildSystem::ZephyrWest, start_time);
+            result.artifacts = artifacts;
+            return built(result);
+        }
+    }
+
+    // Zephyr puts the binary in build/zephyr/zephyr.elf
+    let zephyr_elf = build_dir.join("zephyr/zephyr.elf");
+    if zephyr_elf.exists() && zephyr_elf.is_file() {
+        return built(create_build_result(zephyr_elf.to_string_lossy().to_string(), BuildSystem::ZephyrWest.default_artifact_format().to_string(), BuildSystem::ZephyrWest, start_time));
+    }
+    
+    // Alternative locations
+    let alt_patterns = [
+        "zephyr/zephyr.bin",
+        "zephyr/zephyr.hex",
+        "app.elf"
+    ];
+    
+    for pattern in &alt_patterns {
+        let alt_path = build_dir.join(pattern);
         if alt_path.exists() && alt_path.is_file() {
             let format = alt_path.extension()
                 .and_then(|e| e.to_str())
                 .unwrap_or("bin")
                 .to_string();
-            return Ok(create_build_result(alt_path.to_string_lossy().to_string(), format, BuildSystem::ZephyrWest, start_time));
+            return built(create_build_result(alt_path.to_string_lossy().to_string(), format, BuildSystem::ZephyrWest, start_time));
         }
     }
     
     Err(anyhow!("Could not find Zephyr build output"))
 }
 
-pub async fn build_stm32_original(_path: &Path) -> Result<BuildResult> {
+pub async fn build_stm32_original(_path: &Path, config: &BuildConfig) -> Result<BuildResult> {
     let start_time = Instant::now();
     // STM32CubeIDE typically requires IDE integration
     // However, if using STM32CubeMX with Makefile generation:
     
-    let output = Command::new("make")
+    let mut command = Command::new("make");
+    command
         .arg("-f")
         .arg("STM32Make.make") // Common STM32 makefile name
-        .current_dir(_path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await;
+        .args(make_args(config))
+        .current_dir(_path);
+    let output = run_command(command, config).await;
     
     if let Ok(output) = output {
         if output.status.success() {
@@ -311,7 +2737,7 @@ pub async fn build_stm32_original(_path: &Path) -> Result<BuildResult> {
                 };
                 
                 if let Ok(binary) = find_executable_in_dir(&search_path).await {
-                    return Ok(create_build_result(binary.to_string_lossy().to_string(), "elf".to_string(), BuildSystem::STM32CubeIDE, start_time));
+                    return Ok(create_build_result(binary.to_string_lossy().to_string(), BuildSystem::STM32CubeIDE.default_artifact_format().to_string(), BuildSystem::STM32CubeIDE, start_time));
                 }
             }
         }
@@ -320,17 +2746,52 @@ pub async fn build_stm32_original(_path: &Path) -> Result<BuildResult> {
     Err(anyhow!("STM32CubeIDE build not implemented - requires IDE integration or STM32CubeMX Makefile"))
 }
 
-pub async fn build_scons_original(path: &Path) -> Result<BuildResult> {
+/// Where `scons` runs for the project at `path`, relative to it, and the arguments it gets:
+/// `-f` with `config.sconstruct` run from that file's directory, or the directory of the
+/// SConstruct detection found, and `-j` for `config.scons_jobs`
+pub async fn scons_invocation(path: &Path, config: &BuildConfig) -> (PathBuf, Vec<String>) {
+    let mut args = Vec::new();
+    let dir = match &config.sconstruct {
+        Some(file) => {
+            let file = Path::new(file);
+            if let Some(name) = file.file_name() {
+                args.push("-f".to_string());
+                args.push(name.to_string_lossy().to_string());
+            }
+            file.parent().map(Path::to_path_buf).unwrap_or_default()
+        }
+        None => crate::detection::find_sconstruct(path)
+            .await
+            .and_then(|file| file.parent().map(Path::to_path_buf))
+            .unwrap_or_default(),
+    };
+    if let Some(jobs) = config.scons_jobs {
+        args.push("-j".to_string());
+        args.push(jobs.to_string());
+    }
+    args.extend(verbosity_args(BuildSystem::SCons, config.verbosity));
+    (dir, args)
+}
+
+pub async fn build_scons_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
     let start_time = Instant::now();
-    let output = Command::new("scons")
-        .current_dir(path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await?;
+    if let Some(file) = &config.sconstruct {
+        let invalid = || anyhow!("sconstruct '{}' is not a file in the repository", file);
+        let resolved = fs::canonicalize(path.join(file)).await.map_err(|_| invalid())?;
+        let root = fs::canonicalize(path).await?;
+        if !resolved.starts_with(&root) || !resolved.is_file() {
+            return Err(invalid());
+        }
+    }
+    let (dir, args) = scons_invocation(path, config).await;
+    let dir = path.join(dir);
+
+    let mut command = Command::new("scons");
+    command.current_dir(&dir).args(&args);
+    let output = run_command(command, config).await?;
 
     if !output.status.success() {
-        return Err(anyhow!("SCons build failed: {}", String::from_utf8_lossy(&output.stderr)));
+        return Err(anyhow!("SCons build failed: {}", OutputBuffer::text(&output.stderr)));
     }
 
     // SCons output location varies by SConstruct configuration
@@ -344,9 +2805,465 @@ pub async fn build_scons_original(path: &Path) -> Result<BuildResult> {
         "bin/firmware"
     ];
     
-    let binary_path = find_binary_by_patterns(path, &patterns)
+    // Relative to the SConstruct's directory, which SCons builds from
+    let binary_path = find_binary_by_patterns(&dir, &patterns)
         .await
         .map_err(|_| anyhow!("Could not find SCons build output"))?;
     
-    Ok(create_build_result(binary_path.to_string_lossy().to_string(), "bin".to_string(), BuildSystem::SCons, start_time))
-}
\ No newline at end of file
+    Ok(create_build_result(binary_path.to_string_lossy().to_string(), BuildSystem::SCons.default_artifact_format().to_string(), BuildSystem::SCons, start_time))
+}
+/// Buildroot builds a whole toolchain, kernel and rootfs, so it gets far more time by default
+pub(crate) const DEFAULT_BUILDROOT_TIMEOUT_SECS: u64 = 6 * 3600;
+
+/// Image names Buildroot boards commonly produce, most complete first
+const BUILDROOT_PRIMARY_IMAGES: &[&str] = &[
+    "sdcard.img", "disk.img", "rootfs.ext4", "rootfs.squashfs", "rootfs.ubi", "rootfs.tar",
+    "Image", "zImage", "bzImage", "uImage",
+];
+
+/// Pick the defconfig to load: `build_config.defconfig`, or the only one under `configs/`
+pub async fn buildroot_defconfig(path: &Path, config: &BuildConfig) -> Result<String> {
+    if let Some(defconfig) = &config.defconfig {
+        return Ok(defconfig.clone());
+    }
+
+    let defconfigs = crate::detection::find_defconfigs(path).await;
+    match defconfigs.as_slice() {
+        [single] => Ok(single.clone()),
+        [] => Err(anyhow!("No Buildroot defconfig found under configs/ - set build_config.defconfig")),
+        many => Err(anyhow!(
+            "Multiple Buildroot defconfigs found ({}) - set build_config.defconfig to choose one",
+            many.join(", ")
+        )),
+    }
+}
+
+/// `make` argument lists for the configure and build steps. A BR2_EXTERNAL tree is built
+/// out of the Buildroot source in `buildroot_dir`, with output kept under the project.
+pub fn buildroot_make_invocations(path: &Path, defconfig: &str, buildroot_dir: Option<&Path>) -> Vec<Vec<String>> {
+    let mut common = Vec::new();
+    if let Some(buildroot_dir) = buildroot_dir {
+        common.push("-C".to_string());
+        common.push(buildroot_dir.to_string_lossy().to_string());
+        common.push(format!("BR2_EXTERNAL={}", path.display()));
+        common.push(format!("O={}", path.join("output").display()));
+    }
+
+    let mut configure = common.clone();
+    configure.push(defconfig.to_string());
+
+    vec![configure, common]
+}
+
+pub async fn build_buildroot_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
+    let start_time = Instant::now();
+    let defconfig = buildroot_defconfig(path, config).await?;
+
+    let buildroot_dir = if path.join("external.desc").exists() {
+        let dir = std::env::var("NABLA_BUILDROOT_DIR").map_err(|_| {
+            anyhow!("Buildroot external tree detected but NABLA_BUILDROOT_DIR does not point at a Buildroot source tree")
+        })?;
+        Some(PathBuf::from(dir))
+    } else {
+        None
+    };
+
+    let config = BuildConfig {
+        timeout_secs: config.timeout_secs.or_else(|| {
+            std::env::var("NABLA_BUILDROOT_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok())
+        }).or(Some(DEFAULT_BUILDROOT_TIMEOUT_SECS)),
+        ..config.clone()
+    };
+
+    for args in buildroot_make_invocations(path, &defconfig, buildroot_dir.as_deref()) {
+        let mut command = Command::new("make");
+        command.args(&args).args(verbosity_flags(BuildSystem::Buildroot, config.verbosity)).current_dir(path);
+        let output = run_command(command, &config).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Buildroot make {} failed: {}",
+                args.join(" "),
+                OutputBuffer::text(&output.stderr)
+            ));
+        }
+    }
+
+    let artifacts = buildroot_images(&path.join("output/images")).await?;
+    let primary = artifacts[0].clone();
+
+    let mut result = create_build_result(primary.path, primary.format, BuildSystem::Buildroot, start_time);
+    result.artifacts = artifacts;
+    Ok(result)
+}
+
+/// Every file in `output/images/`, with the most likely flashable image first
+async fn buildroot_images(images_dir: &Path) -> Result<Vec<Artifact>> {
+    let mut files = Vec::new();
+    if let Ok(mut entries) = fs::read_dir(images_dir).await {
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    if files.is_empty() {
+        return Err(anyhow!("Buildroot build produced no files in {:?}", images_dir));
+    }
+
+    let rank = |p: &PathBuf| {
+        let name = p.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        BUILDROOT_PRIMARY_IMAGES.iter().position(|image| *image == name).unwrap_or(usize::MAX)
+    };
+    files.sort_by(|a, b| rank(a).cmp(&rank(b)).then_with(|| a.cmp(b)));
+
+    Ok(files
+        .into_iter()
+        .map(|p| {
+            let format = p.extension().and_then(|e| e.to_str()).unwrap_or(BuildSystem::Buildroot.default_artifact_format()).to_string();
+            Artifact::new(p.to_string_lossy().to_string(), format)
+        })
+        .collect())
+}
+
+/// `make` arguments that build MPLAB X configuration `conf`
+fn mplabx_make_args(conf: &str, config: &BuildConfig) -> Vec<String> {
+    let mut args = vec!["-f".to_string(), "Makefile".to_string(), format!("CONF={}", conf)];
+    args.extend(verbosity_args(BuildSystem::MplabX, config.verbosity));
+    args
+}
+
+/// Build an MPLAB X project's configuration with its generated Makefiles. The XC compiler the
+/// configuration names is checked first, so a runner without it fails with the exact compiler
+/// and version rather than on the first source file. Makefiles missing from the repository are
+/// generated with `prjMakefilesGenerator.sh` when MPLAB X is installed.
+pub async fn build_mplabx_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
+    let start_time = Instant::now();
+    let configurations = mplabx::configurations(path).await;
+    let conf = mplabx::select_configuration(&configurations, config.mplab_conf.as_deref())?;
+
+    let mut config = config.clone();
+    let search_path = config.command_env.get("PATH").map(OsString::from).or_else(|| std::env::var_os("PATH"));
+    if let Some(bin) = mplabx::locate_compiler(&conf, search_path.as_deref())? {
+        let dirs = std::iter::once(bin).chain(search_path.iter().flat_map(std::env::split_paths));
+        let path_var = std::env::join_paths(dirs)?.to_string_lossy().to_string();
+        config.command_env.insert("PATH".to_string(), path_var);
+    }
+
+    if !path.join(format!("nbproject/Makefile-{}.mk", conf.name)).exists() {
+        if !on_path(mplabx::MAKEFILES_GENERATOR, search_path.as_deref()) {
+            return Err(anyhow!(
+                "MPLAB X project has no nbproject/Makefile-{}.mk and {} is not on PATH to generate it - commit the nbproject Makefiles or install MPLAB X",
+                conf.name,
+                mplabx::MAKEFILES_GENERATOR
+            ));
+        }
+        let mut generate = Command::new(mplabx::MAKEFILES_GENERATOR);
+        generate.arg(format!(".@{}", conf.name)).current_dir(path);
+        let output = run_command(generate, &config).await?;
+        if !output.status.success() {
+            return Err(anyhow!("{} failed: {}", mplabx::MAKEFILES_GENERATOR, OutputBuffer::text(&output.stderr)));
+        }
+    }
+
+    let mut command = Command::new("make");
+    command.args(mplabx_make_args(&conf.name, &config)).current_dir(path);
+    let output = run_command(command, &config).await?;
+    if !output.status.success() {
+        return Err(anyhow!("MPLAB X build of configuration '{}' failed: {}", conf.name, OutputBuffer::text(&output.stderr)));
+    }
+
+    let production = path.join("dist").join(&conf.name).join("production");
+    let mut artifacts = Vec::new();
+    if let Ok(mut entries) = fs::read_dir(&production).await {
+        while let Some(entry) = entries.next_entry().await? {
+            let file = entry.path();
+            if file.extension().is_some_and(|ext| ext == "hex") && file.is_file() {
+                artifacts.push(Artifact::new(file.to_string_lossy().to_string(), "hex"));
+            }
+        }
+    }
+    if artifacts.is_empty() {
+        return Err(anyhow!("MPLAB X build produced no .hex in {:?}", production));
+    }
+    artifacts.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let primary = artifacts[0].clone();
+    let mut result = create_build_result(primary.path, primary.format, BuildSystem::MplabX, start_time);
+    result.artifacts = artifacts;
+    Ok(result)
+}
+
+/// Yocto builds need a sourced `oe-init-build-env`, fetched layers and hours of bitbake, which
+/// the runner does not provide. Report the project as recognized, with the steps it needs.
+pub async fn build_yocto_original(_path: &Path, _config: &BuildConfig) -> Result<BuildResult> {
+    Ok(BuildResult {
+        success: false,
+        output_path: None,
+        target_format: None,
+        error_output: Some(
+            "Yocto project recognized but requires a configured bitbake environment: \
+             source oe-init-build-env with the required layers in bblayers.conf, then run \
+             `bitbake <image>` and upload tmp/deploy/images/<machine>/ artifacts"
+                .to_string(),
+        ),
+        build_system: BuildSystem::Yocto,
+        duration_ms: 0,
+        artifacts: Vec::new(),
+        warning_count: 0,
+        error_count: 0,
+        test_results: None,
+        environments: Vec::new(),
+        targets: Vec::new(),
+        matrix: Vec::new(),
+        cache: None,
+        retries: 0,
+        attempts: Vec::new(),
+        config_warnings: Vec::new(),
+        provenance: Provenance::default(),
+        diagnostics: Vec::new(),
+        static_analysis: Vec::new(),
+        stdout: String::new(),
+        stderr: String::new(),
+        post_build_output: None,
+        platform_install_output: None,
+        exit_code: None,
+        signal: None,
+    })
+}
+
+/// `docker build` the repository's Dockerfile, then copy `artifact_in_image` out of the image
+/// through a throwaway container. The image is removed afterwards. Under network isolation the
+/// build's `RUN` steps get `--network none`.
+pub async fn build_dockerfile_original(path: &Path, config: &BuildConfig) -> Result<BuildResult> {
+    if !dockerfile_builds_allowed() {
+        return Err(anyhow!("Dockerfile builds are disabled on this runner (NABLA_ALLOW_DOCKERFILE is not set)"));
+    }
+    let start_time = Instant::now();
+    let artifact_in_image = config
+        .artifact_in_image
+        .as_deref()
+        .ok_or_else(|| anyhow!("Dockerfile build needs build_config.artifact_in_image, e.g. \"/out/firmware.bin\""))?;
+    if crate::container::current().is_some() {
+        return Err(anyhow!("Dockerfile builds run docker themselves and can't be combined with `container`"));
+    }
+
+    let docker = ContainerPolicy::from_env().docker;
+    let tag = format!("nabla-build-{}", uuid::Uuid::new_v4());
+    let mut command = Command::new(&docker);
+    command.args(["build", "-t", &tag]).args(verbosity_flags(BuildSystem::Dockerfile, config.verbosity));
+    if network_isolated() {
+        command.args(["--network", "none"]);
+    }
+    command.arg(".").current_dir(path);
+    let output = run_command(command, config).await?;
+    if !output.status.success() {
+        return Err(anyhow!("Docker build failed: {}", OutputBuffer::text(&output.stderr)));
+    }
+
+    let out_dir = path.join("nabla-docker-out");
+    fs::create_dir_all(&out_dir).await?;
+    let file_name = Path::new(artifact_in_image).file_name().unwrap_or("firmware".as_ref());
+    let artifact = out_dir.join(file_name);
+    let copied = copy_from_image(&docker, &tag, artifact_in_image, &artifact, config).await;
+
+    let mut remove = Command::new(&docker);
+    remove.args(["rmi", "-f", &tag]);
+    if let Err(e) = run_command(remove, config).await {
+        tracing::warn!("Removing build image {} failed: {}", tag, e);
+    }
+    copied?;
+
+    let format = artifact
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or(BuildSystem::Dockerfile.default_artifact_format())
+        .to_string();
+    Ok(create_build_result(artifact.to_string_lossy().to_string(), format, BuildSystem::Dockerfile, start_time))
+}
+
+/// `docker create` a container from `image` without starting it, `docker cp` `source` out of
+/// it to `dest`, and remove the container.
+async fn copy_from_image(docker: &str, image: &str, source: &str, dest: &Path, config: &BuildConfig) -> Result<()> {
+    // Never started, but images without a default command still need one to be created
+    let mut create = Command::new(docker);
+    create.args(["create", image, "nabla-extract"]);
+    let output = run_command(create, config).await?;
+    if !output.status.success() {
+        return Err(anyhow!("Creating a container from the built image failed: {}", OutputBuffer::text(&output.stderr).trim()));
+    }
+    let container = OutputBuffer::text(&output.stdout).trim().to_string();
+
+    let mut copy = Command::new(docker);
+    copy.arg("cp").arg(format!("{}:{}", container, source)).arg(dest);
+    let copied = run_command(copy, config).await;
+
+    let mut remove = Command::new(docker);
+    remove.args(["rm", "-f", &container]);
+    if let Err(e) = run_command(remove, config).await {
+        tracing::warn!("Removing container {} failed: {}", container, e);
+    }
+
+    let output = copied?;
+    if !output.status.success() {
+        return Err(anyhow!("Copying {} out of the built image failed: {}", source, OutputBuffer::text(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+/// What a post-processor knows about the build that produced an artifact
+#[derive(Debug, Clone)]
+pub struct BuildContext {
+    pub repo_dir: PathBuf,
+    pub build_system: BuildSystem,
+    pub config: BuildConfig,
+}
+
+/// A transformation applied to artifacts after discovery (signing, format conversion, ...).
+/// Returns the artifacts that replace the input; return the input itself to keep it.
+#[async_trait]
+pub trait ArtifactProcessor: Send + Sync {
+    fn name(&self) -> &str;
+    async fn process(&self, artifact: &Artifact, ctx: &BuildContext) -> Result<Vec<Artifact>>;
+}
+
+/// Named post-processors that `build_config.post_processors` can select from
+#[derive(Clone)]
+pub struct ProcessorRegistry {
+    processors: HashMap<String, Arc<dyn ArtifactProcessor>>,
+}
+
+impl Default for ProcessorRegistry {
+    fn default() -> Self {
+        let mut registry = Self { processors: HashMap::new() };
+        registry.register(Arc::new(ChecksumProcessor));
+        registry.register(Arc::new(ObjcopyProcessor::new("bin", "binary")));
+        registry.register(Arc::new(ObjcopyProcessor::new("hex", "ihex")));
+        registry.register(Arc::new(GzipProcessor));
+        registry
+    }
+}
+
+impl ProcessorRegistry {
+    pub fn register(&mut self, processor: Arc<dyn ArtifactProcessor>) {
+        self.processors.insert(processor.name().to_string(), processor);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn ArtifactProcessor>> {
+        self.processors.get(name).cloned()
+    }
+
+    /// Run the processors named in `names` in order, each over every artifact the previous one produced.
+    pub async fn run_chain(&self, names: &[String], mut artifacts: Vec<Artifact>, ctx: &BuildContext) -> Result<Vec<Artifact>> {
+        for name in names {
+            let processor = self
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown artifact post-processor '{}'", name))?;
+
+            let mut next = Vec::with_capacity(artifacts.len());
+            for artifact in &artifacts {
+                let produced = processor
+                    .process(artifact, ctx)
+                    .await
+                    .map_err(|e| anyhow!("Post-processor '{}' failed on {}: {}", name, artifact.path, e))?;
+                next.extend(produced);
+            }
+            artifacts = next;
+        }
+
+        Ok(artifacts)
+    }
+}
+
+/// Attaches a `sha256` metadata entry
+pub struct ChecksumProcessor;
+
+#[async_trait]
+impl ArtifactProcessor for ChecksumProcessor {
+    fn name(&self) -> &str {
+        "checksum"
+    }
+
+    async fn process(&self, artifact: &Artifact, _ctx: &BuildContext) -> Result<Vec<Artifact>> {
+        let bytes = fs::read(&artifact.path).await?;
+        let digest = Sha256::digest(&bytes);
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut processed = artifact.clone();
+        processed.metadata.insert("sha256".to_string(), hex);
+        Ok(vec![processed])
+    }
+}
+
+/// Converts ELF artifacts with objcopy, keeping the original alongside the converted file.
+/// Registered as `objcopy:bin` and `objcopy:hex`; `NABLA_OBJCOPY` overrides the binary used.
+pub struct ObjcopyProcessor {
+    name: String,
+    extension: String,
+    bfd_target: String,
+}
+
+impl ObjcopyProcessor {
+    pub fn new(extension: &str, bfd_target: &str) -> Self {
+        Self {
+            name: format!("objcopy:{}", extension),
+            extension: extension.to_string(),
+            bfd_target: bfd_target.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ArtifactProcessor for ObjcopyProcessor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn process(&self, artifact: &Artifact, ctx: &BuildContext) -> Result<Vec<Artifact>> {
+        if artifact.format != "elf" {
+            return Ok(vec![artifact.clone()]);
+        }
+
+        let output_path = Path::new(&artifact.path).with_extension(&self.extension);
+        let objcopy = std::env::var("NABLA_OBJCOPY").unwrap_or_else(|_| "objcopy".to_string());
+
+        let mut command = Command::new(objcopy);
+        command.arg("-O").arg(&self.bfd_target).arg(&artifact.path).arg(&output_path);
+        let output = run_command(command, &ctx.config).await?;
+        if !output.status.success() {
+            return Err(anyhow!("objcopy failed: {}", OutputBuffer::text(&output.stderr)));
+        }
+
+        let mut converted = Artifact::new(output_path.to_string_lossy().to_string(), self.extension.clone());
+        converted.metadata.insert("derived_from".to_string(), artifact.path.clone());
+        Ok(vec![artifact.clone(), converted])
+    }
+}
+
+/// Adds a gzip-compressed copy of each artifact
+pub struct GzipProcessor;
+
+#[async_trait]
+impl ArtifactProcessor for GzipProcessor {
+    fn name(&self) -> &str {
+        "gzip"
+    }
+
+    async fn process(&self, artifact: &Artifact, _ctx: &BuildContext) -> Result<Vec<Artifact>> {
+        let bytes = fs::read(&artifact.path).await?;
+        let compressed = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<u8>> {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&bytes)?;
+            encoder.finish()
+        })
+        .await??;
+
+        let output_path = format!("{}.gz", artifact.path);
+        fs::write(&output_path, compressed).await?;
+
+        let mut gzipped = Artifact::new(output_path, format!("{}.gz", artifact.format));
+        gzipped.metadata.insert("derived_from".to_string(), artifact.path.clone());
+        Ok(vec![artifact.clone(), gzipped])
+    }
+}
@@ -0,0 +1,127 @@
+//! Stable fingerprints of failure messages, so the same underlying error is recognised across
+//! builds even though its temp paths, package versions and addresses differ each time.
+
+use sha2::{Digest, Sha256};
+
+/// Characters trimmed off a word before it is classified, and kept around its placeholder
+const WRAPPING: &[char] = &['\'', '"', '`', '(', ')', '[', ']', '<', '>', '{', '}', ',', ';'];
+
+/// Hex runs at least this long are treated as hashes or ids, e.g. a commit sha or UUID part
+const MIN_HASH_LEN: usize = 8;
+
+/// The fingerprint of `message`: the first 16 hex characters of the sha256 of its
+/// [`skeleton`]
+pub fn fingerprint(message: &str) -> String {
+    let digest = Sha256::digest(skeleton(message).as_bytes());
+    digest.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `message` with what varies between occurrences of the same error replaced by placeholders:
+/// paths by `<path>`, hex addresses by `<addr>`, hashes by `<hash>`, dotted versions by
+/// `<ver>` and other numbers by `<n>`. Digits inside identifiers such as `esp32` or `stm32f4`
+/// are kept. Whitespace is collapsed and blank lines dropped.
+pub fn skeleton(message: &str) -> String {
+    message
+        .lines()
+        .map(|line| line.split_whitespace().map(normalize_word).collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn normalize_word(word: &str) -> String {
+    let core = word.trim_matches(WRAPPING);
+    if core.is_empty() {
+        return word.to_string();
+    }
+    let start = word.find(core).unwrap_or(0);
+    let (prefix, suffix) = (&word[..start], &word[start + core.len()..]);
+
+    let normalized = if is_path(core) { "<path>".to_string() } else { normalize_tokens(core) };
+    format!("{}{}{}", prefix, normalized, suffix)
+}
+
+/// Absolute, home-relative, explicitly relative and Windows paths
+fn is_path(word: &str) -> bool {
+    let windows = word.len() > 2 && word.as_bytes()[0].is_ascii_alphabetic() && word[1..].starts_with(":\\");
+    word.len() > 1 && (word.starts_with('/') || word.starts_with("~/") || word.starts_with("./") || word.starts_with("../") || windows)
+}
+
+/// Replace addresses, hashes, versions and numbers within a word, leaving identifiers alone
+fn normalize_tokens(word: &str) -> String {
+    let chars: Vec<char> = word.chars().collect();
+    // The digits at `at` start a dotted version, which is replaced even after a letter (v6.1.11)
+    let version_at = |at: usize| {
+        let end = (at..chars.len()).find(|&j| !chars[j].is_ascii_digit()).unwrap_or(chars.len());
+        end > at && chars.get(end) == Some(&'.') && chars.get(end + 1).is_some_and(char::is_ascii_digit)
+    };
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let after_letter = i > 0 && (chars[i - 1].is_ascii_alphabetic() || chars[i - 1] == '_');
+
+        // 0x1234abcd
+        if c == '0' && matches!(chars.get(i + 1), Some('x' | 'X')) && chars.get(i + 2).is_some_and(char::is_ascii_hexdigit) {
+            i += 2;
+            while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                i += 1;
+            }
+            out.push_str("<addr>");
+            continue;
+        }
+
+        // A run of hex digits long enough to be a hash, at the start of a token, with any
+        // further `-`-separated hex groups of a UUID
+        if c.is_ascii_hexdigit() && !after_letter && !(i > 0 && chars[i - 1].is_ascii_digit()) {
+            let hex_end = |from: usize| (from..chars.len()).find(|&j| !chars[j].is_ascii_hexdigit()).unwrap_or(chars.len());
+            let boundary = |at: usize| chars.get(at).is_none_or(|next| !next.is_ascii_alphanumeric());
+            let mut end = hex_end(i);
+            if end - i >= MIN_HASH_LEN && boundary(end) && chars[i..end].iter().any(char::is_ascii_digit) {
+                while chars.get(end) == Some(&'-') && hex_end(end + 1) > end + 1 && boundary(hex_end(end + 1)) {
+                    end = hex_end(end + 1);
+                }
+                out.push_str("<hash>");
+                i = end;
+                continue;
+            }
+        }
+
+        // Numbers, and dotted versions such as 6.1.11; digits that continue an identifier stay
+        if c.is_ascii_digit() && (!after_letter || version_at(i)) {
+            let mut end = i;
+            let mut dotted = false;
+            while end < chars.len() {
+                if chars[end].is_ascii_digit() {
+                    end += 1;
+                } else if chars[end] == '.' && chars.get(end + 1).is_some_and(char::is_ascii_digit) {
+                    dotted = true;
+                    end += 1;
+                } else {
+                    break;
+                }
+            }
+            // With its pre-release (`-rc1`) and build metadata (`+20230208`)
+            if dotted && chars.get(end) == Some(&'-') && chars.get(end + 1).is_some_and(char::is_ascii_alphabetic) {
+                end = (end + 1..chars.len()).find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '.')).unwrap_or(chars.len());
+            }
+            if dotted && chars.get(end) == Some(&'+') {
+                end = (end + 1..chars.len()).find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '.')).unwrap_or(chars.len());
+            }
+            out.push_str(if dotted { "<ver>" } else { "<n>" });
+            i = end;
+            continue;
+        }
+
+        // Keep the identifier's own digits, e.g. esp32
+        out.push(c);
+        i += 1;
+        if c.is_ascii_alphanumeric() {
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() && !version_at(i) {
+                out.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
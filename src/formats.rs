@@ -0,0 +1,162 @@
+use crate::core::{parse_address, Artifact, ArtifactFormat, BuildConfig, BuildResult};
+use crate::output::OutputBuffer;
+use crate::process::{on_path, run_command};
+use anyhow::{anyhow, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+/// `e_machine` of an ELF header and the cross objcopy binaries that understand it, tried in order
+const CROSS_OBJCOPY: &[(u16, &[&str])] = &[
+    (40, &["arm-none-eabi-objcopy"]),
+    (183, &["aarch64-none-elf-objcopy", "aarch64-linux-gnu-objcopy"]),
+    (243, &["riscv64-unknown-elf-objcopy", "riscv32-esp-elf-objcopy", "riscv-none-elf-objcopy"]),
+    (94, &["xtensa-esp32-elf-objcopy", "xtensa-esp-elf-objcopy"]),
+    (83, &["avr-objcopy"]),
+    (105, &["msp430-elf-objcopy"]),
+];
+
+/// Put the most preferred of `config.artifact_format` first in `result`, converting an ELF
+/// (or, for `hex`, a raw bin at `bin_load_address`) with objcopy when the build didn't produce
+/// it. Every artifact is marked with an `origin` of `original` or `derived`. A conversion that
+/// fails is skipped with a warning in `config_warnings`; if none of the formats can be
+/// delivered, the build's own artifacts are.
+pub async fn deliver_preferred_format(result: &mut BuildResult, config: &BuildConfig) {
+    if config.artifact_format.is_empty() || !result.success {
+        return;
+    }
+
+    let mut artifacts = std::mem::take(&mut result.artifacts);
+    if artifacts.is_empty() {
+        if let (Some(path), Some(format)) = (&result.output_path, &result.target_format) {
+            artifacts.push(Artifact::new(path.clone(), format.clone()));
+        }
+    }
+    // Host builds leave ELF executables without an extension, which the build reports as bin
+    for artifact in &mut artifacts {
+        if elf_machine(Path::new(&artifact.path)).await.is_some() {
+            artifact.format = ArtifactFormat::Elf.extension().to_string();
+        }
+        artifact.metadata.insert("origin".to_string(), "original".to_string());
+    }
+
+    let mut chosen = None;
+    for &format in &config.artifact_format {
+        if let Some(index) = artifacts.iter().position(|artifact| artifact.format == format.extension()) {
+            chosen = Some(index);
+            break;
+        }
+        match convert(&artifacts, format, config).await {
+            Ok(Some(converted)) => {
+                artifacts.push(converted);
+                chosen = Some(artifacts.len() - 1);
+                break;
+            }
+            Ok(None) => {}
+            Err(e) => result.config_warnings.push(format!("Converting the artifact to {} failed: {}", format.extension(), e)),
+        }
+    }
+
+    match chosen {
+        Some(index) => {
+            let primary = artifacts.remove(index);
+            result.output_path = Some(primary.path.clone());
+            result.target_format = Some(primary.format.clone());
+            artifacts.insert(0, primary);
+        }
+        None => {
+            let wanted: Vec<&str> = config.artifact_format.iter().map(|format| format.extension()).collect();
+            result.config_warnings.push(format!(
+                "None of the requested artifact formats ({}) could be produced; delivering the build's own artifacts",
+                wanted.join(", ")
+            ));
+        }
+    }
+    result.artifacts = artifacts;
+}
+
+/// The artifact `format` converts to from one of `artifacts`, or `None` when none can be
+async fn convert(artifacts: &[Artifact], format: ArtifactFormat, config: &BuildConfig) -> Result<Option<Artifact>> {
+    let find = |source: ArtifactFormat| artifacts.iter().find(|artifact| artifact.format == source.extension());
+    let (source, mut command, load_address) = match (format, find(ArtifactFormat::Elf), find(ArtifactFormat::Bin)) {
+        (ArtifactFormat::Elf, _, _) | (ArtifactFormat::Bin, None, _) | (ArtifactFormat::Hex, None, None) => return Ok(None),
+        (_, Some(elf), _) => {
+            let machine = elf_machine(Path::new(&elf.path)).await;
+            let mut command = Command::new(objcopy(machine, config));
+            command.args(["-O", bfd_target(format)]);
+            (elf, command, None)
+        }
+        (ArtifactFormat::Hex, None, Some(bin)) => {
+            let address = config
+                .bin_load_address
+                .as_deref()
+                .ok_or_else(|| anyhow!("{} is a raw image; set bin_load_address to convert it to hex", bin.path))?;
+            let offset = parse_address(address).ok_or_else(|| anyhow!("invalid bin_load_address '{}'", address))?;
+            let mut command = Command::new(objcopy(None, config));
+            command.args(["-I", "binary", "-O", "ihex", "--change-addresses"]).arg(format!("{:#x}", offset));
+            (bin, command, Some(address))
+        }
+    };
+
+    let output_path = converted_path(Path::new(&source.path), format);
+    command.arg(&source.path).arg(&output_path);
+    let output = run_command(command, config).await?;
+    if !output.status.success() {
+        return Err(anyhow!("objcopy failed: {}", OutputBuffer::text(&output.stderr).trim()));
+    }
+
+    let mut converted = Artifact::new(output_path.to_string_lossy().to_string(), format.extension());
+    converted.metadata.insert("origin".to_string(), "derived".to_string());
+    converted.metadata.insert("derived_from".to_string(), source.path.clone());
+    if let Some(address) = load_address {
+        converted.metadata.insert("load_address".to_string(), address.to_string());
+    }
+    Ok(Some(converted))
+}
+
+fn bfd_target(format: ArtifactFormat) -> &'static str {
+    match format {
+        ArtifactFormat::Hex => "ihex",
+        _ => "binary",
+    }
+}
+
+/// `source` with the extension of `format`, next to it
+fn converted_path(source: &Path, format: ArtifactFormat) -> PathBuf {
+    let path = source.with_extension(format.extension());
+    match path == source {
+        true => source.with_extension(format!("converted.{}", format.extension())),
+        false => path,
+    }
+}
+
+/// The objcopy for an ELF of `machine`: its cross objcopy when one is on the build's `PATH`,
+/// else `NABLA_OBJCOPY`, else the host's `objcopy`
+fn objcopy(machine: Option<u16>, config: &BuildConfig) -> String {
+    let search_path = config.command_env.get("PATH").map(OsString::from).or_else(|| std::env::var_os("PATH"));
+    let cross = CROSS_OBJCOPY
+        .iter()
+        .filter(|(known, _)| Some(*known) == machine)
+        .flat_map(|(_, tools)| tools.iter())
+        .find(|tool| on_path(tool, search_path.as_deref()));
+    match cross {
+        Some(tool) => tool.to_string(),
+        None => std::env::var("NABLA_OBJCOPY").unwrap_or_else(|_| "objcopy".to_string()),
+    }
+}
+
+/// `e_machine` of the ELF file at `path`, or `None` if it isn't one
+async fn elf_machine(path: &Path) -> Option<u16> {
+    let mut header = [0u8; 20];
+    fs::File::open(path).await.ok()?.read_exact(&mut header).await.ok()?;
+    if &header[..4] != b"\x7fELF" {
+        return None;
+    }
+    let machine = [header[18], header[19]];
+    Some(match header[5] {
+        2 => u16::from_be_bytes(machine),
+        _ => u16::from_le_bytes(machine),
+    })
+}
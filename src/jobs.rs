@@ -1,12 +1,93 @@
+use crate::core::SecretEnv;
+use crate::output::LiveLog;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+/// Most audit events kept per job, including the `overflow` event that counts the rest.
+/// The job's final `completed`, `partially_completed`, `failed` or `cancelled` event is always
+/// kept on top.
+pub const MAX_JOB_EVENTS: usize = 200;
+
+/// Distinct failure fingerprints remembered per repository; past it the least recently seen
+/// is forgotten
+pub const MAX_FINGERPRINTS_PER_REPO: usize = 100;
+
+/// Length of [`FailureRecord::example`]
+const FAILURE_EXAMPLE_CHARS: usize = 300;
+
+/// How often a repository's builds have failed with one error, told apart by its
+/// [`crate::fingerprint`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailureRecord {
+    pub fingerprint: String,
+    pub occurrences: u64,
+    /// Unix seconds
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// The start of the error's skeleton, with paths, versions and numbers replaced
+    pub example: String,
+}
+
+/// What an audit trail entry records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobEventKind {
+    /// Who submitted the job, for which repository
+    Submitted,
+    Started,
+    /// A workspace directory or file the runner created or removed, or processes the build left
+    /// running in it and the runner killed
+    Workspace,
+    /// The repository archive is being downloaded (host only) or unpacked from the upload
+    FetchStarted,
+    FetchFinished,
+    /// The build system found and the directory built
+    Detected,
+    /// One run of the build; transient failures are retried as further attempts
+    Attempt,
+    /// An artifact sent to external storage
+    Upload,
+    Completed,
+    /// Built in some environments and not the rest
+    PartiallyCompleted,
+    Failed,
+    /// The request went away before the job finished
+    Cancelled,
+    /// Stands in for events past [`MAX_JOB_EVENTS`]
+    Overflow,
+}
+
+impl JobEventKind {
+    fn is_final(self) -> bool {
+        matches!(
+            self,
+            JobEventKind::Completed | JobEventKind::PartiallyCompleted | JobEventKind::Failed | JobEventKind::Cancelled
+        )
+    }
+}
+
+/// An entry in a job's append-only audit trail
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobEvent {
+    /// Unix time in milliseconds
+    pub timestamp_ms: u64,
+    pub kind: JobEventKind,
+    pub detail: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum JobStatus {
     Queued,
     Running,
     Completed,
+    /// Succeeded in some of its environments; the build's `environments` say which
+    PartiallyCompleted,
     Failed,
 }
 
@@ -26,6 +107,33 @@ pub struct BuildJob {
     pub output: Option<String>,
     pub error: Option<String>,
     pub artifact_path: Option<String>,
+    /// Estimated completion in percent while running, see `progress::ProgressTracker`.
+    #[serde(default)]
+    pub progress: Option<f32>,
+    /// The job only fetched and inspected the repository; nothing was built
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Wall-clock time of the build across every attempt, once it has run
+    #[serde(default)]
+    pub build_ms: Option<u64>,
+    /// Time taken by the build's last attempt, the one whose outcome the job reports
+    #[serde(default)]
+    pub final_attempt_ms: Option<u64>,
+    /// Audit trail, oldest first; see [`BuildJob::record_event`]
+    #[serde(default)]
+    pub events: Vec<JobEvent>,
+    /// Events not kept in `events` because it was full
+    #[serde(default)]
+    pub events_omitted: u64,
+    /// Workspace and build log the job left on disk, removed when it's evicted
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+    /// The client's `job_id` from the request
+    #[serde(default)]
+    pub client_job_id: String,
+    /// Latest lines of the build's output, readable while it runs
+    #[serde(skip)]
+    pub live_log: LiveLog,
 }
 
 impl BuildJob {
@@ -57,9 +165,43 @@ impl BuildJob {
             output: None,
             error: None,
             artifact_path: None,
+            progress: None,
+            dry_run: false,
+            build_ms: None,
+            final_attempt_ms: None,
+            events: Vec::new(),
+            events_omitted: 0,
+            files: Vec::new(),
+            client_job_id: String::new(),
+            live_log: LiveLog::default(),
+        }
+    }
+
+    /// Append to the audit trail. Once it holds [`MAX_JOB_EVENTS`] entries further events are
+    /// only counted, in a trailing `overflow` event, except the job's final one.
+    pub fn record_event(&mut self, kind: JobEventKind, detail: String) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let event = JobEvent { timestamp_ms, kind, detail };
+
+        if kind.is_final() || self.events.len() < MAX_JOB_EVENTS - 1 {
+            self.events.push(event);
+            return;
+        }
+
+        self.events_omitted += 1;
+        let detail = format!("{} further events were not recorded", self.events_omitted);
+        match self.events.last_mut() {
+            Some(last) if last.kind == JobEventKind::Overflow => last.detail = detail,
+            _ => self.events.push(JobEvent { timestamp_ms, kind: JobEventKind::Overflow, detail }),
         }
     }
 
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, JobStatus::Completed | JobStatus::PartiallyCompleted | JobStatus::Failed)
+    }
+
     pub fn start(&mut self) {
         self.status = JobStatus::Running;
         self.started_at = Some(
@@ -80,6 +222,20 @@ impl BuildJob {
         );
         self.output = Some(output);
         self.artifact_path = artifact_path;
+        self.progress = Some(100.0);
+    }
+
+    /// Complete a job that built in only some of its environments
+    pub fn complete_partially(&mut self, output: String, artifact_path: Option<String>) {
+        self.complete(output, artifact_path);
+        self.status = JobStatus::PartiallyCompleted;
+    }
+
+    /// Record a new progress estimate; values lower than the current one are ignored.
+    pub fn set_progress(&mut self, progress: f32) {
+        if self.progress.is_none_or(|current| progress > current) {
+            self.progress = Some(progress.clamp(0.0, 100.0));
+        }
     }
 
     pub fn fail(&mut self, error: String) {
@@ -94,45 +250,184 @@ impl BuildJob {
     }
 }
 
-pub struct SingleJobManager {
-    current_job: Option<BuildJob>,
+/// Jobs retained when `NABLA_MAX_TRACKED_JOBS` isn't set
+pub const DEFAULT_MAX_TRACKED_JOBS: usize = 1000;
+
+/// The runner's jobs by id, running and finished. Past `max_jobs`, the jobs that finished
+/// longest ago are evicted; queued and running jobs are never evicted, so the map can
+/// briefly exceed the cap while they outnumber it.
+pub struct JobManager {
+    jobs: HashMap<Uuid, BuildJob>,
+    /// Finished job ids, least recently finished first
+    finished: VecDeque<Uuid>,
+    max_jobs: usize,
+    /// Keyed by `owner/repo`, then fingerprint; kept when the jobs themselves are evicted
+    failures: HashMap<String, HashMap<String, FailureRecord>>,
 }
 
-impl Default for SingleJobManager {
+impl Default for JobManager {
     fn default() -> Self {
-        Self::new()
+        Self::new(DEFAULT_MAX_TRACKED_JOBS)
     }
 }
 
-impl SingleJobManager {
-    pub fn new() -> Self {
+impl JobManager {
+    pub fn new(max_jobs: usize) -> Self {
         Self {
-            current_job: None,
+            jobs: HashMap::new(),
+            finished: VecDeque::new(),
+            max_jobs: max_jobs.max(1),
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Reads the cap from `NABLA_MAX_TRACKED_JOBS`
+    pub fn from_env() -> Self {
+        let max_jobs = env::var("NABLA_MAX_TRACKED_JOBS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_TRACKED_JOBS);
+        Self::new(max_jobs)
+    }
+
+    /// Track `job`, returning the finished jobs evicted to stay within the cap, oldest first
+    pub fn insert(&mut self, job: BuildJob) -> Vec<BuildJob> {
+        if job.is_finished() {
+            self.finished.push_back(job.id);
+        }
+        self.jobs.insert(job.id, job);
+
+        let mut evicted = Vec::new();
+        while self.jobs.len() > self.max_jobs {
+            let Some(id) = self.finished.pop_front() else {
+                break;
+            };
+            evicted.extend(self.jobs.remove(&id));
         }
+        evicted
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<&BuildJob> {
+        self.jobs.get(&id)
+    }
+
+    /// The latest job submitted with the client's `job_id`, preferring one still running
+    pub fn find_client_job(&self, client_job_id: &str) -> Option<&BuildJob> {
+        self.jobs
+            .values()
+            .filter(|job| job.client_job_id == client_job_id)
+            .max_by_key(|job| (!job.is_finished(), job.created_at))
     }
 
-    pub fn set_job(&mut self, job: BuildJob) {
-        self.current_job = Some(job);
+    /// Every tracked job, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &BuildJob> {
+        self.jobs.values()
     }
 
-    pub fn get_job(&self) -> Option<&BuildJob> {
-        self.current_job.as_ref()
+    pub fn len(&self) -> usize {
+        self.jobs.len()
     }
 
-    pub fn update_job<F>(&mut self, update_fn: F)
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn update<F>(&mut self, id: Uuid, update_fn: F)
     where
         F: FnOnce(&mut BuildJob),
     {
-        if let Some(job) = &mut self.current_job {
-            update_fn(job);
+        let Some(job) = self.jobs.get_mut(&id) else {
+            return;
+        };
+        let was_finished = job.is_finished();
+        update_fn(job);
+        if !was_finished && job.is_finished() {
+            self.finished.push_back(id);
+        }
+    }
+
+    /// Count a failure of `repo`'s build with `error`, returning its updated record
+    pub fn record_failure(&mut self, repo: &str, error: &str) -> FailureRecord {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let fingerprint = crate::fingerprint::fingerprint(error);
+        let records = self.failures.entry(repo.to_string()).or_default();
+
+        if !records.contains_key(&fingerprint) && records.len() >= MAX_FINGERPRINTS_PER_REPO {
+            if let Some(stale) = records.values().min_by_key(|record| record.last_seen).map(|record| record.fingerprint.clone()) {
+                records.remove(&stale);
+            }
         }
+        let record = records.entry(fingerprint.clone()).or_insert_with(|| FailureRecord {
+            fingerprint,
+            occurrences: 0,
+            first_seen: now,
+            last_seen: now,
+            example: crate::fingerprint::skeleton(error).chars().take(FAILURE_EXAMPLE_CHARS).collect(),
+        });
+        record.occurrences += 1;
+        record.last_seen = now;
+        record.clone()
+    }
+
+    /// `repo`'s recorded failures, most frequent first, then most recently seen
+    pub fn failures(&self, repo: &str) -> Vec<FailureRecord> {
+        let mut records: Vec<FailureRecord> = self.failures.get(repo).map(|records| records.values().cloned().collect()).unwrap_or_default();
+        records.sort_by(|a, b| b.occurrences.cmp(&a.occurrences).then(b.last_seen.cmp(&a.last_seen)).then(a.fingerprint.cmp(&b.fingerprint)));
+        records
     }
+
+    /// Whether a tracked job still uses `path`, e.g. a later job with the same client
+    /// `job_id` and therefore the same workspace
+    pub fn uses_path(&self, path: &Path) -> bool {
+        self.jobs.values().any(|job| job.files.iter().any(|file| file == path))
+    }
+}
+
+/// Records audit events on one job held by a [`JobManager`]. Details pass through the
+/// request's secret redaction first. Dropping the recorder before the job's final event, as
+/// happens when the client disconnects mid-build, records a cancellation and fails the job.
+pub struct JobAudit {
+    jobs: Arc<RwLock<JobManager>>,
+    job_id: Uuid,
+    secrets: SecretEnv,
+    finished: AtomicBool,
 }
 
-impl Clone for SingleJobManager {
-    fn clone(&self) -> Self {
+impl JobAudit {
+    pub fn new(jobs: Arc<RwLock<JobManager>>, job_id: Uuid, secrets: SecretEnv) -> Self {
         Self {
-            current_job: self.current_job.clone(),
+            jobs,
+            job_id,
+            secrets,
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&self, kind: JobEventKind, detail: impl Into<String>) {
+        let detail = self.secrets.redact(&detail.into());
+        if kind.is_final() {
+            self.finished.store(true, Ordering::Relaxed);
+        }
+        if let Ok(mut jobs) = self.jobs.write() {
+            jobs.update(self.job_id, |job| job.record_event(kind, detail));
         }
     }
-}
\ No newline at end of file
+
+    /// A copy of the job as it is now, or `None` once it has been evicted
+    pub fn snapshot(&self) -> Option<BuildJob> {
+        self.jobs.read().ok()?.get(self.job_id).cloned()
+    }
+}
+
+impl Drop for JobAudit {
+    fn drop(&mut self) {
+        if self.finished.load(Ordering::Relaxed) {
+            return;
+        }
+        const CANCELLED: &str = "Request ended before the job finished";
+        self.record(JobEventKind::Cancelled, CANCELLED);
+        if let Ok(mut jobs) = self.jobs.write() {
+            jobs.update(self.job_id, |job| job.fail(CANCELLED.to_string()));
+        }
+    }
+}
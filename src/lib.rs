@@ -1,13 +1,51 @@
+pub mod archive;
+pub mod cache;
+pub mod cmake;
+pub mod container;
 pub mod core;
 pub mod detection;
+pub mod diagnostics;
+pub mod events;
 pub mod execution;
+pub mod fingerprint;
+pub mod formats;
 pub mod jobs;
+pub mod limits;
+pub mod logging;
+pub mod mplabx;
+pub mod output;
+pub mod platformio;
+pub mod process;
+pub mod progress;
+pub mod prune;
+pub mod quota;
+pub mod remote;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod selftest;
 pub mod server;
+pub mod source;
+pub mod submodules;
+pub mod west;
+pub mod workspace;
 
 use async_trait::async_trait;
-use anyhow::Result;
-use crate::core::{BuildResult, BuildSystem};
-use std::path::Path;
+use anyhow::{anyhow, Result};
+use crate::cache::CacheProbe;
+use crate::core::{BuildConfig, BuildResult, BuildSystem, Provenance};
+use crate::detection::BuildFlavor;
+use crate::events::BuildPhase;
+use crate::execution::{ArtifactProcessor, BuildContext, BuildStepFailed, CapabilityCheck, ProcessorRegistry};
+use crate::output::{LiveLog, OutputLog};
+use crate::platformio::PlatformInstallFailed;
+use crate::progress::ProgressTracker;
+use crate::submodules::GitSource;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::fs;
+use tracing::Instrument;
 
 #[async_trait]
 pub trait BuildRunner {
@@ -15,7 +53,81 @@ pub trait BuildRunner {
     async fn build(&self, path: &Path, system: BuildSystem) -> Result<BuildResult>;
 }
 
-pub struct FirmwareBuildRunner;
+/// Options for [`FirmwareBuildRunner::run`]
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// The same knobs as a request's `build_config`
+    pub config: BuildConfig,
+    /// Skip detection and build with this system
+    pub build_system: Option<BuildSystem>,
+    /// Append the complete output of the build's commands here. Output kept in the report
+    /// is capped by `NABLA_MAX_OUTPUT_BYTES`; this file is not.
+    pub log_file: Option<PathBuf>,
+    /// The repository the archive came from, for `fetch_submodules`
+    pub git_source: Option<GitSource>,
+    /// Receives each line of the build's command output as it's printed, e.g. to show a
+    /// running job's progress
+    pub live_log: Option<LiveLog>,
+}
+
+impl From<BuildConfig> for RunOptions {
+    fn from(config: BuildConfig) -> Self {
+        Self { config, build_system: None, log_file: None, git_source: None, live_log: None }
+    }
+}
+
+/// Wall-clock time spent in each phase of a run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PhaseTimings {
+    pub detect_ms: u64,
+    pub build_ms: u64,
+}
+
+/// Everything a run found out about a repository, whether or not the build succeeded
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    /// The directory that was built; a lone subdirectory when the given path had no build system
+    pub repo_dir: PathBuf,
+    pub build_system: BuildSystem,
+    /// Set when the build system's files identify a variant, e.g. a CubeMX-generated Makefile
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flavor: Option<BuildFlavor>,
+    /// Artifacts, diagnostics, retries and provenance. A build that errored is reported with
+    /// `success: false` and the error in `error_output`.
+    pub result: BuildResult,
+    /// Human-readable progress lines, as in the server's `build_output`
+    pub log: Vec<String>,
+    pub timings: PhaseTimings,
+}
+
+/// What a dry run found out about a repository without building it
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunReport {
+    pub repo_dir: PathBuf,
+    pub build_system: BuildSystem,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flavor: Option<BuildFlavor>,
+    /// PlatformIO `[env:...]` sections
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+    /// Problems found in the project configuration, e.g. platformio.ini
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub config_warnings: Vec<String>,
+    pub capabilities: Vec<CapabilityCheck>,
+    pub log: Vec<String>,
+}
+
+impl DryRunReport {
+    /// Whether every capability check passed
+    pub fn buildable(&self) -> bool {
+        self.capabilities.iter().all(|check| check.ok)
+    }
+}
+
+#[derive(Clone)]
+pub struct FirmwareBuildRunner {
+    processors: ProcessorRegistry,
+}
 
 impl Default for FirmwareBuildRunner {
     fn default() -> Self {
@@ -25,7 +137,255 @@ impl Default for FirmwareBuildRunner {
 
 impl FirmwareBuildRunner {
     pub fn new() -> Self {
-        Self
+        Self {
+            processors: ProcessorRegistry::default(),
+        }
+    }
+
+    /// Make a custom post-processor selectable by name from `build_config.post_processors`.
+    pub fn register_processor(&mut self, processor: Arc<dyn ArtifactProcessor>) {
+        self.processors.register(processor);
+    }
+
+    pub fn processors(&self) -> &ProcessorRegistry {
+        &self.processors
+    }
+
+    /// Build with per-request options, convert to the preferred `artifact_format`, then run
+    /// the configured post-processor chain.
+    pub async fn build_with_config(&self, path: &Path, system: BuildSystem, config: &BuildConfig) -> Result<BuildResult> {
+        let mut result = execution::execute_build_with_config(path, system, config).await?;
+        formats::deliver_preferred_format(&mut result, config).await;
+
+        if result.success && !config.post_processors.is_empty() {
+            let ctx = BuildContext {
+                repo_dir: path.to_path_buf(),
+                build_system: system,
+                config: config.clone(),
+            };
+            let artifacts = std::mem::take(&mut result.artifacts);
+            result.artifacts = self.processors.run_chain(&config.post_processors, artifacts, &ctx).await?;
+        }
+
+        Ok(result)
+    }
+
+    /// Detect, build and post-process the repository at `path`. Fails only when no build
+    /// system is found; build failures are reported in the returned [`RunReport`].
+    pub async fn run(&self, path: &Path, options: RunOptions) -> Result<RunReport> {
+        self.run_with_progress(path, &options, |_| {}).await
+    }
+
+    /// [`run`](Self::run), calling `on_phase` as the run enters detection and the build
+    pub async fn run_with_progress<F>(&self, path: &Path, options: &RunOptions, on_phase: F) -> Result<RunReport>
+    where
+        F: Fn(BuildPhase) + Send + Sync,
+    {
+        let mut log = Vec::new();
+        let mut timings = PhaseTimings::default();
+
+        on_phase(BuildPhase::Detect);
+        let started = Instant::now();
+        let (repo_dir, build_system, flavor) = self.detect_repository(path, options, &mut log).await?;
+        timings.detect_ms = started.elapsed().as_millis() as u64;
+        if let (Some(live), BuildSystem::PlatformIO) = (&options.live_log, build_system) {
+            live.track_progress(ProgressTracker::for_platformio(platformio_env_count(&repo_dir, &options.config).await));
+        }
+
+        on_phase(BuildPhase::Build);
+        log.push("Starting build...".to_string());
+        let cache_probe = CacheProbe::start(&repo_dir, build_system, &options.config).await;
+        let started = Instant::now();
+        // Boxed: the build future is large, and both arms below would otherwise hold it inline
+        let mut submodule_warnings = Vec::new();
+        let build = Box::pin(async {
+            // Archives leave submodules out; fetch them first, or warn that they're missing
+            submodule_warnings = submodules::prepare_submodules(&repo_dir, options.git_source.as_ref(), &options.config).await?;
+            self.build_with_config(&repo_dir, build_system, &options.config).await
+        }
+        .instrument(logging::build_span(options.config.verbosity)));
+        let build = async {
+            match &options.live_log {
+                Some(live) => process::stream_output_to(live.clone(), build).await,
+                None => build.await,
+            }
+        };
+        let build = match &options.log_file {
+            Some(log_file) => process::log_output_to(OutputLog::open(log_file)?, build).await,
+            None => build.await,
+        };
+        let mut result = match build {
+            Ok(result) => result,
+            Err(e) => failed_build_result(build_system, e, started),
+        };
+        log.extend(submodule_warnings.iter().cloned());
+        result.config_warnings.extend(submodule_warnings);
+        timings.build_ms = started.elapsed().as_millis() as u64;
+        result.cache = Some(cache_probe.finish(&options.config).await);
+
+        if let Some(install_output) = &result.platform_install_output {
+            log.push(format!("PlatformIO platform install:\n{}", install_output));
+        }
+        if !result.success {
+            let error = result.error_output.as_deref().unwrap_or("Unknown build error");
+            log.push(format!("Build failed: {}", error));
+        } else if let Some(artifact) = &result.output_path {
+            log.push(format!("Build completed successfully. Artifact: {}", artifact));
+        }
+        if let Some(hook_output) = &result.post_build_output {
+            log.push(format!("post_build hook output:\n{}", hook_output.trim_end()));
+        }
+
+        Ok(RunReport {
+            repo_dir,
+            build_system,
+            flavor,
+            result,
+            log,
+            timings,
+        })
+    }
+
+    /// Everything [`run`](Self::run) would do before building: detection, project
+    /// configuration checks and whether the runner has what the build needs. Nothing is built.
+    pub async fn dry_run(&self, path: &Path, options: &RunOptions) -> Result<DryRunReport> {
+        let mut log = Vec::new();
+        let (repo_dir, build_system, flavor) = self.detect_repository(path, options, &mut log).await?;
+
+        let (environments, mut config_warnings) = match fs::read_to_string(repo_dir.join("platformio.ini")).await {
+            Ok(ini) if build_system == BuildSystem::PlatformIO => {
+                (platformio::environments(&ini), platformio::preflight_check(&ini, &options.config.pio_envs))
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+        if build_system == BuildSystem::ZephyrWest {
+            config_warnings.extend(west::layout(&repo_dir).await.warnings);
+        }
+        let missing = submodules::missing_submodules(&repo_dir).await;
+        let config_warnings = config_warnings
+            .into_iter()
+            .chain(submodules::missing_warning(&missing, options.git_source.as_ref(), &options.config))
+            .collect();
+        let capabilities = execution::check_capabilities(&repo_dir, build_system, flavor, &options.config).await;
+
+        Ok(DryRunReport {
+            repo_dir,
+            build_system,
+            flavor,
+            environments,
+            config_warnings,
+            capabilities,
+            log,
+        })
+    }
+
+    /// Find the directory to build and its build system
+    async fn detect_repository(
+        &self,
+        path: &Path,
+        options: &RunOptions,
+        log: &mut Vec<String>,
+    ) -> Result<(PathBuf, BuildSystem, Option<BuildFlavor>)> {
+        // Descend into a nested project when the archive root has nothing to build
+        let repo_dir = match &options.config.project_dir {
+            Some(project_dir) => {
+                let wrapper = detection::single_child_root(path).await;
+                let dir = project_directory(path, wrapper.as_deref(), project_dir).await?;
+                log.push(format!("Using project_dir: {}", dir.display()));
+                dir
+            }
+            None => match detection::nested_project_root(path).await {
+                Some(inner) => {
+                    tracing::info!("No build system at archive root, descending into {}", inner.display());
+                    log.push(format!("No build system at archive root, using subdirectory: {}", inner.display()));
+                    inner
+                }
+                None => path.to_path_buf(),
+            },
+        };
+        let build_system = match options.build_system {
+            Some(system) => system,
+            None => match self.detect(&repo_dir).await {
+                Some(system) => system,
+                None => {
+                    let system = detection::default_build_system()?.ok_or_else(|| anyhow!(detection::UNDETECTED_MESSAGE))?;
+                    log.push(format!("No build system detected, using NABLA_DEFAULT_BUILD_SYSTEM: {:?}", system));
+                    system
+                }
+            },
+        };
+        let flavor = detection::detect_flavor(&repo_dir, build_system).await;
+        match flavor {
+            Some(flavor) => log.push(format!("Detected build system: {:?} ({:?})", build_system, flavor)),
+            None => log.push(format!("Detected build system: {:?}", build_system)),
+        }
+        Ok((repo_dir, build_system, flavor))
+    }
+}
+
+/// `project_dir` under the archive root, or under the lone folder an archive wraps the
+/// repository in. Fails unless it's a directory that, with symlinks resolved, stays inside.
+async fn project_directory(root: &Path, wrapper: Option<&Path>, project_dir: &str) -> Result<PathBuf> {
+    let base = match wrapper {
+        Some(wrapper) if !root.join(project_dir).exists() => wrapper,
+        _ => root,
+    };
+    let invalid = || anyhow!("project_dir '{}' is not a directory in the repository", project_dir);
+    let resolved = fs::canonicalize(base.join(project_dir)).await.map_err(|_| invalid())?;
+    if !resolved.starts_with(fs::canonicalize(base).await?) || !resolved.is_dir() {
+        return Err(invalid());
+    }
+    Ok(base.join(project_dir))
+}
+
+/// How many environments a PlatformIO build of `repo_dir` runs: the requested `pio_envs`, else
+/// the ones a plain `pio run` builds
+async fn platformio_env_count(repo_dir: &Path, config: &BuildConfig) -> usize {
+    if !config.pio_envs.is_empty() {
+        return config.pio_envs.len();
+    }
+    match fs::read_to_string(repo_dir.join("platformio.ini")).await {
+        Ok(ini) => platformio::default_environments(&ini).len(),
+        Err(_) => 1,
+    }
+}
+
+/// Report a build that errored out as a failed result, keeping any compiler diagnostics and
+/// how the failing command ended
+fn failed_build_result(build_system: BuildSystem, error: anyhow::Error, started: Instant) -> BuildResult {
+    let step = error.downcast_ref::<BuildStepFailed>();
+    let diagnostics = step.map(|step| step.diagnostics.clone()).unwrap_or_default();
+    let exit = step.map(|step| step.exit).unwrap_or_default();
+    let (stdout, stderr) = step.map(|step| (step.stdout.clone(), step.stderr.clone())).unwrap_or_default();
+    let install = error.chain().find_map(|cause| cause.downcast_ref::<PlatformInstallFailed>());
+
+    BuildResult {
+        success: false,
+        output_path: None,
+        target_format: None,
+        error_output: Some(error.to_string()),
+        build_system,
+        duration_ms: started.elapsed().as_millis() as u64,
+        artifacts: Vec::new(),
+        warning_count: step.map_or(0, |step| step.warning_count),
+        error_count: step.map_or(0, |step| step.error_count),
+        test_results: None,
+        environments: Vec::new(),
+        targets: Vec::new(),
+        matrix: Vec::new(),
+        cache: None,
+        retries: 0,
+        attempts: Vec::new(),
+        config_warnings: Vec::new(),
+        provenance: Provenance::default(),
+        diagnostics,
+        static_analysis: Vec::new(),
+        stdout,
+        stderr,
+        post_build_output: None,
+        platform_install_output: install.filter(|install| !install.log.is_empty()).map(|install| install.log.join("\n")),
+        exit_code: exit.exit_code,
+        signal: exit.signal,
     }
 }
 
@@ -36,6 +396,6 @@ impl BuildRunner for FirmwareBuildRunner {
     }
 
     async fn build(&self, path: &Path, system: BuildSystem) -> Result<BuildResult> {
-        execution::execute_build(path, system).await
+        self.build_with_config(path, system, &BuildConfig::default()).await
     }
 }
\ No newline at end of file
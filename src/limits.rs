@@ -0,0 +1,203 @@
+use crate::core::{BuildConfig, BuildSystem};
+use crate::execution::DEFAULT_BUILDROOT_TIMEOUT_SECS;
+use crate::process::DEFAULT_BUILD_TIMEOUT_SECS;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Limits an operator sets for builds; any left out fall back to the next level
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SystemLimits {
+    /// Wall-clock limit for each build command
+    pub timeout_secs: Option<u64>,
+    /// Output kept from each command's stdout and stderr
+    pub max_log_bytes: Option<usize>,
+    /// Largest artifact a build may return
+    pub max_artifact_bytes: Option<u64>,
+    /// Jobs the build tool runs at once (`make -j`, `cmake --build --parallel`, cargo jobs,
+    /// PlatformIO environments)
+    pub parallelism: Option<usize>,
+}
+
+impl SystemLimits {
+    /// Fill every limit left out here from `fallback`
+    fn or(self, fallback: SystemLimits) -> SystemLimits {
+        SystemLimits {
+            timeout_secs: self.timeout_secs.or(fallback.timeout_secs),
+            max_log_bytes: self.max_log_bytes.or(fallback.max_log_bytes),
+            max_artifact_bytes: self.max_artifact_bytes.or(fallback.max_artifact_bytes),
+            parallelism: self.parallelism.or(fallback.parallelism),
+        }
+    }
+
+    fn validate(&self, section: &str) -> Result<()> {
+        let zero = [
+            ("timeout_secs", self.timeout_secs == Some(0)),
+            ("max_log_bytes", self.max_log_bytes == Some(0)),
+            ("max_artifact_bytes", self.max_artifact_bytes == Some(0)),
+            ("parallelism", self.parallelism == Some(0)),
+        ];
+        match zero.iter().find(|(_, zero)| *zero) {
+            Some((key, _)) => Err(anyhow!("{}.{} must be greater than zero", section, key)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The limits in effect for one build, recorded in its provenance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildLimits {
+    pub timeout_secs: u64,
+    pub max_log_bytes: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_artifact_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitsFile {
+    #[serde(default)]
+    default: SystemLimits,
+    /// Keyed by build system as the API names it, e.g. `ZephyrWest`
+    #[serde(default)]
+    build_system: BTreeMap<String, SystemLimits>,
+    #[serde(default)]
+    max: SystemLimits,
+}
+
+/// The operator's limits file: defaults for every build, per-build-system defaults over those,
+/// and hard maxima no request or default can exceed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LimitsConfig {
+    pub default: SystemLimits,
+    pub build_systems: HashMap<BuildSystem, SystemLimits>,
+    pub max: SystemLimits,
+}
+
+impl LimitsConfig {
+    /// Parse a limits file, TOML unless its name ends in `.json`:
+    ///
+    /// ```toml
+    /// [default]
+    /// timeout_secs = 1800
+    ///
+    /// [build_system.Makefile]
+    /// timeout_secs = 300
+    ///
+    /// [build_system.ZephyrWest]
+    /// timeout_secs = 3600
+    /// parallelism = 8
+    ///
+    /// [max]
+    /// timeout_secs = 7200
+    /// ```
+    pub fn parse(name: &str, contents: &str) -> Result<Self> {
+        let file: LimitsFile = if name.ends_with(".json") {
+            serde_json::from_str(contents)?
+        } else {
+            toml::from_str(contents)?
+        };
+
+        file.default.validate("default")?;
+        file.max.validate("max")?;
+        let mut build_systems = HashMap::new();
+        for (key, limits) in file.build_system {
+            let system: BuildSystem = serde_json::from_value(serde_json::Value::String(key.clone()))
+                .map_err(|_| anyhow!("build_system.{} is not a build system", key))?;
+            limits.validate(&format!("build_system.{}", key))?;
+            build_systems.insert(system, limits);
+        }
+
+        Ok(Self {
+            default: file.default,
+            build_systems,
+            max: file.max,
+        })
+    }
+
+    /// Read the file `NABLA_LIMITS_FILE` names; no limits when it isn't set
+    pub fn from_env() -> Result<Self> {
+        let Ok(path) = env::var("NABLA_LIMITS_FILE") else {
+            return Ok(Self::default());
+        };
+        let contents = std::fs::read_to_string(&path).with_context(|| format!("Failed to read limits file {}", path))?;
+        let name = Path::new(&path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        Self::parse(&name, &contents).with_context(|| format!("Invalid limits file {}", path))
+    }
+
+    /// The limits for a `system` build: the request's `timeout_secs` and `max_parallel_envs`
+    /// first, then the system's defaults, the file's defaults and the runner's environment,
+    /// each capped by the file's maxima. A `verbose` or `debug` request keeps a multiple of the
+    /// output, see [`Verbosity::log_scale`](crate::core::Verbosity::log_scale).
+    pub fn resolve(&self, system: BuildSystem, config: &BuildConfig) -> BuildLimits {
+        let request = SystemLimits {
+            timeout_secs: config.timeout_secs,
+            parallelism: config.max_parallel_envs,
+            ..SystemLimits::default()
+        };
+        let limits = request
+            .or(self.build_systems.get(&system).copied().unwrap_or_default())
+            .or(self.default)
+            .or(runner_defaults(system));
+
+        BuildLimits {
+            timeout_secs: cap(limits.timeout_secs, self.max.timeout_secs).unwrap_or(DEFAULT_BUILD_TIMEOUT_SECS),
+            max_log_bytes: cap(limits.max_log_bytes.map(|bytes| bytes.saturating_mul(config.verbosity.log_scale())), self.max.max_log_bytes)
+                .unwrap_or(crate::output::DEFAULT_OUTPUT_LIMIT),
+            max_artifact_bytes: cap(limits.max_artifact_bytes, self.max.max_artifact_bytes),
+            parallelism: cap(limits.parallelism, self.max.parallelism),
+        }
+    }
+}
+
+/// `value` held to `max`; a maximum alone is the value
+fn cap<T: Ord>(value: Option<T>, max: Option<T>) -> Option<T> {
+    match (value, max) {
+        (Some(value), Some(max)) => Some(value.min(max)),
+        (value, max) => value.or(max),
+    }
+}
+
+/// What applies without a limits file: `NABLA_BUILD_TIMEOUT_SECS` (`NABLA_BUILDROOT_TIMEOUT_SECS`
+/// for Buildroot) and `NABLA_MAX_OUTPUT_BYTES`
+fn runner_defaults(system: BuildSystem) -> SystemLimits {
+    let env_value = |name: &str| env::var(name).ok().and_then(|v| v.parse().ok());
+    let timeout_secs = match system {
+        BuildSystem::Buildroot => env_value("NABLA_BUILDROOT_TIMEOUT_SECS").unwrap_or(DEFAULT_BUILDROOT_TIMEOUT_SECS),
+        _ => env_value("NABLA_BUILD_TIMEOUT_SECS").unwrap_or(DEFAULT_BUILD_TIMEOUT_SECS),
+    };
+    SystemLimits {
+        timeout_secs: Some(timeout_secs),
+        max_log_bytes: Some(crate::output::output_limit()),
+        ..SystemLimits::default()
+    }
+}
+
+static LIMITS: OnceLock<LimitsConfig> = OnceLock::new();
+
+/// Load `NABLA_LIMITS_FILE` for the rest of the process, failing on an invalid file. The
+/// server calls this at startup; library users that don't get the file loaded on first use,
+/// with an invalid one logged and ignored.
+pub fn init_from_env() -> Result<&'static LimitsConfig> {
+    if let Some(limits) = LIMITS.get() {
+        return Ok(limits);
+    }
+    let limits = LimitsConfig::from_env()?;
+    Ok(LIMITS.get_or_init(|| limits))
+}
+
+/// The runner's limits configuration
+pub fn current() -> &'static LimitsConfig {
+    LIMITS.get_or_init(|| {
+        LimitsConfig::from_env().unwrap_or_else(|e| {
+            tracing::error!("{:#}; building without configured limits", e);
+            LimitsConfig::default()
+        })
+    })
+}
@@ -0,0 +1,55 @@
+//! The runner's own logs. Everything at INFO and above is logged; a build whose `verbosity` is
+//! `verbose` or `debug` runs in a span that lets its DEBUG or TRACE lines through as well, so one
+//! job can be debugged without raising the level for every other build on the runner.
+
+use crate::core::Verbosity;
+use tracing::{Level, Metadata, Span, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Name of the span a `verbose` build runs in
+pub const VERBOSE_BUILD_SPAN: &str = "verbose_build";
+
+/// Name of the span a `debug` build runs in
+pub const DEBUG_BUILD_SPAN: &str = "debug_build";
+
+/// The span to run a build at `verbosity` in
+pub fn build_span(verbosity: Verbosity) -> Span {
+    match verbosity {
+        Verbosity::Quiet | Verbosity::Normal => tracing::info_span!("build"),
+        Verbosity::Verbose => tracing::info_span!(VERBOSE_BUILD_SPAN),
+        Verbosity::Debug => tracing::info_span!(DEBUG_BUILD_SPAN),
+    }
+}
+
+/// The most detailed level logged inside a span named `name`, when the span raises it
+fn span_level(name: &str) -> Option<Level> {
+    match name {
+        VERBOSE_BUILD_SPAN => Some(Level::DEBUG),
+        DEBUG_BUILD_SPAN => Some(Level::TRACE),
+        _ => None,
+    }
+}
+
+/// Filter for the runner's log output: INFO, or the level of the innermost enclosing
+/// [`build_span`] that raises it
+pub fn filter<S>() -> impl Filter<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_subscriber::filter::dynamic_filter_fn(|metadata: &Metadata<'_>, cx: &Context<'_, S>| {
+        let level = cx
+            .lookup_current()
+            .and_then(|span| span.scope().find_map(|span| span_level(span.name())))
+            .unwrap_or(Level::INFO);
+        metadata.level() <= &level
+    })
+}
+
+/// Log to stdout through [`filter`]
+pub fn init() {
+    use tracing_subscriber::prelude::*;
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(filter()))
+        .init();
+}
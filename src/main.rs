@@ -4,8 +4,8 @@ use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
+    // Initialize logging, with DEBUG lines for builds that ask for them
+    nabla_runner::logging::init();
 
     info!("Starting Nabla Enterprise Runner Server");
 
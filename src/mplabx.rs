@@ -0,0 +1,151 @@
+//! MPLAB X projects: the configurations in `nbproject/configurations.xml` and the Microchip
+//! XC compiler each one is built with.
+
+use crate::process::on_path;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Where MPLAB X keeps a project's configurations
+pub const CONFIGURATIONS_XML: &str = "nbproject/configurations.xml";
+
+/// Script MPLAB X ships to regenerate a project's `nbproject/Makefile-*.mk`
+pub const MAKEFILES_GENERATOR: &str = "prjMakefilesGenerator.sh";
+
+/// Where the XC installers put each compiler version, `<root>/xc8/v2.40/bin`
+const DEFAULT_XC_ROOT: &str = "/opt/microchip";
+
+/// One `<conf>` of configurations.xml
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Configuration {
+    /// `CONF=` for the project's Makefile, e.g. `default`
+    pub name: String,
+    /// `languageToolchain`, e.g. `XC8`
+    pub toolchain: Option<String>,
+    /// `languageToolchainVersion`, e.g. `2.40`
+    pub toolchain_version: Option<String>,
+    /// `targetDevice`, e.g. `PIC18F46K22`
+    pub device: Option<String>,
+}
+
+impl Configuration {
+    /// The driver the configuration's toolchain compiles with, e.g. `xc8-cc` for XC8
+    pub fn compiler(&self) -> Option<&'static str> {
+        match self.toolchain.as_deref()?.to_ascii_uppercase().as_str() {
+            "XC8" => Some("xc8-cc"),
+            "XC16" => Some("xc16-gcc"),
+            "XC32" => Some("xc32-gcc"),
+            _ => None,
+        }
+    }
+}
+
+/// A configuration's XC compiler isn't installed on the runner
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, thiserror::Error)]
+#[error(
+    "MPLAB X configuration '{configuration}' needs the {toolchain} compiler{version} ({compiler}), which is not installed on the runner. \
+     Install MPLAB {toolchain}{version}, or build in a `container` image that provides it.",
+    version = .version.as_ref().map(|v| format!(" v{}", v)).unwrap_or_default()
+)]
+pub struct MissingCompiler {
+    pub configuration: String,
+    pub toolchain: String,
+    pub compiler: String,
+    pub version: Option<String>,
+}
+
+/// Every `<conf name="...">` in `xml`, in file order
+pub fn parse_configurations(xml: &str) -> Vec<Configuration> {
+    let mut configurations = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<conf ") {
+        let body = &rest[start..];
+        let end = body.find("</conf>").unwrap_or(body.len());
+        let conf = &body[..end];
+        rest = &body[end..];
+
+        let Some(name) = attribute(conf, "name") else {
+            continue;
+        };
+        configurations.push(Configuration {
+            name,
+            toolchain: element(conf, "languageToolchain"),
+            toolchain_version: element(conf, "languageToolchainVersion"),
+            device: element(conf, "targetDevice"),
+        });
+    }
+    configurations
+}
+
+/// The value of `name="..."` in the opening tag that starts `tag`
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let open = &tag[..tag.find('>')?];
+    let pattern = format!("{}=\"", name);
+    let value = &open[open.find(&pattern)? + pattern.len()..];
+    Some(value[..value.find('"')?].to_string())
+}
+
+/// The trimmed text of the first `<name>...</name>` in `xml`; `None` when empty
+fn element(xml: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let text = &xml[xml.find(&open)? + open.len()..];
+    let text = text[..text.find(&format!("</{}>", name))?].trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// The configurations of the MPLAB X project at `path`; empty when it has none
+pub async fn configurations(path: &Path) -> Vec<Configuration> {
+    match tokio::fs::read_to_string(path.join(CONFIGURATIONS_XML)).await {
+        Ok(xml) => parse_configurations(&xml),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The configuration to build: `requested`, or the first, which MPLAB X names `default`
+pub fn select_configuration(configurations: &[Configuration], requested: Option<&str>) -> anyhow::Result<Configuration> {
+    let names = || configurations.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", ");
+    match requested {
+        Some(name) => configurations
+            .iter()
+            .find(|c| c.name == name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("MPLAB X configuration '{}' not found in {} (found: {})", name, CONFIGURATIONS_XML, names())),
+        None => configurations
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No configurations found in {}", CONFIGURATIONS_XML)),
+    }
+}
+
+/// The `bin` directory of the exact compiler version the configuration asks for, under
+/// `NABLA_XC_ROOT` (default `/opt/microchip`), when that version is installed
+fn versioned_compiler_dir(configuration: &Configuration) -> Option<PathBuf> {
+    let root = std::env::var("NABLA_XC_ROOT").unwrap_or_else(|_| DEFAULT_XC_ROOT.to_string());
+    let toolchain = configuration.toolchain.as_deref()?.to_ascii_lowercase();
+    let bin = Path::new(&root)
+        .join(toolchain)
+        .join(format!("v{}", configuration.toolchain_version.as_deref()?))
+        .join("bin");
+    bin.join(configuration.compiler()?).is_file().then_some(bin)
+}
+
+/// Find the configuration's XC compiler. The install directory of the exact version it asks for
+/// is returned so the build can put it first on `PATH`; otherwise any version on `search_path`
+/// is used. A configuration without a known XC toolchain is left to the project's Makefiles.
+pub fn locate_compiler(configuration: &Configuration, search_path: Option<&OsStr>) -> Result<Option<PathBuf>, MissingCompiler> {
+    let (Some(toolchain), Some(compiler)) = (&configuration.toolchain, configuration.compiler()) else {
+        return Ok(None);
+    };
+    if let Some(bin) = versioned_compiler_dir(configuration) {
+        return Ok(Some(bin));
+    }
+    if on_path(compiler, search_path) {
+        return Ok(None);
+    }
+    Err(MissingCompiler {
+        configuration: configuration.name.clone(),
+        toolchain: toolchain.clone(),
+        compiler: compiler.to_string(),
+        version: configuration.toolchain_version.clone(),
+    })
+}
@@ -0,0 +1,262 @@
+use crate::core::SecretEnv;
+use crate::progress::ProgressTracker;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Default cap on the output kept from one command stream, overridable with `NABLA_MAX_OUTPUT_BYTES`
+pub const DEFAULT_OUTPUT_LIMIT: usize = 1024 * 1024;
+
+tokio::task_local! {
+    static OUTPUT_LIMIT: usize;
+}
+
+/// Run `future` with `limit` as the output cap of every buffer it creates
+pub async fn limit_output<F: Future>(limit: usize, future: F) -> F::Output {
+    OUTPUT_LIMIT.scope(limit, future).await
+}
+
+/// The output cap in effect: the [`limit_output`] scope's, `NABLA_MAX_OUTPUT_BYTES`, or
+/// [`DEFAULT_OUTPUT_LIMIT`]
+pub fn output_limit() -> usize {
+    if let Ok(limit) = OUTPUT_LIMIT.try_with(|limit| *limit) {
+        return limit;
+    }
+    std::env::var("NABLA_MAX_OUTPUT_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_OUTPUT_LIMIT)
+}
+
+/// A file receiving every byte of command output, however much the [`OutputBuffer`]s feeding
+/// it keep. Cloning shares the file.
+#[derive(Clone)]
+pub struct OutputLog(Arc<Mutex<File>>);
+
+impl OutputLog {
+    /// Open `path` for appending, creating it and its directory if needed
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        if let Err(e) = self.0.lock().write_all(bytes) {
+            tracing::warn!("Failed to write build log: {}", e);
+        }
+    }
+}
+
+/// Most lines a [`LiveLog`] keeps
+pub const LIVE_LOG_LINES: usize = 1000;
+
+/// The latest lines of a job's command output, readable while the job is still running.
+/// Secrets are redacted as lines arrive, and each line is fed to a [`ProgressTracker`].
+/// Cloning shares the lines and the tracker.
+#[derive(Clone, Default)]
+pub struct LiveLog {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    secrets: SecretEnv,
+    progress: Arc<Mutex<LiveProgress>>,
+}
+
+/// Reports each time the tracker's estimate moves forward
+type ProgressReport = Arc<dyn Fn(f32) + Send + Sync>;
+
+#[derive(Default)]
+struct LiveProgress {
+    tracker: ProgressTracker,
+    report: Option<ProgressReport>,
+}
+
+impl LiveLog {
+    /// A log redacting `secrets`
+    pub fn new(secrets: SecretEnv) -> Self {
+        Self {
+            secrets,
+            ..Self::default()
+        }
+    }
+
+    /// Call `report` with the build's progress whenever a line moves it forward
+    pub fn on_progress(&self, report: impl Fn(f32) + Send + Sync + 'static) {
+        self.progress.lock().report = Some(Arc::new(report));
+    }
+
+    /// Estimate progress with `tracker` from now on, e.g. once the build system is known
+    pub fn track_progress(&self, tracker: ProgressTracker) {
+        self.progress.lock().tracker = tracker;
+    }
+
+    pub fn push_line(&self, line: &str) {
+        let line = self.redact(line.trim_end_matches(['\n', '\r']));
+        let moved = {
+            let mut progress = self.progress.lock();
+            progress.tracker.feed_line(&line).zip(progress.report.clone())
+        };
+        if let Some((value, report)) = moved {
+            report(value);
+        }
+
+        let mut lines = self.lines.lock();
+        if lines.len() == LIVE_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// `line` with the log's secrets masked
+    pub fn redact(&self, line: &str) -> String {
+        match self.secrets.is_empty() {
+            true => line.to_string(),
+            false => self.secrets.redact(line),
+        }
+    }
+
+    /// The last `count` lines, oldest first
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock();
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+}
+
+impl fmt::Debug for LiveLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LiveLog({} lines)", self.lines.lock().len())
+    }
+}
+
+/// Command output held to a fixed size. Bytes are converted to text a line at a time, so a
+/// multi-byte character split across reads survives and invalid UTF-8 (Latin-1 from vendor
+/// tools, raw bytes) becomes U+FFFD. Past `limit`, the first and last `limit / 2` bytes are
+/// kept with a marker counting what was dropped in between; the optional [`OutputLog`] and
+/// [`LiveLog`] still get everything.
+pub struct OutputBuffer {
+    limit: usize,
+    head: String,
+    tail: VecDeque<String>,
+    tail_len: usize,
+    omitted: usize,
+    partial: Vec<u8>,
+    log: Option<OutputLog>,
+    live: Option<LiveLog>,
+}
+
+impl OutputBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            head: String::new(),
+            tail: VecDeque::new(),
+            tail_len: 0,
+            omitted: 0,
+            partial: Vec::new(),
+            log: None,
+            live: None,
+        }
+    }
+
+    /// Also write all output, unbounded, to `log`
+    pub fn with_log(mut self, log: Option<OutputLog>) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Also pass every line to `live` as it completes
+    pub fn with_live(mut self, live: Option<LiveLog>) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Bounded text of a complete output, as `String::from_utf8_lossy` would give it if it fit
+    pub fn text(bytes: &[u8]) -> String {
+        let mut buffer = Self::new(output_limit());
+        buffer.push(bytes);
+        buffer.finish()
+    }
+
+    pub fn push(&mut self, mut bytes: &[u8]) {
+        if let Some(log) = &self.log {
+            log.write(bytes);
+        }
+
+        while let Some(end) = bytes.iter().position(|&b| b == b'\n') {
+            let (line, rest) = bytes.split_at(end + 1);
+            if self.partial.is_empty() {
+                self.push_line(line);
+            } else {
+                self.partial.extend_from_slice(line);
+                let line = std::mem::take(&mut self.partial);
+                self.push_line(&line);
+            }
+            bytes = rest;
+        }
+
+        self.partial.extend_from_slice(bytes);
+        // A line that never ends (progress bars, binary dumps) can't be held back indefinitely
+        if self.partial.len() >= self.limit / 2 {
+            let line = std::mem::take(&mut self.partial);
+            self.push_line(&line);
+        }
+    }
+
+    fn push_line(&mut self, line: &[u8]) {
+        let text = String::from_utf8_lossy(line);
+        if let Some(live) = &self.live {
+            live.push_line(&text);
+        }
+        let half = self.limit / 2;
+
+        if self.omitted == 0 && self.tail.is_empty() && self.head.len() + text.len() <= half {
+            self.head.push_str(&text);
+            return;
+        }
+
+        self.tail_len += text.len();
+        self.tail.push_back(text.into_owned());
+        while self.tail_len > half {
+            let Some(dropped) = self.tail.pop_front() else { break };
+            self.tail_len -= dropped.len();
+            self.omitted += dropped.len();
+        }
+    }
+
+    /// Bytes of text currently held
+    pub fn len(&self) -> usize {
+        self.head.len() + self.tail_len + self.partial.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes dropped from the middle of the output so far
+    pub fn omitted(&self) -> usize {
+        self.omitted
+    }
+
+    pub fn finish(mut self) -> String {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.push_line(&line);
+        }
+
+        let mut text = self.head;
+        if self.omitted > 0 {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str(&format!("[... {} bytes of output omitted ...]\n", self.omitted));
+        }
+        text.extend(self.tail);
+        text
+    }
+}
@@ -0,0 +1,617 @@
+use crate::core::{CheckSeverity, EnvironmentResult, TestSummary};
+use crate::diagnostics::Diagnostic;
+use crate::output::OutputBuffer;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+const DEFAULT_INSTALL_TIMEOUT_SECS: u64 = 600;
+
+/// A single `[section]` of an INI file with its key/value pairs in file order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IniSection {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+impl IniSection {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A platform referenced by a `platform = ...` line in platformio.ini.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformSpec {
+    /// The spec exactly as written, passed to `pio pkg install --platform`.
+    pub spec: String,
+    /// Bare platform name, e.g. `espressif32` for `platformio/espressif32@^6.4.0`.
+    pub name: String,
+    /// Version requirement after `@`, if any.
+    pub version: Option<String>,
+}
+
+/// Parse an INI document the way PlatformIO reads platformio.ini: `;`/`#` comments,
+/// `key = value` pairs, and indented continuation lines appended to the previous value.
+/// Files saved on Windows, with CRLF line endings or a UTF-8 byte order mark, read the same.
+pub fn parse_ini(content: &str) -> Vec<IniSection> {
+    let mut sections: Vec<IniSection> = Vec::new();
+
+    // `trim` keeps the BOM, which would hide a first `[section]` line
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    for raw_line in content.lines() {
+        let trimmed = raw_line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            sections.push(IniSection {
+                name: trimmed[1..trimmed.len() - 1].trim().to_string(),
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(section) = sections.last_mut() else {
+            continue;
+        };
+
+        let is_continuation = raw_line.starts_with(' ') || raw_line.starts_with('\t');
+        if is_continuation {
+            if let Some((_, value)) = section.entries.last_mut() {
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(strip_inline_comment(trimmed));
+                continue;
+            }
+        }
+
+        if let Some((key, value)) = trimmed.split_once('=') {
+            section.entries.push((
+                key.trim().to_string(),
+                strip_inline_comment(value.trim()).to_string(),
+            ));
+        }
+    }
+
+    sections
+}
+
+fn strip_inline_comment(value: &str) -> &str {
+    match value.find(" ;") {
+        Some(idx) => value[..idx].trim_end(),
+        None => value,
+    }
+}
+
+impl PlatformSpec {
+    pub fn parse(spec: &str) -> Self {
+        let spec = spec.trim().to_string();
+
+        // URL and path based specs carry no registry version; derive a name from the last segment
+        if spec.contains("://") || spec.starts_with("file:") {
+            let name = spec
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or(&spec)
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches(".git")
+                .trim_start_matches("platform-")
+                .to_string();
+            return Self { spec, name, version: None };
+        }
+
+        let (package, version) = match spec.split_once('@') {
+            Some((package, version)) => (package.trim(), Some(version.trim().to_string())),
+            None => (spec.as_str(), None),
+        };
+        let name = package.rsplit('/').next().unwrap_or(package).to_string();
+
+        Self { spec: spec.clone(), name, version }
+    }
+
+    /// Whether the version requirement pins one exact release rather than a range.
+    pub fn is_exact_version(&self) -> bool {
+        self.version.as_deref().is_some_and(|v| {
+            !v.is_empty() && v.chars().all(|c| c.is_ascii_digit() || c == '.')
+        })
+    }
+}
+
+/// Collect the distinct platforms referenced from `[env]` and `[env:*]` sections.
+pub fn parse_platforms(ini: &str) -> Vec<PlatformSpec> {
+    let mut seen = HashSet::new();
+    let mut platforms = Vec::new();
+
+    for section in parse_ini(ini) {
+        if section.name != "env" && !section.name.starts_with("env:") {
+            continue;
+        }
+        let Some(value) = section.get("platform") else {
+            continue;
+        };
+        // Interpolated values such as ${common.platform} are resolved by PlatformIO itself
+        if value.is_empty() || value.contains("${") {
+            continue;
+        }
+        if seen.insert(value.to_string()) {
+            platforms.push(PlatformSpec::parse(value));
+        }
+    }
+
+    platforms
+}
+
+/// Installing the project's platforms failed, so the build never ran. Kept apart from build
+/// failures so a registry outage or cold cache isn't reported as broken code.
+#[derive(Debug, thiserror::Error)]
+#[error("PlatformIO platform installation failed: {reason}")]
+pub struct PlatformInstallFailed {
+    pub reason: String,
+    /// What the install phase logged before it failed
+    pub log: Vec<String>,
+}
+
+/// Install any platforms referenced by the project's platformio.ini that are not already
+/// present in the global PlatformIO storage. `envs` is applied to every `pio` invocation so a
+/// per-customer `PLATFORMIO_CORE_DIR` is honored. Returns a log of what was done.
+pub async fn preinstall_platforms(path: &Path, envs: &BTreeMap<String, String>) -> Result<Vec<String>, PlatformInstallFailed> {
+    let mut log = Vec::new();
+    let content = match fs::read_to_string(path.join("platformio.ini")).await {
+        Ok(content) => content,
+        Err(e) => return Err(PlatformInstallFailed { reason: format!("reading platformio.ini: {}", e), log }),
+    };
+    let platforms = parse_platforms(&content);
+    if platforms.is_empty() {
+        return Ok(log);
+    }
+
+    let timeout = install_timeout();
+    // Without the list every platform is handed to `pio pkg install`, which skips installed ones
+    let installed = installed_platforms(path, envs, timeout).await.unwrap_or_else(|e| {
+        warn!("Could not list installed PlatformIO platforms, installing all of them: {}", e);
+        log.push(format!("Could not list installed platforms ({}), installing all of them", e));
+        Vec::new()
+    });
+    let missing = missing_platforms(platforms, &installed);
+    if missing.is_empty() {
+        log.push("All PlatformIO platforms already installed".to_string());
+        return Ok(log);
+    }
+
+    for platform in missing {
+        info!("Installing PlatformIO platform {}", platform.spec);
+        let install = Command::new("pio")
+            .args(["pkg", "install", "--global", "--platform", &platform.spec])
+            .envs(envs)
+            .current_dir(path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .output();
+
+        let reason = match tokio::time::timeout(timeout, install).await {
+            Err(_) => format!("installing {} timed out after {}s", platform.spec, timeout.as_secs()),
+            Ok(Err(e)) => format!("installing {} failed: {}", platform.spec, e),
+            Ok(Ok(output)) if !output.status.success() => format!(
+                "installing {} failed: {}{}",
+                platform.spec,
+                OutputBuffer::text(&output.stdout),
+                OutputBuffer::text(&output.stderr)
+            ),
+            Ok(Ok(_)) => {
+                log.push(format!("Installed platform {}", platform.spec));
+                continue;
+            }
+        };
+        return Err(PlatformInstallFailed { reason, log });
+    }
+
+    Ok(log)
+}
+
+/// Query installed platforms as `(name, version)` pairs via `pio platform list --json-output`.
+async fn installed_platforms(path: &Path, envs: &BTreeMap<String, String>, timeout: Duration) -> Result<Vec<(String, String)>> {
+    let list = Command::new("pio")
+        .args(["platform", "list", "--json-output"])
+        .envs(envs)
+        .current_dir(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+
+    let output = tokio::time::timeout(timeout, list)
+        .await
+        .map_err(|_| anyhow!("listing installed platforms timed out"))??;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "listing installed platforms failed: {}",
+            OutputBuffer::text(&output.stderr)
+        ));
+    }
+
+    let listed: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+    Ok(listed
+        .iter()
+        .filter_map(|p| {
+            let name = p.get("name")?.as_str()?.to_string();
+            let version = p.get("version").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+            Some((name, version))
+        })
+        .collect())
+}
+
+/// Platforms not satisfied by the installed `(name, version)` list. Range requirements are
+/// satisfied by any installed version; exact pins need that exact version.
+pub fn missing_platforms(platforms: Vec<PlatformSpec>, installed: &[(String, String)]) -> Vec<PlatformSpec> {
+    platforms
+        .into_iter()
+        .filter(|platform| {
+            !installed.iter().any(|(name, version)| {
+                name == &platform.name
+                    && (!platform.is_exact_version() || platform.version.as_deref() == Some(version.as_str()))
+            })
+        })
+        .collect()
+}
+
+fn install_timeout() -> Duration {
+    let secs = env::var("NABLA_PIO_INSTALL_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_INSTALL_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Largest major version a registry platform plausibly has (ststm32, the highest, is in the
+/// teens). Pins beyond it are typos or placeholders such as `espressif32@99.99.99`.
+const MAX_PLAUSIBLE_PLATFORM_MAJOR: u64 = 50;
+
+/// Names of the `[env:NAME]` sections, in file order.
+pub fn environments(ini: &str) -> Vec<String> {
+    parse_ini(ini)
+        .into_iter()
+        .filter_map(|section| section.name.strip_prefix("env:").map(|name| name.trim().to_string()))
+        .collect()
+}
+
+/// The environments a plain `pio run` builds: those `[platformio] default_envs` names that
+/// have a section, in its order, or every environment when it isn't set
+pub fn default_environments(ini: &str) -> Vec<String> {
+    let envs = environments(ini);
+    let sections = parse_ini(ini);
+    match sections.iter().find(|s| s.name == "platformio").and_then(|s| s.get("default_envs")) {
+        Some(defaults) => env_list(defaults).into_iter().filter(|env| envs.contains(env)).collect(),
+        None => envs,
+    }
+}
+
+/// Items of a list option, separated by commas or whitespace including newlines
+fn env_list(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Check platformio.ini for mistakes that would otherwise only surface minutes into `pio run`:
+/// no environments, environments without a platform, malformed or implausible version pins,
+/// and references (`default_envs`, `extends`, `requested_envs`) to sections that don't exist.
+/// Returns one message per problem.
+pub fn preflight_check(ini: &str, requested_envs: &[String]) -> Vec<String> {
+    let sections = parse_ini(ini);
+    let section_names: HashSet<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+    let envs = environments(ini);
+    let mut issues = Vec::new();
+
+    if envs.is_empty() {
+        issues.push("platformio.ini defines no [env:...] sections".to_string());
+    }
+
+    if let Some(platformio) = sections.iter().find(|s| s.name == "platformio") {
+        for env in platformio.get("default_envs").map(env_list).unwrap_or_default() {
+            if !env.contains("${") && !envs.contains(&env) {
+                issues.push(format!("default_envs names '{}' but there is no [env:{}] section", env, env));
+            }
+        }
+    }
+
+    let shared = sections.iter().find(|s| s.name == "env");
+    for section in sections.iter().filter(|s| s.name.starts_with("env:")) {
+        let extends = section.get("extends").map(env_list).unwrap_or_default();
+        for parent in &extends {
+            if !parent.contains("${") && !section_names.contains(parent.as_str()) {
+                issues.push(format!("[{}] extends '{}', which is not defined", section.name, parent));
+            }
+        }
+
+        // The effective platform may come from the section itself, an extended section or [env]
+        let platform = section
+            .get("platform")
+            .or_else(|| {
+                extends
+                    .iter()
+                    .find_map(|parent| sections.iter().find(|s| &s.name == parent).and_then(|s| s.get("platform")))
+            })
+            .or_else(|| shared.and_then(|s| s.get("platform")));
+        if platform.is_none_or(str::is_empty) {
+            issues.push(format!("[{}] has no platform", section.name));
+        }
+    }
+
+    for platform in parse_platforms(ini) {
+        if let Some(issue) = version_pin_issue(&platform) {
+            issues.push(issue);
+        }
+    }
+
+    for env in requested_envs {
+        if !envs.contains(env) {
+            issues.push(format!("environment '{}' was requested but there is no [env:{}] section", env, env));
+        }
+    }
+
+    issues
+}
+
+fn version_pin_issue(platform: &PlatformSpec) -> Option<String> {
+    let version = platform.version.as_deref()?;
+    if version.is_empty() {
+        return Some(format!("platform '{}' has an empty version after '@'", platform.spec));
+    }
+    if version != "*" && !version.chars().any(|c| c.is_ascii_digit()) {
+        return Some(format!("platform '{}' has a malformed version '{}'", platform.spec, version));
+    }
+
+    let major: u64 = version
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    (major > MAX_PLAUSIBLE_PLATFORM_MAJOR).then(|| {
+        format!("platform '{}' pins version {}, which does not plausibly exist", platform.spec, version)
+    })
+}
+
+/// Parse `pio test` output. PlatformIO 6 prints a closing line such as
+/// `==== 4 test cases: 1 failed, 3 succeeded in 00:00:01.215 ====`; older Unity output only has
+/// `N Tests N Failures N Ignored`, and as a last resort per-case `[PASSED]`/`[FAILED]` markers are counted.
+pub fn parse_test_summary(output: &str) -> Option<TestSummary> {
+    let count_before = |text: &str, word: &str| -> Option<usize> {
+        let at = text.find(word)?;
+        text[..at].split_whitespace().last()?.parse().ok()
+    };
+
+    for line in output.lines().rev() {
+        if let Some(at) = line.find(" test cases:") {
+            let total = count_before(line, " test cases:")?;
+            let rest = &line[at..];
+            let failed = count_before(rest, " failed").unwrap_or(0) + count_before(rest, " errored").unwrap_or(0);
+            let skipped = count_before(rest, " skipped").unwrap_or(0);
+            let passed = count_before(rest, " succeeded").unwrap_or(total.saturating_sub(failed + skipped));
+            return Some(TestSummary { total, passed, failed, skipped });
+        }
+
+        if line.contains(" Tests ") && line.contains(" Failures ") {
+            let total = count_before(line, " Tests")?;
+            let failed = count_before(line, " Failures")?;
+            let skipped = count_before(line, " Ignored").unwrap_or(0);
+            return Some(TestSummary {
+                total,
+                passed: total.saturating_sub(failed + skipped),
+                failed,
+                skipped,
+            });
+        }
+    }
+
+    let passed = output.lines().filter(|line| line.contains("[PASSED]")).count();
+    let failed = output.lines().filter(|line| line.contains("[FAILED]")).count();
+    let skipped = output.lines().filter(|line| line.contains("[SKIPPED]")).count();
+    if passed + failed + skipped == 0 {
+        return None;
+    }
+    Some(TestSummary { total: passed + failed + skipped, passed, failed, skipped })
+}
+
+/// Tool named on the diagnostics `pio check` defects become
+pub const CHECK_TOOL: &str = "platformio-check";
+
+/// One environment of `pio check --json-output`
+#[derive(Deserialize)]
+struct CheckedEnvironment {
+    env: String,
+    #[serde(default)]
+    succeeded: Option<bool>,
+    #[serde(default)]
+    defects: Vec<CheckDefect>,
+}
+
+#[derive(Deserialize)]
+struct CheckDefect {
+    severity: CheckSeverity,
+    message: String,
+    file: String,
+    #[serde(default)]
+    line: u32,
+    #[serde(default)]
+    column: Option<u32>,
+    #[serde(default)]
+    id: Option<String>,
+}
+
+/// What `pio check --json-output` found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// Environments the analyzer ran on
+    pub checked: Vec<String>,
+    /// Environments it couldn't analyze, e.g. on a platform without check support
+    pub unchecked: Vec<String>,
+    /// Every defect once, even when several environments share the source it's in
+    pub defects: Vec<Diagnostic>,
+}
+
+/// Parse `pio check --json-output`, one JSON array with an entry per environment, into
+/// diagnostics from [`CHECK_TOOL`]. Files under `repo` are reported relative to it; the
+/// defect's check id, e.g. `uninitvar`, becomes its `flag`.
+pub fn parse_check_report(output: &str, repo: &Path) -> Result<CheckReport> {
+    let json = output
+        .lines()
+        .rev()
+        .find(|line| line.trim_start().starts_with('['))
+        .ok_or_else(|| anyhow!("pio check printed no JSON report"))?;
+    let environments: Vec<CheckedEnvironment> =
+        serde_json::from_str(json).map_err(|e| anyhow!("pio check printed an unreadable report: {}", e))?;
+
+    let repo_prefix = format!("{}/", repo.display());
+    let mut report = CheckReport::default();
+    for environment in environments {
+        match environment.succeeded == Some(true) || !environment.defects.is_empty() {
+            true => report.checked.push(environment.env),
+            false => report.unchecked.push(environment.env),
+        }
+        for defect in environment.defects {
+            let diagnostic = Diagnostic {
+                file: defect.file.strip_prefix(&repo_prefix).unwrap_or(&defect.file).to_string(),
+                line: defect.line,
+                column: defect.column.filter(|&column| column > 0),
+                severity: defect.severity.as_severity(),
+                message: defect.message,
+                flag: defect.id,
+                tool: Some(CHECK_TOOL.to_string()),
+                notes: Vec::new(),
+            };
+            if !report.defects.contains(&diagnostic) {
+                report.defects.push(diagnostic);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Parse the per-environment table `pio run` closes with:
+///
+/// ```text
+/// Environment    Status    Duration
+/// -------------  --------  ------------
+/// esp32dev       SUCCESS   00:00:05.123
+/// wrover         FAILED    00:00:01.234
+/// ```
+///
+/// Environments marked `IGNORED` weren't built and are left out. Empty when there's no table.
+pub fn parse_run_summary(output: &str) -> Vec<EnvironmentResult> {
+    let mut lines = output.lines().skip_while(|line| {
+        let mut columns = line.split_whitespace();
+        !(columns.next() == Some("Environment") && columns.next() == Some("Status"))
+    });
+    if lines.next().is_none() {
+        return Vec::new();
+    }
+
+    lines
+        .skip_while(|line| line.trim_start().starts_with('-'))
+        .map_while(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            match columns.as_slice() {
+                [env, status, duration] => Some((*env, *status, *duration)),
+                _ => None,
+            }
+        })
+        .filter(|(_, status, _)| *status != "IGNORED")
+        .map(|(env, status, duration)| EnvironmentResult {
+            env: env.to_string(),
+            success: status == "SUCCESS",
+            skipped: false,
+            error: (status != "SUCCESS").then(|| format!("pio run reported {}", status)),
+            artifacts: Vec::new(),
+            duration_ms: parse_duration_ms(duration).unwrap_or(0),
+        })
+        .collect()
+}
+
+/// `HH:MM:SS.mmm` as milliseconds
+fn parse_duration_ms(duration: &str) -> Option<u64> {
+    let (hms, millis) = duration.split_once('.').unwrap_or((duration, "0"));
+    let mut seconds = 0;
+    for part in hms.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(seconds * 1000 + millis.parse::<u64>().ok()?)
+}
+
+/// A Python module a build script, usually one of `extra_scripts`, imports but PlatformIO's
+/// Python environment lacks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingPythonModule {
+    /// The top-level module, e.g. `git` for `import git.repo`
+    pub module: String,
+    /// The import error, pointing at the script that failed
+    pub diagnostic: Diagnostic,
+}
+
+impl MissingPythonModule {
+    /// The pip package that provides the module
+    pub fn package(&self) -> &str {
+        pip_package(&self.module)
+    }
+}
+
+/// The first `ModuleNotFoundError`/`ImportError: No module named ...` in `pio` output
+pub fn missing_python_module(output: &str) -> Option<MissingPythonModule> {
+    crate::diagnostics::parse_python_errors(output).into_iter().find_map(|diagnostic| {
+        let (_, named) = diagnostic.message.split_once("No module named ")?;
+        let module = named.trim_matches(['\'', '"']).split('.').next()?.to_string();
+        (!module.is_empty()).then_some(MissingPythonModule { module, diagnostic })
+    })
+}
+
+/// pip package names of modules build scripts commonly import under a different name
+const PIP_PACKAGES: &[(&str, &str)] = &[
+    ("git", "GitPython"),
+    ("yaml", "PyYAML"),
+    ("serial", "pyserial"),
+    ("Crypto", "pycryptodome"),
+    ("dateutil", "python-dateutil"),
+    ("PIL", "Pillow"),
+];
+
+/// The pip package to install for `module`: the module's own name unless it is known to differ
+pub fn pip_package(module: &str) -> &str {
+    PIP_PACKAGES.iter().find(|(name, _)| *name == module).map_or(module, |(_, package)| package)
+}
+
+/// Where PlatformIO keeps its Python environment: `penv` under `PLATFORMIO_CORE_DIR` from the
+/// build's environment or the runner's, else under `~/.platformio`
+pub fn penv_dir(envs: &BTreeMap<String, String>) -> std::path::PathBuf {
+    let core_dir = envs
+        .get("PLATFORMIO_CORE_DIR")
+        .cloned()
+        .or_else(|| env::var("PLATFORMIO_CORE_DIR").ok())
+        .unwrap_or_else(|| format!("{}/.platformio", env::var("HOME").unwrap_or_default()));
+    Path::new(&core_dir).join("penv")
+}
+
+/// The command installing `package` into PlatformIO's Python environment with the penv's own pip
+pub fn penv_pip_install(envs: &BTreeMap<String, String>, package: &str) -> Vec<String> {
+    let python = penv_dir(envs).join("bin/python");
+    vec![python.to_string_lossy().to_string(), "-m".to_string(), "pip".to_string(), "install".to_string(), package.to_string()]
+}
@@ -0,0 +1,464 @@
+use crate::core::BuildConfig;
+use crate::output::{output_limit, LiveLog, OutputBuffer, OutputLog};
+use anyhow::{anyhow, Result};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::env;
+use std::ffi::OsStr;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tracing::warn;
+
+pub(crate) const DEFAULT_BUILD_TIMEOUT_SECS: u64 = 3600;
+const DEFAULT_KILL_GRACE_SECS: u64 = 10;
+
+/// Processes found still running after the build command or job that started them finished,
+/// and killed
+static LEAKED_PROCESSES: AtomicU64 = AtomicU64::new(0);
+
+/// How many leaked build processes this runner has killed since it started
+pub fn leaked_processes_killed() -> u64 {
+    LEAKED_PROCESSES.load(Ordering::Relaxed)
+}
+
+/// Whether `tool` is an executable file in one of the directories of `search_path`, a
+/// `PATH`-style list
+pub fn on_path(tool: &str, search_path: Option<&OsStr>) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    search_path.is_some_and(|path| {
+        env::split_paths(path).any(|dir| {
+            std::fs::metadata(dir.join(tool)).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+    })
+}
+
+/// Time limit and termination grace period applied to a spawned build command.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandLimits {
+    pub timeout: Duration,
+    pub kill_grace: Duration,
+    /// Start the command in its own network namespace, with no interfaces but loopback.
+    pub isolate_network: bool,
+}
+
+impl CommandLimits {
+    pub fn from_config(config: &BuildConfig) -> Self {
+        let timeout_secs = config
+            .timeout_secs
+            .or_else(|| env_secs("NABLA_BUILD_TIMEOUT_SECS"))
+            .unwrap_or(DEFAULT_BUILD_TIMEOUT_SECS);
+        let grace_secs = env_secs("NABLA_KILL_GRACE_SECS").unwrap_or(DEFAULT_KILL_GRACE_SECS);
+
+        Self {
+            timeout: Duration::from_secs(timeout_secs),
+            kill_grace: Duration::from_secs(grace_secs),
+            isolate_network: network_isolated(),
+        }
+    }
+}
+
+/// How a build command that failed ended: its exit code, or on Unix the signal that
+/// terminated it, e.g. 9 for a compiler killed by the OOM killer or 11 for one that crashed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessExit {
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+impl From<ExitStatus> for ProcessExit {
+    fn from(status: ExitStatus) -> Self {
+        Self {
+            exit_code: status.code(),
+            signal: status.signal(),
+        }
+    }
+}
+
+/// What a [`capture_output`] scope collected
+#[derive(Debug, Clone, Default)]
+pub struct CapturedOutput {
+    /// The stdout and stderr of every command, bounded like a single command's output
+    pub text: String,
+    /// Only the stdout of every command, bounded the same way
+    pub stdout: String,
+    /// Only the stderr of every command, bounded the same way
+    pub stderr: String,
+    /// How the last command that failed ended
+    pub failed_exit: Option<ProcessExit>,
+}
+
+tokio::task_local! {
+    static CAPTURED_OUTPUT: RefCell<CaptureBuffers>;
+    static FAILED_EXIT: Cell<Option<ProcessExit>>;
+    static NETWORK_ISOLATED: bool;
+    static OUTPUT_LOG: OutputLog;
+    static LIVE_LOG: LiveLog;
+}
+
+/// Run `future` with the full output of every `run_command` it makes appended to `log`.
+/// Like [`capture_output`], commands run on spawned tasks are outside the scope.
+pub async fn log_output_to<F: Future>(log: OutputLog, future: F) -> F::Output {
+    OUTPUT_LOG.scope(log, future).await
+}
+
+/// Run `future` with every line of output its `run_command`s print passed to `live` as it's printed
+pub async fn stream_output_to<F: Future>(live: LiveLog, future: F) -> F::Output {
+    LIVE_LOG.scope(live, future).await
+}
+
+/// The [`stream_output_to`] log in effect, if any
+pub fn live_log() -> Option<LiveLog> {
+    LIVE_LOG.try_with(LiveLog::clone).ok()
+}
+
+/// Run `future` with every `run_command` it makes cut off from the network.
+pub async fn without_network<F: Future>(future: F) -> F::Output {
+    NETWORK_ISOLATED.scope(true, future).await
+}
+
+/// Whether the current task is inside a `without_network` scope
+pub fn network_isolated() -> bool {
+    NETWORK_ISOLATED.try_with(|isolated| *isolated).unwrap_or(false)
+}
+
+static ISOLATION_AVAILABLE: tokio::sync::OnceCell<bool> = tokio::sync::OnceCell::const_new();
+
+/// Whether this runner may create network namespaces: as root, or unprivileged where the
+/// kernel allows user namespaces. Probed once by starting `true` in a fresh namespace.
+pub async fn network_isolation_available() -> bool {
+    *ISOLATION_AVAILABLE
+        .get_or_init(|| async {
+            let mut probe = Command::new("true");
+            // SAFETY: unshare_network only makes async-signal-safe syscalls
+            unsafe {
+                probe.pre_exec(unshare_network);
+            }
+            probe.status().await.is_ok_and(|status| status.success())
+        })
+        .await
+}
+
+/// Move the calling process into a new network namespace. Root can unshare directly; anyone
+/// else needs a user namespace created alongside.
+fn unshare_network() -> std::io::Result<()> {
+    // SAFETY: unshare only changes the namespaces of the calling (forked child) process
+    unsafe {
+        if libc::unshare(libc::CLONE_NEWNET) == 0 || libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) == 0 {
+            return Ok(());
+        }
+    }
+    Err(std::io::Error::last_os_error())
+}
+
+/// The output a [`capture_output`] scope collects, both streams together and each on its own
+struct CaptureBuffers {
+    combined: OutputBuffer,
+    stdout: OutputBuffer,
+    stderr: OutputBuffer,
+}
+
+impl CaptureBuffers {
+    fn new(limit: usize) -> Self {
+        Self {
+            combined: OutputBuffer::new(limit),
+            stdout: OutputBuffer::new(limit),
+            stderr: OutputBuffer::new(limit),
+        }
+    }
+}
+
+/// Run `future`, collecting the stdout and stderr of every `run_command` it makes, and how
+/// the last of them to fail ended.
+pub async fn capture_output<F: Future>(future: F) -> (F::Output, CapturedOutput) {
+    let captured = async {
+        let result = future.await;
+        let buffers = CAPTURED_OUTPUT.with(|output| output.replace(CaptureBuffers::new(0)));
+        let captured = CapturedOutput {
+            text: buffers.combined.finish(),
+            stdout: buffers.stdout.finish(),
+            stderr: buffers.stderr.finish(),
+            failed_exit: FAILED_EXIT.with(Cell::get),
+        };
+        (result, captured)
+    };
+    let captured = FAILED_EXIT.scope(Cell::new(None), captured);
+    CAPTURED_OUTPUT.scope(RefCell::new(CaptureBuffers::new(output_limit())), captured).await
+}
+
+/// Append a command's output to the enclosing `capture_output` scope, if any. Commands run on
+/// spawned tasks are outside the scope and must be recorded by their parent.
+pub(crate) fn record_output(output: &Output) {
+    let _ = CAPTURED_OUTPUT.try_with(|captured| {
+        let mut captured = captured.borrow_mut();
+        captured.combined.push(&output.stdout);
+        captured.combined.push(&output.stderr);
+        captured.stdout.push(&output.stdout);
+        captured.stderr.push(&output.stderr);
+    });
+    if !output.status.success() {
+        record_failed_exit(output.status);
+    }
+}
+
+fn record_failed_exit(status: ExitStatus) {
+    let _ = FAILED_EXIT.try_with(|failed| failed.set(Some(status.into())));
+}
+
+fn env_secs(name: &str) -> Option<u64> {
+    env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+/// Run a build command in its own process group and collect its output.
+///
+/// Build tools routinely spawn their own children (cmake -> make -> cc1), so on timeout the
+/// whole group gets SIGTERM, then SIGKILL once the grace period expires. If the returned
+/// future is dropped mid-build (e.g. the client went away) the group is torn down the same way.
+/// Anything still in the group once the command exits, such as a daemon an uploader forked,
+/// is killed and reaped rather than left to hold the output pipes open and use up PIDs.
+///
+/// `config.secret_env` values are scrubbed from the returned output, so nothing built from it
+/// (logs, error messages, diagnostics) can disclose them. Output is capped as described for
+/// [`run_command_with_limits`].
+pub async fn run_command(mut command: Command, config: &BuildConfig) -> Result<Output> {
+    command.envs(&config.command_env);
+    command.envs(config.secret_env.expose());
+    let mut limits = CommandLimits::from_config(config);
+
+    // Inside a container, docker isolates the network itself
+    let mut running = None;
+    if let Some(container) = crate::container::current() {
+        let (wrapped, container) = container.wrap(&command, limits.isolate_network);
+        command = wrapped;
+        running = Some(container);
+        limits.isolate_network = false;
+    }
+
+    let output = run_command_with_limits(command, limits).await;
+    // A timed out run only killed the docker CLI; the container itself has to go too
+    match (running, &output) {
+        (Some(container), Ok(_)) => container.finished(),
+        (Some(container), Err(_)) => container.remove().await,
+        (None, _) => {}
+    }
+    let mut output = output?;
+    if !config.secret_env.is_empty() {
+        output.stdout = config.secret_env.redact(&OutputBuffer::text(&output.stdout)).into_bytes();
+        output.stderr = config.secret_env.redact(&OutputBuffer::text(&output.stderr)).into_bytes();
+    }
+    record_output(&output);
+    Ok(output)
+}
+
+/// Run `command` under `limits`. Its stdout and stderr are each read through an
+/// [`OutputBuffer`], so the returned output is valid UTF-8 and at most `NABLA_MAX_OUTPUT_BYTES`
+/// per stream however much the build prints. Inside [`log_output_to`] the full output is
+/// also written to the log.
+pub async fn run_command_with_limits(mut command: Command, limits: CommandLimits) -> Result<Output> {
+    command
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if limits.isolate_network {
+        // SAFETY: unshare_network only makes async-signal-safe syscalls
+        unsafe {
+            command.pre_exec(unshare_network);
+        }
+    }
+
+    let mut child = command.spawn()?;
+    let pgid = child
+        .id()
+        .ok_or_else(|| anyhow!("spawned build process has no pid"))? as libc::pid_t;
+    let mut guard = ProcessGroupGuard { pgid, grace: limits.kill_grace, armed: true };
+
+    let log = OUTPUT_LOG.try_with(OutputLog::clone).ok();
+    let stdout = read_bounded(child.stdout.take(), log.clone(), live_log());
+    let stderr = read_bounded(child.stderr.take(), log, live_log());
+    // The pipes only close once every process holding them is gone, so leftovers are
+    // killed as soon as the command itself exits
+    let exited = async {
+        let status = child.wait().await;
+        reap_process_group(pgid).await;
+        status
+    };
+    let finished = async {
+        let (stdout, stderr, status) = tokio::join!(stdout, stderr, exited);
+        Ok::<_, std::io::Error>(Output { status: status?, stdout: stdout?, stderr: stderr? })
+    };
+
+    match tokio::time::timeout(limits.timeout, finished).await {
+        Ok(output) => {
+            guard.armed = false;
+            Ok(output?)
+        }
+        Err(_) => {
+            guard.armed = false;
+            warn!("Build command timed out after {}s, terminating process group {}", limits.timeout.as_secs(), pgid);
+            terminate_process_group(pgid, limits.kill_grace).await;
+            // Reports the signal that ended it: SIGTERM, or SIGKILL after the grace period
+            if let Ok(Ok(status)) = tokio::time::timeout(Duration::from_secs(1), child.wait()).await {
+                record_failed_exit(status);
+            }
+            Err(anyhow!("Build timed out after {}s", limits.timeout.as_secs()))
+        }
+    }
+}
+
+/// Read a child's pipe to the end through an [`OutputBuffer`]
+async fn read_bounded(pipe: Option<impl AsyncRead + Unpin>, log: Option<OutputLog>, live: Option<LiveLog>) -> std::io::Result<Vec<u8>> {
+    let mut buffer = OutputBuffer::new(output_limit()).with_log(log).with_live(live);
+    if let Some(mut pipe) = pipe {
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            let read = pipe.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            buffer.push(&chunk[..read]);
+        }
+    }
+    Ok(buffer.finish().into_bytes())
+}
+
+/// SIGTERM the group, wait up to `grace` for it to exit, then SIGKILL whatever is left.
+pub async fn terminate_process_group(pgid: libc::pid_t, grace: Duration) {
+    signal_group(pgid, libc::SIGTERM);
+
+    let deadline = tokio::time::Instant::now() + grace;
+    while tokio::time::Instant::now() < deadline {
+        if !group_alive(pgid) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    signal_group(pgid, libc::SIGKILL);
+}
+
+/// Kill what is left of the process group of a command that has exited, and reap any of it
+/// that are the runner's own children. Returns how many processes were left.
+async fn reap_process_group(pgid: libc::pid_t) -> usize {
+    let leaked = live_processes(|stat| stat.pgrp == pgid).len();
+    if leaked > 0 {
+        warn!("Killing {} process(es) left in group {} after the build command exited", leaked, pgid);
+        LEAKED_PROCESSES.fetch_add(leaked as u64, Ordering::Relaxed);
+        signal_group(pgid, libc::SIGKILL);
+    }
+
+    // Killed processes take a moment to die; give up after a second rather than stall the build
+    for _ in 0..100 {
+        // SAFETY: waitpid with WNOHANG only collects exit statuses of children in the group
+        while unsafe { libc::waitpid(-pgid, std::ptr::null_mut(), libc::WNOHANG) } > 0 {}
+        if live_processes(|stat| stat.pgrp == pgid).is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    leaked
+}
+
+/// Kill every process of this user whose working directory is inside `dir`, for the end of a
+/// job whose build may have started processes in their own session, outside the process
+/// groups [`run_command`] tears down. Returns how many were killed.
+pub async fn kill_processes_in(dir: &Path) -> usize {
+    let Ok(dir) = tokio::fs::canonicalize(dir).await else {
+        return 0;
+    };
+    let within = dir.clone();
+    let killed = tokio::task::spawn_blocking(move || {
+        let own = std::process::id() as libc::pid_t;
+        let stray = live_processes(|stat| {
+            stat.pid != own
+                && std::fs::read_link(format!("/proc/{}/cwd", stat.pid)).is_ok_and(|cwd| cwd.starts_with(&within))
+        });
+        for &pid in &stray {
+            // SAFETY: kill only sends a signal; a process that already exited gives ESRCH
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+            }
+        }
+        stray.len()
+    })
+    .await
+    .unwrap_or(0);
+
+    if killed > 0 {
+        warn!("Killed {} process(es) left running in {}", killed, dir.display());
+        LEAKED_PROCESSES.fetch_add(killed as u64, Ordering::Relaxed);
+    }
+    killed
+}
+
+/// The fields of `/proc/<pid>/stat` the runner looks at
+struct ProcStat {
+    pid: libc::pid_t,
+    state: char,
+    pgrp: libc::pid_t,
+}
+
+fn proc_stat(pid: libc::pid_t) -> Option<ProcStat> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is in parentheses and may itself contain spaces or parentheses
+    let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let pgrp = fields.nth(1)?.parse().ok()?;
+    Some(ProcStat { pid, state, pgrp })
+}
+
+/// Pids of the processes matching `filter` that haven't exited; zombies are left out, since
+/// they are already dead. Empty where there is no `/proc`.
+fn live_processes(filter: impl Fn(&ProcStat) -> bool) -> Vec<libc::pid_t> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(proc_stat)
+        .filter(|stat| stat.state != 'Z' && filter(stat))
+        .map(|stat| stat.pid)
+        .collect()
+}
+
+fn signal_group(pgid: libc::pid_t, signal: libc::c_int) {
+    // SAFETY: killpg only sends a signal; an already-gone group just returns ESRCH
+    unsafe {
+        libc::killpg(pgid, signal);
+    }
+}
+
+fn group_alive(pgid: libc::pid_t) -> bool {
+    // SAFETY: signal 0 performs only the existence/permission check
+    unsafe { libc::killpg(pgid, 0) == 0 }
+}
+
+/// Tears the process group down if the owning future is dropped before the command finishes.
+struct ProcessGroupGuard {
+    pgid: libc::pid_t,
+    grace: Duration,
+    armed: bool,
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        let (pgid, grace) = (self.pgid, self.grace);
+        warn!("Build cancelled, terminating process group {}", pgid);
+        signal_group(pgid, libc::SIGTERM);
+        // Drop can't await, so escalate from a plain thread
+        std::thread::spawn(move || {
+            std::thread::sleep(grace);
+            if group_alive(pgid) {
+                signal_group(pgid, libc::SIGKILL);
+            }
+        });
+    }
+}
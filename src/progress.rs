@@ -0,0 +1,103 @@
+/// Rough build progress, in percent, derived from the markers build tools print as they run:
+/// make/CMake's `[ 42%]` prefixes, Ninja's `[123/456]` counters and PlatformIO's per-environment
+/// stage lines. Lines without a marker (compiler warnings, notes) are ignored, and the reported
+/// value never goes backwards.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressTracker {
+    platformio_envs: usize,
+    envs_started: usize,
+    env_stage: f32,
+    progress: Option<f32>,
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a PlatformIO build over `envs` environments, each taking an equal share of 0-100%.
+    pub fn for_platformio(envs: usize) -> Self {
+        Self {
+            platformio_envs: envs.max(1),
+            ..Self::default()
+        }
+    }
+
+    pub fn progress(&self) -> Option<f32> {
+        self.progress
+    }
+
+    /// Consume one line of build output; returns the progress if this line moved it forward.
+    pub fn feed_line(&mut self, line: &str) -> Option<f32> {
+        let line = line.trim();
+
+        let value = if self.platformio_envs > 0 {
+            self.platformio_progress(line)
+        } else {
+            parse_percent_marker(line).or_else(|| parse_ninja_marker(line))
+        }?;
+
+        let value = value.clamp(0.0, 100.0);
+        if self.progress.is_some_and(|current| value <= current) {
+            return None;
+        }
+        self.progress = Some(value);
+        self.progress
+    }
+
+    fn platformio_progress(&mut self, line: &str) -> Option<f32> {
+        if line.starts_with("Processing ") {
+            self.envs_started += 1;
+            self.env_stage = 0.0;
+        } else {
+            self.env_stage = self.env_stage.max(platformio_stage(line)?);
+        }
+
+        let envs = self.platformio_envs as f32;
+        let completed_envs = self.envs_started.saturating_sub(1).min(self.platformio_envs) as f32;
+        Some((completed_envs * 100.0 + self.env_stage) / envs)
+    }
+}
+
+/// `[ 42%] Building C object ...` from make-based CMake builds
+pub fn parse_percent_marker(line: &str) -> Option<f32> {
+    let rest = line.strip_prefix('[')?;
+    let (inner, _) = rest.split_once("%]")?;
+    inner.trim().parse().ok()
+}
+
+/// `[123/456] Building CXX object ...` from Ninja
+pub fn parse_ninja_marker(line: &str) -> Option<f32> {
+    let rest = line.strip_prefix('[')?;
+    let (inner, _) = rest.split_once(']')?;
+    let (done, total) = inner.split_once('/')?;
+    let done: f32 = done.trim().parse().ok()?;
+    let total: f32 = total.trim().parse().ok()?;
+    if total <= 0.0 {
+        return None;
+    }
+    Some(done / total * 100.0)
+}
+
+/// Percent of one PlatformIO environment completed when `line` is printed
+fn platformio_stage(line: &str) -> Option<f32> {
+    // Stage lines name a path under .pio/build; "Building in release mode" and friends do not
+    if line.starts_with('=') && line.contains("[SUCCESS]") {
+        return Some(100.0);
+    }
+    if !line.contains(".pio/build") {
+        return None;
+    }
+
+    if line.starts_with("Compiling ") {
+        Some(25.0)
+    } else if line.starts_with("Archiving ") || line.starts_with("Indexing ") {
+        Some(50.0)
+    } else if line.starts_with("Linking ") {
+        Some(75.0)
+    } else if line.starts_with("Building ") {
+        Some(90.0)
+    } else {
+        None
+    }
+}
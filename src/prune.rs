@@ -0,0 +1,333 @@
+use crate::jobs::JobManager;
+use crate::workspace::{create_private_dir, dir_size, CustomerDirs};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock as AsyncRwLock};
+use uuid::Uuid;
+
+/// Prune tasks `GET /admin/prune/:id` reports on; past it the oldest finished one is forgotten
+pub const MAX_PRUNE_TASKS: usize = 20;
+
+/// What `POST /admin/prune` removes. Nothing is selected unless asked for.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PruneRequest {
+    /// Workspaces of jobs that finished and are still tracked
+    pub completed_workspaces: bool,
+    /// `job-*` directories no tracked job uses, e.g. left behind by a runner restart
+    pub orphaned_workspaces: bool,
+    /// Build logs kept with `NABLA_KEEP_BUILD_LOGS`
+    pub build_logs: bool,
+    /// Only workspaces and logs last modified longer ago than this. Caches are pruned whole.
+    pub older_than_secs: Option<u64>,
+    pub caches: Vec<CacheKind>,
+    /// Report what would be removed, and how many bytes it takes up, without removing it
+    pub dry_run: bool,
+}
+
+/// A tool cache under [`CustomerDirs::cache_root`], see [`CustomerDirs::cache_env`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheKind {
+    /// Platforms, toolchains and libraries, `PLATFORMIO_CORE_DIR`
+    Platformio,
+    Ccache,
+    /// `XDG_CACHE_HOME`, where west and pip keep their caches
+    #[serde(alias = "west")]
+    Xdg,
+}
+
+impl CacheKind {
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            CacheKind::Platformio => "platformio",
+            CacheKind::Ccache => "ccache",
+            CacheKind::Xdg => "xdg",
+        }
+    }
+}
+
+/// Why an entry was selected for pruning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneKind {
+    CompletedWorkspace,
+    OrphanedWorkspace,
+    BuildLog,
+    Cache,
+}
+
+/// A directory or file selected for pruning
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PruneEntry {
+    pub path: PathBuf,
+    pub kind: PruneKind,
+    pub bytes: u64,
+}
+
+/// An entry left in place, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedEntry {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// What a prune request selected
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PrunePlan {
+    pub entries: Vec<PruneEntry>,
+    /// Matching entries a queued or running job, or a running build, is using
+    pub skipped: Vec<SkippedEntry>,
+}
+
+impl PrunePlan {
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+
+    fn skip(&mut self, path: PathBuf, reason: &str) {
+        self.skipped.push(SkippedEntry { path, reason: reason.to_string() });
+    }
+}
+
+/// The workspaces and logs of tracked jobs, by whether the job has finished
+#[derive(Debug, Clone, Default)]
+pub struct TrackedPaths {
+    /// Used by a queued or running job
+    pub active: HashSet<PathBuf>,
+    /// Used only by finished jobs
+    pub finished: HashSet<PathBuf>,
+}
+
+impl TrackedPaths {
+    pub fn of(jobs: &JobManager) -> Self {
+        let mut paths = Self::default();
+        for job in jobs.iter() {
+            match job.is_finished() {
+                true => paths.finished.extend(job.files.iter().cloned()),
+                false => paths.active.extend(job.files.iter().cloned()),
+            }
+        }
+        // A job reusing an earlier one's client `job_id` reuses its workspace too
+        let active = &paths.active;
+        paths.finished.retain(|path| !active.contains(path));
+        paths
+    }
+}
+
+/// Held shared by each build while it runs and exclusively while a cache is pruned, so no
+/// cache is removed from under a build using it
+#[derive(Debug, Clone, Default)]
+pub struct CacheLock(Arc<AsyncRwLock<()>>);
+
+impl CacheLock {
+    /// Wait until no cache is being pruned, then keep caches from being pruned until the guard
+    /// is dropped
+    pub async fn hold(&self) -> OwnedRwLockReadGuard<()> {
+        self.0.clone().read_owned().await
+    }
+
+    /// Whether a build holds the caches right now
+    pub fn in_use(&self) -> bool {
+        self.0.try_write().is_err()
+    }
+
+    fn try_exclusive(&self) -> Option<OwnedRwLockWriteGuard<()>> {
+        self.0.clone().try_write_owned().ok()
+    }
+}
+
+/// Select what `request` asks to prune in `dirs`, with the bytes each entry takes up. The
+/// workspaces and logs of queued or running jobs are skipped, as are caches while a build runs
+/// or when they're shared across customers, since other runners' builds may be using them.
+pub async fn plan(dirs: &CustomerDirs, request: &PruneRequest, tracked: &TrackedPaths, caches: &CacheLock) -> PrunePlan {
+    let cutoff = request
+        .older_than_secs
+        .and_then(|secs| SystemTime::now().checked_sub(Duration::from_secs(secs)));
+    let mut plan = PrunePlan::default();
+
+    if request.completed_workspaces || request.orphaned_workspaces {
+        for dir in entries(dirs.root(), |name, is_dir| is_dir && name.starts_with("job-")).await {
+            if tracked.active.contains(&dir) {
+                plan.skip(dir, "a queued or running job uses it");
+                continue;
+            }
+            let (kind, wanted) = match tracked.finished.contains(&dir) {
+                true => (PruneKind::CompletedWorkspace, request.completed_workspaces),
+                false => (PruneKind::OrphanedWorkspace, request.orphaned_workspaces),
+            };
+            if wanted && modified_before(&dir, cutoff).await {
+                let bytes = dir_size(&dir).await;
+                plan.entries.push(PruneEntry { path: dir, kind, bytes });
+            }
+        }
+    }
+
+    if request.build_logs {
+        for log in entries(&dirs.root().join("logs"), |name, is_dir| !is_dir && name.ends_with(".log")).await {
+            if tracked.active.contains(&log) {
+                plan.skip(log, "the job writing it is running");
+            } else if modified_before(&log, cutoff).await {
+                let bytes = fs::metadata(&log).await.map_or(0, |metadata| metadata.len());
+                plan.entries.push(PruneEntry { path: log, kind: PruneKind::BuildLog, bytes });
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for &kind in request.caches.iter().filter(|&&kind| seen.insert(kind)) {
+        let dir = dirs.cache_root().join(kind.dir_name());
+        if !fs::metadata(&dir).await.is_ok_and(|metadata| metadata.is_dir()) {
+            continue;
+        }
+        if dirs.shares_caches() {
+            plan.skip(dir, "it is shared with other customers' runners");
+            continue;
+        }
+        if caches.in_use() {
+            plan.skip(dir, "a running build uses it");
+            continue;
+        }
+        let bytes = dir_size(&dir).await;
+        plan.entries.push(PruneEntry { path: dir, kind: PruneKind::Cache, bytes });
+    }
+    plan
+}
+
+/// Entries of `dir` that `wanted` accepts by name and whether they're directories, sorted
+async fn entries(dir: &Path, wanted: impl Fn(&str, bool) -> bool) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let Ok(mut listing) = fs::read_dir(dir).await else {
+        return found;
+    };
+    while let Ok(Some(entry)) = listing.next_entry().await {
+        let is_dir = entry.file_type().await.is_ok_and(|file_type| file_type.is_dir());
+        if wanted(&entry.file_name().to_string_lossy(), is_dir) {
+            found.push(entry.path());
+        }
+    }
+    found.sort();
+    found
+}
+
+async fn modified_before(path: &Path, cutoff: Option<SystemTime>) -> bool {
+    let Some(cutoff) = cutoff else {
+        return true;
+    };
+    match fs::metadata(path).await.and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified <= cutoff,
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneStatus {
+    Running,
+    Completed,
+}
+
+/// How far a prune has got, as `GET /admin/prune/:id` reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PruneProgress {
+    pub task_id: Uuid,
+    pub status: PruneStatus,
+    pub planned_entries: usize,
+    pub planned_bytes: u64,
+    pub removed_entries: usize,
+    pub freed_bytes: u64,
+    /// Entries left in place: in use when the prune was planned or by the time it got to
+    /// them, or failing to be removed
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// Prunes started with `POST /admin/prune`, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct PruneTasks(Arc<Mutex<VecDeque<PruneProgress>>>);
+
+impl PruneTasks {
+    /// Start tracking a prune of `plan`, returning its task id
+    pub fn register(&self, plan: &PrunePlan) -> Uuid {
+        let task_id = Uuid::new_v4();
+        let mut tasks = self.0.lock();
+        tasks.push_back(PruneProgress {
+            task_id,
+            status: PruneStatus::Running,
+            planned_entries: plan.entries.len(),
+            planned_bytes: plan.total_bytes(),
+            removed_entries: 0,
+            freed_bytes: 0,
+            skipped: plan.skipped.clone(),
+        });
+        while tasks.len() > MAX_PRUNE_TASKS {
+            match tasks.iter().position(|task| task.status == PruneStatus::Completed) {
+                Some(oldest) => tasks.remove(oldest),
+                None => break,
+            };
+        }
+        task_id
+    }
+
+    pub fn get(&self, task_id: Uuid) -> Option<PruneProgress> {
+        self.0.lock().iter().find(|task| task.task_id == task_id).cloned()
+    }
+
+    fn update(&self, task_id: Uuid, update: impl FnOnce(&mut PruneProgress)) {
+        if let Some(task) = self.0.lock().iter_mut().find(|task| task.task_id == task_id) {
+            update(task);
+        }
+    }
+}
+
+/// Remove what `plan` selected, recording progress on `task_id`. Each entry is checked again
+/// first: a workspace or log a job started using since the plan was made, or a cache while a
+/// build runs, is skipped. Caches are emptied rather than removed.
+pub async fn execute(plan: PrunePlan, task_id: Uuid, tasks: &PruneTasks, jobs: &RwLock<JobManager>, caches: &CacheLock) {
+    for entry in plan.entries {
+        let outcome = match entry.kind {
+            PruneKind::Cache => match caches.try_exclusive() {
+                Some(_exclusive) => clear_cache(&entry.path).await.map_err(|e| e.to_string()),
+                None => Err("a running build uses it".to_string()),
+            },
+            _ if used_by_active_job(jobs, &entry.path) => Err("a job started using it".to_string()),
+            _ => remove(&entry.path).await.map_err(|e| e.to_string()),
+        };
+        match &outcome {
+            Ok(()) => tracing::info!("Pruned {} ({} bytes)", entry.path.display(), entry.bytes),
+            Err(reason) => tracing::warn!("Did not prune {}: {}", entry.path.display(), reason),
+        }
+        tasks.update(task_id, |task| match outcome {
+            Ok(()) => {
+                task.removed_entries += 1;
+                task.freed_bytes += entry.bytes;
+            }
+            Err(reason) => task.skipped.push(SkippedEntry { path: entry.path, reason }),
+        });
+    }
+    tasks.update(task_id, |task| task.status = PruneStatus::Completed);
+}
+
+fn used_by_active_job(jobs: &RwLock<JobManager>, path: &Path) -> bool {
+    let jobs = jobs.read().unwrap();
+    TrackedPaths::of(&jobs).active.contains(path)
+}
+
+async fn remove(path: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).await,
+        Ok(_) => fs::remove_file(path).await,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Empty a cache directory, leaving it in place for the next build
+async fn clear_cache(dir: &Path) -> anyhow::Result<()> {
+    remove(dir).await?;
+    create_private_dir(dir).await
+}
@@ -0,0 +1,152 @@
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Resource limits applied to each customer. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuotaLimits {
+    pub max_concurrent_jobs: Option<usize>,
+    pub max_workspace_bytes: Option<u64>,
+}
+
+impl QuotaLimits {
+    pub fn from_env() -> Self {
+        Self {
+            max_concurrent_jobs: env::var("CUSTOMER_MAX_CONCURRENT_JOBS").ok().and_then(|v| v.parse().ok()),
+            max_workspace_bytes: env::var("CUSTOMER_MAX_WORKSPACE_BYTES").ok().and_then(|v| v.parse().ok()),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QuotaError {
+    #[error("customer {customer_id} already has {active} running job(s) (limit {limit})")]
+    ConcurrentJobs { customer_id: String, active: usize, limit: usize },
+    #[error("customer {customer_id} workspaces use {used} bytes (quota {limit} bytes)")]
+    DiskQuota { customer_id: String, used: u64, limit: u64 },
+}
+
+/// Tracks running jobs per customer and hands out guards that release the slot on drop.
+#[derive(Debug, Clone, Default)]
+pub struct QuotaTracker {
+    limits: QuotaLimits,
+    active_jobs: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl QuotaTracker {
+    pub fn new(limits: QuotaLimits) -> Self {
+        Self {
+            limits,
+            active_jobs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Reserve a job slot for `customer_id`, given the bytes its workspaces currently occupy.
+    pub fn try_acquire(&self, customer_id: &str, workspace_bytes: u64) -> Result<QuotaGuard, QuotaError> {
+        if let Some(limit) = self.limits.max_workspace_bytes {
+            if workspace_bytes >= limit {
+                return Err(QuotaError::DiskQuota {
+                    customer_id: customer_id.to_string(),
+                    used: workspace_bytes,
+                    limit,
+                });
+            }
+        }
+
+        let mut active_jobs = self.active_jobs.lock();
+        let active = active_jobs.entry(customer_id.to_string()).or_insert(0);
+        if let Some(limit) = self.limits.max_concurrent_jobs {
+            if *active >= limit {
+                return Err(QuotaError::ConcurrentJobs {
+                    customer_id: customer_id.to_string(),
+                    active: *active,
+                    limit,
+                });
+            }
+        }
+        *active += 1;
+
+        Ok(QuotaGuard {
+            customer_id: customer_id.to_string(),
+            active_jobs: self.active_jobs.clone(),
+        })
+    }
+
+    pub fn active_jobs(&self, customer_id: &str) -> usize {
+        self.active_jobs.lock().get(customer_id).copied().unwrap_or(0)
+    }
+}
+
+/// Releases the customer's job slot when dropped.
+#[derive(Debug)]
+pub struct QuotaGuard {
+    customer_id: String,
+    active_jobs: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Drop for QuotaGuard {
+    fn drop(&mut self) {
+        let mut active_jobs = self.active_jobs.lock();
+        if let Some(active) = active_jobs.get_mut(&self.customer_id) {
+            *active = active.saturating_sub(1);
+        }
+    }
+}
+
+const DEFAULT_DETECT_RATE_LIMIT: usize = 30;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("more than {limit} requests in {}s; retry in {}s", .window.as_secs(), .retry_after.as_secs().max(1))]
+pub struct RateLimited {
+    pub limit: usize,
+    pub window: Duration,
+    pub retry_after: Duration,
+}
+
+/// Caps requests per rolling window, for endpoints cheap enough that callers could flood them
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    recent: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            recent: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// `/detect` requests per minute, from `NABLA_DETECT_RATE_LIMIT`
+    pub fn detect_from_env() -> Self {
+        let limit = env::var("NABLA_DETECT_RATE_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_DETECT_RATE_LIMIT);
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    pub fn try_acquire(&self) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut recent = self.recent.lock();
+        while recent.front().is_some_and(|at| now.duration_since(*at) >= self.window) {
+            recent.pop_front();
+        }
+        if recent.len() >= self.limit {
+            let oldest = recent.front().copied().unwrap_or(now);
+            return Err(RateLimited {
+                limit: self.limit,
+                window: self.window,
+                retry_after: self.window.saturating_sub(now.duration_since(oldest)),
+            });
+        }
+        recent.push_back(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,412 @@
+use crate::archive::{contains_file, extract_archive, max_repo_files, ArchiveFormat, TooManyFiles};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Path depth mirrored from the repository tree: a wrapping folder, plus Yocto recipes up to
+/// three directories below a layer
+const SKELETON_DEPTH: usize = 5;
+
+/// Files whose contents detection and dry-run checks read. Everything else in the skeleton is
+/// left empty.
+const CONTENT_FILES: &[&str] = &["Makefile", "makefile", "CMakeLists.txt", "platformio.ini", "west.yml"];
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sent with every request the runner makes
+const USER_AGENT: &str = "nabla-runner/0.1.0";
+
+/// How long an idle connection stays pooled for the next fetch from the same host
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// The HTTP client every fetch shares, so connections and TLS sessions to a host are reused
+/// from one build to the next. Redirects aren't followed: [`download_archive`] follows them
+/// itself. Connecting is bounded by [`fetch_timeout`].
+pub fn http_client() -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .redirect(reqwest::redirect::Policy::none())
+        .connect_timeout(fetch_timeout())
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .build()?)
+}
+
+/// The repository and ref behind a GitHub archive URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GithubArchive {
+    pub owner: String,
+    pub repo: String,
+    /// Branch, tag or commit; `None` means the default branch
+    pub reference: Option<String>,
+}
+
+impl GithubArchive {
+    /// Recognize `github.com/<owner>/<repo>/archive/<ref>.tar.gz` (or `.zip`),
+    /// `codeload.github.com/<owner>/<repo>/tar.gz/<ref>` and
+    /// `api.github.com/repos/<owner>/<repo>/tarball/<ref>` URLs
+    pub fn parse(archive_url: &str) -> Option<Self> {
+        let url = reqwest::Url::parse(archive_url).ok()?;
+        let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+        let (owner, repo, reference) = match (url.host_str()?, segments.as_slice()) {
+            ("github.com", [owner, repo, "archive", rest @ ..]) => {
+                let path = rest.join("/");
+                let reference = path.strip_suffix(".tar.gz").or_else(|| path.strip_suffix(".zip"))?;
+                (owner, repo, Some(reference.to_string()))
+            }
+            ("codeload.github.com", [owner, repo, "tar.gz" | "zip" | "legacy.tar.gz" | "legacy.zip", rest @ ..])
+            | ("api.github.com", ["repos", owner, repo, "tarball" | "zipball", rest @ ..]) => {
+                (owner, repo, (!rest.is_empty()).then(|| rest.join("/")))
+            }
+            _ => return None,
+        };
+
+        let reference = reference.map(|reference| {
+            reference
+                .strip_prefix("refs/heads/")
+                .or_else(|| reference.strip_prefix("refs/tags/"))
+                .unwrap_or(reference.as_str())
+                .to_string()
+        });
+        if reference.as_deref() == Some("") {
+            return None;
+        }
+        Some(Self {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            reference,
+        })
+    }
+}
+
+impl fmt::Display for GithubArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.owner, self.repo)?;
+        if let Some(reference) = &self.reference {
+            write!(f, "@{}", reference)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct Tree {
+    tree: Vec<TreeEntry>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// The GitHub REST API, used to look at a repository without downloading its archive
+#[derive(Debug, Clone)]
+pub struct GithubApi {
+    pub base_url: String,
+    /// Sent as a bearer token; needed for private repositories and higher rate limits
+    pub token: Option<String>,
+}
+
+impl GithubApi {
+    pub fn from_env() -> Self {
+        Self {
+            base_url: env::var("NABLA_GITHUB_API_URL").unwrap_or_else(|_| DEFAULT_API_URL.to_string()),
+            token: env::var("NABLA_GITHUB_TOKEN").ok().filter(|token| !token.is_empty()),
+        }
+    }
+
+    /// Recreate the repository's layout under `dest` from one tree listing: directories and
+    /// empty files down to a fixed depth, with the few build files detection reads fetched
+    /// in full. Detection and dry-run checks then run on `dest` as on a full checkout.
+    /// Fails, leaving `dest` partly written, when the API can't list the tree or GitHub
+    /// truncated the listing.
+    pub async fn fetch_skeleton(&self, client: &reqwest::Client, archive: &GithubArchive, dest: &Path) -> Result<()> {
+        let reference = archive.reference.as_deref().unwrap_or("HEAD");
+        let url = format!(
+            "{}/repos/{}/{}/git/trees/{}?recursive=1",
+            self.base_url.trim_end_matches('/'),
+            archive.owner,
+            archive.repo,
+            reference
+        );
+        let response = self.get(client, &url, "application/vnd.github+json").send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to list {}: HTTP {}", archive, response.status()));
+        }
+        let tree: Tree = serde_json::from_slice(&response.bytes().await?)?;
+        if tree.truncated {
+            return Err(anyhow!("The file listing of {} is truncated", archive));
+        }
+
+        fs::create_dir_all(dest).await?;
+        for entry in &tree.tree {
+            let path = Path::new(&entry.path);
+            let components = path.components().count();
+            if components > SKELETON_DEPTH || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+                continue;
+            }
+            match entry.kind.as_str() {
+                // Submodules come through as commits; they're directories in the archive
+                "tree" | "commit" => fs::create_dir_all(dest.join(path)).await?,
+                "blob" => {
+                    if let Some(parent) = dest.join(path).parent() {
+                        fs::create_dir_all(parent).await?;
+                    }
+                    fs::write(dest.join(path), b"").await?;
+                }
+                _ => {}
+            }
+        }
+
+        let build_dir = crate::detection::nested_project_root(dest).await.unwrap_or_else(|| dest.to_path_buf());
+        for name in CONTENT_FILES {
+            let file = build_dir.join(name);
+            if !file.is_file() {
+                continue;
+            }
+            let relative = file.strip_prefix(dest)?.to_string_lossy().to_string();
+            let mut url = format!(
+                "{}/repos/{}/{}/contents/{}",
+                self.base_url.trim_end_matches('/'),
+                archive.owner,
+                archive.repo,
+                relative
+            );
+            if let Some(reference) = &archive.reference {
+                url.push_str(&format!("?ref={}", reference));
+            }
+            let response = self.get(client, &url, "application/vnd.github.raw").send().await?;
+            if !response.status().is_success() {
+                return Err(anyhow!("Failed to fetch {} from {}: HTTP {}", relative, archive, response.status()));
+            }
+            fs::write(&file, response.bytes().await?).await?;
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, client: &reqwest::Client, url: &str, accept: &str) -> reqwest::RequestBuilder {
+        let request = client.get(url).timeout(REQUEST_TIMEOUT).header("Accept", accept);
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+/// Redirects an archive download follows, e.g. from a forge's API to its storage host
+const MAX_ARCHIVE_REDIRECTS: usize = 3;
+
+const DEFAULT_FETCH_TIMEOUT_SECS: u64 = 60;
+
+/// `NABLA_FETCH_TIMEOUT_SECS`: how long an archive download may wait to connect, for a
+/// response, or for the next piece of the body. A slow download that keeps receiving data is
+/// never cut off; a stalled one is.
+pub fn fetch_timeout() -> Duration {
+    let secs = env::var("NABLA_FETCH_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(DEFAULT_FETCH_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// How an archive download presents its credential in the `Authorization` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <credential>`, e.g. GitLab or GitHub fine-grained tokens
+    Bearer,
+    /// `Authorization: token <credential>`, GitHub's classic form
+    #[serde(rename = "token")]
+    Token,
+}
+
+/// The `Authorization` header of an archive download. The credential is never printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ArchiveAuthorization {
+    scheme: AuthScheme,
+    credential: String,
+}
+
+impl ArchiveAuthorization {
+    pub fn new(scheme: AuthScheme, credential: impl Into<String>) -> Self {
+        Self { scheme, credential: credential.into() }
+    }
+
+    fn header_value(&self) -> String {
+        match self.scheme {
+            AuthScheme::Bearer => format!("Bearer {}", self.credential),
+            AuthScheme::Token => format!("token {}", self.credential),
+        }
+    }
+
+    /// Replace the credential in `text` with `***`
+    pub fn redact(&self, text: &str) -> String {
+        match self.credential.is_empty() {
+            true => text.to_string(),
+            false => text.replace(&self.credential, "***"),
+        }
+    }
+}
+
+impl fmt::Debug for ArchiveAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveAuthorization")
+            .field("scheme", &self.scheme)
+            .field("credential", &"***")
+            .finish()
+    }
+}
+
+/// Leading bytes of a download kept to show what a host sent instead of an archive
+const ARCHIVE_HEAD_BYTES: usize = 16;
+
+/// What the host answered an archive download with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// The first [`ARCHIVE_HEAD_BYTES`] bytes of the body
+    pub head: Vec<u8>,
+}
+
+/// A download that succeeded but isn't a usable repository archive, e.g. an HTML error page
+/// served with 200 for an expired link. Carries what the host sent so the control plane can
+/// tell a stale link, which it should regenerate, from a broken repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, thiserror::Error)]
+#[error(
+    "Failed to fetch repository archive: {reason} (HTTP {status}, content-type {}, first bytes {first_bytes})",
+    .content_type.as_deref().unwrap_or("unknown")
+)]
+pub struct ArchiveFetchError {
+    pub reason: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// Leading bytes of the body in hex
+    pub first_bytes: String,
+}
+
+impl ArchiveFetchError {
+    pub fn new(reason: impl Into<String>, response: &ArchiveResponse) -> Self {
+        Self {
+            reason: reason.into(),
+            status: response.status,
+            content_type: response.content_type.clone(),
+            first_bytes: response.head.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        }
+    }
+}
+
+/// Download the repository archive at `url` and extract it into `workspace/repo`, dropping
+/// the top-level directory GitHub wraps archives in. A download that isn't a zip or tar.gz,
+/// fails to extract (e.g. truncated) or holds no files is an [`ArchiveFetchError`].
+pub async fn fetch_repository(
+    client: &reqwest::Client,
+    url: &str,
+    authorization: Option<&ArchiveAuthorization>,
+    workspace: &Path,
+) -> Result<PathBuf> {
+    let archive = workspace.join("temp_repo.tar.gz");
+    let response = download_archive(client, url, authorization, &archive).await?;
+    let repo_dir = workspace.join("repo");
+    let extracted = match ArchiveFormat::sniff(&response.head) {
+        None => Err(ArchiveFetchError::new("the download is not a zip or tar.gz archive", &response).into()),
+        Some(_) => match extract_archive(&archive, &repo_dir, 1, max_repo_files()).await {
+            Ok(()) if !contains_file(&repo_dir) => Err(ArchiveFetchError::new("the archive holds no files", &response).into()),
+            Ok(()) => Ok(repo_dir),
+            Err(e) if e.is::<TooManyFiles>() => Err(e),
+            Err(e) => Err(ArchiveFetchError::new(format!("the archive could not be extracted: {}", e.to_string().trim()), &response).into()),
+        },
+    };
+    let _ = fs::remove_file(&archive).await;
+    extracted
+}
+
+/// Download the archive at `url` to `dest`, sending `authorization` when given. Up to three
+/// redirects are followed; the header is dropped once a redirect leaves the original
+/// scheme, host and port, so storage hosts serving signed URLs never see the credential.
+/// A host that goes quiet for [`fetch_timeout`] fails the download. `client` is the runner's
+/// shared [`http_client`].
+pub async fn download_archive(
+    client: &reqwest::Client,
+    url: &str,
+    authorization: Option<&ArchiveAuthorization>,
+    dest: &Path,
+) -> Result<ArchiveResponse> {
+    let redact = |e: anyhow::Error| match authorization {
+        Some(authorization) => anyhow!("{}", authorization.redact(&e.to_string())),
+        None => e,
+    };
+    let timeout = fetch_timeout();
+    let stalled = |waiting_for: &str| {
+        anyhow!(
+            "Failed to fetch repository archive: timed out after {}s waiting for {} (NABLA_FETCH_TIMEOUT_SECS)",
+            timeout.as_secs(),
+            waiting_for
+        )
+    };
+    let mut url = reqwest::Url::parse(url).map_err(|e| redact(e.into()))?;
+    let origin = url.origin();
+    let mut redirects = 0;
+    let mut response = loop {
+        let mut request = client.get(url.clone());
+        if let Some(authorization) = authorization.filter(|_| url.origin() == origin) {
+            request = request.header("Authorization", authorization.header_value());
+        }
+        let response = tokio::time::timeout(timeout, request.send())
+            .await
+            .map_err(|_| stalled("a response"))?
+            .map_err(|e| match e.is_connect() && e.is_timeout() {
+                true => stalled("a connection"),
+                false => redact(e.into()),
+            })?;
+        if !response.status().is_redirection() {
+            break response;
+        }
+        if redirects == MAX_ARCHIVE_REDIRECTS {
+            return Err(anyhow!("Failed to fetch repository archive: more than {} redirects", MAX_ARCHIVE_REDIRECTS));
+        }
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| anyhow!("Failed to fetch repository archive: HTTP {} without a Location", response.status()))?;
+        url = url.join(location).map_err(|e| redact(e.into()))?;
+        redirects += 1;
+    };
+
+    if !response.status().is_success() {
+        return Err(anyhow!("Failed to fetch repository archive: HTTP {}", response.status()));
+    }
+    let mut received = ArchiveResponse {
+        status: response.status().as_u16(),
+        content_type: response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        head: Vec::new(),
+    };
+    let mut file = fs::File::create(dest).await?;
+    while let Some(chunk) = tokio::time::timeout(timeout, response.chunk())
+        .await
+        .map_err(|_| stalled("more of the archive"))?
+        .map_err(|e| redact(e.into()))?
+    {
+        let wanted = ARCHIVE_HEAD_BYTES.saturating_sub(received.head.len()).min(chunk.len());
+        received.head.extend_from_slice(&chunk[..wanted]);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(received)
+}
@@ -0,0 +1,58 @@
+use crate::core::{S3Object, S3Target};
+use anyhow::{anyhow, Result};
+use aws_sdk_s3::primitives::ByteStream;
+use std::path::Path;
+use tracing::info;
+
+/// Upload `artifact` to `target`, under `target.key` followed by `name` when the key ends in
+/// `/`; the artifact's name on disk is kept in the object's `original-filename` metadata. The
+/// endpoint and credentials come from the standard AWS configuration; a custom
+/// `AWS_ENDPOINT_URL` switches to path-style addressing, which S3-compatible stores (MinIO, R2,
+/// Ceph) expect.
+pub async fn upload_artifact(target: &S3Target, artifact: &Path, name: &str) -> Result<S3Object> {
+    let key = match target.key.ends_with('/') {
+        true => format!("{}{}", target.key, name),
+        false => target.key.clone(),
+    };
+    let original_filename = artifact.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut loader = aws_config::from_env();
+    if let Some(region) = &target.region {
+        loader = loader.region(aws_config::Region::new(region.clone()));
+    }
+    let shared = loader.load().await;
+
+    let custom_endpoint = shared.endpoint_url().map(|url| url.trim_end_matches('/').to_string());
+    let config = aws_sdk_s3::config::Builder::from(&shared)
+        .force_path_style(custom_endpoint.is_some())
+        .build();
+    let client = aws_sdk_s3::Client::from_conf(config);
+
+    let body = ByteStream::from_path(artifact)
+        .await
+        .map_err(|e| anyhow!("Failed to read artifact {}: {}", artifact.display(), e))?;
+    client
+        .put_object()
+        .bucket(&target.bucket)
+        .key(&key)
+        .metadata("original-filename", original_filename)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| anyhow!("S3 upload to s3://{}/{} failed: {}", target.bucket, key, e))?;
+
+    let url = match custom_endpoint {
+        Some(endpoint) => format!("{}/{}/{}", endpoint, target.bucket, key),
+        None => {
+            let region = shared.region().map(|r| r.to_string()).unwrap_or_else(|| "us-east-1".to_string());
+            format!("https://{}.s3.{}.amazonaws.com/{}", target.bucket, region, key)
+        }
+    };
+    info!("Uploaded artifact to {}", url);
+
+    Ok(S3Object {
+        bucket: target.bucket.clone(),
+        key,
+        url,
+    })
+}
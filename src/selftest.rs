@@ -0,0 +1,209 @@
+use crate::core::{BuildConfig, BuildSystem};
+use crate::execution::execute_build_with_config;
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tracing::{error, info};
+use uuid::Uuid;
+
+const DEFAULT_SELF_TEST_TIMEOUT_SECS: u64 = 600;
+
+/// A blinky that toggles a fake LED register; compiled by the self-test, never run
+const BLINKY_C: &str = "static volatile unsigned int led;\n\nint main(void) {\n    for (int i = 0; i < 10; i++) {\n        led ^= 1u;\n    }\n    return 0;\n}\n";
+
+/// The names `SELF_TEST` accepts and the build system each tests
+const SELF_TEST_NAMES: &[(&str, BuildSystem)] = &[
+    ("makefile", BuildSystem::Makefile),
+    ("make", BuildSystem::Makefile),
+    ("cmake", BuildSystem::CMake),
+    ("platformio", BuildSystem::PlatformIO),
+    ("pio", BuildSystem::PlatformIO),
+    ("cargo", BuildSystem::Cargo),
+];
+
+/// The files of the minimal project the self-test builds with `system`, all compiled with
+/// the host toolchain so no cross compiler is needed to pass
+fn fixture(system: BuildSystem) -> Vec<(&'static str, &'static str)> {
+    match system {
+        BuildSystem::Makefile => vec![("Makefile", "firmware: main.c\n\t$(CC) -Os -o $@ main.c\n"), ("main.c", BLINKY_C)],
+        BuildSystem::CMake => vec![
+            ("CMakeLists.txt", "cmake_minimum_required(VERSION 3.13)\nproject(blinky C)\nadd_executable(firmware main.c)\n"),
+            ("main.c", BLINKY_C),
+        ],
+        BuildSystem::PlatformIO => vec![("platformio.ini", "[env:native]\nplatform = native\n"), ("src/main.c", BLINKY_C)],
+        BuildSystem::Cargo => vec![
+            ("Cargo.toml", "[package]\nname = \"blinky\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n"),
+            ("src/main.rs", "fn main() {\n    let mut led = false;\n    for _ in 0..10 {\n        led = !led;\n    }\n    std::hint::black_box(led);\n}\n"),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// The build systems in a comma-separated `SELF_TEST` list, e.g. `platformio,cmake`
+pub fn parse_systems(list: &str) -> Result<Vec<BuildSystem>> {
+    let mut systems = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let system = SELF_TEST_NAMES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|&(_, system)| system)
+            .ok_or_else(|| anyhow!("Unknown SELF_TEST build system '{}' - expected makefile, cmake, platformio or cargo", name))?;
+        if !systems.contains(&system) {
+            systems.push(system);
+        }
+    }
+    Ok(systems)
+}
+
+/// The build systems `SELF_TEST` asks to test at startup; none when it is unset
+pub fn systems_from_env() -> Result<Vec<BuildSystem>> {
+    parse_systems(&env::var("SELF_TEST").unwrap_or_default())
+}
+
+/// How one build system's self-test went
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SelfTestResult {
+    pub build_system: BuildSystem,
+    pub ok: bool,
+    /// Why the build failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Build the fixture project for `system` in a scratch directory through the normal build
+/// path, removing the directory afterwards
+pub async fn run_self_test(system: BuildSystem, config: &BuildConfig) -> SelfTestResult {
+    let started = Instant::now();
+    let dir = env::temp_dir().join(format!("nabla-self-test-{}", Uuid::new_v4()));
+    let outcome = async {
+        for (file, contents) in fixture(system) {
+            let path = dir.join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(path, contents).await?;
+        }
+        execute_build_with_config(&dir, system, config).await
+    }
+    .await;
+    let _ = fs::remove_dir_all(&dir).await;
+
+    SelfTestResult {
+        build_system: system,
+        ok: outcome.is_ok(),
+        detail: outcome.err().map(|e| e.to_string()),
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+/// Self-test every system in `systems` at once; those still building after `timeout` fail
+pub async fn run_self_tests(systems: &[BuildSystem], config: &BuildConfig, timeout: Duration) -> Vec<SelfTestResult> {
+    let tests = systems.iter().map(|&system| async move {
+        match tokio::time::timeout(timeout, run_self_test(system, config)).await {
+            Ok(result) => result,
+            Err(_) => SelfTestResult {
+                build_system: system,
+                ok: false,
+                detail: Some(format!("did not finish within {}s", timeout.as_secs())),
+                duration_ms: timeout.as_millis() as u64,
+            },
+        }
+    });
+    futures::future::join_all(tests).await
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requested: Vec<BuildSystem>,
+    /// Empty until every requested system has been tested
+    results: Vec<SelfTestResult>,
+}
+
+/// The startup self-test, shared by the server's handlers. It runs in the background so the
+/// server answers `/health` meanwhile; `/ready` waits for it.
+#[derive(Clone, Default)]
+pub struct SelfTest {
+    state: Arc<RwLock<State>>,
+    /// Failed systems keep the runner unready, rather than only being logged
+    blocks_readiness: bool,
+}
+
+impl SelfTest {
+    /// Start testing `systems` in the background; must be called within a Tokio runtime.
+    pub fn start(systems: Vec<BuildSystem>, config: BuildConfig, timeout: Duration, blocks_readiness: bool) -> Self {
+        let self_test = Self {
+            state: Arc::new(RwLock::new(State { requested: systems.clone(), results: Vec::new() })),
+            blocks_readiness,
+        };
+        if systems.is_empty() {
+            return self_test;
+        }
+
+        let state = self_test.state.clone();
+        tokio::spawn(async move {
+            info!("Self-testing {:?}", systems);
+            let results = run_self_tests(&systems, &config, timeout).await;
+            for result in &results {
+                match &result.detail {
+                    None => info!("Self-test of {:?} passed in {}ms", result.build_system, result.duration_ms),
+                    Some(detail) => error!("Self-test of {:?} FAILED: {}", result.build_system, detail),
+                }
+            }
+            state.write().results = results;
+        });
+        self_test
+    }
+
+    /// Configure from `SELF_TEST`, `SELF_TEST_TIMEOUT_SECS` (default 600) and
+    /// `SELF_TEST_ON_FAILURE`: `unready` (the default) or `log`. An invalid `SELF_TEST` is
+    /// logged and nothing is tested; [`systems_from_env`] reports it at startup.
+    pub fn from_env() -> Self {
+        let systems = systems_from_env().unwrap_or_else(|e| {
+            error!("{}", e);
+            Vec::new()
+        });
+        let timeout = env::var("SELF_TEST_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&secs| secs > 0)
+            .unwrap_or(DEFAULT_SELF_TEST_TIMEOUT_SECS);
+        let blocks_readiness = env::var("SELF_TEST_ON_FAILURE").as_deref() != Ok("log");
+        Self::start(systems, BuildConfig::default(), Duration::from_secs(timeout), blocks_readiness)
+    }
+
+    /// The build systems being tested
+    pub fn requested(&self) -> Vec<BuildSystem> {
+        self.state.read().requested.clone()
+    }
+
+    /// Every result, once the self-test has finished
+    pub fn results(&self) -> Vec<SelfTestResult> {
+        self.state.read().results.clone()
+    }
+
+    pub fn running(&self) -> bool {
+        let state = self.state.read();
+        !state.requested.is_empty() && state.results.is_empty()
+    }
+
+    /// Why the self-test keeps the runner from being ready: still running, or a system failed
+    /// when failures block readiness
+    pub fn not_ready_reasons(&self) -> Vec<String> {
+        if self.running() {
+            return vec!["startup self-test is still running".to_string()];
+        }
+        if !self.blocks_readiness {
+            return Vec::new();
+        }
+        self.results()
+            .into_iter()
+            .filter(|result| !result.ok)
+            .map(|result| format!("self-test of {:?} failed: {}", result.build_system, result.detail.unwrap_or_default()))
+            .collect()
+    }
+}
@@ -1,53 +1,306 @@
 use anyhow::{anyhow, Result};
 use axum::{
-    extract::{Json as JsonExtract, State},
-    http::StatusCode,
-    response::Json,
+    extract::{DefaultBodyLimit, FromRequest, Json as JsonExtract, Multipart, Query, Request, State},
+    extract::multipart::{Field, MultipartError},
+    http::{header::{AUTHORIZATION, CONTENT_RANGE, CONTENT_TYPE, WARNING}, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
     routing::{get, post},
     Router,
 };
-use crate::{detection, execution, jobs::{BuildJob, SingleJobManager}};
+use crate::{core::{build_config_schema, check_build_config, render_artifact_name, Artifact, BuildAttempt, DEFAULT_ARTIFACT_NAME, BuildConfig, BuildResult, ConfigViolation, BuildSystem, EnvironmentResult, MatrixResult, Provenance, S3Object, SecretEnv, TargetResult, TestSummary}, jobs::{BuildJob, JobAudit, JobEventKind, JobManager}, DryRunReport, FirmwareBuildRunner, RunOptions};
+use crate::cache::CacheStats;
+use crate::container::{ContainerPolicy, HostArch};
+use crate::detection::{BuildFlavor, DetectionReport};
+use crate::diagnostics::Diagnostic;
+use crate::execution::{hooks_allowed, BuildStepFailed};
+use crate::events::{BuildPhase, EventKind, EventPublisher, JobEvents};
+use crate::process::{kill_processes_in, leaked_processes_killed, on_path, ProcessExit};
+use crate::quota::{QuotaError, QuotaLimits, QuotaTracker, RateLimiter};
+use crate::output::{LiveLog, LIVE_LOG_LINES};
+use crate::selftest::SelfTest;
+use crate::prune::{CacheLock, PruneRequest, PruneTasks, TrackedPaths};
+use crate::remote::{fetch_repository, http_client, ArchiveAuthorization, ArchiveFetchError, AuthScheme, GithubApi, GithubArchive};
+use crate::submodules::GitSource;
+use crate::workspace::{create_private_dir, CustomerDirs};
+use crate::archive::{extract_archive, max_repo_files};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
-use tokio::process::Command;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 use std::env;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use base64::Engine;
 
 
 #[derive(Debug, Deserialize, Clone)]
 struct BuildParams {
     job_id: String,
+    /// Required for JSON requests; must be absent when the archive is uploaded as multipart.
+    #[serde(default)]
     archive_url: String,
     owner: String,
     repo: String,
     installation_id: String,
+    #[serde(default)]
+    build_config: Option<serde_json::Value>,
+    /// Fetch, extract, detect and check the runner's capabilities, but don't build
+    #[serde(default)]
+    dry_run: bool,
+    /// Commit the archive was made from, for `{short_sha}` in artifact names; otherwise taken
+    /// from the archive URL's ref when that is a commit
+    #[serde(default)]
+    head_sha: Option<String>,
+    /// Authorization header for downloading a private `archive_url`
+    #[serde(default)]
+    archive_auth: Option<ArchiveAuth>,
+    /// Share the build of an identical request that is already running; `false` always builds.
+    /// See [`request_fingerprint`].
+    #[serde(default)]
+    deduplicate: Option<bool>,
 }
 
-#[derive(Debug, Serialize)]
+/// `archive_auth` of a request: the credential itself, or the name of a `secret_env` entry
+/// holding it. Only used for the download, never kept on the job.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArchiveAuth {
+    scheme: AuthScheme,
+    #[serde(default)]
+    secret_ref: Option<String>,
+    #[serde(default)]
+    value: Option<String>,
+}
+
+impl ArchiveAuth {
+    fn resolve(&self, secrets: &SecretEnv) -> Result<ArchiveAuthorization> {
+        let credential = match (&self.secret_ref, &self.value) {
+            (Some(name), None) => secrets
+                .expose()
+                .get(name)
+                .ok_or_else(|| anyhow!("archive_auth.secret_ref '{}' is not in build_config.secret_env", name))?,
+            (None, Some(value)) => value,
+            _ => return Err(anyhow!("archive_auth needs exactly one of secret_ref and value")),
+        };
+        if credential.is_empty() {
+            return Err(anyhow!("archive_auth credential is empty"));
+        }
+        Ok(ArchiveAuthorization::new(self.scheme, credential.clone()))
+    }
+}
+
+impl std::fmt::Debug for ArchiveAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArchiveAuth")
+            .field("scheme", &self.scheme)
+            .field("secret_ref", &self.secret_ref)
+            .field("value", &self.value.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 struct BuildResponse {
     status: String,
     job_id: Uuid,
     message: String,
+    /// Detected build system; `null` when detection failed or never ran, so clients can tell an
+    /// unbuildable repository from code that doesn't compile
+    build_system: Option<BuildSystem>,
     #[serde(skip_serializing_if = "Option::is_none")]
     artifact_data: Option<String>, // Base64 encoded binary
     #[serde(skip_serializing_if = "Option::is_none")]
     artifact_filename: Option<String>,
+    /// The artifact's name on disk, before `artifact_name` was applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_original_filename: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     build_output: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    artifacts: Vec<ArtifactPayload>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    test_results: Option<TestSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    environments: Vec<EnvironmentResult>,
+    /// Outcome of each `pio_targets` entry per environment
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    targets: Vec<TargetResult>,
+    /// Outcome of each `matrix` entry
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    matrix: Vec<MatrixResult>,
+    /// ccache hits and misses, build directory reuse and cache size of the build
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<CacheStats>,
+    /// Times the build was rerun after a transient error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retries: Option<u32>,
+    /// Wall-clock time of the whole build, every attempt and the backoff between them included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build_ms: Option<u64>,
+    /// Time taken by the attempt whose outcome this is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_attempt_ms: Option<u64>,
+    /// Each attempt's duration and, for those that failed, error
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attempts: Vec<BuildAttempt>,
+    /// Where the artifact was uploaded when `build_config.s3` was set; `artifact_data` is then omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s3_object: Option<S3Object>,
+    /// Pre-flight problems found in the project configuration
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    config_warnings: Vec<String>,
+    /// Network policy and whether isolation was achieved, once a build ran
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+    /// Every schema violation when `build_config` was rejected
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    config_errors: Vec<ConfigViolation>,
+    /// Compiler errors and warnings of a failed build, with file, line and column
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diagnostics: Vec<Diagnostic>,
+    /// Defects `pio_check` found, each with the `tool` that reported it
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    static_analysis: Vec<Diagnostic>,
+    /// stdout of the build's commands, bounded by the build log limit
+    #[serde(skip_serializing_if = "String::is_empty")]
+    stdout: String,
+    /// stderr of the build's commands, bounded the same way
+    #[serde(skip_serializing_if = "String::is_empty")]
+    stderr: String,
+    /// What a `dry_run` request found; nothing was built
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dry_run: Option<DryRunSummary>,
+    /// Exit code of the build command that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    /// Signal that killed the build command that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    /// Identifies the failure across builds; see [`crate::fingerprint`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_fingerprint: Option<String>,
+    /// Times this repository's builds have failed with `failure_fingerprint`, this one included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_occurrences: Option<u64>,
+    /// Set when the archive download wasn't a usable repository archive, with what the host
+    /// sent; the link likely expired and should be regenerated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archive_fetch: Option<ArchiveFetchError>,
+    /// This request joined an identical build instead of running its own; `job_id` is that
+    /// build's
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deduplicated: bool,
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct DryRunSummary {
+    #[serde(flatten)]
+    report: DryRunReport,
+    /// `build_config` after merging the header and body, as the build would use it
+    resolved_config: BuildConfig,
+    /// Mean duration of this repository's recent successful builds on this runner
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_duration_ms: Option<u64>,
+}
+
+/// One encoded artifact in the response, including any produced by post-processors
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactPayload {
+    pub filename: String,
+    pub format: String,
+    /// Base64 of the file's contents
+    pub data: String,
+    #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub metadata: std::collections::BTreeMap<String, String>,
+}
+
+/// Everything a successful pipeline run hands back to the handler
+struct PipelineOutput {
+    log: String,
+    build_system: BuildSystem,
+    /// Absent for test runs, which produce results rather than firmware, and for S3 uploads
+    artifact_data: Option<String>,
+    artifact_filename: Option<String>,
+    artifact_original_filename: Option<String>,
+    artifacts: Vec<ArtifactPayload>,
+    warning_count: usize,
+    error_count: usize,
+    test_results: Option<TestSummary>,
+    environments: Vec<EnvironmentResult>,
+    targets: Vec<TargetResult>,
+    matrix: Vec<MatrixResult>,
+    /// Environments and `matrix` entries that didn't build, see [`BuildResult::failed_parts`]
+    failed_parts: Vec<String>,
+    cache: Option<CacheStats>,
+    retries: u32,
+    s3_object: Option<S3Object>,
+    config_warnings: Vec<String>,
+    provenance: Provenance,
+    build_ms: u64,
+    final_attempt_ms: u64,
+    attempts: Vec<BuildAttempt>,
+    static_analysis: Vec<Diagnostic>,
+    stdout: String,
+    stderr: String,
+    /// Set instead of a build for `dry_run` requests
+    dry_run: Option<DryRunSummary>,
+}
+
+/// A build that ran to completion but reported failure, with its diagnostic counts
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+struct BuildFailed {
+    message: String,
+    warning_count: usize,
+    error_count: usize,
+    test_results: Option<TestSummary>,
+    environments: Vec<EnvironmentResult>,
+    targets: Vec<TargetResult>,
+    matrix: Vec<MatrixResult>,
+    cache: Option<CacheStats>,
+    retries: u32,
+    build_ms: u64,
+    final_attempt_ms: u64,
+    attempts: Vec<BuildAttempt>,
+    config_warnings: Vec<String>,
+    provenance: Provenance,
+    diagnostics: Vec<Diagnostic>,
+    static_analysis: Vec<Diagnostic>,
+    stdout: String,
+    stderr: String,
+    exit: ProcessExit,
+}
+
+/// A `build_config` that doesn't match the schema
+#[derive(Debug, thiserror::Error)]
+#[error("{}", .violations.iter().map(ConfigViolation::to_string).collect::<Vec<_>>().join("; "))]
+struct InvalidBuildConfig {
+    violations: Vec<ConfigViolation>,
+}
+
+/// Any pipeline failure after the build system was detected
+#[derive(Debug, thiserror::Error)]
+#[error("{source}")]
+struct DetectedBuildError {
+    build_system: BuildSystem,
+    source: anyhow::Error,
+}
 
 #[derive(Debug, Clone)]
 struct CustomerConfig {
     customer_id: String,
     allowed_installation_ids: HashSet<String>,
+    dirs: CustomerDirs,
 }
 
 impl CustomerConfig {
@@ -65,6 +318,7 @@ impl CustomerConfig {
               customer_id, installation_ids);
 
         Self {
+            dirs: CustomerDirs::from_env(&customer_id),
             customer_id,
             allowed_installation_ids: installation_ids,
         }
@@ -89,158 +343,976 @@ impl CustomerConfig {
 
 #[derive(Clone)]
 struct AppState {
-    job_manager: Arc<std::sync::RwLock<SingleJobManager>>,
+    job_manager: Arc<std::sync::RwLock<JobManager>>,
     customer_config: CustomerConfig,
+    quotas: QuotaTracker,
+    build_slots: Arc<Semaphore>,
+    runner: FirmwareBuildRunner,
+    max_upload_bytes: u64,
+    readiness: ReadinessChecks,
+    events: EventPublisher,
+    history: BuildHistory,
+    cache_metrics: CacheMetrics,
+    dedup: BuildDedup,
+    detect_limiter: RateLimiter,
+    self_test: SelfTest,
+    /// The runner's architecture and emulators, for `/capabilities`
+    host_arch: HostArch,
+    /// Shared by every archive download and GitHub API call, for its connection pool
+    http: reqwest::Client,
+    /// Held by each build so `POST /admin/prune` leaves the caches it uses alone
+    caches: CacheLock,
+    prune_tasks: PruneTasks,
+    /// Bearer token the `/admin` endpoints require, `NABLA_ADMIN_TOKEN`; unset disables them
+    admin_token: Option<String>,
+    /// Whether each build's complete output is kept, `NABLA_KEEP_BUILD_LOGS`
+    keep_build_logs: bool,
+}
+
+/// Durations of each repository's recent successful builds, for dry-run estimates
+#[derive(Clone, Default)]
+struct BuildHistory {
+    /// Keyed by `owner/repo`
+    durations: Arc<parking_lot::Mutex<HashMap<String, VecDeque<u64>>>>,
+}
+
+impl BuildHistory {
+    const KEPT: usize = 5;
+
+    fn record(&self, owner: &str, repo: &str, duration_ms: u64) {
+        let mut durations = self.durations.lock();
+        let recent = durations.entry(format!("{}/{}", owner, repo)).or_default();
+        if recent.len() == Self::KEPT {
+            recent.pop_front();
+        }
+        recent.push_back(duration_ms);
+    }
+
+    fn estimate(&self, owner: &str, repo: &str) -> Option<u64> {
+        let durations = self.durations.lock();
+        let recent = durations.get(&format!("{}/{}", owner, repo))?;
+        Some(recent.iter().sum::<u64>() / recent.len() as u64)
+    }
+}
+
+/// Cache statistics of every build since the runner started, for `GET /metrics`
+#[derive(Clone, Default)]
+struct CacheMetrics {
+    totals: Arc<parking_lot::Mutex<CacheTotals>>,
+}
+
+#[derive(Default)]
+struct CacheTotals {
+    builds: u64,
+    build_dir_reused: u64,
+    ccache_hits: u64,
+    ccache_misses: u64,
+    /// Size of the caches after the most recent build that measured them
+    cache_bytes: Option<u64>,
+}
+
+impl CacheMetrics {
+    fn record(&self, stats: &CacheStats) {
+        let mut totals = self.totals.lock();
+        totals.builds += 1;
+        totals.build_dir_reused += u64::from(stats.build_dir_reused);
+        totals.ccache_hits += stats.ccache_hits.unwrap_or(0);
+        totals.ccache_misses += stats.ccache_misses.unwrap_or(0);
+        totals.cache_bytes = stats.cache_bytes.or(totals.cache_bytes);
+    }
+
+    /// The totals in the Prometheus text format
+    fn render(&self) -> String {
+        let totals = self.totals.lock();
+        let mut metrics = vec![
+            ("nabla_cache_builds_total", "counter", "Builds that reported cache statistics", totals.builds as f64),
+            ("nabla_build_dir_reused_total", "counter", "Builds that started with an earlier build's output directory", totals.build_dir_reused as f64),
+            ("nabla_ccache_hits_total", "counter", "Compilations ccache answered from its cache", totals.ccache_hits as f64),
+            ("nabla_ccache_misses_total", "counter", "Compilations ccache had to run", totals.ccache_misses as f64),
+        ];
+        let calls = totals.ccache_hits + totals.ccache_misses;
+        if calls > 0 {
+            metrics.push(("nabla_ccache_hit_ratio", "gauge", "Share of ccache lookups that hit", totals.ccache_hits as f64 / calls as f64));
+        }
+        if let Some(bytes) = totals.cache_bytes {
+            metrics.push(("nabla_cache_bytes", "gauge", "Size of the tool caches after the latest build", bytes as f64));
+        }
+        metrics
+            .into_iter()
+            .map(|(name, kind, help, value)| format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"))
+            .collect()
+    }
+}
+
+/// What `POST /build` answers with
+type BuildReply = Result<Json<BuildResponse>, (StatusCode, Json<BuildResponse>)>;
+
+/// Builds running now, and ones completed within `NABLA_DEDUP_FRESH_SECS`, by
+/// [`request_fingerprint`], so a retried or doubly delivered request shares the first one's build
+#[derive(Clone, Default)]
+struct BuildDedup {
+    builds: Arc<parking_lot::Mutex<DedupBuilds>>,
+    /// How long a completed build answers identical requests; zero (the default) never
+    fresh_for: std::time::Duration,
+}
+
+#[derive(Default)]
+struct DedupBuilds {
+    running: HashMap<String, tokio::sync::watch::Receiver<Option<Arc<BuildReply>>>>,
+    completed: HashMap<String, (std::time::Instant, Arc<BuildReply>)>,
+}
+
+/// How a request with a fingerprint proceeds, see [`BuildDedup::join`]
+enum DedupJoin {
+    /// No identical build is running: this request builds and hands its reply to the others
+    Leader(DedupLeader),
+    /// An identical build is running; its reply arrives on the channel
+    Follower(tokio::sync::watch::Receiver<Option<Arc<BuildReply>>>),
+    /// An identical build completed within the freshness window
+    Fresh(Arc<BuildReply>),
+}
+
+/// The running build of a fingerprint. Dropped without a reply (the request was cancelled),
+/// it lets a waiting request take over.
+struct DedupLeader {
+    dedup: BuildDedup,
+    fingerprint: String,
+    reply: tokio::sync::watch::Sender<Option<Arc<BuildReply>>>,
+    finished: Option<Arc<BuildReply>>,
+}
+
+impl BuildDedup {
+    fn from_env() -> Self {
+        let fresh_secs = env::var("NABLA_DEDUP_FRESH_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+        Self {
+            builds: Arc::default(),
+            fresh_for: std::time::Duration::from_secs(fresh_secs),
+        }
+    }
+
+    fn join(&self, fingerprint: &str) -> DedupJoin {
+        let mut builds = self.builds.lock();
+        let fresh_for = self.fresh_for;
+        builds.completed.retain(|_, (completed, _)| completed.elapsed() < fresh_for);
+        if let Some((_, reply)) = builds.completed.get(fingerprint) {
+            return DedupJoin::Fresh(reply.clone());
+        }
+        if let Some(running) = builds.running.get(fingerprint) {
+            return DedupJoin::Follower(running.clone());
+        }
+        let (reply, running) = tokio::sync::watch::channel(None);
+        builds.running.insert(fingerprint.to_string(), running);
+        DedupJoin::Leader(DedupLeader {
+            dedup: self.clone(),
+            fingerprint: fingerprint.to_string(),
+            reply,
+            finished: None,
+        })
+    }
+}
+
+impl DedupLeader {
+    fn finish(mut self, reply: &BuildReply) {
+        self.finished = Some(Arc::new(reply.clone()));
+    }
+}
+
+impl Drop for DedupLeader {
+    fn drop(&mut self) {
+        let mut builds = self.dedup.builds.lock();
+        builds.running.remove(&self.fingerprint);
+        if let Some(reply) = self.finished.take() {
+            // A failed build is worth retrying rather than serving again
+            let completed = matches!(reply.as_ref(), Ok(response) if response.0.status != "failed");
+            if !self.dedup.fresh_for.is_zero() && completed {
+                builds.completed.insert(self.fingerprint.clone(), (std::time::Instant::now(), reply.clone()));
+            }
+            self.reply.send_replace(Some(reply));
+        }
+    }
+}
+
+/// Mark `reply` as shared with the request whose build produced it
+fn mark_deduplicated(reply: &mut BuildReply) {
+    let (Ok(Json(response)) | Err((_, Json(response)))) = reply;
+    response.deduplicated = true;
+}
+
+/// Identifies the build a request asks for: the customer and installation, repository, archive
+/// and its credential, dry run, and `build_config` after header and body are merged, including
+/// the secret values its serialized form hides. The client's `job_id` isn't part of it.
+fn request_fingerprint(customer_id: &str, params: &BuildParams, build_config: &BuildConfig) -> String {
+    use sha2::{Digest, Sha256};
+    let auth = params.archive_auth.as_ref().map(|auth| (format!("{:?}", auth.scheme), &auth.secret_ref, &auth.value));
+    let request = serde_json::json!({
+        "customer_id": customer_id,
+        "installation_id": params.installation_id,
+        "owner": params.owner,
+        "repo": params.repo,
+        "archive_url": params.archive_url,
+        "head_sha": params.head_sha,
+        "dry_run": params.dry_run,
+        "archive_auth": auth,
+        "build_config": build_config,
+        "secret_env": build_config.secret_env.expose(),
+    });
+    format!("{:x}", Sha256::digest(request.to_string().as_bytes()))
 }
 
 impl Default for AppState {
     fn default() -> Self {
+        let max_builds = env::var("NABLA_MAX_CONCURRENT_BUILDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+
         Self {
-            job_manager: Arc::new(std::sync::RwLock::new(SingleJobManager::new())),
+            job_manager: Arc::new(std::sync::RwLock::new(JobManager::from_env())),
             customer_config: CustomerConfig::from_env(),
+            quotas: QuotaTracker::new(QuotaLimits::from_env()),
+            build_slots: Arc::new(Semaphore::new(max_builds)),
+            runner: FirmwareBuildRunner::new(),
+            max_upload_bytes: max_upload_bytes(),
+            readiness: ReadinessChecks::from_env(),
+            events: EventPublisher::from_env(),
+            history: BuildHistory::default(),
+            cache_metrics: CacheMetrics::default(),
+            dedup: BuildDedup::from_env(),
+            detect_limiter: RateLimiter::detect_from_env(),
+            self_test: SelfTest::from_env(),
+            host_arch: HostArch::detect(),
+            http: http_client().expect("Failed to create the HTTP client"),
+            caches: CacheLock::default(),
+            prune_tasks: PruneTasks::default(),
+            admin_token: env::var("NABLA_ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+            keep_build_logs: env::var("NABLA_KEEP_BUILD_LOGS").as_deref() == Ok("1"),
         }
     }
 }
 
-fn validate_archive_url(url: &str) -> bool {
-    url.starts_with("https://") && url.len() > 8 && url.len() <= 500
+const DEFAULT_MIN_FREE_DISK_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// What `/ready` requires besides a free build slot
+#[derive(Debug, Clone)]
+struct ReadinessChecks {
+    /// Executables that must be on `PATH`, from the comma-separated `NABLA_REQUIRED_TOOLS`
+    required_tools: Vec<String>,
+    /// Free space needed on the workspace filesystem, `NABLA_MIN_FREE_DISK_BYTES`
+    min_free_disk_bytes: u64,
 }
 
-fn validate_params(params: &BuildParams) -> Result<()> {
-    if !validate_archive_url(&params.archive_url) {
-        return Err(anyhow!("Invalid archive_url - must be a valid HTTPS URL"));
+impl ReadinessChecks {
+    fn from_env() -> Self {
+        Self {
+            required_tools: env::var("NABLA_REQUIRED_TOOLS")
+                .unwrap_or_else(|_| "make,gcc".to_string())
+                .split(',')
+                .map(|tool| tool.trim().to_string())
+                .filter(|tool| !tool.is_empty())
+                .collect(),
+            min_free_disk_bytes: env::var("NABLA_MIN_FREE_DISK_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MIN_FREE_DISK_BYTES),
+        }
     }
-    
-    if params.owner.is_empty() || params.owner.len() > 100 {
-        return Err(anyhow!("Invalid owner - must be 1-100 characters"));
+}
+
+impl AppState {
+    /// Why a new build could not start right now; empty when the runner is ready
+    fn not_ready_reasons(&self) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if self.build_slots.available_permits() == 0 {
+            reasons.push("all build slots are in use".to_string());
+        }
+
+        match free_disk_bytes(self.customer_config.dirs.root()) {
+            Some(free) if free < self.readiness.min_free_disk_bytes => reasons.push(format!(
+                "only {} bytes free on the workspace disk (need {})",
+                free, self.readiness.min_free_disk_bytes
+            )),
+            Some(_) => {}
+            None => reasons.push("workspace disk space could not be determined".to_string()),
+        }
+
+        for tool in &self.readiness.required_tools {
+            if !on_path(tool, env::var_os("PATH").as_deref()) {
+                reasons.push(format!("required tool '{}' not found on PATH", tool));
+            }
+        }
+
+        reasons.extend(self.self_test.not_ready_reasons());
+        reasons
     }
-    
-    if params.repo.is_empty() || params.repo.len() > 100 {
-        return Err(anyhow!("Invalid repo - must be 1-100 characters"));
+}
+
+/// Free bytes available to unprivileged users on the filesystem holding `path`, or its
+/// nearest existing ancestor since workspaces are created lazily
+fn free_disk_bytes(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|dir| dir.exists())?;
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_encoded_bytes()).ok()?;
+    // SAFETY: c_path is NUL-terminated and stats is a valid out-pointer
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
     }
-    
-    let installation_id: u64 = params.installation_id.parse()
-        .map_err(|_| anyhow!("Invalid installation_id"))?;
-    
-    if installation_id == 0 {
-        return Err(anyhow!("Installation ID must be positive"));
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Why `url` can't be fetched from: it must be HTTPS and, when the operator lists hosts in
+/// `NABLA_ARCHIVE_HOSTS`, on one of them
+fn archive_url_problem(url: &str) -> Option<String> {
+    if !url.starts_with("https://") || url.len() <= 8 || url.len() > 500 {
+        return Some("Invalid archive_url - must be a valid HTTPS URL".to_string());
+    }
+    let allowed = archive_hosts();
+    let host = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_ascii_lowercase));
+    match host {
+        None => Some("Invalid archive_url - must be a valid HTTPS URL".to_string()),
+        Some(host) if !allowed.is_empty() && !allowed.iter().any(|pattern| host_matches(pattern, &host)) => {
+            Some(format!("Invalid archive_url - host {} is not allowed on this runner", host))
+        }
+        Some(_) => None,
     }
-    
-    
-    Ok(())
 }
 
-async fn setup_workspace(client_job_id: &str) -> Result<std::path::PathBuf> {
-    // Use client-provided job_id for workspace naming
-    let workspace = if std::path::Path::new("/workspace").exists() {
-        std::path::PathBuf::from("/workspace").join(format!("job-{}", client_job_id))
-    } else {
-        // For local development, use a temp directory
-        let temp_base = std::env::temp_dir().join("nabla-workspace");
-        temp_base.join(format!("job-{}", client_job_id))
+/// `NABLA_ARCHIVE_HOSTS`: comma-separated hosts archives may be fetched from, e.g.
+/// `github.com,*.githubusercontent.com`. Empty allows any host.
+fn archive_hosts() -> Vec<String> {
+    env::var("NABLA_ARCHIVE_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// `host` is `pattern`, or a subdomain of it when the pattern starts with `*.`
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+        None => host == pattern,
+    }
+}
+
+/// A GitHub user or organization name: letters, digits and single hyphens, at most 39
+/// characters, not starting or ending with a hyphen. Enterprise managed users add an
+/// `_<shortcode>` suffix.
+fn is_github_owner(owner: &str) -> bool {
+    let (name, shortcode) = owner.split_once('_').unwrap_or((owner, "a"));
+    let alphanumeric = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric());
+    owner.len() <= 39 && alphanumeric(shortcode) && name.split('-').all(alphanumeric)
+}
+
+/// A GitHub repository name: letters, digits, `.`, `-` and `_`, at most 100 characters
+fn is_github_repo(repo: &str) -> bool {
+    !repo.is_empty()
+        && repo.len() <= 100
+        && repo != "."
+        && repo != ".."
+        && repo.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Checks shared by JSON, multipart and legacy requests, reporting every invalid field at
+/// once. `archive_url` is only checked when the request fetches its archive.
+fn validate_params(params: &BuildParams, fetches_archive: bool) -> Result<()> {
+    let mut problems = Vec::new();
+
+    // job_id names the workspace directory, so it must stay a single path component
+    let job_id_valid = params
+        .job_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if params.job_id.is_empty() || params.job_id.len() > 100 || !job_id_valid {
+        problems.push("Invalid job_id - must be 1-100 characters of letters, digits, '-' or '_'".to_string());
+    }
+
+    if fetches_archive {
+        problems.extend(archive_url_problem(&params.archive_url));
+    }
+
+    if !is_github_owner(&params.owner) {
+        problems.push("Invalid owner - must be a GitHub user or organization name: letters, digits and single hyphens, at most 39 characters".to_string());
+    }
+
+    if !is_github_repo(&params.repo) {
+        problems.push("Invalid repo - must be 1-100 letters, digits, '.', '-' or '_'".to_string());
+    }
+
+    match params.installation_id.parse::<u64>() {
+        Ok(0) | Err(_) => problems.push("Invalid installation_id - must be a positive integer".to_string()),
+        Ok(_) => {}
+    }
+
+    if params.head_sha.as_deref().is_some_and(|sha| !is_commit_sha(sha)) {
+        problems.push("Invalid head_sha - must be 7-40 hex digits".to_string());
+    }
+
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!("{}", problems.join("; "))),
+    }
+}
+
+const BUILD_CONFIG_HEADER: &str = "x-nabla-build-config";
+
+/// Combine the base64-encoded JSON `X-Nabla-Build-Config` header with the body's `build_config`.
+/// Keys from the body win over keys from the header, which win over defaults.
+fn merge_build_config(headers: &HeaderMap, body: Option<&serde_json::Value>) -> Result<Option<serde_json::Value>> {
+    let Some(header) = headers.get(BUILD_CONFIG_HEADER) else {
+        return Ok(body.cloned());
     };
-    
+
+    let encoded = header
+        .to_str()
+        .map_err(|_| anyhow!("X-Nabla-Build-Config header is not valid ASCII"))?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| anyhow!("X-Nabla-Build-Config header is not valid base64: {}", e))?;
+    let header_config: serde_json::Value = serde_json::from_slice(&decoded)
+        .map_err(|e| anyhow!("X-Nabla-Build-Config header is not valid JSON: {}", e))?;
+
+    let serde_json::Value::Object(mut merged) = header_config else {
+        return Err(anyhow!("X-Nabla-Build-Config header must contain a JSON object"));
+    };
+
+    match body {
+        Some(serde_json::Value::Object(body_config)) => {
+            merged.extend(body_config.clone());
+        }
+        Some(serde_json::Value::Null) | None => {}
+        Some(_) => return Err(anyhow!("Invalid build_config: must be a JSON object")),
+    }
+
+    Ok(Some(serde_json::Value::Object(merged)))
+}
+
+fn parse_build_config(value: Option<&serde_json::Value>) -> Result<BuildConfig> {
+    match value {
+        Some(value) if !value.is_null() => {
+            let violations = check_build_config(value);
+            if !violations.is_empty() {
+                return Err(InvalidBuildConfig { violations }.into());
+            }
+            let config: BuildConfig = serde_json::from_value(value.clone())
+                .map_err(|e| anyhow!("Invalid build_config: {}", e))?;
+            config.validate()?;
+            Ok(config)
+        }
+        _ => Ok(BuildConfig::default()),
+    }
+}
+
+async fn setup_workspace(dirs: &CustomerDirs, client_job_id: &str) -> Result<std::path::PathBuf> {
+    // Use client-provided job_id for workspace naming, under the customer's own root
+    dirs.ensure().await?;
+    let workspace = dirs.job_workspace(client_job_id);
+
     // Create workspace directories
-    fs::create_dir_all(&workspace).await?;
+    create_private_dir(&workspace).await?;
     fs::create_dir_all(workspace.join("build")).await?;
-    fs::create_dir_all(workspace.join("out")).await?;
+    // Everything in out/ is returned as an artifact; drop what an earlier run of the job left
+    let out_dir = workspace.join("out");
+    if out_dir.exists() {
+        fs::remove_dir_all(&out_dir).await?;
+    }
+    fs::create_dir_all(&out_dir).await?;
 
     info!("Created workspace: {}", workspace.display());
     Ok(workspace)
 }
 
-async fn fetch_and_extract_repository(archive_url: &str, workspace: &Path) -> Result<std::path::PathBuf> {
+async fn fetch_and_extract_repository(
+    http: &reqwest::Client,
+    archive_url: &str,
+    authorization: Option<&ArchiveAuthorization>,
+    workspace: &Path,
+) -> Result<std::path::PathBuf> {
     info!("Fetching repository archive from: {}", archive_url);
-    
-    // Fetch the archive
-    let client = reqwest::Client::new();
-    let response = client
-        .get(archive_url)
-        .header("User-Agent", "nabla-runner/0.1.0")
-        .send()
-        .await?;
-    
-    if !response.status().is_success() {
-        return Err(anyhow!(
-            "Failed to fetch repository archive: HTTP {}",
-            response.status()
-        ));
-    }
-    
-    let archive_bytes = response.bytes().await?;
-    
-    // Write archive to temporary file
-    let temp_archive = workspace.join("temp_repo.tar.gz");
-    fs::write(&temp_archive, archive_bytes).await?;
-    
+    fetch_repository(http, archive_url, authorization, workspace).await
+}
+
+/// Mirror the layout of a GitHub repository into `workspace/repo` from its file listing, or
+/// `None` when the API can't list it
+async fn fetch_file_listing(http: &reqwest::Client, archive: &GithubArchive, workspace: &Path) -> Option<PathBuf> {
     let repo_dir = workspace.join("repo");
-    fs::create_dir_all(&repo_dir).await?;
-    
-    // Extract tarball using tar command
-    let output = Command::new("tar")
-        .arg("-xzf")
-        .arg(&temp_archive)
-        .arg("-C")
-        .arg(&repo_dir)
-        .arg("--strip-components=1")  // Remove the top-level directory from archive
-        .output()
-        .await?;
-    
-    if !output.status.success() {
-        return Err(anyhow!(
-            "Failed to extract tar.gz: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    match GithubApi::from_env().fetch_skeleton(http, archive, &repo_dir).await {
+        Ok(()) => Some(repo_dir),
+        Err(e) => {
+            warn!("No file listing for {}, downloading the archive instead: {}", archive, e);
+            let _ = fs::remove_dir_all(&repo_dir).await;
+            None
+        }
     }
-    
-    // Clean up temporary archive file
-    let _ = fs::remove_file(&temp_archive).await;
-    
-    Ok(repo_dir)
 }
 
+/// Where the repository archive for a build comes from
+enum ArchiveSource<'a> {
+    /// `archive_url` from a JSON request
+    Url(&'a str),
+    /// The `archive` part of a multipart request, already streamed to disk
+    Upload(&'a Path),
+}
+
+/// An uploaded archive on disk, removed once the request is done with it
+struct UploadedArchive(PathBuf);
+
+impl Drop for UploadedArchive {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+const DEFAULT_MAX_UPLOAD_BYTES: u64 = 200 * 1024 * 1024;
+
+/// Headroom over `MAX_UPLOAD` for the metadata part and multipart framing
+const MULTIPART_OVERHEAD_BYTES: u64 = 1024 * 1024;
+
+fn max_upload_bytes() -> u64 {
+    env::var("MAX_UPLOAD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES)
+}
+
+fn multipart_error(e: MultipartError) -> (StatusCode, Json<BuildResponse>) {
+    error_response(e.status(), format!("invalid multipart request: {}", e.body_text()))
+}
+
+/// Stream a multipart `archive` part to a new file in `upload_dir`, failing once it passes
+/// `max_bytes`
+async fn store_upload(
+    mut field: Field<'_>,
+    upload_dir: &Path,
+    max_bytes: u64,
+) -> Result<UploadedArchive, (StatusCode, String)> {
+    let internal = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("failed to store upload: {}", e));
+    let invalid = |e: MultipartError| (e.status(), format!("invalid multipart request: {}", e.body_text()));
+    create_private_dir(upload_dir).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("failed to store upload: {}", e)))?;
+    let archive = UploadedArchive(upload_dir.join(format!("upload-{}", Uuid::new_v4())));
+    let mut file = fs::File::create(&archive.0).await.map_err(internal)?;
+
+    let mut written: u64 = 0;
+    while let Some(chunk) = field.chunk().await.map_err(invalid)? {
+        written += chunk.len() as u64;
+        if written > max_bytes {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("archive exceeds the {} byte upload limit", max_bytes)));
+        }
+        file.write_all(&chunk).await.map_err(internal)?;
+    }
+    file.flush().await.map_err(internal)?;
+    Ok(archive)
+}
+
+/// Read a `multipart/form-data` build request: a `metadata` JSON part with the BuildParams
+/// fields other than `archive_url`, and an `archive` file part (zip or tar.gz) streamed to
+/// `upload_dir` and capped at `max_bytes`. Unknown parts are ignored.
+async fn read_multipart_upload(
+    mut multipart: Multipart,
+    upload_dir: &Path,
+    max_bytes: u64,
+) -> Result<(BuildParams, UploadedArchive), (StatusCode, Json<BuildResponse>)> {
+    let mut params: Option<BuildParams> = None;
+    let mut upload: Option<UploadedArchive> = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("metadata") => {
+                let text = field.text().await.map_err(multipart_error)?;
+                let parsed: BuildParams = serde_json::from_str(&text).map_err(|e| {
+                    error_response(StatusCode::BAD_REQUEST, format!("invalid request: metadata part: {}", e))
+                })?;
+                if !parsed.archive_url.is_empty() {
+                    return Err(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "invalid request: archive_url cannot be combined with an uploaded archive".to_string(),
+                    ));
+                }
+                if parsed.archive_auth.is_some() {
+                    return Err(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "invalid request: archive_auth only applies to an archive_url".to_string(),
+                    ));
+                }
+                params = Some(parsed);
+            }
+            Some("archive") => {
+                let archive = store_upload(field, upload_dir, max_bytes)
+                    .await
+                    .map_err(|(status, message)| error_response(status, message))?;
+                upload = Some(archive);
+            }
+            _ => {}
+        }
+    }
+
+    match (params, upload) {
+        (Some(params), Some(upload)) => Ok((params, upload)),
+        (None, _) => Err(error_response(StatusCode::BAD_REQUEST, "invalid request: missing metadata part".to_string())),
+        (_, None) => Err(error_response(StatusCode::BAD_REQUEST, "invalid request: missing archive part".to_string())),
+    }
+}
+
+
+
+fn is_commit_sha(value: &str) -> bool {
+    (7..=40).contains(&value.len()) && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The filename `artifact` is returned and uploaded under: the request's `artifact_name`, or
+/// [`DEFAULT_ARTIFACT_NAME`], rendered for it
+fn artifact_filename(params: &BuildParams, build_system: BuildSystem, build_config: &BuildConfig, artifact: &Artifact) -> Result<String> {
+    let path = Path::new(&artifact.path);
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    // The matrix entry, the PlatformIO environment, the Rust target, or failing those the built
+    // file's own name
+    let env_or_target = match (artifact.metadata.get("matrix").or(artifact.metadata.get("env")), &build_config.cargo_target) {
+        (Some(env), _) => env.clone(),
+        (None, Some(target)) if build_system == BuildSystem::Cargo => target.clone(),
+        _ => path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default(),
+    };
+    let template = build_config.artifact_name.as_deref().unwrap_or(DEFAULT_ARTIFACT_NAME);
+    render_artifact_name(template, |field| match field {
+        "env_or_target" => Some(env_or_target.clone()),
+        field => artifact_name_field(params, build_system, ext, field),
+    })
+}
+
+/// `name`, or with `-2`, `-3`, ... before its extension when an earlier artifact took it
+fn unique_filename(name: String, taken: &mut HashSet<String>) -> String {
+    if taken.insert(name.clone()) {
+        return name;
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+        _ => (name.clone(), String::new()),
+    };
+    (2..)
+        .map(|n| format!("{}-{}{}", stem, n, ext))
+        .find(|candidate| taken.insert(candidate.clone()))
+        .unwrap()
+}
+
+/// Value for one `artifact_name` placeholder. `sha` is the ref named by the archive URL,
+/// e.g. `abc123` for `.../archive/abc123.tar.gz`.
+fn artifact_name_field(params: &BuildParams, build_system: BuildSystem, ext: &str, field: &str) -> Option<String> {
+    let value = match field {
+        "owner" => params.owner.clone(),
+        "repo" => params.repo.clone(),
+        "installation_id" => params.installation_id.clone(),
+        "job_id" => params.job_id.clone(),
+        "build_system" => format!("{:?}", build_system).to_lowercase(),
+        "ext" => ext.to_string(),
+        "sha" => {
+            let last = params.archive_url.split(['?', '#']).next()?.rsplit('/').next()?;
+            [".tar.gz", ".tgz", ".zip"]
+                .iter()
+                .find_map(|suffix| last.strip_suffix(suffix))
+                .unwrap_or(last)
+                .to_string()
+        }
+        // Empty when neither `head_sha` nor the archive's ref is a commit
+        "short_sha" => params
+            .head_sha
+            .clone()
+            .or_else(|| artifact_name_field(params, build_system, ext, "sha").filter(|sha| is_commit_sha(sha)))
+            .map(|sha| sha[..7].to_lowercase())
+            .unwrap_or_default(),
+        _ => return None,
+    };
+    Some(value)
+}
 
+fn error_response(status: StatusCode, message: String) -> (StatusCode, Json<BuildResponse>) {
+    (
+        status,
+        Json(BuildResponse {
+            status: "error".to_string(),
+            job_id: Uuid::nil(),
+            message,
+            build_system: None,
+            artifact_data: None,
+            artifact_filename: None,
+            artifact_original_filename: None,
+            build_output: None,
+            artifacts: Vec::new(),
+            warning_count: None,
+            error_count: None,
+            test_results: None,
+            environments: Vec::new(),
+            targets: Vec::new(),
+            matrix: Vec::new(),
+            cache: None,
+            retries: None,
+            build_ms: None,
+            final_attempt_ms: None,
+            attempts: Vec::new(),
+            s3_object: None,
+            config_warnings: Vec::new(),
+            provenance: None,
+            config_errors: Vec::new(),
+            diagnostics: Vec::new(),
+            static_analysis: Vec::new(),
+            stdout: String::new(),
+            stderr: String::new(),
+            dry_run: None,
+            exit_code: None,
+            signal: None,
+            failure_fingerprint: None,
+            failure_occurrences: None,
+            archive_fetch: None,
+            deduplicated: false,
+        }),
+    )
+}
 
+/// Query parameters of the deprecated `POST /build?owner=...` API, whose body was the archive
+/// itself, zipped or base64-encoded
+#[derive(Debug, Deserialize)]
+struct LegacyBuildQuery {
+    owner: String,
+    repo: String,
+    installation_id: String,
+    #[serde(default)]
+    head_sha: Option<String>,
+    /// Artifacts are returned in the response now; accepted and ignored
+    #[serde(default)]
+    upload_url: Option<String>,
+}
+
+/// `Warning` header on responses to legacy query-parameter requests
+const LEGACY_BUILD_WARNING: &str =
+    "299 nabla-runner \"POST /build with query parameters is deprecated; send the parameters as JSON or multipart metadata\"";
+
+/// `POST /build` takes either a JSON body naming an `archive_url`, or `multipart/form-data`
+/// carrying the archive itself. Requests in the deprecated query-parameter form are still
+/// built, with `Deprecation` and `Warning` headers on the response.
 async fn build_handler(
     State(state): State<Arc<AppState>>,
-    JsonExtract(params): JsonExtract<BuildParams>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    let legacy = request
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|pair| pair.starts_with("owner=")));
+    if !legacy {
+        return build_request(state, headers, request).await.into_response();
+    }
+    let mut response = legacy_build_request(&state, &headers, request).await.into_response();
+    response.headers_mut().insert("deprecation", HeaderValue::from_static("true"));
+    response.headers_mut().insert(WARNING, HeaderValue::from_static(LEGACY_BUILD_WARNING));
+    response
+}
+
+/// Build from the legacy query parameters, with the request body as the uploaded archive
+async fn legacy_build_request(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: Request,
+) -> Result<Json<BuildResponse>, (StatusCode, Json<BuildResponse>)> {
+    let Query(query) = Query::<LegacyBuildQuery>::try_from_uri(request.uri())
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, format!("invalid request: {}", e.body_text())))?;
+    warn!("Deprecated query-parameter build request for {}/{}", query.owner, query.repo);
+    if query.upload_url.is_some() {
+        warn!("Ignoring upload_url; the artifact is returned in the response");
+    }
+
+    let too_large = || {
+        error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("archive exceeds the {} byte upload limit", state.max_upload_bytes),
+        )
+    };
+    let limit = usize::try_from(state.max_upload_bytes.saturating_add(MULTIPART_OVERHEAD_BYTES)).unwrap_or(usize::MAX);
+    let body = axum::body::to_bytes(request.into_body(), limit).await.map_err(|_| too_large())?;
+    let base64 = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/base64") || value.starts_with("text/plain"));
+    let archive = match base64 {
+        true => {
+            let text: Vec<u8> = body.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+            base64::engine::general_purpose::STANDARD
+                .decode(text)
+                .map_err(|e| error_response(StatusCode::BAD_REQUEST, format!("invalid request: body is not base64: {}", e)))?
+        }
+        false => body.to_vec(),
+    };
+    if archive.len() as u64 > state.max_upload_bytes {
+        return Err(too_large());
+    }
+
+    let internal = |e: anyhow::Error| error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to store upload: {}", e));
+    let upload_dir = state.customer_config.dirs.root().join("uploads");
+    create_private_dir(&upload_dir).await.map_err(internal)?;
+    let upload = UploadedArchive(upload_dir.join(format!("upload-{}", Uuid::new_v4())));
+    fs::write(&upload.0, archive).await.map_err(|e| internal(e.into()))?;
+
+    let params = BuildParams {
+        job_id: format!("legacy-{}", Uuid::new_v4()),
+        archive_url: String::new(),
+        owner: query.owner,
+        repo: query.repo,
+        installation_id: query.installation_id,
+        build_config: None,
+        dry_run: false,
+        head_sha: query.head_sha,
+        archive_auth: None,
+        deduplicate: None,
+    };
+    run_build(state, headers, params, ArchiveSource::Upload(&upload.0)).await
+}
+
+/// A current-form `POST /build`: JSON or multipart
+async fn build_request(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    request: Request,
+) -> Result<Json<BuildResponse>, (StatusCode, Json<BuildResponse>)> {
+    let is_multipart = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+
+    if is_multipart {
+        let multipart = Multipart::from_request(request, &state)
+            .await
+            .map_err(|e| error_response(e.status(), format!("invalid multipart request: {}", e.body_text())))?;
+        let upload_dir = state.customer_config.dirs.root().join("uploads");
+        let (params, upload) = read_multipart_upload(multipart, &upload_dir, state.max_upload_bytes).await?;
+        return run_build(&state, &headers, params, ArchiveSource::Upload(&upload.0)).await;
+    }
+
+    let JsonExtract(params) = JsonExtract::<BuildParams>::from_request(request, &state)
+        .await
+        .map_err(|e| error_response(e.status(), format!("invalid request: {}", e.body_text())))?;
+    run_build(&state, &headers, params.clone(), ArchiveSource::Url(&params.archive_url)).await
+}
+
+async fn run_build(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: BuildParams,
+    source: ArchiveSource<'_>,
 ) -> Result<Json<BuildResponse>, (StatusCode, Json<BuildResponse>)> {
     // Validate parameters
-    if let Err(e) = validate_params(&params) {
-        return Err((
+    if let Err(e) = validate_params(&params, matches!(source, ArchiveSource::Url(_))) {
+        return Err(error_response(StatusCode::BAD_REQUEST, format!("invalid request: {}", e)));
+    }
+
+    let build_config = match merge_build_config(headers, params.build_config.as_ref())
+        .and_then(|merged| parse_build_config(merged.as_ref()))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            let (status, mut response) = error_response(StatusCode::BAD_REQUEST, format!("invalid request: {}", e));
+            if let Some(invalid) = e.downcast_ref::<InvalidBuildConfig>() {
+                response.0.config_errors = invalid.violations.clone();
+            }
+            return Err((status, response));
+        }
+    };
+
+    if let Some(Err(e)) = params.archive_auth.as_ref().map(|auth| auth.resolve(&build_config.secret_env)) {
+        return Err(error_response(StatusCode::BAD_REQUEST, format!("invalid request: {}", e)));
+    }
+
+    if let Some(unknown) = build_config.post_processors.iter().find(|name| state.runner.processors().get(name).is_none()) {
+        return Err(error_response(
             StatusCode::BAD_REQUEST,
-            Json(BuildResponse {
-                status: "error".to_string(),
-                job_id: Uuid::nil(),
-                message: format!("invalid request: {}", e),
-                artifact_data: None,
-                artifact_filename: None,
-                build_output: None,
-            }),
+            format!("invalid request: unknown post-processor '{}'", unknown),
+        ));
+    }
+
+    if let Some(container) = &build_config.container {
+        let policy = ContainerPolicy::from_env();
+        // A platform the runner can't run is the request's mistake, not a policy refusal
+        if let Some(Err(e)) = container.platform.as_deref().map(|platform| policy.host.check_platform(platform)) {
+            return Err(error_response(StatusCode::BAD_REQUEST, format!("invalid request: {}", e)));
+        }
+        if let Err(e) = policy.check(container) {
+            return Err(error_response(StatusCode::FORBIDDEN, e.to_string()));
+        }
+    }
+
+    if build_config.post_build.is_some() && !hooks_allowed() {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "post_build hooks are disabled on this runner (NABLA_ALLOW_HOOKS is not set)".to_string(),
         ));
     }
 
     // Validate installation ID for this customer
     if !state.customer_config.validate_installation_id(&params.installation_id) {
-        return Err((
+        return Err(error_response(
             StatusCode::FORBIDDEN,
-            Json(BuildResponse {
-                status: "error".to_string(),
-                job_id: Uuid::nil(),
-                message: format!("Installation ID {} not allowed for this customer", params.installation_id),
-                artifact_data: None,
-                artifact_filename: None,
-                build_output: None,
-            }),
+            format!("Installation ID {} not allowed for this customer", params.installation_id),
         ));
     }
 
+    // Uploads carry their own archive, so only requests naming an archive_url are shared
+    if params.deduplicate == Some(false) || !matches!(source, ArchiveSource::Url(_)) {
+        return build_validated(state, params, source, build_config).await;
+    }
+    let fingerprint = request_fingerprint(&state.customer_config.customer_id, &params, &build_config);
+    loop {
+        match state.dedup.join(&fingerprint) {
+            DedupJoin::Leader(leader) => {
+                let reply = build_validated(state, params, source, build_config).await;
+                leader.finish(&reply);
+                return reply;
+            }
+            DedupJoin::Fresh(reply) => {
+                info!("Answering build request for {}/{} with an identical build that just completed", params.owner, params.repo);
+                let mut reply = (*reply).clone();
+                mark_deduplicated(&mut reply);
+                return reply;
+            }
+            DedupJoin::Follower(mut running) => {
+                info!("Build request for {}/{} joins an identical build already running", params.owner, params.repo);
+                // A leader that went away without a reply was cancelled; build in its place
+                if let Ok(reply) = running.wait_for(Option::is_some).await {
+                    if let Some(reply) = reply.as_deref() {
+                        let mut reply = reply.clone();
+                        mark_deduplicated(&mut reply);
+                        return reply;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Build a request that passed validation
+async fn build_validated(state: &AppState, params: BuildParams, source: ArchiveSource<'_>, build_config: BuildConfig) -> BuildReply {
     info!("Build request: {}/{} from {} (installation: {}, customer: {})", 
           params.owner, params.repo, params.archive_url, 
           params.installation_id, state.customer_config.customer_id);
 
+    // Enforce customer quotas before any workspace is created or a build slot is taken
+    let workspace_bytes = state.customer_config.dirs.workspace_bytes().await;
+    let _quota_guard = match state.quotas.try_acquire(&state.customer_config.customer_id, workspace_bytes) {
+        Ok(guard) => guard,
+        Err(e) => {
+            let status = match e {
+                QuotaError::ConcurrentJobs { .. } => StatusCode::TOO_MANY_REQUESTS,
+                QuotaError::DiskQuota { .. } => StatusCode::INSUFFICIENT_STORAGE,
+            };
+            warn!("Rejecting build: {}", e);
+            return Err(error_response(status, format!("quota exceeded: {}", e)));
+        }
+    };
+
+    let _build_slot = state
+        .build_slots
+        .acquire()
+        .await
+        .map_err(|_| error_response(StatusCode::SERVICE_UNAVAILABLE, "build slots closed".to_string()))?;
+    let _caches = state.caches.hold().await;
+
     // Create new job
-    let job = BuildJob::new(
+    let mut job = BuildJob::new(
         params.archive_url.clone(),
         params.owner.clone(),
         params.repo.clone(),
@@ -249,50 +1321,214 @@ async fn build_handler(
         Some(state.customer_config.customer_id.clone()),
     );
 
+    job.files.push(state.customer_config.dirs.job_workspace(&params.job_id));
+    job.files.extend(kept_log_file(state, &params.job_id));
+    job.client_job_id = params.job_id.clone();
+    job.live_log = LiveLog::new(build_config.secret_env.clone());
+    let live_log = job.live_log.clone();
     let job_id = job.id;
+    // Weak: the job manager holds the live log, which holds this callback
+    let jobs = Arc::downgrade(&state.job_manager);
+    live_log.on_progress(move |progress| {
+        if let Some(jobs) = jobs.upgrade() {
+            jobs.write().unwrap().update(job_id, |job| job.set_progress(progress));
+        }
+    });
+    let events = JobEvents::new(
+        state.events.clone(),
+        job_id,
+        &params.job_id,
+        &state.customer_config.customer_id,
+        &params.owner,
+        &params.repo,
+    )
+    .with_audit(JobAudit::new(state.job_manager.clone(), job_id, build_config.secret_env.clone()));
     
-    // Set the single job
-    state.job_manager.write().unwrap().set_job(job);
+    track_job(&state.job_manager, job);
+    events.emit(EventKind::Queued);
+    events.audit(
+        JobEventKind::Submitted,
+        format!(
+            "Installation {} of customer {} submitted {}/{} as job_id {}",
+            params.installation_id, state.customer_config.customer_id, params.owner, params.repo, params.job_id
+        ),
+    );
 
     // Execute build task synchronously and return result
     info!("Starting build job {}", job_id);
     
     // Update job status to running
-    state.job_manager.write().unwrap().update_job(|job| job.start());
+    state.job_manager.write().unwrap().update(job_id, |job| job.start());
+    events.emit(EventKind::Started);
+    events.audit(JobEventKind::Started, "Build slot acquired");
     
-    match execute_build_pipeline(&params).await {
-        Ok((output, artifact_base64, artifact_filename, _workspace)) => {
-            // Build succeeded
-            info!("Build job {} completed successfully", job_id);
-            state.job_manager.write().unwrap().update_job(|job| {
-                job.complete(output.clone(), Some(artifact_filename.clone()));
+    // Point tool caches at this customer's cache root
+    let mut build_config = build_config;
+    build_config.command_env.extend(state.customer_config.dirs.cache_env());
+    build_config.command_env.extend(state.customer_config.dirs.workspace_env(&params.job_id));
+
+    match execute_build_pipeline(state, &params, source, &build_config, live_log, &events).await {
+        Ok(mut output) => {
+            // Build succeeded, or a dry run found the build system
+            let dry_run = output.dry_run.take().map(|summary| DryRunSummary {
+                estimated_duration_ms: state.history.estimate(&params.owner, &params.repo),
+                ..summary
             });
-            
+            let built = dry_run.is_none();
+            if let Some(cache) = &output.cache {
+                state.cache_metrics.record(cache);
+            }
+            if built {
+                state.history.record(&params.owner, &params.repo, output.build_ms);
+                info!("Build job {} completed successfully", job_id);
+            } else {
+                info!("Dry run {} completed", job_id);
+            }
+            // Some environments or matrix entries built and the rest failed or were skipped
+            let failed_envs = std::mem::take(&mut output.failed_parts);
+            let partial = !failed_envs.is_empty();
+            state.job_manager.write().unwrap().update(job_id, |job| {
+                job.dry_run = !built;
+                if built {
+                    job.build_ms = Some(output.build_ms);
+                    job.final_attempt_ms = Some(output.final_attempt_ms);
+                }
+                match partial {
+                    true => job.complete_partially(output.log.clone(), output.artifact_filename.clone()),
+                    false => job.complete(output.log.clone(), output.artifact_filename.clone()),
+                }
+            });
+            let detail = match &output.artifact_filename {
+                Some(artifact) => format!("{:?} build produced {}", output.build_system, artifact),
+                None => format!("{:?} project, no artifact", output.build_system),
+            };
+            if partial {
+                events.emit(EventKind::PartiallyCompleted { build_system: output.build_system, failed: failed_envs.clone() });
+                events.audit(JobEventKind::PartiallyCompleted, format!("{}; failed in {}", detail, failed_envs.join(", ")));
+            } else {
+                events.emit(EventKind::Completed { build_system: output.build_system });
+                events.audit(JobEventKind::Completed, detail);
+            }
+
             Ok(Json(BuildResponse {
-                status: "completed".to_string(),
+                status: match partial {
+                    true => "partially_completed".to_string(),
+                    false => "completed".to_string(),
+                },
                 job_id,
-                message: "Build completed successfully".to_string(),
-                artifact_data: Some(artifact_base64),
-                artifact_filename: Some(artifact_filename),
-                build_output: Some(output),
+                message: match (built, partial) {
+                    (false, _) => "Dry run completed; nothing was built".to_string(),
+                    (true, true) => format!("Build completed except in {}", failed_envs.join(", ")),
+                    (true, false) => "Build completed successfully".to_string(),
+                },
+                build_system: Some(output.build_system),
+                artifact_data: output.artifact_data,
+                artifact_filename: output.artifact_filename,
+                artifact_original_filename: output.artifact_original_filename,
+                build_output: Some(output.log),
+                artifacts: output.artifacts,
+                warning_count: built.then_some(output.warning_count),
+                error_count: built.then_some(output.error_count),
+                test_results: output.test_results,
+                environments: output.environments,
+                targets: output.targets,
+                matrix: output.matrix,
+                cache: output.cache,
+                retries: built.then_some(output.retries),
+                build_ms: built.then_some(output.build_ms),
+                final_attempt_ms: built.then_some(output.final_attempt_ms),
+                attempts: output.attempts,
+                s3_object: output.s3_object,
+                config_warnings: output.config_warnings,
+                provenance: built.then_some(output.provenance),
+                config_errors: Vec::new(),
+                diagnostics: Vec::new(),
+                static_analysis: output.static_analysis,
+                stdout: output.stdout,
+                stderr: output.stderr,
+                dry_run,
+                exit_code: None,
+                signal: None,
+                failure_fingerprint: None,
+                failure_occurrences: None,
+                archive_fetch: None,
+                deduplicated: false,
             }))
         }
         Err(e) => {
             // Build failed
-            let error_msg = e.to_string();
+            let (build_system, e) = match e.downcast::<DetectedBuildError>() {
+                Ok(detected) => (Some(detected.build_system), detected.source),
+                Err(e) => (None, e),
+            };
+            // Redacted once, before the message reaches the log, the job, events or the response
+            let error_msg = build_config.secret_env.redact(&e.to_string());
             error!("Build job {} failed: {}", job_id, error_msg);
             
-            state.job_manager.write().unwrap().update_job(|job| {
-                job.fail(error_msg.clone());
+            let failed = e.downcast_ref::<BuildFailed>();
+            if let Some(cache) = failed.and_then(|f| f.cache.as_ref()) {
+                state.cache_metrics.record(cache);
+            }
+            let failure = {
+                let mut jobs = state.job_manager.write().unwrap();
+                jobs.update(job_id, |job| {
+                    job.build_ms = failed.map(|f| f.build_ms);
+                    job.final_attempt_ms = failed.map(|f| f.final_attempt_ms);
+                    job.fail(error_msg.clone());
+                });
+                let repo = format!("{}/{}", params.owner, params.repo);
+                jobs.record_failure(&repo, &error_msg)
+            };
+            events.emit(EventKind::Failed {
+                build_system,
+                error: error_msg.clone(),
+                fingerprint: failure.fingerprint.clone(),
+                occurrences: failure.occurrences,
             });
-            
+            events.audit(JobEventKind::Failed, error_msg.clone());
+
+            let step = e.downcast_ref::<BuildStepFailed>();
+            let (diagnostics, exit, stdout, stderr) = match (failed, step) {
+                (Some(failed), _) => (failed.diagnostics.clone(), failed.exit, failed.stdout.clone(), failed.stderr.clone()),
+                (None, Some(step)) => (step.diagnostics.clone(), step.exit, step.stdout.clone(), step.stderr.clone()),
+                (None, None) => (Vec::new(), ProcessExit::default(), String::new(), String::new()),
+            };
             Ok(Json(BuildResponse {
                 status: "failed".to_string(),
                 job_id,
                 message: format!("Build failed: {}", error_msg),
+                build_system,
                 artifact_data: None,
                 artifact_filename: None,
+                artifact_original_filename: None,
                 build_output: Some(error_msg),
+                artifacts: Vec::new(),
+                warning_count: failed.map(|f| f.warning_count).or(step.map(|step| step.warning_count)),
+                error_count: failed.map(|f| f.error_count).or(step.map(|step| step.error_count)),
+                test_results: failed.and_then(|f| f.test_results),
+                environments: failed.map(|f| f.environments.clone()).unwrap_or_default(),
+                targets: failed.map(|f| f.targets.clone()).unwrap_or_default(),
+                matrix: failed.map(|f| f.matrix.clone()).unwrap_or_default(),
+                cache: failed.and_then(|f| f.cache.clone()),
+                retries: failed.map(|f| f.retries),
+                build_ms: failed.map(|f| f.build_ms),
+                final_attempt_ms: failed.map(|f| f.final_attempt_ms),
+                attempts: failed.map(|f| f.attempts.clone()).unwrap_or_default(),
+                s3_object: None,
+                config_warnings: failed.map(|f| f.config_warnings.clone()).unwrap_or_default(),
+                provenance: failed.map(|f| f.provenance.clone()),
+                config_errors: Vec::new(),
+                diagnostics,
+                static_analysis: failed.map(|f| f.static_analysis.clone()).unwrap_or_default(),
+                stdout,
+                stderr,
+                dry_run: None,
+                exit_code: exit.exit_code,
+                signal: exit.signal,
+                failure_fingerprint: Some(failure.fingerprint),
+                failure_occurrences: Some(failure.occurrences),
+                archive_fetch: e.downcast_ref::<ArchiveFetchError>().cloned(),
+                deduplicated: false,
             }))
         }
     }
@@ -300,74 +1536,1023 @@ async fn build_handler(
 
 
 
-async fn execute_build_pipeline(params: &BuildParams) -> Result<(String, String, String, std::path::PathBuf)> {
+async fn execute_build_pipeline(
+    state: &AppState,
+    params: &BuildParams,
+    source: ArchiveSource<'_>,
+    build_config: &BuildConfig,
+    live_log: LiveLog,
+    events: &JobEvents,
+) -> Result<PipelineOutput> {
     let mut output_log = Vec::new();
+    events.phase(BuildPhase::Fetch);
     
     // Setup workspace using client job_id
-    let workspace = setup_workspace(&params.job_id).await?;
+    let workspace = setup_workspace(&state.customer_config.dirs, &params.job_id).await?;
     output_log.push(format!("Workspace ready: {}", workspace.display()));
+    events.audit(JobEventKind::Workspace, format!("Created {}", workspace.display()));
+
+    // Where `fetch_submodules` can clone from; uploads have nowhere
+    let git_source = match &source {
+        ArchiveSource::Url(url) => GithubArchive::parse(url).map(|archive| GitSource::from_archive(&archive, GithubApi::from_env().token)),
+        ArchiveSource::Upload(_) => None,
+    };
+
+    // A dry run only needs the file listing, which GitHub serves without the archive
+    let listed = match (&source, params.dry_run) {
+        (ArchiveSource::Url(url), true) => match GithubArchive::parse(url) {
+            Some(archive) => {
+                events.audit(JobEventKind::FetchStarted, format!("Listing files of {} via the GitHub API", archive));
+                fetch_file_listing(&state.http, &archive, &workspace).await
+            }
+            None => None,
+        },
+        _ => None,
+    };
+    let repo_dir = match listed {
+        Some(repo_dir) => {
+            output_log.push(format!("Repository file listing fetched to: {} (archive not downloaded)", repo_dir.display()));
+            events.audit(JobEventKind::FetchFinished, format!("Listed files into {}", repo_dir.display()));
+            repo_dir
+        }
+        None => {
+            // Fetch and extract repository from archive URL, or unpack the uploaded archive
+            events.audit(
+                JobEventKind::FetchStarted,
+                match &source {
+                    // Only the host: archive URLs often carry signed query strings
+                    ArchiveSource::Url(url) => match reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string)) {
+                        Some(host) => format!("Downloading archive from {}", host),
+                        None => "Downloading archive".to_string(),
+                    },
+                    ArchiveSource::Upload(archive) => match std::fs::metadata(archive) {
+                        Ok(meta) => format!("Unpacking uploaded archive ({} bytes)", meta.len()),
+                        Err(_) => "Unpacking uploaded archive".to_string(),
+                    },
+                },
+            );
+            let repo_dir = match source {
+                ArchiveSource::Url(url) => {
+                    let authorization = params.archive_auth.as_ref().map(|auth| auth.resolve(&build_config.secret_env)).transpose()?;
+                    fetch_and_extract_repository(&state.http, url, authorization.as_ref(), &workspace).await?
+                }
+                ArchiveSource::Upload(archive) => {
+                    let repo_dir = workspace.join("repo");
+                    extract_archive(archive, &repo_dir, 0, max_repo_files()).await?;
+                    repo_dir
+                }
+            };
+            output_log.push(format!("Repository fetched and extracted to: {}", repo_dir.display()));
+            events.audit(JobEventKind::FetchFinished, format!("Extracted to {}", repo_dir.display()));
+            repo_dir
+        }
+    };
 
-    // Fetch and extract repository from archive URL
-    let repo_dir = fetch_and_extract_repository(&params.archive_url, &workspace).await?;
-    output_log.push(format!("Repository fetched and extracted to: {}", repo_dir.display()));
+    let options = RunOptions {
+        config: build_config.clone(),
+        build_system: None,
+        // Full, uncapped build output, kept per customer when the operator asks for it
+        log_file: kept_log_file(state, &params.job_id),
+        git_source,
+        live_log: Some(live_log),
+    };
+    if params.dry_run {
+        events.phase(BuildPhase::Detect);
+        let report = state.runner.dry_run(&repo_dir, &options).await;
+        // Nothing in a dry run's workspace is worth keeping
+        if let Err(e) = fs::remove_dir_all(&workspace).await {
+            warn!("Failed to remove dry-run workspace {}: {}", workspace.display(), e);
+        }
+        events.audit(JobEventKind::Workspace, format!("Removed {}", workspace.display()));
+        let report = report?;
+        events.audit(JobEventKind::Detected, detected_detail(report.build_system, report.flavor, &report.repo_dir));
+        output_log.extend(report.log.iter().cloned());
+        output_log.push(format!("Dry run complete; removed workspace {}", workspace.display()));
+        return Ok(PipelineOutput {
+            log: log_tail(&output_log),
+            build_system: report.build_system,
+            artifact_data: None,
+            artifact_filename: None,
+            artifact_original_filename: None,
+            artifacts: Vec::new(),
+            warning_count: 0,
+            error_count: 0,
+            test_results: None,
+            environments: Vec::new(),
+            targets: Vec::new(),
+            matrix: Vec::new(),
+            failed_parts: Vec::new(),
+            cache: None,
+            retries: 0,
+            s3_object: None,
+            config_warnings: report.config_warnings.clone(),
+            provenance: Provenance::default(),
+            build_ms: 0,
+            final_attempt_ms: 0,
+            attempts: Vec::new(),
+            static_analysis: Vec::new(),
+            stdout: String::new(),
+            stderr: String::new(),
+            dry_run: Some(DryRunSummary {
+                report,
+                resolved_config: build_config.clone(),
+                estimated_duration_ms: None,
+            }),
+        });
+    }
+    let report = state.runner.run_with_progress(&repo_dir, &options, |phase| events.phase(phase)).await;
+    // Tools that started a new session escaped the build commands' process groups
+    let stray = kill_processes_in(&workspace).await;
+    if stray > 0 {
+        output_log.push(format!("Killed {} process(es) the build left running in the workspace", stray));
+        events.audit(JobEventKind::Workspace, format!("Killed {} process(es) left running in {}", stray, workspace.display()));
+    }
+    let report = report?;
+    let build_system = report.build_system;
+    output_log.extend(report.log);
+    events.audit(JobEventKind::Detected, detected_detail(build_system, report.flavor, &report.repo_dir));
+    let attempts = report.result.retries + 1;
+    for attempt in 1..attempts {
+        let took = report.result.attempts.get(attempt as usize - 1).map_or(String::new(), |a| format!(" after {}ms", a.duration_ms));
+        events.audit(JobEventKind::Attempt, format!("Attempt {} of {} failed with a transient error{} and was retried", attempt, attempts, took));
+    }
+    let final_attempt_ms = report.result.final_attempt_ms();
+    events.audit(
+        JobEventKind::Attempt,
+        match report.result.success {
+            true => format!("Attempt {} of {} succeeded in {}ms ({}ms in total)", attempts, attempts, final_attempt_ms, report.result.duration_ms),
+            false => format!("Attempt {} of {} failed in {}ms ({}ms in total)", attempts, attempts, final_attempt_ms, report.result.duration_ms),
+        },
+    );
 
-    // Detect build system
-    let build_system = detection::detect_build_system(&repo_dir).await
-        .ok_or_else(|| anyhow!("Unsupported or undetected build system"))?;
-    output_log.push(format!("Detected build system: {:?}", build_system));
+    package_build(params, report.result, build_system, build_config, output_log, events)
+        .await
+        .map_err(|source| DetectedBuildError { build_system, source }.into())
+}
 
-    // Execute build
-    output_log.push("Starting build...".to_string());
-    let build_result = execution::execute_build(&repo_dir, build_system).await?;
+/// Where a job's complete build output is written, when `NABLA_KEEP_BUILD_LOGS` is set
+fn kept_log_file(state: &AppState, client_job_id: &str) -> Option<PathBuf> {
+    state
+        .keep_build_logs
+        .then(|| state.customer_config.dirs.root().join("logs").join(format!("{}.log", client_job_id)))
+}
 
+/// Start tracking `job`. The workspaces and logs of jobs evicted to make room are removed in
+/// the background, unless a job still tracked reuses them.
+fn track_job(jobs: &std::sync::RwLock<JobManager>, job: BuildJob) {
+    let mut jobs = jobs.write().unwrap();
+    let evicted = jobs.insert(job);
+    let stale: Vec<PathBuf> = evicted
+        .into_iter()
+        .inspect(|job| info!("Evicted job {} from the job list", job.id))
+        .flat_map(|job| job.files)
+        .filter(|path| !jobs.uses_path(path))
+        .collect();
+    drop(jobs);
+    if stale.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        for path in stale {
+            let removed = match fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path).await,
+                Ok(_) => fs::remove_file(&path).await,
+                Err(_) => continue,
+            };
+            if let Err(e) = removed {
+                warn!("Failed to remove {} of an evicted job: {}", path.display(), e);
+            }
+        }
+    });
+}
+
+/// Audit detail for a detected repository
+fn detected_detail(build_system: BuildSystem, flavor: Option<BuildFlavor>, repo_dir: &Path) -> String {
+    match flavor {
+        Some(flavor) => format!("{:?} ({:?}) in {}", build_system, flavor, repo_dir.display()),
+        None => format!("{:?} in {}", build_system, repo_dir.display()),
+    }
+}
+
+/// Package the artifacts of a finished build, or turn its failure into a [`BuildFailed`]
+async fn package_build(
+    params: &BuildParams,
+    build_result: BuildResult,
+    build_system: BuildSystem,
+    build_config: &BuildConfig,
+    mut output_log: Vec<String>,
+    events: &JobEvents,
+) -> Result<PipelineOutput> {
+    let final_attempt_ms = build_result.final_attempt_ms();
+    let failed_parts = build_result.failed_parts();
     if !build_result.success {
         let error_msg = build_result.error_output.unwrap_or_else(|| "Unknown build error".to_string());
-        output_log.push(format!("Build failed: {}", error_msg));
-        return Err(anyhow!("Build failed: {}", error_msg));
+        return Err(BuildFailed {
+            message: error_msg,
+            warning_count: build_result.warning_count,
+            error_count: build_result.error_count,
+            test_results: build_result.test_results,
+            environments: build_result.environments,
+            targets: build_result.targets,
+            matrix: build_result.matrix,
+            cache: build_result.cache,
+            retries: build_result.retries,
+            build_ms: build_result.duration_ms,
+            final_attempt_ms,
+            attempts: build_result.attempts,
+            config_warnings: build_result.config_warnings,
+            provenance: build_result.provenance,
+            diagnostics: build_result.diagnostics,
+            static_analysis: build_result.static_analysis,
+            stdout: build_result.stdout,
+            stderr: build_result.stderr,
+            exit: ProcessExit {
+                exit_code: build_result.exit_code,
+                signal: build_result.signal,
+            },
+        }
+        .into());
+    }
+
+    if let Some(test_results) = build_result.test_results {
+        output_log.push(format!("Tests passed: {} of {}", test_results.passed, test_results.total));
+        return Ok(PipelineOutput {
+            log: log_tail(&output_log),
+            build_system,
+            artifact_data: None,
+            artifact_filename: None,
+            artifact_original_filename: None,
+            artifacts: Vec::new(),
+            warning_count: build_result.warning_count,
+            error_count: build_result.error_count,
+            test_results: Some(test_results),
+            environments: Vec::new(),
+            targets: Vec::new(),
+            matrix: Vec::new(),
+            failed_parts: Vec::new(),
+            cache: build_result.cache,
+            retries: build_result.retries,
+            s3_object: None,
+            config_warnings: build_result.config_warnings,
+            provenance: build_result.provenance,
+            build_ms: build_result.duration_ms,
+            final_attempt_ms,
+            attempts: build_result.attempts,
+            static_analysis: build_result.static_analysis,
+            stdout: build_result.stdout,
+            stderr: build_result.stderr,
+            dry_run: None,
+        });
     }
 
     let artifact_path = build_result.output_path
         .ok_or_else(|| anyhow!("Build succeeded but no artifact path returned"))?;
-    output_log.push(format!("Build completed successfully. Artifact: {}", artifact_path));
-
-    // Read artifact and encode as base64
-    let artifact_bytes = fs::read(&artifact_path).await?;
-    let artifact_base64 = base64::engine::general_purpose::STANDARD.encode(&artifact_bytes);
-    output_log.push(format!("Artifact encoded to base64 ({} bytes)", artifact_bytes.len()));
+    events.phase(BuildPhase::Package);
 
-    // Extract filename from path
-    let artifact_filename = Path::new(&artifact_path)
+    // Every artifact gets a name of its own from the template, the primary one first
+    let mut taken = HashSet::new();
+    let mut names = Vec::new();
+    for artifact in &build_result.artifacts {
+        names.push(unique_filename(artifact_filename(params, build_system, build_config, artifact)?, &mut taken));
+    }
+    let artifact_filename = match build_result.artifacts.iter().position(|artifact| artifact.path == artifact_path) {
+        Some(index) => names[index].clone(),
+        None => {
+            let primary = Artifact::new(artifact_path.clone(), build_result.target_format.clone().unwrap_or_default());
+            artifact_filename(params, build_system, build_config, &primary)?
+        }
+    };
+    let original_filename = Path::new(&artifact_path)
         .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("artifact.bin")
-        .to_string();
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // Upload to the requested bucket, or read artifact and encode as base64
+    let s3_object = upload_to_s3(build_config, Path::new(&artifact_path), &artifact_filename).await?;
+    let artifact_base64 = match &s3_object {
+        Some(object) => {
+            output_log.push(format!("Artifact uploaded to {}", object.url));
+            events.audit(JobEventKind::Upload, format!("Uploaded {} to s3://{}/{}", artifact_path, object.bucket, object.key));
+            None
+        }
+        None => {
+            let artifact_bytes = fs::read(&artifact_path).await?;
+            output_log.push(format!("Artifact encoded to base64 ({} bytes)", artifact_bytes.len()));
+            Some(base64::engine::general_purpose::STANDARD.encode(&artifact_bytes))
+        }
+    };
 
-    // Return last 4000 chars of logs to keep response manageable
+    // Post-processed and multi-image builds carry more than the primary artifact; include all of them
+    let mut artifacts = Vec::new();
+    if !build_config.post_processors.is_empty() || build_result.artifacts.len() > 1 {
+        artifacts = encode_artifacts(&build_result.artifacts).await?;
+        for (payload, name) in artifacts.iter_mut().zip(names) {
+            let original = std::mem::replace(&mut payload.filename, name);
+            payload.metadata.insert("original_filename".to_string(), original);
+        }
+        output_log.push(format!("Encoded {} artifacts", artifacts.len()));
+    }
+
+    Ok(PipelineOutput {
+        log: log_tail(&output_log),
+        build_system,
+        artifact_data: artifact_base64,
+        artifact_filename: Some(artifact_filename),
+        artifact_original_filename: Some(original_filename),
+        artifacts,
+        warning_count: build_result.warning_count,
+        error_count: build_result.error_count,
+        test_results: None,
+        environments: build_result.environments,
+        targets: build_result.targets,
+        matrix: build_result.matrix,
+        failed_parts,
+        cache: build_result.cache,
+        retries: build_result.retries,
+        s3_object,
+        config_warnings: build_result.config_warnings,
+        provenance: build_result.provenance,
+        build_ms: build_result.duration_ms,
+        final_attempt_ms,
+        attempts: build_result.attempts,
+        static_analysis: build_result.static_analysis,
+        stdout: build_result.stdout,
+        stderr: build_result.stderr,
+        dry_run: None,
+    })
+}
+
+/// Read and base64-encode `artifacts` concurrently, at most one per CPU at a time. The payloads
+/// come back in the order of `artifacts`, however the tasks finish.
+pub async fn encode_artifacts(artifacts: &[Artifact]) -> Result<Vec<ArtifactPayload>> {
+    let parallelism = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let semaphore = Arc::new(Semaphore::new(parallelism));
+
+    let mut tasks = JoinSet::new();
+    for (index, artifact) in artifacts.iter().cloned().enumerate() {
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let bytes = fs::read(&artifact.path)
+                .await
+                .map_err(|e| anyhow!("Reading artifact {}: {}", artifact.path, e))?;
+            let data = tokio::task::spawn_blocking(move || base64::engine::general_purpose::STANDARD.encode(bytes)).await?;
+            let filename = Path::new(&artifact.path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("artifact.bin")
+                .to_string();
+            Ok::<_, anyhow::Error>((
+                index,
+                ArtifactPayload {
+                    filename,
+                    format: artifact.format,
+                    data,
+                    metadata: artifact.metadata,
+                },
+            ))
+        });
+    }
+
+    let mut payloads: Vec<Option<ArtifactPayload>> = artifacts.iter().map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        let (index, payload) = joined??;
+        payloads[index] = Some(payload);
+    }
+    Ok(payloads.into_iter().flatten().collect())
+}
+
+/// Upload the artifact when the request named an S3 target
+#[cfg_attr(not(feature = "s3"), allow(unused_variables))]
+async fn upload_to_s3(build_config: &BuildConfig, artifact_path: &Path, #[cfg_attr(not(feature = "s3"), allow(unused_variables))] name: &str) -> Result<Option<S3Object>> {
+    match &build_config.s3 {
+        #[cfg(feature = "s3")]
+        Some(target) => crate::s3::upload_artifact(target, artifact_path, name).await.map(Some),
+        #[cfg(not(feature = "s3"))]
+        Some(_) => Err(anyhow!("S3 upload is not available - the runner was built without the `s3` feature")),
+        None => Ok(None),
+    }
+}
+
+/// Return last 4000 chars of logs to keep response manageable
+fn log_tail(output_log: &[String]) -> String {
     let full_output = output_log.join("\n");
-    let tail = if full_output.len() > 4000 {
+    if full_output.len() > 4000 {
         full_output.chars().skip(full_output.len() - 4000).collect()
     } else {
         full_output
-    };
-
-    Ok((tail, artifact_base64, artifact_filename, workspace))
+    }
 }
 
 
-async fn health_handler() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
+async fn health_handler(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let mut health = serde_json::json!({
         "status": "healthy",
         "service": "nabla-runner",
-        "version": env!("CARGO_PKG_VERSION")
+        "version": env!("CARGO_PKG_VERSION"),
+        "leaked_processes_killed": leaked_processes_killed(),
+    });
+    if state.events.is_enabled() {
+        health["event_bus"] = serde_json::json!({
+            "pending_events": state.events.pending(),
+            "dropped_events": state.events.dropped(),
+        });
+    }
+    Json(health)
+}
+
+/// Readiness, unlike `/health`: whether a new build would start now. Load balancers should
+/// stop routing to a runner while this returns `503`.
+async fn ready_handler(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let reasons = state.not_ready_reasons();
+    if reasons.is_empty() {
+        (StatusCode::OK, Json(serde_json::json!({ "status": "ready" })))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "not_ready", "reasons": reasons })),
+        )
+    }
+}
+
+/// What this runner can build: its architecture and the ones it emulates, and for each build
+/// system, whether the tools its build runs are on `PATH` and how its startup self-test went
+/// when `SELF_TEST` named it
+async fn capabilities_handler(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let search_path = env::var_os("PATH");
+    let results = state.self_test.results();
+    let build_systems: Vec<serde_json::Value> = crate::detection::DETECTION_ORDER
+        .iter()
+        .map(|&system| {
+            let tools: Vec<serde_json::Value> = crate::execution::required_tools(system, None)
+                .into_iter()
+                .map(|tool| serde_json::json!({ "name": tool, "ok": on_path(tool, search_path.as_deref()) }))
+                .collect();
+            let mut entry = serde_json::json!({ "build_system": system, "tools": tools });
+            if let Some(result) = results.iter().find(|result| result.build_system == system) {
+                entry["self_test"] = serde_json::json!(result);
+            }
+            entry
+        })
+        .collect();
+    Json(serde_json::json!({
+        "host": state.host_arch,
+        "build_systems": build_systems,
+        "self_test": {
+            "requested": state.self_test.requested(),
+            "running": state.self_test.running(),
+        },
     }))
 }
 
+#[derive(Debug, Deserialize)]
+struct DetectParams {
+    archive_url: String,
+    installation_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DetectResponse {
+    /// `detected`, `undetected` or `error`
+    status: String,
+    message: String,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    report: Option<DetectionReport>,
+}
+
+fn detect_error(status: StatusCode, message: String) -> (StatusCode, Json<DetectResponse>) {
+    (
+        status,
+        Json(DetectResponse {
+            status: "error".to_string(),
+            message,
+            report: None,
+        }),
+    )
+}
+
+/// `POST /detect` classifies a repository without building it. No job is recorded, and the
+/// workspace is removed before responding.
+async fn detect_handler(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+) -> Result<Json<DetectResponse>, (StatusCode, Json<DetectResponse>)> {
+    if let Err(e) = state.detect_limiter.try_acquire() {
+        warn!("Rejecting detect request: {}", e);
+        return Err(detect_error(StatusCode::TOO_MANY_REQUESTS, format!("rate limited: {}", e)));
+    }
+
+    let JsonExtract(params) = JsonExtract::<DetectParams>::from_request(request, &state)
+        .await
+        .map_err(|e| detect_error(e.status(), format!("invalid request: {}", e.body_text())))?;
+    if let Some(problem) = archive_url_problem(&params.archive_url) {
+        return Err(detect_error(StatusCode::BAD_REQUEST, format!("invalid request: {}", problem)));
+    }
+    if !state.customer_config.validate_installation_id(&params.installation_id) {
+        return Err(detect_error(
+            StatusCode::FORBIDDEN,
+            format!("Installation ID {} not allowed for this customer", params.installation_id),
+        ));
+    }
+
+    let workspace = setup_workspace(&state.customer_config.dirs, &format!("detect-{}", Uuid::new_v4()))
+        .await
+        .map_err(|e| detect_error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create workspace: {}", e)))?;
+    let listed = match GithubArchive::parse(&params.archive_url) {
+        Some(archive) => fetch_file_listing(&state.http, &archive, &workspace).await,
+        None => None,
+    };
+    let fetched = match listed {
+        Some(repo_dir) => Ok(repo_dir),
+        None => fetch_and_extract_repository(&state.http, &params.archive_url, None, &workspace).await,
+    };
+    let report = match &fetched {
+        Ok(repo_dir) => crate::detection::analyze(repo_dir).await,
+        Err(_) => None,
+    };
+    if let Err(e) = fs::remove_dir_all(&workspace).await {
+        warn!("Failed to remove detect workspace {}: {}", workspace.display(), e);
+    }
+
+    if let Err(e) = fetched {
+        return Err(detect_error(StatusCode::BAD_GATEWAY, format!("Failed to fetch repository: {}", e)));
+    }
+    Ok(Json(match report {
+        Some(report) => DetectResponse {
+            status: "detected".to_string(),
+            message: format!("Detected {:?}", report.build_system),
+            report: Some(report),
+        },
+        None => DetectResponse {
+            status: "undetected".to_string(),
+            message: crate::detection::UNDETECTED_MESSAGE.to_string(),
+            report: None,
+        },
+    }))
+}
+
+/// Files `POST /inspect` lists when the request doesn't ask for fewer
+const MAX_INSPECT_FILES: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct InspectParams {
+    /// Required for JSON requests; must be absent when the archive is uploaded as multipart
+    #[serde(default)]
+    archive_url: String,
+    installation_id: String,
+    /// List at most this many files, up to [`MAX_INSPECT_FILES`]
+    max_files: Option<usize>,
+}
+
+/// A regular file in an inspected repository
+#[derive(Debug, Serialize)]
+struct InspectedFile {
+    /// Relative to the repository root, `/`-separated
+    path: String,
+    size: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct InspectResponse {
+    /// Always `inspected`; failures get a [`DetectResponse`] with `error`
+    status: String,
+    message: String,
+    /// The first files in path order, at most `max_files` of them
+    files: Vec<InspectedFile>,
+    total_files: usize,
+    total_bytes: u64,
+    /// Files left out of `files` by the limit
+    omitted_files: usize,
+    /// What `/detect` reports for the repository, when it has a supported build system
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detection: Option<DetectionReport>,
+}
+
+/// Every regular file under `root`, sorted by path
+async fn list_files(root: &Path) -> Vec<InspectedFile> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(mut entries) = fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                let path = entry.path();
+                let Ok(relative) = path.strip_prefix(root) else {
+                    continue;
+                };
+                files.push(InspectedFile {
+                    path: relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"),
+                    size: entry.metadata().await.map(|m| m.len()).unwrap_or(0),
+                });
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+/// Read a `multipart/form-data` inspect request: a `metadata` JSON part with the
+/// InspectParams fields other than `archive_url`, and an `archive` file part
+async fn read_inspect_upload(
+    mut multipart: Multipart,
+    upload_dir: &Path,
+    max_bytes: u64,
+) -> Result<(InspectParams, UploadedArchive), (StatusCode, Json<DetectResponse>)> {
+    let invalid = |e: MultipartError| detect_error(e.status(), format!("invalid multipart request: {}", e.body_text()));
+    let mut params: Option<InspectParams> = None;
+    let mut upload: Option<UploadedArchive> = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        match field.name() {
+            Some("metadata") => {
+                let text = field.text().await.map_err(invalid)?;
+                let parsed: InspectParams = serde_json::from_str(&text).map_err(|e| {
+                    detect_error(StatusCode::BAD_REQUEST, format!("invalid request: metadata part: {}", e))
+                })?;
+                if !parsed.archive_url.is_empty() {
+                    return Err(detect_error(
+                        StatusCode::BAD_REQUEST,
+                        "invalid request: archive_url cannot be combined with an uploaded archive".to_string(),
+                    ));
+                }
+                params = Some(parsed);
+            }
+            Some("archive") => {
+                let archive = store_upload(field, upload_dir, max_bytes)
+                    .await
+                    .map_err(|(status, message)| detect_error(status, message))?;
+                upload = Some(archive);
+            }
+            _ => {}
+        }
+    }
+
+    match (params, upload) {
+        (Some(params), Some(upload)) => Ok((params, upload)),
+        (None, _) => Err(detect_error(StatusCode::BAD_REQUEST, "invalid request: missing metadata part".to_string())),
+        (_, None) => Err(detect_error(StatusCode::BAD_REQUEST, "invalid request: missing archive part".to_string())),
+    }
+}
+
+/// `POST /inspect` lists the files of a repository, with their sizes, alongside what detection
+/// makes of it. Like `/detect` it records no job and removes the workspace before responding,
+/// but the archive is always downloaded, or uploaded as multipart, so the sizes are real.
+async fn inspect_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    request: Request,
+) -> Result<Json<InspectResponse>, (StatusCode, Json<DetectResponse>)> {
+    if let Err(e) = state.detect_limiter.try_acquire() {
+        warn!("Rejecting inspect request: {}", e);
+        return Err(detect_error(StatusCode::TOO_MANY_REQUESTS, format!("rate limited: {}", e)));
+    }
+
+    let is_multipart = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+    let (params, upload) = if is_multipart {
+        let multipart = Multipart::from_request(request, &state)
+            .await
+            .map_err(|e| detect_error(e.status(), format!("invalid multipart request: {}", e.body_text())))?;
+        let upload_dir = state.customer_config.dirs.root().join("uploads");
+        let (params, upload) = read_inspect_upload(multipart, &upload_dir, state.max_upload_bytes).await?;
+        (params, Some(upload))
+    } else {
+        let JsonExtract(params) = JsonExtract::<InspectParams>::from_request(request, &state)
+            .await
+            .map_err(|e| detect_error(e.status(), format!("invalid request: {}", e.body_text())))?;
+        if let Some(problem) = archive_url_problem(&params.archive_url) {
+            return Err(detect_error(StatusCode::BAD_REQUEST, format!("invalid request: {}", problem)));
+        }
+        (params, None)
+    };
+    if !state.customer_config.validate_installation_id(&params.installation_id) {
+        return Err(detect_error(
+            StatusCode::FORBIDDEN,
+            format!("Installation ID {} not allowed for this customer", params.installation_id),
+        ));
+    }
+
+    let workspace = setup_workspace(&state.customer_config.dirs, &format!("inspect-{}", Uuid::new_v4()))
+        .await
+        .map_err(|e| detect_error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create workspace: {}", e)))?;
+    let fetched = match &upload {
+        Some(upload) => {
+            let repo_dir = workspace.join("repo");
+            extract_archive(&upload.0, &repo_dir, 0, max_repo_files()).await.map(|_| repo_dir)
+        }
+        None => fetch_and_extract_repository(&state.http, &params.archive_url, None, &workspace).await,
+    };
+    let inspected = match fetched {
+        Ok(repo_dir) => Ok((list_files(&repo_dir).await, crate::detection::analyze(&repo_dir).await)),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = fs::remove_dir_all(&workspace).await {
+        warn!("Failed to remove inspect workspace {}: {}", workspace.display(), e);
+    }
+
+    let (mut files, detection) = match inspected {
+        Ok(inspected) => inspected,
+        Err(e) if upload.is_some() => {
+            return Err(detect_error(StatusCode::BAD_REQUEST, format!("invalid request: archive could not be unpacked: {}", e)))
+        }
+        Err(e) => return Err(detect_error(StatusCode::BAD_GATEWAY, format!("Failed to fetch repository: {}", e))),
+    };
+    let total_files = files.len();
+    let total_bytes = files.iter().map(|file| file.size).sum();
+    files.truncate(params.max_files.unwrap_or(MAX_INSPECT_FILES).min(MAX_INSPECT_FILES));
+    Ok(Json(InspectResponse {
+        status: "inspected".to_string(),
+        message: match &detection {
+            Some(report) => format!("Detected {:?}", report.build_system),
+            None => crate::detection::UNDETECTED_MESSAGE.to_string(),
+        },
+        omitted_files: total_files - files.len(),
+        files,
+        total_files,
+        total_bytes,
+        detection,
+    }))
+}
+
+/// A tracked job: its status, timestamps, output and audit trail
+async fn job_handler(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<BuildJob>, StatusCode> {
+    let jobs = state.job_manager.read().unwrap();
+    jobs.get(id).cloned().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// The audit trail of a tracked job
+async fn job_events_handler(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let jobs = state.job_manager.read().unwrap();
+    let job = jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(serde_json::json!({
+        "job_id": job.id,
+        "status": job.status,
+        "events": job.events,
+        "events_omitted": job.events_omitted,
+    })))
+}
+
+/// Fingerprints `GET /admin/failures` returns without `limit`
+const DEFAULT_FAILURES_LIMIT: usize = 20;
+
+#[derive(Debug, Deserialize)]
+struct FailuresQuery {
+    /// `owner/repo`
+    repo: Option<String>,
+    limit: Option<usize>,
+}
+
+/// The errors a repository's builds failed with since the runner started, by fingerprint,
+/// most frequent first
+async fn admin_failures_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<FailuresQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<BuildResponse>)> {
+    if let Some((status, message)) = admin_auth_problem(&state, &headers) {
+        return Err(error_response(status, message));
+    }
+    let repo = query.repo.filter(|repo| repo.contains('/')).ok_or_else(|| {
+        error_response(StatusCode::BAD_REQUEST, "invalid request: repo is required, as owner/repo".to_string())
+    })?;
+    let records = state.job_manager.read().unwrap().failures(&repo);
+    let total: u64 = records.iter().map(|record| record.occurrences).sum();
+    let fingerprints = records.len();
+    let top: Vec<_> = records.into_iter().take(query.limit.unwrap_or(DEFAULT_FAILURES_LIMIT)).collect();
+    Ok(Json(serde_json::json!({
+        "repo": repo,
+        "total_failures": total,
+        "fingerprints": fingerprints,
+        "failures": top,
+    })))
+}
+
+/// Why the request may not use an admin endpoint: its
+/// `Authorization: Bearer` token isn't `NABLA_ADMIN_TOKEN`, or no token is configured
+fn admin_auth_problem(state: &AppState, headers: &HeaderMap) -> Option<(StatusCode, String)> {
+    let Some(token) = &state.admin_token else {
+        return Some((
+            StatusCode::FORBIDDEN,
+            "admin endpoints are disabled on this runner (NABLA_ADMIN_TOKEN is not set)".to_string(),
+        ));
+    };
+    let presented = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if tokens_match(presented, token) => None,
+        _ => Some((StatusCode::UNAUTHORIZED, "missing or invalid admin token".to_string())),
+    }
+}
+
+/// Compare without returning early on the first differing byte
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Select workspaces, logs and caches to remove. A `dry_run` returns the selection with the
+/// bytes it takes up; otherwise the removal runs in the background and its task is returned.
+async fn admin_prune_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    request: Request,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<BuildResponse>)> {
+    if let Some((status, message)) = admin_auth_problem(&state, &headers) {
+        return Err(error_response(status, message));
+    }
+    let JsonExtract(prune) = JsonExtract::<PruneRequest>::from_request(request, &state)
+        .await
+        .map_err(|e| error_response(e.status(), format!("invalid request: {}", e.body_text())))?;
+
+    let tracked = TrackedPaths::of(&state.job_manager.read().unwrap());
+    let plan = crate::prune::plan(&state.customer_config.dirs, &prune, &tracked, &state.caches).await;
+    if prune.dry_run {
+        return Ok((
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "dry_run": true,
+                "total_bytes": plan.total_bytes(),
+                "entries": plan.entries,
+                "skipped": plan.skipped,
+            })),
+        ));
+    }
+
+    let task_id = state.prune_tasks.register(&plan);
+    info!("Pruning {} entries ({} bytes) as task {}", plan.entries.len(), plan.total_bytes(), task_id);
+    let progress = state.prune_tasks.get(task_id);
+    let state = state.clone();
+    tokio::spawn(async move {
+        crate::prune::execute(plan, task_id, &state.prune_tasks, &state.job_manager, &state.caches).await;
+    });
+    Ok((StatusCode::ACCEPTED, Json(serde_json::json!(progress))))
+}
+
+/// Progress of a prune started with `POST /admin/prune`
+async fn admin_prune_task_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<crate::prune::PruneProgress>, (StatusCode, Json<BuildResponse>)> {
+    if let Some((status, message)) = admin_auth_problem(&state, &headers) {
+        return Err(error_response(status, message));
+    }
+    state
+        .prune_tasks
+        .get(id)
+        .map(Json)
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, format!("no prune task {}", id)))
+}
+
+/// Lines `GET /jobs/:id/logs` returns without `tail`
+const DEFAULT_LOG_TAIL: usize = 100;
+
+/// Lines of the full log one `GET /jobs/:id/logs?full=true` returns without `limit`
+const DEFAULT_LOG_PAGE_LINES: usize = 1000;
+
+/// Most lines of the full log one request may ask for
+const MAX_LOG_PAGE_LINES: usize = 10_000;
+
+/// A page of the full log ends early once it holds this many bytes
+const MAX_LOG_PAGE_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct LogsQuery {
+    tail: Option<usize>,
+    /// Page through the complete log kept with `NABLA_KEEP_BUILD_LOGS` instead
+    #[serde(default)]
+    full: bool,
+    /// First line of the page, counting from 0
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+/// The latest lines of a job's build output, also while it's running. `id` is the runner's job
+/// id or, since a synchronous `/build` only returns that once it's done, the client's `job_id`.
+/// With `full=true`, a page of the complete log instead, see [`full_log_page`].
+async fn job_logs_handler(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, StatusCode> {
+    let (log_file, live_log) = {
+        let jobs = state.job_manager.read().unwrap();
+        let job = match Uuid::parse_str(&id) {
+            Ok(id) => jobs.get(id),
+            Err(_) => jobs.find_client_job(&id),
+        }
+        .ok_or(StatusCode::NOT_FOUND)?;
+        if !query.full {
+            let tail = query.tail.unwrap_or(DEFAULT_LOG_TAIL).min(LIVE_LOG_LINES);
+            return Ok(Json(serde_json::json!({
+                "job_id": job.id,
+                "status": job.status,
+                "lines": job.live_log.tail(tail),
+            }))
+            .into_response());
+        }
+        let log_file = kept_log_file(&state, &job.client_job_id).filter(|file| job.files.contains(file));
+        (log_file.ok_or(StatusCode::NOT_FOUND)?, job.live_log.clone())
+    };
+
+    let limit = query.limit.unwrap_or(DEFAULT_LOG_PAGE_LINES).clamp(1, MAX_LOG_PAGE_LINES);
+    full_log_page(&log_file, &live_log, query.offset, limit).await.map_err(|e| {
+        warn!("Failed to read {}: {}", log_file.display(), e);
+        StatusCode::NOT_FOUND
+    })
+}
+
+/// Lines `offset..offset + limit` of the log at `path`, redacted like the live log, as text.
+/// A page that leaves out part of the log is a 206 whose `Content-Range: lines 0-999/5000`
+/// names its first and last line and the log's line count; an `offset` past the end is a 416
+/// with `Content-Range: lines */5000`.
+async fn full_log_page(path: &Path, live_log: &LiveLog, offset: usize, limit: usize) -> std::io::Result<Response> {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut reader = tokio::io::BufReader::new(fs::File::open(path).await?);
+    let mut page = String::new();
+    let mut end = offset;
+    let mut total = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        if total == end && end - offset < limit && page.len() < MAX_LOG_PAGE_BYTES {
+            let text = String::from_utf8_lossy(&line);
+            page.push_str(&live_log.redact(text.trim_end_matches(['\n', '\r'])));
+            page.push('\n');
+            end += 1;
+        }
+        total += 1;
+    }
+
+    let text = [(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"))];
+    if offset > 0 && offset >= total {
+        let range = HeaderValue::from_str(&format!("lines */{}", total)).expect("digits are a valid header");
+        return Ok((StatusCode::RANGE_NOT_SATISFIABLE, text, [(CONTENT_RANGE, range)]).into_response());
+    }
+    if offset == 0 && end == total {
+        return Ok((text, page).into_response());
+    }
+    let range = HeaderValue::from_str(&format!("lines {}-{}/{}", offset, end - 1, total)).expect("digits are a valid header");
+    Ok((StatusCode::PARTIAL_CONTENT, text, [(CONTENT_RANGE, range)], page).into_response())
+}
+
+/// Build cache statistics summed over every build since the runner started
+async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], state.cache_metrics.render())
+}
+
+/// The JSON Schema `build_config` is validated against
+async fn build_config_schema_handler() -> Json<serde_json::Value> {
+    Json(build_config_schema())
+}
+
 pub fn create_app() -> Router {
     let state = Arc::new(AppState::default());
+    let body_limit = state.max_upload_bytes.saturating_add(MULTIPART_OVERHEAD_BYTES);
 
     Router::new()
         .route("/build", post(build_handler))
+        .layer(DefaultBodyLimit::max(usize::try_from(body_limit).unwrap_or(usize::MAX)))
         .route("/health", get(health_handler))
+        .route("/ready", get(ready_handler))
+        .route("/capabilities", get(capabilities_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/schema/build_config.json", get(build_config_schema_handler))
+        .route("/jobs/:id", get(job_handler))
+        .route("/jobs/:id/events", get(job_events_handler))
+        .route("/jobs/:id/logs", get(job_logs_handler))
+        .route("/admin/failures", get(admin_failures_handler))
+        .route("/admin/prune", post(admin_prune_handler))
+        .route("/admin/prune/:id", get(admin_prune_task_handler))
+        .route("/detect", post(detect_handler))
+        .route(
+            "/inspect",
+            post(inspect_handler).layer(DefaultBodyLimit::max(usize::try_from(body_limit).unwrap_or(usize::MAX))),
+        )
         .layer(
             ServiceBuilder::new()
                 .layer(CorsLayer::permissive())
@@ -377,6 +2562,10 @@ pub fn create_app() -> Router {
 }
 
 pub async fn run_server(port: u16) -> Result<()> {
+    // An invalid limits file stops the runner here rather than at its first build
+    crate::limits::init_from_env()?;
+    // Likewise a SELF_TEST naming a build system the self-test doesn't know
+    crate::selftest::systems_from_env()?;
     let app = create_app();
     
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
@@ -0,0 +1,100 @@
+use anyhow::Result;
+use ignore::WalkBuilder;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+tokio::task_local! {
+    static NEW_FILES_ONLY: Option<Arc<SourceSnapshot>>;
+}
+
+/// The source files of a repository as it was before the build: every file not excluded by a
+/// `.gitignore` (or `.ignore`), outside `.git`
+#[derive(Debug, Clone)]
+pub struct SourceSnapshot {
+    /// Hex sha256 over each file's path relative to the root and its contents, in path order
+    pub sha256: String,
+    /// Size and modification time of each file, to tell untouched sources from build outputs
+    files: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl SourceSnapshot {
+    /// Hash the repository at `root`. Build directories listed in `.gitignore` don't affect the
+    /// hash, so it identifies the source whether or not the tree was built before.
+    pub async fn take(root: &Path) -> Result<Self> {
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || Self::take_blocking(&root)).await?
+    }
+
+    fn take_blocking(root: &Path) -> Result<Self> {
+        let mut paths = Vec::new();
+        let walker = WalkBuilder::new(root)
+            .hidden(false)
+            // Only the repository's own ignore files; nothing from the runner's environment
+            .parents(false)
+            .git_global(false)
+            .git_exclude(false)
+            .require_git(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        for entry in walker {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|kind| kind.is_file()) {
+                paths.push(entry.into_path());
+            }
+        }
+        paths.sort();
+
+        let mut hasher = Sha256::new();
+        let mut files = HashMap::new();
+        let mut buffer = vec![0; 64 * 1024];
+        for path in paths {
+            let relative = path.strip_prefix(root)?;
+            let mut file = std::fs::File::open(&path)?;
+            let metadata = file.metadata()?;
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(metadata.len().to_le_bytes());
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+            files.insert(path, (metadata.len(), metadata.modified().ok()));
+        }
+
+        Ok(Self {
+            sha256: format!("{:x}", hasher.finalize()),
+            files,
+        })
+    }
+
+    /// Whether `path` is a source file the build left as it was
+    pub fn is_unchanged_source(&self, path: &Path) -> bool {
+        let Some((len, modified)) = self.files.get(path) else {
+            return false;
+        };
+        std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == *len && metadata.modified().ok() == *modified)
+    }
+}
+
+/// Run `future` with artifact discovery limited to files the build created or changed, when
+/// given a snapshot, so a binary committed to the repository isn't mistaken for the build's
+/// output
+pub async fn new_files_only<F: Future>(snapshot: Option<Arc<SourceSnapshot>>, future: F) -> F::Output {
+    NEW_FILES_ONLY.scope(snapshot, future).await
+}
+
+/// Whether artifact discovery should pass over `path`: inside [`new_files_only`] with a
+/// snapshot, an untouched source file
+pub fn skip_as_artifact(path: &Path) -> bool {
+    NEW_FILES_ONLY
+        .try_with(|snapshot| snapshot.as_ref().is_some_and(|snapshot| snapshot.is_unchanged_source(path)))
+        .unwrap_or(false)
+}
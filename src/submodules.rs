@@ -0,0 +1,168 @@
+use crate::core::BuildConfig;
+use crate::output::OutputBuffer;
+use crate::process::run_command;
+use crate::remote::GithubArchive;
+use anyhow::{anyhow, Result};
+use base64::Engine as _;
+use std::fmt;
+use std::path::{Component, Path};
+use tokio::fs;
+use tokio::process::Command;
+
+/// Where the repository behind an archive can be fetched with git, so submodules the archive
+/// leaves out can be checked out at the commits it pins
+#[derive(Clone, PartialEq, Eq)]
+pub struct GitSource {
+    pub url: String,
+    /// Branch, tag or commit the archive was made from; `None` is the default branch
+    pub reference: Option<String>,
+    /// GitHub token for private repositories, sent as HTTP basic auth to github.com only
+    pub token: Option<String>,
+}
+
+impl GitSource {
+    /// The repository a GitHub archive URL points at
+    pub fn from_archive(archive: &GithubArchive, token: Option<String>) -> Self {
+        Self {
+            url: format!("https://github.com/{}/{}.git", archive.owner, archive.repo),
+            reference: archive.reference.clone(),
+            token,
+        }
+    }
+}
+
+impl fmt::Debug for GitSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GitSource")
+            .field("url", &self.url)
+            .field("reference", &self.reference)
+            .field("token", &self.token.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+/// A `[submodule "..."]` section of `.gitmodules`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    pub url: Option<String>,
+}
+
+/// The submodules a `.gitmodules` file declares. Sections without a `path` are left out.
+pub fn parse_gitmodules(contents: &str) -> Vec<Submodule> {
+    let mut submodules = Vec::new();
+    let mut current: Option<(String, Option<String>, Option<String>)> = None;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            submodules.extend(current.take().and_then(|(name, path, url)| Some(Submodule { name, path: path?, url })));
+            current = line
+                .strip_prefix("[submodule")
+                .and_then(|rest| rest.trim().strip_suffix(']'))
+                .map(|name| (name.trim().trim_matches('"').to_string(), None, None));
+            continue;
+        }
+        let (Some((_, path, url)), Some((key, value))) = (current.as_mut(), line.split_once('=')) else {
+            continue;
+        };
+        match key.trim() {
+            "path" => *path = Some(value.trim().to_string()),
+            "url" => *url = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    submodules.extend(current.and_then(|(name, path, url)| Some(Submodule { name, path: path?, url })));
+    submodules
+}
+
+/// Submodules declared in `repo_dir/.gitmodules` whose directories are missing or empty, as
+/// in every GitHub archive
+pub async fn missing_submodules(repo_dir: &Path) -> Vec<Submodule> {
+    let Ok(contents) = fs::read_to_string(repo_dir.join(".gitmodules")).await else {
+        return Vec::new();
+    };
+    let mut missing = Vec::new();
+    for submodule in parse_gitmodules(&contents) {
+        let path = Path::new(&submodule.path);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            continue;
+        }
+        let empty = match fs::read_dir(repo_dir.join(path)).await {
+            Ok(mut entries) => entries.next_entry().await.ok().flatten().is_none(),
+            Err(_) => true,
+        };
+        if empty {
+            missing.push(submodule);
+        }
+    }
+    missing
+}
+
+/// What a build would say about `missing` submodules: nothing when they'll be fetched
+pub fn missing_warning(missing: &[Submodule], source: Option<&GitSource>, config: &BuildConfig) -> Option<String> {
+    if missing.is_empty() {
+        return None;
+    }
+    let paths = missing.iter().map(|s| s.path.as_str()).collect::<Vec<_>>().join(", ");
+    match (config.fetch_submodules, source) {
+        (true, Some(_)) => None,
+        (true, None) => Some(format!(
+            "Submodules {} are missing from the archive and can't be fetched: fetch_submodules needs a GitHub archive_url to clone from",
+            paths
+        )),
+        (false, _) => Some(format!(
+            "Submodules {} are missing from the archive; set fetch_submodules to fetch them",
+            paths
+        )),
+    }
+}
+
+/// Fetch the submodules missing from the archive at `repo_dir` when `fetch_submodules` is set
+/// and there's a `source` to fetch from. Returns a warning naming them when they stay missing.
+pub async fn prepare_submodules(repo_dir: &Path, source: Option<&GitSource>, config: &BuildConfig) -> Result<Vec<String>> {
+    let missing = missing_submodules(repo_dir).await;
+    if let Some(warning) = missing_warning(&missing, source, config) {
+        tracing::warn!("{}", warning);
+        return Ok(vec![warning]);
+    }
+    let Some(source) = source.filter(|_| !missing.is_empty()) else {
+        return Ok(Vec::new());
+    };
+
+    // The archive has the working tree but no history; fetch the commit it was made from so
+    // its index pins each submodule, then check them out
+    let reference = source.reference.as_deref().unwrap_or("HEAD");
+    let steps: [&[&str]; 4] = [
+        &["init", "-q"],
+        &["fetch", "-q", "--depth", "1", &source.url, reference],
+        &["read-tree", "FETCH_HEAD"],
+        &["submodule", "update", "--init", "--recursive", "--depth", "1"],
+    ];
+    for args in steps {
+        let mut command = Command::new("git");
+        command.args(args).current_dir(repo_dir);
+        if let Some(token) = &source.token {
+            let credentials = base64::engine::general_purpose::STANDARD.encode(format!("x-access-token:{}", token));
+            command
+                .env("GIT_CONFIG_COUNT", "1")
+                .env("GIT_CONFIG_KEY_0", "http.https://github.com/.extraheader")
+                .env("GIT_CONFIG_VALUE_0", format!("Authorization: Basic {}", credentials));
+        }
+        let output = run_command(command, config)
+            .await
+            .map_err(|e| anyhow!("Fetching submodules: git {} failed: {}", args[0], e))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Fetching submodules: git {} failed: {}",
+                args[0],
+                OutputBuffer::text(&output.stderr).trim()
+            ));
+        }
+    }
+    tracing::info!(
+        "Fetched submodules {} from {}",
+        missing.iter().map(|s| s.path.as_str()).collect::<Vec<_>>().join(", "),
+        source.url
+    );
+    Ok(Vec::new())
+}
@@ -0,0 +1,184 @@
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+/// Where a Zephyr repository keeps its west manifest and the application to build.
+///
+/// In a T1 layout the manifest repository is the application: `west.yml` and `CMakeLists.txt`
+/// sit side by side. In a T2 layout the application lives in a subdirectory, either the one
+/// `west.yml` names in `self: path:` or the conventional `app/` next to the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WestLayout {
+    /// Directory holding `west.yml`, relative to the repository; empty for the root
+    pub manifest_dir: PathBuf,
+    /// The application `west build` builds, relative to the repository; empty for the root
+    pub app_dir: PathBuf,
+    /// A west workspace already exists, in the repository or the directory it was unpacked in
+    pub initialized: bool,
+    /// Paths the manifest or workspace config names that aren't in the repository
+    pub warnings: Vec<String>,
+}
+
+impl WestLayout {
+    /// Arguments for `west init -l`, run from the repository
+    pub fn init_args(&self) -> Vec<String> {
+        vec!["init".to_string(), "-l".to_string(), display(&self.manifest_dir)]
+    }
+}
+
+/// `.` for the repository root, otherwise the relative path
+fn display(dir: &Path) -> String {
+    if dir.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        dir.display().to_string()
+    }
+}
+
+/// The `path` of the `self:` stanza in a west.yml, e.g. `app` for
+///
+/// ```yaml
+/// manifest:
+///   self:
+///     path: app
+/// ```
+pub fn manifest_self_path(manifest: &str) -> Option<String> {
+    let mut self_indent = None;
+    for line in manifest.lines() {
+        let content = line.split(" #").next().unwrap_or(line).trim_end();
+        let trimmed = content.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = content.len() - trimmed.len();
+
+        match self_indent {
+            None if trimmed == "self:" => self_indent = Some(indent),
+            Some(outer) if indent <= outer => self_indent = (trimmed == "self:").then_some(indent),
+            Some(_) => {
+                if let Some(value) = trimmed.strip_prefix("path:") {
+                    let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+                    return (!value.is_empty()).then(|| value.to_string());
+                }
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+/// Whether a west.yml imports the nRF Connect SDK: a project named `nrf`, or one fetched from
+/// the `sdk-nrf` repository
+///
+/// ```yaml
+/// manifest:
+///   projects:
+///     - name: nrf
+///       remote: ncs
+///       revision: v2.6.0
+///       import: true
+/// ```
+pub fn manifest_uses_nrf(manifest: &str) -> bool {
+    manifest.lines().any(|line| {
+        let content = line.split(" #").next().unwrap_or(line).trim();
+        let content = content.strip_prefix("- ").unwrap_or(content).trim_start();
+        let Some((key, value)) = content.split_once(':') else {
+            return false;
+        };
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        match key.trim() {
+            "name" => value == "nrf",
+            "repo-path" => value == "sdk-nrf",
+            "url" => value.trim_end_matches(".git").ends_with("/sdk-nrf"),
+            _ => false,
+        }
+    })
+}
+
+/// Boards an application has its own `boards/<board>.overlay` or `boards/<board>.conf` for,
+/// sorted. Zephyr writes board names with `/` as `_` in these file names.
+pub async fn board_overlays(app_dir: &Path) -> Vec<String> {
+    let mut boards = Vec::new();
+    let Ok(mut entries) = fs::read_dir(app_dir.join("boards")).await else {
+        return boards;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let board_file = matches!(path.extension().and_then(|e| e.to_str()), Some("overlay" | "conf"));
+        if let Some(stem) = path.file_stem().filter(|_| board_file && path.is_file()) {
+            boards.push(stem.to_string_lossy().to_string());
+        }
+    }
+    boards.sort();
+    boards.dedup();
+    boards
+}
+
+/// The manifest repository's path in a workspace's `.west/config`:
+///
+/// ```ini
+/// [manifest]
+/// path = app
+/// ```
+pub fn workspace_manifest_path(config: &str) -> Option<String> {
+    let mut in_manifest = false;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_manifest = line == "[manifest]";
+        } else if let Some((key, value)) = line.split_once('=') {
+            if in_manifest && key.trim() == "path" && !value.trim().is_empty() {
+                return Some(value.trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// A relative path that stays inside the repository
+fn within_repository(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        .then(|| path.components().collect())
+}
+
+/// Work out where the manifest and application of the Zephyr repository at `repo` are
+pub async fn layout(repo: &Path) -> WestLayout {
+    let mut warnings = Vec::new();
+    // `west init -l` makes the directory the manifest repository sits in the workspace
+    let initialized = repo.join(".west").is_dir() || repo.parent().is_some_and(|parent| parent.join(".west").is_dir());
+
+    let mut manifest_dir = PathBuf::new();
+    if !repo.join("west.yml").is_file() {
+        let config = fs::read_to_string(repo.join(".west/config")).await.unwrap_or_default();
+        if let Some(path) = workspace_manifest_path(&config) {
+            match within_repository(&path).filter(|dir| repo.join(dir).join("west.yml").is_file()) {
+                Some(dir) => manifest_dir = dir,
+                None => warnings.push(format!(".west/config names manifest path '{}', which has no west.yml in the repository", path)),
+            }
+        }
+    }
+
+    let manifest = fs::read_to_string(repo.join(&manifest_dir).join("west.yml")).await.unwrap_or_default();
+    let mut candidates = Vec::new();
+    if let Some(path) = manifest_self_path(&manifest) {
+        match within_repository(&path).filter(|dir| repo.join(dir).is_dir()) {
+            Some(dir) => candidates.push(dir),
+            None => warnings.push(format!("west.yml names self path '{}', which is not in the repository", path)),
+        }
+    }
+    candidates.push(manifest_dir.clone());
+    candidates.push(manifest_dir.join("app"));
+
+    let app_dir = candidates
+        .into_iter()
+        .find(|dir| repo.join(dir).join("CMakeLists.txt").is_file())
+        .unwrap_or_else(|| manifest_dir.clone());
+
+    WestLayout {
+        manifest_dir,
+        app_dir,
+        initialized,
+        warnings,
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const MAX_CUSTOMER_DIR_LEN: usize = 64;
+
+/// Root under which all per-customer state lives: `/workspace` in the container, a temp
+/// directory for local development.
+/// Names the directory where a build may leave its output. Files there are returned as
+/// artifacts ahead of the ones the runner finds itself.
+pub const OUT_DIR_ENV: &str = "NABLA_OUT_DIR";
+
+pub fn workspace_root() -> PathBuf {
+    if Path::new("/workspace").exists() {
+        PathBuf::from("/workspace")
+    } else {
+        env::temp_dir().join("nabla-workspace")
+    }
+}
+
+/// Make a customer id safe to use as a single path component. Ids that had to be altered get a
+/// short hash suffix so that e.g. `acme/a` and `acme_a` still map to different directories.
+pub fn sanitize_customer_id(customer_id: &str) -> String {
+    let cleaned: String = customer_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .take(MAX_CUSTOMER_DIR_LEN)
+        .collect();
+
+    if cleaned == customer_id && !cleaned.is_empty() {
+        return cleaned;
+    }
+
+    let digest = Sha256::digest(customer_id.as_bytes());
+    let suffix: String = digest.iter().take(4).map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", cleaned, suffix)
+}
+
+/// Each tool cache variable [`CustomerDirs::cache_env`] sets, with its directory under the cache root
+pub const CACHE_DIR_VARS: [(&str, &str); 3] = [
+    ("PLATFORMIO_CORE_DIR", "platformio"),
+    ("CCACHE_DIR", "ccache"),
+    ("XDG_CACHE_HOME", "xdg"),
+];
+
+/// On-disk locations owned by one customer: job workspaces and tool caches
+/// (PlatformIO packages, ccache objects, west/pip caches). Everything is created mode 0700.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomerDirs {
+    root: PathBuf,
+    cache_root: PathBuf,
+}
+
+impl CustomerDirs {
+    /// Caches live under the customer's own root unless `shared_caches` is set, in which case
+    /// every customer uses `<workspace_root>/shared-cache`.
+    pub fn new(workspace_root: &Path, customer_id: &str, shared_caches: bool) -> Self {
+        let root = workspace_root.join(sanitize_customer_id(customer_id));
+        let cache_root = if shared_caches {
+            workspace_root.join("shared-cache")
+        } else {
+            root.join("cache")
+        };
+
+        Self { root, cache_root }
+    }
+
+    /// Reads `NABLA_SHARE_CUSTOMER_CACHES` to decide whether caches are shared across customers.
+    pub fn from_env(customer_id: &str) -> Self {
+        let shared = env::var("NABLA_SHARE_CUSTOMER_CACHES")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        Self::new(&workspace_root(), customer_id, shared)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn cache_root(&self) -> &Path {
+        &self.cache_root
+    }
+
+    /// Whether caches are shared with every customer's runner rather than this customer's own
+    pub fn shares_caches(&self) -> bool {
+        !self.cache_root.starts_with(&self.root)
+    }
+
+    /// Bytes the customer's workspaces and kept logs take up, which the disk quota limits. Tool
+    /// caches under the root don't count: they're the runner's to keep warm and to prune.
+    pub async fn workspace_bytes(&self) -> u64 {
+        let total = dir_size(&self.root).await;
+        match self.shares_caches() {
+            true => total,
+            false => total.saturating_sub(dir_size(&self.cache_root).await),
+        }
+    }
+
+    pub fn job_workspace(&self, job_id: &str) -> PathBuf {
+        self.root.join(format!("job-{}", job_id))
+    }
+
+    /// Environment describing a job's workspace to its build commands: `NABLA_WORKSPACE`, its
+    /// `out/` directory as `NABLA_OUT_DIR`, and the client's `NABLA_JOB_ID`
+    pub fn workspace_env(&self, job_id: &str) -> BTreeMap<String, String> {
+        let workspace = self.job_workspace(job_id);
+        BTreeMap::from([
+            ("NABLA_WORKSPACE".to_string(), workspace.to_string_lossy().to_string()),
+            (OUT_DIR_ENV.to_string(), workspace.join("out").to_string_lossy().to_string()),
+            ("NABLA_JOB_ID".to_string(), job_id.to_string()),
+        ])
+    }
+
+    /// Environment that points each build tool's cache at this customer's cache root
+    pub fn cache_env(&self) -> BTreeMap<String, String> {
+        CACHE_DIR_VARS
+            .iter()
+            .map(|(var, name)| (var.to_string(), self.cache_root.join(name).to_string_lossy().to_string()))
+            .collect()
+    }
+
+    /// Create the customer root and cache directories with owner-only permissions
+    pub async fn ensure(&self) -> Result<()> {
+        create_private_dir(&self.root).await?;
+        create_private_dir(&self.cache_root).await?;
+        for dir in self.cache_env().values() {
+            create_private_dir(Path::new(dir)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// `create_dir_all` followed by chmod 0700 on the leaf
+pub async fn create_private_dir(path: &Path) -> Result<()> {
+    fs::create_dir_all(path).await?;
+    fs::set_permissions(path, Permissions::from_mode(0o700)).await?;
+    Ok(())
+}
+
+/// Total size in bytes of all regular files under `path`; missing directories count as empty.
+pub async fn dir_size(path: &Path) -> u64 {
+    let mut total = 0;
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(mut entries) = fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                total += entry.metadata().await.map(|m| m.len()).unwrap_or(0);
+            }
+        }
+    }
+
+    total
+}
@@ -0,0 +1,80 @@
+use nabla_runner::archive::extract_archive;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tempfile::TempDir;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+const FILES: usize = 6;
+
+/// A repository of [`FILES`] files spread over nested directories, wrapped in one top-level
+/// directory like a GitHub tarball
+fn synthetic_repo() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    for i in 0..FILES {
+        let path = dir.path().join("repo-main").join(format!("src/mod{}/file{}.c", i % 2, i));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "int x;\n").unwrap();
+    }
+    dir
+}
+
+fn extracted_files(dir: &Path) -> usize {
+    walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()).count()
+}
+
+#[tokio::test]
+async fn test_tar_over_file_limit_is_refused() {
+    let repo = synthetic_repo();
+    let work = TempDir::new().unwrap();
+    let archive = work.path().join("repo.tar.gz");
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(repo.path())
+        .arg("repo-main")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let dest = work.path().join("too-many");
+    let error = extract_archive(&archive, &dest, 1, FILES - 1).await.unwrap_err().to_string();
+    assert!(error.contains(&format!("more than {} files", FILES - 1)), "{}", error);
+    assert!(error.contains("NABLA_MAX_REPO_FILES"), "{}", error);
+    assert_eq!(extracted_files(&dest), 0);
+
+    // Directories don't count towards the limit
+    let dest = work.path().join("at-limit");
+    extract_archive(&archive, &dest, 1, FILES).await.unwrap();
+    assert_eq!(extracted_files(&dest), FILES);
+    assert!(dest.join("src/mod1/file5.c").is_file());
+}
+
+#[tokio::test]
+async fn test_zip_over_file_limit_is_refused() {
+    let repo = synthetic_repo();
+    let work = TempDir::new().unwrap();
+    let archive = work.path().join("repo.zip");
+    let mut zip = ZipWriter::new(fs::File::create(&archive).unwrap());
+    for entry in walkdir::WalkDir::new(repo.path()).into_iter().filter_map(|e| e.ok()) {
+        let name = entry.path().strip_prefix(repo.path()).unwrap().to_string_lossy().to_string();
+        if entry.file_type().is_file() {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(b"int x;\n").unwrap();
+        } else if !name.is_empty() {
+            zip.add_directory(name, FileOptions::default()).unwrap();
+        }
+    }
+    zip.finish().unwrap();
+
+    let dest = work.path().join("too-many");
+    let error = extract_archive(&archive, &dest, 0, FILES - 1).await.unwrap_err().to_string();
+    assert!(error.contains(&format!("more than {} files", FILES - 1)), "{}", error);
+    assert_eq!(extracted_files(&dest), 0);
+
+    let dest = work.path().join("at-limit");
+    extract_archive(&archive, &dest, 0, FILES).await.unwrap();
+    assert_eq!(extracted_files(&dest), FILES);
+}
@@ -0,0 +1,73 @@
+use nabla_runner::cmake::{minimum_required_version, parse_cmake_version, version_issue, CmakeVersion};
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::execution::execute_build_with_config;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+
+fn version(v: &str) -> CmakeVersion {
+    CmakeVersion::parse(v).unwrap()
+}
+
+#[test]
+fn test_minimum_required_version_forms() {
+    let cases = [
+        ("cmake_minimum_required(VERSION 3.20)\nproject(app C)\n", Some("3.20")),
+        ("CMAKE_MINIMUM_REQUIRED(VERSION 3.13.1 FATAL_ERROR)", Some("3.13.1")),
+        ("  cmake_minimum_required( VERSION 3.5...3.27 )", Some("3.5")),
+        ("# cmake_minimum_required(VERSION 9.0)\nproject(app)\n", None),
+        ("project(app)\n", None),
+    ];
+    for (cmakelists, expected) in cases {
+        assert_eq!(minimum_required_version(cmakelists), expected.map(version), "{}", cmakelists);
+    }
+}
+
+#[test]
+fn test_installed_version_parsed_and_compared() {
+    assert_eq!(parse_cmake_version("cmake version 3.22.1\n\nCMake suite maintained by Kitware"), Some(version("3.22.1")));
+    assert_eq!(parse_cmake_version("cmake version 3.28.0-rc2\n"), Some(version("3.28.0")));
+    assert_eq!(parse_cmake_version("not cmake"), None);
+
+    assert!(version("3.25.0").at_least(&version("3.25")));
+    assert!(version("3.22.1").at_least(&version("3.13")));
+    assert!(!version("3.22.1").at_least(&version("3.25")));
+
+    let issue = version_issue(&version("3.25"), &version("3.22.1")).unwrap();
+    assert!(issue.contains("requires CMake 3.25 or newer"), "{}", issue);
+    assert!(issue.contains("runner has CMake 3.22.1"), "{}", issue);
+    assert!(issue.contains("Upgrade CMake"), "{}", issue);
+    assert_eq!(version_issue(&version("3.13"), &version("3.22.1")), None);
+}
+
+#[tokio::test]
+async fn test_preflight_rejects_implausible_minimum_before_configure() {
+    // A stand-in cmake that reports its version and records any other invocation
+    let tools = TempDir::new().unwrap();
+    let cmake = tools.path().join("cmake");
+    fs::write(
+        &cmake,
+        format!(
+            "#!/bin/sh\nif [ \"$1\" = --version ]; then echo 'cmake version 3.22.1'; exit 0; fi\necho \"$@\" >> {}\nexit 1\n",
+            tools.path().join("invocations").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&cmake, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("CMakeLists.txt"), "cmake_minimum_required(VERSION 99.0)\nproject(app C)\n").unwrap();
+
+    let mut config = BuildConfig::default();
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+
+    let error = execute_build_with_config(repo.path(), BuildSystem::CMake, &config)
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.starts_with("CMake pre-flight check failed"), "{}", error);
+    assert!(error.contains("requires CMake 99.0 or newer"), "{}", error);
+    assert!(!tools.path().join("invocations").exists(), "configure ran despite the failed pre-flight");
+}
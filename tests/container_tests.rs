@@ -0,0 +1,279 @@
+use nabla_runner::container::{parse_image_reference, ContainerContext, ContainerPolicy, HostArch};
+use nabla_runner::core::{BuildConfig, BuildSystem, ContainerConfig, PullPolicy};
+use nabla_runner::execution::execute_build_with_config;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::process::Command;
+
+const DIGEST: &str = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+fn policy(images: &[&str], run_args: &[&str]) -> ContainerPolicy {
+    ContainerPolicy {
+        docker: "docker".to_string(),
+        allowed_images: images.iter().map(|i| i.to_string()).collect(),
+        allowed_run_args: run_args.iter().map(|a| a.to_string()).collect(),
+        pull_timeout: Duration::from_secs(5),
+        images: HashMap::new(),
+        host: HostArch { arch: "amd64".to_string(), emulated: Vec::new() },
+    }
+}
+
+fn container(image: &str) -> ContainerConfig {
+    ContainerConfig {
+        image: Some(image.to_string()),
+        pull_policy: PullPolicy::default(),
+        env: BTreeMap::new(),
+        run_args_allowlisted: Vec::new(),
+        platform: None,
+    }
+}
+
+#[test]
+fn test_parse_image_references() {
+    let image = parse_image_reference(&format!("ghcr.io/acme/nrf-sdk:2.5.0@{}", DIGEST)).unwrap();
+    assert_eq!(image.name(), "ghcr.io/acme/nrf-sdk");
+    assert_eq!(image.tag.as_deref(), Some("2.5.0"));
+    assert_eq!(image.digest.as_deref(), Some(DIGEST));
+
+    assert_eq!(parse_image_reference("ubuntu").unwrap().name(), "docker.io/library/ubuntu");
+    let image = parse_image_reference("localhost:5000/tools/sdk").unwrap();
+    assert_eq!((image.registry.as_str(), image.tag), ("localhost:5000", None));
+
+    for bad in ["", "Acme/SDK", "ghcr.io/acme/sdk:", "ghcr.io/acme/sdk@md5:abc", "ghcr.io//sdk", "sdk:-bad"] {
+        assert!(parse_image_reference(bad).is_err(), "{} accepted", bad);
+    }
+}
+
+#[test]
+fn test_policy_allows_only_listed_registries_and_run_args() {
+    let policy = policy(&["ghcr.io/acme", "docker.io/library/ubuntu"], &["--cpus"]);
+
+    assert!(policy.check(&container("ghcr.io/acme/nrf-sdk:2.5.0")).is_ok());
+    assert!(policy.check(&container("ubuntu:22.04")).is_ok());
+    assert!(policy.check(&container("ghcr.io/acme-evil/sdk")).is_err());
+    assert!(policy.check(&container("docker.io/acme/sdk")).is_err());
+
+    let mut with_args = container("ghcr.io/acme/sdk");
+    with_args.run_args_allowlisted = vec!["--cpus=2".to_string()];
+    assert!(policy.check(&with_args).is_ok());
+    for bad in ["--privileged", "--cpus", "--volume=/:/host"] {
+        with_args.run_args_allowlisted = vec![bad.to_string()];
+        assert!(policy.check(&with_args).is_err(), "{} accepted", bad);
+    }
+
+    // Nothing allowlisted means no custom images at all
+    assert!(ContainerPolicy::default().check(&container("ghcr.io/acme/sdk")).is_err());
+}
+
+#[test]
+fn test_image_chosen_by_build_system() {
+    let mut policy = policy(&["ghcr.io/acme"], &[]);
+    policy.images.insert(BuildSystem::PlatformIO, "registry.internal/hardened/pio:6".to_string());
+    let unnamed = ContainerConfig { image: None, ..container("") };
+
+    // The operator's images needn't be allowlisted; a request's own image still wins
+    assert!(policy.check(&unnamed).is_ok());
+    assert_eq!(policy.image_for(&unnamed, BuildSystem::PlatformIO).unwrap(), "registry.internal/hardened/pio:6");
+    assert_eq!(policy.image_for(&container("ghcr.io/acme/sdk"), BuildSystem::PlatformIO).unwrap(), "ghcr.io/acme/sdk");
+
+    let error = policy.image_for(&unnamed, BuildSystem::CMake).unwrap_err().to_string();
+    assert!(error.contains("set NABLA_IMAGE_CMAKE"), "{}", error);
+}
+
+#[test]
+fn test_wrap_runs_command_in_image() {
+    let context = ContainerContext {
+        docker: "docker".to_string(),
+        image: "ghcr.io/acme/sdk:1".to_string(),
+        digest: format!("ghcr.io/acme/sdk@{}", DIGEST),
+        mounts: vec![PathBuf::from("/work/repo"), PathBuf::from("/cache/pio")],
+        env: BTreeMap::from([("BOARD".to_string(), "nrf52840dk".to_string())]),
+        run_args: vec!["--cpus=2".to_string()],
+        platform: None,
+    };
+    let mut command = Command::new("make");
+    command.args(["-j4", "all"]).current_dir("/work/repo").env("API_TOKEN", "hunter2");
+
+    let (wrapped, running) = context.wrap(&command, true);
+    running.finished();
+    let wrapped = wrapped.as_std();
+    let args: Vec<String> = wrapped.get_args().map(|a| a.to_string_lossy().into_owned()).collect();
+    let joined = args.join(" ");
+
+    assert_eq!(wrapped.get_program(), "docker");
+    assert!(joined.starts_with("run --rm --name=nabla-run-"), "{}", joined);
+    assert!(joined.contains(" --user "), "{}", joined);
+    assert!(joined.contains("--network none"), "{}", joined);
+    assert!(joined.contains("-v /work/repo:/work/repo -v /cache/pio:/cache/pio -w /work/repo"), "{}", joined);
+    assert!(joined.ends_with("-e API_TOKEN -e BOARD --cpus=2 ghcr.io/acme/sdk:1 make -j4 all"), "{}", joined);
+    // Values travel through docker's environment, never its command line
+    assert!(!joined.contains("hunter2") && !joined.contains("nrf52840dk"), "{}", joined);
+    let envs: Vec<_> = wrapped.get_envs().collect();
+    assert!(envs.iter().any(|(k, v)| *k == "API_TOKEN" && v.is_some_and(|v| v == "hunter2")));
+
+    let (unisolated, running) = context.wrap(&command, false);
+    running.finished();
+    assert!(!unisolated.as_std().get_args().any(|a| a == "--network"));
+}
+
+/// A binfmt_misc directory with an `enabled` or `disabled` handler for each qemu-user name
+fn binfmt_misc(handlers: &[(&str, bool)]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("status"), "enabled\n").unwrap();
+    for (qemu, enabled) in handlers {
+        let state = if *enabled { "enabled" } else { "disabled" };
+        fs::write(dir.path().join(format!("qemu-{}", qemu)), format!("{}\ninterpreter /usr/bin/qemu-{}-static\n", state, qemu)).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_container_platform_must_run_on_the_host() {
+    let with_qemu = binfmt_misc(&[("aarch64", true), ("arm", true), ("riscv64", false)]);
+    let amd64_with_qemu = HostArch::read("x86_64", with_qemu.path());
+    assert_eq!(amd64_with_qemu, HostArch { arch: "amd64".to_string(), emulated: vec!["arm64".to_string(), "arm".to_string()] });
+    let amd64 = HostArch::read("x86_64", binfmt_misc(&[]).path());
+    assert!(amd64.emulated.is_empty());
+    // A qemu-aarch64 handler on an arm64 host is no emulation
+    let arm64 = HostArch::read("aarch64", binfmt_misc(&[("aarch64", true)]).path());
+    assert_eq!(arm64, HostArch { arch: "arm64".to_string(), emulated: Vec::new() });
+
+    for platform in ["linux/amd64", "linux/arm64", "linux/arm/v7"] {
+        assert!(amd64_with_qemu.check_platform(platform).is_ok(), "{}", platform);
+    }
+    assert!(amd64.check_platform("linux/amd64").is_ok());
+    assert_eq!(
+        amd64.check_platform("linux/arm64").unwrap_err().to_string(),
+        "Container platform 'linux/arm64' can't run on this amd64 runner: no qemu-user emulator for arm64 is registered with binfmt_misc (emulated: none)"
+    );
+    assert!(arm64.check_platform("linux/arm64").is_ok());
+    let error = arm64.check_platform("linux/amd64").unwrap_err().to_string();
+    assert!(error.starts_with("Container platform 'linux/amd64' can't run on this arm64 runner"), "{}", error);
+    for bad in ["arm64", "windows/amd64", "linux/", "linux/ARM64", "linux/arm/v7/x"] {
+        assert!(amd64_with_qemu.check_platform(bad).unwrap_err().to_string().starts_with("Invalid container platform"), "{}", bad);
+    }
+
+    // The policy refuses a platform the host can't run, and docker is told the platform
+    let policy = policy(&["ghcr.io/acme"], &[]);
+    let arm_image = ContainerConfig { platform: Some("linux/arm64".to_string()), ..container("ghcr.io/acme/sdk") };
+    assert!(policy.check(&arm_image).unwrap_err().to_string().contains("can't run on this amd64 runner"));
+    assert!(policy.check(&ContainerConfig { platform: Some("linux/amd64".to_string()), ..arm_image.clone() }).is_ok());
+    let context = ContainerContext {
+        docker: "docker".to_string(),
+        image: "ghcr.io/acme/sdk:1".to_string(),
+        digest: format!("ghcr.io/acme/sdk@{}", DIGEST),
+        mounts: Vec::new(),
+        env: BTreeMap::new(),
+        run_args: Vec::new(),
+        platform: Some("linux/arm64".to_string()),
+    };
+    let (wrapped, running) = context.wrap(&Command::new("make"), false);
+    running.finished();
+    let args: Vec<String> = wrapped.as_std().get_args().map(|a| a.to_string_lossy().into_owned()).collect();
+    assert_eq!(args[..2], ["run", "--rm"]);
+    assert!(args[2].starts_with("--name=nabla-run-"), "{:?}", args);
+    assert_eq!(args[3..5], ["--platform", "linux/arm64"]);
+}
+
+/// A `docker` stand-in that logs its arguments, knows one image, and executes `run` on the host
+/// in the requested working directory.
+fn write_docker_shim(dir: &Path) -> PathBuf {
+    let shim = dir.join("docker");
+    fs::write(
+        &shim,
+        format!(
+            r#"#!/bin/sh
+echo "$@" >> "{log}"
+case "$1" in
+  pull) exit 0 ;;
+  image) echo "ghcr.io/acme/sdk@{digest}"; exit 0 ;;
+  run) shift ;;
+  *) exit 1 ;;
+esac
+while [ $# -gt 0 ]; do
+  case "$1" in
+    --rm) shift ;;
+    --user|--network|-v|-e) shift 2 ;;
+    -w) cd "$2"; shift 2 ;;
+    --*=*) shift ;;
+    *) break ;;
+  esac
+done
+shift
+exec "$@"
+"#,
+            log = dir.join("docker.log").display(),
+            digest = DIGEST,
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+    shim
+}
+
+#[tokio::test]
+async fn test_build_runs_in_container_and_records_digest() {
+    let tools = TempDir::new().unwrap();
+    std::env::set_var("NABLA_DOCKER", write_docker_shim(tools.path()));
+    std::env::set_var("NABLA_CONTAINER_REGISTRIES", "ghcr.io/acme");
+    std::env::set_var("NABLA_IMAGE_MAKEFILE", "registry.internal/hardened/make:3");
+
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("main.c"), "int main(void) { return 0; }\n").unwrap();
+    fs::write(
+        repo.path().join("Makefile"),
+        "firmware: main.c\n\techo $$BOARD > board.txt\n\tgcc -o firmware main.c\n",
+    )
+    .unwrap();
+    let mut config = BuildConfig { container: Some(container("ghcr.io/acme/sdk:1")), ..BuildConfig::default() };
+    config.container.as_mut().unwrap().env.insert("BOARD".to_string(), "nrf52840dk".to_string());
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error_output);
+    assert_eq!(result.provenance.container_image.as_deref(), Some("ghcr.io/acme/sdk:1"));
+    assert_eq!(result.provenance.image_digest, Some(format!("ghcr.io/acme/sdk@{}", DIGEST)));
+    assert_eq!(fs::read_to_string(repo.path().join("board.txt")).unwrap().trim(), "nrf52840dk");
+
+    let log = fs::read_to_string(tools.path().join("docker.log")).unwrap();
+    assert!(log.contains("pull ghcr.io/acme/sdk:1") || log.contains("image inspect ghcr.io/acme/sdk:1"), "{}", log);
+    assert!(log.lines().any(|l| l.starts_with("run ") && l.contains("ghcr.io/acme/sdk:1 make")), "{}", log);
+
+    // Images outside the allowlist are refused before anything runs
+    let config = BuildConfig { container: Some(container("docker.io/library/ubuntu")), ..BuildConfig::default() };
+    let error = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap_err();
+    assert!(error.to_string().contains("not from an allowed registry"), "{}", error);
+
+    // Without an image the runner's image for the build system is used
+    let config = BuildConfig { container: Some(ContainerConfig { image: None, ..container("") }), ..BuildConfig::default() };
+    let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    assert_eq!(result.provenance.container_image.as_deref(), Some("registry.internal/hardened/make:3"));
+    let log = fs::read_to_string(tools.path().join("docker.log")).unwrap();
+    assert!(log.lines().any(|l| l.starts_with("run ") && l.contains("registry.internal/hardened/make:3 make")), "{}", log);
+    // Runs that ended by themselves leave their container to `--rm`
+    assert!(!log.lines().any(|l| l.starts_with("rm ")), "{}", log);
+
+    // Here rather than in a test of its own, which would race this one for NABLA_DOCKER
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("Makefile"), "firmware:\n\tsleep 30\n").unwrap();
+    let config = BuildConfig { container: Some(container("ghcr.io/acme/sdk:1")), timeout_secs: Some(1), ..BuildConfig::default() };
+    assert!(execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.is_err());
+    let config = BuildConfig { container: Some(container("ghcr.io/acme/sdk:1")), ..BuildConfig::default() };
+    let build = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config);
+    assert!(tokio::time::timeout(Duration::from_secs(1), build).await.is_err());
+    std::thread::sleep(Duration::from_millis(500));
+
+    // Killing the docker CLI leaves the container running; it's removed by the name it ran under
+    let log = fs::read_to_string(tools.path().join("docker.log")).unwrap();
+    let name = |line: &str| line.split_whitespace().find_map(|arg| arg.strip_prefix("--name=")).map(str::to_string);
+    let workspace = format!("-w {} ", repo.path().display());
+    let runs: Vec<String> = log.lines().filter(|l| l.starts_with("run ") && l.contains(&workspace)).filter_map(name).collect();
+    let removed: Vec<String> = log.lines().filter_map(|l| l.strip_prefix("rm -f ")).map(str::to_string).collect();
+    assert_eq!(runs.len(), 2, "{}", log);
+    assert_eq!(removed, runs, "{}", log);
+}
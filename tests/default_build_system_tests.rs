@@ -0,0 +1,35 @@
+use nabla_runner::core::BuildSystem;
+use nabla_runner::{FirmwareBuildRunner, RunOptions};
+use std::fs;
+use tempfile::TempDir;
+
+// One test, since NABLA_DEFAULT_BUILD_SYSTEM is read by every build in the process
+#[tokio::test]
+async fn test_repo_without_markers_builds_with_the_default_build_system() {
+    // GNU make reads GNUmakefile, which detection doesn't look for
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("GNUmakefile"), "firmware.bin:\n\techo built > firmware.bin\n").unwrap();
+    let runner = FirmwareBuildRunner::new();
+
+    std::env::remove_var("NABLA_DEFAULT_BUILD_SYSTEM");
+    let error = runner.run(dir.path(), RunOptions::default()).await.unwrap_err();
+    assert!(error.to_string().contains("Unsupported or undetected build system"), "{}", error);
+
+    std::env::set_var("NABLA_DEFAULT_BUILD_SYSTEM", "makefile");
+    let report = runner.run(dir.path(), RunOptions::default()).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::Makefile);
+    assert!(report.result.success, "{:?}", report.result.error_output);
+    assert!(report.result.output_path.unwrap().ends_with("firmware.bin"));
+    assert!(report.log.iter().any(|line| line == "No build system detected, using NABLA_DEFAULT_BUILD_SYSTEM: Makefile"), "{:?}", report.log);
+
+    // Detection still wins where it finds something
+    fs::write(dir.path().join("CMakeLists.txt"), "project(firmware C)\n").unwrap();
+    let report = runner.dry_run(dir.path(), &RunOptions::default()).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::CMake);
+
+    std::env::set_var("NABLA_DEFAULT_BUILD_SYSTEM", "ninja");
+    fs::remove_file(dir.path().join("CMakeLists.txt")).unwrap();
+    let error = runner.run(dir.path(), RunOptions::default()).await.unwrap_err();
+    assert!(error.to_string().starts_with("NABLA_DEFAULT_BUILD_SYSTEM 'ninja' is not a build system (one of: Yocto, Buildroot, Cargo"), "{}", error);
+    std::env::remove_var("NABLA_DEFAULT_BUILD_SYSTEM");
+}
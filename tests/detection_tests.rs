@@ -0,0 +1,331 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::detection::{analyze, detect_build_system, detect_build_systems, detect_flavor, nested_project_root, single_child_root, BuildFlavor, DETECTION_ORDER};
+use nabla_runner::execution::execute_build_with_config;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn test_single_child_descent() {
+    let temp_dir = TempDir::new().unwrap();
+    let inner = temp_dir.path().join("inner");
+    fs::create_dir(&inner).unwrap();
+    fs::create_dir(temp_dir.path().join(".git")).unwrap();
+    fs::write(temp_dir.path().join("README.md"), "# firmware\n").unwrap();
+    fs::write(inner.join("Makefile"), "all:\n").unwrap();
+
+    assert_eq!(detect_build_system(temp_dir.path()).await, None);
+
+    let root = single_child_root(temp_dir.path()).await.unwrap();
+    assert_eq!(root, inner);
+    assert_eq!(detect_build_system(&root).await, Some(BuildSystem::Makefile));
+}
+
+#[tokio::test]
+async fn test_no_descent_with_markers_or_several_subdirs() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("src")).unwrap();
+    fs::write(temp_dir.path().join("CMakeLists.txt"), "project(app)\n").unwrap();
+    assert_eq!(single_child_root(temp_dir.path()).await, None);
+
+    let siblings = TempDir::new().unwrap();
+    fs::create_dir(siblings.path().join("app")).unwrap();
+    fs::create_dir(siblings.path().join("bootloader")).unwrap();
+    assert_eq!(single_child_root(siblings.path()).await, None);
+}
+
+#[tokio::test]
+async fn test_dockerfile_detected_after_native_systems() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("Dockerfile"), "FROM alpine\n").unwrap();
+    assert_eq!(detect_build_system(temp_dir.path()).await, Some(BuildSystem::Dockerfile));
+
+    fs::write(temp_dir.path().join("platformio.ini"), "[env:esp32]\n").unwrap();
+    assert_eq!(detect_build_system(temp_dir.path()).await, Some(BuildSystem::PlatformIO));
+}
+
+#[tokio::test]
+async fn test_cubemx_flavor_needs_prefix_and_stm32_drivers() {
+    let temp_dir = TempDir::new().unwrap();
+    let makefile = temp_dir.path().join("Makefile");
+
+    fs::write(&makefile, "PREFIX = arm-none-eabi-\nC_INCLUDES = -IDrivers/CMSIS/Include\n").unwrap();
+    assert_eq!(detect_flavor(temp_dir.path(), BuildSystem::Makefile).await, Some(BuildFlavor::Stm32Cubemx));
+
+    // A generic ARM project, and a host build that merely vendors CMSIS
+    fs::write(&makefile, "PREFIX = arm-none-eabi-\nSRC = main.c\n").unwrap();
+    assert_eq!(detect_flavor(temp_dir.path(), BuildSystem::Makefile).await, None);
+    fs::write(&makefile, "CC = gcc\nC_INCLUDES = -IDrivers/CMSIS/Include\n").unwrap();
+    assert_eq!(detect_flavor(temp_dir.path(), BuildSystem::Makefile).await, None);
+}
+
+#[tokio::test]
+async fn test_analyze_reports_sub_path_markers_and_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let inner = temp_dir.path().join("firmware-main");
+    fs::create_dir(&inner).unwrap();
+    fs::write(inner.join("Makefile"), "all:\n").unwrap();
+
+    let report = analyze(temp_dir.path()).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::Makefile);
+    assert_eq!(report.sub_path, Some(PathBuf::from("firmware-main")));
+    assert_eq!(report.markers, ["Makefile"]);
+    assert_eq!(report.flavor, None);
+    assert_eq!(report.suggested_command, "make");
+
+    fs::write(temp_dir.path().join("README.md"), "nothing to build\n").unwrap();
+    fs::remove_dir_all(&inner).unwrap();
+    assert!(analyze(temp_dir.path()).await.is_none());
+
+    fs::create_dir(temp_dir.path().join("configs")).unwrap();
+    fs::write(temp_dir.path().join("configs/board_defconfig"), "BR2_arm=y\n").unwrap();
+    fs::write(temp_dir.path().join("Config.in"), "config BR2_HAVE_DOT_CONFIG\n").unwrap();
+    let report = analyze(temp_dir.path()).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::Buildroot);
+    assert_eq!(report.sub_path, None);
+    assert_eq!(report.markers, ["Config.in", "configs/board_defconfig"]);
+    assert_eq!(report.suggested_command, "make board_defconfig && make");
+}
+
+#[tokio::test]
+async fn test_analyze_lists_every_candidate_and_target_arch() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("Cargo.toml"), "[package]\nname = \"blinky\"\n").unwrap();
+    fs::write(temp_dir.path().join("Makefile"), "flash:\n\tcargo flash\n").unwrap();
+    fs::write(temp_dir.path().join("Dockerfile"), "FROM rust\n").unwrap();
+    fs::create_dir(temp_dir.path().join(".cargo")).unwrap();
+    fs::write(temp_dir.path().join(".cargo/config.toml"), "[build]\ntarget = \"thumbv7em-none-eabihf\"\n").unwrap();
+
+    assert_eq!(
+        detect_build_systems(temp_dir.path()).await,
+        [BuildSystem::Cargo, BuildSystem::Makefile, BuildSystem::Dockerfile]
+    );
+    let report = analyze(temp_dir.path()).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::Cargo);
+    let candidates: Vec<(BuildSystem, Vec<String>)> =
+        report.candidates.into_iter().map(|c| (c.build_system, c.markers)).collect();
+    assert_eq!(candidates[1], (BuildSystem::Makefile, vec!["Makefile".to_string()]));
+    assert_eq!(report.target_arch.as_deref(), Some("arm"));
+}
+
+#[tokio::test]
+async fn test_analyze_reports_default_artifact_format() {
+    let formats: Vec<(BuildSystem, &str)> =
+        DETECTION_ORDER.iter().map(|&system| (system, system.default_artifact_format())).collect();
+    assert_eq!(
+        formats,
+        [
+            (BuildSystem::Yocto, "wic"),
+            (BuildSystem::Buildroot, "img"),
+            (BuildSystem::Cargo, "elf"),
+            (BuildSystem::MplabX, "hex"),
+            (BuildSystem::Makefile, "bin"),
+            (BuildSystem::ZephyrWest, "elf"),
+            (BuildSystem::CMake, "elf"),
+            (BuildSystem::PlatformIO, "hex"),
+            (BuildSystem::STM32CubeIDE, "elf"),
+            (BuildSystem::SCons, "bin"),
+            (BuildSystem::Dockerfile, "bin"),
+        ]
+    );
+
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("platformio.ini"), "[env:uno]\nplatform = atmelavr\nboard = uno\n").unwrap();
+    assert_eq!(analyze(temp_dir.path()).await.unwrap().artifact_format, "hex");
+}
+
+/// Build each fixture with stub tools that log how they were invoked, and check the logged
+/// commands end with the report's suggested command. Dockerfile and Yocto builds are left
+/// out: the former tags a random image, the latter is never run by the runner.
+#[tokio::test]
+async fn test_suggested_command_matches_executor() {
+    let tools = TempDir::new().unwrap();
+    let log = tools.path().join("invocations.log");
+    for tool in ["make", "cmake", "pio", "west", "scons"] {
+        let stub = tools.path().join(tool);
+        fs::write(
+            &stub,
+            format!("#!/bin/sh\necho \"{} $*\" >> '{}'\n", tool, log.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let mut config = BuildConfig::default();
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    // Platform installs are setup, not part of the build command
+    config.preinstall_platforms = false;
+
+    let fixtures: &[(BuildSystem, &str, &str)] = &[
+        (BuildSystem::Makefile, "Makefile", "all:\n"),
+        (BuildSystem::CMake, "CMakeLists.txt", "project(firmware C)\n"),
+        (BuildSystem::PlatformIO, "platformio.ini", "[env:native]\nplatform = native\n"),
+        (BuildSystem::ZephyrWest, "west.yml", "manifest:\n  self:\n    path: app\n"),
+        (BuildSystem::STM32CubeIDE, ".cproject", "<cproject/>\n"),
+        (BuildSystem::SCons, "SConstruct", "Program('firmware', 'main.c')\n"),
+        (BuildSystem::Buildroot, "configs/board_defconfig", "BR2_arm=y\n"),
+    ];
+
+    for (system, marker, contents) in fixtures {
+        let repo = TempDir::new().unwrap();
+        let marker_path = repo.path().join(marker);
+        fs::create_dir_all(marker_path.parent().unwrap()).unwrap();
+        fs::write(&marker_path, contents).unwrap();
+        let mut markers = vec![*marker];
+        if *system == BuildSystem::Buildroot {
+            // Defconfigs alone could be U-Boot's or Linux's
+            fs::write(repo.path().join("Config.in"), "config BR2_HAVE_DOT_CONFIG\n").unwrap();
+            markers.insert(0, "Config.in");
+        }
+
+        let report = analyze(repo.path()).await.unwrap();
+        assert_eq!(report.build_system, *system);
+        assert_eq!(report.markers, markers);
+
+        let _ = fs::remove_file(&log);
+        // The stubs produce no artifacts, so the builds fail after running their commands
+        let _ = execute_build_with_config(repo.path(), *system, &config).await;
+
+        let expected: Vec<&str> = report
+            .suggested_command
+            .split(" && ")
+            .filter(|step| !step.starts_with("mkdir ") && !step.starts_with("cd "))
+            .collect();
+        let logged = fs::read_to_string(&log).unwrap_or_default();
+        let logged: Vec<&str> = logged.lines().map(str::trim_end).collect();
+        assert!(
+            logged.ends_with(&expected),
+            "{:?}: suggested {:?}, executor ran {:?}",
+            system,
+            report.suggested_command,
+            logged
+        );
+    }
+}
+
+/// A 50k-file Makefile project: 10,000 sources at the top and 100 layer directories of 10
+/// packages each, plus a recipe in `.git` that detection must not walk into
+fn large_tree(root: &Path) {
+    for i in 0..10_000 {
+        fs::write(root.join(format!("src_{i}.c")), "").unwrap();
+    }
+    for layer in 0..100 {
+        for pkg in 0..10 {
+            let dir = root.join(format!("layer-{layer}/pkg-{pkg}"));
+            fs::create_dir_all(&dir).unwrap();
+            for file in 0..40 {
+                fs::write(dir.join(format!("file_{file}.h")), "").unwrap();
+            }
+        }
+    }
+    fs::write(root.join("Makefile"), "all:\n").unwrap();
+    // Recipes in hidden directories don't make a Yocto layer
+    fs::create_dir_all(root.join(".git/objects")).unwrap();
+    fs::write(root.join(".git/objects/stale.bb"), "").unwrap();
+}
+
+#[tokio::test]
+async fn test_detection_on_large_tree() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    large_tree(root);
+
+    let report = analyze(root).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::Makefile);
+    assert_eq!(report.markers, ["Makefile"]);
+    assert_eq!(report.candidates.len(), 1);
+
+    // A recipe outside a layer is packaging, not a Yocto build
+    fs::write(root.join("layer-99/pkg-9/pkg.bb"), "").unwrap();
+    assert_eq!(detect_build_systems(root).await, [BuildSystem::Makefile]);
+    fs::create_dir(root.join("layer-99/conf")).unwrap();
+    fs::write(root.join("layer-99/conf/layer.conf"), "").unwrap();
+    assert_eq!(
+        detect_build_systems(root).await,
+        [BuildSystem::Yocto, BuildSystem::Makefile]
+    );
+}
+
+/// How long detection takes over [`large_tree`]. Wall-clock numbers depend on the machine, so
+/// this only reports them, for comparing revisions on one machine:
+/// `cargo test --test detection_tests -- --ignored --nocapture`
+#[tokio::test]
+#[ignore]
+async fn bench_detection_on_large_tree() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    large_tree(root);
+
+    for run in 1..=3 {
+        let started = std::time::Instant::now();
+        analyze(root).await.unwrap();
+        println!("analyze, run {}: {:?}", run, started.elapsed());
+    }
+    fs::write(root.join("layer-99/pkg-9/pkg.bb"), "").unwrap();
+    let started = std::time::Instant::now();
+    detect_build_systems(root).await;
+    println!("detect_build_systems with a stray recipe: {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn test_analyze_explains_the_detected_build_system() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("west.yml"), "manifest:\n  projects: []\n").unwrap();
+    fs::write(temp_dir.path().join("CMakeLists.txt"), "find_package(Zephyr)\nproject(app)\n").unwrap();
+    let report = analyze(temp_dir.path()).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::ZephyrWest);
+    assert_eq!(
+        report.explanation,
+        "detected ZephyrWest: found west.yml at repo root; preferred over CMake (CMakeLists.txt)"
+    );
+
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("firmware-main")).unwrap();
+    fs::write(temp_dir.path().join("firmware-main/Makefile"), "all:\n").unwrap();
+    let report = analyze(temp_dir.path()).await.unwrap();
+    assert_eq!(report.explanation, "detected Makefile: found Makefile in firmware-main/");
+}
+
+#[tokio::test]
+async fn test_nested_projects_pick_priority_then_shallowest_then_first_path() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    let project = |dir: &str, file: &str| {
+        fs::create_dir_all(root.join(dir)).unwrap();
+        fs::write(root.join(dir).join(file), "").unwrap();
+    };
+    fs::write(root.join("README.md"), "# monorepo\n").unwrap();
+    for i in 0..24 {
+        project(&format!("tools/tool{:02}", i), "SConstruct");
+    }
+    project("apps/zeta", "platformio.ini");
+    project("apps/beta/fw", "Makefile");
+    project("libs/alpha/fw", "Makefile");
+    project("deep/a/b/c", "Cargo.toml");
+    project(".hidden/fw", "Cargo.toml");
+    // A project's own subdirectories aren't separate projects
+    project("apps/zeta/lib/sub", "Cargo.toml");
+
+    // Makefile ranks above PlatformIO and SCons; of the two, both two levels down, apps/ sorts first
+    for _ in 0..3 {
+        assert_eq!(nested_project_root(root).await, Some(root.join("apps/beta/fw")));
+    }
+
+    project("bootloader", "Makefile");
+    assert_eq!(nested_project_root(root).await, Some(root.join("bootloader")));
+    let report = analyze(root).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::Makefile);
+    assert_eq!(report.sub_path, Some(PathBuf::from("bootloader")));
+
+    // Deeper than the search goes, leaving the single-folder fallback
+    for dir in ["bootloader", "apps", "libs", "tools"] {
+        fs::remove_dir_all(root.join(dir)).unwrap();
+    }
+    assert_eq!(nested_project_root(root).await, Some(root.join("deep")));
+
+    // Nothing to descend into at a root that builds
+    project("", "CMakeLists.txt");
+    project("apps/beta", "Makefile");
+    assert_eq!(nested_project_root(root).await, None);
+}
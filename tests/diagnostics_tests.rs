@@ -0,0 +1,146 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::diagnostics::{glob_match, parse_diagnostics, Severity};
+use nabla_runner::execution::{execute_build_with_config, BuildStepFailed};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+const GCC_OUTPUT: &str = "\
+gcc -Wall -c main.c -o main.o
+main.c: In function 'main':
+main.c:4:9: warning: unused variable 'unused' [-Wunused-variable]
+    4 |     int unused;
+      |         ^~~~~~
+vendor/lib/crc.c:10:5: warning: implicit declaration of function 'foo' [-Wimplicit-function-declaration]
+src/app.c:7: error: expected ';' before '}' token
+collect2: error: ld returned 1 exit status
+make: *** [Makefile:3: firmware] Error 1
+";
+
+/// A Makefile project whose main.c has a deliberate -Wunused-variable warning, plus a
+/// warning from vendored code
+fn write_warning_project(dir: &Path) {
+    fs::create_dir_all(dir.join("vendor")).unwrap();
+    fs::write(
+        dir.join("Makefile"),
+        "firmware: main.c vendor/lib.c\n\tgcc -Wall -o firmware main.c vendor/lib.c\n",
+    )
+    .unwrap();
+    fs::write(dir.join("main.c"), "int lib(void);\n\nint main(void) {\n    int unused;\n    return lib();\n}\n").unwrap();
+    fs::write(dir.join("vendor/lib.c"), "int lib(void) {\n    int spare;\n    return 0;\n}\n").unwrap();
+}
+
+#[test]
+fn test_parse_gcc_output() {
+    let diagnostics = parse_diagnostics(GCC_OUTPUT);
+
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0].file, "main.c");
+    assert_eq!((diagnostics[0].line, diagnostics[0].column), (4, Some(9)));
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].flag.as_deref(), Some("-Wunused-variable"));
+    assert_eq!(diagnostics[0].message, "unused variable 'unused'");
+    assert_eq!((diagnostics[2].file.as_str(), diagnostics[2].line, diagnostics[2].column), ("src/app.c", 7, None));
+    assert_eq!(diagnostics[2].severity, Severity::Error);
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("vendor/**", "vendor/lib/crc.c"));
+    assert!(glob_match("**/third_party/**", "src/third_party/x.c"));
+    assert!(glob_match("*.c", "main.c"));
+    assert!(!glob_match("*.c", "src/main.c"));
+    assert!(!glob_match("vendor/**", "src/vendor.c"));
+}
+
+#[tokio::test]
+async fn test_warnings_counted_and_excluded() {
+    let dir = TempDir::new().unwrap();
+    write_warning_project(dir.path());
+
+    let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &BuildConfig::default())
+        .await
+        .unwrap();
+    assert!(result.success);
+    assert_eq!((result.warning_count, result.error_count), (2, 0));
+
+    // Fresh tree so make recompiles rather than reporting the target up to date
+    let dir = TempDir::new().unwrap();
+    write_warning_project(dir.path());
+    let config = BuildConfig {
+        warning_excludes: vec!["vendor/**".to_string()],
+        ..BuildConfig::default()
+    };
+    let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &config).await.unwrap();
+    assert_eq!(result.warning_count, 1);
+}
+
+#[tokio::test]
+async fn test_fail_on_warnings() {
+    let dir = TempDir::new().unwrap();
+    write_warning_project(dir.path());
+
+    let config = BuildConfig {
+        fail_on_warnings: true,
+        warning_excludes: vec!["vendor/**".to_string()],
+        ..BuildConfig::default()
+    };
+    let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &config).await.unwrap();
+
+    assert!(!result.success);
+    let error = result.error_output.unwrap();
+    assert!(error.contains("main.c:4: unused variable 'unused' [-Wunused-variable]"), "{}", error);
+    assert!(!error.contains("vendor"), "{}", error);
+}
+
+const CLANG_OUTPUT: &str = "\
+clang -c src/app.c -o app.o
+src/app.c:10:3: error: use of undeclared identifier 'led_pin'
+   10 |   led_pin = 1;
+      |   ^
+src/app.c:14:6: error: conflicting types for 'init'
+void init(void) {
+     ^
+src/board.h:3:6: note: previous declaration is here
+void init(int);
+     ^
+src/board.h:9:1: note: expanded from macro 'BOARD_INIT'
+2 errors generated.
+";
+
+#[test]
+fn test_clang_notes_attach_to_their_error() {
+    let diagnostics = parse_diagnostics(CLANG_OUTPUT);
+
+    assert_eq!(diagnostics.len(), 2, "{:#?}", diagnostics);
+    assert_eq!(diagnostics[0].message, "use of undeclared identifier 'led_pin'");
+    assert!(diagnostics[0].notes.is_empty());
+
+    let conflict = &diagnostics[1];
+    assert_eq!((conflict.file.as_str(), conflict.line, conflict.column), ("src/app.c", 14, Some(6)));
+    let notes: Vec<(&str, u32, &str)> =
+        conflict.notes.iter().map(|n| (n.file.as_str(), n.line, n.message.as_str())).collect();
+    assert_eq!(
+        notes,
+        [("src/board.h", 3, "previous declaration is here"), ("src/board.h", 9, "expanded from macro 'BOARD_INIT'")]
+    );
+    assert!(conflict.notes.iter().all(|n| n.severity == Severity::Note));
+}
+
+#[tokio::test]
+async fn test_failed_build_carries_diagnostics() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("Makefile"), "firmware: main.c\n\tgcc -o firmware main.c\n").unwrap();
+    fs::write(dir.path().join("main.c"), "int main(void) {\n    return undeclared_symbol;\n}\n").unwrap();
+
+    let error = execute_build_with_config(dir.path(), BuildSystem::Makefile, &BuildConfig::default())
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().starts_with("Make build failed"), "{}", error);
+    let failed = error.downcast_ref::<BuildStepFailed>().expect("diagnostics attached");
+    let first = &failed.diagnostics[0];
+    assert_eq!((first.file.as_str(), first.line, first.column), ("main.c", 2, Some(12)));
+    assert_eq!(first.severity, Severity::Error);
+    assert!(first.message.contains("undeclared_symbol"), "{}", first.message);
+}
@@ -0,0 +1,28 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::execution::execute_build_with_config;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+
+// Its own test binary, since the other Dockerfile tests set NABLA_ALLOW_DOCKERFILE
+#[tokio::test]
+async fn test_dockerfile_builds_are_refused_unless_the_operator_allows_them() {
+    std::env::remove_var("NABLA_ALLOW_DOCKERFILE");
+    let tools = TempDir::new().unwrap();
+    let docker = tools.path().join("docker");
+    fs::write(&docker, format!("#!/bin/sh\necho \"$@\" >> \"{}\"\n", tools.path().join("docker.log").display())).unwrap();
+    fs::set_permissions(&docker, fs::Permissions::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("Dockerfile"), "FROM alpine\nRUN echo hello\n").unwrap();
+    let mut config = BuildConfig {
+        artifact_in_image: Some("/out/firmware.bin".to_string()),
+        ..BuildConfig::default()
+    };
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+
+    let error = execute_build_with_config(repo.path(), BuildSystem::Dockerfile, &config).await.unwrap_err();
+
+    assert_eq!(error.to_string(), "Dockerfile builds are disabled on this runner (NABLA_ALLOW_DOCKERFILE is not set)");
+    assert!(!tools.path().join("docker.log").exists(), "docker ran without the operator's opt-in");
+}
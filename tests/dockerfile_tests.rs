@@ -0,0 +1,114 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::execution::execute_build_with_config;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A `docker` stand-in that logs its arguments and "copies" a fixed artifact out of any image
+fn write_docker_shim(dir: &Path) {
+    let shim = dir.join("docker");
+    fs::write(
+        &shim,
+        format!(
+            r#"#!/bin/sh
+echo "$@" >> "{log}"
+case "$1" in
+  build) echo "Successfully built" ;;
+  create) echo "c0ffee" ;;
+  cp) [ "$2" = "c0ffee:/out/firmware.bin" ] || {{ echo "no such file: $2" >&2; exit 1; }}; echo firmware > "$3" ;;
+esac
+"#,
+            log = dir.join("docker.log").display(),
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Every test here builds Dockerfiles, so none of them toggle the opt-in off again; refusal is
+/// covered by `dockerfile_policy_tests`
+fn allow_dockerfile_builds() {
+    std::env::set_var("NABLA_ALLOW_DOCKERFILE", "1");
+}
+
+fn shim_config(tools: &Path) -> BuildConfig {
+    let mut config = BuildConfig {
+        artifact_in_image: Some("/out/firmware.bin".to_string()),
+        ..BuildConfig::default()
+    };
+    let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    config
+}
+
+#[tokio::test]
+async fn test_dockerfile_build_copies_artifact_and_cleans_up() {
+    allow_dockerfile_builds();
+    let tools = TempDir::new().unwrap();
+    write_docker_shim(tools.path());
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("Dockerfile"), "FROM alpine\n").unwrap();
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::Dockerfile, &shim_config(tools.path()))
+        .await
+        .unwrap();
+
+    assert!(result.success, "{:?}", result.error_output);
+    assert_eq!(result.build_system, BuildSystem::Dockerfile);
+    let artifact = result.output_path.unwrap();
+    assert!(artifact.ends_with("nabla-docker-out/firmware.bin"), "{}", artifact);
+    assert_eq!(fs::read_to_string(&artifact).unwrap(), "firmware\n");
+
+    let log = fs::read_to_string(tools.path().join("docker.log")).unwrap();
+    let commands: Vec<&str> = log.lines().map(|line| line.split(' ').next().unwrap()).collect();
+    assert_eq!(commands, ["build", "create", "cp", "rm", "rmi"], "{}", log);
+    let tag = log.lines().next().unwrap().split(' ').nth(2).unwrap();
+    assert!(tag.starts_with("nabla-build-"), "{}", log);
+    assert!(log.contains(&format!("create {} ", tag)) && log.contains(&format!("rmi -f {}", tag)), "{}", log);
+}
+
+#[tokio::test]
+async fn test_dockerfile_build_errors() {
+    allow_dockerfile_builds();
+    let tools = TempDir::new().unwrap();
+    write_docker_shim(tools.path());
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("Dockerfile"), "FROM alpine\n").unwrap();
+
+    let mut config = shim_config(tools.path());
+    config.artifact_in_image = Some("/missing.bin".to_string());
+    let error = execute_build_with_config(repo.path(), BuildSystem::Dockerfile, &config).await.unwrap_err();
+    assert!(error.to_string().contains("Copying /missing.bin out of the built image failed"), "{}", error);
+    // The image is removed even when the copy fails
+    assert!(fs::read_to_string(tools.path().join("docker.log")).unwrap().contains("rmi -f nabla-build-"));
+
+    config.artifact_in_image = None;
+    let error = execute_build_with_config(repo.path(), BuildSystem::Dockerfile, &config).await.unwrap_err();
+    assert!(error.to_string().contains("artifact_in_image"), "{}", error);
+
+    let config: BuildConfig = serde_json::from_str(r#"{"artifact_in_image": "out/firmware.bin"}"#).unwrap();
+    assert!(config.validate().is_err());
+}
+
+/// Builds a real image; needs a Docker daemon and network access to pull `busybox`
+#[cfg(feature = "docker-tests")]
+#[tokio::test]
+async fn test_dockerfile_build_with_docker() {
+    allow_dockerfile_builds();
+    let repo = TempDir::new().unwrap();
+    fs::write(
+        repo.path().join("Dockerfile"),
+        "FROM busybox\nRUN mkdir /out && echo firmware > /out/firmware.bin\n",
+    )
+    .unwrap();
+    let config = BuildConfig {
+        artifact_in_image: Some("/out/firmware.bin".to_string()),
+        ..BuildConfig::default()
+    };
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::Dockerfile, &config).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error_output);
+    assert_eq!(fs::read_to_string(result.output_path.unwrap()).unwrap(), "firmware\n");
+}
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use nabla_runner::core::BuildSystem;
+use nabla_runner::events::{BuildEvent, BuildPhase, EventKind, EventPublisher, EventSink, JobEvents};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Records what it receives; while `down` is set every send fails like an unreachable bus
+#[derive(Default)]
+struct RecordingSink {
+    down: AtomicBool,
+    received: Mutex<Vec<(String, BuildEvent)>>,
+}
+
+#[async_trait]
+impl EventSink for RecordingSink {
+    async fn send(&self, subject: &str, payload: Vec<u8>) -> Result<()> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(anyhow!("connection refused"));
+        }
+        self.received.lock().push((subject.to_string(), serde_json::from_slice(&payload)?));
+        Ok(())
+    }
+}
+
+impl RecordingSink {
+    async fn wait_for(&self, count: usize) -> Vec<(String, BuildEvent)> {
+        for _ in 0..200 {
+            if self.received.lock().len() >= count {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        self.received.lock().clone()
+    }
+}
+
+fn job_events(publisher: &EventPublisher) -> JobEvents {
+    JobEvents::new(publisher.clone(), Uuid::new_v4(), "ci-42", "acme", "acme", "firmware")
+}
+
+#[tokio::test]
+async fn test_events_published_in_order_with_subjects() {
+    let sink = Arc::new(RecordingSink::default());
+    let publisher = EventPublisher::new(sink.clone(), "runners.eu.", 100);
+    let events = job_events(&publisher);
+
+    events.emit(EventKind::Queued);
+    events.emit(EventKind::Started);
+    events.phase(BuildPhase::Build);
+    events.emit(EventKind::Completed { build_system: BuildSystem::Makefile });
+
+    let received = sink.wait_for(4).await;
+    let subjects: Vec<&str> = received.iter().map(|(subject, _)| subject.as_str()).collect();
+    assert_eq!(subjects, ["runners.eu.queued", "runners.eu.started", "runners.eu.phase", "runners.eu.completed"]);
+    let sequences: Vec<u64> = received.iter().map(|(_, event)| event.sequence).collect();
+    assert_eq!(sequences, [0, 1, 2, 3]);
+    assert_eq!(received[2].1.kind, EventKind::Phase { phase: BuildPhase::Build });
+    assert_eq!(received[0].1.client_job_id, "ci-42");
+
+    let json = serde_json::to_value(&received[3].1).unwrap();
+    assert_eq!(json["event"], "completed");
+    assert_eq!(json["build_system"], "Makefile");
+}
+
+#[tokio::test]
+async fn test_unreachable_bus_buffers_and_drops_oldest() {
+    let sink = Arc::new(RecordingSink::default());
+    sink.down.store(true, Ordering::SeqCst);
+    let publisher = EventPublisher::new(sink.clone(), "nabla.builds", 3);
+    let events = job_events(&publisher);
+
+    // Publishing never waits on the bus
+    for _ in 0..5 {
+        events.phase(BuildPhase::Fetch);
+    }
+    assert_eq!(publisher.pending(), 3);
+    assert_eq!(publisher.dropped(), 2);
+
+    // Once the bus is back, the buffered events arrive in order, oldest dropped
+    sink.down.store(false, Ordering::SeqCst);
+    let received = sink.wait_for(3).await;
+    let sequences: Vec<u64> = received.iter().map(|(_, event)| event.sequence).collect();
+    assert_eq!(sequences, [2, 3, 4]);
+    assert_eq!(publisher.pending(), 0);
+}
+
+#[test]
+fn test_disabled_publisher_discards_events() {
+    let publisher = EventPublisher::disabled();
+    assert!(!publisher.is_enabled());
+    job_events(&publisher).emit(EventKind::Queued);
+    assert_eq!(publisher.pending(), 0);
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use nabla_runner::server::create_app;
+    use serde_json::{json, Value};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tower::util::ServiceExt;
+
+    /// Just enough of a NATS server for a client to connect, publish and flush. Returns the
+    /// server's URL and the `(subject, payload)` of every PUB it receives.
+    async fn fake_nats_server() -> (String, Arc<Mutex<Vec<(String, Value)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let published = Arc::new(Mutex::new(Vec::new()));
+
+        let received = published.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = socket.into_split();
+                    let mut reader = BufReader::new(reader);
+                    writer
+                        .write_all(b"INFO {\"server_id\":\"fake\",\"version\":\"2.10.0\",\"proto\":1,\"max_payload\":1048576}\r\n")
+                        .await
+                        .unwrap();
+
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+                        let parts: Vec<&str> = line.split_whitespace().collect();
+                        match parts.first().copied() {
+                            Some("PING") => writer.write_all(b"PONG\r\n").await.unwrap(),
+                            Some("PUB") => {
+                                let len: usize = parts.last().unwrap().parse().unwrap();
+                                let mut payload = vec![0; len + 2];
+                                reader.read_exact(&mut payload).await.unwrap();
+                                let event = serde_json::from_slice(&payload[..len]).unwrap();
+                                received.lock().push((parts[1].to_string(), event));
+                            }
+                            _ => {}
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        (url, published)
+    }
+
+    fn build_request() -> Request<Body> {
+        let project = tempfile::TempDir::new().unwrap();
+        std::fs::write(project.path().join("Makefile"), ".PHONY: firmware\nfirmware:\n\techo built > firmware\n").unwrap();
+        let archive = std::process::Command::new("tar")
+            .arg("-czf")
+            .arg("-")
+            .arg("-C")
+            .arg(project.path())
+            .arg(".")
+            .output()
+            .unwrap();
+
+        let boundary = "events-test-boundary";
+        let metadata = json!({"job_id": "events-test", "owner": "acme", "repo": "firmware", "installation_id": "123"});
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{metadata}\r\n\
+             --{boundary}\r\nContent-Disposition: form-data; name=\"archive\"; filename=\"repo\"\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(&archive.stdout);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        Request::builder()
+            .method("POST")
+            .uri("/build")
+            .header("content-type", format!("multipart/form-data; boundary={boundary}"))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_job_lifecycle_published_to_nats() {
+        let (url, published) = fake_nats_server().await;
+        std::env::set_var("EVENT_BUS_URL", &url);
+        std::env::set_var("EVENT_BUS_SUBJECT_PREFIX", "ci.builds");
+        let app = create_app();
+
+        let response = app.oneshot(build_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut events = Vec::new();
+        for _ in 0..200 {
+            events = published.lock().clone();
+            if events.len() >= 7 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let kinds: Vec<String> = events
+            .iter()
+            .map(|(_, event)| match event.get("phase") {
+                Some(phase) => format!("phase:{}", phase.as_str().unwrap()),
+                None => event["event"].as_str().unwrap().to_string(),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            ["queued", "started", "phase:fetch", "phase:detect", "phase:build", "phase:package", "completed"]
+        );
+        assert_eq!(events[0].0, "ci.builds.queued");
+        assert_eq!(events[6].0, "ci.builds.completed");
+        assert_eq!(events[6].1["build_system"], "Makefile");
+        assert!(events.iter().enumerate().all(|(i, (_, event))| event["sequence"] == i as u64));
+        assert!(events.iter().all(|(_, event)| event["client_job_id"] == "events-test"));
+    }
+}
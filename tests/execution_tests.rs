@@ -0,0 +1,1068 @@
+use nabla_runner::core::BuildConfig;
+use nabla_runner::execution::{cmake_configure_args, make_args, platformio_build_flags};
+use std::ffi::OsString;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Builds read some settings from the environment while they run, so a test that changes
+/// one holds this for its whole build
+static ENV: Mutex<()> = Mutex::const_new(());
+
+/// `name` set to `value`, or unset, until dropped, when it's put back as it was
+struct EnvVar {
+    name: &'static str,
+    saved: Option<OsString>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+async fn env_var(name: &'static str, value: Option<&str>) -> EnvVar {
+    let lock = ENV.lock().await;
+    let saved = std::env::var_os(name);
+    match value {
+        Some(value) => std::env::set_var(name, value),
+        None => std::env::remove_var(name),
+    }
+    EnvVar { name, saved, _lock: lock }
+}
+
+impl Drop for EnvVar {
+    fn drop(&mut self) {
+        match self.saved.take() {
+            Some(value) => std::env::set_var(self.name, value),
+            None => std::env::remove_var(self.name),
+        }
+    }
+}
+
+fn standards_config(c: &str, cpp: &str) -> BuildConfig {
+    BuildConfig {
+        c_standard: Some(c.to_string()),
+        cpp_standard: Some(cpp.to_string()),
+        ..BuildConfig::default()
+    }
+}
+
+#[test]
+fn test_language_standard_reaches_each_build_system() {
+    let config = standards_config("c11", "c++17");
+
+    let cmake = cmake_configure_args(&config);
+    assert_eq!(cmake[0], "..");
+    assert!(cmake.contains(&"-DCMAKE_C_STANDARD=11".to_string()));
+    assert!(cmake.contains(&"-DCMAKE_C_EXTENSIONS=OFF".to_string()));
+    assert!(cmake.contains(&"-DCMAKE_CXX_STANDARD=17".to_string()));
+
+    assert_eq!(make_args(&config), vec!["CFLAGS+=-std=c11", "CXXFLAGS+=-std=c++17"]);
+
+    assert_eq!(platformio_build_flags(&config).as_deref(), Some("-std=c11 -std=c++17"));
+}
+
+#[test]
+fn test_gnu_standard_enables_cmake_extensions() {
+    let config = standards_config("gnu99", "gnu++20");
+
+    let cmake = cmake_configure_args(&config);
+    assert!(cmake.contains(&"-DCMAKE_C_STANDARD=99".to_string()));
+    assert!(cmake.contains(&"-DCMAKE_C_EXTENSIONS=ON".to_string()));
+    assert!(cmake.contains(&"-DCMAKE_CXX_STANDARD=20".to_string()));
+    assert!(cmake.contains(&"-DCMAKE_CXX_EXTENSIONS=ON".to_string()));
+}
+
+#[test]
+fn test_no_standard_leaves_invocations_unchanged() {
+    let config = BuildConfig::default();
+
+    assert_eq!(cmake_configure_args(&config), vec![".."]);
+    assert!(make_args(&config).is_empty());
+    assert_eq!(platformio_build_flags(&config), None);
+}
+
+#[test]
+fn test_invalid_standard_rejected() {
+    assert!(standards_config("c11", "c++17").validate().is_ok());
+    assert!(standards_config("c++17", "c++17").validate().is_err());
+    assert!(standards_config("c11", "c17").validate().is_err());
+    assert!(standards_config("c11; rm -rf /", "c++17").validate().is_err());
+}
+
+mod post_processors {
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use nabla_runner::core::{Artifact, BuildConfig, BuildSystem};
+    use nabla_runner::execution::{ArtifactProcessor, BuildContext, ProcessorRegistry};
+    use nabla_runner::FirmwareBuildRunner;
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// Writes a ".signed" copy of each artifact, standing in for a vendor signing tool
+    struct FakeSigner;
+
+    #[async_trait]
+    impl ArtifactProcessor for FakeSigner {
+        fn name(&self) -> &str {
+            "fake-sign"
+        }
+
+        async fn process(&self, artifact: &Artifact, _ctx: &BuildContext) -> Result<Vec<Artifact>> {
+            let mut bytes = fs::read(&artifact.path)?;
+            bytes.extend_from_slice(b"SIGNED");
+            let signed_path = format!("{}.signed", artifact.path);
+            fs::write(&signed_path, bytes)?;
+            Ok(vec![Artifact::new(signed_path, artifact.format.clone())])
+        }
+    }
+
+    struct AlwaysFails;
+
+    #[async_trait]
+    impl ArtifactProcessor for AlwaysFails {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        async fn process(&self, _artifact: &Artifact, _ctx: &BuildContext) -> Result<Vec<Artifact>> {
+            Err(anyhow!("tool exploded"))
+        }
+    }
+
+    fn context(dir: &TempDir) -> BuildContext {
+        BuildContext {
+            repo_dir: dir.path().to_path_buf(),
+            build_system: BuildSystem::Makefile,
+            config: BuildConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_second_processor_consumes_first_output() {
+        let dir = TempDir::new().unwrap();
+        let firmware = dir.path().join("firmware.bin");
+        fs::write(&firmware, b"abc").unwrap();
+
+        let mut registry = ProcessorRegistry::default();
+        registry.register(Arc::new(FakeSigner));
+
+        let chain = vec!["fake-sign".to_string(), "checksum".to_string()];
+        let artifacts = registry
+            .run_chain(&chain, vec![Artifact::new(firmware.to_string_lossy(), "bin")], &context(&dir))
+            .await
+            .unwrap();
+
+        assert_eq!(artifacts.len(), 1);
+        assert!(artifacts[0].path.ends_with("firmware.bin.signed"));
+        // sha256("abcSIGNED"), proving checksum ran on the signer's output rather than the original
+        assert_eq!(
+            artifacts[0].metadata["sha256"],
+            "e0415936a1a8374b974e94cd05c73da209bef584f9e6e6b7cbabc60a8b00aa78"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_processor_failure_names_processor_and_artifact() {
+        let dir = TempDir::new().unwrap();
+        let firmware = dir.path().join("firmware.bin");
+        fs::write(&firmware, b"abc").unwrap();
+
+        let mut registry = ProcessorRegistry::default();
+        registry.register(Arc::new(AlwaysFails));
+
+        let err = registry
+            .run_chain(&["broken".to_string()], vec![Artifact::new(firmware.to_string_lossy(), "bin")], &context(&dir))
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("'broken'"), "{}", err);
+        assert!(err.contains("firmware.bin"), "{}", err);
+        assert!(err.contains("tool exploded"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_keeps_original_and_unknown_processor_rejected() {
+        let dir = TempDir::new().unwrap();
+        let firmware = dir.path().join("firmware.bin");
+        fs::write(&firmware, vec![0u8; 4096]).unwrap();
+
+        let registry = ProcessorRegistry::default();
+        let artifacts = registry
+            .run_chain(&["gzip".to_string()], vec![Artifact::new(firmware.to_string_lossy(), "bin")], &context(&dir))
+            .await
+            .unwrap();
+        assert_eq!(artifacts.len(), 2);
+        assert_eq!(artifacts[1].format, "bin.gz");
+        assert!(fs::metadata(&artifacts[1].path).unwrap().len() < 4096);
+
+        assert!(registry
+            .run_chain(&["nope".to_string()], artifacts, &context(&dir))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_runner_accepts_custom_processors() {
+        let mut runner = FirmwareBuildRunner::new();
+        assert!(runner.processors().get("fake-sign").is_none());
+        runner.register_processor(Arc::new(FakeSigner));
+        assert!(runner.processors().get("fake-sign").is_some());
+        assert!(runner.processors().get("checksum").is_some());
+    }
+}
+
+mod zephyr_sysbuild {
+    use nabla_runner::core::BuildConfig;
+    use nabla_runner::execution::{collect_zephyr_sysbuild_artifacts, zephyr_build_args};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_collects_every_image_default_first() {
+        let dir = TempDir::new().unwrap();
+        let build = dir.path().join("build");
+        for (image, files) in [
+            ("mcuboot", &["zephyr.elf", "zephyr.bin"][..]),
+            ("blinky", &["zephyr.elf", "zephyr.signed.hex"][..]),
+            ("hci_ipc", &["zephyr.hex"][..]),
+        ] {
+            let out = build.join(image).join("zephyr");
+            fs::create_dir_all(&out).unwrap();
+            for file in files {
+                fs::write(out.join(file), image).unwrap();
+            }
+        }
+        // sysbuild's own CMake tree is not an image
+        fs::create_dir_all(build.join("zephyr")).unwrap();
+        fs::write(build.join("domains.yaml"), "default: blinky\nbuild_dir: build\ndomains:\n  - name: blinky\n  - name: mcuboot\n").unwrap();
+        fs::write(build.join("merged.hex"), "merged").unwrap();
+
+        let artifacts = collect_zephyr_sysbuild_artifacts(&build).await.unwrap();
+
+        let summary: Vec<(&str, &str, &str)> = artifacts
+            .iter()
+            .map(|a| (a.metadata["image"].as_str(), a.metadata["role"].as_str(), a.format.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("blinky", "application", "elf"),
+                ("blinky", "application", "hex"),
+                ("hci_ipc", "image", "hex"),
+                ("mcuboot", "bootloader", "elf"),
+                ("mcuboot", "bootloader", "bin"),
+                ("merged", "merged", "hex"),
+            ]
+        );
+        assert!(artifacts[0].path.ends_with("blinky/zephyr/zephyr.elf"));
+        assert!(artifacts[1].path.ends_with("blinky/zephyr/zephyr.signed.hex"));
+        assert!(artifacts[5].path.ends_with("build/merged.hex"));
+    }
+
+    #[test]
+    fn test_sysbuild_flag_only_when_requested() {
+        let repo = TempDir::new().unwrap();
+        assert_eq!(zephyr_build_args(&BuildConfig::default(), repo.path(), Path::new("")), vec!["build"]);
+
+        let config = BuildConfig {
+            sysbuild: true,
+            ..BuildConfig::default()
+        };
+        assert_eq!(zephyr_build_args(&config, repo.path(), Path::new("app")), vec!["build", "--sysbuild", "app"]);
+
+        // A sysbuild.conf in the application turns it on too
+        fs::create_dir_all(repo.path().join("app")).unwrap();
+        fs::write(repo.path().join("app/sysbuild.conf"), "SB_CONFIG_BOOTLOADER_MCUBOOT=y\n").unwrap();
+        assert_eq!(zephyr_build_args(&BuildConfig::default(), repo.path(), Path::new("app")), vec!["build", "--sysbuild", "app"]);
+    }
+
+    #[test]
+    fn test_mcuboot_key_passed_to_sysbuild() {
+        let repo = TempDir::new().unwrap();
+        let config = BuildConfig {
+            mcuboot_key: Some("keys/root.pem".to_string()),
+            ..BuildConfig::default()
+        };
+        let key = format!("-DSB_CONFIG_BOOT_SIGNATURE_KEY_FILE=\"{}\"", repo.path().join("keys/root.pem").display());
+        assert_eq!(zephyr_build_args(&config, repo.path(), Path::new("")), ["build", "--sysbuild", "--", key.as_str()]);
+
+        assert!(config.validate().is_ok());
+        let outside = BuildConfig {
+            mcuboot_key: Some("../root.pem".to_string()),
+            ..BuildConfig::default()
+        };
+        assert!(outside.validate().unwrap_err().to_string().contains("Invalid mcuboot_key '../root.pem'"));
+        let missing = BuildConfig {
+            mcuboot_key_secret: Some("MCUBOOT_KEY".to_string()),
+            ..BuildConfig::default()
+        };
+        assert!(missing.validate().unwrap_err().to_string().contains("Invalid mcuboot_key_secret 'MCUBOOT_KEY' - not in secret_env"));
+    }
+}
+
+mod transient_retries {
+    use super::env_var;
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::execution::execute_build_with_config;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A Makefile whose first `failures` runs die with a registry 503, as `pio` does when a
+    /// package server flakes, before building normally
+    fn write_flaky_project(dir: &std::path::Path, failures: u32) {
+        fs::write(
+            dir.join("Makefile"),
+            format!(
+                "firmware: main.c\n\
+                 \t@n=$$(cat attempts 2>/dev/null || echo 0); n=$$((n+1)); echo $$n > attempts; \\\n\
+                 \tif [ $$n -le {failures} ]; then echo 'HTTPClientError: 503 Service Unavailable' >&2; exit 1; fi\n\
+                 \tgcc -o firmware main.c\n"
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join("main.c"), "int main(void) { return 0; }\n").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transient_failure_retried_until_success() {
+        let _env = env_var("NABLA_RETRY_BACKOFF_MS", Some("10")).await;
+        let dir = TempDir::new().unwrap();
+        write_flaky_project(dir.path(), 2);
+
+        let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &BuildConfig::default())
+            .await
+            .unwrap();
+
+        assert!(result.success, "{:?}", result.error_output);
+        assert_eq!(result.retries, 2);
+        assert_eq!(fs::read_to_string(dir.path().join("attempts")).unwrap().trim(), "3");
+        let numbers: Vec<u32> = result.attempts.iter().map(|attempt| attempt.attempt).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        assert!(result.attempts[0].error.as_deref().unwrap().contains("503"), "{:?}", result.attempts);
+        assert_eq!(result.attempts[2].error, None);
+    }
+
+    #[tokio::test]
+    async fn test_duration_covers_every_attempt() {
+        let _env = env_var("NABLA_RETRY_BACKOFF_MS", Some("10")).await;
+        let dir = TempDir::new().unwrap();
+        write_flaky_project(dir.path(), 1);
+        // The failing first attempt takes a second before giving up
+        let makefile = fs::read_to_string(dir.path().join("Makefile")).unwrap();
+        fs::write(dir.path().join("Makefile"), makefile.replace("then echo", "then sleep 1; echo")).unwrap();
+
+        let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &BuildConfig::default())
+            .await
+            .unwrap();
+
+        assert!(result.success, "{:?}", result.error_output);
+        assert_eq!(result.attempts.len(), 2);
+        assert!(result.attempts[0].duration_ms >= 1000, "{:?}", result.attempts);
+        assert_eq!(result.final_attempt_ms(), result.attempts[1].duration_ms);
+        assert!(result.final_attempt_ms() < 1000, "{:?}", result.attempts);
+        assert!(result.duration_ms >= result.attempts[0].duration_ms + result.attempts[1].duration_ms, "{}", result.duration_ms);
+    }
+
+    #[tokio::test]
+    async fn test_retries_bounded_and_skip_ordinary_failures() {
+        let _env = env_var("NABLA_RETRY_BACKOFF_MS", Some("10")).await;
+        let dir = TempDir::new().unwrap();
+        write_flaky_project(dir.path(), 5);
+        let config = BuildConfig { transient_retries: Some(1), ..BuildConfig::default() };
+
+        let outcome = execute_build_with_config(dir.path(), BuildSystem::Makefile, &config).await;
+        assert!(outcome.is_err() || !outcome.unwrap().success);
+        assert_eq!(fs::read_to_string(dir.path().join("attempts")).unwrap().trim(), "2");
+
+        // A compile error is not transient and runs once
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("Makefile"), "firmware: main.c\n\tgcc -o firmware main.c\n").unwrap();
+        fs::write(dir.path().join("main.c"), "int main(void) { return missing; }\n").unwrap();
+        let outcome = execute_build_with_config(dir.path(), BuildSystem::Makefile, &BuildConfig::default()).await;
+        match outcome {
+            Ok(result) => assert_eq!((result.success, result.retries), (false, 0)),
+            Err(e) => assert!(!e.to_string().contains("retries"), "{}", e),
+        }
+    }
+}
+
+mod network_policy {
+    use nabla_runner::core::{BuildConfig, BuildSystem, NetworkPolicy};
+    use nabla_runner::execution::{attempted_network_access, execute_build_with_config};
+    use nabla_runner::process::network_isolation_available;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn isolated_config() -> BuildConfig {
+        BuildConfig {
+            network_policy: NetworkPolicy::FetchThenIsolate,
+            transient_retries: Some(0),
+            ..BuildConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_fetching_from_network_fails_under_isolation() {
+        if !network_isolation_available().await {
+            eprintln!("skipping: network namespaces not permitted here");
+            return;
+        }
+        let dir = TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("Makefile"),
+            "firmware:\n\tcurl -sS --max-time 5 -o firmware https://example.com/blob.bin\n",
+        )
+        .unwrap();
+
+        let outcome = execute_build_with_config(dir.path(), BuildSystem::Makefile, &isolated_config()).await;
+
+        let message = match outcome {
+            Ok(result) => {
+                assert!(!result.success);
+                assert!(result.provenance.network_isolated);
+                result.error_output.unwrap()
+            }
+            Err(e) => e.to_string(),
+        };
+        assert!(message.starts_with("Build attempted network access while network-isolated"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_offline_build_records_isolation_in_provenance() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("Makefile"), "firmware: main.c\n\tgcc -o firmware main.c\n").unwrap();
+        fs::write(dir.path().join("main.c"), "int main(void) { return 0; }\n").unwrap();
+
+        let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &isolated_config())
+            .await
+            .unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.provenance.network_policy, NetworkPolicy::FetchThenIsolate);
+        let available = network_isolation_available().await;
+        assert_eq!(result.provenance.network_isolated, available);
+        // A runner that can't isolate says so in the record, not only in its own log
+        assert_eq!(result.provenance.network_isolation_unavailable.is_some(), !available);
+
+        let config: BuildConfig = serde_json::from_str(r#"{"network_policy": "fetch-then-isolate"}"#).unwrap();
+        assert_eq!(config.network_policy, NetworkPolicy::FetchThenIsolate);
+
+        let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &BuildConfig::default()).await.unwrap();
+        assert_eq!(result.provenance.network_isolation_unavailable, None);
+    }
+
+    #[test]
+    fn test_network_access_is_name_resolution_or_an_unreachable_network() {
+        let failed = "make: *** [Makefile:2: firmware] Error 1";
+        assert!(attempted_network_access(failed, "curl: (6) Could not resolve host: example.com"));
+        assert!(attempted_network_access(failed, "socket.gaierror: [Errno -3] Temporary failure in name resolution"));
+        assert!(attempted_network_access(failed, "connect: Network is unreachable"));
+        // Loopback stays up inside the namespace, so these are the build's own services
+        assert!(!attempted_network_access(failed, "curl: (7) Failed to connect to 127.0.0.1 port 8080: Connection refused"));
+        assert!(!attempted_network_access(failed, "psql: error: connection to server at \"localhost\" failed: Connection refused"));
+    }
+}
+
+mod secret_env {
+    use nabla_runner::core::{BuildConfig, BuildSystem, SecretEnv};
+    use nabla_runner::execution::execute_build_with_config;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use tempfile::TempDir;
+
+    const SECRET: &str = "sk_live_5f9c2a7e41";
+
+    fn secret_config() -> BuildConfig {
+        BuildConfig {
+            secret_env: SecretEnv::new(BTreeMap::from([("API_KEY".to_string(), SECRET.to_string())])),
+            ..BuildConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_secret_reaches_build_but_never_output() {
+        let dir = TempDir::new().unwrap();
+        // A verbose build that echoes the key, bakes it into the artifact, then fails
+        fs::write(
+            dir.path().join("Makefile"),
+            "firmware:\n\t@echo \"baking key $$API_KEY\"\n\t@printf '%s' \"$$API_KEY\" > firmware\n\
+             \t@echo \"main.c:1:1: warning: key $$API_KEY is hardcoded\" >&2\n\t@false\n",
+        )
+        .unwrap();
+        let config = secret_config();
+
+        let outcome = execute_build_with_config(dir.path(), BuildSystem::Makefile, &config).await;
+
+        assert_eq!(fs::read_to_string(dir.path().join("firmware")).unwrap(), SECRET);
+        let report = match outcome {
+            Ok(result) => serde_json::to_string(&result).unwrap(),
+            Err(e) => format!("{:#}", e),
+        };
+        assert!(report.contains("***"), "{}", report);
+        assert!(!report.contains(SECRET), "{}", report);
+        assert!(!format!("{:?}", config).contains(SECRET));
+        assert!(!serde_json::to_string(&config).unwrap().contains(SECRET));
+    }
+
+    #[test]
+    fn test_secret_env_parsed_and_validated() {
+        let config: BuildConfig =
+            serde_json::from_str(r#"{"secret_env": {"SIGNING_KEY": "hunter2hunter2"}}"#).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.secret_env.redact("key=hunter2hunter2"), "key=***");
+
+        let config: BuildConfig = serde_json::from_str(r#"{"secret_env": {"BAD NAME": "x"}}"#).unwrap();
+        assert!(config.validate().is_err());
+    }
+}
+
+mod post_build {
+    use super::env_var;
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::execution::execute_build_with_config;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn hook_config(hook: &str) -> BuildConfig {
+        BuildConfig {
+            post_build: Some(hook.to_string()),
+            ..BuildConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_build_hook_opt_in_and_outcome() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("Makefile"), ".PHONY: firmware\nfirmware:\n\techo built > firmware\n").unwrap();
+
+        let env = env_var("NABLA_ALLOW_HOOKS", None).await;
+        let error = execute_build_with_config(dir.path(), BuildSystem::Makefile, &hook_config("true"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("NABLA_ALLOW_HOOKS"), "{}", error);
+
+        drop(env);
+        let _env = env_var("NABLA_ALLOW_HOOKS", Some("1")).await;
+        let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &hook_config("cp {artifact} signed.bin && echo signed {artifact}"))
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error_output);
+        assert_eq!(fs::read_to_string(dir.path().join("signed.bin")).unwrap(), "built\n");
+        let artifact = result.output_path.unwrap();
+        assert_eq!(result.post_build_output.unwrap().trim(), format!("signed {}", artifact));
+
+        let result = execute_build_with_config(dir.path(), BuildSystem::Makefile, &hook_config("echo bad signature >&2; exit 3"))
+            .await
+            .unwrap();
+        assert!(!result.success);
+        let error = result.error_output.unwrap();
+        assert!(error.starts_with("post_build hook failed"), "{}", error);
+        assert!(error.contains("bad signature"), "{}", error);
+    }
+}
+
+mod make_success {
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::execution::{execute_build_with_config, make_default_goal};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_make_default_goal_from_the_makefile() {
+        let makefile = "CC = arm-none-eabi-gcc\n.PHONY: clean\n\nbuild/blinky.elf: main.o \\\n\tstartup.o\n\t$(CC) -o $@ $^\n\nclean:\n\trm -rf build\n";
+        assert_eq!(make_default_goal(makefile).as_deref(), Some("build/blinky.elf"));
+        assert_eq!(make_default_goal("firmware: main.c\n\tcc -o firmware main.c\n").as_deref(), Some("firmware"));
+        assert_eq!(make_default_goal("all: out.bin\nout.bin:\n\ttouch out.bin\n.DEFAULT_GOAL := out.bin\n").as_deref(), Some("out.bin"));
+        // Special targets and assignments aren't goals
+        assert_eq!(make_default_goal(".SUFFIXES:\nLDFLAGS := -T link.ld\nbuild/app.hex: FLAGS = -O2\nbuild/app.hex:\n").as_deref(), Some("build/app.hex"));
+        // Conventionally phony, whether declared or not
+        assert_eq!(make_default_goal("all: firmware\n"), None);
+        assert_eq!(make_default_goal(".PHONY: clean out.bin\nout.bin:\n"), None);
+        // What an include or a variable names can't be known without make
+        assert_eq!(make_default_goal("include rules.mk\nfirmware.elf:\n"), None);
+        assert_eq!(make_default_goal("$(TARGET).elf: main.o\n"), None);
+        assert_eq!(make_default_goal("# no rules\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_default_goal_is_found_without_running_make() {
+        let repo = TempDir::new().unwrap();
+        // Every make that reads this Makefile, `make -n` included, appends a line
+        let runs = repo.path().join("runs");
+        fs::write(
+            repo.path().join("Makefile"),
+            format!("PROBE := $(shell echo ran >> {})\nfirmware.bin:\n\techo built > firmware.bin\n", runs.display()),
+        )
+        .unwrap();
+
+        let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default()).await.unwrap();
+
+        assert!(result.success, "{:?}", result.error_output);
+        // Only the build itself, which runs under the request's container, isolation and limits
+        assert_eq!(fs::read_to_string(&runs).unwrap(), "ran\n");
+    }
+
+    #[tokio::test]
+    async fn test_make_exiting_zero_without_its_target_fails() {
+        let repo = TempDir::new().unwrap();
+        // The leading `-` makes make ignore the compiler error and exit 0
+        fs::write(
+            repo.path().join("Makefile"),
+            "build/firmware.elf:\n\t-@echo \"main.c:3:1: error: expected ';'\" >&2; false\n",
+        )
+        .unwrap();
+        // An executable the artifact search would otherwise settle for
+        fs::write(repo.path().join("flash.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(repo.path().join("flash.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let error = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default())
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("did not produce its default target `build/firmware.elf`"), "{}", error);
+        assert!(error.contains("error: expected ';'"), "{}", error);
+    }
+}
+
+mod exit_status {
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::execution::{execute_build_with_config, BuildStepFailed};
+    use std::fs;
+    use tempfile::TempDir;
+
+    async fn failed_make(recipe: &str) -> BuildStepFailed {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("Makefile"), format!("firmware.elf:\n\t{}\n", recipe)).unwrap();
+
+        execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default())
+            .await
+            .unwrap_err()
+            .downcast::<BuildStepFailed>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failed_build_reports_exit_code() {
+        let failed = failed_make("@exit 3").await;
+        // make exits 2 for a failed recipe whatever the recipe's own status
+        assert_eq!((failed.exit.exit_code, failed.exit.signal), (Some(2), None));
+    }
+
+    #[tokio::test]
+    async fn test_killed_build_reports_signal() {
+        // Kill make itself, as the OOM killer would
+        let failed = failed_make("@kill -9 $$PPID").await;
+        assert_eq!((failed.exit.exit_code, failed.exit.signal), (None, Some(9)));
+    }
+}
+
+mod output_streams {
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::execution::{execute_build_with_config, BuildStepFailed};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_stdout_and_stderr_captured_separately() {
+        let repo = TempDir::new().unwrap();
+        fs::write(
+            repo.path().join("Makefile"),
+            "firmware.bin:\n\t@echo '   text    data     bss' && echo '   1024      16     256'\n\
+             \t@echo 'note: flash almost full' >&2\n\t@printf fw > firmware.bin\n",
+        )
+        .unwrap();
+
+        let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default()).await.unwrap();
+        assert!(result.success, "{:?}", result.error_output);
+        assert_eq!(result.stdout, "   text    data     bss\n   1024      16     256\n");
+        assert_eq!(result.stderr, "note: flash almost full\n");
+
+        // A failed build keeps both, too
+        fs::write(repo.path().join("Makefile"), "out.elf:\n\t@echo 'Ran 3 tests, 1 failed'\n\t@echo 'boom' >&2; exit 1\n").unwrap();
+        let failed = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default())
+            .await
+            .unwrap_err()
+            .downcast::<BuildStepFailed>()
+            .unwrap();
+        assert!(failed.stdout.contains("Ran 3 tests, 1 failed"), "{}", failed.stdout);
+        assert!(failed.stderr.contains("boom") && !failed.stderr.contains("Ran 3 tests"), "{}", failed.stderr);
+    }
+}
+
+mod artifact_globs {
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::execution::execute_build_with_config;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_output_found_only_by_custom_glob() {
+        let repo = TempDir::new().unwrap();
+        fs::write(
+            repo.path().join("Makefile"),
+            "all:\n\tmkdir -p out/images && printf image > out/images/app_v2.img\n.PHONY: all\n",
+        )
+        .unwrap();
+
+        let error = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Could not find built binary"), "{}", error);
+
+        let config = BuildConfig {
+            artifact_globs: vec!["out/*.elf".to_string(), "out/images/app_*".to_string()],
+            ..BuildConfig::default()
+        };
+        let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap();
+        assert!(result.success, "{:?}", result.error_output);
+        assert!(result.output_path.unwrap().ends_with("out/images/app_v2.img"));
+    }
+
+    #[test]
+    fn test_artifact_globs_stay_in_the_repository() {
+        for glob in ["../secrets/*", "/etc/*", ""] {
+            let config = BuildConfig {
+                artifact_globs: vec![glob.to_string()],
+                ..BuildConfig::default()
+            };
+            assert!(config.validate().unwrap_err().to_string().contains("Invalid artifact_globs entry"), "{}", glob);
+        }
+    }
+}
+
+mod stm32_cubemx {
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::detection::{detect_flavor, BuildFlavor};
+    use nabla_runner::execution::execute_build_with_config;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    /// The parts of a CubeMX-exported Makefile the runner relies on
+    const CUBEMX_MAKEFILE: &str = "\
+# File automatically-generated by tool: [projectgenerator] version: [4.1.0-B60]
+TARGET = blinky
+BUILD_DIR = build
+C_SOURCES = Core/Src/main.c Drivers/STM32F4xx_HAL_Driver/Src/stm32f4xx_hal.c
+PREFIX = arm-none-eabi-
+ifdef GCC_PATH
+CC = $(GCC_PATH)/$(PREFIX)gcc
+CP = $(GCC_PATH)/$(PREFIX)objcopy
+else
+CC = $(PREFIX)gcc
+CP = $(PREFIX)objcopy
+endif
+HEX = $(CP) -O ihex
+BIN = $(CP) -O binary -S
+C_INCLUDES = -ICore/Inc -IDrivers/CMSIS/Include
+LDSCRIPT = STM32F407VGTx_FLASH.ld
+
+all: $(BUILD_DIR)/$(TARGET).elf $(BUILD_DIR)/$(TARGET).hex $(BUILD_DIR)/$(TARGET).bin
+
+$(BUILD_DIR)/$(TARGET).elf: | $(BUILD_DIR)
+\t$(CC) $(C_SOURCES) $(C_INCLUDES) -T$(LDSCRIPT) -o $@
+
+$(BUILD_DIR)/%.hex: $(BUILD_DIR)/%.elf
+\t$(HEX) $< $@
+
+$(BUILD_DIR)/%.bin: $(BUILD_DIR)/%.elf
+\t$(BIN) $< $@
+
+$(BUILD_DIR):
+\tmkdir $@
+";
+
+    /// Stand-ins for the ARM toolchain that write their tool name to the output file
+    fn write_arm_toolchain(dir: &Path) {
+        for tool in ["arm-none-eabi-gcc", "arm-none-eabi-objcopy"] {
+            let path = dir.join(tool);
+            fs::write(
+                &path,
+                "#!/bin/sh\n[ \"$1\" = --version ] && { echo \"$(basename \"$0\") 13.2.1\"; exit 0; }\n\
+                 for last; do :; done\nbasename \"$0\" > \"$last\"\n",
+            )
+            .unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    fn config_with_path(path: String) -> BuildConfig {
+        let mut config = BuildConfig::default();
+        config.command_env.insert("PATH".to_string(), path);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_cubemx_makefile_builds_with_cross_toolchain() {
+        let tools = TempDir::new().unwrap();
+        write_arm_toolchain(tools.path());
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("Makefile"), CUBEMX_MAKEFILE).unwrap();
+        assert_eq!(detect_flavor(repo.path(), BuildSystem::Makefile).await, Some(BuildFlavor::Stm32Cubemx));
+
+        let config = config_with_path(format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap()));
+        let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap();
+
+        assert!(result.success, "{:?}", result.error_output);
+        let formats: Vec<&str> = result.artifacts.iter().map(|a| a.format.as_str()).collect();
+        assert_eq!(formats, ["bin", "hex", "elf"]);
+        assert!(result.output_path.unwrap().ends_with("build/blinky.bin"));
+        assert_eq!(fs::read_to_string(repo.path().join("build/blinky.elf")).unwrap(), "arm-none-eabi-gcc\n");
+    }
+
+    #[tokio::test]
+    async fn test_cubemx_makefile_without_toolchain_fails_up_front() {
+        let empty = TempDir::new().unwrap();
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("Makefile"), CUBEMX_MAKEFILE).unwrap();
+
+        let config = config_with_path(empty.path().display().to_string());
+        let error = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap_err();
+
+        assert!(error.to_string().contains("arm-none-eabi-gcc was not found"), "{}", error);
+        assert!(!repo.path().join("build").exists());
+    }
+}
+
+mod cargo {
+    use nabla_runner::core::{BuildConfig, BuildSystem};
+    use nabla_runner::execution::{analyze_cargo_error, build_command_line, cargo_build_args, cargo_workspace_members, execute_build_with_config};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_cargo_invocation_from_config() {
+        assert_eq!(cargo_build_args(&BuildConfig::default()), ["build"]);
+
+        let config: BuildConfig = serde_json::from_value(serde_json::json!({
+            "cargo_target": "thumbv7em-none-eabihf",
+            "features": ["defmt", "rtic/thumbv7-backend"],
+            "no_default_features": true,
+            "release": true,
+        }))
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            cargo_build_args(&config),
+            ["build", "--release", "--target", "thumbv7em-none-eabihf", "--no-default-features", "--features", "defmt,rtic/thumbv7-backend"]
+        );
+
+        let dir = TempDir::new().unwrap();
+        assert_eq!(
+            build_command_line(dir.path(), BuildSystem::Cargo, &config).await,
+            "cargo build --release --target thumbv7em-none-eabihf --no-default-features --features defmt,rtic/thumbv7-backend"
+        );
+    }
+
+    #[test]
+    fn test_invalid_cargo_options_rejected() {
+        let config = BuildConfig {
+            cargo_target: Some("--config=build.rustc-wrapper='sh'".to_string()),
+            ..BuildConfig::default()
+        };
+        assert!(config.validate().unwrap_err().to_string().contains("Invalid cargo_target"));
+
+        let config = BuildConfig {
+            features: vec!["-Zbuild-std".to_string()],
+            ..BuildConfig::default()
+        };
+        assert!(config.validate().unwrap_err().to_string().contains("Invalid features entry"));
+    }
+
+    #[test]
+    fn test_analyze_cargo_error() {
+        let missing_target = "\
+error[E0463]: can't find crate for `core`
+  |
+  = note: the `thumbv7em-none-eabihf` target may not be installed
+  = help: consider downloading the target with `rustup target add thumbv7em-none-eabihf`
+";
+        let hint = analyze_cargo_error(missing_target).unwrap();
+        assert!(hint.contains("rustup target add thumbv7em-none-eabihf"), "{}", hint);
+
+        let hint = analyze_cargo_error("sh: 1: cargo: not found\n").unwrap();
+        assert!(hint.starts_with("cargo was not found on the runner"), "{}", hint);
+
+        let hint = analyze_cargo_error("error: no bin target named `blinkey`.\n\nAvailable bin targets:\n    blinky\n").unwrap();
+        assert!(hint.contains("no binary named `blinkey`") && hint.contains("cargo_bin"), "{}", hint);
+
+        let hint = analyze_cargo_error("error: package ID specification `firmwre` did not match any packages\n").unwrap();
+        assert!(hint.contains("`firmwre`") && hint.contains("cargo_package"), "{}", hint);
+
+        let hint = analyze_cargo_error("error: Package `blinky v0.1.0 (/src)` does not have the feature `defmt`\n").unwrap();
+        assert!(hint.contains("no feature `defmt`"), "{}", hint);
+
+        let hint = analyze_cargo_error("error: none of the selected packages contains these features: rtt, defmt\n").unwrap();
+        assert!(hint.contains("no feature rtt, defmt"), "{}", hint);
+
+        assert_eq!(analyze_cargo_error("error[E0425]: cannot find value `x` in this scope\n"), None);
+    }
+
+    /// A `cargo` that links an ELF for the requested target and profile, or fails like rustc
+    /// does when the target's standard library is missing
+    const STUB_CARGO: &str = r#"#!/bin/sh
+target=host; profile=debug
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --release) profile=release ;;
+        --target) shift; target="$1" ;;
+    esac
+    shift
+done
+if [ "$target" = host ] && [ -f .cargo/config.toml ]; then
+    target=$(sed -n 's/^target = "\(.*\)"/\1/p' .cargo/config.toml)
+fi
+if [ "$target" = riscv32imac-unknown-none-elf ]; then
+    echo "error[E0463]: can't find crate for \`core\`" >&2
+    echo "  = note: the \`$target\` target may not be installed" >&2
+    exit 101
+fi
+dir=target/$target/$profile
+[ "$target" = host ] && dir=target/$profile
+mkdir -p "$dir"
+printf '\177ELF\001\001\001' > "$dir/blinky"
+chmod +x "$dir/blinky"
+"#;
+
+    #[tokio::test]
+    async fn test_cargo_build_locates_cross_compiled_elf() {
+        let tools = TempDir::new().unwrap();
+        let cargo = tools.path().join("cargo");
+        fs::write(&cargo, STUB_CARGO).unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("Cargo.toml"), "[package]\nname = \"blinky\"\nversion = \"0.1.0\"\n\n[dependencies]\nname = \"not-the-package\"\n").unwrap();
+        let mut config = BuildConfig {
+            cargo_target: Some("thumbv7em-none-eabihf".to_string()),
+            release: true,
+            ..BuildConfig::default()
+        };
+        let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+        config.command_env.insert("PATH".to_string(), path);
+
+        let result = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap();
+        assert!(result.success, "{:?}", result.error_output);
+        assert_eq!(result.target_format.as_deref(), Some("elf"));
+        let expected = repo.path().join("target/thumbv7em-none-eabihf/release/blinky");
+        assert_eq!(result.output_path.as_deref(), Some(expected.to_str().unwrap()));
+
+        // `[build] target` in .cargo/config.toml decides where the output lands too
+        fs::create_dir(repo.path().join(".cargo")).unwrap();
+        fs::write(repo.path().join(".cargo/config.toml"), "[build]\ntarget = \"thumbv6m-none-eabi\"\n").unwrap();
+        config.cargo_target = None;
+        let result = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap();
+        assert!(result.success, "{:?}", result.error_output);
+        assert!(result.output_path.unwrap().ends_with("target/thumbv6m-none-eabi/release/blinky"));
+
+        config.cargo_target = Some("riscv32imac-unknown-none-elf".to_string());
+        let error = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap_err();
+        assert!(error.to_string().contains("rustup target add riscv32imac-unknown-none-elf"), "{}", error);
+    }
+
+    /// A `cargo` for workspaces: logs where it ran and with what, then links the selected binary
+    /// under the workspace root's target directory, for the target in the current directory's
+    /// `.cargo/config.toml`
+    const STUB_WORKSPACE_CARGO: &str = r#"#!/bin/sh
+root=$PWD
+while ! grep -q '^\[workspace\]' "$root/Cargo.toml" 2>/dev/null; do root=$(dirname "$root"); done
+echo "${PWD#$root} $*" >> "$root/cargo.log"
+target=host; profile=debug; bin=unknown
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --release) profile=release ;;
+        --bin) shift; bin="$1" ;;
+    esac
+    shift
+done
+if [ -f .cargo/config.toml ]; then
+    target=$(sed -n 's/^target = "\(.*\)"/\1/p' .cargo/config.toml)
+fi
+dir=$root/target/$target/$profile
+[ "$target" = host ] && dir=$root/target/$profile
+mkdir -p "$dir"
+printf '\177ELF\001\001\001' > "$dir/$bin"
+chmod +x "$dir/$bin"
+"#;
+
+    /// Firmware for a Cortex-M target and a host-side flashing tool in one workspace
+    fn two_member_workspace() -> (TempDir, TempDir, BuildConfig) {
+        let tools = TempDir::new().unwrap();
+        let cargo = tools.path().join("cargo");
+        fs::write(&cargo, STUB_WORKSPACE_CARGO).unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let repo = TempDir::new().unwrap();
+        let root = repo.path();
+        fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\n    \"firmware\",\n    \"tools/*\",\n]\nresolver = \"2\"\n").unwrap();
+        fs::create_dir_all(root.join("firmware/src")).unwrap();
+        fs::create_dir_all(root.join("firmware/.cargo")).unwrap();
+        fs::write(
+            root.join("firmware/Cargo.toml"),
+            "[package]\nname = \"blinky-fw\"\nversion = \"0.1.0\"\n\n[[bin]]\nname = \"blinky\"\npath = \"src/main.rs\"\n",
+        )
+        .unwrap();
+        fs::write(root.join("firmware/src/main.rs"), "#![no_std]\n#![no_main]\n").unwrap();
+        fs::write(root.join("firmware/.cargo/config.toml"), "[build]\ntarget = \"thumbv7em-none-eabihf\"\n").unwrap();
+        fs::create_dir_all(root.join("tools/flasher/src")).unwrap();
+        fs::write(root.join("tools/flasher/Cargo.toml"), "[package]\nname = \"flasher\"\nversion = \"0.1.0\"\n").unwrap();
+        fs::write(root.join("tools/flasher/src/main.rs"), "fn main() {}\n").unwrap();
+
+        let mut config = BuildConfig {
+            release: true,
+            ..BuildConfig::default()
+        };
+        let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+        config.command_env.insert("PATH".to_string(), path);
+        (tools, repo, config)
+    }
+
+    #[tokio::test]
+    async fn test_workspace_builds_embedded_member() {
+        let (_tools, repo, config) = two_member_workspace();
+
+        let members = cargo_workspace_members(repo.path()).await.unwrap();
+        let members: Vec<(&str, Vec<String>)> = members.iter().map(|m| (m.package.as_str(), m.bins.clone())).collect();
+        assert_eq!(members, [("blinky-fw", vec!["blinky".to_string()]), ("flasher", vec!["flasher".to_string()])]);
+
+        let result = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap();
+        assert!(result.success, "{:?}", result.error_output);
+        let expected = repo.path().join("target/thumbv7em-none-eabihf/release/blinky");
+        assert_eq!(result.output_path.as_deref(), Some(expected.to_str().unwrap()));
+        assert_eq!(fs::read_to_string(repo.path().join("cargo.log")).unwrap(), "/firmware build -p blinky-fw --bin blinky --release\n");
+        assert_eq!(
+            build_command_line(repo.path(), BuildSystem::Cargo, &config).await,
+            "cd firmware && cargo build -p blinky-fw --bin blinky --release"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workspace_member_chosen_by_config() {
+        let (_tools, repo, mut config) = two_member_workspace();
+        config.cargo_package = Some("flasher".to_string());
+
+        let result = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap();
+        assert!(result.success, "{:?}", result.error_output);
+        assert!(result.output_path.unwrap().ends_with("target/release/flasher"));
+        assert_eq!(fs::read_to_string(repo.path().join("cargo.log")).unwrap(), "/tools/flasher build -p flasher --bin flasher --release\n");
+
+        config.cargo_package = Some("bootloader".to_string());
+        let error = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap_err();
+        assert!(error.to_string().contains("members: blinky-fw, flasher"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_missing_cargo_reported_as_failed_build() {
+        let empty = TempDir::new().unwrap();
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("Cargo.toml"), "[package]\nname = \"blinky\"\n").unwrap();
+        let mut config = BuildConfig::default();
+        config.command_env.insert("PATH".to_string(), empty.path().display().to_string());
+
+        let result = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap();
+        assert!(!result.success);
+        assert!(result.error_output.unwrap().starts_with("cargo was not found on the runner"));
+    }
+}
@@ -0,0 +1,46 @@
+use nabla_runner::remote::{download_archive, http_client};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// An archive host that reads each request, sends `reply`, then goes quiet for a minute
+async fn stalling_host(reply: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/repo.tar.gz", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = vec![0; 4096];
+                let _ = socket.read(&mut request).await;
+                let _ = socket.write_all(reply.as_bytes()).await;
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            });
+        }
+    });
+    url
+}
+
+#[tokio::test]
+async fn test_stalled_archive_host_times_out() {
+    std::env::set_var("NABLA_FETCH_TIMEOUT_SECS", "1");
+    let dest = TempDir::new().unwrap();
+    let client = http_client().unwrap();
+
+    // No response at all, then headers and part of the body
+    let cases = [
+        ("", "waiting for a response"),
+        ("HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\npartial", "waiting for more of the archive"),
+    ];
+    for (reply, expected) in cases {
+        let url = stalling_host(reply).await;
+        let started = Instant::now();
+        let error = download_archive(&client, &url, None, &dest.path().join("repo.tar.gz")).await.unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let message = error.to_string();
+        assert!(message.contains("timed out after 1s"), "{}", message);
+        assert!(message.contains(expected), "{}", message);
+    }
+}
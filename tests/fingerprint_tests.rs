@@ -0,0 +1,59 @@
+use nabla_runner::fingerprint::{fingerprint, skeleton};
+
+#[test]
+fn test_variations_of_one_error_share_a_fingerprint() {
+    let variations = [
+        (
+            "Failed to install platform espressif32@6.1.11: HTTPClientError in /tmp/job-1a2b/.platformio/packages",
+            "Failed to install platform espressif32@6.3.2: HTTPClientError in /workspace/acme/job-77/.platformio/packages",
+        ),
+        (
+            "Segmentation fault (core dumped) at 0x7ffd3a2c1b40 in cc1 pid 31337",
+            "Segmentation fault (core dumped) at 0x55aa00ff in cc1 pid 42",
+        ),
+        (
+            "fatal: reference is not a tree: 1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b",
+            "fatal: reference is not a tree: 0f9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3a2f1e",
+        ),
+        (
+            "/tmp/nabla-1/src/main.c:12:5: error: 'LED_PIN' undeclared\nmake: *** [Makefile:20: main.o] Error 1",
+            "/tmp/nabla-2/src/main.c:14:9: error: 'LED_PIN' undeclared\n\n  make: ***   [Makefile:31: main.o] Error 1",
+        ),
+        ("Tool manager: Installing toolchain-xtensa-esp32 v8.4.0+2021r2", "Tool manager: Installing toolchain-xtensa-esp32 v12.2.0+20230208"),
+    ];
+    for (first, second) in variations {
+        assert_eq!(fingerprint(first), fingerprint(second), "{:?} vs {:?}", skeleton(first), skeleton(second));
+    }
+}
+
+#[test]
+fn test_different_errors_get_different_fingerprints() {
+    let errors = [
+        "/tmp/a/main.c:3:1: error: 'LED_PIN' undeclared",
+        "/tmp/a/main.c:3:1: error: 'BUTTON_PIN' undeclared",
+        "Failed to install platform espressif32@6.1.11",
+        "Failed to install platform espressif8266@4.2.1",
+        "arm-none-eabi-gcc: command not found",
+        "cmake: command not found",
+        "undefined reference to `HAL_Init'",
+    ];
+    for (i, a) in errors.iter().enumerate() {
+        for b in &errors[i + 1..] {
+            assert_ne!(fingerprint(a), fingerprint(b), "{:?} and {:?}", a, b);
+        }
+    }
+}
+
+#[test]
+fn test_skeleton_placeholders() {
+    assert_eq!(
+        skeleton("  Installing 'framework-arduinoespressif32 @ 3.20014.231204' into /root/.platformio (took 12s)  "),
+        "Installing 'framework-arduinoespressif32 @ <ver>' into <path> (took <n>s)"
+    );
+    assert_eq!(skeleton("stm32f4xx_hal.c at 0xDEADBEEF, job 3f2a9c1e-77b0-4c1d-9a8b-0123456789ab"), "stm32f4xx_hal.c at <addr>, job <hash>");
+    // Deterministic, and 16 hex characters
+    let print = fingerprint("Error 1");
+    assert_eq!(print, fingerprint("Error 2"));
+    assert_eq!(print.len(), 16);
+    assert!(print.chars().all(|c| c.is_ascii_hexdigit()));
+}
@@ -0,0 +1,74 @@
+use nabla_runner::core::{ArtifactFormat, BuildConfig, BuildResult, BuildSystem};
+use nabla_runner::FirmwareBuildRunner;
+use std::fs;
+use tempfile::TempDir;
+
+/// A project whose Makefile compiles `firmware` for the host, an ELF without an extension
+fn elf_project() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("main.c"), "int main(void) { return 0; }\n").unwrap();
+    fs::write(dir.path().join("Makefile"), "firmware: main.c\n\tgcc -o firmware main.c\n").unwrap();
+    dir
+}
+
+async fn build(dir: &TempDir, config: &BuildConfig) -> BuildResult {
+    let result = FirmwareBuildRunner::new().build_with_config(dir.path(), BuildSystem::Makefile, config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    result
+}
+
+#[tokio::test]
+async fn test_elf_converted_to_preferred_format() {
+    let dir = elf_project();
+    let config = BuildConfig { artifact_format: vec![ArtifactFormat::Hex, ArtifactFormat::Bin], ..BuildConfig::default() };
+    let result = build(&dir, &config).await;
+
+    let elf = dir.path().join("firmware").to_string_lossy().to_string();
+    let hex = dir.path().join("firmware.hex").to_string_lossy().to_string();
+    assert_eq!((result.output_path.as_deref(), result.target_format.as_deref()), (Some(hex.as_str()), Some("hex")));
+    assert!(fs::read_to_string(&hex).unwrap().starts_with(':'));
+
+    let delivered: Vec<(&str, &str, &str)> =
+        result.artifacts.iter().map(|a| (a.path.as_str(), a.format.as_str(), a.metadata["origin"].as_str())).collect();
+    assert_eq!(delivered, [(hex.as_str(), "hex", "derived"), (elf.as_str(), "elf", "original")]);
+    assert_eq!(result.artifacts[0].metadata["derived_from"], elf);
+    assert!(result.config_warnings.is_empty(), "{:?}", result.config_warnings);
+
+    let config = BuildConfig { artifact_format: vec![ArtifactFormat::Bin], ..BuildConfig::default() };
+    let result = build(&elf_project(), &config).await;
+    assert_eq!(result.target_format.as_deref(), Some("bin"));
+    assert!(result.output_path.unwrap().ends_with("firmware.bin"));
+}
+
+#[tokio::test]
+async fn test_raw_bin_converted_to_hex_at_load_address() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("Makefile"), "firmware.bin:\n\tprintf '\\001\\002\\003\\004' > firmware.bin\n").unwrap();
+    let mut config = BuildConfig { artifact_format: vec![ArtifactFormat::Hex], ..BuildConfig::default() };
+
+    // Without a load address the bin is delivered as it is, with a warning
+    let result = build(&dir, &config).await;
+    assert_eq!(result.target_format.as_deref(), Some("bin"));
+    assert_eq!(result.artifacts.len(), 1);
+    assert!(result.config_warnings[0].contains("set bin_load_address"), "{:?}", result.config_warnings);
+    assert!(result.config_warnings[1].contains("None of the requested artifact formats (hex)"), "{:?}", result.config_warnings);
+
+    config.bin_load_address = Some("0x08000000".to_string());
+    let result = build(&dir, &config).await;
+    assert_eq!(result.target_format.as_deref(), Some("hex"));
+    assert_eq!(result.artifacts[0].metadata["load_address"], "0x08000000");
+    let hex = fs::read_to_string(dir.path().join("firmware.hex")).unwrap();
+    // Extended linear address 0x0800, then the four bytes at offset 0
+    let records: Vec<&str> = hex.lines().map(str::trim_end).take(2).collect();
+    assert_eq!(records, [":020000040800F2", ":0400000001020304F2"], "{}", hex);
+}
+
+#[test]
+fn test_bin_load_address_validated() {
+    let config = |address: &str| BuildConfig { bin_load_address: Some(address.to_string()), ..BuildConfig::default() };
+
+    assert!(config("0x08000000").validate().is_ok());
+    assert!(config("134217728").validate().is_ok());
+    let error = config("0x0800zz").validate().unwrap_err().to_string();
+    assert!(error.contains("Invalid bin_load_address '0x0800zz'"), "{}", error);
+}
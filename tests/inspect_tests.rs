@@ -0,0 +1,97 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use nabla_runner::server::create_app;
+use serde_json::{json, Value};
+use std::fs;
+use tower::util::ServiceExt;
+
+const CUBEMX_MAKEFILE: &str = "TARGET = blinky\nPREFIX = arm-none-eabi-\nC_SOURCES = Core/Src/main.c Drivers/STM32F4xx_HAL_Driver/Src/stm32f4xx_hal.c\n";
+
+/// A small CubeMX project as a tar.gz
+fn blinky_archive() -> Vec<u8> {
+    let project = tempfile::TempDir::new().unwrap();
+    let files = [
+        ("Makefile", CUBEMX_MAKEFILE),
+        ("README.md", "# blinky\n"),
+        ("Core/Src/main.c", "int main(void) { for (;;); }\n"),
+        ("Core/Inc/main.h", "#pragma once\n"),
+    ];
+    for (file, contents) in files {
+        let path = project.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    let archive = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg("-")
+        .arg("-C")
+        .arg(project.path())
+        .arg(".")
+        .output()
+        .unwrap();
+    archive.stdout
+}
+
+async fn inspect(metadata: Value) -> (StatusCode, Value) {
+    let boundary = "inspect-boundary";
+    let mut body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{metadata}\r\n\
+         --{boundary}\r\nContent-Disposition: form-data; name=\"archive\"; filename=\"repo\"\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(&blinky_archive());
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/inspect")
+        .header("content-type", format!("multipart/form-data; boundary={boundary}"))
+        .body(Body::from(body))
+        .unwrap();
+    let response = create_app().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_inspect_lists_files_and_detection() {
+    let (status, json) = inspect(json!({"installation_id": "123"})).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["status"], "inspected");
+    assert_eq!(
+        json["files"],
+        json!([
+            {"path": "Core/Inc/main.h", "size": 13},
+            {"path": "Core/Src/main.c", "size": 29},
+            {"path": "Makefile", "size": CUBEMX_MAKEFILE.len()},
+            {"path": "README.md", "size": 9},
+        ])
+    );
+    assert_eq!(json["total_files"], 4);
+    assert_eq!(json["total_bytes"], 51 + CUBEMX_MAKEFILE.len());
+    assert_eq!(json["omitted_files"], 0);
+    assert_eq!(json["detection"]["build_system"], "Makefile");
+    assert_eq!(json["detection"]["flavor"], "stm32_cubemx");
+}
+
+#[tokio::test]
+async fn test_inspect_truncates_listing() {
+    let (status, json) = inspect(json!({"installation_id": "123", "max_files": 2})).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    let paths: Vec<&str> = json["files"].as_array().unwrap().iter().map(|file| file["path"].as_str().unwrap()).collect();
+    assert_eq!(paths, ["Core/Inc/main.h", "Core/Src/main.c"]);
+    assert_eq!(json["total_files"], 4);
+    assert_eq!(json["omitted_files"], 2);
+}
+
+#[tokio::test]
+async fn test_inspect_rejects_archive_url_with_upload() {
+    let (status, json) = inspect(json!({"installation_id": "123", "archive_url": "https://example.com/a.tar.gz"})).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["message"], "invalid request: archive_url cannot be combined with an uploaded archive");
+}
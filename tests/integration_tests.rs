@@ -2,39 +2,45 @@ use anyhow::Result;
 use axum::{
     body::Body,
     http::{Request, StatusCode},
+    Router,
 };
 use base64::{engine::general_purpose, Engine as _};
 use nabla_runner::server::create_app;
-use serde_json;
+use serde_json::{json, Value};
 use std::fs;
+use std::io::Write;
 use std::path::Path;
+use std::sync::Mutex;
 use tempfile::TempDir;
 use tower::util::ServiceExt;
 use zip::write::FileOptions;
 use zip::ZipWriter;
 
-fn create_test_cargo_project(temp_dir: &Path) -> Result<()> {
-    // Create Cargo.toml
-    let cargo_toml = r#"[package]
-name = "test-firmware"
-version = "0.1.0"
-edition = "2021"
+const BOUNDARY: &str = "nabla-test-boundary";
 
-[[bin]]
-name = "firmware"
-path = "src/main.rs"
-"#;
-    fs::write(temp_dir.join("Cargo.toml"), cargo_toml)?;
+/// The app reads its settings from the environment when it's built, so every test here
+/// builds it under this lock and never sees another test's settings
+static ENV: Mutex<()> = Mutex::new(());
 
-    // Create src directory and main.rs
-    fs::create_dir_all(temp_dir.join("src"))?;
-    let main_rs = r#"fn main() {
-    println!("Hello, firmware world!");
+/// An app built with `vars` set, which are put back as they were afterwards
+fn app_with_env(vars: &[(&str, &str)]) -> Router {
+    let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let saved: Vec<_> = vars.iter().map(|(name, _)| (*name, std::env::var_os(name))).collect();
+    for (name, value) in vars {
+        std::env::set_var(name, value);
+    }
+    let app = create_app();
+    for (name, value) in saved {
+        match value {
+            Some(value) => std::env::set_var(name, value),
+            None => std::env::remove_var(name),
+        }
+    }
+    app
 }
-"#;
-    fs::write(temp_dir.join("src").join("main.rs"), main_rs)?;
 
-    Ok(())
+fn app() -> Router {
+    app_with_env(&[])
 }
 
 fn create_test_makefile_project(temp_dir: &Path) -> Result<()> {
@@ -65,89 +71,6 @@ int main() {
     Ok(())
 }
 
-fn create_test_cmake_project(temp_dir: &Path) -> Result<()> {
-    // Create CMakeLists.txt
-    let cmake_lists = r#"cmake_minimum_required(VERSION 3.10)
-project(TestFirmware)
-
-set(CMAKE_C_STANDARD 99)
-
-add_executable(firmware main.c)
-"#;
-    fs::write(temp_dir.join("CMakeLists.txt"), cmake_lists)?;
-
-    // Create main.c
-    let main_c = r#"#include <stdio.h>
-
-int main() {
-    printf("Hello, firmware world!\n");
-    return 0;
-}
-"#;
-    fs::write(temp_dir.join("main.c"), main_c)?;
-
-    Ok(())
-}
-
-fn create_test_platformio_project(temp_dir: &Path) -> Result<()> {
-    // Create platformio.ini
-    let platformio_ini = r#"[env:uno]
-platform = atmelavr
-board = uno
-framework = arduino
-"#;
-    fs::write(temp_dir.join("platformio.ini"), platformio_ini)?;
-
-    // Create src/main.cpp
-    fs::create_dir_all(temp_dir.join("src"))?;
-    let main_cpp = r#"#include <Arduino.h>
-
-void setup() {
-    Serial.begin(9600);
-}
-
-void loop() {
-    Serial.println("Hello, firmware world!");
-    delay(1000);
-}
-"#;
-    fs::write(temp_dir.join("src").join("main.cpp"), main_cpp)?;
-
-    Ok(())
-}
-
-fn create_test_zephyr_project(temp_dir: &Path) -> Result<()> {
-    // Create west.yml
-    let west_yml = r#"manifest:
-  projects:
-    - name: zephyr
-      url: https://github.com/zephyrproject-rtos/zephyr
-      revision: main
-"#;
-    fs::write(temp_dir.join("west.yml"), west_yml)?;
-
-    // Create CMakeLists.txt
-    let cmake_lists = r#"cmake_minimum_required(VERSION 3.20.0)
-find_package(Zephyr REQUIRED HINTS $ENV{ZEPHYR_BASE})
-project(test_firmware)
-
-target_sources(app PRIVATE src/main.c)
-"#;
-    fs::write(temp_dir.join("CMakeLists.txt"), cmake_lists)?;
-
-    // Create src/main.c
-    fs::create_dir_all(temp_dir.join("src"))?;
-    let main_c = r#"#include <zephyr/kernel.h>
-
-void main(void) {
-    printk("Hello, firmware world!\n");
-}
-"#;
-    fs::write(temp_dir.join("src").join("main.c"), main_c)?;
-
-    Ok(())
-}
-
 fn zip_directory(dir_path: &Path) -> Result<Vec<u8>> {
     let mut buffer = Vec::new();
     {
@@ -176,285 +99,596 @@ fn zip_directory(dir_path: &Path) -> Result<Vec<u8>> {
     Ok(buffer)
 }
 
-#[tokio::test]
-async fn test_cargo_project_detection_via_http() -> Result<()> {
-    let app = create_app();
-    let temp_dir = TempDir::new()?;
-    create_test_cargo_project(temp_dir.path())?;
+fn tar_gz_directory(dir_path: &Path) -> Result<Vec<u8>> {
+    let output = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg("-")
+        .arg("-C")
+        .arg(dir_path)
+        .arg(".")
+        .output()?;
+    assert!(output.status.success(), "tar failed: {}", String::from_utf8_lossy(&output.stderr));
+    Ok(output.stdout)
+}
 
-    let zip_data = zip_directory(temp_dir.path())?;
+fn metadata(job_id: &str) -> Value {
+    json!({
+        "job_id": job_id,
+        "owner": "test",
+        "repo": "firmware",
+        "installation_id": "123"
+    })
+}
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data))
-                .unwrap(),
+/// Build a multipart/form-data request with an optional metadata part and archive part
+fn multipart_request(metadata: Option<&Value>, archive: Option<&[u8]>) -> Request<Body> {
+    let mut body = Vec::new();
+    if let Some(metadata) = metadata {
+        write!(
+            body,
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"metadata\"\r\nContent-Type: application/json\r\n\r\n{metadata}\r\n"
         )
-        .await
         .unwrap();
-
-    // The build will fail because we don't have /workspace in tests, but we should get an error response
-    assert!(response.status() == StatusCode::INTERNAL_SERVER_ERROR);
-    
-    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
-    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
-        // Check that it at least tried to process the request
-        assert_eq!(json["status"], "error");
-        let error = json["error"].as_str().unwrap_or("");
-        // The error should be about workspace or build failure, not about invalid request
-        assert!(error.contains("build failed") || error.contains("workspace") || error.contains("No such file"));
     }
+    if let Some(archive) = archive {
+        write!(
+            body,
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"archive\"; filename=\"repo\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        )
+        .unwrap();
+        body.extend_from_slice(archive);
+        body.extend_from_slice(b"\r\n");
+    }
+    write!(body, "--{BOUNDARY}--\r\n").unwrap();
+
+    Request::builder()
+        .method("POST")
+        .uri("/build")
+        .header("content-type", format!("multipart/form-data; boundary={BOUNDARY}"))
+        .body(Body::from(body))
+        .unwrap()
+}
 
-    Ok(())
+async fn send(request: Request<Body>) -> (StatusCode, Value) {
+    let response = app().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
 }
 
 #[tokio::test]
-async fn test_makefile_project_detection_via_http() -> Result<()> {
-    let app = create_app();
+async fn test_makefile_project_zip_upload_builds() -> Result<()> {
     let temp_dir = TempDir::new()?;
     create_test_makefile_project(temp_dir.path())?;
-
     let zip_data = zip_directory(temp_dir.path())?;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let (status, json) = send(multipart_request(Some(&metadata("it-zip-upload")), Some(&zip_data))).await;
 
-    // The build will fail because we don't have /workspace in tests
-    assert!(response.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "completed", "{}", json);
+    // No sha for an upload, so the default name leaves it out
+    assert_eq!(json["artifact_filename"], "firmware-firmware-makefile");
+    assert_eq!(json["artifact_original_filename"], "firmware");
+    assert_eq!(json["build_system"], "Makefile");
+    let artifact = general_purpose::STANDARD.decode(json["artifact_data"].as_str().unwrap())?;
+    assert!(artifact.starts_with(b"\x7fELF"));
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_cmake_project_detection_via_http() -> Result<()> {
-    let app = create_app();
+async fn test_makefile_project_tar_gz_upload_in_subdirectory_builds() -> Result<()> {
     let temp_dir = TempDir::new()?;
-    create_test_cmake_project(temp_dir.path())?;
-
-    let zip_data = zip_directory(temp_dir.path())?;
+    // Packed one level too deep, as a tarball of a checkout directory usually is
+    let project = temp_dir.path().join("firmware-main");
+    fs::create_dir(&project)?;
+    create_test_makefile_project(&project)?;
+    let tar_data = tar_gz_directory(temp_dir.path())?;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let (status, json) = send(multipart_request(Some(&metadata("it-tar-upload")), Some(&tar_data))).await;
 
-    // The build will fail because we don't have /workspace in tests
-    assert!(response.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "completed", "{}", json);
+    assert_eq!(json["artifact_filename"], "firmware-firmware-makefile");
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_platformio_project_detection_via_http() -> Result<()> {
-    let app = create_app();
+async fn test_missing_parts_rejected() -> Result<()> {
     let temp_dir = TempDir::new()?;
-    create_test_platformio_project(temp_dir.path())?;
-
+    create_test_makefile_project(temp_dir.path())?;
     let zip_data = zip_directory(temp_dir.path())?;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let (status, json) = send(multipart_request(Some(&metadata("it-no-archive")), None)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json["message"].as_str().unwrap().contains("archive"), "{}", json);
 
-    // The build will fail because we don't have /workspace in tests
-    assert!(response.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    let (status, json) = send(multipart_request(None, Some(&zip_data))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json["message"].as_str().unwrap().contains("metadata"), "{}", json);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_zephyr_project_detection_via_http() -> Result<()> {
-    let app = create_app();
+async fn test_archive_url_with_upload_rejected() -> Result<()> {
     let temp_dir = TempDir::new()?;
-    create_test_zephyr_project(temp_dir.path())?;
-
+    create_test_makefile_project(temp_dir.path())?;
     let zip_data = zip_directory(temp_dir.path())?;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let mut meta = metadata("it-url-and-upload");
+    meta["archive_url"] = json!("https://example.invalid/repo.tar.gz");
 
-    // The build will fail because we don't have /workspace in tests
-    assert!(response.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    let (status, json) = send(multipart_request(Some(&meta), Some(&zip_data))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json["message"].as_str().unwrap().contains("archive_url"), "{}", json);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_invalid_base64_data_via_http() -> Result<()> {
-    let app = create_app();
+async fn test_unrecognized_archive_fails_build() -> Result<()> {
+    let (status, json) = send(multipart_request(Some(&metadata("it-not-archive")), Some(b"not an archive"))).await;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/base64")
-                .body(Body::from("invalid-base64-data!!!"))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "failed");
+    assert!(json["message"].as_str().unwrap().contains("Unsupported archive format"), "{}", json);
 
-    // Should fail with internal server error due to invalid base64
-    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
-    
-    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
-    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
-        assert_eq!(json["status"], "error");
-        let error = json["error"].as_str().unwrap_or("");
-        // The error should mention base64 decoding or build failure
-        assert!(error.contains("base64") || error.contains("decode") || error.contains("build failed"), 
-                "Unexpected error message: {}", error);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zip_path_traversal_rejected() -> Result<()> {
+    let mut buffer = Vec::new();
+    {
+        let mut zip = ZipWriter::new(std::io::Cursor::new(&mut buffer));
+        zip.start_file("../escaped.txt", FileOptions::default())?;
+        zip.write_all(b"outside")?;
+        zip.finish()?;
     }
 
+    let (status, json) = send(multipart_request(Some(&metadata("it-traversal")), Some(&buffer))).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "failed");
+    assert!(json["message"].as_str().unwrap().contains("escapes"), "{}", json);
+
     Ok(())
 }
 
 #[tokio::test]
-async fn test_unsupported_project_type_via_http() -> Result<()> {
-    let app = create_app();
+async fn test_failed_build_reports_detected_build_system() -> Result<()> {
     let temp_dir = TempDir::new()?;
-    
-    // Create a directory with just a README file (no build system)
-    fs::write(temp_dir.path().join("README.md"), "# Test Project\n\nThis is a test.")?;
+    fs::write(
+        temp_dir.path().join("CMakeLists.txt"),
+        "cmake_minimum_required(VERSION 3.10)\nproject(firmware C)\nadd_executable(firmware main.c)\n",
+    )?;
+    fs::write(temp_dir.path().join("main.c"), "int main(void) {\n    return undeclared_symbol;\n}\n")?;
+    let zip_data = zip_directory(temp_dir.path())?;
 
+    let (status, json) = send(multipart_request(Some(&metadata("it-cmake-broken")), Some(&zip_data))).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "failed", "{}", json);
+    assert_eq!(json["build_system"], "CMake", "{}", json);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_makefile_build_reports_diagnostics() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    fs::write(temp_dir.path().join("Makefile"), "firmware: main.c\n\tgcc -o firmware main.c\n")?;
+    fs::write(temp_dir.path().join("main.c"), "int main(void) {\n    return undeclared_symbol;\n}\n")?;
     let zip_data = zip_directory(temp_dir.path())?;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let (status, json) = send(multipart_request(Some(&metadata("it-make-broken")), Some(&zip_data))).await;
 
-    // Should fail with internal server error due to unsupported build system
-    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
-    
-    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
-    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
-        assert_eq!(json["status"], "error");
-        let error = json["error"].as_str().unwrap_or("");
-        // The error might be about workspace or unsupported build system
-        assert!(error.contains("Unsupported") || error.contains("undetected") || error.contains("workspace") || error.contains("build failed"),
-                "Unexpected error message: {}", error);
-    }
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "failed", "{}", json);
+    let first = &json["diagnostics"][0];
+    assert_eq!(first["file"], "main.c", "{}", json);
+    assert_eq!((first["line"].as_u64(), first["column"].as_u64()), (Some(2), Some(12)), "{}", json);
+    assert_eq!(first["severity"], "error");
+    assert!(first["message"].as_str().unwrap().contains("undeclared_symbol"), "{}", json);
+    assert!(json["error_count"].as_u64().is_some_and(|count| count >= 1), "{}", json);
+    assert!(json["warning_count"].is_u64(), "{}", json);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_base64_and_zip_content_types() -> Result<()> {
+async fn test_undetected_build_system_reported_as_null() -> Result<()> {
     let temp_dir = TempDir::new()?;
-    create_test_cargo_project(temp_dir.path())?;
+    fs::write(temp_dir.path().join("README.md"), "nothing to build\n")?;
     let zip_data = zip_directory(temp_dir.path())?;
 
-    // Test with ZIP content type
-    let app = create_app();
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data.clone()))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let (status, json) = send(multipart_request(Some(&metadata("it-undetected")), Some(&zip_data))).await;
 
-    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR); // Expected due to /workspace
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "failed");
+    assert!(json.get("build_system").is_some_and(Value::is_null), "{}", json);
 
-    // Test with BASE64 content type
-    let app = create_app();
-    let base64_data = general_purpose::STANDARD.encode(&zip_data);
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/base64")
-                .body(Body::from(base64_data))
-                .unwrap(),
-        )
-        .await
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dry_run_detects_without_building() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    // Any command the build spawns leaves a trace in the spy file
+    let spy = TempDir::new()?;
+    let spy_log = spy.path().join("spawned");
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        format!("firmware:\n\techo make >> {}\n\techo built > firmware\n", spy_log.display()),
+    )?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+
+    let mut metadata = metadata("dry-run-test");
+    metadata["dry_run"] = json!(true);
+    metadata["build_config"] = json!({"warning_excludes": ["vendor/**"]});
+    let (status, json) = send(multipart_request(Some(&metadata), Some(&archive))).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "completed", "{}", json);
+    assert!(json["message"].as_str().unwrap().contains("Dry run"), "{}", json);
+    assert_eq!(json["build_system"], "Makefile");
+    assert!(json.get("artifact_data").is_none() && json.get("provenance").is_none(), "{}", json);
+
+    let dry_run = &json["dry_run"];
+    assert_eq!(dry_run["build_system"], "Makefile");
+    assert_eq!(dry_run["resolved_config"]["warning_excludes"][0], "vendor/**");
+    let make = dry_run["capabilities"].as_array().unwrap().iter().find(|c| c["name"] == "make").unwrap();
+    assert_eq!(make["ok"], true, "{}", json);
+
+    assert!(!spy_log.exists(), "dry run spawned a build command");
+    let workspace = dry_run["repo_dir"].as_str().unwrap();
+    assert!(!Path::new(workspace).exists(), "dry-run workspace {} was kept", workspace);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recurring_failures_fingerprinted() -> Result<()> {
+    let app = app_with_env(&[("NABLA_ADMIN_TOKEN", "failures-token")]);
+    let temp_dir = TempDir::new()?;
+    // The error names the job's own workspace, which differs every time
+    fs::write(temp_dir.path().join("Makefile"), "firmware:\n\t@echo \"cc1: fatal error: $$PWD/main.c: No such file\" >&2; false\n")?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+
+    let mut failures = Vec::new();
+    for job_id in ["recurring-1", "recurring-2"] {
+        let response = app.clone().oneshot(multipart_request(Some(&metadata(job_id)), Some(&archive))).await?;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        let json: Value = serde_json::from_slice(&body)?;
+        assert_eq!(json["status"], "failed", "{}", json);
+        failures.push((json["failure_fingerprint"].as_str().unwrap().to_string(), json["failure_occurrences"].as_u64()));
+    }
+    assert_eq!(failures[0].0, failures[1].0);
+    assert_eq!((failures[0].1, failures[1].1), (Some(1), Some(2)));
+
+    let get_as = |uri: &str, token: Option<&str>| {
+        let mut request = Request::builder().uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {token}"));
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        }
+    };
+    for token in [None, Some("wrong-token")] {
+        let (status, json) = get_as("/admin/failures?repo=test/firmware", token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(json.get("failures").is_none(), "{}", json);
+    }
+    let get = |uri: &str| get_as(uri, Some("failures-token"));
+    let (status, summary) = get("/admin/failures?repo=test/firmware").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["total_failures"], 2, "{}", summary);
+    assert_eq!(summary["failures"][0]["fingerprint"], failures[0].0.as_str());
+    assert_eq!(summary["failures"][0]["occurrences"], 2);
+    assert!(summary["failures"][0]["example"].as_str().unwrap().contains("cc1: fatal error: <path>"), "{}", summary);
+
+    let (status, json) = get("/admin/failures").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["message"], "invalid request: repo is required, as owner/repo");
+    Ok(())
+}
+
+async fn job_events(app: &axum::Router, job_id: &str) -> Value {
+    let request = Request::builder()
+        .uri(format!("/jobs/{}/events", job_id))
+        .body(Body::empty())
         .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
 
-    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR); // Expected due to /workspace
+#[tokio::test]
+async fn test_job_audit_trail_follows_the_pipeline() -> Result<()> {
+    let app = app();
+    let temp_dir = TempDir::new()?;
+    fs::write(temp_dir.path().join("Makefile"), "firmware:\n\techo built > firmware\n")?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+
+    let response = app.clone().oneshot(multipart_request(Some(&metadata("audit-ok")), Some(&archive))).await?;
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    let json: Value = serde_json::from_slice(&body)?;
+    assert_eq!(json["status"], "completed", "{}", json);
+    let (build_ms, final_attempt_ms) = (json["build_ms"].as_u64().unwrap(), json["final_attempt_ms"].as_u64().unwrap());
+    assert!(build_ms >= final_attempt_ms, "{}", json);
+    assert_eq!(json["attempts"][0]["duration_ms"], final_attempt_ms);
+
+    let request = Request::builder().uri(format!("/jobs/{}", json["job_id"].as_str().unwrap())).body(Body::empty())?;
+    let body = axum::body::to_bytes(app.clone().oneshot(request).await?.into_body(), usize::MAX).await?;
+    let job: Value = serde_json::from_slice(&body)?;
+    assert_eq!((job["build_ms"].as_u64(), job["final_attempt_ms"].as_u64()), (Some(build_ms), Some(final_attempt_ms)), "{}", job);
+
+    let audit = job_events(&app, json["job_id"].as_str().unwrap()).await;
+    let events = audit["events"].as_array().unwrap();
+    let kinds: Vec<&str> = events.iter().map(|event| event["kind"].as_str().unwrap()).collect();
+    assert_eq!(
+        kinds,
+        ["submitted", "started", "workspace", "fetch_started", "fetch_finished", "detected", "attempt", "completed"]
+    );
+    assert!(events[0]["detail"].as_str().unwrap().contains("Installation 123 of customer"), "{}", audit);
+    assert!(events[3]["detail"].as_str().unwrap().starts_with("Unpacking uploaded archive ("), "{}", audit);
+    assert!(events[5]["detail"].as_str().unwrap().starts_with("Makefile in "), "{}", audit);
+    assert!(events.windows(2).all(|pair| pair[0]["timestamp_ms"].as_u64() <= pair[1]["timestamp_ms"].as_u64()));
+    assert_eq!(audit["status"], "Completed");
+    let attempt = events[6]["detail"].as_str().unwrap();
+    assert!(attempt.starts_with("Attempt 1 of 1 succeeded in ") && attempt.ends_with("ms in total)"), "{}", attempt);
+
+    // A failed job's error is recorded, with secrets redacted
+    fs::write(temp_dir.path().join("Makefile"), "firmware:\n\t@echo \"token $$API_TOKEN rejected\" >&2; false\n")?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+    let mut failing = metadata("audit-failed");
+    failing["build_config"] = json!({"secret_env": {"API_TOKEN": "tok-5ecret"}});
+    let response = app.clone().oneshot(multipart_request(Some(&failing), Some(&archive))).await?;
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    let json: Value = serde_json::from_slice(&body)?;
+    assert_eq!(json["status"], "failed", "{}", json);
+
+    let audit = job_events(&app, json["job_id"].as_str().unwrap()).await;
+    let last = audit["events"].as_array().unwrap().last().unwrap();
+    assert_eq!(last["kind"], "failed");
+    assert!(last["detail"].as_str().unwrap().contains("token *** rejected"), "{}", audit);
+    assert!(!audit.to_string().contains("tok-5ecret"));
+
+    // Errors the runner words itself, here naming the Makefile's goal, are redacted everywhere too
+    fs::write(temp_dir.path().join("Makefile"), "tok-5ecret.bin:\n\t@true\n")?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+    failing["job_id"] = json!("audit-failed-goal");
+    let response = app.clone().oneshot(multipart_request(Some(&failing), Some(&archive))).await?;
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    let json: Value = serde_json::from_slice(&body)?;
+    assert!(json["message"].as_str().unwrap().contains("did not produce its default target `***.bin`"), "{}", json);
+    assert!(!json.to_string().contains("tok-5ecret"), "{}", json);
+    let job_id = json["job_id"].as_str().unwrap();
+    let request = Request::builder().uri(format!("/jobs/{}", job_id)).body(Body::empty())?;
+    let body = axum::body::to_bytes(app.clone().oneshot(request).await?.into_body(), usize::MAX).await?;
+    assert!(!String::from_utf8_lossy(&body).contains("tok-5ecret"), "{}", String::from_utf8_lossy(&body));
+    assert!(!job_events(&app, job_id).await.to_string().contains("tok-5ecret"));
+
+    // Jobs the runner never ran aren't found
+    let request = Request::builder()
+        .uri(format!("/jobs/{}/events", uuid::Uuid::new_v4()))
+        .body(Body::empty())?;
+    assert_eq!(app.clone().oneshot(request).await?.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}
 
+#[tokio::test]
+async fn test_oldest_finished_job_evicted_past_the_cap() -> Result<()> {
+    let app = app_with_env(&[("NABLA_MAX_TRACKED_JOBS", "2")]);
+    let temp_dir = TempDir::new()?;
+    fs::write(temp_dir.path().join("Makefile"), "firmware:\n\techo built > firmware\n")?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+
+    let mut jobs = Vec::new();
+    for name in ["evict-1", "evict-2", "evict-3"] {
+        let response = app.clone().oneshot(multipart_request(Some(&metadata(name)), Some(&archive))).await?;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        let json: Value = serde_json::from_slice(&body)?;
+        assert_eq!(json["status"], "completed", "{}", json);
+        let log = json["build_output"].as_str().unwrap();
+        let workspace = log.lines().find_map(|line| line.strip_prefix("Workspace ready: ")).unwrap().to_string();
+        jobs.push((json["job_id"].as_str().unwrap().to_string(), workspace));
+    }
+
+    let status = |job_id: &str| {
+        let request = Request::builder().uri(format!("/jobs/{}", job_id)).body(Body::empty()).unwrap();
+        let app = app.clone();
+        async move { app.oneshot(request).await.unwrap().status() }
+    };
+    assert_eq!(status(&jobs[0].0).await, StatusCode::NOT_FOUND);
+    assert_eq!(status(&jobs[1].0).await, StatusCode::OK);
+    assert_eq!(status(&jobs[2].0).await, StatusCode::OK);
+
+    // The evicted job's workspace is removed in the background
+    for _ in 0..50 {
+        if !Path::new(&jobs[0].1).exists() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(!Path::new(&jobs[0].1).exists(), "workspace {} of the evicted job was kept", jobs[0].1);
+    assert!(Path::new(&jobs[2].1).exists());
     Ok(())
 }
 
 #[tokio::test]
-async fn test_multiple_build_systems_priority() -> Result<()> {
-    let app = create_app();
+async fn test_running_job_logs_tail_the_output_so_far() -> Result<()> {
+    let app = app();
     let temp_dir = TempDir::new()?;
-    
-    // Create a project with multiple build systems - Cargo should take priority
-    create_test_cargo_project(temp_dir.path())?;
-    
-    // Also add a Makefile
-    let makefile = r#"all:
-	echo "This should not be used"
-"#;
-    fs::write(temp_dir.path().join("Makefile"), makefile)?;
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        "app.bin:\n\t@echo compiling main.c; echo compiling hal.c; sleep 2; echo linking; echo built > app.bin\n",
+    )?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+
+    // Workspaces are named by the client's job_id; a fresh one keeps a previous run's app.bin out
+    let client_job_id = format!("live-logs-{}", uuid::Uuid::new_v4());
+    let build = tokio::spawn(app.clone().oneshot(multipart_request(Some(&metadata(&client_job_id)), Some(&archive))));
+
+    // Poll by the client's job_id, as a synchronous client that has no runner job id yet would
+    let mut lines = Value::Null;
+    for _ in 0..500 {
+        let request = Request::builder().uri(format!("/jobs/{}/logs?tail=10", client_job_id)).body(Body::empty())?;
+        let response = app.clone().oneshot(request).await?;
+        if response.status() == StatusCode::OK {
+            let json: Value = serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await?)?;
+            if json["lines"].as_array().is_some_and(|lines| lines.len() >= 2) {
+                assert_eq!(json["status"], "Running", "{}", json);
+                lines = json["lines"].clone();
+                break;
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(lines, json!(["compiling main.c", "compiling hal.c"]));
+
+    let response = build.await??;
+    let json: Value = serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await?)?;
+    assert_eq!(json["status"], "completed", "{}", json);
+    let request = Request::builder()
+        .uri(format!("/jobs/{}/logs?tail=1", json["job_id"].as_str().unwrap()))
+        .body(Body::empty())?;
+    let json: Value = serde_json::from_slice(&axum::body::to_bytes(app.oneshot(request).await?.into_body(), usize::MAX).await?)?;
+    assert_eq!(json["lines"], json!(["linking"]));
+    Ok(())
+}
 
-    let zip_data = zip_directory(temp_dir.path())?;
+#[tokio::test]
+async fn test_running_job_reports_progress_from_its_output() -> Result<()> {
+    let app = app();
+    let temp_dir = TempDir::new()?;
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        "app.bin:\n\t@echo '[ 20%] Building C object main.o'; echo 'main.c:3:1: warning: unused [-Wunused]'; echo '[ 60%] Linking app'; sleep 2; echo built > app.bin\n",
+    )?;
+    let archive = tar_gz_directory(temp_dir.path())?;
+    let client_job_id = format!("progress-{}", uuid::Uuid::new_v4());
+    let build = tokio::spawn(app.clone().oneshot(multipart_request(Some(&metadata(&client_job_id)), Some(&archive))));
+
+    let get = |uri: String| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null))
+        }
+    };
+    let mut job = Value::Null;
+    for _ in 0..500 {
+        let (status, logs) = get(format!("/jobs/{}/logs", client_job_id)).await;
+        if status == StatusCode::OK {
+            (_, job) = get(format!("/jobs/{}", logs["job_id"].as_str().unwrap())).await;
+            if job["progress"] == json!(60.0) {
+                break;
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["progress"], json!(60.0), "{}", job);
+    assert_eq!(job["status"], "Running", "{}", job);
+
+    let response = build.await??;
+    let json: Value = serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await?)?;
+    assert_eq!(json["status"], "completed", "{}", json);
+    let (_, job) = get(format!("/jobs/{}", json["job_id"].as_str().unwrap())).await;
+    assert_eq!(job["progress"], json!(100.0), "{}", job);
+    Ok(())
+}
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/build?owner=test&repo=test&head_sha=abc123def&installation_id=123&upload_url=http://httpbin.org/post")
-                .header("content-type", "application/zip")
-                .body(Body::from(zip_data))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+#[tokio::test]
+async fn test_full_log_pages_reassemble_the_kept_log() -> Result<()> {
+    let app = app_with_env(&[("NABLA_KEEP_BUILD_LOGS", "1")]);
+    let temp_dir = TempDir::new()?;
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        "app.bin:\n\t@echo key=$$SIGNING_KEY; seq 1 2500 | sed 's/^/line /'; echo built > app.bin\n",
+    )?;
+    let mut request = metadata(&format!("full-log-{}", uuid::Uuid::new_v4()));
+    request["build_config"] = json!({"secret_env": {"SIGNING_KEY": "k3y-5ecret"}});
+    let response = app.clone().oneshot(multipart_request(Some(&request), Some(&tar_gz_directory(temp_dir.path())?))).await?;
+    let json: Value = serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await?)?;
+    assert_eq!(json["status"], "completed", "{}", json);
+    let job_id = json["job_id"].as_str().unwrap().to_string();
+
+    let page = |query: String| {
+        let request = Request::builder().uri(format!("/jobs/{}/logs?full=true&{}", job_id, query)).body(Body::empty()).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let range = response.headers().get("content-range").map(|range| range.to_str().unwrap().to_string());
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, range, String::from_utf8(body.to_vec()).unwrap())
+        }
+    };
+
+    let (status, _, whole) = page("limit=10000".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    let total = whole.lines().count();
+    assert!(whole.contains("key=***\nline 1\n"), "{}", whole);
+    assert!(whole.contains("line 2500\n") && !whole.contains("k3y-5ecret"));
+
+    let mut reassembled = String::new();
+    let mut offset = 0;
+    while offset < total {
+        let (status, range, text) = page(format!("offset={}&limit=700", offset)).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        let last = (offset + 700).min(total) - 1;
+        assert_eq!(range.unwrap(), format!("lines {}-{}/{}", offset, last, total));
+        assert_eq!(text.lines().count(), last + 1 - offset);
+        reassembled.push_str(&text);
+        offset = last + 1;
+    }
+    assert_eq!(reassembled, whole);
 
-    // The build will fail due to /workspace, but it should detect Cargo first
-    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let (status, range, _) = page(format!("offset={}", total)).await;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(range.unwrap(), format!("lines */{}", total));
+    Ok(())
+}
 
+#[tokio::test]
+async fn test_files_left_in_nabla_out_dir_are_the_artifacts() -> Result<()> {
+    // Workspaces are named by the client's job_id; a fresh one keeps a previous run's out/ away
+    let job_id = format!("out-dir-{}", uuid::Uuid::new_v4());
+    let temp_dir = TempDir::new()?;
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        "all:\n\tprintf '%s %s %s' \"$(NABLA_BUILD_SYSTEM)\" \"$(NABLA_JOB_ID)\" \"$(notdir $(NABLA_WORKSPACE))\" > \"$(NABLA_OUT_DIR)/blinky.hex\"\n\
+         \ttest \"$(NABLA_REPO_DIR)\" -ef .\n",
+    )?;
+
+    // Nothing the runner would find by itself, so the build succeeds with what it left in out/
+    let (_, json) = send(multipart_request(Some(&metadata(&job_id)), Some(&tar_gz_directory(temp_dir.path())?))).await;
+    assert_eq!(json["status"], "completed", "{}", json);
+    assert_eq!(json["artifact_original_filename"], "blinky.hex");
+    let artifact = general_purpose::STANDARD.decode(json["artifact_data"].as_str().unwrap())?;
+    assert_eq!(String::from_utf8(artifact)?, format!("makefile {} job-{}", job_id, job_id));
+
+    // Ahead of the artifact the runner finds
+    fs::write(temp_dir.path().join("firmware.c"), "int main(void) { return 0; }\n")?;
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        "all: firmware\n\tcp firmware \"$(NABLA_OUT_DIR)/firmware.elf\"\nfirmware: firmware.c\n\tcc -o firmware firmware.c\n",
+    )?;
+    let (_, json) = send(multipart_request(Some(&metadata(&job_id)), Some(&tar_gz_directory(temp_dir.path())?))).await;
+    assert_eq!(json["status"], "completed", "{}", json);
+    assert_eq!(json["artifact_original_filename"], "firmware.elf");
+    let artifacts: Vec<&str> = json["artifacts"].as_array().unwrap().iter().map(|a| a["metadata"]["original_filename"].as_str().unwrap()).collect();
+    assert_eq!(artifacts, ["firmware.elf", "firmware"]);
     Ok(())
-}
\ No newline at end of file
+}
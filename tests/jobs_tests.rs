@@ -0,0 +1,97 @@
+use nabla_runner::jobs::{BuildJob, JobManager, MAX_FINGERPRINTS_PER_REPO};
+use std::path::PathBuf;
+
+fn job(workspace: &str) -> BuildJob {
+    let mut job = BuildJob::new(
+        "https://example.com/fw.tar.gz".to_string(),
+        "acme".to_string(),
+        "fw".to_string(),
+        "123".to_string(),
+        String::new(),
+        None,
+    );
+    job.files.push(PathBuf::from(workspace));
+    job
+}
+
+#[test]
+fn test_running_jobs_are_never_evicted() {
+    let mut jobs = JobManager::new(2);
+    let running = job("/ws/job-a");
+    let running_id = running.id;
+    assert!(jobs.insert(running).is_empty());
+    jobs.update(running_id, |job| job.start());
+
+    let queued = job("/ws/job-b");
+    let queued_id = queued.id;
+    assert!(jobs.insert(queued).is_empty());
+
+    // Nothing has finished, so the cap is exceeded rather than dropping live jobs
+    assert!(jobs.insert(job("/ws/job-c")).is_empty());
+    assert_eq!(jobs.len(), 3);
+
+    // The first job to finish goes first, not the first one created
+    jobs.update(queued_id, |job| job.fail("boom".to_string()));
+    jobs.update(running_id, |job| job.complete(String::new(), None));
+    let evicted = jobs.insert(job("/ws/job-d"));
+    let evicted: Vec<_> = evicted.iter().map(|job| job.id).collect();
+    assert_eq!(evicted, [queued_id, running_id]);
+    assert_eq!(jobs.len(), 2);
+    assert!(jobs.get(queued_id).is_none() && jobs.get(running_id).is_none());
+}
+
+#[test]
+fn test_reused_workspace_stays_in_use() {
+    let mut jobs = JobManager::new(1);
+    let first = job("/ws/job-nightly");
+    let first_id = first.id;
+    jobs.insert(first);
+    jobs.update(first_id, |job| job.complete(String::new(), None));
+
+    // The client reused its job_id, and with it the workspace
+    let evicted = jobs.insert(job("/ws/job-nightly"));
+    assert_eq!(evicted.len(), 1);
+    assert!(jobs.uses_path(&evicted[0].files[0]));
+    assert!(!jobs.uses_path(&PathBuf::from("/ws/job-other")));
+}
+
+#[test]
+fn test_partially_completed_job_is_finished() {
+    let mut jobs = JobManager::new(1);
+    let partial = job("/ws/job-multi-env");
+    let partial_id = partial.id;
+    jobs.insert(partial);
+    jobs.update(partial_id, |job| job.complete_partially(String::new(), Some("firmware.bin".to_string())));
+
+    assert!(jobs.get(partial_id).unwrap().is_finished());
+    assert_eq!(jobs.insert(job("/ws/job-next")).len(), 1);
+}
+
+#[test]
+fn test_failures_counted_per_repo_and_fingerprint() {
+    let mut jobs = JobManager::new(10);
+    let first = jobs.record_failure("acme/blinky", "Failed to install platform espressif32@6.1.11 in /tmp/job-1");
+    let again = jobs.record_failure("acme/blinky", "Failed to install platform espressif32@6.3.2 in /tmp/job-2");
+    jobs.record_failure("acme/blinky", "cmake: command not found");
+    jobs.record_failure("acme/other", "cmake: command not found");
+
+    assert_eq!(first.occurrences, 1);
+    assert_eq!((again.fingerprint.as_str(), again.occurrences), (first.fingerprint.as_str(), 2));
+    assert!(again.first_seen <= again.last_seen);
+    assert_eq!(again.example, "Failed to install platform espressif32@<ver> in <path>");
+
+    let failures = jobs.failures("acme/blinky");
+    let counts: Vec<u64> = failures.iter().map(|record| record.occurrences).collect();
+    assert_eq!(counts, [2, 1]);
+    assert_eq!(failures[0].fingerprint, first.fingerprint);
+    assert_eq!(jobs.failures("acme/other").len(), 1);
+    assert!(jobs.failures("acme/unknown").is_empty());
+
+    // Distinct errors beyond the cap replace the least recently seen
+    for i in 0..MAX_FINGERPRINTS_PER_REPO {
+        let name: String = [b'a' + (i / 26) as u8, b'a' + (i % 26) as u8].iter().map(|&b| b as char).collect();
+        jobs.record_failure("acme/many", &format!("undefined reference to `{}'", name));
+    }
+    jobs.record_failure("acme/many", "one more error");
+    assert_eq!(jobs.failures("acme/many").len(), MAX_FINGERPRINTS_PER_REPO);
+}
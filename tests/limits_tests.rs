@@ -0,0 +1,101 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::execution::execute_build_with_config;
+use nabla_runner::limits::LimitsConfig;
+use std::fs;
+use tempfile::TempDir;
+
+const LIMITS_TOML: &str = r#"
+[default]
+timeout_secs = 1800
+max_log_bytes = 524288
+
+[build_system.Makefile]
+timeout_secs = 120
+
+[build_system.ZephyrWest]
+timeout_secs = 3600
+parallelism = 8
+
+[max]
+timeout_secs = 3600
+max_artifact_bytes = 67108864
+parallelism = 4
+"#;
+
+#[test]
+fn test_request_then_system_then_default_capped_by_max() {
+    let limits = LimitsConfig::parse("limits.toml", LIMITS_TOML).unwrap();
+    let request = |timeout_secs: Option<u64>| BuildConfig {
+        timeout_secs,
+        ..BuildConfig::default()
+    };
+
+    // The build system's default over the file's
+    let makefile = limits.resolve(BuildSystem::Makefile, &request(None));
+    assert_eq!(makefile.timeout_secs, 120);
+    assert_eq!(makefile.max_log_bytes, 524288);
+    assert_eq!(limits.resolve(BuildSystem::CMake, &request(None)).timeout_secs, 1800);
+
+    // The request over both, but never past the maximum
+    assert_eq!(limits.resolve(BuildSystem::Makefile, &request(Some(300))).timeout_secs, 300);
+    assert_eq!(limits.resolve(BuildSystem::Makefile, &request(Some(86400))).timeout_secs, 3600);
+
+    // Maxima cap the system's defaults too, and stand in for limits nothing else sets
+    let zephyr = limits.resolve(BuildSystem::ZephyrWest, &request(None));
+    assert_eq!(zephyr.parallelism, Some(4));
+    assert_eq!(zephyr.max_artifact_bytes, Some(64 * 1024 * 1024));
+}
+
+#[test]
+fn test_without_limits_file_runner_defaults_apply() {
+    let limits = LimitsConfig::default();
+
+    let resolved = limits.resolve(BuildSystem::Makefile, &BuildConfig::default());
+    assert_eq!(resolved.max_artifact_bytes, None);
+    assert_eq!(resolved.parallelism, None);
+    assert!(limits.resolve(BuildSystem::Buildroot, &BuildConfig::default()).timeout_secs > resolved.timeout_secs);
+}
+
+#[test]
+fn test_invalid_limits_file_names_the_key() {
+    let error = |name: &str, contents: &str| format!("{:#}", LimitsConfig::parse(name, contents).unwrap_err());
+
+    let unknown_field = error("limits.toml", "[build_system.Makefile]\ntimeout = 300\n");
+    assert!(unknown_field.contains("unknown field `timeout`"), "{}", unknown_field);
+
+    let unknown_system = error("limits.toml", "[build_system.Zephyr]\ntimeout_secs = 300\n");
+    assert!(unknown_system.contains("build_system.Zephyr is not a build system"), "{}", unknown_system);
+
+    let zero = error("limits.json", r#"{"max": {"parallelism": 0}}"#);
+    assert!(zero.contains("max.parallelism must be greater than zero"), "{}", zero);
+
+    let wrong_type = error("limits.json", r#"{"default": {"timeout_secs": "1h"}}"#);
+    assert!(wrong_type.contains("invalid type"), "{}", wrong_type);
+}
+
+#[tokio::test]
+async fn test_build_runs_under_limits_file() {
+    let dir = TempDir::new().unwrap();
+    let limits_file = dir.path().join("limits.json");
+    fs::write(
+        &limits_file,
+        r#"{"build_system": {"Makefile": {"timeout_secs": 30, "max_artifact_bytes": 4, "parallelism": 2}}}"#,
+    )
+    .unwrap();
+    // The only test in this binary that reads the runner's own limits
+    std::env::set_var("NABLA_LIMITS_FILE", &limits_file);
+
+    let repo = TempDir::new().unwrap();
+    fs::write(
+        repo.path().join("Makefile"),
+        "firmware:\n\techo \"$$MAKEFLAGS\" > makeflags && printf 0123456789 > firmware\n",
+    )
+    .unwrap();
+    let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default()).await.unwrap();
+
+    assert!(!result.success);
+    assert!(result.error_output.unwrap().contains("is 10 bytes, over the 4 byte limit for Makefile builds"));
+    let limits = result.provenance.limits.unwrap();
+    assert_eq!((limits.timeout_secs, limits.max_artifact_bytes, limits.parallelism), (30, Some(4), Some(2)));
+    assert!(fs::read_to_string(repo.path().join("makeflags")).unwrap().contains("j2"));
+}
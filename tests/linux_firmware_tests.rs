@@ -0,0 +1,127 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::detection;
+use nabla_runner::execution::{buildroot_defconfig, buildroot_make_invocations, execute_build};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn test_detect_buildroot_before_makefile() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("Makefile"), "all:\n").unwrap();
+    fs::create_dir(temp_dir.path().join("configs")).unwrap();
+    fs::write(temp_dir.path().join("configs/raspberrypi4_defconfig"), "BR2_aarch64=y\n").unwrap();
+    fs::write(temp_dir.path().join("Config.in"), "config BR2_HAVE_DOT_CONFIG\n\tbool\n\tdefault y\n").unwrap();
+
+    let detected = detection::detect_build_system(temp_dir.path()).await;
+    assert_eq!(detected, Some(BuildSystem::Buildroot));
+
+    let external = TempDir::new().unwrap();
+    fs::write(external.path().join("Config.in"), "").unwrap();
+    fs::write(external.path().join("external.desc"), "name: ACME\n").unwrap();
+
+    let detected = detection::detect_build_system(external.path()).await;
+    assert_eq!(detected, Some(BuildSystem::Buildroot));
+}
+
+#[tokio::test]
+async fn test_uboot_defconfigs_are_not_buildroot() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("Makefile"), "all:\n").unwrap();
+    fs::write(temp_dir.path().join("Kconfig"), "mainmenu \"U-Boot Configuration\"\n").unwrap();
+    fs::create_dir(temp_dir.path().join("configs")).unwrap();
+    fs::write(temp_dir.path().join("configs/rpi_4_defconfig"), "CONFIG_ARM=y\n").unwrap();
+    fs::create_dir(temp_dir.path().join("board")).unwrap();
+    assert_eq!(detection::detect_build_system(temp_dir.path()).await, Some(BuildSystem::Makefile));
+
+    // A Config.in that isn't Buildroot's doesn't make it one either
+    fs::write(temp_dir.path().join("Config.in"), "menu \"Options\"\nendmenu\n").unwrap();
+    assert_eq!(detection::detect_build_system(temp_dir.path()).await, Some(BuildSystem::Makefile));
+
+    // ...unless it sits next to Buildroot's package/ tree
+    fs::create_dir(temp_dir.path().join("package")).unwrap();
+    assert_eq!(detection::detect_build_system(temp_dir.path()).await, Some(BuildSystem::Buildroot));
+}
+
+#[tokio::test]
+async fn test_detect_yocto_from_conf_or_recipes() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("conf")).unwrap();
+    fs::write(temp_dir.path().join("conf/bblayers.conf"), "BBLAYERS ?= \"\"\n").unwrap();
+    assert_eq!(detection::detect_build_system(temp_dir.path()).await, Some(BuildSystem::Yocto));
+
+    let layer = TempDir::new().unwrap();
+    let recipe_dir = layer.path().join("recipes-app/blinky");
+    fs::create_dir_all(&recipe_dir).unwrap();
+    fs::write(recipe_dir.join("blinky_1.0.bb"), "LICENSE = \"MIT\"\n").unwrap();
+    fs::write(layer.path().join("Makefile"), "all:\n").unwrap();
+    // Without a layer.conf the recipe is just packaging for the Makefile build
+    assert_eq!(detection::detect_build_system(layer.path()).await, Some(BuildSystem::Makefile));
+    fs::create_dir(layer.path().join("conf")).unwrap();
+    fs::write(layer.path().join("conf/layer.conf"), "BBFILE_COLLECTIONS += \"blinky\"\n").unwrap();
+    assert_eq!(detection::detect_build_system(layer.path()).await, Some(BuildSystem::Yocto));
+
+    let result = execute_build(layer.path(), BuildSystem::Yocto).await.unwrap();
+    assert!(!result.success);
+    assert!(result.error_output.unwrap().contains("bitbake"));
+}
+
+#[tokio::test]
+async fn test_packaging_recipe_in_a_firmware_repo_is_not_yocto() {
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("CMakeLists.txt"), "project(firmware C)\n").unwrap();
+    let recipes = repo.path().join("meta-foo/recipes-x/foo");
+    fs::create_dir_all(&recipes).unwrap();
+    fs::write(recipes.join("foo.bb"), "SRC_URI = \"git://example.com/foo.git\"\n").unwrap();
+    assert_eq!(detection::detect_build_system(repo.path()).await, Some(BuildSystem::CMake));
+
+    // The same recipe in a layer nested in the repository is a Yocto build
+    fs::create_dir(repo.path().join("meta-foo/conf")).unwrap();
+    fs::write(repo.path().join("meta-foo/conf/layer.conf"), "BBFILE_COLLECTIONS += \"foo\"\n").unwrap();
+    assert_eq!(detection::detect_build_system(repo.path()).await, Some(BuildSystem::Yocto));
+    let report = detection::analyze(repo.path()).await.unwrap();
+    assert_eq!(report.markers, ["*/conf/layer.conf"]);
+}
+
+#[tokio::test]
+async fn test_buildroot_defconfig_selection() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("configs")).unwrap();
+    fs::write(temp_dir.path().join("configs/board_a_defconfig"), "").unwrap();
+
+    let config = BuildConfig::default();
+    assert_eq!(buildroot_defconfig(temp_dir.path(), &config).await.unwrap(), "board_a_defconfig");
+
+    fs::write(temp_dir.path().join("configs/board_b_defconfig"), "").unwrap();
+    let err = buildroot_defconfig(temp_dir.path(), &config).await.unwrap_err();
+    assert!(err.to_string().contains("board_a_defconfig, board_b_defconfig"));
+
+    let config = BuildConfig {
+        defconfig: Some("board_b_defconfig".to_string()),
+        ..BuildConfig::default()
+    };
+    assert_eq!(buildroot_defconfig(temp_dir.path(), &config).await.unwrap(), "board_b_defconfig");
+
+    let config = BuildConfig {
+        defconfig: Some("-f/etc/passwd".to_string()),
+        ..BuildConfig::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_buildroot_make_invocations() {
+    let project = Path::new("/work/repo");
+
+    let in_tree = buildroot_make_invocations(project, "qemu_x86_64_defconfig", None);
+    assert_eq!(in_tree, vec![vec!["qemu_x86_64_defconfig".to_string()], vec![]]);
+
+    let external = buildroot_make_invocations(project, "acme_defconfig", Some(Path::new("/opt/buildroot")));
+    assert_eq!(
+        external,
+        vec![
+            vec!["-C", "/opt/buildroot", "BR2_EXTERNAL=/work/repo", "O=/work/repo/output", "acme_defconfig"],
+            vec!["-C", "/opt/buildroot", "BR2_EXTERNAL=/work/repo", "O=/work/repo/output"],
+        ]
+    );
+}
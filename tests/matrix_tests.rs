@@ -0,0 +1,135 @@
+use nabla_runner::core::{BuildConfig, BuildSystem, MatrixEntry};
+use nabla_runner::execution::execute_build_with_config;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn entry(name: &str) -> MatrixEntry {
+    MatrixEntry { name: name.to_string(), ..MatrixEntry::default() }
+}
+
+fn board(name: &str, board: &str) -> MatrixEntry {
+    MatrixEntry { board: Some(board.to_string()), ..entry(name) }
+}
+
+/// A config whose commands find the stub tools in `tools` first
+fn with_tools(tools: &Path, matrix: Vec<MatrixEntry>) -> BuildConfig {
+    let mut config = BuildConfig { matrix, max_parallel_envs: Some(2), ..BuildConfig::default() };
+    let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    config
+}
+
+#[test]
+fn test_matrix_entry_names_must_be_unique_and_path_safe() {
+    let config = |matrix: Vec<MatrixEntry>| BuildConfig { matrix, ..BuildConfig::default() };
+    assert!(config(vec![board("f401", "nucleo_f401re"), board("h743", "nucleo_h743zi")]).validate().is_ok());
+
+    let error = config(vec![entry("a"), entry("b"), entry("a")]).validate().unwrap_err().to_string();
+    assert_eq!(error, "Invalid matrix - entry name 'a' is used more than once");
+    for name in ["", "../up", "a/b", ".hidden", "two words"] {
+        let error = config(vec![entry(name)]).validate().unwrap_err().to_string();
+        assert!(error.starts_with(&format!("Invalid matrix entry name '{}'", name)), "{}", error);
+    }
+
+    let defines = MatrixEntry { cmake_defines: BTreeMap::from([("-Werror".to_string(), String::new())]), ..entry("a") };
+    assert!(config(vec![defines]).validate().is_err());
+    let with_envs = BuildConfig { pio_envs: vec!["esp32".to_string()], ..config(vec![entry("a")]) };
+    assert!(with_envs.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_matrix_entries_fields_must_suit_the_build_system() {
+    let repo = TempDir::new().unwrap();
+    let vars = MatrixEntry { make_vars: BTreeMap::from([("BOARD".to_string(), "f4".to_string())]), ..entry("f4") };
+    let config = BuildConfig { matrix: vec![vars], ..BuildConfig::default() };
+
+    let error = execute_build_with_config(repo.path(), BuildSystem::CMake, &config).await.unwrap_err().to_string();
+    assert_eq!(error, "matrix entry 'f4' sets make_vars, which CMake builds don't use (they use board, cmake_defines)");
+    let error = execute_build_with_config(repo.path(), BuildSystem::Cargo, &config).await.unwrap_err().to_string();
+    assert_eq!(error, "matrix builds aren't supported for Cargo projects");
+}
+
+#[tokio::test]
+async fn test_cmake_matrix_builds_each_entry_in_its_own_directory() {
+    let tools = TempDir::new().unwrap();
+    let cmake = tools.path().join("cmake");
+    // Configure records its arguments in the build directory; the build turns them into firmware
+    fs::write(
+        &cmake,
+        "#!/bin/sh\n\
+         case \"$1\" in\n\
+           --version) echo 'cmake version 3.28.0' ;;\n\
+           --build) grep -q FAIL defines && { echo 'undefined reference to main' >&2; exit 1; }; cp defines firmware.elf ;;\n\
+           *) echo \"$*\" > defines ;;\n\
+         esac\n",
+    )
+    .unwrap();
+    fs::set_permissions(&cmake, fs::Permissions::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("CMakeLists.txt"), "project(blinky C)\n").unwrap();
+
+    let h743 = MatrixEntry {
+        cmake_defines: BTreeMap::from([("APP_LED".to_string(), "2".to_string())]),
+        ..board("h743", "nucleo_h743zi")
+    };
+    let config = with_tools(tools.path(), vec![board("f401", "nucleo_f401re"), h743]);
+    let result = execute_build_with_config(repo.path(), BuildSystem::CMake, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    assert!(!result.is_partial());
+
+    let entries: Vec<_> = result.matrix.iter().map(|e| (e.name.as_str(), e.success, e.build_dir.as_str(), e.artifacts.clone())).collect();
+    assert_eq!(
+        entries,
+        [
+            ("f401", true, "build-f401", vec!["build-f401/firmware.elf".to_string()]),
+            ("h743", true, "build-h743", vec!["build-h743/firmware.elf".to_string()]),
+        ]
+    );
+    let built = |dir: &str| fs::read_to_string(repo.path().join(dir).join("firmware.elf")).unwrap();
+    assert_eq!(built("build-f401").trim(), ".. -DBOARD=nucleo_f401re");
+    assert_eq!(built("build-h743").trim(), ".. -DBOARD=nucleo_h743zi -DAPP_LED=2");
+    assert!(!repo.path().join("build").exists());
+
+    let labels: Vec<_> = result.artifacts.iter().map(|a| (a.metadata["matrix"].as_str(), a.path.ends_with("build-h743/firmware.elf"))).collect();
+    assert_eq!(labels, [("f401", false), ("h743", true)]);
+    assert_eq!(result.output_path, Some(result.artifacts[0].path.clone()));
+
+    // One entry failing leaves a partial build, or a failed one with require_all
+    let broken = MatrixEntry { cmake_defines: BTreeMap::from([("FAIL".to_string(), "1".to_string())]), ..board("h743", "nucleo_h743zi") };
+    let config = with_tools(tools.path(), vec![board("f401", "nucleo_f401re"), broken]);
+    let result = execute_build_with_config(repo.path(), BuildSystem::CMake, &config).await.unwrap();
+    assert!(result.success && result.is_partial());
+    assert_eq!(result.failed_parts(), ["h743"]);
+    assert!(result.matrix[1].error.as_deref().unwrap().contains("undefined reference to main"));
+    assert_eq!(result.artifacts.len(), 1);
+
+    let config = BuildConfig { require_all: true, ..config };
+    let result = execute_build_with_config(repo.path(), BuildSystem::CMake, &config).await.unwrap();
+    assert!(!result.success);
+    let error = result.error_output.unwrap();
+    assert!(error.starts_with("Matrix build failed in 1 of 2 entries (h743):\n[h743] "), "{}", error);
+}
+
+#[tokio::test]
+async fn test_makefile_matrix_passes_vars_and_build_dir() {
+    let repo = TempDir::new().unwrap();
+    fs::write(
+        repo.path().join("Makefile"),
+        "BUILD_DIR ?= build\nBOARD ?= none\n\nall:\n\tmkdir -p $(BUILD_DIR)\n\techo $(BOARD) > $(BUILD_DIR)/firmware.bin\n",
+    )
+    .unwrap();
+    let vars = |name: &str, board: &str| MatrixEntry { make_vars: BTreeMap::from([("BOARD".to_string(), board.to_string())]), ..entry(name) };
+    let config = BuildConfig { matrix: vec![vars("f401", "nucleo_f401re"), vars("h743", "nucleo_h743zi")], ..BuildConfig::default() };
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    for (name, board) in [("f401", "nucleo_f401re"), ("h743", "nucleo_h743zi")] {
+        let firmware = repo.path().join(format!("build-{}/firmware.bin", name));
+        assert_eq!(fs::read_to_string(&firmware).unwrap().trim(), board);
+        let artifact = result.artifacts.iter().find(|a| a.metadata["matrix"] == name).unwrap();
+        assert_eq!(Path::new(&artifact.path), firmware);
+    }
+}
@@ -0,0 +1,151 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::detection::{analyze, detect_build_system};
+use nabla_runner::execution::execute_build_with_config;
+use nabla_runner::mplabx::{parse_configurations, select_configuration, MissingCompiler};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Trimmed from a project MPLAB X v6 saved, with a production and a debug-board configuration
+const CONFIGURATIONS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<configurationDescriptor version="65">
+  <logicalFolder name="root" displayName="root" projectFiles="true">
+    <logicalFolder name="SourceFiles" displayName="Source Files" projectFiles="true">
+      <itemPath>main.c</itemPath>
+    </logicalFolder>
+  </logicalFolder>
+  <projectmakefile>Makefile</projectmakefile>
+  <confs>
+    <conf name="default" type="2">
+      <toolsSet>
+        <developmentServer>localhost</developmentServer>
+        <targetDevice>PIC18F46K22</targetDevice>
+        <targetHeader></targetHeader>
+        <platformTool>noID</platformTool>
+        <languageToolchain>XC8</languageToolchain>
+        <languageToolchainVersion>2.40</languageToolchainVersion>
+        <platform>3</platform>
+      </toolsSet>
+      <compileType>
+        <linkerTool><linkerLibItems></linkerLibItems></linkerTool>
+      </compileType>
+    </conf>
+    <conf name="curiosity" type="2">
+      <toolsSet>
+        <targetDevice>PIC24FJ128GA010</targetDevice>
+        <languageToolchain>XC16</languageToolchain>
+        <languageToolchainVersion>2.10</languageToolchainVersion>
+      </toolsSet>
+    </conf>
+  </confs>
+</configurationDescriptor>
+"#;
+
+/// An MPLAB X project whose generated Makefile writes `dist/<conf>/production/blink.X.production.hex`
+fn mplabx_project() -> TempDir {
+    let repo = TempDir::new().unwrap();
+    fs::create_dir(repo.path().join("nbproject")).unwrap();
+    fs::write(repo.path().join("nbproject/configurations.xml"), CONFIGURATIONS_XML).unwrap();
+    for (conf, compiler) in [("default", "xc8-cc"), ("curiosity", "xc16-gcc")] {
+        fs::write(
+            repo.path().join(format!("nbproject/Makefile-{}.mk", conf)),
+            format!("build:\n\tmkdir -p dist/{0}/production\n\t{1} > dist/{0}/production/blink.X.production.hex\n", conf, compiler),
+        )
+        .unwrap();
+    }
+    fs::write(repo.path().join("Makefile"), "all:\n\t$(MAKE) -f nbproject/Makefile-$(CONF).mk build\n").unwrap();
+    fs::write(repo.path().join("main.c"), "void main(void) {}\n").unwrap();
+    repo
+}
+
+fn with_path(tools: &Path) -> BuildConfig {
+    let mut config = BuildConfig::default();
+    config.command_env.insert("PATH".to_string(), format!("{}:{}", tools.display(), std::env::var("PATH").unwrap()));
+    config
+}
+
+#[test]
+fn test_configurations_xml_names_each_conf_and_its_compiler() {
+    let configurations = parse_configurations(CONFIGURATIONS_XML);
+    let parsed: Vec<_> = configurations
+        .iter()
+        .map(|c| (c.name.as_str(), c.toolchain.as_deref(), c.toolchain_version.as_deref(), c.device.as_deref(), c.compiler()))
+        .collect();
+    assert_eq!(
+        parsed,
+        [
+            ("default", Some("XC8"), Some("2.40"), Some("PIC18F46K22"), Some("xc8-cc")),
+            ("curiosity", Some("XC16"), Some("2.10"), Some("PIC24FJ128GA010"), Some("xc16-gcc")),
+        ]
+    );
+
+    assert_eq!(select_configuration(&configurations, None).unwrap().name, "default");
+    assert_eq!(select_configuration(&configurations, Some("curiosity")).unwrap().name, "curiosity");
+    let error = select_configuration(&configurations, Some("release")).unwrap_err().to_string();
+    assert_eq!(error, "MPLAB X configuration 'release' not found in nbproject/configurations.xml (found: default, curiosity)");
+    assert!(parse_configurations("<configurationDescriptor/>").is_empty());
+}
+
+#[tokio::test]
+async fn test_mplabx_project_is_not_built_as_a_plain_makefile() {
+    let repo = mplabx_project();
+    assert_eq!(detect_build_system(repo.path()).await, Some(BuildSystem::MplabX));
+
+    let report = analyze(repo.path()).await.unwrap();
+    assert_eq!(report.markers, ["nbproject/configurations.xml"]);
+    assert_eq!(report.artifact_format, "hex");
+    assert_eq!(report.suggested_command, "make -f Makefile CONF=default");
+}
+
+#[tokio::test]
+async fn test_missing_xc_compiler_fails_before_make_runs() {
+    let repo = mplabx_project();
+    let tools = TempDir::new().unwrap();
+
+    let error = execute_build_with_config(repo.path(), BuildSystem::MplabX, &with_path(tools.path())).await.unwrap_err();
+    let missing = error.downcast_ref::<MissingCompiler>().expect("a MissingCompiler error");
+    assert_eq!(
+        missing,
+        &MissingCompiler {
+            configuration: "default".to_string(),
+            toolchain: "XC8".to_string(),
+            compiler: "xc8-cc".to_string(),
+            version: Some("2.40".to_string()),
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "MPLAB X configuration 'default' needs the XC8 compiler v2.40 (xc8-cc), which is not installed on the runner. \
+         Install MPLAB XC8 v2.40, or build in a `container` image that provides it."
+    );
+    assert!(!repo.path().join("dist").exists());
+}
+
+#[tokio::test]
+async fn test_mplabx_builds_the_requested_configuration() {
+    let repo = mplabx_project();
+    let tools = TempDir::new().unwrap();
+    for compiler in ["xc8-cc", "xc16-gcc"] {
+        let path = tools.path().join(compiler);
+        fs::write(&path, format!("#!/bin/sh\necho ':00000001FF {}'\n", compiler)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::MplabX, &with_path(tools.path())).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    let hex = repo.path().join("dist/default/production/blink.X.production.hex");
+    assert_eq!(result.output_path.as_deref(), Some(hex.to_str().unwrap()));
+    assert_eq!(result.target_format.as_deref(), Some("hex"));
+
+    let config = BuildConfig { mplab_conf: Some("curiosity".to_string()), ..with_path(tools.path()) };
+    let result = execute_build_with_config(repo.path(), BuildSystem::MplabX, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    assert!(result.output_path.unwrap().ends_with("dist/curiosity/production/blink.X.production.hex"));
+
+    // Without its Makefiles, and without MPLAB X to generate them, the build can't start
+    fs::remove_file(repo.path().join("nbproject/Makefile-default.mk")).unwrap();
+    let error = execute_build_with_config(repo.path(), BuildSystem::MplabX, &with_path(tools.path())).await.unwrap_err();
+    assert!(error.to_string().starts_with("MPLAB X project has no nbproject/Makefile-default.mk and prjMakefilesGenerator.sh is not on PATH"), "{}", error);
+    assert!(BuildConfig { mplab_conf: Some("../x".to_string()), ..BuildConfig::default() }.validate().is_err());
+}
@@ -0,0 +1,86 @@
+use nabla_runner::core::{BuildConfig, BuildResult};
+use nabla_runner::output::{OutputBuffer, OutputLog, DEFAULT_OUTPUT_LIMIT};
+use nabla_runner::process::{log_output_to, run_command};
+use tempfile::TempDir;
+use tokio::process::Command;
+
+#[test]
+fn test_invalid_utf8_is_replaced_per_line() {
+    let mut buffer = OutputBuffer::new(1024);
+    buffer.push(b"main.c:1:1: error: stray '\\351' in program: caf\xe9\n");
+    // A multi-byte character split across two reads is kept intact
+    buffer.push("température: ".as_bytes());
+    buffer.push(&"é\n".as_bytes()[..1]);
+    buffer.push(&"é\n".as_bytes()[1..]);
+    buffer.push(b"\xff\xfe no trailing newline");
+
+    let text = buffer.finish();
+    assert_eq!(
+        text,
+        "main.c:1:1: error: stray '\\351' in program: caf\u{FFFD}\ntempérature: é\n\u{FFFD}\u{FFFD} no trailing newline"
+    );
+}
+
+#[test]
+fn test_large_stream_keeps_head_and_tail() {
+    let limit = 64 * 1024;
+    let mut buffer = OutputBuffer::new(limit);
+    buffer.push(b"first line\n");
+    let line = [b'x'; 999].iter().copied().chain([b'\n']).collect::<Vec<u8>>();
+    let mut chunk = Vec::new();
+    for _ in 0..64 {
+        chunk.extend_from_slice(&line);
+    }
+    // 50 MB in 64 KB chunks, never holding more than the limit
+    for _ in 0..(50_000_000 / chunk.len()) {
+        buffer.push(&chunk);
+        assert!(buffer.len() <= limit, "buffer grew to {} bytes", buffer.len());
+    }
+    buffer.push(b"last line\n");
+
+    assert!(buffer.omitted() > 49_000_000);
+    let text = buffer.finish();
+    assert!(text.len() <= limit + 100);
+    assert!(text.starts_with("first line\n"));
+    assert!(text.ends_with("last line\n"));
+    assert!(text.contains(" bytes of output omitted ...]\n"), "{}", &text[..200]);
+}
+
+#[tokio::test]
+async fn test_run_command_bounds_output_and_logs_everything() {
+    let dir = TempDir::new().unwrap();
+    let log_path = dir.path().join("logs/job.log");
+    let log = OutputLog::open(&log_path).unwrap();
+
+    let mut command = Command::new("sh");
+    command.args([
+        "-c",
+        "printf 'caf\\351\\n' >&2; head -c 50000000 /dev/zero | tr '\\000' x; printf 'make: *** [all] Error 1\\n' >&2",
+    ]);
+    let output = log_output_to(log, run_command(command, &BuildConfig::default())).await.unwrap();
+
+    assert!(output.stdout.len() <= DEFAULT_OUTPUT_LIMIT + 100, "{} bytes kept", output.stdout.len());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "caf\u{FFFD}\nmake: *** [all] Error 1\n");
+    assert_eq!(std::fs::metadata(&log_path).unwrap().len(), 50_000_000 + 5 + 24);
+
+    let result = BuildResult {
+        error_output: Some(format!("Make build failed: {}{}", String::from_utf8(output.stdout).unwrap(), stderr)),
+        ..failed_result()
+    };
+    let json = serde_json::to_string(&result).unwrap();
+    let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert!(parsed["error_output"].as_str().unwrap().ends_with("Error 1\n"));
+}
+
+fn failed_result() -> BuildResult {
+    serde_json::from_value(serde_json::json!({
+        "success": false,
+        "output_path": null,
+        "target_format": null,
+        "error_output": null,
+        "build_system": "Makefile",
+        "duration_ms": 0,
+    }))
+    .unwrap()
+}
@@ -0,0 +1,681 @@
+use nabla_runner::core::{BuildConfig, BuildSystem, CheckSeverity, PioCheck, TestSummary};
+use nabla_runner::diagnostics::Severity;
+use nabla_runner::execution::{analyze_platformio_error, execute_build_with_config, BuildStepFailed, platformio_env_parallelism, platformio_test_args, run_env_builds};
+use nabla_runner::output::LiveLog;
+use nabla_runner::{FirmwareBuildRunner, RunOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tempfile::TempDir;
+use tokio::process::Command;
+use nabla_runner::platformio::{
+    default_environments, environments, missing_platforms, missing_python_module, parse_check_report, parse_ini, parse_platforms, parse_run_summary, parse_test_summary,
+    penv_pip_install, pip_package, preflight_check, PlatformInstallFailed, PlatformSpec,
+};
+
+const MULTI_ENV_INI: &str = r#"; Tiltbridge-style multi-environment project
+[platformio]
+default_envs = lolin_d32
+
+[env]
+framework = arduino
+build_flags =
+    -DVERSION=1
+    -DDEBUG=0
+
+[env:lolin_d32]
+platform = espressif32@6.4.0
+board = lolin_d32
+
+[env:d32_pro]
+platform = espressif32@6.4.0
+board = lolin_d32_pro
+
+[env:uno]
+platform = atmelavr
+board = uno
+
+[env:tft]
+platform = platformio/espressif32@^6.3.0 ; pinned range
+board = esp32dev
+
+[env:interpolated]
+platform = ${env:lolin_d32.platform}
+"#;
+
+#[test]
+fn test_parse_platforms_multi_env() {
+    let platforms = parse_platforms(MULTI_ENV_INI);
+    let specs: Vec<&str> = platforms.iter().map(|p| p.spec.as_str()).collect();
+
+    assert_eq!(specs, vec!["espressif32@6.4.0", "atmelavr", "platformio/espressif32@^6.3.0"]);
+
+    assert_eq!(platforms[0].name, "espressif32");
+    assert_eq!(platforms[0].version.as_deref(), Some("6.4.0"));
+    assert!(platforms[0].is_exact_version());
+
+    assert_eq!(platforms[1].name, "atmelavr");
+    assert_eq!(platforms[1].version, None);
+
+    assert_eq!(platforms[2].name, "espressif32");
+    assert_eq!(platforms[2].version.as_deref(), Some("^6.3.0"));
+    assert!(!platforms[2].is_exact_version());
+}
+
+#[test]
+fn test_parse_platform_url_spec() {
+    let spec = PlatformSpec::parse("https://github.com/platformio/platform-espressif32.git#v6.4.0");
+    assert_eq!(spec.name, "espressif32");
+    assert_eq!(spec.version, None);
+}
+
+#[test]
+fn test_parse_ini_continuation_lines() {
+    let sections = parse_ini(MULTI_ENV_INI);
+    let env = sections.iter().find(|s| s.name == "env").unwrap();
+    assert_eq!(env.get("build_flags"), Some("-DVERSION=1\n-DDEBUG=0"));
+}
+
+#[test]
+fn test_parse_ini_windows_line_endings_and_bom() {
+    let ini = "\u{feff}[env:esp32dev]\r\nplatform = espressif32 \r\nbuild_flags =\r\n  -DVERSION=1\r\n  -DDEBUG=0\r\n";
+    let sections = parse_ini(ini);
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections[0].name, "env:esp32dev");
+    assert_eq!(sections[0].get("platform"), Some("espressif32"));
+    assert_eq!(sections[0].get("build_flags"), Some("-DVERSION=1\n-DDEBUG=0"));
+    assert_eq!(environments(ini), ["esp32dev"]);
+}
+
+#[test]
+fn test_missing_platforms_noop_when_cached() {
+    let installed = vec![
+        ("espressif32".to_string(), "6.4.0".to_string()),
+        ("atmelavr".to_string(), "5.0.0".to_string()),
+    ];
+
+    let missing = missing_platforms(parse_platforms(MULTI_ENV_INI), &installed);
+    assert!(missing.is_empty());
+
+    let older = vec![("espressif32".to_string(), "6.3.2".to_string())];
+    let missing = missing_platforms(parse_platforms(MULTI_ENV_INI), &older);
+    let specs: Vec<&str> = missing.iter().map(|p| p.spec.as_str()).collect();
+    assert_eq!(specs, vec!["espressif32@6.4.0", "atmelavr"]);
+}
+
+const PIO_TEST_OUTPUT: &str = "\
+Verbosity level can be increased via `-v, -vv, or -vvv` option
+Collected 2 tests
+
+Processing test_math in native environment
+--------------------------------------------------------------------------------
+Building...
+Testing...
+test/test_math/test_main.c:20: test_add	[PASSED]
+test/test_math/test_main.c:21: test_sub: Expected 1 Was 2	[FAILED]
+test/test_math/test_main.c:22: test_mul	[PASSED]
+---------------- native:test_math [FAILED] Took 0.61 seconds ----------------
+
+=================================== SUMMARY ===================================
+Environment    Test       Status    Duration
+-------------  ---------  --------  ------------
+native         test_math  FAILED    00:00:00.609
+native         test_io    SKIPPED
+================= 3 test cases: 1 failed, 2 succeeded in 00:00:00.609 =================
+";
+
+#[test]
+fn test_parse_pio_test_summary() {
+    assert_eq!(
+        parse_test_summary(PIO_TEST_OUTPUT),
+        Some(TestSummary { total: 3, passed: 2, failed: 1, skipped: 0 })
+    );
+    assert_eq!(
+        parse_test_summary("-----------------------\n5 Tests 0 Failures 1 Ignored\nOK\n"),
+        Some(TestSummary { total: 5, passed: 4, failed: 0, skipped: 1 })
+    );
+    assert_eq!(parse_test_summary("Building...\nNo tests found\n"), None);
+}
+
+#[test]
+fn test_pio_test_config() {
+    let config: BuildConfig = serde_json::from_str(r#"{"pio_test": true, "pio_test_env": "native"}"#).unwrap();
+    config.validate().unwrap();
+    assert_eq!(platformio_test_args(&config), ["test", "-e", "native"]);
+
+    let config = BuildConfig { pio_test_env: Some("native; rm -rf /".to_string()), ..BuildConfig::default() };
+    assert!(config.validate().is_err());
+}
+
+/// A stand-in for `pio run -e <env>` that marks itself running, sleeps, then records how many
+/// environments were running at once. Environments named `broken*` fail.
+fn stub_env_command(dir: &Path, env: &str) -> Command {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(
+            r#"touch running/$ENV; sleep 0.3; ls running | wc -l > peak_$ENV; rm running/$ENV
+            echo "Building $ENV"; case $ENV in broken*) echo "error: no board" >&2; exit 1;; esac"#,
+        )
+        .env("ENV", env)
+        .current_dir(dir);
+    command
+}
+
+fn peak(dir: &Path, env: &str) -> usize {
+    std::fs::read_to_string(dir.join(format!("peak_{}", env))).unwrap().trim().parse().unwrap()
+}
+
+#[tokio::test]
+async fn test_env_builds_bounded_by_parallelism() {
+    let dir = TempDir::new().unwrap();
+    std::fs::create_dir(dir.path().join("running")).unwrap();
+    let envs: Vec<String> = ["esp32", "d32_pro", "broken_env", "m5stick"].iter().map(|e| e.to_string()).collect();
+
+    let started = Instant::now();
+    let results = run_env_builds(&envs, 2, false, &BuildConfig::default(), |env| stub_env_command(dir.path(), env)).await;
+    let elapsed = started.elapsed();
+
+    let peaks: Vec<usize> = envs.iter().map(|env| peak(dir.path(), env)).collect();
+    assert!(peaks.iter().all(|&p| p <= 2), "{:?}", peaks);
+    assert!(peaks.contains(&2), "{:?}", peaks);
+    assert!(elapsed.as_secs_f32() < 1.1, "took {:?}", elapsed);
+
+    // Results stay in request order; one failure doesn't affect the others
+    let names: Vec<&str> = results.iter().map(|r| r.env.as_str()).collect();
+    assert_eq!(names, ["esp32", "d32_pro", "broken_env", "m5stick"]);
+    let succeeded: Vec<bool> = results.iter().map(|r| r.output.as_ref().unwrap().status.success()).collect();
+    assert_eq!(succeeded, [true, true, false, true]);
+    assert!(results[2].log.contains("[broken_env] error: no board"), "{}", results[2].log);
+    assert!(results[0].log.starts_with("[esp32] Building esp32"), "{}", results[0].log);
+}
+
+#[tokio::test]
+async fn test_env_builds_serialize_first_on_cold_cache() {
+    let dir = TempDir::new().unwrap();
+    std::fs::create_dir(dir.path().join("running")).unwrap();
+    let envs: Vec<String> = ["first", "second", "third"].iter().map(|e| e.to_string()).collect();
+
+    run_env_builds(&envs, 3, true, &BuildConfig::default(), |env| stub_env_command(dir.path(), env)).await;
+
+    assert_eq!(peak(dir.path(), "first"), 1);
+    // Whichever of the two finishes first saw the other still running
+    assert_eq!(peak(dir.path(), "second").max(peak(dir.path(), "third")), 2);
+}
+
+#[test]
+fn test_env_parallelism_clamped() {
+    let config = BuildConfig { max_parallel_envs: Some(8), ..BuildConfig::default() };
+    assert_eq!(platformio_env_parallelism(&config, 3), 3);
+    let config = BuildConfig { max_parallel_envs: Some(2), ..BuildConfig::default() };
+    assert_eq!(platformio_env_parallelism(&config, 3), 2);
+
+    let config: BuildConfig = serde_json::from_str(r#"{"pio_envs": ["esp32", "bad env"]}"#).unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_preflight_accepts_valid_multi_env_config() {
+    assert!(preflight_check(MULTI_ENV_INI, &["d32_pro".to_string()]).is_empty());
+    assert_eq!(environments(MULTI_ENV_INI), ["lolin_d32", "d32_pro", "uno", "tft", "interpolated"]);
+}
+
+#[tokio::test]
+async fn test_detection_reports_environments_and_defaults() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("platformio.ini"), MULTI_ENV_INI).unwrap();
+
+    let report = nabla_runner::detection::analyze(dir.path()).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::PlatformIO);
+    assert_eq!(report.environments, ["lolin_d32", "d32_pro", "uno", "tft", "interpolated"]);
+    assert_eq!(report.default_environments, ["lolin_d32"]);
+
+    // Several defaults across lines, one of them undefined; without default_envs, all of them
+    let ini = "[platformio]\ndefault_envs =\n    uno, missing\n    native\n\n[env:native]\nplatform = native\n\n[env:uno]\nplatform = atmelavr\n";
+    assert_eq!(default_environments(ini), ["uno", "native"]);
+    assert_eq!(default_environments("[env:a]\n[env:b]\n"), ["a", "b"]);
+}
+
+#[test]
+fn test_preflight_flags_invalid_config() {
+    let ini = "[platformio]\ndefault_envs = esp32, missing\n\n\
+               [env:esp32]\nplatform = espressif32@99.99.99\nboard = esp32dev\n\n\
+               [env:boardless]\nextends = common\nboard = uno\n";
+
+    let issues = preflight_check(ini, &["nrf52".to_string()]);
+
+    assert_eq!(issues.len(), 5, "{:#?}", issues);
+    assert!(issues.iter().any(|i| i.contains("espressif32@99.99.99") && i.contains("does not plausibly exist")));
+    assert!(issues.iter().any(|i| i.contains("default_envs names 'missing'")));
+    assert!(issues.iter().any(|i| i.contains("extends 'common'")));
+    assert!(issues.iter().any(|i| i.contains("[env:boardless] has no platform")));
+    assert!(issues.iter().any(|i| i.contains("'nrf52' was requested")));
+}
+
+#[tokio::test]
+async fn test_strict_config_fails_before_building() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(
+        dir.path().join("platformio.ini"),
+        "[env:esp32]\nplatform = espressif32@99.99.99\nboard = esp32dev\n",
+    )
+    .unwrap();
+    let config = BuildConfig { strict_config: true, ..BuildConfig::default() };
+
+    let error = execute_build_with_config(dir.path(), BuildSystem::PlatformIO, &config)
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.starts_with("platformio.ini pre-flight check failed"), "{}", error);
+    assert!(error.contains("espressif32@99.99.99"), "{}", error);
+}
+
+const RUN_SUMMARY: &str = "\
+========================= [SUCCESS] Took 5.12 seconds =========================
+========================= [FAILED] Took 1.23 seconds =========================
+
+Environment    Status    Duration
+-------------  --------  ------------
+esp32dev       SUCCESS   00:00:05.123
+wrover         FAILED    00:00:01.234
+native         IGNORED
+==================== 1 failed, 1 succeeded in 00:00:06.357 ====================
+";
+
+#[test]
+fn test_parse_pio_run_summary() {
+    let environments = parse_run_summary(RUN_SUMMARY);
+    let parsed: Vec<(&str, bool, u64)> = environments.iter().map(|e| (e.env.as_str(), e.success, e.duration_ms)).collect();
+    assert_eq!(parsed, [("esp32dev", true, 5123), ("wrover", false, 1234)]);
+    assert_eq!(environments[1].error.as_deref(), Some("pio run reported FAILED"));
+
+    assert!(parse_run_summary("Building .pio/build/uno/firmware.hex\n[SUCCESS] Took 2.00 seconds\n").is_empty());
+}
+
+#[tokio::test]
+async fn test_pio_run_exiting_zero_with_failed_env() {
+    let tools = TempDir::new().unwrap();
+    let pio = tools.path().join("pio");
+    // Builds esp32dev, fails wrover, and still exits 0
+    std::fs::write(
+        &pio,
+        format!("#!/bin/sh\nmkdir -p .pio/build/esp32dev .pio/build/wrover\nprintf fw > .pio/build/esp32dev/firmware.bin\ncat <<'EOF'\n{}EOF\n", RUN_SUMMARY),
+    )
+    .unwrap();
+    std::fs::set_permissions(&pio, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    std::fs::write(
+        repo.path().join("platformio.ini"),
+        "[env:esp32dev]\nplatform = espressif32\nboard = esp32dev\n\n[env:wrover]\nplatform = espressif32\nboard = esp-wrover-kit\n",
+    )
+    .unwrap();
+
+    let mut config = BuildConfig { preinstall_platforms: false, ..BuildConfig::default() };
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error_output);
+    let environments: Vec<(&str, bool)> = result.environments.iter().map(|e| (e.env.as_str(), e.success)).collect();
+    assert_eq!(environments, [("esp32dev", true), ("wrover", false)]);
+    assert_eq!(result.artifacts.len(), 1);
+    assert_eq!(result.artifacts[0].metadata["env"], "esp32dev");
+
+    // Reported successful, but nothing was left in .pio/build: no environment built
+    std::fs::write(&pio, format!("#!/bin/sh\ncat <<'EOF'\n{}EOF\n", RUN_SUMMARY)).unwrap();
+    std::fs::remove_dir_all(repo.path().join(".pio")).unwrap();
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+
+    assert!(!result.success);
+    assert!(result.environments.iter().all(|e| !e.success));
+    assert!(result.error_output.unwrap().contains("every environment (esp32dev, wrover)"));
+}
+
+/// A project with `esp32` and `broken` environments and a stand-in `pio` on `config`'s PATH
+/// that builds `esp32` and fails `broken`
+fn two_env_project(tools: &Path, repo: &Path) -> BuildConfig {
+    let pio = tools.join("pio");
+    std::fs::write(
+        &pio,
+        "#!/bin/sh\n[ \"$3\" = broken ] && { echo 'error: no board' >&2; exit 1; }\nmkdir -p .pio/build/$3 && printf fw > .pio/build/$3/firmware.bin\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&pio, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    std::fs::write(repo.join("platformio.ini"), "[env:esp32]\nboard = esp32dev\n\n[env:broken]\nboard = none\n").unwrap();
+
+    let mut config = BuildConfig {
+        preinstall_platforms: false,
+        pio_envs: vec!["esp32".to_string(), "broken".to_string()],
+        ..BuildConfig::default()
+    };
+    let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    config
+}
+
+#[tokio::test]
+async fn test_env_failure_is_partial_unless_all_required() {
+    let (tools, repo) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let config = two_env_project(tools.path(), repo.path());
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    assert!(result.is_partial());
+    assert_eq!(result.environments[0].artifacts, [".pio/build/esp32/firmware.bin"]);
+    assert_eq!(result.environments[1].error.as_deref(), Some("pio run exited with exit status: 1"));
+
+    let config = BuildConfig { require_all: true, ..config };
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+    assert!(!result.success);
+    assert!(!result.is_partial());
+    let error = result.error_output.unwrap();
+    assert!(error.contains("failed in 1 of 2 environments (broken)"), "{}", error);
+    assert!(error.contains("[broken] error: no board"), "{}", error);
+    assert_eq!(result.environments[0].artifacts.len(), 1);
+}
+
+#[tokio::test]
+async fn test_fail_fast_skips_envs_not_yet_started() {
+    let dir = TempDir::new().unwrap();
+    std::fs::create_dir(dir.path().join("running")).unwrap();
+    let envs: Vec<String> = ["broken_env", "esp32", "m5stick"].iter().map(|e| e.to_string()).collect();
+    let config = BuildConfig { fail_fast: true, ..BuildConfig::default() };
+
+    let results = run_env_builds(&envs, 1, false, &config, |env| stub_env_command(dir.path(), env)).await;
+
+    let skipped: Vec<bool> = results.iter().map(|r| r.skipped).collect();
+    assert_eq!(skipped, [false, true, true]);
+    assert!(!dir.path().join("peak_esp32").exists());
+}
+
+/// `pio check --json-output` for a project whose `native` environment cppcheck can't analyze;
+/// the out-of-bounds access is in source both environments share
+const CHECK_REPORT: &str = r#"[{"env": "esp32dev", "tool": "cppcheck", "duration": 3.42, "succeeded": true, "stats": {"high": 1, "medium": 1, "low": 1}, "defects": [{"severity": "high", "category": "error", "message": "Array 'buf[8]' accessed at index 8, which is out of bounds.", "file": "/work/repo/src/main.cpp", "line": 12, "column": 9, "callstack": null, "id": "arrayIndexOutOfBounds", "cwe": 788}, {"severity": "medium", "category": "warning", "message": "Member variable 'Sensor::last' is not initialized in the constructor.", "file": "/work/repo/lib/sensor/sensor.cpp", "line": 4, "column": 0, "callstack": null, "id": "uninitMemberVar", "cwe": 398}, {"severity": "low", "category": "style", "message": "The function 'unused' is never used.", "file": "/work/repo/src/main.cpp", "line": 20, "column": 0, "callstack": null, "id": "unusedFunction", "cwe": 561}]}, {"env": "wrover", "tool": "cppcheck", "duration": 3.1, "succeeded": true, "stats": {"high": 1, "medium": 0, "low": 0}, "defects": [{"severity": "high", "category": "error", "message": "Array 'buf[8]' accessed at index 8, which is out of bounds.", "file": "/work/repo/src/main.cpp", "line": 12, "column": 9, "callstack": null, "id": "arrayIndexOutOfBounds", "cwe": 788}]}, {"env": "native", "tool": "cppcheck", "duration": 0.01, "succeeded": null, "stats": {"high": 0, "medium": 0, "low": 0}, "defects": []}]"#;
+
+#[test]
+fn test_parse_pio_check_report() {
+    let report = parse_check_report(&format!("{}\n", CHECK_REPORT), Path::new("/work/repo")).unwrap();
+
+    assert_eq!(report.checked, ["esp32dev", "wrover"]);
+    assert_eq!(report.unchecked, ["native"]);
+    let defects: Vec<_> = report
+        .defects
+        .iter()
+        .map(|d| (d.file.as_str(), d.line, d.column, d.severity, d.flag.as_deref()))
+        .collect();
+    assert_eq!(
+        defects,
+        [
+            ("src/main.cpp", 12, Some(9), Severity::Error, Some("arrayIndexOutOfBounds")),
+            ("lib/sensor/sensor.cpp", 4, None, Severity::Warning, Some("uninitMemberVar")),
+            ("src/main.cpp", 20, None, Severity::Note, Some("unusedFunction")),
+        ]
+    );
+    assert!(report.defects.iter().all(|d| d.tool.as_deref() == Some("platformio-check")));
+
+    assert!(parse_check_report("Error: Nothing to check\n", Path::new("/work/repo")).is_err());
+    assert!(parse_check_report("[{\"env\": ", Path::new("/work/repo")).is_err());
+}
+
+#[tokio::test]
+async fn test_pio_check_reports_defects_and_fails_above_threshold() {
+    let tools = TempDir::new().unwrap();
+    let pio = tools.path().join("pio");
+    let report = CHECK_REPORT.replace("/work/repo/", "");
+    std::fs::write(
+        &pio,
+        format!(
+            "#!/bin/sh\necho \"pio $*\" >> pio.log\n\
+             [ \"$1\" = check ] && {{ cat <<'EOF'\n{}\nEOF\nexit 0; }}\n\
+             mkdir -p .pio/build/esp32dev && printf fw > .pio/build/esp32dev/firmware.bin\n",
+            report
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&pio, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    std::fs::write(repo.path().join("platformio.ini"), "[env:esp32dev]\nplatform = espressif32\nboard = esp32dev\n").unwrap();
+
+    let mut config = BuildConfig {
+        preinstall_platforms: false,
+        pio_check: Some(PioCheck {
+            enabled: true,
+            environments: vec!["esp32dev".to_string(), "native".to_string()],
+            ..PioCheck::default()
+        }),
+        ..BuildConfig::default()
+    };
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+
+    // Without a threshold, defects are only reported
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    assert_eq!(result.static_analysis.len(), 3);
+    assert_eq!(result.config_warnings, ["pio check could not analyze: native"]);
+    let log = std::fs::read_to_string(repo.path().join("pio.log")).unwrap();
+    assert_eq!(log.lines().last(), Some("pio check --json-output -e esp32dev -e native"));
+
+    config.pio_check.as_mut().unwrap().severity_threshold = Some(CheckSeverity::High);
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+    assert!(!result.success);
+    let error = result.error_output.unwrap();
+    assert!(error.starts_with("pio check found 1 defect(s) of high severity or higher:"), "{}", error);
+    assert!(error.contains("src/main.cpp:12: Array 'buf[8]' accessed at index 8"), "{}", error);
+    assert_eq!(result.static_analysis.len(), 3);
+
+    // A project pio check can't analyze at all still builds
+    std::fs::write(
+        &pio,
+        "#!/bin/sh\n[ \"$1\" = check ] && { echo 'Error: Nothing to check' >&2; exit 1; }\n\
+         mkdir -p .pio/build/esp32dev && printf fw > .pio/build/esp32dev/firmware.bin\n",
+    )
+    .unwrap();
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    assert!(result.static_analysis.is_empty());
+    assert_eq!(
+        result.config_warnings,
+        ["pio check did not complete: pio check printed no JSON report: Error: Nothing to check"]
+    );
+}
+
+#[test]
+fn test_pio_targets_refuse_upload_and_monitor() {
+    for target in ["upload", "uploadfs", "monitor"] {
+        let config = BuildConfig { pio_targets: vec!["size".to_string(), target.to_string()], ..BuildConfig::default() };
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.starts_with(&format!("pio_targets entry '{}' is not allowed", target)), "{}", error);
+    }
+    let config = BuildConfig { pio_targets: vec!["-e".to_string()], ..BuildConfig::default() };
+    assert!(config.validate().is_err());
+    let config = BuildConfig { pio_targets: vec!["buildfs".to_string(), "size".to_string()], ..BuildConfig::default() };
+    assert!(config.validate().is_ok());
+}
+
+#[tokio::test]
+async fn test_pio_targets_run_after_build_and_report_their_files() {
+    let tools = TempDir::new().unwrap();
+    let pio = tools.path().join("pio");
+    std::fs::write(
+        &pio,
+        "#!/bin/sh\necho \"pio $*\" >> pio.log\n\
+         case \"$*\" in\n\
+           *'-t buildfs'*) printf fs > .pio/build/esp32dev/littlefs.bin ;;\n\
+           *'-t size'*) echo 'RAM: 10%' ;;\n\
+           *'-t broken'*) echo 'no such target' >&2; exit 1 ;;\n\
+           *) mkdir -p .pio/build/esp32dev && printf fw > .pio/build/esp32dev/firmware.bin ;;\n\
+         esac\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&pio, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    std::fs::write(repo.path().join("platformio.ini"), "[env:esp32dev]\nplatform = espressif32\nboard = esp32dev\n").unwrap();
+
+    let mut config = BuildConfig {
+        preinstall_platforms: false,
+        pio_targets: vec!["buildfs".to_string(), "size".to_string()],
+        ..BuildConfig::default()
+    };
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+    assert!(result.success, "{:?}", result.error_output);
+    let targets: Vec<_> = result.targets.iter().map(|t| (t.env.as_str(), t.target.as_str(), t.success, t.artifacts.clone())).collect();
+    assert_eq!(
+        targets,
+        [
+            ("esp32dev", "buildfs", true, vec![".pio/build/esp32dev/littlefs.bin".to_string()]),
+            ("esp32dev", "size", true, Vec::new()),
+        ]
+    );
+    let filesystem = result.artifacts.iter().find(|a| a.path.ends_with("littlefs.bin")).unwrap();
+    assert_eq!(filesystem.format, "bin");
+    assert_eq!(filesystem.metadata.get("pio_target").map(String::as_str), Some("buildfs"));
+    assert!(result.output_path.unwrap().ends_with("firmware.bin"));
+    let log = std::fs::read_to_string(repo.path().join("pio.log")).unwrap();
+    assert_eq!(log.lines().collect::<Vec<_>>(), ["pio run", "pio run -e esp32dev -t buildfs", "pio run -e esp32dev -t size"]);
+
+    // A failing target fails the build, with its own outcome recorded
+    config.pio_targets = vec!["broken".to_string(), "size".to_string()];
+    let result = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap();
+    assert!(!result.success);
+    assert!(result.error_output.unwrap().contains("[esp32dev] pio run -t broken exited with"));
+    let outcomes: Vec<_> = result.targets.iter().map(|t| (t.target.as_str(), t.success)).collect();
+    assert_eq!(outcomes, [("broken", false), ("size", true)]);
+    assert!(result.targets[0].error.as_deref().unwrap().ends_with("no such target"));
+}
+
+/// What `pio run` prints when a `pre:` extra script imports a module PlatformIO's Python lacks
+const EXTRA_SCRIPT_TRACEBACK: &str = r#"Processing esp32dev (platform: espressif32; board: esp32dev; framework: arduino)
+--------------------------------------------------------------------------------
+ModuleNotFoundError: No module named 'git':
+  File "/root/.platformio/penv/lib/python3.11/site-packages/platformio/builder/main.py", line 173:
+    env.SConscript(env.GetExtraScripts("pre"), exports="env")
+  File "/root/.platformio/packages/tool-scons/scons-local-4.5.2/SCons/Script/SConscript.py", line 597:
+    return _SConscript(self.fs, *files, **subst_kw)
+  File "/src/firmware/scripts/build_version.py", line 3:
+    import git
+========================== [FAILED] Took 0.41 seconds ==========================
+"#;
+
+#[test]
+fn test_missing_python_module_in_extra_script() {
+    let missing = missing_python_module(EXTRA_SCRIPT_TRACEBACK).unwrap();
+    assert_eq!(missing.module, "git");
+    assert_eq!(missing.package(), "GitPython");
+    assert_eq!((missing.diagnostic.file.as_str(), missing.diagnostic.line), ("/src/firmware/scripts/build_version.py", 3));
+    assert_eq!(missing.diagnostic.message, "ModuleNotFoundError: No module named 'git'");
+    assert_eq!(missing.diagnostic.severity, Severity::Error);
+
+    // Python's own traceback layout, a submodule import and the Python 2 wording
+    let python = "Traceback (most recent call last):\n  File \"scripts/gen.py\", line 7, in <module>\n    import yaml.loader\nModuleNotFoundError: No module named 'yaml.loader'\n";
+    let missing = missing_python_module(python).unwrap();
+    assert_eq!((missing.module.as_str(), missing.package(), missing.diagnostic.line), ("yaml", "PyYAML", 7));
+    let python2 = "Traceback (most recent call last):\n  File \"pre.py\", line 1, in <module>\n    import intelhex\nImportError: No module named intelhex\n";
+    assert_eq!(missing_python_module(python2).unwrap().module, "intelhex");
+    assert_eq!(pip_package("intelhex"), "intelhex");
+    assert_eq!(missing_python_module("NameError: name 'env' is not defined:\n  File \"pre.py\", line 2:\n"), None);
+
+    let envs = [("PLATFORMIO_CORE_DIR".to_string(), "/cache/platformio".to_string())].into();
+    assert_eq!(penv_pip_install(&envs, "GitPython"), ["/cache/platformio/penv/bin/python", "-m", "pip", "install", "GitPython"]);
+    assert_eq!(
+        analyze_platformio_error(EXTRA_SCRIPT_TRACEBACK, &envs).unwrap(),
+        "/src/firmware/scripts/build_version.py:3 imports the Python module `git`, which is not installed in PlatformIO's Python \
+         environment; install it with `/cache/platformio/penv/bin/python -m pip install GitPython` or build in a `container` that has it"
+    );
+    assert_eq!(analyze_platformio_error("src/main.cpp:3:10: fatal error: Arduino.h: No such file or directory\n", &envs), None);
+}
+
+#[tokio::test]
+async fn test_extra_script_import_failure_names_the_script_and_install_command() {
+    let tools = TempDir::new().unwrap();
+    let pio = tools.path().join("pio");
+    std::fs::write(&pio, format!("#!/bin/sh\ncat >&2 <<'EOF'\n{}EOF\nexit 1\n", EXTRA_SCRIPT_TRACEBACK)).unwrap();
+    std::fs::set_permissions(&pio, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    std::fs::write(
+        repo.path().join("platformio.ini"),
+        "[env:esp32dev]\nplatform = espressif32\nboard = esp32dev\nextra_scripts = pre:scripts/build_version.py\n",
+    )
+    .unwrap();
+
+    let mut config = BuildConfig { preinstall_platforms: false, ..BuildConfig::default() };
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    config.command_env.insert("PLATFORMIO_CORE_DIR".to_string(), "/cache/platformio".to_string());
+    let error = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap_err();
+
+    assert!(error.to_string().contains("install it with `/cache/platformio/penv/bin/python -m pip install GitPython`"), "{}", error);
+    let failed = error.downcast_ref::<BuildStepFailed>().expect("diagnostics attached");
+    let script: Vec<_> = failed.diagnostics.iter().map(|d| (d.file.as_str(), d.line, d.tool.as_deref())).collect();
+    assert_eq!(script, [("/src/firmware/scripts/build_version.py", 3, Some("python"))]);
+}
+
+#[tokio::test]
+async fn test_platformio_progress_is_split_between_its_environments() {
+    let tools = TempDir::new().unwrap();
+    let pio = tools.path().join("pio");
+    std::fs::write(
+        &pio,
+        "#!/bin/sh\nfor env in uno nano; do\n  echo \"Processing $env (platform: atmelavr)\"\n  echo \"Compiling .pio/build/$env/src/main.cpp.o\"\n  \
+         echo \"Linking .pio/build/$env/firmware.elf\"\n  mkdir -p .pio/build/$env\n  printf hex > .pio/build/$env/firmware.hex\n  \
+         echo \"========== [SUCCESS] Took 1.00 seconds ==========\"\ndone\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&pio, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    std::fs::write(repo.path().join("platformio.ini"), "[env:uno]\nplatform = atmelavr\n\n[env:nano]\nplatform = atmelavr\n").unwrap();
+
+    let mut config = BuildConfig { preinstall_platforms: false, ..BuildConfig::default() };
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    let live_log = LiveLog::default();
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let sink = reported.clone();
+    live_log.on_progress(move |progress| sink.lock().unwrap().push(progress));
+
+    let options = RunOptions { live_log: Some(live_log), ..RunOptions::from(config) };
+    let report = FirmwareBuildRunner::new().run(repo.path(), options).await.unwrap();
+    assert!(report.result.success, "{:?}", report.result.error_output);
+    assert_eq!(*reported.lock().unwrap(), [0.0, 12.5, 37.5, 50.0, 62.5, 87.5, 100.0]);
+}
+
+#[tokio::test]
+async fn test_platform_install_is_its_own_phase_of_the_build_output() {
+    let tools = TempDir::new().unwrap();
+    let pio = tools.path().join("pio");
+    // `platform list` is broken; installing works unless INSTALL_FAILS is set
+    std::fs::write(
+        &pio,
+        "#!/bin/sh\ncase \"$1 $2\" in\n  'platform list') echo 'Error: storage is corrupt' >&2; exit 1 ;;\n  \
+         'pkg install') [ -n \"$INSTALL_FAILS\" ] && { echo 'HTTPClientError: registry unreachable' >&2; exit 1; }; exit 0 ;;\nesac\n\
+         mkdir -p .pio/build/uno && printf hex > .pio/build/uno/firmware.hex\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&pio, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let repo = TempDir::new().unwrap();
+    std::fs::write(repo.path().join("platformio.ini"), "[env:uno]\nplatform = atmelavr\nboard = uno\n").unwrap();
+
+    let mut config = BuildConfig::default();
+    let path = format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    let report = FirmwareBuildRunner::new().run(repo.path(), RunOptions::from(config.clone())).await.unwrap();
+
+    assert!(report.result.success, "{:?}", report.result.error_output);
+    let install = report.log.iter().find(|line| line.starts_with("PlatformIO platform install:")).expect("install phase logged");
+    assert!(install.contains("Could not list installed platforms (listing installed platforms failed: Error: storage is corrupt"), "{}", install);
+    assert!(install.ends_with("\nInstalled platform atmelavr"), "{}", install);
+
+    config.command_env.insert("INSTALL_FAILS".to_string(), "1".to_string());
+    let error = execute_build_with_config(repo.path(), BuildSystem::PlatformIO, &config).await.unwrap_err();
+    let failed = error.downcast_ref::<PlatformInstallFailed>().expect("an install failure, not a build failure");
+    assert!(failed.reason.contains("installing atmelavr failed: HTTPClientError: registry unreachable"), "{}", failed.reason);
+
+    let report = FirmwareBuildRunner::new().run(repo.path(), RunOptions::from(config)).await.unwrap();
+    assert!(!report.result.success);
+    assert!(report.result.error_output.unwrap().starts_with("PlatformIO platform installation failed: installing atmelavr failed"));
+    assert!(report.log.iter().any(|line| line.starts_with("PlatformIO platform install:\nCould not list installed platforms")), "{:?}", report.log);
+}
@@ -0,0 +1,141 @@
+#![cfg(target_os = "linux")]
+
+use nabla_runner::process::{
+    kill_processes_in, leaked_processes_killed, network_isolation_available, run_command_with_limits, CommandLimits,
+};
+use std::fs;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::process::Command;
+
+fn process_gone(pid: &str) -> bool {
+    // A reparented grandchild may linger as a zombie if PID 1 doesn't reap; that still counts as dead
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat.rsplit(')').next().unwrap_or("").trim_start().starts_with('Z'),
+        Err(_) => true,
+    }
+}
+
+#[tokio::test]
+async fn test_timeout_terminates_grandchildren() {
+    let temp_dir = TempDir::new().unwrap();
+    let pid_file = temp_dir.path().join("grandchild.pid");
+
+    // sh -> sh -> sleep, with the grandchild ignoring SIGTERM so escalation to SIGKILL is exercised
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(format!(
+        "sh -c 'trap \"\" TERM; sleep 300' & echo $! > {}; wait",
+        pid_file.display()
+    ));
+
+    let limits = CommandLimits {
+        timeout: Duration::from_millis(500),
+        kill_grace: Duration::from_millis(500),
+        isolate_network: false,
+    };
+
+    let started = Instant::now();
+    let result = run_command_with_limits(command, limits).await;
+    assert!(result.unwrap_err().to_string().contains("timed out"));
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let pid = fs::read_to_string(&pid_file).unwrap().trim().to_string();
+    for _ in 0..50 {
+        if process_gone(&pid) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("grandchild process {} survived build timeout", pid);
+}
+
+#[tokio::test]
+async fn test_leftover_children_killed_when_command_exits() {
+    // The forked sleep keeps stdout open, so the command only returns once it is killed
+    let mut command = Command::new("sh");
+    command.arg("-c").arg("sleep 300 & echo $!");
+
+    let limits = CommandLimits {
+        timeout: Duration::from_secs(60),
+        kill_grace: Duration::from_secs(1),
+        isolate_network: false,
+    };
+
+    let started = Instant::now();
+    let output = run_command_with_limits(command, limits).await.unwrap();
+    assert!(output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let pid = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    assert!(process_gone(&pid), "forked child {} survived the command", pid);
+    assert!(leaked_processes_killed() >= 1);
+}
+
+#[tokio::test]
+async fn test_processes_left_in_workspace_killed() {
+    let workspace = TempDir::new().unwrap();
+    // setsid takes the sleep out of the command's process group, as a daemonizing tool would
+    let mut command = Command::new("sh");
+    command
+        .current_dir(workspace.path())
+        .arg("-c")
+        .arg("setsid sleep 300 > /dev/null 2>&1 & echo $!; sleep 0.5");
+
+    let limits = CommandLimits {
+        timeout: Duration::from_secs(60),
+        kill_grace: Duration::from_secs(1),
+        isolate_network: false,
+    };
+    let output = run_command_with_limits(command, limits).await.unwrap();
+    let pid = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    assert!(!process_gone(&pid), "the detached process should outlive its process group");
+
+    assert_eq!(kill_processes_in(workspace.path()).await, 1);
+    for _ in 0..50 {
+        if process_gone(&pid) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("process {} left in the workspace survived the sweep", pid);
+}
+
+#[tokio::test]
+async fn test_command_output_collected_within_limits() {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg("echo out; echo err >&2");
+
+    let limits = CommandLimits {
+        timeout: Duration::from_secs(10),
+        kill_grace: Duration::from_secs(1),
+        isolate_network: false,
+    };
+
+    let output = run_command_with_limits(command, limits).await.unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "out\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "err\n");
+}
+
+#[tokio::test]
+async fn test_isolated_command_gets_own_network_namespace() {
+    if !network_isolation_available().await {
+        eprintln!("skipping: network namespaces not permitted here");
+        return;
+    }
+
+    let mut command = Command::new("sh");
+    command.arg("-c").arg("readlink /proc/self/ns/net; tail -n +3 /proc/net/dev | cut -d: -f1");
+    let limits = CommandLimits {
+        timeout: Duration::from_secs(10),
+        kill_grace: Duration::from_secs(1),
+        isolate_network: true,
+    };
+
+    let output = run_command_with_limits(command, limits).await.unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    assert_ne!(lines.next().unwrap(), fs::read_link("/proc/self/ns/net").unwrap().to_string_lossy());
+    let interfaces: Vec<&str> = lines.map(str::trim).collect();
+    assert_eq!(interfaces, ["lo"]);
+}
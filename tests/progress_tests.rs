@@ -0,0 +1,86 @@
+use nabla_runner::jobs::BuildJob;
+use nabla_runner::progress::{parse_ninja_marker, parse_percent_marker, ProgressTracker};
+
+const CMAKE_MAKE_OUTPUT: &str = "\
+[ 10%] Building C object CMakeFiles/app.dir/src/main.c.o
+/src/main.c:12:5: warning: unused variable 'x' [-Wunused-variable]
+   12 |     int x;
+      |     ^
+[ 45%] Building C object CMakeFiles/app.dir/src/uart.c.o
+[ 30%] Building C object CMakeFiles/hal.dir/src/gpio.c.o
+[100%] Linking C executable app.elf
+";
+
+const NINJA_OUTPUT: &str = "\
+[1/4] Building C object CMakeFiles/app.dir/main.c.obj
+[2/4] Building C object CMakeFiles/app.dir/uart.c.obj
+../uart.c: In function 'uart_init':
+../uart.c:8:3: note: [1/2] something odd
+[4/4] Linking C executable app.elf
+";
+
+const PLATFORMIO_OUTPUT: &str = "\
+Processing lolin_d32 (platform: espressif32@6.4.0; board: lolin_d32; framework: arduino)
+Building in release mode
+Compiling .pio/build/lolin_d32/src/main.cpp.o
+Linking .pio/build/lolin_d32/firmware.elf
+Building .pio/build/lolin_d32/firmware.bin
+========================= [SUCCESS] Took 12.34 seconds =========================
+Processing uno (platform: atmelavr; board: uno; framework: arduino)
+Compiling .pio/build/uno/src/main.cpp.o
+Linking .pio/build/uno/firmware.elf
+========================= [SUCCESS] Took 3.21 seconds =========================
+";
+
+fn feed_all(tracker: &mut ProgressTracker, output: &str) -> Vec<f32> {
+    output.lines().filter_map(|line| tracker.feed_line(line)).collect()
+}
+
+#[test]
+fn test_cmake_percent_is_monotonic_and_ignores_warnings() {
+    let mut tracker = ProgressTracker::new();
+    let updates = feed_all(&mut tracker, CMAKE_MAKE_OUTPUT);
+
+    assert_eq!(updates, vec![10.0, 45.0, 100.0]);
+    assert_eq!(parse_percent_marker("[ 42%] Built target hal"), Some(42.0));
+    assert_eq!(parse_percent_marker("main.c:1: [ 42%] not a marker"), None);
+}
+
+#[test]
+fn test_ninja_counters() {
+    let mut tracker = ProgressTracker::new();
+    let updates = feed_all(&mut tracker, NINJA_OUTPUT);
+
+    assert_eq!(updates, vec![25.0, 50.0, 100.0]);
+    assert_eq!(parse_ninja_marker("[3/0] bogus"), None);
+}
+
+#[test]
+fn test_platformio_scales_across_envs() {
+    let mut tracker = ProgressTracker::for_platformio(2);
+    let updates = feed_all(&mut tracker, PLATFORMIO_OUTPUT);
+
+    assert_eq!(updates[..2], [0.0, 12.5]);
+    assert!(updates.windows(2).all(|w| w[1] > w[0]));
+    assert!(updates.contains(&50.0));
+    assert_eq!(tracker.progress(), Some(100.0));
+}
+
+#[test]
+fn test_job_progress_never_goes_backwards() {
+    let mut job = BuildJob::new(
+        "https://example.com/repo.tar.gz".to_string(),
+        "owner".to_string(),
+        "repo".to_string(),
+        "1".to_string(),
+        String::new(),
+        None,
+    );
+
+    job.set_progress(40.0);
+    job.set_progress(20.0);
+    assert_eq!(job.progress, Some(40.0));
+
+    job.complete("done".to_string(), None);
+    assert_eq!(job.progress, Some(100.0));
+}
@@ -0,0 +1,143 @@
+use nabla_runner::jobs::{BuildJob, JobManager};
+use nabla_runner::prune::{execute, plan, CacheKind, CacheLock, PruneKind, PruneRequest, PruneStatus, PruneTasks, TrackedPaths};
+use nabla_runner::workspace::CustomerDirs;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tempfile::TempDir;
+
+fn write(path: &Path, bytes: usize) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, vec![b'x'; bytes]).unwrap();
+}
+
+fn job(files: &[PathBuf], finished: bool) -> BuildJob {
+    let mut job = BuildJob::new(
+        "https://example.com/fw.tar.gz".to_string(),
+        "acme".to_string(),
+        "fw".to_string(),
+        "123".to_string(),
+        String::new(),
+        None,
+    );
+    job.files.extend(files.iter().cloned());
+    job.start();
+    if finished {
+        job.complete(String::new(), None);
+    }
+    job
+}
+
+/// A finished job's workspace and log, a running job's, an orphaned workspace and a cache
+fn fabricate(root: &Path) -> (CustomerDirs, JobManager) {
+    let dirs = CustomerDirs::new(root, "acme", false);
+    write(&dirs.job_workspace("done").join("repo/src/main.c"), 100);
+    write(&dirs.job_workspace("done").join("out/firmware.bin"), 20);
+    write(&dirs.job_workspace("running").join("repo/main.c"), 400);
+    write(&dirs.job_workspace("orphan").join("repo/a/b/c.o"), 300);
+    write(&dirs.root().join("logs/done.log"), 10);
+    write(&dirs.root().join("logs/running.log"), 7);
+    write(&dirs.cache_root().join("platformio/packages/toolchain.tar"), 50);
+    fs::create_dir_all(dirs.root().join("not-a-job")).unwrap();
+
+    let mut jobs = JobManager::default();
+    jobs.insert(job(&[dirs.job_workspace("done"), dirs.root().join("logs/done.log")], true));
+    jobs.insert(job(&[dirs.job_workspace("running"), dirs.root().join("logs/running.log")], false));
+    (dirs, jobs)
+}
+
+fn everything() -> PruneRequest {
+    PruneRequest {
+        completed_workspaces: true,
+        orphaned_workspaces: true,
+        build_logs: true,
+        caches: vec![CacheKind::Platformio, CacheKind::Ccache, CacheKind::Platformio],
+        ..PruneRequest::default()
+    }
+}
+
+#[tokio::test]
+async fn test_plan_selects_finished_and_orphaned_entries_with_their_size() {
+    let root = TempDir::new().unwrap();
+    let (dirs, jobs) = fabricate(root.path());
+    let tracked = TrackedPaths::of(&jobs);
+
+    let selected = plan(&dirs, &everything(), &tracked, &CacheLock::default()).await;
+    let entries: Vec<_> = selected.entries.iter().map(|entry| (entry.path.clone(), entry.kind, entry.bytes)).collect();
+    assert_eq!(
+        entries,
+        [
+            (dirs.job_workspace("done"), PruneKind::CompletedWorkspace, 120),
+            (dirs.job_workspace("orphan"), PruneKind::OrphanedWorkspace, 300),
+            (dirs.root().join("logs/done.log"), PruneKind::BuildLog, 10),
+            (dirs.cache_root().join("platformio"), PruneKind::Cache, 50),
+        ]
+    );
+    assert_eq!(selected.total_bytes(), 480);
+    let skipped: Vec<_> = selected.skipped.iter().map(|entry| entry.path.clone()).collect();
+    assert_eq!(skipped, [dirs.job_workspace("running"), dirs.root().join("logs/running.log")]);
+
+    // Only what was asked for
+    let orphans = PruneRequest { orphaned_workspaces: true, ..PruneRequest::default() };
+    let selected = plan(&dirs, &orphans, &tracked, &CacheLock::default()).await;
+    assert_eq!(selected.entries.len(), 1);
+    assert_eq!(selected.entries[0].path, dirs.job_workspace("orphan"));
+
+    // Nothing was modified an hour ago yet; caches aren't filtered by age
+    let old = PruneRequest { older_than_secs: Some(3600), ..everything() };
+    let selected = plan(&dirs, &old, &tracked, &CacheLock::default()).await;
+    let kinds: Vec<_> = selected.entries.iter().map(|entry| entry.kind).collect();
+    assert_eq!(kinds, [PruneKind::Cache]);
+
+    // A running build holds the caches; shared caches may be in use by other runners
+    let caches = CacheLock::default();
+    let held = caches.hold().await;
+    let selected = plan(&dirs, &everything(), &tracked, &caches).await;
+    assert!(selected.entries.iter().all(|entry| entry.kind != PruneKind::Cache));
+    assert_eq!(selected.skipped.last().unwrap().reason, "a running build uses it");
+    drop(held);
+    let shared = CustomerDirs::new(root.path(), "acme", true);
+    write(&shared.cache_root().join("ccache/0/1.o"), 5);
+    let selected = plan(&shared, &everything(), &tracked, &caches).await;
+    assert!(selected.entries.iter().all(|entry| entry.kind != PruneKind::Cache));
+    assert_eq!(selected.skipped.last().unwrap().path, shared.cache_root().join("ccache"));
+}
+
+#[tokio::test]
+async fn test_execute_removes_selection_and_rechecks_running_jobs() {
+    let root = TempDir::new().unwrap();
+    let (dirs, jobs) = fabricate(root.path());
+    let selected = plan(&dirs, &everything(), &TrackedPaths::of(&jobs), &CacheLock::default()).await;
+
+    // A new job reuses the orphaned workspace after the plan was made
+    let jobs = RwLock::new(jobs);
+    jobs.write().unwrap().insert(job(&[dirs.job_workspace("orphan")], false));
+
+    let tasks = PruneTasks::default();
+    let task_id = tasks.register(&selected);
+    assert_eq!(tasks.get(task_id).unwrap().status, PruneStatus::Running);
+    execute(selected, task_id, &tasks, &jobs, &CacheLock::default()).await;
+
+    let progress = tasks.get(task_id).unwrap();
+    assert_eq!(progress.status, PruneStatus::Completed);
+    assert_eq!((progress.planned_entries, progress.planned_bytes), (4, 480));
+    assert_eq!((progress.removed_entries, progress.freed_bytes), (3, 180));
+    let skipped: Vec<_> = progress.skipped.iter().map(|entry| (entry.path.clone(), entry.reason.as_str())).collect();
+    assert_eq!(
+        skipped,
+        [
+            (dirs.job_workspace("running"), "a queued or running job uses it"),
+            (dirs.root().join("logs/running.log"), "the job writing it is running"),
+            (dirs.job_workspace("orphan"), "a job started using it"),
+        ]
+    );
+
+    assert!(!dirs.job_workspace("done").exists());
+    assert!(!dirs.root().join("logs/done.log").exists());
+    assert!(dirs.job_workspace("orphan").join("repo/a/b/c.o").exists());
+    assert!(dirs.job_workspace("running").join("repo/main.c").exists());
+    assert!(dirs.root().join("not-a-job").exists());
+    // Emptied, but left for the next build
+    let cache = dirs.cache_root().join("platformio");
+    assert!(cache.is_dir() && fs::read_dir(&cache).unwrap().next().is_none());
+}
@@ -0,0 +1,62 @@
+use nabla_runner::quota::{QuotaError, QuotaLimits, QuotaTracker, RateLimiter};
+use std::time::Duration;
+
+#[test]
+fn test_customer_over_concurrent_limit_rejected_while_other_proceeds() {
+    let tracker = QuotaTracker::new(QuotaLimits {
+        max_concurrent_jobs: Some(1),
+        max_workspace_bytes: None,
+    });
+
+    let acme_job = tracker.try_acquire("acme", 0).unwrap();
+
+    let rejected = tracker.try_acquire("acme", 0).unwrap_err();
+    assert!(matches!(rejected, QuotaError::ConcurrentJobs { active: 1, limit: 1, .. }));
+
+    let globex_job = tracker.try_acquire("globex", 0);
+    assert!(globex_job.is_ok());
+
+    // Finishing the first job frees the slot again
+    drop(acme_job);
+    assert_eq!(tracker.active_jobs("acme"), 0);
+    assert!(tracker.try_acquire("acme", 0).is_ok());
+}
+
+#[test]
+fn test_disk_quota_rejects_before_taking_a_slot() {
+    let tracker = QuotaTracker::new(QuotaLimits {
+        max_concurrent_jobs: None,
+        max_workspace_bytes: Some(1024),
+    });
+
+    let err = tracker.try_acquire("acme", 4096).unwrap_err();
+    assert_eq!(
+        err,
+        QuotaError::DiskQuota { customer_id: "acme".to_string(), used: 4096, limit: 1024 }
+    );
+    assert_eq!(tracker.active_jobs("acme"), 0);
+
+    assert!(tracker.try_acquire("acme", 512).is_ok());
+}
+
+#[test]
+fn test_unlimited_by_default() {
+    let tracker = QuotaTracker::new(QuotaLimits::default());
+    let guards: Vec<_> = (0..5).map(|_| tracker.try_acquire("acme", u64::MAX).unwrap()).collect();
+    assert_eq!(tracker.active_jobs("acme"), 5);
+    drop(guards);
+}
+
+#[test]
+fn test_rate_limiter_frees_requests_as_the_window_passes() {
+    let limiter = RateLimiter::new(2, Duration::from_millis(200));
+
+    assert!(limiter.try_acquire().is_ok());
+    assert!(limiter.try_acquire().is_ok());
+    let limited = limiter.try_acquire().unwrap_err();
+    assert_eq!(limited.limit, 2);
+    assert!(limited.retry_after <= Duration::from_millis(200));
+
+    std::thread::sleep(Duration::from_millis(250));
+    assert!(limiter.try_acquire().is_ok());
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "redis")]
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use nabla_runner::server::create_app;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tower::util::ServiceExt;
+
+/// Just enough of a Redis server for a client to connect and PUBLISH. Other commands the
+/// client sends on connect get `+OK`. Returns the server's URL and the `(channel, payload)` of
+/// every PUBLISH it receives.
+async fn fake_redis_server() -> (String, Arc<Mutex<Vec<(String, Value)>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("redis://{}", listener.local_addr().unwrap());
+    let published = Arc::new(Mutex::new(Vec::new()));
+
+    let received = published.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let received = received.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = socket.into_split();
+                let mut reader = BufReader::new(reader);
+
+                let mut line = String::new();
+                while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+                    let count: usize = line.trim_end().trim_start_matches('*').parse().unwrap();
+                    let mut args = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        line.clear();
+                        reader.read_line(&mut line).await.unwrap();
+                        let len: usize = line.trim_end().trim_start_matches('$').parse().unwrap();
+                        let mut arg = vec![0; len + 2];
+                        reader.read_exact(&mut arg).await.unwrap();
+                        arg.truncate(len);
+                        args.push(arg);
+                    }
+
+                    if args[0].eq_ignore_ascii_case(b"PUBLISH") {
+                        let channel = String::from_utf8(args[1].clone()).unwrap();
+                        let event = serde_json::from_slice(&args[2]).unwrap();
+                        received.lock().push((channel, event));
+                        writer.write_all(b":1\r\n").await.unwrap();
+                    } else {
+                        writer.write_all(b"+OK\r\n").await.unwrap();
+                    }
+                    line.clear();
+                }
+            });
+        }
+    });
+
+    (url, published)
+}
+
+fn build_request(job_id: &str) -> Request<Body> {
+    let project = tempfile::TempDir::new().unwrap();
+    std::fs::write(project.path().join("Makefile"), "app.bin:\n\techo built > app.bin\n").unwrap();
+    let archive = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg("-")
+        .arg("-C")
+        .arg(project.path())
+        .arg(".")
+        .output()
+        .unwrap();
+
+    let boundary = "redis-events-boundary";
+    let metadata = json!({"job_id": job_id, "owner": "acme", "repo": "firmware", "installation_id": "123"});
+    let mut body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{metadata}\r\n\
+         --{boundary}\r\nContent-Disposition: form-data; name=\"archive\"; filename=\"repo\"\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(&archive.stdout);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    Request::builder()
+        .method("POST")
+        .uri("/build")
+        .header("content-type", format!("multipart/form-data; boundary={boundary}"))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_completed_build_published_to_redis_with_job() {
+    let (url, published) = fake_redis_server().await;
+    std::env::set_var("NABLA_EVENT_BUS_URL", &url);
+    let app = create_app();
+
+    let job_id = format!("redis-events-{}", uuid::Uuid::new_v4());
+    let response = app.oneshot(build_request(&job_id)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut events = Vec::new();
+    for _ in 0..500 {
+        events = published.lock().clone();
+        if events.iter().any(|(channel, _)| channel == "nabla.builds.completed") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let channels: Vec<&str> = events.iter().map(|(channel, _)| channel.as_str()).collect();
+    assert_eq!(channels.first(), Some(&"nabla.builds.queued"));
+    assert_eq!(channels.last(), Some(&"nabla.builds.completed"));
+
+    let (_, completed) = events.last().unwrap();
+    assert_eq!(completed["event"], "completed");
+    assert_eq!(completed["client_job_id"], job_id.as_str());
+    assert_eq!(completed["job"]["status"], "Completed");
+    assert_eq!(completed["job"]["id"], completed["job_id"]);
+    assert_eq!(events[0].1["job"]["status"], "Queued");
+}
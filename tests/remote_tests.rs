@@ -0,0 +1,514 @@
+use axum::{
+    body::Body,
+    extract::{Path as UrlPath, RawQuery, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    response::Redirect,
+    routing::get,
+    Json, Router,
+};
+use nabla_runner::remote::{download_archive, fetch_repository, http_client, ArchiveAuthorization, ArchiveFetchError, AuthScheme, GithubApi, GithubArchive};
+use nabla_runner::server::create_app;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use tempfile::TempDir;
+use tower::util::ServiceExt;
+
+const CUBEMX_MAKEFILE: &str = "TARGET = blinky\nPREFIX = arm-none-eabi-\nC_SOURCES = Drivers/STM32F4xx_HAL_Driver/Src/stm32f4xx_hal.c\n";
+
+const SENSOR_INI: &str = "[env:esp32dev]\nplatform = espressif32\nboard = esp32dev\nframework = arduino\n\n[env:wrover]\nplatform = espressif32\nboard = esp-wrover-kit\nframework = arduino\n";
+
+fn blob(path: &str) -> Value {
+    json!({"path": path, "type": "blob"})
+}
+
+fn tree(path: &str) -> Value {
+    json!({"path": path, "type": "tree"})
+}
+
+/// The tree listing and file contents of each `acme/<repo>` the mock serves
+fn fixture(repo: &str) -> Option<(Value, &'static [(&'static str, &'static str)])> {
+    match repo {
+        "fw" => Some((
+            json!([
+                blob("Makefile"),
+                blob("README.md"),
+                tree("Core"),
+                tree("Core/Src"),
+                blob("Core/Src/main.c"),
+                blob("Drivers/STM32F4xx_HAL_Driver/Src/Legacy/deep/stm32f4xx_hal.c"),
+                blob("../escape"),
+            ]),
+            &[("Makefile", CUBEMX_MAKEFILE)],
+        )),
+        "sensor" => Some((
+            json!([blob("platformio.ini"), tree("src"), blob("src/main.cpp")]),
+            &[("platformio.ini", SENSOR_INI)],
+        )),
+        "docs" => Some((json!([blob("README.md"), tree("guides"), blob("guides/setup.md")]), &[])),
+        // Listed slowly, so requests for it overlap
+        "slow" => Some((json!([blob("Makefile")]), &[("Makefile", "all:\n\ttouch firmware.bin\n")])),
+        _ => None,
+    }
+}
+
+struct MockGithub {
+    endpoint: String,
+    /// `<repo> tree <ref>` and `<repo> contents <path> <query>`, in arrival order
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockGithub {
+    fn requests_for(&self, repo: &str) -> Vec<String> {
+        self.requests.lock().iter().filter(|r| r.starts_with(&format!("{} ", repo))).cloned().collect()
+    }
+}
+
+/// One mock GitHub API per test binary, served from its own thread so it outlives each test's
+/// runtime; `NABLA_GITHUB_API_URL` points at it
+fn mock_github() -> &'static MockGithub {
+    static MOCK: OnceLock<MockGithub> = OnceLock::new();
+    MOCK.get_or_init(|| {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route(
+                "/repos/acme/:repo/git/trees/:reference",
+                get(|State(requests): State<Arc<Mutex<Vec<String>>>>, UrlPath((repo, reference)): UrlPath<(String, String)>| async move {
+                    requests.lock().push(format!("{} tree {}", repo, reference));
+                    if repo == "slow" {
+                        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                    }
+                    match fixture(&repo) {
+                        // "huge" stands in for a repository too big for one listing
+                        Some((tree, _)) => Ok(Json(json!({"truncated": false, "tree": tree}))),
+                        None if repo == "huge" => Ok(Json(json!({"truncated": true, "tree": [blob("Makefile")]}))),
+                        None => Err(StatusCode::NOT_FOUND),
+                    }
+                }),
+            )
+            .route(
+                "/repos/acme/:repo/contents/*path",
+                get(|State(requests): State<Arc<Mutex<Vec<String>>>>, UrlPath((repo, path)): UrlPath<(String, String)>, RawQuery(query): RawQuery| async move {
+                    requests.lock().push(format!("{} contents {} {}", repo, path, query.unwrap_or_default()));
+                    let (_, files) = fixture(&repo).ok_or(StatusCode::NOT_FOUND)?;
+                    files.iter().find(|(name, _)| *name == path).map(|(_, content)| *content).ok_or(StatusCode::NOT_FOUND)
+                }),
+            )
+            .with_state(requests.clone());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await.unwrap()
+            })
+        });
+        std::env::set_var("NABLA_GITHUB_API_URL", &endpoint);
+        MockGithub { endpoint, requests }
+    })
+}
+
+async fn post(uri: &str, body: Value) -> (StatusCode, Value) {
+    post_to(create_app(), uri, body).await
+}
+
+async fn post_to(app: Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[test]
+fn test_github_archive_urls_parsed() {
+    let parsed = |url: &str| GithubArchive::parse(url).map(|archive| archive.to_string());
+
+    assert_eq!(parsed("https://github.com/acme/fw/archive/refs/heads/main.tar.gz").as_deref(), Some("acme/fw@main"));
+    assert_eq!(parsed("https://github.com/acme/fw/archive/v1.2.zip").as_deref(), Some("acme/fw@v1.2"));
+    assert_eq!(parsed("https://codeload.github.com/acme/fw/tar.gz/refs/tags/v1.2").as_deref(), Some("acme/fw@v1.2"));
+    assert_eq!(parsed("https://api.github.com/repos/acme/fw/tarball").as_deref(), Some("acme/fw"));
+    assert_eq!(parsed("https://api.github.com/repos/acme/fw/zipball/feature/x?token=abc").as_deref(), Some("acme/fw@feature/x"));
+
+    assert_eq!(parsed("https://example.com/acme/fw/archive/main.tar.gz"), None);
+    assert_eq!(parsed("https://github.com/acme/fw/releases/download/v1/fw.tar.gz"), None);
+    assert_eq!(parsed("https://github.com/acme/fw/archive/.tar.gz"), None);
+}
+
+#[tokio::test]
+async fn test_dry_run_detects_from_file_listing() {
+    let mock = mock_github();
+
+    // github.com is never contacted: a download attempt would fail the request
+    let (status, json) = post(
+        "/build",
+        json!({
+            "job_id": "remote-dry-run",
+            "archive_url": "https://github.com/acme/fw/archive/refs/heads/main.tar.gz",
+            "owner": "acme",
+            "repo": "fw",
+            "installation_id": "123",
+            "dry_run": true,
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "completed", "{}", json);
+    assert_eq!(json["dry_run"]["build_system"], "Makefile", "{}", json);
+    assert_eq!(json["dry_run"]["flavor"], "stm32_cubemx", "{}", json);
+    assert!(json["build_output"].as_str().unwrap().contains("archive not downloaded"), "{}", json);
+    assert_eq!(mock.requests_for("fw"), ["fw tree main", "fw contents Makefile ref=main"]);
+}
+
+#[tokio::test]
+async fn test_concurrent_identical_requests_share_one_build() {
+    let mock = mock_github();
+    let request = |job_id: &str, deduplicate: Option<bool>| {
+        let mut request = json!({
+            "job_id": job_id,
+            "archive_url": "https://github.com/acme/slow/archive/refs/heads/main.tar.gz",
+            "owner": "acme",
+            "repo": "slow",
+            "installation_id": "123",
+            "dry_run": true,
+        });
+        if let Some(deduplicate) = deduplicate {
+            request["deduplicate"] = json!(deduplicate);
+        }
+        request
+    };
+    let listings = || mock.requests_for("slow").iter().filter(|r| r.contains(" tree ")).count();
+
+    let app = create_app();
+    let ((first_status, first), (second_status, second)) = tokio::join!(
+        post_to(app.clone(), "/build", request("dedup-first", None)),
+        post_to(app.clone(), "/build", request("dedup-retry", None)),
+    );
+    assert_eq!((first_status, second_status), (StatusCode::OK, StatusCode::OK));
+    assert_eq!((&first["status"], &second["status"]), (&json!("completed"), &json!("completed")), "{} {}", first, second);
+    assert_eq!(first["job_id"], second["job_id"]);
+    let marked: Vec<bool> = [&first, &second].iter().map(|json| json.get("deduplicated") == Some(&json!(true))).collect();
+    assert_eq!(marked.iter().filter(|&&marked| marked).count(), 1, "{} {}", first, second);
+    assert_eq!(listings(), 1);
+
+    // Opted out, each request builds; finished, a build isn't shared without a freshness window
+    let ((_, first), (_, second)) = tokio::join!(
+        post_to(app.clone(), "/build", request("dedup-first", Some(false))),
+        post_to(app.clone(), "/build", request("dedup-retry", Some(false))),
+    );
+    assert_ne!(first["job_id"], second["job_id"]);
+    assert_eq!(listings(), 3);
+    let (_, again) = post_to(app, "/build", request("dedup-again", None)).await;
+    assert!(again.get("deduplicated").is_none(), "{}", again);
+    assert_eq!(listings(), 4);
+
+    std::env::set_var("NABLA_DEDUP_FRESH_SECS", "60");
+    let app = create_app();
+    std::env::remove_var("NABLA_DEDUP_FRESH_SECS");
+    let (_, built) = post_to(app.clone(), "/build", request("dedup-fresh", None)).await;
+    let (_, served) = post_to(app, "/build", request("dedup-fresh-retry", None)).await;
+    assert_eq!(built["job_id"], served["job_id"]);
+    assert_eq!(served["deduplicated"], true, "{}", served);
+    assert_eq!(listings(), 5);
+}
+
+#[tokio::test]
+async fn test_truncated_listing_is_not_used() {
+    let api = GithubApi {
+        base_url: mock_github().endpoint.clone(),
+        token: None,
+    };
+    let archive = GithubArchive::parse("https://codeload.github.com/acme/huge/tar.gz/main").unwrap();
+    let dest = TempDir::new().unwrap();
+
+    let err = api.fetch_skeleton(&http_client().unwrap(), &archive, &dest.path().join("repo")).await.unwrap_err();
+
+    assert!(err.to_string().contains("truncated"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_detect_reports_platformio_metadata() {
+    mock_github();
+
+    let (status, json) = post(
+        "/detect",
+        json!({"archive_url": "https://codeload.github.com/acme/sensor/tar.gz/main", "installation_id": "123"}),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["status"], "detected");
+    assert_eq!(json["build_system"], "PlatformIO");
+    assert_eq!(json["candidates"], json!([{"build_system": "PlatformIO", "markers": ["platformio.ini"]}]));
+    assert_eq!(json["environments"], json!(["esp32dev", "wrover"]));
+    assert_eq!(json["default_environments"], json!(["esp32dev", "wrover"]));
+    assert_eq!(json["boards"], json!(["esp32dev", "esp-wrover-kit"]));
+    assert_eq!(json["target_arch"], "xtensa");
+    assert!(json.get("job_id").is_none(), "{}", json);
+}
+
+#[tokio::test]
+async fn test_detect_undetectable_repository() {
+    mock_github();
+
+    let (status, json) = post(
+        "/detect",
+        json!({"archive_url": "https://github.com/acme/docs/archive/main.zip", "installation_id": "123"}),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["status"], "undetected", "{}", json);
+    assert!(json.get("build_system").is_none(), "{}", json);
+}
+
+#[tokio::test]
+async fn test_detect_rejects_non_https_url() {
+    let (status, json) = post("/detect", json!({"archive_url": "http://example.com/a.tar.gz", "installation_id": "123"})).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["status"], "error");
+}
+
+/// An archive host requiring `Bearer s3cret` at `/private.tar.gz`, with redirects to it from
+/// the same host and from another one (`localhost` instead of `127.0.0.1`). Every request's
+/// path and Authorization header are recorded.
+async fn private_archive_host() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let record = |requests: &Arc<Mutex<Vec<String>>>, path: &str, headers: &HeaderMap| {
+        let auth = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).unwrap_or("-");
+        requests.lock().push(format!("{} {}", path, auth));
+    };
+
+    let app = Router::new()
+        .route(
+            "/private.tar.gz",
+            get(move |State(requests): State<Arc<Mutex<Vec<String>>>>, headers: HeaderMap| async move {
+                record(&requests, "private", &headers);
+                match headers.get(header::AUTHORIZATION).is_some_and(|v| v == "Bearer s3cret") {
+                    true => Ok("archive bytes"),
+                    false => Err(StatusCode::UNAUTHORIZED),
+                }
+            }),
+        )
+        .route(
+            "/same-host",
+            get(move |State(requests): State<Arc<Mutex<Vec<String>>>>, headers: HeaderMap| async move {
+                record(&requests, "same-host", &headers);
+                Redirect::temporary("/private.tar.gz")
+            }),
+        )
+        .route(
+            "/cross-host",
+            get(move |State(requests): State<Arc<Mutex<Vec<String>>>>, headers: HeaderMap| async move {
+                record(&requests, "cross-host", &headers);
+                Redirect::temporary(&format!("http://localhost:{}/private.tar.gz", port))
+            }),
+        )
+        .route(
+            "/loop",
+            get(move |State(requests): State<Arc<Mutex<Vec<String>>>>, headers: HeaderMap| async move {
+                record(&requests, "loop", &headers);
+                Redirect::temporary("/loop")
+            }),
+        )
+        .with_state(requests.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://127.0.0.1:{}", port), requests)
+}
+
+#[tokio::test]
+async fn test_archive_download_sends_authorization_to_its_host_only() {
+    let (base, requests) = private_archive_host().await;
+    let dir = TempDir::new().unwrap();
+    let dest = dir.path().join("archive.tar.gz");
+    let auth = ArchiveAuthorization::new(AuthScheme::Bearer, "s3cret");
+    let client = http_client().unwrap();
+
+    download_archive(&client, &format!("{}/private.tar.gz", base), Some(&auth), &dest).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), "archive bytes");
+    let error = download_archive(&client, &format!("{}/private.tar.gz", base), None, &dest).await.unwrap_err();
+    assert!(error.to_string().contains("401"), "{}", error);
+
+    // Redirects within the host keep the header
+    download_archive(&client, &format!("{}/same-host", base), Some(&auth), &dest).await.unwrap();
+
+    // Another host never sees it
+    let error = download_archive(&client, &format!("{}/cross-host", base), Some(&auth), &dest).await.unwrap_err();
+    assert!(error.to_string().contains("401"), "{}", error);
+    assert_eq!(
+        requests.lock().drain(..).collect::<Vec<_>>(),
+        [
+            "private Bearer s3cret",
+            "private -",
+            "same-host Bearer s3cret",
+            "private Bearer s3cret",
+            "cross-host Bearer s3cret",
+            "private -",
+        ]
+    );
+
+    // At most three redirects are followed
+    let error = download_archive(&client, &format!("{}/loop", base), Some(&auth), &dest).await.unwrap_err();
+    assert!(error.to_string().contains("more than 3 redirects"), "{}", error);
+    assert_eq!(requests.lock().len(), 4);
+    assert!(!format!("{:?}", auth).contains("s3cret"));
+}
+
+/// An archive host that keeps connections alive, counting the connections it accepts and
+/// recording each request's User-Agent
+async fn keep_alive_host() -> (String, Arc<Mutex<Vec<String>>>, Arc<AtomicUsize>) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/repo.tar.gz", listener.local_addr().unwrap());
+    let agents = Arc::new(Mutex::new(Vec::new()));
+    let connections = Arc::new(AtomicUsize::new(0));
+    let (recorded, counted) = (agents.clone(), connections.clone());
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            counted.fetch_add(1, Ordering::SeqCst);
+            let agents = recorded.clone();
+            tokio::spawn(async move {
+                let (read, mut write) = socket.into_split();
+                let mut lines = BufReader::new(read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(agent) = line.to_ascii_lowercase().strip_prefix("user-agent: ") {
+                        agents.lock().push(agent.to_string());
+                    }
+                    if line.is_empty() {
+                        let reply = "HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\narchive bytes";
+                        if write.write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    (url, agents, connections)
+}
+
+#[tokio::test]
+async fn test_archive_downloads_reuse_pooled_connections() {
+    let (url, agents, connections) = keep_alive_host().await;
+    let dir = TempDir::new().unwrap();
+    let client = http_client().unwrap();
+
+    for build in ["first", "second", "third"] {
+        let dest = dir.path().join(format!("{}.tar.gz", build));
+        download_archive(&client, &url, None, &dest).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "archive bytes");
+    }
+
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    assert_eq!(*agents.lock(), ["nabla-runner/0.1.0"; 3]);
+}
+
+#[tokio::test]
+async fn test_archive_auth_secret_ref_must_name_a_secret() {
+    let request = |archive_auth: Value| {
+        json!({
+            "job_id": "private-archive",
+            "archive_url": "https://gitlab.example.com/api/v4/projects/1/repository/archive.tar.gz",
+            "owner": "acme",
+            "repo": "fw",
+            "installation_id": "123",
+            "build_config": {"secret_env": {"GITLAB_TOKEN": "glpat-123"}},
+            "archive_auth": archive_auth,
+        })
+    };
+
+    let (status, json) = post("/build", request(json!({"scheme": "Bearer", "secret_ref": "GITHUB_TOKEN"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json["message"].as_str().unwrap().contains("'GITHUB_TOKEN' is not in build_config.secret_env"), "{}", json);
+
+    let (status, json) = post("/build", request(json!({"scheme": "token", "secret_ref": "GITLAB_TOKEN", "value": "glpat-123"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json["message"].as_str().unwrap().contains("exactly one of"), "{}", json);
+
+    let (status, _) = post("/build", request(json!({"scheme": "Basic", "value": "glpat-123"}))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+/// A gzipped tarball of `dir`'s `repo-main` directory
+fn tarball(dir: &std::path::Path) -> Vec<u8> {
+    let archive = dir.join("repo.tar.gz");
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(dir)
+        .arg("repo-main")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::fs::read(archive).unwrap()
+}
+
+#[tokio::test]
+async fn test_unusable_archive_downloads_report_what_the_host_sent() {
+    let work = TempDir::new().unwrap();
+    std::fs::create_dir(work.path().join("repo-main")).unwrap();
+    let empty = tarball(work.path());
+    std::fs::create_dir(work.path().join("repo-main/src")).unwrap();
+    std::fs::write(work.path().join("repo-main/src/main.c"), "int main(void) { return 0; }\n".repeat(200)).unwrap();
+    let full = tarball(work.path());
+    let truncated = full[..full.len() / 2].to_vec();
+
+    let gzip = |bytes: Vec<u8>| ([(header::CONTENT_TYPE, "application/x-gzip")], bytes);
+    let app = Router::new()
+        .route(
+            "/expired.tar.gz",
+            get(|| async { ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], "<!DOCTYPE html><html>Not Found</html>") }),
+        )
+        .route("/empty.tar.gz", get(move || async move { gzip(empty) }))
+        .route("/truncated.tar.gz", get(move || async move { gzip(truncated) }))
+        .route("/full.tar.gz", get(move || async move { gzip(full) }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = http_client().unwrap();
+
+    let fetch = |name: &str| {
+        let workspace = TempDir::new().unwrap();
+        let url = format!("{}/{}", base, name);
+        let client = client.clone();
+        async move {
+            let result = fetch_repository(&client, &url, None, workspace.path()).await;
+            assert!(!workspace.path().join("temp_repo.tar.gz").exists());
+            result.map(|repo| repo.join("src/main.c").is_file())
+        }
+    };
+
+    assert!(fetch("full.tar.gz").await.unwrap());
+
+    let error = fetch("expired.tar.gz").await.unwrap_err();
+    let fetch_error = error.downcast_ref::<ArchiveFetchError>().unwrap();
+    assert_eq!(fetch_error.reason, "the download is not a zip or tar.gz archive");
+    assert_eq!(fetch_error.status, 200);
+    assert_eq!(fetch_error.content_type.as_deref(), Some("text/html; charset=utf-8"));
+    assert_eq!(fetch_error.first_bytes, "3c21444f43545950452068746d6c3e3c");
+    assert!(error.to_string().contains("content-type text/html; charset=utf-8, first bytes 3c21"), "{}", error);
+
+    let error = fetch("empty.tar.gz").await.unwrap_err();
+    let fetch_error = error.downcast_ref::<ArchiveFetchError>().unwrap();
+    assert_eq!(fetch_error.reason, "the archive holds no files");
+    assert!(fetch_error.first_bytes.starts_with("1f8b"), "{}", fetch_error.first_bytes);
+
+    let error = fetch("truncated.tar.gz").await.unwrap_err();
+    let fetch_error = error.downcast_ref::<ArchiveFetchError>().unwrap();
+    assert!(fetch_error.reason.starts_with("the archive could not be extracted:"), "{}", fetch_error.reason);
+    assert_eq!(fetch_error.content_type.as_deref(), Some("application/x-gzip"));
+}
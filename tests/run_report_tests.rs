@@ -0,0 +1,165 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::diagnostics::Severity;
+use nabla_runner::events::BuildPhase;
+use nabla_runner::{FirmwareBuildRunner, RunOptions};
+use parking_lot::Mutex;
+use std::fs;
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn test_run_reports_successful_build() {
+    let dir = TempDir::new().unwrap();
+    let project = dir.path().join("firmware-main");
+    fs::create_dir(&project).unwrap();
+    fs::write(
+        project.join("Makefile"),
+        ".PHONY: firmware\nfirmware:\n\t@echo 'main.c:3:5: warning: unused variable [-Wunused-variable]' >&2\n\techo built > firmware\n",
+    )
+    .unwrap();
+
+    let phases = Mutex::new(Vec::new());
+    let report = FirmwareBuildRunner::new()
+        .run_with_progress(dir.path(), &RunOptions::default(), |phase| phases.lock().push(phase))
+        .await
+        .unwrap();
+
+    assert_eq!(*phases.lock(), [BuildPhase::Detect, BuildPhase::Build]);
+    assert_eq!(report.repo_dir, project);
+    assert_eq!(report.build_system, BuildSystem::Makefile);
+    assert!(report.result.success, "{:?}", report.result.error_output);
+    assert_eq!(report.result.artifacts.len(), 1);
+    assert_eq!(report.result.warning_count, 1);
+    assert_eq!(report.result.diagnostics[0].severity, Severity::Warning);
+    assert!(report.log.iter().any(|line| line.starts_with("No build system at archive root")), "{:?}", report.log);
+    assert!(report.log.iter().any(|line| line == "Detected build system: Makefile"), "{:?}", report.log);
+    assert!(report.log.last().unwrap().starts_with("Build completed successfully. Artifact: "), "{:?}", report.log);
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert!(json["timings"]["build_ms"].is_u64(), "{}", json);
+    assert_eq!(json["result"]["build_system"], "Makefile");
+}
+
+#[tokio::test]
+async fn test_run_reports_failed_build_with_diagnostics() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("Makefile"),
+        "firmware:\n\t@echo \"main.c:7:3: error: 'undeclared_symbol' undeclared\" >&2\n\t@false\n",
+    )
+    .unwrap();
+
+    let report = FirmwareBuildRunner::new()
+        .run(dir.path(), RunOptions::from(BuildConfig::default()))
+        .await
+        .unwrap();
+
+    assert_eq!(report.build_system, BuildSystem::Makefile);
+    assert!(!report.result.success);
+    assert!(report.result.error_output.as_deref().unwrap().contains("Make build failed"));
+    assert_eq!(report.result.error_count, 1);
+    assert!(report.result.diagnostics[0].message.contains("undeclared_symbol"));
+    assert!(report.log.last().unwrap().starts_with("Build failed: "), "{:?}", report.log);
+}
+
+#[tokio::test]
+async fn test_run_with_explicit_build_system_and_undetected_repo() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("README.md"), "nothing to build\n").unwrap();
+
+    let runner = FirmwareBuildRunner::new();
+    let error = runner.run(dir.path(), RunOptions::default()).await.unwrap_err();
+    assert!(error.to_string().contains("Unsupported or undetected build system"));
+
+    let options = RunOptions {
+        build_system: Some(BuildSystem::Yocto),
+        ..RunOptions::default()
+    };
+    let report = runner.run(dir.path(), options).await.unwrap();
+    assert_eq!(report.build_system, BuildSystem::Yocto);
+    assert!(!report.result.success);
+}
+
+#[tokio::test]
+async fn test_dry_run_reports_missing_tools_without_building() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("platformio.ini"), "[platformio]\ndefault_envs = esp32\n\n[env:native]\nplatform = native\n").unwrap();
+
+    let empty = TempDir::new().unwrap();
+    let mut options = RunOptions::default();
+    options.config.command_env.insert("PATH".to_string(), empty.path().display().to_string());
+    let report = FirmwareBuildRunner::new().dry_run(dir.path(), &options).await.unwrap();
+
+    assert_eq!(report.build_system, BuildSystem::PlatformIO);
+    assert_eq!(report.environments, ["native"]);
+    assert!(report.config_warnings.iter().any(|w| w.contains("default_envs names 'esp32'")), "{:?}", report.config_warnings);
+    assert!(!report.buildable());
+    let pio = report.capabilities.iter().find(|check| check.name == "pio").unwrap();
+    assert_eq!(pio.detail.as_deref(), Some("pio not found on PATH"));
+    assert!(!dir.path().join(".pio").exists());
+}
+
+#[tokio::test]
+async fn test_project_dir_picks_the_subproject_to_build() {
+    let dir = TempDir::new().unwrap();
+    let repo = dir.path().join("monorepo-main");
+    for (app, output) in [("apps/sensor", "sensor"), ("apps/gateway", "gateway")] {
+        fs::create_dir_all(repo.join(app)).unwrap();
+        fs::write(repo.join(app).join("Makefile"), format!("firmware:\n\techo {} > firmware\n", output)).unwrap();
+    }
+    let outside = TempDir::new().unwrap();
+    std::os::unix::fs::symlink(outside.path(), repo.join("apps/escape")).unwrap();
+
+    let options = |project_dir: &str| RunOptions::from(BuildConfig { project_dir: Some(project_dir.to_string()), ..BuildConfig::default() });
+    let runner = FirmwareBuildRunner::new();
+
+    // Relative to the repository inside the archive's wrapping folder
+    let report = runner.run(dir.path(), options("apps/sensor")).await.unwrap();
+    assert_eq!(report.repo_dir, repo.join("apps/sensor"));
+    assert!(report.result.success, "{:?}", report.result.error_output);
+    assert_eq!(fs::read_to_string(repo.join("apps/sensor/firmware")).unwrap(), "sensor\n");
+    assert!(!repo.join("apps/gateway/firmware").exists());
+
+    for project_dir in ["apps/missing", "apps/escape", "apps/sensor/Makefile"] {
+        let error = runner.run(dir.path(), options(project_dir)).await.unwrap_err();
+        assert!(error.to_string().contains("is not a directory in the repository"), "{}: {}", project_dir, error);
+    }
+    let invalid = BuildConfig { project_dir: Some("../outside".to_string()), ..BuildConfig::default() };
+    assert!(invalid.validate().unwrap_err().to_string().contains("Invalid project_dir"));
+}
+
+#[tokio::test]
+async fn test_cached_build_reports_reuse_and_ccache_counts() {
+    let tools = TempDir::new().unwrap();
+    let ccache = tools.path().join("ccache");
+    fs::write(&ccache, "#!/bin/sh\ncat \"$CCACHE_DIR/stats\" 2>/dev/null || printf 'Hits: 0 / 0\\nMisses: 0 / 0\\n'\n").unwrap();
+    fs::set_permissions(&ccache, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let cache_dir = TempDir::new().unwrap();
+
+    // A cold build misses three compilations; a rebuild over its output hits them
+    let repo = TempDir::new().unwrap();
+    fs::write(
+        repo.path().join("Makefile"),
+        "all:\n\
+         \tif [ -d build ]; then printf 'Hits: 3 / 6\\nMisses: 3 / 6\\n'; else printf 'Hits: 0 / 3\\nMisses: 3 / 3\\n'; fi > $(CCACHE_DIR)/stats\n\
+         \tmkdir -p build\n\
+         \techo built > build/firmware.bin\n",
+    )
+    .unwrap();
+    let mut config = BuildConfig::default();
+    config.command_env.insert("PATH".to_string(), format!("{}:{}", tools.path().display(), std::env::var("PATH").unwrap()));
+    config.command_env.insert("CCACHE_DIR".to_string(), cache_dir.path().to_string_lossy().to_string());
+    let runner = FirmwareBuildRunner::new();
+
+    let cold = runner.run(repo.path(), RunOptions::from(config.clone())).await.unwrap();
+    assert!(cold.result.success, "{:?}", cold.result.error_output);
+    let cache = cold.result.cache.unwrap();
+    assert!(!cache.build_dir_reused);
+    assert_eq!((cache.ccache_hits, cache.ccache_misses), (Some(0), Some(3)));
+    assert!(cache.cache_bytes.unwrap() > 0);
+
+    let warm = runner.run(repo.path(), RunOptions::from(config)).await.unwrap();
+    let json = serde_json::to_value(&warm).unwrap();
+    assert_eq!(json["result"]["cache"]["build_dir_reused"], true, "{}", json);
+    assert_eq!(json["result"]["cache"]["ccache_hits"], 3);
+    assert_eq!(json["result"]["cache"]["ccache_misses"], 0);
+}
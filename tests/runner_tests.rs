@@ -1,5 +1,5 @@
 use nabla_runner::{FirmwareBuildRunner, BuildRunner};
-use nabla_core::{BuildSystem, BuildResult};
+use nabla_runner::core::{BuildResult, BuildSystem, Provenance};
 use std::fs::File;
 use std::io::Write;
 use tempfile::tempdir;
@@ -30,6 +30,26 @@ impl BuildRunner for MockBuildRunner {
             error_output: None,
             build_system: system,
             duration_ms: 1234,
+            artifacts: Vec::new(),
+            warning_count: 0,
+            error_count: 0,
+            test_results: None,
+            environments: Vec::new(),
+            targets: Vec::new(),
+            matrix: Vec::new(),
+            cache: None,
+            retries: 0,
+            attempts: Vec::new(),
+            config_warnings: Vec::new(),
+            provenance: Provenance::default(),
+            diagnostics: Vec::new(),
+            static_analysis: Vec::new(),
+            stdout: String::new(),
+            stderr: String::new(),
+            post_build_output: None,
+            platform_install_output: None,
+            exit_code: None,
+            signal: None,
         })
     }
 }
@@ -0,0 +1,89 @@
+use nabla_runner::core::BuildConfig;
+
+#[test]
+fn test_s3_target_requires_bucket_and_key() {
+    let config: BuildConfig = serde_json::from_str(r#"{"s3": {"bucket": "firmware", "key": ""}}"#).unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[cfg(not(feature = "s3"))]
+#[test]
+fn test_s3_rejected_without_feature() {
+    let config: BuildConfig = serde_json::from_str(r#"{"s3": {"bucket": "firmware", "key": "app.bin"}}"#).unwrap();
+    let error = config.validate().unwrap_err().to_string();
+    assert!(error.contains("`s3` feature"), "{}", error);
+}
+
+#[cfg(feature = "s3")]
+mod upload {
+    use axum::body::Bytes;
+    use axum::extract::{Path, State};
+    use axum::http::HeaderMap;
+    use axum::routing::put;
+    use axum::Router;
+    use nabla_runner::core::S3Target;
+    use nabla_runner::s3::upload_artifact;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    /// Bucket, key, `original-filename` metadata and body of each upload
+    type Uploads = Arc<Mutex<Vec<(String, String, Option<String>, Vec<u8>)>>>;
+
+    /// A stand-in S3 endpoint that records path-style PutObject requests
+    async fn mock_s3() -> (String, Uploads) {
+        let uploads: Uploads = Arc::default();
+        let app = Router::new()
+            .route(
+                "/:bucket/*key",
+                put(|State(uploads): State<Uploads>, Path((bucket, key)): Path<(String, String)>, headers: HeaderMap, body: Bytes| async move {
+                    let original_filename = headers.get("x-amz-meta-original-filename").map(|value| value.to_str().unwrap().to_string());
+                    uploads.lock().unwrap().push((bucket, key, original_filename, body.to_vec()));
+                    [("ETag", "\"mock\"")]
+                }),
+            )
+            .with_state(uploads.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (endpoint, uploads)
+    }
+
+    #[tokio::test]
+    async fn test_artifact_uploaded_to_endpoint() {
+        let (endpoint, uploads) = mock_s3().await;
+        std::env::set_var("AWS_ENDPOINT_URL", &endpoint);
+        std::env::set_var("AWS_ACCESS_KEY_ID", "test");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "test");
+        std::env::set_var("AWS_REQUEST_CHECKSUM_CALCULATION", "when_required");
+
+        let dir = TempDir::new().unwrap();
+        let artifact = dir.path().join("firmware.bin");
+        std::fs::write(&artifact, b"\x7fELF firmware").unwrap();
+        let target = S3Target {
+            bucket: "builds".to_string(),
+            key: "acme/firmware/".to_string(),
+            region: Some("us-east-1".to_string()),
+        };
+
+        // A key ending in `/` is a prefix for the artifact's name
+        let object = upload_artifact(&target, &artifact, "blinky-1.2.0.bin").await.unwrap();
+        assert_eq!(object.key, "acme/firmware/blinky-1.2.0.bin");
+        assert_eq!(object.url, format!("{}/builds/acme/firmware/blinky-1.2.0.bin", endpoint));
+
+        let exact = S3Target { key: "acme/latest.bin".to_string(), ..target };
+        let object = upload_artifact(&exact, &artifact, "blinky-1.2.0.bin").await.unwrap();
+        assert_eq!(object.key, "acme/latest.bin");
+
+        let uploads = uploads.lock().unwrap();
+        let recorded: Vec<_> = uploads.iter().map(|(bucket, key, original, _)| (bucket.as_str(), key.as_str(), original.as_deref())).collect();
+        assert_eq!(
+            recorded,
+            [
+                ("builds", "acme/firmware/blinky-1.2.0.bin", Some("firmware.bin")),
+                ("builds", "acme/latest.bin", Some("firmware.bin")),
+            ]
+        );
+        assert_eq!(uploads[0].3, b"\x7fELF firmware");
+    }
+}
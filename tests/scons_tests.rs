@@ -0,0 +1,86 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::detection::{analyze, detect_build_system};
+use nabla_runner::execution::execute_build_with_config;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn project(files: &[(&str, &str)]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    for (file, contents) in files {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+/// A `scons` that logs its working directory and arguments, and leaves `firmware.bin` where
+/// it ran
+fn stub_scons(tools: &Path, log: &Path) -> BuildConfig {
+    let stub = tools.join("scons");
+    fs::write(&stub, format!("#!/bin/sh\necho \"$PWD scons $*\" >> '{}'\necho bin > firmware.bin\n", log.display())).unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut config = BuildConfig::default();
+    let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    config
+}
+
+const NESTED: &[(&str, &str)] = &[
+    ("build/SConstruct", "SConscript('../src/SConscript')\n"),
+    ("src/main.c", "int main(void) { return 0; }\n"),
+    ("docs/README.md", "# firmware\n"),
+];
+
+#[tokio::test]
+async fn test_nested_sconstruct_detected() {
+    let dir = project(NESTED);
+
+    assert_eq!(detect_build_system(dir.path()).await, Some(BuildSystem::SCons));
+    let report = analyze(dir.path()).await.unwrap();
+    assert_eq!(report.markers, ["build/SConstruct"]);
+    assert_eq!(report.suggested_command, "cd build && scons");
+}
+
+#[tokio::test]
+async fn test_sconstruct_and_jobs_passed_to_scons() {
+    let tools = TempDir::new().unwrap();
+    let log = tools.path().join("scons.log");
+    let mut config = stub_scons(tools.path(), &log);
+    config.sconstruct = Some("build/SConstruct".to_string());
+    config.scons_jobs = Some(4);
+
+    let dir = project(NESTED);
+    let result = execute_build_with_config(dir.path(), BuildSystem::SCons, &config).await.unwrap();
+
+    let build_dir = dir.path().join("build");
+    assert_eq!(fs::read_to_string(&log).unwrap(), format!("{} scons -f SConstruct -j 4\n", build_dir.display()));
+    assert_eq!(result.output_path, Some(format!("{}/firmware.bin", build_dir.display())));
+}
+
+#[tokio::test]
+async fn test_renamed_build_file() {
+    let tools = TempDir::new().unwrap();
+    let log = tools.path().join("scons.log");
+    let mut config = stub_scons(tools.path(), &log);
+    config.sconstruct = Some("firmware.scons".to_string());
+
+    let dir = project(&[("firmware.scons", "Program('firmware', 'main.c')\n"), ("main.c", "")]);
+    execute_build_with_config(dir.path(), BuildSystem::SCons, &config).await.unwrap();
+    assert_eq!(fs::read_to_string(&log).unwrap(), format!("{} scons -f firmware.scons\n", dir.path().display()));
+
+    config.sconstruct = Some("missing.scons".to_string());
+    let error = execute_build_with_config(dir.path(), BuildSystem::SCons, &config).await.unwrap_err();
+    assert!(error.to_string().contains("sconstruct 'missing.scons' is not a file in the repository"), "{}", error);
+}
+
+#[test]
+fn test_scons_options_validated() {
+    let invalid = |config: BuildConfig| config.validate().unwrap_err().to_string();
+
+    assert!(invalid(BuildConfig { sconstruct: Some("../SConstruct".to_string()), ..BuildConfig::default() }).contains("Invalid sconstruct"));
+    assert!(invalid(BuildConfig { scons_jobs: Some(0), ..BuildConfig::default() }).contains("Invalid scons_jobs"));
+}
@@ -0,0 +1,122 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use nabla_runner::container::HostArch;
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::selftest::{parse_systems, run_self_test, run_self_tests, SelfTest};
+use nabla_runner::server::create_app;
+use serde_json::Value;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+use tower::util::ServiceExt;
+
+/// A config whose `make` is `script`
+fn stub_make(tools: &Path, script: &str) -> BuildConfig {
+    let stub = tools.join("make");
+    fs::write(&stub, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut config = BuildConfig::default();
+    let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+    config.command_env.insert("PATH".to_string(), path);
+    config
+}
+
+#[test]
+fn test_self_test_names() {
+    assert_eq!(
+        parse_systems(" platformio, CMake,make,makefile ").unwrap(),
+        [BuildSystem::PlatformIO, BuildSystem::CMake, BuildSystem::Makefile]
+    );
+    assert!(parse_systems("").unwrap().is_empty());
+    let error = parse_systems("cmake,scons").unwrap_err().to_string();
+    assert!(error.contains("Unknown SELF_TEST build system 'scons'"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_makefile_fixture_builds() {
+    let result = run_self_test(BuildSystem::Makefile, &BuildConfig::default()).await;
+    assert!(result.ok, "{:?}", result.detail);
+}
+
+#[tokio::test]
+async fn test_broken_toolchain_fails_self_test() {
+    let tools = TempDir::new().unwrap();
+    let config = stub_make(tools.path(), "echo 'cc: internal compiler error' >&2; exit 2");
+
+    let result = run_self_test(BuildSystem::Makefile, &config).await;
+    assert!(!result.ok);
+    assert!(result.detail.unwrap().contains("internal compiler error"));
+
+    // A build that hangs is cut off
+    let config = stub_make(tools.path(), "sleep 30");
+    let results = run_self_tests(&[BuildSystem::Makefile], &config, Duration::from_secs(1)).await;
+    assert_eq!(results[0].detail.as_deref(), Some("did not finish within 1s"));
+}
+
+async fn finished(self_test: &SelfTest) {
+    for _ in 0..500 {
+        if !self_test.running() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("self-test did not finish");
+}
+
+#[tokio::test]
+async fn test_failed_self_test_blocks_readiness_unless_logged_only() {
+    let tools = TempDir::new().unwrap();
+    let config = stub_make(tools.path(), "exit 2");
+
+    let blocking = SelfTest::start(vec![BuildSystem::Makefile], config.clone(), Duration::from_secs(60), true);
+    assert_eq!(blocking.not_ready_reasons(), ["startup self-test is still running"]);
+    finished(&blocking).await;
+    let reasons = blocking.not_ready_reasons();
+    assert_eq!(reasons.len(), 1);
+    assert!(reasons[0].starts_with("self-test of Makefile failed: "), "{:?}", reasons);
+
+    let logged = SelfTest::start(vec![BuildSystem::Makefile], config, Duration::from_secs(60), false);
+    finished(&logged).await;
+    assert!(!logged.results()[0].ok);
+    assert!(logged.not_ready_reasons().is_empty());
+}
+
+async fn get(app: &axum::Router, uri: &str) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_self_test_results_in_capabilities() {
+    std::env::set_var("SELF_TEST", "makefile");
+    let app = create_app();
+
+    let mut capabilities = Value::Null;
+    for _ in 0..500 {
+        (_, capabilities) = get(&app, "/capabilities").await;
+        if capabilities["self_test"]["running"] == false {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert_eq!(capabilities["self_test"]["requested"], serde_json::json!(["Makefile"]));
+    let systems = capabilities["build_systems"].as_array().unwrap();
+    let makefile = systems.iter().find(|entry| entry["build_system"] == "Makefile").unwrap();
+    assert_eq!(makefile["tools"], serde_json::json!([{"name": "make", "ok": true}]));
+    assert_eq!(makefile["self_test"]["ok"], true, "{}", makefile);
+    let cmake = systems.iter().find(|entry| entry["build_system"] == "CMake").unwrap();
+    assert!(cmake.get("self_test").is_none());
+
+    let host = HostArch::detect();
+    assert_eq!(capabilities["host"], serde_json::json!({"arch": host.arch, "emulated": host.emulated}));
+
+    let (_, ready) = get(&app, "/ready").await;
+    let reasons = ready["reasons"].as_array().cloned().unwrap_or_default();
+    assert!(!reasons.iter().any(|reason| reason.as_str().unwrap().contains("self-test")), "{}", ready);
+}
@@ -0,0 +1,279 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use base64::{engine::general_purpose, Engine as _};
+use nabla_runner::core::{check_build_config, render_artifact_name, Artifact, BuildConfig, DEFAULT_ARTIFACT_NAME};
+use nabla_runner::server::{create_app, encode_artifacts};
+use serde_json::{json, Value};
+use tower::util::ServiceExt; // for `oneshot`
+
+fn build_request() -> axum::http::request::Builder {
+    Request::builder()
+        .method("POST")
+        .uri("/build")
+        .header("content-type", "application/json")
+}
+
+fn valid_params() -> Value {
+    json!({
+        "job_id": "test-job",
+        "archive_url": "https://example.invalid/archive.tar.gz",
+        "owner": "test",
+        "repo": "firmware",
+        "installation_id": "123"
+    })
+}
+
+async fn send(request: Request<Body>) -> (StatusCode, Value) {
+    let response = create_app().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_build_config_from_header_is_honored() {
+    // An unknown post-processor is rejected during config validation, so a 400 naming it
+    // proves the header config made it into the resolved build_config
+    let header_config = general_purpose::STANDARD.encode(r#"{"post_processors": ["from-header"]}"#);
+    let body = valid_params();
+
+    let request = build_request()
+        .header("x-nabla-build-config", header_config)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let (status, json) = send(request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json["message"].as_str().unwrap().contains("from-header"), "{}", json);
+}
+
+#[tokio::test]
+async fn test_body_build_config_overrides_header() {
+    let header_config = general_purpose::STANDARD.encode(r#"{"post_processors": ["from-header"]}"#);
+    let mut body = valid_params();
+    body["build_config"] = json!({"post_processors": ["from-body"]});
+
+    let request = build_request()
+        .header("x-nabla-build-config", header_config)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let (status, json) = send(request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let message = json["message"].as_str().unwrap();
+    assert!(message.contains("from-body") && !message.contains("from-header"), "{}", message);
+}
+
+#[tokio::test]
+async fn test_malformed_build_config_header_rejected() {
+    for header in ["%%%not-base64%%%", &general_purpose::STANDARD.encode("{not json"), &general_purpose::STANDARD.encode("[1, 2]")] {
+        let body = valid_params();
+        let request = build_request()
+            .header("x-nabla-build-config", header)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let (status, json) = send(request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "header {:?}", header);
+        assert!(json["message"].as_str().unwrap().contains("X-Nabla-Build-Config"), "{}", json);
+    }
+}
+
+#[test]
+fn test_artifact_name_template_renders() {
+    let lookup = |field: &str| match field {
+        "owner" => Some("acme".to_string()),
+        "repo" => Some("firmware".to_string()),
+        "sha" => Some("abc123".to_string()),
+        "build_system" => Some("platformio".to_string()),
+        "ext" => Some("hex".to_string()),
+        _ => None,
+    };
+
+    let name = render_artifact_name("{owner}-{repo}-{sha}.{ext}", lookup).unwrap();
+    assert_eq!(name, "acme-firmware-abc123.hex");
+    assert_eq!(render_artifact_name("{repo}_{build_system}.bin", lookup).unwrap(), "firmware_platformio.bin");
+    assert!(render_artifact_name("{branch}.hex", lookup).is_err());
+}
+
+#[test]
+fn test_default_artifact_name_edge_cases() {
+    let render = |repo: &str, short_sha: &str, env_or_target: &str, ext: &str| {
+        render_artifact_name(DEFAULT_ARTIFACT_NAME, |field| match field {
+            "repo" => Some(repo.to_string()),
+            "short_sha" => Some(short_sha.to_string()),
+            "env_or_target" => Some(env_or_target.to_string()),
+            "build_system" => Some("platformio".to_string()),
+            "ext" => Some(ext.to_string()),
+            _ => None,
+        })
+        .unwrap()
+    };
+
+    assert_eq!(render("blinky", "1a2b3c4", "esp32dev", "bin"), "blinky-1a2b3c4-esp32dev-platformio.bin");
+    // A missing sha or extension takes its separator along
+    assert_eq!(render("blinky", "", "esp32dev", "bin"), "blinky-esp32dev-platformio.bin");
+    assert_eq!(render("blinky", "", "firmware", ""), "blinky-firmware-platformio");
+    // Every component is safe as a filename and object key
+    assert_eq!(render("ファームウェア", "1a2b3c4", "esp32", "bin"), "1a2b3c4-esp32-platformio.bin");
+    assert_eq!(render("café fw", "1a2b3c4", "../esp32 dev", "hex"), "caf_fw-1a2b3c4-esp32_dev-platformio.hex");
+}
+
+#[tokio::test]
+async fn test_invalid_head_sha_rejected() {
+    let mut body = valid_params();
+    body["head_sha"] = json!("main; rm -rf /");
+    let request = build_request().body(Body::from(body.to_string())).unwrap();
+
+    let (status, json) = send(request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json["message"].as_str().unwrap().contains("head_sha"), "{}", json);
+}
+
+#[tokio::test]
+async fn test_artifact_name_with_path_separator_rejected() {
+    for template in ["../{repo}.hex", "out\\{repo}.hex", "{repo.hex"] {
+        let mut body = valid_params();
+        body["build_config"] = json!({ "artifact_name": template });
+        let request = build_request().body(Body::from(body.to_string())).unwrap();
+
+        let (status, json) = send(request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "template {:?}", template);
+        assert!(json["message"].as_str().unwrap().contains("artifact_name"), "{}", json);
+    }
+}
+
+#[tokio::test]
+async fn test_build_config_schema_violations_all_reported() {
+    let mut body = valid_params();
+    body["build_config"] = json!({
+        "enviroments": ["esp32"],
+        "timeout_secs": "soon",
+        "container": {"image": "ghcr.io/acme/sdk", "pull_policy": "sometimes"}
+    });
+    let request = build_request().body(Body::from(body.to_string())).unwrap();
+
+    let (status, json) = send(request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let mut paths: Vec<&str> = json["config_errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["path"].as_str().unwrap())
+        .collect();
+    paths.sort();
+    assert_eq!(paths, ["/container/pull_policy", "/enviroments", "/timeout_secs"], "{}", json);
+    assert_eq!(json["config_errors"][0]["message"].as_str().map(str::is_empty), Some(false));
+    assert!(json["message"].as_str().unwrap().contains("build_config.enviroments is not a known field"), "{}", json);
+}
+
+#[tokio::test]
+async fn test_malformed_build_config_gets_precise_errors() {
+    let cases = [
+        (json!({"timeout_secs": "soon"}), "build_config.timeout_secs must be a positive integer"),
+        (json!({"timeout_secs": -1}), "build_config.timeout_secs must be a positive integer"),
+        (json!({"timeout_secs": 0}), "build_config.timeout_secs must be a positive integer"),
+        (json!({"max_parallel_envs": 1.5}), "build_config.max_parallel_envs must be a positive integer"),
+        (json!({"transient_retries": 9}), "build_config.transient_retries must be an integer from 0 to 5"),
+        (json!({"sysbuild": "yes"}), "build_config.sysbuild must be a boolean"),
+        (json!({"pio_envs": "esp32"}), "build_config.pio_envs must be an array"),
+        (json!({"pio_envs": ["esp32", 2]}), "build_config.pio_envs[1] must be a string"),
+        (
+            json!({"network_policy": "offline"}),
+            r#"build_config.network_policy must be one of "allow", "fetch-then-isolate""#,
+        ),
+        (
+            json!({"container": {"image": "ghcr.io/acme/sdk", "pull_policy": "sometimes"}}),
+            r#"build_config.container.pull_policy must be one of "always", "if-not-present", "never""#,
+        ),
+        (json!({"s3": {"bucket": "firmware"}}), "build_config.s3.key is required"),
+        (json!({"jobs": 8}), "build_config.jobs is not a known field"),
+    ];
+    for (build_config, expected) in cases {
+        let mut body = valid_params();
+        body["build_config"] = build_config.clone();
+        let (status, json) = send(build_request().body(Body::from(body.to_string())).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", build_config);
+        assert_eq!(json["message"], format!("invalid request: {expected}"), "{}", build_config);
+        assert_eq!(json["config_errors"].as_array().map(Vec::len), Some(1), "{}", json);
+    }
+}
+
+#[tokio::test]
+async fn test_valid_and_non_strict_build_config_pass_schema() {
+    let valid = json!({
+        "c_standard": "c11",
+        "container": null,
+        "pio_envs": ["esp32"],
+        "network_policy": "fetch-then-isolate",
+        "secret_env": {"API_KEY": "hunter2"},
+        "post_processors": ["marker"]
+    });
+    assert!(check_build_config(&valid).is_empty());
+    let config: BuildConfig = serde_json::from_value(valid.clone()).unwrap();
+    assert_eq!(config.pio_envs, ["esp32"]);
+    assert_eq!(config.c_standard.as_deref(), Some("c11"));
+
+    // Unknown fields are ignored with strict: false, but wrong types are still errors
+    let lenient = json!({"strict": false, "option_from_the_future": 1, "post_processors": ["marker"]});
+    assert!(check_build_config(&lenient).is_empty());
+    let lenient_bad_type = json!({"strict": false, "sysbuild": "yes"});
+    assert_eq!(check_build_config(&lenient_bad_type)[0].path, "/sysbuild");
+
+    // The config reaches later validation: the unknown post-processor is what gets rejected
+    for build_config in [valid, lenient] {
+        let mut body = valid_params();
+        body["build_config"] = build_config;
+        let (status, json) = send(build_request().body(Body::from(body.to_string())).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(json["message"].as_str().unwrap().contains("unknown post-processor 'marker'"), "{}", json);
+        assert!(json.get("config_errors").is_none(), "{}", json);
+    }
+}
+
+#[tokio::test]
+async fn test_build_config_schema_served() {
+    let request = Request::builder().uri("/schema/build_config.json").body(Body::empty()).unwrap();
+    let (status, schema) = send(request).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(schema["additionalProperties"], false);
+    assert!(schema["properties"]["pio_envs"].is_object(), "{}", schema);
+    assert!(schema["properties"].get("command_env").is_none(), "{}", schema);
+}
+
+#[tokio::test]
+async fn test_artifacts_encoded_concurrently_in_order() {
+    let dir = tempfile::TempDir::new().unwrap();
+    // The largest first, so it is the last to finish encoding
+    let contents: [(&str, Vec<u8>); 3] = [
+        ("zephyr.signed.bin", vec![0xA5; 8 << 20]),
+        ("mcuboot.hex", b":00000001FF\n".to_vec()),
+        ("app.elf", b"\x7fELF".to_vec()),
+    ];
+    let mut artifacts = Vec::new();
+    for (name, bytes) in &contents {
+        let path = dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        let format = name.rsplit('.').next().unwrap();
+        let mut artifact = Artifact::new(path.to_string_lossy(), format);
+        artifact.metadata.insert("image".to_string(), name.to_string());
+        artifacts.push(artifact);
+    }
+
+    let payloads = encode_artifacts(&artifacts).await.unwrap();
+
+    let names: Vec<&str> = payloads.iter().map(|p| p.filename.as_str()).collect();
+    assert_eq!(names, ["zephyr.signed.bin", "mcuboot.hex", "app.elf"]);
+    for ((name, bytes), payload) in contents.iter().zip(&payloads) {
+        assert_eq!(general_purpose::STANDARD.decode(&payload.data).unwrap(), *bytes, "{}", name);
+        assert_eq!(payload.metadata["image"], *name);
+    }
+    assert_eq!(payloads[1].format, "hex");
+
+    artifacts.push(Artifact::new(dir.path().join("missing.bin").to_string_lossy(), "bin"));
+    let error = encode_artifacts(&artifacts).await.unwrap_err();
+    assert!(error.to_string().contains("missing.bin"), "{}", error);
+}
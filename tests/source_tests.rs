@@ -0,0 +1,51 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::execution::execute_build_with_config;
+use nabla_runner::source::SourceSnapshot;
+use std::fs;
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn test_source_hash_ignores_gitignored_build_output() {
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join(".gitignore"), "build/\n*.o\n").unwrap();
+    fs::create_dir(repo.path().join("src")).unwrap();
+    fs::write(repo.path().join("src/main.c"), "int main(void) { return 0; }\n").unwrap();
+    let clean = SourceSnapshot::take(repo.path()).await.unwrap().sha256;
+
+    fs::create_dir_all(repo.path().join("build/obj")).unwrap();
+    fs::write(repo.path().join("build/firmware.elf"), b"\x7fELF").unwrap();
+    fs::write(repo.path().join("src/main.o"), b"object").unwrap();
+    fs::create_dir(repo.path().join(".git")).unwrap();
+    fs::write(repo.path().join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+    assert_eq!(SourceSnapshot::take(repo.path()).await.unwrap().sha256, clean);
+
+    fs::write(repo.path().join("src/main.c"), "int main(void) { return 1; }\n").unwrap();
+    assert_ne!(SourceSnapshot::take(repo.path()).await.unwrap().sha256, clean);
+}
+
+#[tokio::test]
+async fn test_artifacts_new_only_skips_committed_binary() {
+    let repo = TempDir::new().unwrap();
+    // A stale binary checked in next to the sources, found before build/firmware by name
+    fs::write(repo.path().join("firmware.bin"), b"committed").unwrap();
+    fs::write(
+        repo.path().join("Makefile"),
+        "build/firmware:\n\tmkdir -p build && printf built > build/firmware\n",
+    )
+    .unwrap();
+
+    let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default())
+        .await
+        .unwrap();
+    assert!(result.output_path.unwrap().ends_with("firmware.bin"));
+    let hash = result.provenance.source_sha256.unwrap();
+
+    fs::remove_dir_all(repo.path().join("build")).unwrap();
+    let config = BuildConfig {
+        artifacts_new_only: true,
+        ..BuildConfig::default()
+    };
+    let result = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap();
+    assert!(result.output_path.unwrap().ends_with("build/firmware"));
+    assert_eq!(result.provenance.source_sha256.unwrap(), hash);
+}
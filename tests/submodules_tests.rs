@@ -0,0 +1,103 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::submodules::{parse_gitmodules, GitSource};
+use nabla_runner::{FirmwareBuildRunner, RunOptions};
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=nabla", "-c", "user.email=ci@example.com", "-c", "protocol.file.allow=always"])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// A repository vendoring `vendor/hal` as a submodule, and its GitHub-style archive: the
+/// working tree without history or submodule contents
+fn repo_with_submodule(root: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    let hal = root.join("hal");
+    std::fs::create_dir(&hal).unwrap();
+    std::fs::write(hal.join("hal.bin"), "hal v2").unwrap();
+    git(&hal, &["init", "-q"]);
+    git(&hal, &["add", "."]);
+    git(&hal, &["commit", "-q", "-m", "hal"]);
+
+    let firmware = root.join("firmware");
+    std::fs::create_dir(&firmware).unwrap();
+    std::fs::write(firmware.join("Makefile"), "firmware.bin:\n\tcp vendor/hal/hal.bin firmware.bin\n").unwrap();
+    git(&firmware, &["init", "-q"]);
+    git(&firmware, &["submodule", "add", "-q", hal.to_str().unwrap(), "vendor/hal"]);
+    git(&firmware, &["add", "."]);
+    git(&firmware, &["commit", "-q", "-m", "firmware"]);
+
+    let archive = root.join("archive");
+    std::fs::create_dir(&archive).unwrap();
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("git -C {} archive HEAD | tar -x -C {}", firmware.display(), archive.display()))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    (firmware, archive)
+}
+
+#[test]
+fn test_parse_gitmodules() {
+    let submodules = parse_gitmodules(
+        "[submodule \"hal\"]\n\tpath = vendor/hal\n\turl = https://github.com/acme/hal.git\n[core]\n\tbare = false\n[submodule \"docs\"]\n\turl = ../docs.git\n",
+    );
+    assert_eq!(submodules.len(), 1);
+    assert_eq!((submodules[0].name.as_str(), submodules[0].path.as_str()), ("hal", "vendor/hal"));
+    assert_eq!(submodules[0].url.as_deref(), Some("https://github.com/acme/hal.git"));
+}
+
+#[tokio::test]
+async fn test_missing_submodules_warned_or_fetched() {
+    let root = TempDir::new().unwrap();
+    let (firmware, archive) = repo_with_submodule(root.path());
+    let runner = FirmwareBuildRunner::new();
+
+    // Without fetch_submodules the build runs on the archive as it is, and says why it failed
+    let options = RunOptions {
+        build_system: Some(BuildSystem::Makefile),
+        ..RunOptions::default()
+    };
+    let report = runner.run(&archive, options).await.unwrap();
+    assert!(!report.result.success);
+    assert_eq!(
+        report.result.config_warnings,
+        ["Submodules vendor/hal are missing from the archive; set fetch_submodules to fetch them"]
+    );
+
+    // Nothing to clone from
+    let mut config = BuildConfig { fetch_submodules: true, ..BuildConfig::default() };
+    let options = RunOptions {
+        config: config.clone(),
+        build_system: Some(BuildSystem::Makefile),
+        ..RunOptions::default()
+    };
+    let report = runner.run(&archive, options).await.unwrap();
+    assert!(report.result.config_warnings[0].contains("can't be fetched"), "{:?}", report.result.config_warnings);
+
+    // The submodule is fetched at the commit the repository pins
+    for (key, value) in [("GIT_CONFIG_COUNT", "1"), ("GIT_CONFIG_KEY_0", "protocol.file.allow"), ("GIT_CONFIG_VALUE_0", "always")] {
+        config.command_env.insert(key.to_string(), value.to_string());
+    }
+    let options = RunOptions {
+        config,
+        build_system: Some(BuildSystem::Makefile),
+        git_source: Some(GitSource {
+            url: firmware.to_string_lossy().to_string(),
+            reference: None,
+            token: None,
+        }),
+        ..RunOptions::default()
+    };
+    let report = runner.run(&archive, options).await.unwrap();
+    assert!(report.result.success, "{:?}", report.result.error_output);
+    assert!(report.result.config_warnings.is_empty());
+    assert_eq!(std::fs::read_to_string(archive.join("firmware.bin")).unwrap(), "hal v2");
+}
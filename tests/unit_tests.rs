@@ -1,8 +1,7 @@
 use nabla_runner::{detection, execution};
-use nabla_core::BuildSystem;
+use nabla_runner::core::BuildSystem;
 use std::fs;
 use tempfile::TempDir;
-use tokio;
 
 #[tokio::test]
 async fn test_detect_cargo_project() {
@@ -0,0 +1,118 @@
+use nabla_runner::core::{BuildConfig, BuildSystem, Verbosity};
+use nabla_runner::execution::{
+    build_command_line, cargo_build_args, cmake_build_args, cmake_configure_args, execute_build_with_config, make_args, verbosity_flags, zephyr_build_args,
+};
+use nabla_runner::logging::{build_span, filter};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tracing_subscriber::prelude::*;
+
+#[test]
+fn test_verbosity_flags_per_build_system() {
+    let flags = |system| [Verbosity::Quiet, Verbosity::Verbose, Verbosity::Debug].map(|verbosity| verbosity_flags(system, verbosity));
+    let expected: [(BuildSystem, [&[&str]; 3]); 11] = [
+        (BuildSystem::Cargo, [&["-q"], &["-v"], &["-vv"]]),
+        (BuildSystem::Makefile, [&["-s"], &["V=1"], &["V=1", "--debug=basic"]]),
+        (BuildSystem::STM32CubeIDE, [&["-s"], &["V=1"], &["V=1", "--debug=basic"]]),
+        (BuildSystem::Buildroot, [&["-s"], &["V=1"], &["V=1", "--debug=basic"]]),
+        (BuildSystem::MplabX, [&["-s"], &["V=1"], &["V=1", "--debug=basic"]]),
+        (BuildSystem::CMake, [&["--log-level=WARNING"], &["--log-level=VERBOSE"], &["--log-level=DEBUG"]]),
+        (BuildSystem::PlatformIO, [&["-s"], &["-v"], &["-v"]]),
+        (BuildSystem::ZephyrWest, [&["-q"], &["-v"], &["-vv"]]),
+        (BuildSystem::SCons, [&["-s"], &["--debug=explain"], &["--debug=explain", "--debug=time"]]),
+        (BuildSystem::Dockerfile, [&["--quiet"], &["--progress=plain"], &["--progress=plain"]]),
+        (BuildSystem::Yocto, [&[], &[], &[]]),
+    ];
+    for (system, levels) in expected {
+        assert_eq!(flags(system), levels, "{:?}", system);
+        assert!(verbosity_flags(system, Verbosity::Normal).is_empty(), "{:?}", system);
+    }
+}
+
+#[tokio::test]
+async fn test_verbosity_reaches_each_build_command() {
+    let config = |verbosity| BuildConfig { verbosity, ..BuildConfig::default() };
+    let verbose = config(Verbosity::Verbose);
+
+    assert_eq!(cargo_build_args(&config(Verbosity::Debug)), ["build", "-vv"]);
+    assert_eq!(make_args(&config(Verbosity::Quiet)), ["-s"]);
+    assert_eq!(cmake_configure_args(&verbose), ["..", "--log-level=VERBOSE"]);
+    assert_eq!(cmake_build_args(&verbose), ["--build", ".", "--verbose"]);
+    assert_eq!(cmake_build_args(&config(Verbosity::Quiet)), ["--build", "."]);
+    let repo = TempDir::new().unwrap();
+    assert_eq!(zephyr_build_args(&verbose, repo.path(), Path::new("app")), ["-v", "build", "app"]);
+
+    assert_eq!(build_command_line(repo.path(), BuildSystem::PlatformIO, &verbose).await, "pio run -v");
+    assert_eq!(build_command_line(repo.path(), BuildSystem::Dockerfile, &config(Verbosity::Quiet)).await, "docker build --quiet .");
+    assert_eq!(build_command_line(repo.path(), BuildSystem::Makefile, &BuildConfig::default()).await, "make");
+
+    let parsed: BuildConfig = serde_json::from_value(serde_json::json!({"verbosity": "debug"})).unwrap();
+    assert_eq!(parsed.verbosity, Verbosity::Debug);
+    assert!(serde_json::from_value::<BuildConfig>(serde_json::json!({"verbosity": "loud"})).is_err());
+}
+
+#[tokio::test]
+async fn test_verbose_build_keeps_more_output_and_records_its_verbosity() {
+    let repo = TempDir::new().unwrap();
+    fs::write(repo.path().join("Makefile"), "V ?= 0\n\nall:\n\techo verbose=$(V) > firmware.bin\n").unwrap();
+
+    let normal = execute_build_with_config(repo.path(), BuildSystem::Makefile, &BuildConfig::default()).await.unwrap();
+    assert!(normal.success, "{:?}", normal.error_output);
+    assert_eq!(fs::read_to_string(repo.path().join("firmware.bin")).unwrap().trim(), "verbose=0");
+    assert_eq!(serde_json::to_value(&normal.provenance).unwrap().get("verbosity"), None);
+
+    let config = BuildConfig { verbosity: Verbosity::Verbose, ..BuildConfig::default() };
+    let verbose = execute_build_with_config(repo.path(), BuildSystem::Makefile, &config).await.unwrap();
+    assert!(verbose.success, "{:?}", verbose.error_output);
+    assert_eq!(fs::read_to_string(repo.path().join("firmware.bin")).unwrap().trim(), "verbose=1");
+    assert_eq!(verbose.provenance.verbosity, Verbosity::Verbose);
+    assert_eq!(serde_json::to_value(&verbose.provenance).unwrap()["verbosity"], "verbose");
+
+    let log_bytes = |result: &nabla_runner::core::BuildResult| result.provenance.limits.as_ref().unwrap().max_log_bytes;
+    assert_eq!(log_bytes(&verbose), 4 * log_bytes(&normal));
+}
+
+/// Log lines written by a subscriber using the runner's [`filter`]
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_only_the_verbose_builds_span_logs_debug_lines() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .with_filter(filter()),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::debug!("outside any build");
+        build_span(Verbosity::Normal).in_scope(|| tracing::debug!("normal build"));
+        build_span(Verbosity::Verbose).in_scope(|| {
+            tracing::debug!("verbose build");
+            tracing::trace!("verbose build trace");
+        });
+        build_span(Verbosity::Debug).in_scope(|| tracing::trace!("debug build trace"));
+        tracing::info!("info is always logged");
+    });
+
+    let logged = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    let messages: Vec<_> = logged.lines().map(|line| line.rsplit(": ").next().unwrap()).collect();
+    assert_eq!(messages, ["verbose build", "debug build trace", "info is always logged"]);
+}
@@ -0,0 +1,84 @@
+use nabla_runner::core::{BuildConfig, BuildSystem};
+use nabla_runner::execution::execute_build_with_config;
+use nabla_runner::workspace::{sanitize_customer_id, CustomerDirs};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A Makefile project whose "firmware" records the cache directory the build saw
+fn write_cache_probe_project(dir: &Path) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("Makefile"), "all:\n\tprintf '%s' \"$$CCACHE_DIR\" > firmware\n").unwrap();
+}
+
+#[tokio::test]
+async fn test_customers_get_separate_workspaces_and_caches() {
+    let root = TempDir::new().unwrap();
+    let mut seen_cache_dirs = Vec::new();
+
+    for customer in ["acme", "globex"] {
+        let dirs = CustomerDirs::new(root.path(), customer, false);
+        dirs.ensure().await.unwrap();
+
+        let mode = fs::metadata(dirs.root()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700, "{} root is not private", customer);
+
+        let project = dirs.job_workspace("job1").join("repo");
+        write_cache_probe_project(&project);
+
+        let config = BuildConfig {
+            command_env: dirs.cache_env(),
+            ..BuildConfig::default()
+        };
+        let result = execute_build_with_config(&project, BuildSystem::Makefile, &config).await.unwrap();
+        let cache_dir = fs::read_to_string(result.output_path.unwrap()).unwrap();
+
+        assert!(Path::new(&cache_dir).starts_with(dirs.root()), "{} not under {:?}", cache_dir, dirs.root());
+        seen_cache_dirs.push((dirs.root().to_path_buf(), cache_dir));
+    }
+
+    assert_ne!(seen_cache_dirs[0].0, seen_cache_dirs[1].0);
+    assert_ne!(seen_cache_dirs[0].1, seen_cache_dirs[1].1);
+}
+
+#[test]
+fn test_shared_caches_are_opt_in() {
+    let root = Path::new("/workspace");
+    let acme = CustomerDirs::new(root, "acme", true);
+    let globex = CustomerDirs::new(root, "globex", true);
+
+    assert_ne!(acme.root(), globex.root());
+    assert_eq!(acme.cache_env(), globex.cache_env());
+    assert_ne!(
+        CustomerDirs::new(root, "acme", false).cache_env(),
+        CustomerDirs::new(root, "globex", false).cache_env()
+    );
+}
+
+#[tokio::test]
+async fn test_cache_bytes_do_not_count_toward_the_disk_quota() {
+    let root = TempDir::new().unwrap();
+    for shared in [false, true] {
+        let dirs = CustomerDirs::new(root.path(), &format!("acme-{}", shared), shared);
+        dirs.ensure().await.unwrap();
+        let workspace = dirs.job_workspace("job1");
+        fs::create_dir_all(&workspace).unwrap();
+        fs::write(workspace.join("firmware.bin"), [0u8; 100]).unwrap();
+        let packages = dirs.cache_root().join("platformio/packages");
+        fs::create_dir_all(&packages).unwrap();
+        fs::write(packages.join("toolchain.tar"), [0u8; 4096]).unwrap();
+
+        assert_eq!(dirs.workspace_bytes().await, 100, "shared caches: {}", shared);
+    }
+}
+
+#[test]
+fn test_customer_id_sanitized_for_paths() {
+    assert_eq!(sanitize_customer_id("acme-corp_1"), "acme-corp_1");
+
+    let escaped = sanitize_customer_id("../etc");
+    assert!(!escaped.contains('/') && !escaped.contains(".."), "{}", escaped);
+    assert_ne!(sanitize_customer_id("acme/a"), sanitize_customer_id("acme_a"));
+    assert!(!sanitize_customer_id("").is_empty());
+}